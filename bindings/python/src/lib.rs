@@ -0,0 +1,326 @@
+//! Python bindings of Fabrik (`import fabrik`)
+//!
+//! Exposes the local cache (`Cache`), script cache keys (`cache_key`) and cached script
+//! runs (`run_cached`) to Python build scripts. Built with maturin:
+//!
+//! ```bash
+//! cd bindings/python && maturin build --release
+//! ```
+//!
+//! Calls release the GIL while they hash, read or write, so Python threads can use the
+//! cache concurrently.
+
+use std::fmt::Display;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use fabrik::eviction::EvictionConfig;
+use fabrik::hashing::HashAlgorithm;
+use fabrik::recipe::annotations::{parse_annotations, ScriptAnnotations};
+use fabrik::recipe::cache::{create_metadata, CacheEntry, ScriptCache};
+use fabrik::recipe::cache_key::compute_cache_key;
+use fabrik::recipe::executor::{ExecutionResult, ScriptExecutor};
+use fabrik::recipe::inputs::{get_runtime_version, InputHasher};
+use fabrik::recipe::lock::{LockOptions, LockOutcome};
+use fabrik::recipe::memo::HashMemo;
+use fabrik::recipe::outputs::{archive_outputs, extract_outputs};
+use fabrik::recipe::CreateMetadataParams;
+use fabrik::storage::{default_cache_dir, FilesystemStorage, Storage};
+
+create_exception!(fabrik, FabrikError, PyException);
+
+fn error(e: impl Display) -> PyErr {
+    FabrikError::new_err(format!("{:#}", e))
+}
+
+/// Hash algorithm named `name` (sha256, blake3), SHA-256 by default
+fn hash_algorithm(name: Option<&str>) -> PyResult<HashAlgorithm> {
+    name.map_or(Ok(HashAlgorithm::default()), |name| {
+        name.parse()
+            .map_err(|e| PyValueError::new_err(format!("{}", e)))
+    })
+}
+
+/// A local Fabrik cache directory
+///
+/// Keys are strings, stored as their UTF-8 bytes like the C API's hashes.
+#[pyclass(module = "fabrik", frozen)]
+struct Cache {
+    storage: FilesystemStorage,
+}
+
+#[pymethods]
+impl Cache {
+    /// Open the cache in `dir` (Fabrik's default cache directory if omitted), evicting
+    /// beyond `max_size` (e.g. "10GB", 5GB by default)
+    #[new]
+    #[pyo3(signature = (dir=None, max_size=None))]
+    fn new(py: Python<'_>, dir: Option<PathBuf>, max_size: Option<&str>) -> PyResult<Self> {
+        let mut eviction = EvictionConfig::default();
+        if let Some(max_size) = max_size {
+            eviction.max_size_bytes = EvictionConfig::parse_size(max_size)
+                .map_err(|e| PyValueError::new_err(format!("Invalid max_size: {}", e)))?;
+        }
+        let dir = dir.unwrap_or_else(default_cache_dir);
+        let storage = py
+            .allow_threads(|| FilesystemStorage::with_eviction(&dir, Some(eviction)))
+            .map_err(error)?;
+        Ok(Self { storage })
+    }
+
+    /// Contents stored under `key`, None on a miss
+    fn get<'py>(&self, py: Python<'py>, key: &str) -> PyResult<Option<Bound<'py, PyBytes>>> {
+        let data = py
+            .allow_threads(|| self.storage.get(key.as_bytes()))
+            .map_err(error)?;
+        Ok(data.map(|data| PyBytes::new(py, &data)))
+    }
+
+    /// Store `data` under `key`
+    fn put(&self, py: Python<'_>, key: &str, data: &[u8]) -> PyResult<()> {
+        py.allow_threads(|| self.storage.put(key.as_bytes(), data))
+            .map_err(error)
+    }
+
+    /// Whether something is stored under `key`
+    fn exists(&self, py: Python<'_>, key: &str) -> PyResult<bool> {
+        py.allow_threads(|| self.storage.exists(key.as_bytes()))
+            .map_err(error)
+    }
+
+    /// Delete what's stored under `key`
+    fn delete(&self, py: Python<'_>, key: &str) -> PyResult<()> {
+        py.allow_threads(|| self.storage.delete(key.as_bytes()))
+            .map_err(error)
+    }
+
+    fn __contains__(&self, py: Python<'_>, key: &str) -> PyResult<bool> {
+        self.exists(py, key)
+    }
+}
+
+/// Outcome of `run_cached`
+#[pyclass(module = "fabrik", frozen, get_all)]
+struct RunResult {
+    /// Exit code of the script (of the cached run on a hit)
+    exit_code: i32,
+    /// Cache key of the script, None if its annotations disable caching
+    cache_key: Option<String>,
+    /// Whether the outputs were restored from the cache instead of running the script
+    cached: bool,
+}
+
+#[pymethods]
+impl RunResult {
+    fn __repr__(&self) -> String {
+        format!(
+            "RunResult(exit_code={}, cache_key={:?}, cached={})",
+            self.exit_code, self.cache_key, self.cached
+        )
+    }
+}
+
+/// Cache key of the script at `script`, from its `#FABRIK` annotations (as
+/// `fabrik run` computes it)
+#[pyfunction]
+#[pyo3(signature = (script, hash_algorithm=None))]
+fn cache_key(py: Python<'_>, script: PathBuf, hash_algorithm: Option<&str>) -> PyResult<String> {
+    let hasher = InputHasher::new(self::hash_algorithm(hash_algorithm)?);
+    py.allow_threads(|| {
+        let annotations = parse_annotations(&script)?;
+        compute_cache_key(&script, &annotations, &hasher)
+    })
+    .map_err(error)
+}
+
+/// Run the script at `script` with `args`, or restore its outputs from the cache, like
+/// `fabrik run`
+///
+/// The script's output goes to the process's stdout and stderr. Scripts with
+/// `depends-on` annotations need `fabrik run`, which runs their dependencies first.
+#[pyfunction]
+#[pyo3(signature = (script, args=Vec::new(), cache_dir=None, hash_algorithm=None, verbose=false))]
+fn run_cached(
+    py: Python<'_>,
+    script: PathBuf,
+    args: Vec<String>,
+    cache_dir: Option<PathBuf>,
+    hash_algorithm: Option<&str>,
+    verbose: bool,
+) -> PyResult<RunResult> {
+    let algorithm = self::hash_algorithm(hash_algorithm)?;
+    let cache_dir = cache_dir.unwrap_or_else(default_cache_dir);
+    py.allow_threads(|| run(&script, &args, cache_dir, algorithm, verbose))
+        .map_err(error)
+}
+
+fn run(
+    script: &Path,
+    args: &[String],
+    cache_dir: PathBuf,
+    algorithm: HashAlgorithm,
+    verbose: bool,
+) -> anyhow::Result<RunResult> {
+    let annotations = parse_annotations(script)
+        .with_context(|| format!("Failed to parse script annotations: {}", script.display()))?;
+    let executor = ScriptExecutor::new(verbose);
+
+    if annotations.cache_disabled {
+        let result = execute(&executor, script, &annotations, args)?;
+        return Ok(RunResult {
+            exit_code: result.exit_code,
+            cache_key: None,
+            cached: false,
+        });
+    }
+    if !annotations.depends_on.is_empty() {
+        anyhow::bail!(
+            "{} has dependencies; run it with `fabrik run` instead",
+            script.display()
+        );
+    }
+
+    // Input content hashes are memoized across runs, unless another run has the memo open
+    let hasher = match HashMemo::open(&cache_dir) {
+        Ok(memo) => InputHasher::new(algorithm).with_memo(memo),
+        Err(_) => InputHasher::new(algorithm),
+    };
+    let key =
+        compute_cache_key(script, &annotations, &hasher).context("Failed to compute cache key")?;
+    let cache = ScriptCache::new(cache_dir).context("Failed to initialize script cache")?;
+
+    if let Some(entry) = cache.get(&key)? {
+        return restore(&cache, &entry, script, &annotations, key);
+    }
+
+    // Concurrent runs of the same script wait for this one instead of duplicating it
+    let lock = match cache.lock(&key, &LockOptions::default())? {
+        LockOutcome::Acquired { lock, waited } => {
+            if waited {
+                if let Some(entry) = cache.get(&key)? {
+                    drop(lock);
+                    return restore(&cache, &entry, script, &annotations, key);
+                }
+            }
+            Some(lock)
+        }
+        LockOutcome::TimedOut { .. } => None,
+    };
+
+    let result = execute(&executor, script, &annotations, args)?;
+
+    if result.exit_code == 0 {
+        store(&cache, &key, script, &annotations, &result)?;
+    }
+    drop(lock);
+
+    Ok(RunResult {
+        exit_code: result.exit_code,
+        cache_key: Some(key),
+        cached: false,
+    })
+}
+
+/// Execute the script, passing on the output the executor captured
+fn execute(
+    executor: &ScriptExecutor,
+    script: &Path,
+    annotations: &ScriptAnnotations,
+    args: &[String],
+) -> anyhow::Result<ExecutionResult> {
+    let result = executor
+        .execute(script, annotations, args)
+        .context("Script execution failed")?;
+    std::io::stdout()
+        .write_all(&result.stdout)
+        .context("Failed to write stdout")?;
+    std::io::stderr()
+        .write_all(&result.stderr)
+        .context("Failed to write stderr")?;
+    Ok(result)
+}
+
+/// Restore the outputs of a cache hit
+fn restore(
+    cache: &ScriptCache,
+    entry: &CacheEntry,
+    script: &Path,
+    annotations: &ScriptAnnotations,
+    key: String,
+) -> anyhow::Result<RunResult> {
+    let archive = cache
+        .read_archive(entry)
+        .context("Failed to read cached outputs")?;
+    extract_outputs(&archive, base_dir(script), annotations.cache_restore)
+        .context("Failed to extract cached outputs")?;
+    Ok(RunResult {
+        exit_code: entry.metadata.execution.exit_code,
+        cache_key: Some(key),
+        cached: true,
+    })
+}
+
+/// Archive the outputs of a successful run and store them under `key`
+fn store(
+    cache: &ScriptCache,
+    key: &str,
+    script: &Path,
+    annotations: &ScriptAnnotations,
+    result: &ExecutionResult,
+) -> anyhow::Result<()> {
+    let archive = tempfile::NamedTempFile::new().context("Failed to create temporary archive")?;
+    let (outputs, images) = archive_outputs(
+        &annotations.outputs,
+        &annotations.image_outputs,
+        base_dir(script),
+        archive.path(),
+    )
+    .context("Failed to archive outputs")?;
+
+    let mut metadata = create_metadata(CreateMetadataParams {
+        cache_key: key.to_string(),
+        script_path: script,
+        exit_code: result.exit_code,
+        duration: result.duration,
+        runtime: annotations.runtime.clone(),
+        runtime_version: if annotations.runtime_version {
+            get_runtime_version(&annotations.runtime).ok()
+        } else {
+            None
+        },
+        outputs,
+        env_vars: &annotations.env_vars,
+        ttl: annotations.cache_ttl,
+    });
+    metadata.docker_images = images;
+
+    cache
+        .put(key, metadata, archive.path())
+        .context("Failed to store in cache")
+}
+
+/// Directory a script's outputs are relative to
+fn base_dir(script: &Path) -> &Path {
+    script
+        .parent()
+        .filter(|p| *p != Path::new(""))
+        .unwrap_or_else(|| Path::new("."))
+}
+
+#[pymodule]
+#[pyo3(name = "fabrik")]
+fn fabrik_python(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add("FabrikError", m.py().get_type::<FabrikError>())?;
+    m.add_class::<Cache>()?;
+    m.add_class::<RunResult>()?;
+    m.add_function(wrap_pyfunction!(cache_key, m)?)?;
+    m.add_function(wrap_pyfunction!(run_cached, m)?)?;
+    Ok(())
+}
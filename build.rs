@@ -1,8 +1,12 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Descriptor sets of each proto group, served by gRPC reflection
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+
     // Compile XCBBuildService proto files
     tonic_prost_build::configure()
         .build_server(true)
         .build_client(false) // We only need the server side
+        .file_descriptor_set_path(out_dir.join("xcode_descriptor.bin"))
         .compile_protos(
             &["proto/xcode/cas.proto", "proto/xcode/keyvalue.proto"],
             &["proto/xcode"],
@@ -14,6 +18,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .build_server(true)
         .build_client(false) // We only need the server side
         .compile_well_known_types(true)
+        .file_descriptor_set_path(out_dir.join("bazel_descriptor.bin"))
         .extern_path(".google.protobuf", "::prost_types")
         .extern_path(".google.rpc.Status", "crate::bazel::rpc_status::Status")
         .compile_protos(
@@ -28,8 +33,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_prost_build::configure()
         .build_server(true)
         .build_client(true) // We need both server and client for P2P
+        .file_descriptor_set_path(out_dir.join("p2p_descriptor.bin"))
         .compile_protos(&["proto/p2p.proto"], &["proto"])?;
 
+    // Compile Layer 2 cluster proto files
+    tonic_prost_build::configure()
+        .build_server(true)
+        .build_client(true) // Nodes are both servers and clients of each other
+        .file_descriptor_set_path(out_dir.join("cluster_descriptor.bin"))
+        .compile_protos(&["proto/cluster.proto"], &["proto"])?;
+
     // Generate C header file using cbindgen
     let crate_dir = std::env::var("CARGO_MANIFEST_DIR")?;
     let output_file = std::path::Path::new(&crate_dir)
@@ -51,6 +64,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("cargo:rerun-if-changed=src/capi/mod.rs");
     println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=proto");
 
     // Link AppKit framework on macOS (required for notify-rust)
     #[cfg(target_os = "macos")]
@@ -55,6 +55,14 @@ export default defineConfig({
                 text: "Xcode",
                 link: "/cache/build-systems/xcode",
               },
+              {
+                text: "S3 API",
+                link: "/cache/build-systems/s3",
+              },
+              {
+                text: "WebDAV",
+                link: "/cache/build-systems/webdav",
+              },
             ],
           },
           {
@@ -106,6 +114,7 @@ export default defineConfig({
           { text: "Configuration File", link: "/reference/config-file" },
           { text: "API Reference", link: "/reference/api" },
           { text: "C API", link: "/reference/c-api" },
+          { text: "Python API", link: "/reference/python" },
         ],
       },
     ],
@@ -27,8 +27,8 @@ console.log("[fabrik] Read", selfContent.length, "bytes from cache-api-demo.js")
 
 // Example 5: Using Fabrik.exec() to run commands
 console.log("[fabrik] Running command with Fabrik.exec()...");
-const exitCode = await Fabrik.exec("echo", ["Hello from Fabrik!"]);
-console.log("[fabrik] Command exit code:", exitCode);
+const { code, stdout } = await Fabrik.exec("echo", ["Hello from Fabrik!"]);
+console.log("[fabrik] Command exit code:", code, "output:", stdout.trim());
 
 // Example 6: Using Fabrik.writeFile() (accepts strings like Node.js)
 // Parent directories are created automatically
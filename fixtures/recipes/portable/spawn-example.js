@@ -5,11 +5,12 @@ console.log("[fabrik] Testing Fabrik.exec functionality...");
 
 // Execute a simple echo command using Fabrik.exec
 console.log("[fabrik] Running: echo 'Hello from Fabrik.exec'");
-const exitCode = await Fabrik.exec("echo", ["Hello from Fabrik.exec"]);
+const { code, stdout } = await Fabrik.exec("echo", ["Hello from Fabrik.exec"]);
 
-if (exitCode !== 0) {
-  throw new Error(`Command failed with exit code ${exitCode}`);
+if (code !== 0) {
+  throw new Error(`Command failed with exit code ${code}`);
 }
 
-console.log("[fabrik] Command executed successfully with exit code:", exitCode);
+console.log("[fabrik] Command printed:", stdout.trim());
+console.log("[fabrik] Command executed successfully with exit code:", code);
 console.log("[fabrik] Exec test complete!");
@@ -45,6 +45,8 @@
  * ## Memory Management
  *
  * - All cache handles must be freed with `fabrik_cache_free()`
+ * - Buffers from `fabrik_cache_get_alloc()` must be freed with `fabrik_free_buffer()`
+ * - Readers from `fabrik_cache_open()` must be closed with `fabrik_reader_close()`
  * - Error strings are owned by the library and should not be freed
  * - All functions are thread-safe
  *
@@ -100,6 +102,11 @@
  */
 typedef struct FabrikFilesystemStorage FabrikFilesystemStorage;
 
+/*
+ Opaque handle to an artifact opened for streaming reads
+ */
+typedef struct FabrikFabrikReader FabrikFabrikReader;
+
 /*
  Opaque handle to a Fabrik cache instance
  */
@@ -146,11 +153,55 @@ struct FabrikFabrikCache *fabrik_cache_init_with_eviction(const char *aCacheDir,
                                                           int aEvictionPolicy,
                                                           uint64_t aTtlSeconds);
 
+/*
+ Initialize a new Fabrik cache instance without RocksDB, for small caches
+
+ Metadata is kept in a single append-only log, which opens faster and needs no
+ background threads. Least recently used objects are evicted beyond `max_objects`.
+ A cache directory can't be opened with both this and the other init functions.
+
+ # Arguments
+ * `cache_dir` - Path to cache directory (NULL-terminated C string)
+ * `max_objects` - Maximum number of cached objects (0 for default: 10000)
+
+ # Returns
+ * Pointer to FabrikCache on success
+ * NULL on error (use `fabrik_last_error()` to get error message)
+
+ # Safety
+ * `cache_dir` must be a valid NULL-terminated C string
+ * Returned pointer must be freed with `fabrik_cache_free()`
+ */
+struct FabrikFabrikCache *fabrik_cache_init_embedded(const char *aCacheDir, uintptr_t aMaxObjects);
+
+/*
+ Connect to a running daemon instead of opening its cache directory
+
+ The daemon holds the lock of its cache directory, so `fabrik_cache_init()` can't
+ open it while the daemon runs. The handle returned here sends each get, put and
+ exists to the daemon's HTTP API instead. Hashes must be hex-encoded, as the daemon
+ decodes them; `fabrik_cache_delete()` isn't supported.
+
+ # Arguments
+ * `url_or_socket` - Daemon's HTTP address (`http://127.0.0.1:7070`) or HTTP socket
+   (`/path/to/http.sock`, `unix:<path>` or `@name`), as a NULL-terminated C string
+
+ # Returns
+ * Pointer to FabrikCache on success
+ * NULL if the daemon can't be reached (use `fabrik_last_error()` to get error message)
+
+ # Safety
+ * `url_or_socket` must be a valid NULL-terminated C string
+ * Returned pointer must be freed with `fabrik_cache_free()`
+ */
+struct FabrikFabrikCache *fabrik_client_connect(const char *aUrlOrSocket);
+
 /*
  Free a Fabrik cache instance
 
  # Safety
- * `cache` must be a valid pointer returned by `fabrik_cache_init()`
+ * `cache` must be a valid pointer returned by `fabrik_cache_init()` or
+   `fabrik_client_connect()`
  * Must not be used after calling this function
  */
 fabrik_ void fabrik_cache_free(struct FabrikFabrikCache *aCache);
@@ -163,12 +214,14 @@ fabrik_ void fabrik_cache_free(struct FabrikFabrikCache *aCache);
  * `hash` - Content hash (NULL-terminated C string)
  * `output_buffer` - Buffer to write data (must be pre-allocated)
  * `buffer_size` - Size of output buffer
- * `bytes_written` - Output: actual bytes written
+ * `bytes_written` - Output: actual bytes written, or the size of the artifact if the
+   buffer is too small
 
  # Returns
  * `FABRIK_OK` on success
  * `FABRIK_ERROR_NOT_FOUND` if artifact not found
- * `FABRIK_ERROR` on other errors
+ * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+ * `FABRIK_ERROR` on other errors (including a buffer that is too small)
 
  # Safety
  * All pointers must be valid
@@ -181,6 +234,103 @@ int fabrik_cache_get(struct FabrikFabrikCache *aCache,
                      uintptr_t aBufferSize,
                      uintptr_t *aBytesWritten);
 
+/*
+ Get the size of an artifact, e.g. to allocate the buffer of `fabrik_cache_get()`
+
+ # Arguments
+ * `cache` - Cache instance
+ * `hash` - Content hash (NULL-terminated C string)
+ * `size` - Output: size of the artifact in bytes
+
+ # Returns
+ * `FABRIK_OK` on success
+ * `FABRIK_ERROR_NOT_FOUND` if artifact not found
+ * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+ * `FABRIK_ERROR` on other errors
+
+ # Safety
+ * All pointers must be valid
+ */
+int fabrik_cache_get_size(struct FabrikFabrikCache *aCache, const char *aHash, uintptr_t *aSize);
+
+/*
+ Get an artifact from the cache into a buffer allocated by the library
+
+ # Arguments
+ * `cache` - Cache instance
+ * `hash` - Content hash (NULL-terminated C string)
+ * `data` - Output: the artifact's data
+ * `data_len` - Output: length of data in bytes
+
+ # Returns
+ * `FABRIK_OK` on success
+ * `FABRIK_ERROR_NOT_FOUND` if artifact not found
+ * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+ * `FABRIK_ERROR` on other errors
+
+ # Safety
+ * All pointers must be valid
+ * On success, `*data` must be freed with `fabrik_free_buffer(*data, *data_len)`
+ */
+int fabrik_cache_get_alloc(struct FabrikFabrikCache *aCache,
+                           const char *aHash,
+                           uint8_t **aData,
+                           uintptr_t *aDataLen);
+
+/*
+ Open an artifact for reading in pieces, without loading it into memory
+
+ # Arguments
+ * `cache` - Cache instance
+ * `hash` - Content hash (NULL-terminated C string)
+ * `reader` - Output: reader of the artifact
+
+ # Returns
+ * `FABRIK_OK` on success
+ * `FABRIK_ERROR_NOT_FOUND` if artifact not found
+ * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+ * `FABRIK_ERROR` on other errors
+
+ # Safety
+ * All pointers must be valid
+ * On success, `*reader` must be closed with `fabrik_reader_close()`
+ */
+int fabrik_cache_open(struct FabrikFabrikCache *aCache,
+                      const char *aHash,
+                      struct FabrikFabrikReader **aReader);
+
+/*
+ Read the next bytes of an opened artifact
+
+ # Arguments
+ * `reader` - Reader returned by `fabrik_cache_open()`
+ * `buffer` - Buffer to write data
+ * `buffer_size` - Size of buffer
+ * `bytes_read` - Output: bytes written to the buffer, 0 at the end of the artifact
+
+ # Returns
+ * `FABRIK_OK` on success
+ * `FABRIK_ERROR_IO` if the artifact can't be read (e.g. it was evicted)
+ * `FABRIK_ERROR` on other errors
+
+ # Safety
+ * All pointers must be valid
+ * `buffer` must have at least `buffer_size` bytes allocated
+ */
+int fabrik_reader_read(struct FabrikFabrikReader *aReader,
+                       uint8_t *aBuffer,
+                       uintptr_t aBufferSize,
+                       uintptr_t *aBytesRead);
+
+/*
+ Close a reader
+
+ # Safety
+ * `reader` must be a valid pointer returned by `fabrik_cache_open()`
+ * Must not be used after calling this function
+ */
+void fabrik_reader_close(struct FabrikFabrikReader *aReader);
+
 /*
  Put an artifact into the cache
 
@@ -192,7 +342,8 @@ int fabrik_cache_get(struct FabrikFabrikCache *aCache,
 
  # Returns
  * `FABRIK_OK` on success
- * `FABRIK_ERROR` on error
+ * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+ * `FABRIK_ERROR` on other errors
 
  # Safety
  * All pointers must be valid
@@ -214,7 +365,8 @@ int fabrik_cache_put(struct FabrikFabrikCache *aCache,
 
  # Returns
  * `FABRIK_OK` on success
- * `FABRIK_ERROR` on error
+ * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+ * `FABRIK_ERROR` on other errors
 
  # Safety
  * All pointers must be valid
@@ -230,7 +382,8 @@ fabrik_ int fabrik_cache_exists(struct FabrikFabrikCache *aCache, const char *aH
 
  # Returns
  * `FABRIK_OK` on success
- * `FABRIK_ERROR` on error
+ * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+ * `FABRIK_ERROR` on other errors
 
  # Safety
  * All pointers must be valid
@@ -258,6 +411,15 @@ fabrik_ const char *fabrik_last_error(void);
  */
 fabrik_ void fabrik_free_string(char *aS);
 
+/*
+ Free a buffer allocated by the Fabrik library
+
+ # Safety
+ * `buffer` and `len` must be a buffer and its length returned by
+   `fabrik_cache_get_alloc()`
+ */
+void fabrik_free_buffer(uint8_t *aBuffer, uintptr_t aLen);
+
 /*
  Get the library version
 
@@ -1,4 +1,8 @@
+pub mod keys;
+pub mod layer;
 pub mod provider;
+pub mod scopes;
+pub mod service;
 pub mod token;
 
 pub use provider::AuthProvider;
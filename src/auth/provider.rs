@@ -498,6 +498,49 @@ impl AuthProvider {
         }
     }
 
+    /// Store a machine token in the OAuth2 storage backend, under a key derived from `name`
+    ///
+    /// Returns the storage key. Machine tokens are never used to authenticate this
+    /// client; they are kept so they can be looked up again after creation.
+    pub async fn save_machine_token(
+        &self,
+        name: &str,
+        access_token: &str,
+        expires_at: Option<u64>,
+    ) -> Result<String, AuthenticationError> {
+        let wrapper = self
+            .oauth2_wrapper
+            .as_ref()
+            .ok_or(AuthenticationError::ConfigError(
+                "No token storage configured (set auth.oauth2.storage)".to_string(),
+            ))?
+            .clone();
+
+        let token_key = format!(
+            "{}:fabrik:machine:{}",
+            self.oauth2_url.as_ref().expect("OAuth2 URL should be set"),
+            name
+        );
+        let token: Token = serde_json::from_value(serde_json::json!({
+            "access_token": access_token,
+            "token_type": "Bearer",
+            "expires_at": expires_at,
+        }))
+        .map_err(|e| AuthenticationError::OAuth2Error(format!("Invalid token: {}", e)))?;
+
+        let key = token_key.clone();
+        tokio::task::spawn_blocking(move || wrapper.save_token(&key, token))
+            .await
+            .map_err(|e| AuthenticationError::OAuth2Error(format!("Task join error: {}", e)))??;
+
+        Ok(token_key)
+    }
+
+    /// Name of the storage backend tokens are saved to, if any
+    pub fn storage_backend(&self) -> Option<&str> {
+        self.config.oauth2.as_ref().map(|o| o.storage.as_str())
+    }
+
     /// Create a preview of the token (first 8 and last 4 characters)
     fn preview_token(token: &str) -> Option<String> {
         if token.len() > 12 {
@@ -0,0 +1,305 @@
+/// Scope/claim-based authorization
+///
+/// Maps JWT scopes to the operations a token may perform on each service, so that
+/// e.g. CI runners building fork PRs can be given read-only tokens.
+///
+/// Scopes have the form `<target>:<permission>`:
+/// - `cache:read`, `cache:write`, `cache:admin` apply to every service
+/// - `<service>:<permission>` (e.g. `bazel:write`, `gradle:read`) applies to one service
+///
+/// Permissions are hierarchical: `admin` implies `write`, which implies `read`.
+///
+/// The authentication layer attaches [`Grants`] to each validated request (as a request
+/// extension); protocol handlers then check them with [`authorize`]. Requests without
+/// grants (authentication disabled) are allowed.
+use axum::http::Method;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Scope target that applies to every service
+pub const ALL_SERVICES: &str = "cache";
+
+/// Service identifiers used in scopes
+#[allow(dead_code)]
+pub mod services {
+    pub const BAZEL: &str = "bazel";
+    pub const XCODE: &str = "xcode";
+    pub const GRADLE: &str = "gradle";
+    pub const NX: &str = "nx";
+    pub const TURBOREPO: &str = "turborepo";
+    pub const METRO: &str = "metro";
+    pub const FABRIK: &str = "fabrik";
+    pub const S3: &str = "s3";
+    pub const WEBDAV: &str = "webdav";
+}
+
+/// Permission level granted by a scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Permission {
+    /// Read artifacts (GET, EXISTS, FindMissingBlobs, ...)
+    Read,
+    /// Store artifacts (PUT, UpdateActionResult, ...)
+    Write,
+    /// Destructive and management operations (DELETE, ...)
+    Admin,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Admin => "admin",
+        }
+    }
+
+    /// Permission required for an HTTP request method
+    pub fn for_http_method(method: &Method) -> Self {
+        match *method {
+            Method::GET | Method::HEAD | Method::OPTIONS => Permission::Read,
+            Method::DELETE => Permission::Admin,
+            _ => Permission::Write,
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Permission {
+    type Err = AuthorizationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Permission::Read),
+            "write" => Ok(Permission::Write),
+            "admin" => Ok(Permission::Admin),
+            _ => Err(AuthorizationError::InvalidScope(s.to_string())),
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AuthorizationError {
+    #[error("Invalid scope: {0} (expected <service>:<read|write|admin>)")]
+    InvalidScope(String),
+
+    #[error("Token lacks {permission} permission for {service}")]
+    Forbidden {
+        service: String,
+        permission: Permission,
+    },
+}
+
+/// Authorization-relevant JWT claims
+///
+/// Supports both the OAuth2 `scope` claim (space-separated string) and a `scopes` array.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[allow(dead_code)]
+pub struct Claims {
+    #[serde(default)]
+    pub sub: Option<String>,
+
+    #[serde(default)]
+    pub scope: Option<String>,
+
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+impl Claims {
+    /// All scopes carried by the token
+    #[allow(dead_code)]
+    pub fn scope_list(&self) -> Vec<String> {
+        let mut scopes: Vec<String> = self
+            .scope
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|s| s.to_string())
+            .collect();
+        scopes.extend(self.scopes.iter().cloned());
+        scopes
+    }
+}
+
+/// Permissions granted to a token, per service
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Grants {
+    all_services: Option<Permission>,
+    services: HashMap<String, Permission>,
+}
+
+impl Grants {
+    /// Build grants from a list of scopes
+    ///
+    /// Scopes that don't follow the `<target>:<permission>` format are rejected so that
+    /// typos in token issuance surface instead of silently granting nothing.
+    pub fn from_scopes<I, T>(scopes: I) -> Result<Self, AuthorizationError>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let mut grants = Self::default();
+
+        for scope in scopes {
+            let scope = scope.as_ref();
+            let (target, permission) = scope
+                .split_once(':')
+                .ok_or_else(|| AuthorizationError::InvalidScope(scope.to_string()))?;
+            let permission: Permission = permission
+                .parse()
+                .map_err(|_| AuthorizationError::InvalidScope(scope.to_string()))?;
+
+            if target == ALL_SERVICES {
+                grants.all_services = grants.all_services.max(Some(permission));
+            } else {
+                let entry = grants
+                    .services
+                    .entry(target.to_string())
+                    .or_insert(permission);
+                *entry = (*entry).max(permission);
+            }
+        }
+
+        Ok(grants)
+    }
+
+    /// Build grants from token claims, falling back to `default_scopes` when the
+    /// token carries no scopes (tokens issued before scopes were introduced)
+    #[allow(dead_code)]
+    pub fn from_claims(
+        claims: &Claims,
+        default_scopes: &[String],
+    ) -> Result<Self, AuthorizationError> {
+        let scopes = claims.scope_list();
+        if scopes.is_empty() {
+            Self::from_scopes(default_scopes)
+        } else {
+            Self::from_scopes(scopes)
+        }
+    }
+
+    /// Highest permission granted for a service
+    pub fn permission_for(&self, service: &str) -> Option<Permission> {
+        self.all_services.max(self.services.get(service).copied())
+    }
+
+    /// Whether `permission` is granted for `service`
+    pub fn allows(&self, service: &str, permission: Permission) -> bool {
+        self.permission_for(service)
+            .is_some_and(|granted| granted >= permission)
+    }
+}
+
+/// Check that the grants attached to a request (if any) allow an operation
+///
+/// Returns Ok when no grants are attached, i.e. authentication is disabled.
+pub fn authorize(
+    grants: Option<&Grants>,
+    service: &str,
+    permission: Permission,
+) -> Result<(), AuthorizationError> {
+    match grants {
+        Some(grants) if !grants.allows(service, permission) => Err(AuthorizationError::Forbidden {
+            service: service.to_string(),
+            permission,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Authorize a gRPC request, mapping failures to `PERMISSION_DENIED`
+#[allow(clippy::result_large_err)]
+pub fn authorize_grpc<T>(
+    request: &tonic::Request<T>,
+    service: &str,
+    permission: Permission,
+) -> Result<(), tonic::Status> {
+    authorize(request.extensions().get::<Grants>(), service, permission)
+        .map_err(|e| tonic::Status::permission_denied(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permission_hierarchy() {
+        let grants = Grants::from_scopes(["cache:write"]).unwrap();
+        assert!(grants.allows(services::BAZEL, Permission::Read));
+        assert!(grants.allows(services::BAZEL, Permission::Write));
+        assert!(!grants.allows(services::BAZEL, Permission::Admin));
+    }
+
+    #[test]
+    fn test_read_only_token() {
+        let grants = Grants::from_scopes(["cache:read"]).unwrap();
+        assert!(grants.allows(services::GRADLE, Permission::Read));
+        assert!(!grants.allows(services::GRADLE, Permission::Write));
+
+        let err = authorize(Some(&grants), services::GRADLE, Permission::Write).unwrap_err();
+        assert_eq!(
+            err,
+            AuthorizationError::Forbidden {
+                service: "gradle".to_string(),
+                permission: Permission::Write,
+            }
+        );
+    }
+
+    #[test]
+    fn test_service_specific_scopes() {
+        let grants = Grants::from_scopes(["cache:read", "bazel:write"]).unwrap();
+        assert!(grants.allows(services::BAZEL, Permission::Write));
+        assert!(!grants.allows(services::XCODE, Permission::Write));
+        assert!(grants.allows(services::XCODE, Permission::Read));
+        assert!(!grants.allows(services::BAZEL, Permission::Admin));
+    }
+
+    #[test]
+    fn test_invalid_scope() {
+        assert!(Grants::from_scopes(["cache"]).is_err());
+        assert!(Grants::from_scopes(["cache:delete"]).is_err());
+    }
+
+    #[test]
+    fn test_claims_scope_formats() {
+        let claims: Claims =
+            serde_json::from_str(r#"{"sub": "ci", "scope": "cache:read nx:write"}"#).unwrap();
+        assert_eq!(claims.scope_list(), vec!["cache:read", "nx:write"]);
+
+        let claims: Claims = serde_json::from_str(r#"{"scopes": ["cache:admin"]}"#).unwrap();
+        let grants = Grants::from_claims(&claims, &[]).unwrap();
+        assert!(grants.allows(services::METRO, Permission::Admin));
+    }
+
+    #[test]
+    fn test_unscoped_token_uses_defaults() {
+        let claims = Claims::default();
+        let grants = Grants::from_claims(&claims, &["cache:read".to_string()]).unwrap();
+        assert!(grants.allows(services::TURBOREPO, Permission::Read));
+        assert!(!grants.allows(services::TURBOREPO, Permission::Write));
+    }
+
+    #[test]
+    fn test_no_grants_allows_everything() {
+        assert!(authorize(None, services::BAZEL, Permission::Admin).is_ok());
+    }
+
+    #[test]
+    fn test_permission_for_http_method() {
+        assert_eq!(Permission::for_http_method(&Method::GET), Permission::Read);
+        assert_eq!(Permission::for_http_method(&Method::PUT), Permission::Write);
+        assert_eq!(
+            Permission::for_http_method(&Method::DELETE),
+            Permission::Admin
+        );
+    }
+}
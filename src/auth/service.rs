@@ -0,0 +1,176 @@
+/// Client for the token API of the Fabrik service (root `url`)
+///
+/// Machine tokens are minted by the service on behalf of an authenticated user, so CI
+/// credentials can be created from the terminal instead of the dashboard:
+///
+/// ```text
+/// POST {url}/api/fabrik/tokens
+/// Authorization: Bearer <user token>
+/// {"name": "ci", "scopes": ["cache:read"], "expires_in": 2592000}
+///
+/// 201 Created
+/// {"token": "...", "expires_at": 1767225600}
+/// ```
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::scopes::Grants;
+use crate::error::{FabrikError, Result, ResultExt};
+use crate::eviction::EvictionConfig;
+
+/// Path of the token API, relative to the service URL
+pub const TOKENS_PATH: &str = "/api/fabrik/tokens";
+
+/// Timeout of requests to the service
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Machine token to mint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Lifetime in seconds
+    pub expires_in: u64,
+}
+
+impl TokenRequest {
+    /// Validate scopes and parse the lifetime (e.g. `30d`)
+    pub fn new(name: String, scopes: Vec<String>, ttl: &str) -> Result<Self> {
+        Grants::from_scopes(&scopes).map_err(|e| FabrikError::config(e.to_string()))?;
+        let expires_in = EvictionConfig::parse_ttl(ttl)
+            .ok()
+            .filter(|secs| *secs > 0)
+            .ok_or_else(|| FabrikError::config(format!("Invalid token TTL: {}", ttl)))?;
+
+        Ok(Self {
+            name,
+            scopes,
+            expires_in,
+        })
+    }
+}
+
+/// Machine token minted by the service
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MachineToken {
+    pub token: String,
+    /// Unix timestamp, if the service reports one
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+/// Mint a machine token, authenticating as the user holding `bearer`
+pub async fn create_token(
+    service_url: &str,
+    bearer: &str,
+    request: &TokenRequest,
+) -> Result<MachineToken> {
+    let url = tokens_url(service_url);
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .upstream_context("Failed to create HTTP client")?;
+
+    let response = client
+        .post(&url)
+        .bearer_auth(bearer)
+        .json(request)
+        .send()
+        .await
+        .upstream_context(&format!("Failed to reach {}", url))?;
+    let status = response.status().as_u16();
+    let body = response
+        .text()
+        .await
+        .upstream_context("Failed to read token API response")?;
+
+    if !(200..300).contains(&status) {
+        return Err(status_error(status, &body));
+    }
+    serde_json::from_str(&body)
+        .map_err(|e| FabrikError::corrupt(format!("Unexpected token API response: {}", e)))
+}
+
+/// Token API URL of a service
+fn tokens_url(service_url: &str) -> String {
+    format!("{}{}", service_url.trim_end_matches('/'), TOKENS_PATH)
+}
+
+/// Classify an error response of the token API
+fn status_error(status: u16, body: &str) -> FabrikError {
+    let detail = body.trim();
+    let message = if detail.is_empty() {
+        format!("Token API returned HTTP {}", status)
+    } else {
+        format!("Token API returned HTTP {}: {}", status, detail)
+    };
+
+    match status {
+        401 | 403 => FabrikError::auth_failed(format!(
+            "{} (run `fabrik auth login` with an account allowed to create tokens)",
+            message
+        )),
+        404 => FabrikError::not_found(format!(
+            "{} (the service doesn't support machine tokens)",
+            message
+        )),
+        429 => FabrikError::QuotaExceeded(message),
+        400..=499 => FabrikError::config(message),
+        _ => FabrikError::unavailable(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_request() {
+        let request = TokenRequest::new("ci".into(), vec!["cache:read".into()], "30d").unwrap();
+        assert_eq!(request.expires_in, 30 * 24 * 60 * 60);
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({"name": "ci", "scopes": ["cache:read"], "expires_in": 2592000})
+        );
+
+        assert!(TokenRequest::new("ci".into(), vec!["cache:readonly".into()], "30d").is_err());
+        assert!(TokenRequest::new("ci".into(), vec!["cache:read".into()], "soon").is_err());
+        assert!(TokenRequest::new("ci".into(), vec!["cache:read".into()], "0d").is_err());
+    }
+
+    #[test]
+    fn test_tokens_url() {
+        assert_eq!(
+            tokens_url("https://tuist.dev/"),
+            "https://tuist.dev/api/fabrik/tokens"
+        );
+        assert_eq!(
+            tokens_url("https://tuist.dev"),
+            "https://tuist.dev/api/fabrik/tokens"
+        );
+    }
+
+    #[test]
+    fn test_status_error() {
+        assert!(matches!(status_error(401, ""), FabrikError::AuthFailed(_)));
+        assert!(matches!(status_error(404, ""), FabrikError::NotFound(_)));
+        assert!(matches!(
+            status_error(422, "{\"error\": \"invalid scope\"}"),
+            FabrikError::Config(m) if m.contains("invalid scope")
+        ));
+        assert!(status_error(503, "").is_transient());
+    }
+
+    #[test]
+    fn test_parse_token() {
+        let token: MachineToken =
+            serde_json::from_str(r#"{"token": "abc", "id": "tok_1"}"#).unwrap();
+        assert_eq!(
+            token,
+            MachineToken {
+                token: "abc".into(),
+                expires_at: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,510 @@
+/// Snapshot backups of Layer 2 caches (`fabrik server backup` and `fabrik server restore`)
+///
+/// A backup is a consistent snapshot of the cache (see `storage::snapshot`) uploaded to a
+/// directory or an S3 bucket:
+///
+/// - `objects/<algorithm>/<checksum>`: the objects, content-addressed, so each is only
+///   uploaded once across backups (objects the previous backup has are skipped)
+/// - `snapshots/<name>/`: the metadata checkpoint and `manifest.json.zst`, mapping the
+///   snapshot's object IDs to their object keys
+/// - `latest`: the name of the newest complete snapshot, written last
+///
+/// Restoring downloads a snapshot into an empty cache directory and verifies every object
+/// against its checksum; objects that are gone or don't match are dropped from the
+/// restored metadata.
+mod store;
+
+pub use store::BackupStore;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+use crate::hashing::HashAlgorithm;
+use crate::storage::snapshot::{self, SnapshotObject};
+use crate::storage::{FilesystemStorage, Storage, TierConfig};
+
+/// Version of the manifest format
+const MANIFEST_VERSION: u32 = 1;
+
+const MANIFEST_FILE: &str = "manifest.json.zst";
+
+/// Key holding the name of the newest snapshot
+const LATEST_KEY: &str = "latest";
+
+/// Index of a snapshot's files and objects
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub fabrik_version: String,
+    /// Files of the snapshot, under `snapshots/<name>/`
+    pub files: Vec<String>,
+    pub objects: Vec<ManifestObject>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestObject {
+    /// Object ID, hex-encoded
+    pub id: String,
+    /// Key of the object's contents in the store
+    pub key: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BackupStats {
+    pub snapshot: String,
+    /// Objects in the snapshot
+    pub objects: u64,
+    pub uploaded: u64,
+    pub uploaded_bytes: u64,
+    /// Objects already in the store from an earlier backup
+    pub unchanged: u64,
+    /// Objects evicted while the snapshot was uploaded (left out)
+    pub missing: u64,
+    /// Objects that don't match their checksum (left out)
+    pub corrupt: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RestoreStats {
+    pub snapshot: String,
+    pub restored: u64,
+    pub restored_bytes: u64,
+    /// Objects missing from the store or not matching their checksum (dropped)
+    pub missing: u64,
+}
+
+fn snapshot_key(name: &str, file: &str) -> String {
+    format!("snapshots/{}/{}", name, file)
+}
+
+fn object_key(algorithm: HashAlgorithm, checksum: &[u8; 32]) -> String {
+    format!("objects/{}/{}", algorithm, hex::encode(checksum))
+}
+
+/// Algorithm and checksum of an object key
+fn parse_object_key(key: &str) -> Option<(HashAlgorithm, [u8; 32])> {
+    let (algorithm, checksum) = key.strip_prefix("objects/")?.split_once('/')?;
+    let algorithm = algorithm.parse().ok()?;
+    let checksum = hex::decode(checksum).ok()?.try_into().ok()?;
+    Some((algorithm, checksum))
+}
+
+/// Read a snapshot's manifest, None if there's no such snapshot
+pub async fn load_manifest(store: &BackupStore, name: &str) -> Result<Option<Manifest>> {
+    let Some(data) = store.get(&snapshot_key(name, MANIFEST_FILE)).await? else {
+        return Ok(None);
+    };
+    let json = zstd::decode_all(data.as_slice())
+        .with_context(|| format!("Manifest of snapshot {} is corrupt", name))?;
+    let manifest: Manifest = serde_json::from_slice(&json)
+        .with_context(|| format!("Manifest of snapshot {} is corrupt", name))?;
+    if manifest.version > MANIFEST_VERSION {
+        anyhow::bail!(
+            "Snapshot {} was written by a newer Fabrik ({}); upgrade to restore it",
+            name,
+            manifest.fabrik_version
+        );
+    }
+    Ok(Some(manifest))
+}
+
+/// Name of the newest snapshot in the store
+pub async fn latest_snapshot(store: &BackupStore) -> Result<Option<String>> {
+    Ok(store
+        .get(LATEST_KEY)
+        .await?
+        .map(|name| String::from_utf8_lossy(&name).trim().to_string())
+        .filter(|name| !name.is_empty()))
+}
+
+enum Uploaded {
+    Object(ManifestObject, Option<u64>),
+    Missing,
+    Corrupt,
+}
+
+/// Upload one object of a snapshot, unless the store already has its contents
+async fn upload_object(
+    store: &BackupStore,
+    object: SnapshotObject,
+    previous: &HashSet<String>,
+) -> Result<Uploaded> {
+    // Objects with a known checksum the previous backup has aren't even read
+    if let Some(checksum) = &object.checksum {
+        let key = object_key(object.checksum_algorithm, checksum);
+        if previous.contains(&key) {
+            return Ok(Uploaded::Object(
+                ManifestObject {
+                    id: hex::encode(&object.id),
+                    key,
+                    size: object.size,
+                },
+                None,
+            ));
+        }
+    }
+
+    let data = match object.read().await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Uploaded::Missing),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", object.path.display()))
+        }
+    };
+    // Objects written before checksums are keyed by their SHA-256
+    let (algorithm, checksum) = match object.checksum {
+        Some(checksum) => (object.checksum_algorithm, checksum),
+        None => (HashAlgorithm::Sha256, HashAlgorithm::Sha256.digest(&data)),
+    };
+    if algorithm.digest(&data) != checksum {
+        warn!(
+            "Object {} doesn't match its checksum, leaving it out of the backup",
+            hex::encode(&object.id)
+        );
+        return Ok(Uploaded::Corrupt);
+    }
+
+    let key = object_key(algorithm, &checksum);
+    let size = data.len() as u64;
+    let uploaded = if previous.contains(&key) {
+        None
+    } else {
+        store.put(&key, data).await?;
+        Some(size)
+    };
+    Ok(Uploaded::Object(
+        ManifestObject {
+            id: hex::encode(&object.id),
+            key,
+            size,
+        },
+        uploaded,
+    ))
+}
+
+/// Snapshot `storage` (whose directory is `cache_dir`) and upload it to `store`
+pub async fn backup(
+    storage: Arc<FilesystemStorage>,
+    cache_dir: &Path,
+    store: Arc<BackupStore>,
+    jobs: usize,
+) -> Result<BackupStats> {
+    let created_at = Utc::now();
+    let name = created_at.format("%Y%m%dT%H%M%S%.3fZ").to_string();
+
+    // In the cache directory, so the checkpoint hard-links the SST files
+    let dir = tempfile::Builder::new()
+        .prefix(".snapshot-")
+        .tempdir_in(cache_dir)
+        .context("Failed to create snapshot directory")?;
+    let snapshot = {
+        let storage = storage.clone();
+        let path = dir.path().to_path_buf();
+        tokio::task::spawn_blocking(move || storage.snapshot(&path))
+            .await
+            .context("Snapshot task failed")?
+            .context("Failed to snapshot the cache")?
+    };
+
+    let previous: Arc<HashSet<String>> = Arc::new(match latest_snapshot(&store).await? {
+        Some(latest) => load_manifest(&store, &latest)
+            .await?
+            .map(|manifest| manifest.objects.into_iter().map(|o| o.key).collect())
+            .unwrap_or_default(),
+        None => HashSet::new(),
+    });
+
+    let mut stats = BackupStats {
+        snapshot: name.clone(),
+        corrupt: snapshot.corrupt,
+        ..Default::default()
+    };
+    let mut objects = Vec::with_capacity(snapshot.objects.len());
+    let mut workers = JoinSet::new();
+    let mut pending = snapshot.objects.clone().into_iter();
+    loop {
+        while workers.len() < jobs.max(1) {
+            let Some(object) = pending.next() else {
+                break;
+            };
+            let store = store.clone();
+            let previous = previous.clone();
+            workers.spawn(async move { upload_object(&store, object, &previous).await });
+        }
+        let Some(result) = workers.join_next().await else {
+            break;
+        };
+        match result.context("Upload task failed")?? {
+            Uploaded::Object(object, uploaded) => {
+                match uploaded {
+                    Some(size) => {
+                        stats.uploaded += 1;
+                        stats.uploaded_bytes += size;
+                    }
+                    None => stats.unchanged += 1,
+                }
+                objects.push(object);
+            }
+            Uploaded::Missing => stats.missing += 1,
+            Uploaded::Corrupt => stats.corrupt += 1,
+        }
+    }
+    objects.sort_by(|a, b| a.id.cmp(&b.id));
+    stats.objects = objects.len() as u64;
+
+    for file in &snapshot.files {
+        let data = tokio::fs::read(dir.path().join(file))
+            .await
+            .with_context(|| format!("Failed to read snapshot file {}", file))?;
+        store.put(&snapshot_key(&name, file), data).await?;
+    }
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        name: name.clone(),
+        created_at,
+        fabrik_version: env!("CARGO_PKG_VERSION").to_string(),
+        files: snapshot.files.clone(),
+        objects,
+    };
+    let json = serde_json::to_vec(&manifest)?;
+    let compressed = zstd::encode_all(json.as_slice(), 3).context("Failed to compress manifest")?;
+    store
+        .put(&snapshot_key(&name, MANIFEST_FILE), compressed)
+        .await?;
+    // Only now is the snapshot complete
+    store.put(LATEST_KEY, name.into_bytes()).await?;
+
+    Ok(stats)
+}
+
+/// Download one object of a snapshot and write it into `storage`
+async fn restore_object(
+    store: &BackupStore,
+    storage: Arc<FilesystemStorage>,
+    object: ManifestObject,
+) -> Result<Option<u64>> {
+    let (Ok(id), Some((algorithm, checksum))) =
+        (hex::decode(&object.id), parse_object_key(&object.key))
+    else {
+        anyhow::bail!("Invalid object in manifest: {} ({})", object.id, object.key);
+    };
+    let Some(data) = store.get(&object.key).await? else {
+        warn!("Object {} is missing from the backup", object.id);
+        return Ok(None);
+    };
+    if algorithm.digest(&data) != checksum {
+        warn!("Object {} doesn't match its checksum", object.id);
+        return Ok(None);
+    }
+    let size = data.len() as u64;
+    tokio::task::spawn_blocking(move || storage.restore_object(&id, &data))
+        .await
+        .context("Restore task failed")??;
+    Ok(Some(size))
+}
+
+/// Restore a snapshot (the newest if `name` is None) from `store` into `cache_dir`, which
+/// mustn't hold a cache yet
+pub async fn restore(
+    cache_dir: &Path,
+    tiers: Option<TierConfig>,
+    store: Arc<BackupStore>,
+    name: Option<&str>,
+    jobs: usize,
+) -> Result<RestoreStats> {
+    snapshot::check_restore_target(cache_dir)?;
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => latest_snapshot(&store)
+            .await?
+            .with_context(|| format!("No snapshots in {}", store.describe()))?,
+    };
+    let manifest = load_manifest(&store, &name)
+        .await?
+        .with_context(|| format!("No snapshot {} in {}", name, store.describe()))?;
+
+    tokio::fs::create_dir_all(cache_dir)
+        .await
+        .with_context(|| format!("Failed to create {}", cache_dir.display()))?;
+    for file in &manifest.files {
+        let data = store
+            .get(&snapshot_key(&name, file))
+            .await?
+            .with_context(|| format!("Snapshot {} is incomplete: {} is missing", name, file))?;
+        snapshot::restore_file(cache_dir, file, &data)?;
+    }
+
+    let storage = FilesystemStorage::new(cache_dir)?;
+    let storage = Arc::new(match tiers {
+        Some(tiers) => storage.with_cold_tier(tiers)?,
+        None => storage,
+    });
+
+    let mut stats = RestoreStats {
+        snapshot: name,
+        ..Default::default()
+    };
+    let mut restored = HashSet::new();
+    let mut workers = JoinSet::new();
+    let mut pending = manifest.objects.into_iter();
+    loop {
+        while workers.len() < jobs.max(1) {
+            let Some(object) = pending.next() else {
+                break;
+            };
+            let store = store.clone();
+            let storage = storage.clone();
+            workers.spawn(async move {
+                let id = object.id.clone();
+                (id, restore_object(&store, storage, object).await)
+            });
+        }
+        let Some(result) = workers.join_next().await else {
+            break;
+        };
+        let (id, result) = result.context("Restore task failed")?;
+        match result? {
+            Some(size) => {
+                stats.restored += 1;
+                stats.restored_bytes += size;
+                restored.insert(id);
+            }
+            None => stats.missing += 1,
+        }
+    }
+
+    // Drop the metadata of objects that weren't restored (or weren't backed up)
+    let storage_for_cleanup = storage.clone();
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        for id in storage_for_cleanup.list_ids()? {
+            if !restored.contains(&hex::encode(&id)) {
+                storage_for_cleanup.delete(&id)?;
+            }
+        }
+        Ok(())
+    })
+    .await
+    .context("Restore task failed")??;
+
+    Ok(stats)
+}
+
+/// Back up the cache every `interval` (`[backup] interval`)
+pub fn spawn_scheduled(
+    storage: Arc<FilesystemStorage>,
+    cache_dir: std::path::PathBuf,
+    store: Arc<BackupStore>,
+    interval: Duration,
+    jobs: usize,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately; don't back up on startup
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            match backup(storage.clone(), &cache_dir, store.clone(), jobs).await {
+                Ok(stats) => info!(
+                    "Backed up snapshot {} to {}: {} object(s), {} uploaded, {} unchanged",
+                    stats.snapshot,
+                    store.describe(),
+                    stats.objects,
+                    stats.uploaded,
+                    stats.unchanged
+                ),
+                Err(e) => warn!("Backup to {} failed: {:#}", store.describe(), e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_backup_and_restore() {
+        let cache = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(cache.path()).unwrap());
+        storage.put(b"one", b"first object").unwrap();
+        storage.put(b"two", b"second object").unwrap();
+
+        let backups = TempDir::new().unwrap();
+        let store = Arc::new(BackupStore::Local(backups.path().to_path_buf()));
+        let stats = backup(storage.clone(), cache.path(), store.clone(), 2)
+            .await
+            .unwrap();
+        assert_eq!(stats.objects, 2);
+        assert_eq!(stats.uploaded, 2);
+        assert_eq!(stats.uploaded_bytes, 25);
+        assert_eq!(
+            latest_snapshot(&store).await.unwrap(),
+            Some(stats.snapshot.clone())
+        );
+
+        // The next backup only uploads what's new
+        storage.put(b"three", b"third object").unwrap();
+        let stats = backup(storage.clone(), cache.path(), store.clone(), 2)
+            .await
+            .unwrap();
+        assert_eq!(stats.objects, 3);
+        assert_eq!(stats.uploaded, 1);
+        assert_eq!(stats.unchanged, 2);
+
+        // An object lost from the store is dropped on restore
+        let manifest = load_manifest(&store, &stats.snapshot)
+            .await
+            .unwrap()
+            .unwrap();
+        let lost = manifest
+            .objects
+            .iter()
+            .find(|o| o.id == hex::encode(b"two"))
+            .unwrap();
+        std::fs::remove_file(store::local_path(backups.path(), &lost.key)).unwrap();
+
+        let restored = TempDir::new().unwrap();
+        let stats = restore(restored.path(), None, store.clone(), None, 2)
+            .await
+            .unwrap();
+        assert_eq!(stats.restored, 2);
+        assert_eq!(stats.missing, 1);
+
+        let target = FilesystemStorage::new(restored.path()).unwrap();
+        assert_eq!(target.get(b"one").unwrap(), Some(b"first object".to_vec()));
+        assert_eq!(
+            target.get(b"three").unwrap(),
+            Some(b"third object".to_vec())
+        );
+        assert!(!target.exists(b"two").unwrap());
+        drop(target);
+
+        // Restoring over a cache is refused
+        assert!(restore(restored.path(), None, store, None, 2)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_object_keys() {
+        let checksum = HashAlgorithm::Blake3.digest(b"data");
+        let key = object_key(HashAlgorithm::Blake3, &checksum);
+        assert_eq!(
+            parse_object_key(&key),
+            Some((HashAlgorithm::Blake3, checksum))
+        );
+        assert_eq!(parse_object_key("objects/md5/abcd"), None);
+        assert_eq!(parse_object_key("snapshots/x/manifest.json.zst"), None);
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::{Context, Result};
+use axum::http::{HeaderMap, Method};
+use bytes::Bytes;
+use chrono::Utc;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::http::{sign_request, uri_encode, S3Credentials};
+use crate::merger::MergedServerConfig;
+use crate::retry::{Protocol, RetryPolicy};
+
+/// Longest an upload or download of a single file may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Where backups are kept: a directory or an S3 bucket
+pub enum BackupStore {
+    Local(PathBuf),
+    S3(Box<S3Store>),
+}
+
+/// An S3 (or S3-compatible) bucket, signed with SigV4
+pub struct S3Store {
+    client: reqwest::Client,
+    /// `https://host` the requests go to
+    endpoint: String,
+    /// Bucket in the path rather than the host (custom endpoints, e.g. MinIO)
+    path_style: bool,
+    bucket: String,
+    prefix: String,
+    region: String,
+    credentials: S3Credentials,
+    retry: RetryPolicy,
+}
+
+impl BackupStore {
+    /// Store at `url`: `s3://bucket/prefix`, `file:///path` or a plain path
+    ///
+    /// S3 credentials, region and endpoint come from the server's S3 settings
+    /// (`--config-s3-*` or the AWS environment variables).
+    pub fn open(url: &str, config: &MergedServerConfig) -> Result<Self> {
+        let Some(location) = url.strip_prefix("s3://") else {
+            let path = url.strip_prefix("file://").unwrap_or(url);
+            return Ok(Self::Local(PathBuf::from(path)));
+        };
+
+        let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+        if bucket.is_empty() {
+            anyhow::bail!("Backup URL has no bucket: {}", url);
+        }
+        let credentials = match (&config.s3_access_key, &config.s3_secret_key) {
+            (Some(access_key), Some(secret_key)) => S3Credentials {
+                access_key: access_key.clone(),
+                secret_key: secret_key.clone(),
+            },
+            _ => anyhow::bail!(
+                "S3 backups need credentials: set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"
+            ),
+        };
+        let region = config
+            .s3_region
+            .clone()
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let (endpoint, path_style) = match &config.s3_endpoint {
+            Some(endpoint) => (endpoint.trim_end_matches('/').to_string(), true),
+            None => (
+                format!("https://{}.s3.{}.amazonaws.com", bucket, region),
+                false,
+            ),
+        };
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        Ok(Self::S3(Box::new(S3Store {
+            client,
+            endpoint,
+            path_style,
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            region,
+            credentials,
+            retry: RetryPolicy::default(),
+        })))
+    }
+
+    /// Retry failed S3 requests with `retry` (`[retry]` defaults otherwise)
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        if let Self::S3(s3) = &mut self {
+            s3.retry = retry;
+        }
+        self
+    }
+
+    /// Human-readable location of the store
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Local(dir) => dir.display().to_string(),
+            Self::S3(s3) if s3.prefix.is_empty() => format!("s3://{}", s3.bucket),
+            Self::S3(s3) => format!("s3://{}/{}", s3.bucket, s3.prefix),
+        }
+    }
+
+    pub async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        match self {
+            Self::Local(dir) => {
+                let path = local_path(dir, key);
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent)
+                        .await
+                        .with_context(|| format!("Failed to create {}", parent.display()))?;
+                }
+                // Write then rename, so an interrupted backup never leaves a partial file
+                let partial = path.with_extension("partial");
+                tokio::fs::write(&partial, data)
+                    .await
+                    .with_context(|| format!("Failed to write {}", partial.display()))?;
+                tokio::fs::rename(&partial, &path)
+                    .await
+                    .with_context(|| format!("Failed to write {}", path.display()))
+            }
+            Self::S3(s3) => s3.put(key, data).await,
+        }
+    }
+
+    /// Contents of `key`, None if it doesn't exist
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Local(dir) => {
+                let path = local_path(dir, key);
+                match tokio::fs::read(&path).await {
+                    Ok(data) => Ok(Some(data)),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+                }
+            }
+            Self::S3(s3) => s3.get(key).await,
+        }
+    }
+}
+
+pub(super) fn local_path(dir: &Path, key: &str) -> PathBuf {
+    key.split('/')
+        .fold(dir.to_path_buf(), |path, part| path.join(part))
+}
+
+impl S3Store {
+    /// URL and signed headers of a request for `key`
+    fn signed(&self, method: Method, key: &str, payload: &[u8]) -> (String, HeaderMap) {
+        let object_key = if self.prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{}", self.prefix, key)
+        };
+        let path = if self.path_style {
+            format!("/{}/{}", self.bucket, uri_encode(&object_key, false))
+        } else {
+            format!("/{}", uri_encode(&object_key, false))
+        };
+        let host = self
+            .endpoint
+            .split("://")
+            .nth(1)
+            .unwrap_or(&self.endpoint)
+            .to_string();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("host", host.parse().expect("endpoint is a valid host"));
+        sign_request(
+            &self.credentials,
+            &self.region,
+            &method,
+            &path,
+            &mut headers,
+            payload,
+            Utc::now(),
+        );
+        (format!("{}{}", self.endpoint, path), headers)
+    }
+
+    fn request(
+        &self,
+        builder: reqwest::RequestBuilder,
+        headers: &HeaderMap,
+    ) -> reqwest::RequestBuilder {
+        headers
+            .iter()
+            .filter(|(name, _)| name.as_str() != "host")
+            .fold(builder, |builder, (name, value)| {
+                builder.header(name.as_str(), value.to_str().unwrap_or_default())
+            })
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        let data = Bytes::from(data);
+        let response = self
+            .retry
+            .http(Protocol::S3, || {
+                // Signatures expire, so each attempt is signed anew
+                let (url, headers) = self.signed(Method::PUT, key, &data);
+                self.request(self.client.put(&url), &headers)
+                    .body(data.clone())
+                    .send()
+            })
+            .await
+            .with_context(|| format!("Failed to upload {}", key))?;
+        if !response.status().is_success() {
+            anyhow::bail!(
+                "Failed to upload {}: HTTP {}",
+                key,
+                response.status().as_u16()
+            );
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let response = self
+            .retry
+            .http(Protocol::S3, || {
+                let (url, headers) = self.signed(Method::GET, key, b"");
+                self.request(self.client.get(&url), &headers).send()
+            })
+            .await
+            .with_context(|| format!("Failed to download {}", key))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            anyhow::bail!(
+                "Failed to download {}: HTTP {}",
+                key,
+                response.status().as_u16()
+            );
+        }
+        let data = response
+            .bytes()
+            .await
+            .with_context(|| format!("Failed to download {}", key))?;
+        Ok(Some(data.to_vec()))
+    }
+}
@@ -0,0 +1,124 @@
+//! Bandwidth limits of upstream and P2P transfers (`max_upload_rate`, `max_download_rate`)
+//!
+//! A [`Throttle`] is a token bucket refilled at the configured rate and holding at most
+//! one second of transfer. Transfers take tokens for each chunk they send or receive
+//! and wait while the bucket is in debt, so every transfer sharing a throttle (e.g. all
+//! uploads to one upstream) stays under its rate together.
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::eviction::EvictionConfig;
+
+/// Size of the chunks throttled uploads are sent in
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Bytes per second of a rate such as "20MBps", "20MB/s" or "20MB"
+pub fn parse_rate(rate: &str) -> Result<u64> {
+    let trimmed = rate.trim();
+    let size = trimmed
+        .strip_suffix("ps")
+        .or_else(|| trimmed.strip_suffix("/s"))
+        .unwrap_or(trimmed);
+    let bytes = EvictionConfig::parse_size(size)
+        .with_context(|| format!("Invalid rate '{}' (e.g. 20MBps)", rate))?;
+    anyhow::ensure!(bytes > 0, "Rate '{}' must be greater than zero", rate);
+    Ok(bytes)
+}
+
+struct Bucket {
+    /// Negative while transfers wait for earlier ones
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket limiting transfers to a number of bytes per second
+pub struct Throttle {
+    bytes_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: bytes_per_sec as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Throttle of a configured rate, None when unset
+    pub fn from_config(rate: Option<&str>) -> Result<Option<Arc<Self>>> {
+        rate.map(|rate| Ok(Arc::new(Self::new(parse_rate(rate)?))))
+            .transpose()
+    }
+
+    /// Take `bytes` tokens, waiting until the transfer is within the rate
+    pub async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.updated).as_secs_f64() * self.bytes_per_sec;
+            bucket.tokens = (bucket.tokens + refill).min(self.bytes_per_sec) - bytes as f64;
+            bucket.updated = now;
+            Duration::from_secs_f64((-bucket.tokens).max(0.0) / self.bytes_per_sec)
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Stream `data` in chunks, each sent once `throttle` allows it (an upload body)
+pub fn throttled_stream(
+    data: Bytes,
+    throttle: Arc<Throttle>,
+) -> ReceiverStream<std::io::Result<Bytes>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    tokio::spawn(async move {
+        let mut offset = 0;
+        while offset < data.len() {
+            let end = (offset + CHUNK_SIZE).min(data.len());
+            throttle.consume(end - offset).await;
+            if tx.send(Ok(data.slice(offset..end))).await.is_err() {
+                break;
+            }
+            offset = end;
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("20MBps").unwrap(), 20 * 1024 * 1024);
+        assert_eq!(parse_rate("512KB/s").unwrap(), 512 * 1024);
+        assert_eq!(parse_rate("1GB").unwrap(), 1024 * 1024 * 1024);
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("0MBps").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_throttle_waits_for_tokens() {
+        let throttle = Throttle::new(1_000_000);
+
+        // The first second of transfer goes through at once
+        let start = Instant::now();
+        throttle.consume(1_000_000).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        // Then the rate applies
+        throttle.consume(100_000).await;
+        throttle.consume(100_000).await;
+        assert!(start.elapsed() >= Duration::from_millis(190));
+    }
+}
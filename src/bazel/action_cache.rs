@@ -1,29 +1,118 @@
+use super::build_metadata::ActionCacheStats;
+use super::limits::{self, CacheLimits};
 use super::proto::remote_execution::*;
+use crate::auth::scopes::{self, Permission};
 use crate::storage::Storage;
 use prost::Message;
 use std::sync::Arc;
 use tonic::{Request, Response, Status};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 /// Bazel ActionCache service implementation
+///
+/// Stored ActionResults pin the CAS blobs they reference (output files, output
+/// directory trees, stdout and stderr), so eviction doesn't remove them while the
+/// ActionResult is cached. Blobs can still go missing (e.g. an ActionResult stored
+/// before its outputs were uploaded), so by default GetActionResult also checks that
+/// every referenced blob is stored, and answers NOT_FOUND otherwise: the client then
+/// runs the action instead of failing to download its outputs.
 pub struct BazelActionCacheService<S: Storage> {
     storage: Arc<S>,
+    stats: Option<Arc<ActionCacheStats>>,
+    validate_outputs: bool,
+    limits: Arc<CacheLimits>,
 }
 
 impl<S: Storage> BazelActionCacheService<S> {
     pub fn new(storage: Arc<S>) -> Self {
-        Self { storage }
+        Self {
+            storage,
+            stats: None,
+            validate_outputs: true,
+            limits: Arc::new(CacheLimits::default()),
+        }
+    }
+
+    /// Check that referenced blobs are stored before serving an ActionResult
+    pub fn with_output_validation(mut self, enabled: bool) -> Self {
+        self.validate_outputs = enabled;
+        self
+    }
+
+    /// Count action cache hits/misses (for build metadata reports)
+    pub fn with_stats(mut self, stats: Arc<ActionCacheStats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Enforce the digest functions and symlink policy of the cache
+    pub fn with_limits(mut self, limits: Arc<CacheLimits>) -> Self {
+        self.limits = limits;
+        self
     }
 
     /// Generate cache key from action digest and instance name
-    fn action_cache_key(instance_name: &str, digest: &Digest) -> Vec<u8> {
-        format!(
-            "action_cache:{}:{}:{}",
-            instance_name, digest.hash, digest.size_bytes
-        )
+    fn action_cache_key(
+        instance_name: &str,
+        function: digest_function::Value,
+        digest: &Digest,
+    ) -> Vec<u8> {
+        match function {
+            digest_function::Value::Sha256 => format!(
+                "action_cache:{}:{}:{}",
+                instance_name, digest.hash, digest.size_bytes
+            ),
+            function => format!(
+                "action_cache:{}:{}:{}:{}",
+                instance_name,
+                limits::digest_function_name(function),
+                digest.hash,
+                digest.size_bytes
+            ),
+        }
         .into_bytes()
     }
 
+    /// CAS keys (same as the CAS service's) of the blobs an ActionResult references
+    ///
+    /// Empty blobs are left out: clients don't upload them.
+    fn referenced_blob_keys(
+        function: digest_function::Value,
+        result: &ActionResult,
+    ) -> Vec<Vec<u8>> {
+        result
+            .output_files
+            .iter()
+            .filter_map(|file| file.digest.as_ref())
+            .chain(
+                result
+                    .output_directories
+                    .iter()
+                    .filter_map(|dir| dir.tree_digest.as_ref()),
+            )
+            .chain(result.stdout_digest.as_ref())
+            .chain(result.stderr_digest.as_ref())
+            .filter(|digest| digest.size_bytes > 0)
+            .map(|digest| limits::cas_blob_key(function, &digest.hash, digest.size_bytes))
+            .collect()
+    }
+
+    /// First blob referenced by an ActionResult that isn't stored
+    fn missing_blob(
+        &self,
+        function: digest_function::Value,
+        result: &ActionResult,
+    ) -> Option<Vec<u8>> {
+        let keys = Self::referenced_blob_keys(function, result);
+        let exists = self
+            .storage
+            .exists_many(&keys)
+            .unwrap_or_else(|_| vec![false; keys.len()]);
+        keys.into_iter()
+            .zip(exists)
+            .find_map(|(key, exists)| (!exists).then_some(key))
+    }
+
     /// Serialize ActionResult to bytes
     #[allow(clippy::result_large_err)]
     fn serialize_result(result: &ActionResult) -> Result<Vec<u8>, Status> {
@@ -48,6 +137,8 @@ impl<S: Storage + 'static> action_cache_server::ActionCache for BazelActionCache
         &self,
         request: Request<GetActionResultRequest>,
     ) -> Result<Response<ActionResult>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::BAZEL, Permission::Read)?;
+
         let req = request.into_inner();
 
         debug!(
@@ -62,15 +153,35 @@ impl<S: Storage + 'static> action_cache_server::ActionCache for BazelActionCache
         let digest = req
             .action_digest
             .ok_or_else(|| Status::invalid_argument("Missing action_digest"))?;
+        let function = self.limits.digest_function(req.digest_function)?;
 
-        let key = Self::action_cache_key(&req.instance_name, &digest);
+        let key = Self::action_cache_key(&req.instance_name, function, &digest);
 
         // Retrieve from storage
         match self.storage.get(&key) {
             Ok(Some(data)) => {
                 let result = Self::deserialize_result(&data)?;
 
+                if self.validate_outputs {
+                    if let Some(missing) = self.missing_blob(function, &result) {
+                        info!(
+                            "<== GetActionResult - Cache MISS for action {}: referenced blob {} is missing",
+                            digest.hash,
+                            String::from_utf8_lossy(&missing)
+                        );
+                        if let Some(stats) = &self.stats {
+                            stats.record_miss();
+                        }
+                        return Err(Status::not_found(
+                            "ActionResult references blobs missing from the CAS",
+                        ));
+                    }
+                }
+
                 info!("<== GetActionResult - Cache HIT for action {}", digest.hash);
+                if let Some(stats) = &self.stats {
+                    stats.record_hit();
+                }
 
                 Ok(Response::new(result))
             }
@@ -79,6 +190,9 @@ impl<S: Storage + 'static> action_cache_server::ActionCache for BazelActionCache
                     "<== GetActionResult - Cache MISS for action {}",
                     digest.hash
                 );
+                if let Some(stats) = &self.stats {
+                    stats.record_miss();
+                }
                 Err(Status::not_found("ActionResult not found in cache"))
             }
         }
@@ -88,6 +202,8 @@ impl<S: Storage + 'static> action_cache_server::ActionCache for BazelActionCache
         &self,
         request: Request<UpdateActionResultRequest>,
     ) -> Result<Response<ActionResult>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::BAZEL, Permission::Write)?;
+
         let req = request.into_inner();
 
         debug!(
@@ -106,8 +222,10 @@ impl<S: Storage + 'static> action_cache_server::ActionCache for BazelActionCache
         let result = req
             .action_result
             .ok_or_else(|| Status::invalid_argument("Missing action_result"))?;
+        let function = self.limits.digest_function(req.digest_function)?;
+        self.limits.check_symlinks(&result)?;
 
-        let key = Self::action_cache_key(&req.instance_name, &digest);
+        let key = Self::action_cache_key(&req.instance_name, function, &digest);
         let serialized = Self::serialize_result(&result)?;
 
         // Store in storage
@@ -115,6 +233,14 @@ impl<S: Storage + 'static> action_cache_server::ActionCache for BazelActionCache
             .put(&key, &serialized)
             .map_err(|e| Status::internal(format!("Failed to store ActionResult: {}", e)))?;
 
+        // Pins are released when the ActionResult is evicted
+        if let Err(e) = self
+            .storage
+            .pin(&key, &Self::referenced_blob_keys(function, &result))
+        {
+            warn!("Failed to pin outputs of action {}: {}", digest.hash, e);
+        }
+
         info!(
             "<== UpdateActionResult - Stored action result for {}",
             digest.hash
@@ -123,3 +249,119 @@ impl<S: Storage + 'static> action_cache_server::ActionCache for BazelActionCache
         Ok(Response::new(result))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bazel::proto::remote_execution::action_cache_server::ActionCache;
+    use crate::storage::FilesystemStorage;
+    use tempfile::TempDir;
+
+    fn digest(hash: &str, size_bytes: i64) -> Digest {
+        Digest {
+            hash: hash.to_string(),
+            size_bytes,
+        }
+    }
+
+    fn get_request() -> Request<GetActionResultRequest> {
+        Request::new(GetActionResultRequest {
+            instance_name: "main".to_string(),
+            action_digest: Some(digest("action", 10)),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_action_results_with_missing_outputs_are_not_found() {
+        let dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(dir.path()).unwrap());
+        let service = BazelActionCacheService::new(storage.clone());
+
+        storage.put(b"cas:out:3", b"out").unwrap();
+        storage.put(b"cas:log:3", b"log").unwrap();
+        let result = ActionResult {
+            output_files: vec![OutputFile {
+                path: "bazel-out/out".to_string(),
+                digest: Some(digest("out", 3)),
+                ..Default::default()
+            }],
+            stdout_digest: Some(digest("log", 3)),
+            // Empty blobs aren't uploaded
+            stderr_digest: Some(digest("empty", 0)),
+            ..Default::default()
+        };
+        service
+            .update_action_result(Request::new(UpdateActionResultRequest {
+                instance_name: "main".to_string(),
+                action_digest: Some(digest("action", 10)),
+                action_result: Some(result.clone()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+
+        // Outputs are pinned while the ActionResult is stored
+        let pinned = storage.pinned_ids().unwrap();
+        assert!(pinned.contains(b"cas:out:3".as_slice()));
+        assert!(pinned.contains(b"cas:log:3".as_slice()));
+
+        let response = service.get_action_result(get_request()).await.unwrap();
+        assert_eq!(response.into_inner(), result);
+
+        storage.delete(b"cas:out:3").unwrap();
+        let status = service.get_action_result(get_request()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let unchecked = BazelActionCacheService::new(storage).with_output_validation(false);
+        assert!(unchecked.get_action_result(get_request()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_token_scopes_enforced() {
+        use crate::auth::layer::{test_support, AuthLayer};
+        use crate::bazel::proto::remote_execution::action_cache_server::ActionCacheServer;
+        use prost::Message;
+        use tower::{Layer, ServiceExt};
+
+        let dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(dir.path()).unwrap());
+        let server = AuthLayer::new(Some(test_support::validator(true))).layer(
+            ActionCacheServer::new(BazelActionCacheService::new(storage)),
+        );
+
+        // UpdateActionResult as sent by Bazel, framed as a gRPC message
+        let update = |scope: &str| {
+            let message = UpdateActionResultRequest {
+                instance_name: "main".to_string(),
+                action_digest: Some(digest("action", 10)),
+                action_result: Some(ActionResult::default()),
+                ..Default::default()
+            }
+            .encode_to_vec();
+            let mut frame = vec![0];
+            frame.extend((message.len() as u32).to_be_bytes());
+            frame.extend(message);
+            axum::http::Request::post(
+                "/build.bazel.remote.execution.v2.ActionCache/UpdateActionResult",
+            )
+            .header("content-type", "application/grpc")
+            .header("te", "trailers")
+            .header(
+                "authorization",
+                format!("Bearer {}", test_support::token("ci", scope)),
+            )
+            .body(axum::body::Body::from(frame))
+            .unwrap()
+        };
+
+        let response = server.clone().oneshot(update("cache:read")).await.unwrap();
+        assert_eq!(
+            response.headers()["grpc-status"],
+            (tonic::Code::PermissionDenied as i32).to_string()
+        );
+
+        let response = server.oneshot(update("bazel:write")).await.unwrap();
+        assert!(response.headers().get("grpc-status").is_none());
+    }
+}
@@ -1,31 +1,64 @@
+use super::limits::{self, CacheLimits};
 use super::proto::bytestream::*;
+use super::proto::remote_execution::digest_function;
+use crate::auth::scopes::{self, Permission};
+use crate::quota::UploadLimits;
+use crate::rate_limit::grpc_client_identity;
 use crate::storage::Storage;
 use std::sync::Arc;
 use tonic::{Request, Response, Status};
-use tracing::{debug, info, warn};
+use tracing::{debug, info};
 
 /// ByteStream service implementation for large blob transfers
 pub struct BazelByteStreamService<S: Storage> {
     storage: Arc<S>,
+    limits: Arc<UploadLimits>,
+    cache_limits: Arc<CacheLimits>,
 }
 
 impl<S: Storage> BazelByteStreamService<S> {
     pub fn new(storage: Arc<S>) -> Self {
-        Self { storage }
+        Self {
+            storage,
+            limits: Arc::new(UploadLimits::unlimited()),
+            cache_limits: Arc::new(CacheLimits::default()),
+        }
+    }
+
+    /// Enforce upload size limits and quotas
+    pub fn with_upload_limits(mut self, limits: Arc<UploadLimits>) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Enforce the digest functions of the cache
+    pub fn with_limits(mut self, limits: Arc<CacheLimits>) -> Self {
+        self.cache_limits = limits;
+        self
     }
 
-    /// Parse resource name to extract hash and size
-    /// Format: [instance_name/]uploads/[uuid]/blobs/{hash}/{size}
-    /// or: [instance_name/]blobs/{hash}/{size}
-    fn parse_resource_name(resource_name: &str) -> Option<(String, i64)> {
+    /// Parse resource name to extract digest function, hash and size
+    /// Format: [instance_name/]uploads/[uuid]/blobs/[digest_function/]{hash}/{size}
+    /// or: [instance_name/]blobs/[digest_function/]{hash}/{size}
+    ///
+    /// The digest function is `Unknown` when the name leaves it out (SHA-256).
+    fn parse_resource_name(resource_name: &str) -> Option<(digest_function::Value, String, i64)> {
         let parts: Vec<&str> = resource_name.split('/').collect();
 
         // Find "blobs" in the path
         if let Some(blobs_idx) = parts.iter().position(|&p| p == "blobs") {
-            if blobs_idx + 2 < parts.len() {
-                let hash = parts[blobs_idx + 1].to_string();
-                if let Ok(size) = parts[blobs_idx + 2].parse::<i64>() {
-                    return Some((hash, size));
+            let mut rest = &parts[blobs_idx + 1..];
+            let mut function = digest_function::Value::Unknown;
+            if rest.len() > 2 {
+                if let Some(named) = limits::parse_digest_function_name(rest[0]) {
+                    function = named;
+                    rest = &rest[1..];
+                }
+            }
+            if rest.len() >= 2 {
+                let hash = rest[0].to_string();
+                if let Ok(size) = rest[1].parse::<i64>() {
+                    return Some((function, hash, size));
                 }
             }
         }
@@ -33,9 +66,16 @@ impl<S: Storage> BazelByteStreamService<S> {
         None
     }
 
-    /// Generate CAS blob key from hash and size
-    fn cas_blob_key(hash: &str, size: i64) -> Vec<u8> {
-        format!("cas:{}:{}", hash, size).into_bytes()
+    /// Parse a resource name and check its digest function
+    #[allow(clippy::result_large_err)]
+    fn resolve_resource_name(
+        &self,
+        resource_name: &str,
+    ) -> Result<(digest_function::Value, String, i64), Status> {
+        let (function, hash, size) = Self::parse_resource_name(resource_name)
+            .ok_or_else(|| Status::invalid_argument("Invalid resource name format"))?;
+        let function = self.cache_limits.digest_function(function as i32)?;
+        Ok((function, hash, size))
     }
 }
 
@@ -47,14 +87,15 @@ impl<S: Storage + 'static> byte_stream_server::ByteStream for BazelByteStreamSer
         &self,
         request: Request<ReadRequest>,
     ) -> Result<Response<Self::ReadStream>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::BAZEL, Permission::Read)?;
+
         let req = request.into_inner();
 
         debug!("==> ByteStream Read - resource: {}", req.resource_name);
 
-        let (hash, size) = Self::parse_resource_name(&req.resource_name)
-            .ok_or_else(|| Status::invalid_argument("Invalid resource name format"))?;
+        let (function, hash, size) = self.resolve_resource_name(&req.resource_name)?;
 
-        let key = Self::cas_blob_key(&hash, size);
+        let key = limits::cas_blob_key(function, &hash, size);
 
         // Retrieve blob from storage
         let data = match self.storage.get(&key) {
@@ -110,9 +151,14 @@ impl<S: Storage + 'static> byte_stream_server::ByteStream for BazelByteStreamSer
         &self,
         request: Request<tonic::Streaming<WriteRequest>>,
     ) -> Result<Response<WriteResponse>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::BAZEL, Permission::Write)?;
+        let client = grpc_client_identity(&request);
+        let max_size = self.limits.max_artifact_size(scopes::services::BAZEL);
+
         let mut stream = request.into_inner();
 
         let mut resource_name: Option<String> = None;
+        let mut declared_size: Option<u64> = None;
         let mut buffer = Vec::new();
         let mut total_written = 0usize;
 
@@ -128,6 +174,16 @@ impl<S: Storage + 'static> byte_stream_server::ByteStream for BazelByteStreamSer
                 }
                 resource_name = Some(req.resource_name.clone());
                 debug!("  Resource: {}", req.resource_name);
+
+                // Reject unsupported digest functions and oversized uploads (and charge the
+                // quota) before receiving data; the data must then match the declared size
+                if let Some((function, _, size)) = Self::parse_resource_name(&req.resource_name) {
+                    self.cache_limits.digest_function(function as i32)?;
+                    let size = size.max(0) as u64;
+                    self.limits
+                        .check_upload(scopes::services::BAZEL, &client, size)?;
+                    declared_size = Some(size);
+                }
             }
 
             // Verify write_offset matches our current position
@@ -142,25 +198,40 @@ impl<S: Storage + 'static> byte_stream_server::ByteStream for BazelByteStreamSer
             buffer.extend_from_slice(&req.data);
             total_written += req.data.len();
 
+            // Guard against clients sending more than they were charged for
+            if let Some(size) = declared_size.filter(|&size| total_written as u64 > size) {
+                return Err(Status::invalid_argument(format!(
+                    "Upload exceeds the declared size of {} bytes",
+                    size
+                )));
+            }
+            // or, when the resource name has no size, more than the size limit
+            if max_size.is_some_and(|max| total_written as u64 > max) {
+                return Err(Status::resource_exhausted(format!(
+                    "Upload exceeds the {} byte limit for {}",
+                    max_size.unwrap_or_default(),
+                    scopes::services::BAZEL
+                )));
+            }
+
             // If this is the final write, store in storage
             if req.finish_write {
                 let resource = resource_name
                     .as_ref()
                     .ok_or_else(|| Status::internal("Missing resource_name"))?;
 
-                let (hash, size) = Self::parse_resource_name(resource)
-                    .ok_or_else(|| Status::invalid_argument("Invalid resource name format"))?;
+                let (function, hash, size) = self.resolve_resource_name(resource)?;
 
-                // Verify size matches
+                // The blob's key is built from the declared size
                 if size != buffer.len() as i64 {
-                    warn!(
+                    return Err(Status::invalid_argument(format!(
                         "Size mismatch: resource claims {}, got {}",
                         size,
                         buffer.len()
-                    );
+                    )));
                 }
 
-                let key = Self::cas_blob_key(&hash, size);
+                let key = limits::cas_blob_key(function, &hash, size);
 
                 // Store in storage
                 self.storage
@@ -1,14 +1,33 @@
+use super::limits::CacheLimits;
 use super::proto::remote_execution::*;
+use crate::auth::scopes;
+use crate::quota::UploadLimits;
+use std::sync::Arc;
 use tonic::{Request, Response, Status};
 use tracing::{debug, info};
 
 /// Bazel Capabilities service implementation
 #[derive(Default)]
-pub struct BazelCapabilitiesService;
+pub struct BazelCapabilitiesService {
+    limits: Arc<CacheLimits>,
+    upload_limits: Option<Arc<UploadLimits>>,
+}
 
 impl BazelCapabilitiesService {
     pub fn new() -> Self {
-        Self
+        Self::default()
+    }
+
+    /// Advertise the batch size, digest functions and symlink policy of the cache
+    pub fn with_limits(mut self, limits: Arc<CacheLimits>) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Advertise the largest blob uploads may store
+    pub fn with_upload_limits(mut self, limits: Arc<UploadLimits>) -> Self {
+        self.upload_limits = Some(limits);
+        self
     }
 }
 
@@ -22,16 +41,34 @@ impl capabilities_server::Capabilities for BazelCapabilitiesService {
 
         debug!("==> GetCapabilities - instance: {}", req.instance_name);
 
+        let symlink_absolute_path_strategy = if self.limits.allow_absolute_symlinks {
+            symlink_absolute_path_strategy::Value::Allowed
+        } else {
+            symlink_absolute_path_strategy::Value::Disallowed
+        };
+        let max_cas_blob_size_bytes = self
+            .upload_limits
+            .as_ref()
+            .and_then(|limits| limits.max_artifact_size(scopes::services::BAZEL))
+            .unwrap_or(0);
+
         // Return capabilities for cache-only server (no remote execution)
         let capabilities = ServerCapabilities {
             cache_capabilities: Some(CacheCapabilities {
-                digest_functions: vec![digest_function::Value::Sha256 as i32],
+                digest_functions: self
+                    .limits
+                    .digest_functions
+                    .iter()
+                    .map(|function| *function as i32)
+                    .collect(),
                 action_cache_update_capabilities: Some(ActionCacheUpdateCapabilities {
                     update_enabled: true,
                 }),
-                max_batch_total_size_bytes: 4 * 1024 * 1024, // 4MB max batch size
+                max_batch_total_size_bytes: self.limits.max_batch_total_size_bytes as i64,
+                symlink_absolute_path_strategy: symlink_absolute_path_strategy as i32,
                 supported_compressors: vec![compressor::Value::Identity as i32],
                 supported_batch_update_compressors: vec![compressor::Value::Identity as i32],
+                max_cas_blob_size_bytes: max_cas_blob_size_bytes as i64,
             }),
             execution_capabilities: None, // We don't support remote execution
             deprecated_api_version: Some(SemVer {
@@ -59,3 +96,36 @@ impl capabilities_server::Capabilities for BazelCapabilitiesService {
         Ok(Response::new(capabilities))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bazel::proto::remote_execution::capabilities_server::Capabilities;
+
+    #[tokio::test]
+    async fn test_capabilities_reflect_limits() {
+        let limits = CacheLimits::parse("1MB", &["blake3"], true).unwrap();
+        let service = BazelCapabilitiesService::new().with_limits(Arc::new(limits));
+        let capabilities = service
+            .get_capabilities(Request::new(GetCapabilitiesRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .cache_capabilities
+            .unwrap();
+
+        assert_eq!(
+            capabilities.digest_functions,
+            vec![
+                digest_function::Value::Sha256 as i32,
+                digest_function::Value::Blake3 as i32
+            ]
+        );
+        assert_eq!(capabilities.max_batch_total_size_bytes, 1024 * 1024);
+        assert_eq!(
+            capabilities.symlink_absolute_path_strategy,
+            symlink_absolute_path_strategy::Value::Allowed as i32
+        );
+        assert_eq!(capabilities.max_cas_blob_size_bytes, 0);
+    }
+}
@@ -1,24 +1,46 @@
+use super::limits::{self, CacheLimits};
 use super::proto::google::rpc::Status as RpcStatus;
 use super::proto::remote_execution::*;
+use crate::auth::scopes::{self, Permission};
 use crate::logging::{operations, services, status};
+use crate::quota::UploadLimits;
+use crate::rate_limit::grpc_client_identity;
 use crate::storage::Storage;
 use std::sync::Arc;
 use tonic::{Request, Response, Status};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 /// Bazel ContentAddressableStorage service implementation
 pub struct BazelCasService<S: Storage> {
     storage: Arc<S>,
+    limits: Arc<UploadLimits>,
+    cache_limits: Arc<CacheLimits>,
 }
 
 impl<S: Storage> BazelCasService<S> {
     pub fn new(storage: Arc<S>) -> Self {
-        Self { storage }
+        Self {
+            storage,
+            limits: Arc::new(UploadLimits::unlimited()),
+            cache_limits: Arc::new(CacheLimits::default()),
+        }
+    }
+
+    /// Enforce upload size limits and quotas
+    pub fn with_upload_limits(mut self, limits: Arc<UploadLimits>) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Enforce the batch size and digest functions of the cache
+    pub fn with_limits(mut self, limits: Arc<CacheLimits>) -> Self {
+        self.cache_limits = limits;
+        self
     }
 
     /// Generate CAS blob key from digest
-    fn cas_blob_key(digest: &Digest) -> Vec<u8> {
-        format!("cas:{}:{}", digest.hash, digest.size_bytes).into_bytes()
+    fn cas_blob_key(function: digest_function::Value, digest: &Digest) -> Vec<u8> {
+        limits::cas_blob_key(function, &digest.hash, digest.size_bytes)
     }
 }
 
@@ -31,7 +53,10 @@ impl<S: Storage + 'static> content_addressable_storage_server::ContentAddressabl
         &self,
         request: Request<FindMissingBlobsRequest>,
     ) -> Result<Response<FindMissingBlobsResponse>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::BAZEL, Permission::Read)?;
+
         let req = request.into_inner();
+        let function = self.cache_limits.digest_function(req.digest_function)?;
         let blob_count = req.blob_digests.len();
 
         debug!(
@@ -42,22 +67,38 @@ impl<S: Storage + 'static> content_addressable_storage_server::ContentAddressabl
             "checking blobs"
         );
 
-        let mut missing = Vec::new();
-
-        for digest in req.blob_digests {
-            let key = Self::cas_blob_key(&digest);
+        // One batched lookup; if it fails, every blob is reported missing
+        let keys: Vec<Vec<u8>> = req
+            .blob_digests
+            .iter()
+            .map(|digest| Self::cas_blob_key(function, digest))
+            .collect();
+        let exists = self
+            .storage
+            .exists_many(&keys)
+            .unwrap_or_else(|_| vec![false; keys.len()]);
 
-            // Check if blob exists in storage
-            match self.storage.get(&key) {
-                Ok(None) | Err(_) => {
-                    missing.push(digest);
-                }
-                Ok(Some(_)) => {
-                    // Blob exists, don't add to missing
-                }
+        let mut missing = Vec::new();
+        let mut found = Vec::new();
+        for ((digest, key), exists) in req.blob_digests.into_iter().zip(keys).zip(exists) {
+            if exists {
+                found.push(key);
+            } else {
+                missing.push(digest);
             }
         }
 
+        // The client is about to use them: keep them fresh for eviction
+        if let Err(e) = self.storage.touch_many(&found) {
+            warn!(
+                service = services::BAZEL_CAS,
+                operation = operations::FIND_MISSING,
+                blob_count = found.len(),
+                "Failed to record access to found blobs: {}",
+                e
+            );
+        }
+
         info!(
             service = services::BAZEL_CAS,
             operation = operations::FIND_MISSING,
@@ -77,6 +118,9 @@ impl<S: Storage + 'static> content_addressable_storage_server::ContentAddressabl
         &self,
         request: Request<BatchUpdateBlobsRequest>,
     ) -> Result<Response<BatchUpdateBlobsResponse>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::BAZEL, Permission::Write)?;
+        let client = grpc_client_identity(&request);
+
         let req = request.into_inner();
 
         debug!(
@@ -85,6 +129,14 @@ impl<S: Storage + 'static> content_addressable_storage_server::ContentAddressabl
             req.requests.len()
         );
 
+        let function = self.cache_limits.digest_function(req.digest_function)?;
+        let total_bytes = req
+            .requests
+            .iter()
+            .map(|blob_request| blob_request.data.len() as u64)
+            .sum();
+        self.cache_limits.check_batch_size(total_bytes)?;
+
         let mut responses = Vec::new();
         let mut success_count = 0;
         let mut error_count = 0;
@@ -94,7 +146,7 @@ impl<S: Storage + 'static> content_addressable_storage_server::ContentAddressabl
                 .digest
                 .ok_or_else(|| Status::invalid_argument("Missing digest"))?;
 
-            let key = Self::cas_blob_key(&digest);
+            let key = Self::cas_blob_key(function, &digest);
 
             debug!(
                 "  Uploading blob: hash={}, size={}",
@@ -110,6 +162,25 @@ impl<S: Storage + 'static> content_addressable_storage_server::ContentAddressabl
                 );
             }
 
+            // Enforce upload limits per blob so one oversized blob doesn't fail the batch
+            if let Err(e) = self.limits.check_upload(
+                scopes::services::BAZEL,
+                &client,
+                blob_request.data.len() as u64,
+            ) {
+                error_count += 1;
+                debug!("  Upload rejected: {}", e);
+                responses.push(batch_update_blobs_response::Response {
+                    digest: Some(digest),
+                    status: Some(RpcStatus {
+                        code: tonic::Code::ResourceExhausted as i32,
+                        message: e.to_string(),
+                        details: Vec::new(),
+                    }),
+                });
+                continue;
+            }
+
             // Store blob in storage
             let status = match self.storage.put(&key, &blob_request.data) {
                 Ok(_) => {
@@ -150,6 +221,8 @@ impl<S: Storage + 'static> content_addressable_storage_server::ContentAddressabl
         &self,
         request: Request<BatchReadBlobsRequest>,
     ) -> Result<Response<BatchReadBlobsResponse>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::BAZEL, Permission::Read)?;
+
         let req = request.into_inner();
 
         debug!(
@@ -158,10 +231,18 @@ impl<S: Storage + 'static> content_addressable_storage_server::ContentAddressabl
             req.digests.len()
         );
 
+        let function = self.cache_limits.digest_function(req.digest_function)?;
+        let total_bytes = req
+            .digests
+            .iter()
+            .map(|digest| digest.size_bytes.max(0) as u64)
+            .sum();
+        self.cache_limits.check_batch_size(total_bytes)?;
+
         let mut responses = Vec::new();
 
         for digest in req.digests {
-            let key = Self::cas_blob_key(&digest);
+            let key = Self::cas_blob_key(function, &digest);
 
             // Retrieve blob from storage
             let (data, status) = match self.storage.get(&key) {
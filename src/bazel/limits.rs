@@ -0,0 +1,254 @@
+/// Limits of the Bazel remote cache
+///
+/// The Capabilities service advertises these limits and the other services enforce
+/// them, answering INVALID_ARGUMENT when a request breaks one:
+///
+/// - The total size of the blobs in a BatchUpdateBlobs or BatchReadBlobs request.
+/// - The digest functions blobs and actions can be addressed with. SHA-256 is always
+///   supported and BLAKE3 can be enabled. Each digest function has its own key space, so
+///   SHA-256 keys stay `cas:<hash>:<size>` and BLAKE3 ones are `cas:blake3:<hash>:<size>`.
+/// - Whether ActionResults may hold output symlinks with absolute targets.
+use tonic::Status;
+
+use super::proto::remote_execution::{digest_function, ActionResult};
+use crate::error::{FabrikError, Result};
+use crate::eviction::EvictionConfig;
+use crate::hashing::HashAlgorithm;
+
+/// Default `max_batch_size`
+pub const DEFAULT_MAX_BATCH_SIZE: &str = "4MB";
+
+/// Digest functions that can be enabled
+const SUPPORTED_DIGEST_FUNCTIONS: &[digest_function::Value] = &[
+    digest_function::Value::Sha256,
+    digest_function::Value::Blake3,
+];
+
+/// Limits of the Bazel cache services
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheLimits {
+    /// Largest total size of the blobs in a batch request
+    pub max_batch_total_size_bytes: u64,
+    /// Accepted digest functions, SHA-256 first
+    pub digest_functions: Vec<digest_function::Value>,
+    /// Whether ActionResults may hold symlinks to absolute paths
+    pub allow_absolute_symlinks: bool,
+}
+
+impl Default for CacheLimits {
+    fn default() -> Self {
+        Self {
+            max_batch_total_size_bytes: 4 * 1024 * 1024,
+            digest_functions: vec![digest_function::Value::Sha256],
+            allow_absolute_symlinks: false,
+        }
+    }
+}
+
+impl CacheLimits {
+    /// Parse the limits of `[build_systems.bazel]` (sizes such as "4MB", digest functions
+    /// such as "blake3")
+    pub fn parse<S: AsRef<str>>(
+        max_batch_size: &str,
+        digest_functions: &[S],
+        allow_absolute_symlinks: bool,
+    ) -> Result<Self> {
+        let max_batch_total_size_bytes =
+            EvictionConfig::parse_size(max_batch_size).map_err(|e| {
+                FabrikError::config(format!(
+                    "Invalid build_systems.bazel.max_batch_size '{}': {:#}",
+                    max_batch_size, e
+                ))
+            })?;
+
+        let mut functions = vec![digest_function::Value::Sha256];
+        for name in digest_functions {
+            let name = name.as_ref();
+            let function = digest_function::Value::from_str_name(&name.to_uppercase())
+                .filter(|function| SUPPORTED_DIGEST_FUNCTIONS.contains(function))
+                .ok_or_else(|| {
+                    FabrikError::config(format!(
+                        "Unsupported digest function '{}' in build_systems.bazel.digest_functions \
+                         (supported: sha256, blake3)",
+                        name
+                    ))
+                })?;
+            if !functions.contains(&function) {
+                functions.push(function);
+            }
+        }
+
+        Ok(Self {
+            max_batch_total_size_bytes,
+            digest_functions: functions,
+            allow_absolute_symlinks,
+        })
+    }
+
+    /// Also accept the digest function of `algorithm`
+    pub fn accepting(mut self, algorithm: HashAlgorithm) -> Self {
+        let function = match algorithm {
+            HashAlgorithm::Sha256 => digest_function::Value::Sha256,
+            HashAlgorithm::Blake3 => digest_function::Value::Blake3,
+        };
+        if !self.digest_functions.contains(&function) {
+            self.digest_functions.push(function);
+        }
+        self
+    }
+
+    /// Digest function of a request (`requested` is its `digest_function` field)
+    ///
+    /// Requests that leave it unset use SHA-256, the only enabled function a server can
+    /// infer from the length of a hash.
+    #[allow(clippy::result_large_err)]
+    pub fn digest_function(&self, requested: i32) -> Result<digest_function::Value, Status> {
+        let function = match digest_function::Value::try_from(requested) {
+            Ok(digest_function::Value::Unknown) => digest_function::Value::Sha256,
+            Ok(function) => function,
+            Err(_) => {
+                return Err(Status::invalid_argument(format!(
+                    "Unknown digest function {}",
+                    requested
+                )))
+            }
+        };
+        if !self.digest_functions.contains(&function) {
+            return Err(Status::invalid_argument(format!(
+                "Digest function {} is not supported by this cache",
+                function.as_str_name()
+            )));
+        }
+        Ok(function)
+    }
+
+    /// Refuse batches of more than `max_batch_total_size_bytes`
+    #[allow(clippy::result_large_err)]
+    pub fn check_batch_size(&self, total_bytes: u64) -> Result<(), Status> {
+        if total_bytes > self.max_batch_total_size_bytes {
+            return Err(Status::invalid_argument(format!(
+                "Batch of {} bytes exceeds the {} byte limit",
+                total_bytes, self.max_batch_total_size_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    /// Refuse ActionResults with absolute symlinks, unless they're allowed
+    #[allow(clippy::result_large_err)]
+    pub fn check_symlinks(&self, result: &ActionResult) -> Result<(), Status> {
+        if self.allow_absolute_symlinks {
+            return Ok(());
+        }
+        let absolute = result
+            .output_file_symlinks
+            .iter()
+            .chain(&result.output_directory_symlinks)
+            .chain(&result.output_symlinks)
+            .find(|symlink| symlink.target.starts_with('/'));
+        match absolute {
+            Some(symlink) => Err(Status::invalid_argument(format!(
+                "Output symlink {} has an absolute target ({}), which this cache doesn't allow",
+                symlink.path, symlink.target
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Lowercase name of a digest function, as used in ByteStream resource names
+pub(super) fn digest_function_name(function: digest_function::Value) -> String {
+    function.as_str_name().to_lowercase()
+}
+
+/// Digest function named in a ByteStream resource name (e.g. "blake3")
+pub(super) fn parse_digest_function_name(name: &str) -> Option<digest_function::Value> {
+    if name.is_empty() || name.bytes().any(|byte| byte.is_ascii_uppercase()) {
+        return None;
+    }
+    digest_function::Value::from_str_name(&name.to_uppercase())
+        .filter(|function| *function != digest_function::Value::Unknown)
+}
+
+/// Key of a CAS blob (SHA-256 keys carry no digest function, as before BLAKE3 support)
+pub(super) fn cas_blob_key(function: digest_function::Value, hash: &str, size: i64) -> Vec<u8> {
+    match function {
+        digest_function::Value::Sha256 => format!("cas:{}:{}", hash, size).into_bytes(),
+        function => {
+            format!("cas:{}:{}:{}", digest_function_name(function), hash, size).into_bytes()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bazel::proto::remote_execution::OutputSymlink;
+
+    #[test]
+    fn test_parse_and_enforce() {
+        let limits = CacheLimits::parse("1MB", &["blake3", "sha256"], false).unwrap();
+        assert_eq!(limits.max_batch_total_size_bytes, 1024 * 1024);
+        assert_eq!(
+            limits.digest_functions,
+            vec![
+                digest_function::Value::Sha256,
+                digest_function::Value::Blake3
+            ]
+        );
+        assert!(CacheLimits::parse("1MB", &["md5"], false).is_err());
+        assert!(CacheLimits::parse("big", &[] as &[&str], false).is_err());
+        assert_eq!(
+            CacheLimits::default().accepting(HashAlgorithm::Blake3),
+            CacheLimits::parse("4MB", &["blake3"], false).unwrap()
+        );
+
+        let default = CacheLimits::default();
+        assert_eq!(
+            default.digest_function(0).unwrap(),
+            digest_function::Value::Sha256
+        );
+        let blake3 = digest_function::Value::Blake3 as i32;
+        assert_eq!(
+            default.digest_function(blake3).unwrap_err().code(),
+            tonic::Code::InvalidArgument
+        );
+        assert_eq!(
+            limits.digest_function(blake3).unwrap(),
+            digest_function::Value::Blake3
+        );
+        assert!(limits.digest_function(99).is_err());
+
+        assert!(limits.check_batch_size(1024 * 1024).is_ok());
+        assert!(limits.check_batch_size(1024 * 1024 + 1).is_err());
+
+        let result = ActionResult {
+            output_symlinks: vec![OutputSymlink {
+                path: "bazel-out/sdk".to_string(),
+                target: "/opt/sdk".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(limits.check_symlinks(&result).is_err());
+        let permissive = CacheLimits {
+            allow_absolute_symlinks: true,
+            ..limits
+        };
+        assert!(permissive.check_symlinks(&result).is_ok());
+
+        assert_eq!(
+            cas_blob_key(digest_function::Value::Sha256, "abc", 3),
+            b"cas:abc:3"
+        );
+        assert_eq!(
+            cas_blob_key(digest_function::Value::Blake3, "abc", 3),
+            b"cas:blake3:abc:3"
+        );
+        assert_eq!(
+            parse_digest_function_name("blake3"),
+            Some(digest_function::Value::Blake3)
+        );
+        assert_eq!(parse_digest_function_name("0123abcd"), None);
+    }
+}
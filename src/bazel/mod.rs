@@ -1,13 +1,24 @@
 mod action_cache;
+pub mod build_metadata;
 mod bytestream;
 mod capabilities;
 mod cas;
+mod limits;
 mod rpc_status;
 
 pub use action_cache::BazelActionCacheService;
 pub use bytestream::BazelByteStreamService;
 pub use capabilities::BazelCapabilitiesService;
 pub use cas::BazelCasService;
+pub use limits::{CacheLimits, DEFAULT_MAX_BATCH_SIZE};
+
+/// gRPC service names of the Bazel remote cache server (for health checking)
+pub const SERVICE_NAMES: &[&str] = &[
+    proto::remote_execution::capabilities_server::SERVICE_NAME,
+    proto::remote_execution::action_cache_server::SERVICE_NAME,
+    proto::remote_execution::content_addressable_storage_server::SERVICE_NAME,
+    proto::bytestream::byte_stream_server::SERVICE_NAME,
+];
 
 // Include generated proto code
 pub mod proto {
@@ -26,4 +37,7 @@ pub mod proto {
             pub use crate::bazel::rpc_status::Status;
         }
     }
+
+    /// Encoded descriptors of the Remote Execution API and ByteStream protos
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("bazel_descriptor");
 }
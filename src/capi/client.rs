@@ -0,0 +1,412 @@
+//! Client handles of a running daemon (`fabrik_client_connect()`)
+//!
+//! A daemon holds the RocksDB lock of its cache directory, so opening the same
+//! directory with `fabrik_cache_init()` fails while it runs. A client handle instead
+//! sends each request to the daemon's artifact API (`/api/v1/artifacts/{hash}`), over
+//! TCP or the daemon's `http_socket`, and the daemon keeps sole ownership of the cache.
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::client::conn::http1::SendRequest;
+use hyper::{header, Method, Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::runtime::Runtime;
+
+use crate::error::{FabrikError, Result};
+use crate::storage::{Storage, StorageStats};
+
+const ARTIFACTS_PATH: &str = "/api/v1/artifacts";
+const FIND_MISSING_PATH: &str = "/api/v1/artifacts/find-missing";
+
+/// Most hashes the daemon accepts per find-missing request
+const FIND_MISSING_BATCH: usize = 10_000;
+
+/// Longest a request to the daemon may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where the daemon listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    /// `host:port`
+    Tcp(String),
+    /// Socket path, or abstract name (`@name`)
+    Unix(PathBuf),
+}
+
+impl Target {
+    /// Target of `address`: `http://host:port`, `host:port`, `unix:<socket>`, a socket
+    /// path or an abstract socket name (`@name`)
+    fn parse(address: &str) -> Result<Self> {
+        if let Some(socket) = address.strip_prefix("unix:") {
+            return Ok(Self::Unix(PathBuf::from(socket)));
+        }
+        if address.starts_with(['/', '.', '@']) {
+            return Ok(Self::Unix(PathBuf::from(address)));
+        }
+        if address.starts_with("https://") {
+            return Err(FabrikError::config(
+                "Daemons serve plain HTTP; use http:// or a socket",
+            ));
+        }
+        let authority = address
+            .strip_prefix("http://")
+            .unwrap_or(address)
+            .trim_end_matches('/');
+        if authority.is_empty() || authority.contains('/') {
+            return Err(FabrikError::config(format!(
+                "Invalid daemon address: {}",
+                address
+            )));
+        }
+        Ok(Self::Tcp(authority.to_string()))
+    }
+
+    /// Open a connection and run the HTTP/1.1 handshake
+    async fn connect(&self) -> io::Result<SendRequest<Full<Bytes>>> {
+        match self {
+            Self::Tcp(authority) => {
+                let stream = tokio::net::TcpStream::connect(authority.as_str()).await?;
+                stream.set_nodelay(true)?;
+                handshake(stream).await
+            }
+            #[cfg(unix)]
+            Self::Unix(socket) => {
+                let stream = crate::unix_socket::connect(socket)?;
+                stream.set_nonblocking(true)?;
+                handshake(tokio::net::UnixStream::from_std(stream)?).await
+            }
+            #[cfg(not(unix))]
+            Self::Unix(socket) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Socket {} requires Unix", socket.display()),
+            )),
+        }
+    }
+
+    /// Value of the Host header
+    fn host(&self) -> &str {
+        match self {
+            Self::Tcp(authority) => authority,
+            Self::Unix(_) => "localhost",
+        }
+    }
+}
+
+async fn handshake<S>(stream: S) -> io::Result<SendRequest<Full<Bytes>>>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .map_err(io::Error::other)?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+    Ok(sender)
+}
+
+/// Body of the daemon's artifact info
+#[derive(Deserialize)]
+struct ArtifactInfo {
+    size_bytes: u64,
+}
+
+/// Storage backed by a running daemon; blobs are addressed by their hex-encoded ID
+pub struct DaemonClient {
+    target: Target,
+    runtime: Runtime,
+}
+
+impl DaemonClient {
+    /// Client of the daemon at `address` (see `Target::parse`), checking that it's up
+    pub fn connect(address: &str) -> Result<Self> {
+        let target = Target::parse(address)?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| FabrikError::Io {
+                context: "Failed to start the client runtime".to_string(),
+                source: e,
+            })?;
+        let client = Self { target, runtime };
+
+        let (status, _) = client.request(Method::GET, "/health", Bytes::new())?;
+        if !status.is_success() {
+            return Err(FabrikError::unavailable(format!(
+                "Daemon at {} isn't healthy ({})",
+                address, status
+            )));
+        }
+        Ok(client)
+    }
+
+    /// Send a request to the daemon, returning the status and body of its response
+    fn request(&self, method: Method, path: &str, body: Bytes) -> Result<(StatusCode, Bytes)> {
+        let context = format!("{} {} failed", method, path);
+        // find-missing is the only POST, and takes JSON
+        let content_type = if method == Method::POST {
+            "application/json"
+        } else {
+            "application/octet-stream"
+        };
+        let response = self.runtime.block_on(async {
+            tokio::time::timeout(REQUEST_TIMEOUT, async {
+                let mut sender = self.target.connect().await?;
+                let request = Request::builder()
+                    .method(method)
+                    .uri(path)
+                    .header(header::HOST, self.target.host())
+                    .header(header::CONTENT_TYPE, content_type)
+                    .body(Full::new(body))
+                    .map_err(io::Error::other)?;
+                let response = sender
+                    .send_request(request)
+                    .await
+                    .map_err(io::Error::other)?;
+                let status = response.status();
+                let body = response
+                    .into_body()
+                    .collect()
+                    .await
+                    .map_err(io::Error::other)?
+                    .to_bytes();
+                Ok::<_, io::Error>((status, body))
+            })
+            .await
+            .unwrap_or_else(|_| Err(io::Error::from(io::ErrorKind::TimedOut)))
+        });
+        response.map_err(|e| FabrikError::UpstreamUnavailable {
+            context,
+            source: Some(Box::new(e)),
+        })
+    }
+
+    /// Error of a response the daemon answered with an unexpected status
+    fn status_error(action: &str, id: &[u8], status: StatusCode, body: &[u8]) -> FabrikError {
+        let message = format!(
+            "Failed to {} {}: {} {}",
+            action,
+            hex::encode(id),
+            status,
+            String::from_utf8_lossy(body).trim()
+        );
+        match status {
+            StatusCode::NOT_FOUND => FabrikError::not_found(message),
+            StatusCode::FORBIDDEN => FabrikError::read_only(message),
+            StatusCode::PAYLOAD_TOO_LARGE | StatusCode::INSUFFICIENT_STORAGE => {
+                FabrikError::QuotaExceeded(message)
+            }
+            _ => FabrikError::unavailable(message),
+        }
+    }
+
+    fn unsupported(operation: &str) -> FabrikError {
+        FabrikError::config(format!(
+            "{} isn't supported through a daemon client",
+            operation
+        ))
+    }
+}
+
+impl Storage for DaemonClient {
+    fn put(&self, id: &[u8], data: &[u8]) -> Result<()> {
+        let path = format!("{}/{}", ARTIFACTS_PATH, hex::encode(id));
+        let (status, body) = self.request(Method::PUT, &path, Bytes::copy_from_slice(data))?;
+        if !status.is_success() {
+            return Err(Self::status_error("store", id, status, &body));
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: &[u8]) -> Result<Option<Vec<u8>>> {
+        let path = format!("{}/{}", ARTIFACTS_PATH, hex::encode(id));
+        match self.request(Method::GET, &path, Bytes::new())? {
+            (StatusCode::OK, body) => Ok(Some(body.to_vec())),
+            (StatusCode::NOT_FOUND, _) => Ok(None),
+            (status, body) => Err(Self::status_error("get", id, status, &body)),
+        }
+    }
+
+    fn exists(&self, id: &[u8]) -> Result<bool> {
+        Ok(self.exists_many(&[id.to_vec()])?[0])
+    }
+
+    /// Asked with find-missing, so blobs the daemon stores as chunks count as stored
+    fn exists_many(&self, ids: &[Vec<u8>]) -> Result<Vec<bool>> {
+        let mut exists = Vec::with_capacity(ids.len());
+        for batch in ids.chunks(FIND_MISSING_BATCH) {
+            let hashes: Vec<String> = batch.iter().map(hex::encode).collect();
+            let request = serde_json::json!({ "hashes": hashes });
+            let (status, body) = self.request(
+                Method::POST,
+                FIND_MISSING_PATH,
+                Bytes::from(request.to_string()),
+            )?;
+            if !status.is_success() {
+                return Err(FabrikError::unavailable(format!(
+                    "Find missing failed: {} {}",
+                    status,
+                    String::from_utf8_lossy(&body).trim()
+                )));
+            }
+            let missing: Vec<String> = serde_json::from_slice::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|response| serde_json::from_value(response["missing"].clone()).ok())
+                .ok_or_else(|| FabrikError::corrupt("Invalid find-missing response"))?;
+            exists.extend(hashes.iter().map(|hash| !missing.contains(hash)));
+        }
+        Ok(exists)
+    }
+
+    fn delete(&self, _id: &[u8]) -> Result<()> {
+        Err(Self::unsupported("Deleting artifacts"))
+    }
+
+    fn size(&self, id: &[u8]) -> Result<Option<u64>> {
+        let path = format!("{}/{}/info", ARTIFACTS_PATH, hex::encode(id));
+        match self.request(Method::GET, &path, Bytes::new())? {
+            (StatusCode::OK, body) => serde_json::from_slice::<ArtifactInfo>(&body)
+                .map(|info| Some(info.size_bytes))
+                .map_err(|e| FabrikError::corrupt(format!("Invalid artifact info: {}", e))),
+            (StatusCode::NOT_FOUND, _) => Ok(None),
+            (status, body) => Err(Self::status_error("get the size of", id, status, &body)),
+        }
+    }
+
+    /// The daemon records accesses itself
+    fn touch(&self, _id: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn list_ids(&self) -> Result<Vec<Vec<u8>>> {
+        Err(Self::unsupported("Listing artifacts"))
+    }
+
+    fn stats(&self) -> Result<StorageStats> {
+        Err(Self::unsupported("Cache statistics"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Bytes as Body;
+    use axum::extract::{Path, State};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    type Artifacts = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Stand-in for the daemon's artifact API
+    fn daemon(artifacts: Artifacts) -> Router {
+        Router::new()
+            .route("/health", get(|| async { "OK" }))
+            .route(
+                "/api/v1/artifacts/{hash}",
+                get(
+                    |State(artifacts): State<Artifacts>, Path(hash): Path<String>| async move {
+                        match artifacts.lock().unwrap().get(&hash) {
+                            Some(data) => (StatusCode::OK, data.clone()),
+                            None => (StatusCode::NOT_FOUND, b"Not found".to_vec()),
+                        }
+                    },
+                )
+                .put(
+                    |State(artifacts): State<Artifacts>,
+                     Path(hash): Path<String>,
+                     body: Body| async move {
+                        artifacts.lock().unwrap().insert(hash, body.to_vec());
+                        "Stored"
+                    },
+                ),
+            )
+            .route(
+                "/api/v1/artifacts/{hash}/info",
+                get(
+                    |State(artifacts): State<Artifacts>, Path(hash): Path<String>| async move {
+                        match artifacts.lock().unwrap().get(&hash) {
+                            Some(data) => Ok(Json(serde_json::json!({ "size_bytes": data.len() }))),
+                            None => Err(StatusCode::NOT_FOUND),
+                        }
+                    },
+                ),
+            )
+            .route(
+                FIND_MISSING_PATH,
+                post(
+                    |State(artifacts): State<Artifacts>, Json(request): Json<serde_json::Value>| async move {
+                        let artifacts = artifacts.lock().unwrap();
+                        let missing: Vec<&str> = request["hashes"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .filter_map(|hash| hash.as_str())
+                            .filter(|hash| !artifacts.contains_key(*hash))
+                            .collect();
+                        Json(serde_json::json!({ "missing": missing }))
+                    },
+                ),
+            )
+            .with_state(artifacts)
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            Target::parse("http://127.0.0.1:7070/").unwrap(),
+            Target::Tcp("127.0.0.1:7070".to_string())
+        );
+        assert_eq!(
+            Target::parse("localhost:7070").unwrap(),
+            Target::Tcp("localhost:7070".to_string())
+        );
+        assert_eq!(
+            Target::parse("unix:.fabrik/http.sock").unwrap(),
+            Target::Unix(PathBuf::from(".fabrik/http.sock"))
+        );
+        assert_eq!(
+            Target::parse("@fabrik-ci").unwrap(),
+            Target::Unix(PathBuf::from("@fabrik-ci"))
+        );
+        assert!(Target::parse("https://cache.example.com").is_err());
+        assert!(Target::parse("http://host:7070/api").is_err());
+    }
+
+    #[test]
+    fn test_daemon_client() {
+        let artifacts = Artifacts::default();
+        let server = tokio::runtime::Runtime::new().unwrap();
+        let listener = server
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let address = listener.local_addr().unwrap();
+        let router = daemon(artifacts.clone());
+        server.spawn(async move { axum::serve(listener, router).await });
+
+        let client = DaemonClient::connect(&format!("http://{}", address)).unwrap();
+        let id = hex::decode("abcdef").unwrap();
+        assert_eq!(client.get(&id).unwrap(), None);
+        assert!(!client.exists(&id).unwrap());
+        assert_eq!(client.size(&id).unwrap(), None);
+
+        client.put(&id, b"through the daemon").unwrap();
+        assert_eq!(
+            artifacts.lock().unwrap().get("abcdef"),
+            Some(&b"through the daemon".to_vec())
+        );
+        assert_eq!(
+            client.get(&id).unwrap(),
+            Some(b"through the daemon".to_vec())
+        );
+        assert!(client.exists(&id).unwrap());
+        assert_eq!(client.size(&id).unwrap(), Some(18));
+        assert!(client.delete(&id).is_err());
+    }
+}
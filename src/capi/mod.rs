@@ -6,8 +6,11 @@
 //! # Memory Management
 //!
 //! - All strings returned by the API must be freed using `fabrik_free_string()`
+//! - Buffers returned by `fabrik_cache_get_alloc()` must be freed using `fabrik_free_buffer()`
+//! - Readers returned by `fabrik_cache_open()` must be closed using `fabrik_reader_close()`
 //! - Error messages are owned by the caller and must be freed
-//! - Cache handles must be freed using `fabrik_cache_free()`
+//! - Cache handles must be freed using `fabrik_cache_free()`, including those of
+//!   `fabrik_client_connect()`
 //!
 //! # Error Handling
 //!
@@ -16,12 +19,16 @@
 //! - Non-zero: Error (use `fabrik_last_error()` to get error message)
 
 use std::ffi::{CStr, CString};
+use std::io::Read;
 use std::os::raw::{c_char, c_int};
 use std::ptr;
 use std::sync::Mutex;
 
+mod client;
+
+use crate::error::FabrikError;
 use crate::eviction::EvictionConfig;
-use crate::storage::{FilesystemStorage, Storage};
+use crate::storage::{EmbeddedStorage, FilesystemStorage, Storage};
 
 // Thread-local error storage
 thread_local! {
@@ -31,7 +38,33 @@ thread_local! {
 /// Opaque handle to a Fabrik cache instance
 #[repr(C)]
 pub struct FabrikCache {
-    storage: FilesystemStorage,
+    storage: Box<dyn Storage>,
+    /// Whether hashes are hex-decoded into IDs, as the daemon's artifact API does
+    /// (client handles); local caches use the hash string itself
+    hex_ids: bool,
+}
+
+impl FabrikCache {
+    fn local(storage: impl Storage + 'static) -> Self {
+        Self {
+            storage: Box::new(storage),
+            hex_ids: false,
+        }
+    }
+
+    /// ID of the artifact `hash`, None if a client handle's hash isn't hex
+    fn artifact_id(&self, hash: &str) -> Option<Vec<u8>> {
+        if self.hex_ids {
+            hex::decode(hash).ok()
+        } else {
+            Some(hash.as_bytes().to_vec())
+        }
+    }
+}
+
+/// Opaque handle to an artifact opened for streaming reads
+pub struct FabrikReader {
+    reader: Box<dyn Read + Send>,
 }
 
 /// Result codes
@@ -51,6 +84,15 @@ fn set_last_error(err: impl std::fmt::Display) {
     }
 }
 
+/// Result code for a storage error
+fn error_code(err: &FabrikError) -> c_int {
+    match err {
+        FabrikError::NotFound(_) => FABRIK_ERROR_NOT_FOUND,
+        FabrikError::Io { .. } => FABRIK_ERROR_IO,
+        _ => FABRIK_ERROR,
+    }
+}
+
 /// Clear the last error
 fn clear_last_error() {
     LAST_ERROR.with(|last| {
@@ -90,7 +132,7 @@ pub unsafe extern "C" fn fabrik_cache_init(cache_dir: *const c_char) -> *mut Fab
     // Use default eviction config (5GB, LFU policy, 7 days TTL)
     let eviction_config = EvictionConfig::default();
     match FilesystemStorage::with_eviction(cache_dir_str, Some(eviction_config)) {
-        Ok(storage) => Box::into_raw(Box::new(FabrikCache { storage })),
+        Ok(storage) => Box::into_raw(Box::new(FabrikCache::local(storage))),
         Err(e) => {
             set_last_error(format!("Failed to initialize cache: {}", e));
             ptr::null_mut()
@@ -166,7 +208,59 @@ pub unsafe extern "C" fn fabrik_cache_init_with_eviction(
     };
 
     match FilesystemStorage::with_eviction(cache_dir_str, Some(eviction_config)) {
-        Ok(storage) => Box::into_raw(Box::new(FabrikCache { storage })),
+        Ok(storage) => Box::into_raw(Box::new(FabrikCache::local(storage))),
+        Err(e) => {
+            set_last_error(format!("Failed to initialize cache: {}", e));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Initialize a new Fabrik cache instance without RocksDB, for small caches
+///
+/// Metadata is kept in a single append-only log, which opens faster and needs no
+/// background threads. Least recently used objects are evicted beyond `max_objects`.
+/// A cache directory can't be opened with both this and the other init functions.
+///
+/// # Arguments
+/// * `cache_dir` - Path to cache directory (NULL-terminated C string)
+/// * `max_objects` - Maximum number of cached objects (0 for default: 10000)
+///
+/// # Returns
+/// * Pointer to FabrikCache on success
+/// * NULL on error (use `fabrik_last_error()` to get error message)
+///
+/// # Safety
+/// * `cache_dir` must be a valid NULL-terminated C string
+/// * Returned pointer must be freed with `fabrik_cache_free()`
+#[no_mangle]
+pub unsafe extern "C" fn fabrik_cache_init_embedded(
+    cache_dir: *const c_char,
+    max_objects: usize,
+) -> *mut FabrikCache {
+    clear_last_error();
+
+    if cache_dir.is_null() {
+        set_last_error("cache_dir is NULL");
+        return ptr::null_mut();
+    }
+
+    let cache_dir_str = match CStr::from_ptr(cache_dir).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_last_error(format!("Invalid UTF-8 in cache_dir: {}", e));
+            return ptr::null_mut();
+        }
+    };
+
+    let max_objects = if max_objects == 0 {
+        crate::storage::embedded::DEFAULT_MAX_OBJECTS
+    } else {
+        max_objects
+    };
+
+    match EmbeddedStorage::open(cache_dir_str, max_objects, None) {
+        Ok(storage) => Box::into_raw(Box::new(FabrikCache::local(storage))),
         Err(e) => {
             set_last_error(format!("Failed to initialize cache: {}", e));
             ptr::null_mut()
@@ -174,10 +268,58 @@ pub unsafe extern "C" fn fabrik_cache_init_with_eviction(
     }
 }
 
+/// Connect to a running daemon instead of opening its cache directory
+///
+/// The daemon holds the lock of its cache directory, so `fabrik_cache_init()` can't
+/// open it while the daemon runs. The handle returned here sends each get, put and
+/// exists to the daemon's HTTP API instead. Hashes must be hex-encoded, as the daemon
+/// decodes them; `fabrik_cache_delete()` isn't supported.
+///
+/// # Arguments
+/// * `url_or_socket` - Daemon's HTTP address (`http://127.0.0.1:7070`) or HTTP socket
+///   (`/path/to/http.sock`, `unix:<path>` or `@name`), as a NULL-terminated C string
+///
+/// # Returns
+/// * Pointer to FabrikCache on success
+/// * NULL if the daemon can't be reached (use `fabrik_last_error()` to get error message)
+///
+/// # Safety
+/// * `url_or_socket` must be a valid NULL-terminated C string
+/// * Returned pointer must be freed with `fabrik_cache_free()`
+#[no_mangle]
+pub unsafe extern "C" fn fabrik_client_connect(url_or_socket: *const c_char) -> *mut FabrikCache {
+    clear_last_error();
+
+    if url_or_socket.is_null() {
+        set_last_error("url_or_socket is NULL");
+        return ptr::null_mut();
+    }
+
+    let address = match CStr::from_ptr(url_or_socket).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_last_error(format!("Invalid UTF-8 in url_or_socket: {}", e));
+            return ptr::null_mut();
+        }
+    };
+
+    match client::DaemonClient::connect(address) {
+        Ok(client) => Box::into_raw(Box::new(FabrikCache {
+            storage: Box::new(client),
+            hex_ids: true,
+        })),
+        Err(e) => {
+            set_last_error(format!("Failed to connect to daemon at {}: {}", address, e));
+            ptr::null_mut()
+        }
+    }
+}
+
 /// Free a Fabrik cache instance
 ///
 /// # Safety
-/// * `cache` must be a valid pointer returned by `fabrik_cache_init()`
+/// * `cache` must be a valid pointer returned by `fabrik_cache_init()` or
+///   `fabrik_client_connect()`
 /// * Must not be used after calling this function
 #[no_mangle]
 pub unsafe extern "C" fn fabrik_cache_free(cache: *mut FabrikCache) {
@@ -193,12 +335,14 @@ pub unsafe extern "C" fn fabrik_cache_free(cache: *mut FabrikCache) {
 /// * `hash` - Content hash (NULL-terminated C string)
 /// * `output_buffer` - Buffer to write data (must be pre-allocated)
 /// * `buffer_size` - Size of output buffer
-/// * `bytes_written` - Output: actual bytes written
+/// * `bytes_written` - Output: actual bytes written, or the size of the artifact if the
+///   buffer is too small
 ///
 /// # Returns
 /// * `FABRIK_OK` on success
 /// * `FABRIK_ERROR_NOT_FOUND` if artifact not found
-/// * `FABRIK_ERROR` on other errors
+/// * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+/// * `FABRIK_ERROR` on other errors (including a buffer that is too small)
 ///
 /// # Safety
 /// * All pointers must be valid
@@ -226,10 +370,15 @@ pub unsafe extern "C" fn fabrik_cache_get(
             return FABRIK_ERROR;
         }
     };
+    let Some(id) = cache.artifact_id(hash_str) else {
+        set_last_error(format!("Invalid hash format (hex expected): {}", hash_str));
+        return FABRIK_ERROR_INVALID_HASH;
+    };
 
-    match cache.storage.get(hash_str.as_bytes()) {
+    match cache.storage.get(&id) {
         Ok(Some(data)) => {
             if data.len() > buffer_size {
+                *bytes_written = data.len();
                 set_last_error(format!(
                     "Buffer too small: need {} bytes, have {}",
                     data.len(),
@@ -248,11 +397,247 @@ pub unsafe extern "C" fn fabrik_cache_get(
         }
         Err(e) => {
             set_last_error(format!("Failed to get artifact: {}", e));
-            FABRIK_ERROR
+            error_code(&e)
+        }
+    }
+}
+
+/// Get the size of an artifact, e.g. to allocate the buffer of `fabrik_cache_get()`
+///
+/// # Arguments
+/// * `cache` - Cache instance
+/// * `hash` - Content hash (NULL-terminated C string)
+/// * `size` - Output: size of the artifact in bytes
+///
+/// # Returns
+/// * `FABRIK_OK` on success
+/// * `FABRIK_ERROR_NOT_FOUND` if artifact not found
+/// * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+/// * `FABRIK_ERROR` on other errors
+///
+/// # Safety
+/// * All pointers must be valid
+#[no_mangle]
+pub unsafe extern "C" fn fabrik_cache_get_size(
+    cache: *mut FabrikCache,
+    hash: *const c_char,
+    size: *mut usize,
+) -> c_int {
+    clear_last_error();
+
+    if cache.is_null() || hash.is_null() || size.is_null() {
+        set_last_error("NULL pointer argument");
+        return FABRIK_ERROR;
+    }
+
+    let cache = &(*cache);
+    let hash_str = match CStr::from_ptr(hash).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_last_error(format!("Invalid UTF-8 in hash: {}", e));
+            return FABRIK_ERROR;
+        }
+    };
+    let Some(id) = cache.artifact_id(hash_str) else {
+        set_last_error(format!("Invalid hash format (hex expected): {}", hash_str));
+        return FABRIK_ERROR_INVALID_HASH;
+    };
+
+    match cache.storage.size(&id) {
+        Ok(Some(artifact_size)) => {
+            *size = artifact_size as usize;
+            FABRIK_OK
+        }
+        Ok(None) => {
+            set_last_error(format!("Artifact not found: {}", hash_str));
+            FABRIK_ERROR_NOT_FOUND
+        }
+        Err(e) => {
+            set_last_error(format!("Failed to get artifact size: {}", e));
+            error_code(&e)
         }
     }
 }
 
+/// Get an artifact from the cache into a buffer allocated by the library
+///
+/// # Arguments
+/// * `cache` - Cache instance
+/// * `hash` - Content hash (NULL-terminated C string)
+/// * `data` - Output: the artifact's data
+/// * `data_len` - Output: length of data in bytes
+///
+/// # Returns
+/// * `FABRIK_OK` on success
+/// * `FABRIK_ERROR_NOT_FOUND` if artifact not found
+/// * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+/// * `FABRIK_ERROR` on other errors
+///
+/// # Safety
+/// * All pointers must be valid
+/// * On success, `*data` must be freed with `fabrik_free_buffer(*data, *data_len)`
+#[no_mangle]
+pub unsafe extern "C" fn fabrik_cache_get_alloc(
+    cache: *mut FabrikCache,
+    hash: *const c_char,
+    data: *mut *mut u8,
+    data_len: *mut usize,
+) -> c_int {
+    clear_last_error();
+
+    if cache.is_null() || hash.is_null() || data.is_null() || data_len.is_null() {
+        set_last_error("NULL pointer argument");
+        return FABRIK_ERROR;
+    }
+
+    let cache = &(*cache);
+    let hash_str = match CStr::from_ptr(hash).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_last_error(format!("Invalid UTF-8 in hash: {}", e));
+            return FABRIK_ERROR;
+        }
+    };
+    let Some(id) = cache.artifact_id(hash_str) else {
+        set_last_error(format!("Invalid hash format (hex expected): {}", hash_str));
+        return FABRIK_ERROR_INVALID_HASH;
+    };
+
+    match cache.storage.get(&id) {
+        Ok(Some(artifact)) => {
+            let buffer = artifact.into_boxed_slice();
+            *data_len = buffer.len();
+            *data = Box::into_raw(buffer) as *mut u8;
+            FABRIK_OK
+        }
+        Ok(None) => {
+            set_last_error(format!("Artifact not found: {}", hash_str));
+            FABRIK_ERROR_NOT_FOUND
+        }
+        Err(e) => {
+            set_last_error(format!("Failed to get artifact: {}", e));
+            error_code(&e)
+        }
+    }
+}
+
+/// Open an artifact for reading in pieces, without loading it into memory
+///
+/// # Arguments
+/// * `cache` - Cache instance
+/// * `hash` - Content hash (NULL-terminated C string)
+/// * `reader` - Output: reader of the artifact
+///
+/// # Returns
+/// * `FABRIK_OK` on success
+/// * `FABRIK_ERROR_NOT_FOUND` if artifact not found
+/// * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+/// * `FABRIK_ERROR` on other errors
+///
+/// # Safety
+/// * All pointers must be valid
+/// * On success, `*reader` must be closed with `fabrik_reader_close()`
+#[no_mangle]
+pub unsafe extern "C" fn fabrik_cache_open(
+    cache: *mut FabrikCache,
+    hash: *const c_char,
+    reader: *mut *mut FabrikReader,
+) -> c_int {
+    clear_last_error();
+
+    if cache.is_null() || hash.is_null() || reader.is_null() {
+        set_last_error("NULL pointer argument");
+        return FABRIK_ERROR;
+    }
+
+    let cache = &(*cache);
+    let hash_str = match CStr::from_ptr(hash).to_str() {
+        Ok(s) => s,
+        Err(e) => {
+            set_last_error(format!("Invalid UTF-8 in hash: {}", e));
+            return FABRIK_ERROR;
+        }
+    };
+    let Some(id) = cache.artifact_id(hash_str) else {
+        set_last_error(format!("Invalid hash format (hex expected): {}", hash_str));
+        return FABRIK_ERROR_INVALID_HASH;
+    };
+
+    match cache.storage.open(&id) {
+        Ok(Some(opened)) => {
+            *reader = Box::into_raw(Box::new(FabrikReader { reader: opened }));
+            FABRIK_OK
+        }
+        Ok(None) => {
+            set_last_error(format!("Artifact not found: {}", hash_str));
+            FABRIK_ERROR_NOT_FOUND
+        }
+        Err(e) => {
+            set_last_error(format!("Failed to open artifact: {}", e));
+            error_code(&e)
+        }
+    }
+}
+
+/// Read the next bytes of an opened artifact
+///
+/// # Arguments
+/// * `reader` - Reader returned by `fabrik_cache_open()`
+/// * `buffer` - Buffer to write data
+/// * `buffer_size` - Size of buffer
+/// * `bytes_read` - Output: bytes written to the buffer, 0 at the end of the artifact
+///
+/// # Returns
+/// * `FABRIK_OK` on success
+/// * `FABRIK_ERROR_IO` if the artifact can't be read (e.g. it was evicted)
+/// * `FABRIK_ERROR` on other errors
+///
+/// # Safety
+/// * All pointers must be valid
+/// * `buffer` must have at least `buffer_size` bytes allocated
+#[no_mangle]
+pub unsafe extern "C" fn fabrik_reader_read(
+    reader: *mut FabrikReader,
+    buffer: *mut u8,
+    buffer_size: usize,
+    bytes_read: *mut usize,
+) -> c_int {
+    clear_last_error();
+
+    if reader.is_null() || buffer.is_null() || bytes_read.is_null() {
+        set_last_error("NULL pointer argument");
+        return FABRIK_ERROR;
+    }
+
+    let reader = &mut (*reader);
+    let buffer = std::slice::from_raw_parts_mut(buffer, buffer_size);
+    loop {
+        match reader.reader.read(buffer) {
+            Ok(read) => {
+                *bytes_read = read;
+                return FABRIK_OK;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                set_last_error(format!("Failed to read artifact: {}", e));
+                return FABRIK_ERROR_IO;
+            }
+        }
+    }
+}
+
+/// Close a reader
+///
+/// # Safety
+/// * `reader` must be a valid pointer returned by `fabrik_cache_open()`
+/// * Must not be used after calling this function
+#[no_mangle]
+pub unsafe extern "C" fn fabrik_reader_close(reader: *mut FabrikReader) {
+    if !reader.is_null() {
+        let _ = Box::from_raw(reader);
+    }
+}
+
 /// Put an artifact into the cache
 ///
 /// # Arguments
@@ -263,7 +648,8 @@ pub unsafe extern "C" fn fabrik_cache_get(
 ///
 /// # Returns
 /// * `FABRIK_OK` on success
-/// * `FABRIK_ERROR` on error
+/// * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+/// * `FABRIK_ERROR` on other errors
 ///
 /// # Safety
 /// * All pointers must be valid
@@ -290,14 +676,18 @@ pub unsafe extern "C" fn fabrik_cache_put(
             return FABRIK_ERROR;
         }
     };
+    let Some(id) = cache.artifact_id(hash_str) else {
+        set_last_error(format!("Invalid hash format (hex expected): {}", hash_str));
+        return FABRIK_ERROR_INVALID_HASH;
+    };
 
     let data_slice = std::slice::from_raw_parts(data, data_len);
 
-    match cache.storage.put(hash_str.as_bytes(), data_slice) {
+    match cache.storage.put(&id, data_slice) {
         Ok(_) => FABRIK_OK,
         Err(e) => {
             set_last_error(format!("Failed to put artifact: {}", e));
-            FABRIK_ERROR
+            error_code(&e)
         }
     }
 }
@@ -311,7 +701,8 @@ pub unsafe extern "C" fn fabrik_cache_put(
 ///
 /// # Returns
 /// * `FABRIK_OK` on success
-/// * `FABRIK_ERROR` on error
+/// * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+/// * `FABRIK_ERROR` on other errors
 ///
 /// # Safety
 /// * All pointers must be valid
@@ -336,15 +727,19 @@ pub unsafe extern "C" fn fabrik_cache_exists(
             return FABRIK_ERROR;
         }
     };
+    let Some(id) = cache.artifact_id(hash_str) else {
+        set_last_error(format!("Invalid hash format (hex expected): {}", hash_str));
+        return FABRIK_ERROR_INVALID_HASH;
+    };
 
-    match cache.storage.exists(hash_str.as_bytes()) {
+    match cache.storage.exists(&id) {
         Ok(result) => {
             *exists = if result { 1 } else { 0 };
             FABRIK_OK
         }
         Err(e) => {
             set_last_error(format!("Failed to check existence: {}", e));
-            FABRIK_ERROR
+            error_code(&e)
         }
     }
 }
@@ -357,7 +752,8 @@ pub unsafe extern "C" fn fabrik_cache_exists(
 ///
 /// # Returns
 /// * `FABRIK_OK` on success
-/// * `FABRIK_ERROR` on error
+/// * `FABRIK_ERROR_IO` if the cache directory can't be read or written
+/// * `FABRIK_ERROR` on other errors
 ///
 /// # Safety
 /// * All pointers must be valid
@@ -381,12 +777,16 @@ pub unsafe extern "C" fn fabrik_cache_delete(
             return FABRIK_ERROR;
         }
     };
+    let Some(id) = cache.artifact_id(hash_str) else {
+        set_last_error(format!("Invalid hash format (hex expected): {}", hash_str));
+        return FABRIK_ERROR_INVALID_HASH;
+    };
 
-    match cache.storage.delete(hash_str.as_bytes()) {
+    match cache.storage.delete(&id) {
         Ok(_) => FABRIK_OK,
         Err(e) => {
             set_last_error(format!("Failed to delete artifact: {}", e));
-            FABRIK_ERROR
+            error_code(&e)
         }
     }
 }
@@ -422,6 +822,18 @@ pub unsafe extern "C" fn fabrik_free_string(s: *mut c_char) {
     }
 }
 
+/// Free a buffer allocated by the Fabrik library
+///
+/// # Safety
+/// * `buffer` and `len` must be a buffer and its length returned by
+///   `fabrik_cache_get_alloc()`
+#[no_mangle]
+pub unsafe extern "C" fn fabrik_free_buffer(buffer: *mut u8, len: usize) {
+    if !buffer.is_null() {
+        let _ = Box::from_raw(ptr::slice_from_raw_parts_mut(buffer, len));
+    }
+}
+
 /// Get the library version
 ///
 /// # Returns
@@ -0,0 +1,232 @@
+//! Content-defined chunking of large artifacts (`[[upstream]] chunking`)
+//!
+//! Large artifacts such as app bundles and archives often differ only slightly between
+//! builds. FastCDC cuts them at content-defined boundaries, so an edit only changes the
+//! chunks around it, and a Layer 1 cache uploads only the chunks Layer 2 lacks:
+//!
+//! 1. chunks are stored as ordinary artifacts, under their SHA256
+//!    (`POST /api/v1/artifacts/find-missing`, then `PUT /api/v1/artifacts/{chunk}`)
+//! 2. the blob is stored as a [`ChunkManifest`], the list of its chunks
+//!    (`PUT /api/v1/artifacts/{hash}/chunks`)
+//!
+//! Layer 2 serves chunked blobs like any other (`GET /api/v1/artifacts/{hash}`),
+//! assembling them from their chunks. Artifacts under [`MIN_CHUNKED_SIZE`] are always
+//! transferred whole.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{FabrikError, Result};
+use crate::storage::Storage;
+
+/// Smallest artifact transferred as chunks; smaller ones go whole
+pub const MIN_CHUNKED_SIZE: usize = 4 * 1024 * 1024;
+
+/// Chunk sizes: no cut before MIN, cuts get likelier past AVG, forced cut at MAX
+const MIN_CHUNK_SIZE: usize = 256 * 1024;
+const AVG_CHUNK_SIZE: usize = 1024 * 1024;
+const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Cut masks of normalized chunking (level 2), over the top bits of the gear hash:
+/// before AVG_CHUNK_SIZE a cut needs 22 zero bits, after it 18 (log2(AVG) = 20)
+const MASK_HARD: u64 = ((1 << 22) - 1) << (64 - 22);
+const MASK_EASY: u64 = ((1 << 18) - 1) << (64 - 18);
+
+/// Random value of each byte for the gear hash; fixed, so every machine cuts the same
+/// content at the same boundaries
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // SplitMix64
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x6661_6272_696b_0001;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Length of the first chunk of `data`
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK_SIZE);
+    let normal = end.min(AVG_CHUNK_SIZE);
+
+    let mut hash: u64 = 0;
+    for (i, byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        let mask = if i < normal { MASK_HARD } else { MASK_EASY };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Content-defined chunks of `data`, in order
+pub fn chunks(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (chunk, tail) = rest.split_at(cut_point(rest));
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+/// Chunk of a chunked blob
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkRef {
+    /// SHA256 of the chunk (hex)
+    pub hash: String,
+    pub size: u64,
+}
+
+/// Chunks a blob is made of, in order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkManifest {
+    pub size: u64,
+    pub chunks: Vec<ChunkRef>,
+}
+
+impl ChunkManifest {
+    /// Manifest of `chunks` (from [`chunks`])
+    pub fn new(chunks: &[&[u8]]) -> Self {
+        Self {
+            size: chunks.iter().map(|chunk| chunk.len() as u64).sum(),
+            chunks: chunks
+                .iter()
+                .map(|chunk| ChunkRef {
+                    hash: hex::encode(Sha256::digest(chunk)),
+                    size: chunk.len() as u64,
+                })
+                .collect(),
+        }
+    }
+
+    /// Chunk hashes, in order
+    pub fn hashes(&self) -> Vec<String> {
+        self.chunks.iter().map(|chunk| chunk.hash.clone()).collect()
+    }
+}
+
+/// Artifact ID the manifest of blob `hash` is stored under
+pub fn manifest_id(hash: &str) -> String {
+    hex::encode(Sha256::digest(format!("chunks/{}", hash).as_bytes()))
+}
+
+/// Manifest of the chunked blob `hash`, None if it isn't stored as chunks
+pub fn load_manifest<S: Storage + ?Sized>(
+    storage: &S,
+    hash: &str,
+) -> Result<Option<ChunkManifest>> {
+    let Some(json) = storage.get(&hex::decode(manifest_id(hash)).expect("hex"))? else {
+        return Ok(None);
+    };
+    serde_json::from_slice(&json)
+        .map(Some)
+        .map_err(|e| FabrikError::corrupt(format!("Invalid chunk manifest of {}: {}", hash, e)))
+}
+
+/// Store the manifest of blob `hash`, whose chunks are all stored
+pub fn store_manifest<S: Storage + ?Sized>(
+    storage: &S,
+    hash: &str,
+    manifest: &ChunkManifest,
+) -> Result<()> {
+    let json = serde_json::to_vec(manifest).expect("manifest serializes");
+    storage.put(&hex::decode(manifest_id(hash)).expect("hex"), &json)
+}
+
+/// Chunks of `manifest` that aren't stored, in order
+pub fn missing_chunks<S: Storage + ?Sized>(
+    storage: &S,
+    manifest: &ChunkManifest,
+) -> Result<Vec<String>> {
+    let ids = manifest
+        .chunks
+        .iter()
+        .map(|chunk| hex::decode(&chunk.hash))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| FabrikError::corrupt("Invalid chunk hash"))?;
+    let exists = storage.exists_many(&ids)?;
+    Ok(manifest
+        .chunks
+        .iter()
+        .zip(exists)
+        .filter(|(_, exists)| !exists)
+        .map(|(chunk, _)| chunk.hash.clone())
+        .collect())
+}
+
+/// Blob assembled from the chunks of `manifest`, None if a chunk is missing (e.g.
+/// evicted)
+pub fn assemble<S: Storage + ?Sized>(
+    storage: &S,
+    manifest: &ChunkManifest,
+) -> Result<Option<Vec<u8>>> {
+    let mut data = Vec::with_capacity(manifest.size as usize);
+    for chunk in &manifest.chunks {
+        let id =
+            hex::decode(&chunk.hash).map_err(|_| FabrikError::corrupt("Invalid chunk hash"))?;
+        match storage.get(&id)? {
+            Some(bytes) => data.extend_from_slice(&bytes),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Incompressible test data (xorshift)
+    fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks() {
+        // Small data is a single chunk
+        assert_eq!(chunks(b"small").len(), 1);
+        assert!(chunks(&[]).is_empty());
+
+        let data = random_bytes(12 * 1024 * 1024, 42);
+        let original = chunks(&data);
+        assert!(original.len() > 3);
+        assert_eq!(original.concat(), data);
+        assert!(original.iter().all(|chunk| chunk.len() <= MAX_CHUNK_SIZE));
+
+        // An insertion near the start only changes the chunks around it
+        let mut edited = b"inserted".to_vec();
+        edited.extend_from_slice(&data);
+        let before: HashSet<String> = ChunkManifest::new(&original).hashes().into_iter().collect();
+        let after = ChunkManifest::new(&chunks(&edited)).hashes();
+        let changed = after.iter().filter(|hash| !before.contains(*hash)).count();
+        assert!(
+            changed <= 2,
+            "{} of {} chunks changed",
+            changed,
+            after.len()
+        );
+    }
+}
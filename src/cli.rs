@@ -1,5 +1,8 @@
 use clap::{Parser, Subcommand};
 
+use crate::cli_utils::OutputFormat;
+use crate::completion::{CompletionKind, CompletionShell, DEFAULT_LIMIT};
+
 /// Fabrik - Multi-layer build cache infrastructure
 ///
 /// Fabrik provides transparent, high-performance caching for build systems
@@ -10,6 +13,21 @@ use clap::{Parser, Subcommand};
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(about = "Multi-layer build cache infrastructure", long_about = None)]
 pub struct Cli {
+    /// Apply the [profile.<name>] overrides of the config files (e.g., ci)
+    #[arg(long, global = true, env = "FABRIK_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Output mode: text, or json for one JSON document per line and JSON error objects
+    /// on stderr (not `--output`, which names output files of several commands)
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        env = "FABRIK_OUTPUT_FORMAT",
+        default_value = "text"
+    )]
+    pub output_format: OutputFormat,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -79,21 +97,92 @@ pub enum Commands {
     /// Key-Value storage operations (Action Cache)
     Kv(KvArgs),
 
+    /// Cache usage statistics
+    Stats(StatsArgs),
+
+    /// Live dashboard of the daemon serving the current directory
+    Top(TopArgs),
+
     /// Authentication management
     Auth(AuthArgs),
 
     /// P2P cache sharing management
     P2p(P2pArgs),
+
+    /// Print a shell completion script of all commands and options
+    Completions(CompletionsArgs),
+
+    /// Generate man pages
+    Man(ManArgs),
+
+    /// Print completion candidates for cache hashes and keys (used by shell hooks)
+    #[command(name = "__complete", hide = true)]
+    Complete(CompleteArgs),
 }
 
 #[derive(Parser, Debug)]
 pub struct ActivateArgs {
-    /// Shell type (bash, zsh, fish)
+    /// Shell type (bash, zsh, fish, powershell/pwsh, nu/nushell)
     pub shell: Option<String>,
 
-    /// Check status and start daemon if needed
+    /// Check status and start daemon if needed, printing env vars for the shell (bash
+    /// by default)
     #[arg(long)]
     pub status: bool,
+
+    /// Print a direnv library function (`use fabrik` in an .envrc); with --status, also
+    /// have direnv watch the config file
+    #[arg(long)]
+    pub direnv: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct CompleteArgs {
+    /// What to complete
+    #[arg(value_enum)]
+    pub kind: CompletionKind,
+
+    /// Prefix typed so far
+    #[arg(default_value = "")]
+    pub prefix: String,
+
+    /// Maximum number of candidates
+    #[arg(long, default_value_t = DEFAULT_LIMIT)]
+    pub limit: usize,
+
+    /// Local cache directory (used when no daemon is running)
+    #[arg(long, env = "FABRIK_CONFIG_CACHE_DIR")]
+    pub config_cache_dir: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct TopArgs {
+    /// Config file path (discovered from the current directory if omitted)
+    #[arg(short = 'c', long, env = "FABRIK_CONFIG")]
+    pub config: Option<String>,
+
+    /// Seconds between refreshes
+    #[arg(long, default_value_t = 1.0)]
+    pub interval: f64,
+
+    /// Print the daemon's statistics once, as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct CompletionsArgs {
+    /// Shell to generate the script for
+    #[arg(value_enum)]
+    pub shell: CompletionShell,
+}
+
+#[derive(Parser, Debug)]
+pub struct ManArgs {
+    /// Directory to write the man pages of fabrik and all its subcommands to (the page
+    /// of fabrik itself is printed to stdout if omitted)
+    #[arg(short, long)]
+    pub output: Option<std::path::PathBuf>,
 }
 
 #[derive(Parser, Debug)]
@@ -142,7 +231,7 @@ pub struct ExecArgs {
     #[arg(long, env = "FABRIK_CONFIG_GRPC_PORT")]
     pub config_grpc_port: Option<u16>,
 
-    /// S3 API port (0 = random)
+    /// S3 API port (0 = random; the API is off when unset)
     #[arg(long, env = "FABRIK_CONFIG_S3_PORT")]
     pub config_s3_port: Option<u16>,
 
@@ -162,6 +251,14 @@ pub struct ExecArgs {
     #[arg(long, env = "FABRIK_CONFIG_OFFLINE")]
     pub config_offline: bool,
 
+    /// Serve the local cache read-only, rejecting puts and deletes
+    #[arg(long, env = "FABRIK_CONFIG_READ_ONLY")]
+    pub config_read_only: bool,
+
+    /// Hash algorithm for object checksums (sha256|blake3)
+    #[arg(long, env = "FABRIK_CONFIG_HASH_ALGORITHM")]
+    pub config_hash_algorithm: Option<crate::hashing::HashAlgorithm>,
+
     /// Log level (trace|debug|info|warn|error)
     #[arg(long, env = "FABRIK_CONFIG_LOG_LEVEL")]
     pub config_log_level: Option<String>,
@@ -170,6 +267,14 @@ pub struct ExecArgs {
     #[arg(long, env = "FABRIK_CONFIG_METRICS_PORT")]
     pub config_metrics_port: Option<u16>,
 
+    /// Publish cache topology to Bazel's Build Event Stream (--build_metadata)
+    #[arg(long, env = "FABRIK_CONFIG_BUILD_METADATA")]
+    pub config_build_metadata: bool,
+
+    /// Write cache topology and hit rate as JSON to this file when the command exits
+    #[arg(long, env = "FABRIK_CONFIG_BUILD_METADATA_FILE")]
+    pub config_build_metadata_file: Option<String>,
+
     // RUNTIME-ONLY OPTIONS (not in config file)
     /// Export cache URLs as environment variables
     #[arg(long)]
@@ -179,6 +284,20 @@ pub struct ExecArgs {
     #[arg(long, default_value = "FABRIK_")]
     pub env_prefix: String,
 
+    /// Serve the cache from this process even if a daemon is running for the config
+    #[arg(long, env = "FABRIK_EXEC_IN_PROCESS")]
+    pub in_process: bool,
+
+    /// Print the ports, environment, upstreams and build systems the command would use,
+    /// without starting servers or running it
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// ID tagging the command's cache traffic, e.g. the CI job ID (generated if omitted;
+    /// see `fabrik stats --invocation`)
+    #[arg(long, env = "FABRIK_INVOCATION_ID")]
+    pub invocation_id: Option<String>,
+
     /// Command to execute
     #[arg(last = true, required = true)]
     pub command: Vec<String>,
@@ -230,6 +349,12 @@ pub struct DaemonArgs {
     #[arg(long, env = "FABRIK_CONFIG_OFFLINE")]
     pub config_offline: bool,
 
+    #[arg(long, env = "FABRIK_CONFIG_READ_ONLY")]
+    pub config_read_only: bool,
+
+    #[arg(long, env = "FABRIK_CONFIG_HASH_ALGORITHM")]
+    pub config_hash_algorithm: Option<crate::hashing::HashAlgorithm>,
+
     #[arg(long, env = "FABRIK_CONFIG_LOG_LEVEL")]
     pub config_log_level: Option<String>,
 
@@ -248,10 +373,18 @@ pub struct DaemonArgs {
     /// Unix socket for IPC
     #[arg(long)]
     pub socket: Option<String>,
+
+    /// Print the listeners, environment, upstreams and build systems the daemon would
+    /// use, without starting it
+    #[arg(long)]
+    pub dry_run: bool,
 }
 
 #[derive(Parser, Debug)]
 pub struct ServerArgs {
+    #[command(subcommand)]
+    pub command: Option<ServerCommand>,
+
     /// Config file path
     #[arg(short = 'c', long, env = "FABRIK_CONFIG")]
     pub config: Option<String>,
@@ -311,6 +444,10 @@ pub struct ServerArgs {
     #[arg(long, env = "FABRIK_CONFIG_JWT_KEY_REFRESH")]
     pub config_jwt_key_refresh: Option<String>,
 
+    /// How long rotated-out JWT keys stay valid (e.g. 1h)
+    #[arg(long, env = "FABRIK_CONFIG_JWT_KEY_GRACE_PERIOD")]
+    pub config_jwt_key_grace_period: Option<String>,
+
     #[arg(long, env = "FABRIK_CONFIG_JWT_REQUIRED")]
     pub config_jwt_required: Option<bool>,
 
@@ -324,6 +461,12 @@ pub struct ServerArgs {
     #[arg(long, env = "FABRIK_CONFIG_WRITE_THROUGH")]
     pub config_write_through: bool,
 
+    #[arg(long, env = "FABRIK_CONFIG_READ_ONLY")]
+    pub config_read_only: bool,
+
+    #[arg(long, env = "FABRIK_CONFIG_HASH_ALGORITHM")]
+    pub config_hash_algorithm: Option<crate::hashing::HashAlgorithm>,
+
     #[arg(long, env = "FABRIK_CONFIG_UPSTREAM_WORKERS")]
     pub config_upstream_workers: Option<u32>,
 
@@ -367,6 +510,103 @@ pub struct ServerArgs {
     // HIGH AVAILABILITY
     #[arg(long, env = "FABRIK_CONFIG_GRACEFUL_SHUTDOWN")]
     pub config_graceful_shutdown: Option<String>,
+
+    // RATE LIMITING
+    /// Sustained requests per second per client (0 = unlimited)
+    #[arg(long, env = "FABRIK_CONFIG_RATE_LIMIT_PER_CLIENT")]
+    pub config_rate_limit_per_client: Option<u32>,
+
+    /// Burst size per client (0 = same as the rate)
+    #[arg(long, env = "FABRIK_CONFIG_RATE_LIMIT_BURST")]
+    pub config_rate_limit_burst: Option<u32>,
+
+    /// Maximum requests in flight per client (0 = unlimited)
+    #[arg(long, env = "FABRIK_CONFIG_MAX_IN_FLIGHT_PER_CLIENT")]
+    pub config_max_in_flight_per_client: Option<u32>,
+
+    // UPLOAD LIMITS
+    /// Maximum artifact size for all protocols (e.g., "5GB")
+    #[arg(long, env = "FABRIK_CONFIG_MAX_ARTIFACT_SIZE")]
+    pub config_max_artifact_size: Option<String>,
+
+    /// Bytes each client may upload per day (e.g., "500GB")
+    #[arg(long, env = "FABRIK_CONFIG_DAILY_UPLOAD_QUOTA")]
+    pub config_daily_upload_quota: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ServerCommand {
+    /// Copy the artifacts another region's server has and this one is missing
+    Replicate(ServerReplicateArgs),
+    /// Back up the cache as a consistent snapshot (the server mustn't be running)
+    Backup(ServerBackupArgs),
+    /// Restore a snapshot into an empty cache directory
+    Restore(ServerRestoreArgs),
+}
+
+#[derive(Parser, Debug)]
+pub struct ServerReplicateArgs {
+    /// gRPC address of the server to copy from (e.g., grpc://cache.us.example.com:7070)
+    #[arg(long)]
+    pub from: String,
+
+    /// gRPC address of the server to copy to (default: this server's gRPC bind address)
+    #[arg(long)]
+    pub to: Option<String>,
+
+    /// Cluster secret of the server copied to (default: [cluster] secret)
+    #[arg(long, env = "FABRIK_CLUSTER_SECRET")]
+    pub secret: Option<String>,
+
+    /// Cluster secret of the server copied from (default: the --secret)
+    #[arg(long, env = "FABRIK_REPLICATE_FROM_SECRET")]
+    pub from_secret: Option<String>,
+
+    /// Only copy these namespaces (xcode-cas, xcode-kv)
+    #[arg(long = "namespace", value_delimiter = ',')]
+    pub namespaces: Vec<String>,
+
+    /// Only copy artifacts up to this size (e.g., 100MB)
+    #[arg(long)]
+    pub max_size: Option<String>,
+
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct ServerBackupArgs {
+    /// Where to back up to: s3://bucket/path or a directory (default: [backup] url)
+    #[arg(long)]
+    pub to: Option<String>,
+
+    /// Objects uploaded at once (default: [backup] jobs)
+    #[arg(long)]
+    pub jobs: Option<usize>,
+
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct ServerRestoreArgs {
+    /// Where to restore from: s3://bucket/path or a directory (default: [backup] url)
+    #[arg(long)]
+    pub from: Option<String>,
+
+    /// Snapshot to restore (default: the newest)
+    #[arg(long)]
+    pub snapshot: Option<String>,
+
+    /// Objects downloaded at once (default: [backup] jobs)
+    #[arg(long)]
+    pub jobs: Option<usize>,
+
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -381,18 +621,56 @@ pub enum ConfigCommands {
     Validate {
         /// Path to config file
         path: String,
+
+        /// Reject unknown keys (typos), reporting the line and column of each
+        #[arg(long)]
+        strict: bool,
     },
+    /// Print the JSON Schema of config files (for editor completion and CI checks)
+    Schema,
     /// Generate example config file
     Generate {
         /// Template type (exec, daemon, server)
         #[arg(long, default_value = "server")]
         template: String,
+
+        /// Output format (toml, yaml, json)
+        #[arg(long, default_value = "toml")]
+        format: String,
     },
     /// Show effective configuration (merged from all sources)
     Show {
         /// Config file path
         #[arg(short = 'c', long, env = "FABRIK_CONFIG")]
         config: Option<String>,
+
+        /// Annotate each value with the file, profile or environment variable it comes from
+        #[arg(long)]
+        origin: bool,
+    },
+    /// Print the effective value of a key (e.g. `cache.max_size`)
+    Get {
+        /// Dotted key
+        key: String,
+
+        /// Config file path
+        #[arg(short = 'c', long, env = "FABRIK_CONFIG")]
+        config: Option<String>,
+    },
+    /// Set a key in the config file, keeping its comments and formatting
+    ///
+    /// Edits the project's fabrik.toml (created in the current directory if there's
+    /// none), or the profile's table with --profile.
+    Set {
+        /// Dotted key
+        key: String,
+
+        /// Value; arrays as `a,b` or `["a", "b"]`
+        value: String,
+
+        /// Config file path
+        #[arg(short = 'c', long, env = "FABRIK_CONFIG")]
+        config: Option<String>,
     },
 }
 
@@ -420,6 +698,15 @@ pub struct DoctorArgs {
     /// Verbose output
     #[arg(short, long, env = "FABRIK_VERBOSE")]
     pub verbose: bool,
+
+    /// Print the checks as JSON (name, status, message and fix of each)
+    #[arg(long)]
+    pub json: bool,
+
+    /// Write a support bundle (tar.gz) to attach to bug reports
+    /// (default path: fabrik-bundle-<timestamp>.tar.gz)
+    #[arg(long, value_name = "PATH", num_args = 0..=1, env = "FABRIK_DOCTOR_BUNDLE")]
+    pub bundle: Option<Option<String>>,
 }
 
 #[derive(Parser, Debug)]
@@ -439,11 +726,22 @@ pub struct InitArgs {
     /// Upstream cache URL
     #[arg(long)]
     pub upstream_url: Option<String>,
+
+    /// Build systems to configure instead of detecting them
+    /// (gradle, bazel, nx, turborepo, xcode, cargo)
+    #[arg(long, value_delimiter = ',')]
+    pub build_systems: Option<Vec<String>>,
+
+    /// Point Bazel (.bazelrc import) and Gradle (init script in ~/.gradle/init.d) at
+    /// the daemon
+    #[arg(long)]
+    pub write_snippets: bool,
 }
 
 #[derive(Parser, Debug)]
 pub struct RunArgs {
-    /// Runtime and script file (either "script.sh" or "bash script.sh")
+    /// Runtime and script file (either "script.sh" or "bash script.sh"), or a portable
+    /// recipe and the tasks to run ("recipe.js build test")
     /// Omit to use --status, --list, or --stats
     pub positional_args: Vec<String>,
 
@@ -473,6 +771,14 @@ pub struct RunArgs {
     #[arg(long)]
     pub dry_run: bool,
 
+    /// Print the components of the cache key, and what changed since the last --explain
+    #[arg(
+        long,
+        env = "FABRIK_RUN_EXPLAIN",
+        conflicts_with_all = ["dry_run", "watch", "no_cache"]
+    )]
+    pub explain: bool,
+
     /// Verbose output
     #[arg(short, long)]
     pub verbose: bool,
@@ -485,6 +791,79 @@ pub struct RunArgs {
     #[arg(long)]
     pub clean: bool,
 
+    /// Only use the local cache, not the upstreams in the config file
+    #[arg(long, env = "FABRIK_RUN_NO_UPSTREAM")]
+    pub no_upstream: bool,
+
+    /// Don't lock the cache key (allows concurrent runs of the same script to execute in parallel)
+    #[arg(long, env = "FABRIK_RUN_NO_LOCK")]
+    pub no_lock: bool,
+
+    /// Max time to wait for another process running the same cache key (e.g., 30s, 5m)
+    #[arg(long, default_value = "5m", env = "FABRIK_RUN_LOCK_TIMEOUT")]
+    pub lock_timeout: String,
+
+    /// Age after which a cache key lock is considered abandoned and taken over
+    #[arg(long, default_value = "30m", env = "FABRIK_RUN_LOCK_STALE_AFTER")]
+    pub lock_stale_after: String,
+
+    /// Max concurrent Fabrik.exec subprocesses per portable recipe (0 = number of CPUs)
+    #[arg(long, default_value = "0", env = "FABRIK_RUN_EXEC_CONCURRENCY")]
+    pub exec_concurrency: usize,
+
+    /// Total CPU time Fabrik.exec subprocesses of a portable recipe may use (e.g., 10m)
+    #[arg(long, env = "FABRIK_RUN_EXEC_CPU_BUDGET")]
+    pub exec_cpu_budget: Option<String>,
+
+    /// Total time Fabrik.exec subprocesses of a portable recipe may run for (e.g., 1h)
+    #[arg(long, env = "FABRIK_RUN_EXEC_TIME_BUDGET")]
+    pub exec_time_budget: Option<String>,
+
+    /// What to do when a portable recipe uses an undeclared permission (prompt|deny|allow)
+    #[arg(long, default_value = "prompt", env = "FABRIK_RUN_RECIPE_PERMISSIONS")]
+    pub recipe_permissions: String,
+
+    /// Times to retry fetching a remote recipe after transient failures
+    #[arg(long, default_value = "3", env = "FABRIK_RUN_FETCH_RETRIES")]
+    pub fetch_retries: u32,
+
+    /// Mirror to fetch remote recipes from when their host fails (HOST=URL, repeatable)
+    #[arg(long, env = "FABRIK_RUN_RECIPE_MIRRORS", value_delimiter = ',')]
+    pub recipe_mirror: Vec<String>,
+
+    /// Max defineTasks() tasks of a portable recipe to run in parallel (0 = number of CPUs)
+    #[arg(long, default_value = "0", env = "FABRIK_RUN_TASK_CONCURRENCY")]
+    pub task_concurrency: usize,
+
+    /// Re-run the script whenever its script, inputs or dependencies change
+    #[arg(long, env = "FABRIK_RUN_WATCH", conflicts_with_all = ["dry_run", "cache_only"])]
+    pub watch: bool,
+
+    /// Milliseconds changes must settle before a watch re-run
+    #[arg(long, default_value = "300", env = "FABRIK_RUN_WATCH_DEBOUNCE_MS")]
+    pub watch_debounce_ms: u64,
+
+    /// Run the script with only its declared env vars and a system allowlist
+    #[arg(long, env = "FABRIK_RUN_HERMETIC")]
+    pub hermetic: bool,
+
+    /// Extra environment variables to pass to hermetic runs (comma-separated)
+    #[arg(long, env = "FABRIK_RUN_ENV_ALLOW", value_delimiter = ',')]
+    pub env_allow: Vec<String>,
+
+    /// Warn about environment variables the script reads but doesn't declare
+    #[arg(long, env = "FABRIK_RUN_CHECK_ENV")]
+    pub check_env: bool,
+
+    /// Only run the script if its inputs changed relative to this git ref (e.g., origin/main)
+    #[arg(
+        long,
+        value_name = "REF",
+        env = "FABRIK_RUN_AFFECTED_BASE",
+        conflicts_with_all = ["watch", "explain"]
+    )]
+    pub affected_base: Option<String>,
+
     /// Config file path
     #[arg(short = 'c', long, env = "FABRIK_CONFIG")]
     pub config: Option<String>,
@@ -492,11 +871,19 @@ pub struct RunArgs {
     /// Local cache directory
     #[arg(long, env = "FABRIK_CONFIG_CACHE_DIR")]
     pub config_cache_dir: Option<String>,
+
+    /// Hash algorithm for script cache keys (sha256|blake3)
+    #[arg(long, env = "FABRIK_CONFIG_HASH_ALGORITHM")]
+    pub config_hash_algorithm: Option<crate::hashing::HashAlgorithm>,
 }
 
 impl RunArgs {
     /// Parse positional args to extract optional runtime and script path
     pub fn parse_runtime_and_script(&self) -> (Option<String>, String) {
+        if self.starts_with_recipe() {
+            // Recipe + tasks: fabrik run recipe.js build test
+            return (None, self.positional_args[0].clone());
+        }
         match self.positional_args.len() {
             0 => panic!("No positional args provided"), // Should never happen due to clap validation
             1 => {
@@ -512,6 +899,21 @@ impl RunArgs {
             }
         }
     }
+
+    /// defineTasks() tasks given after a portable recipe (`fabrik run recipe.js build test`)
+    pub fn recipe_targets(&self) -> Vec<String> {
+        if self.starts_with_recipe() {
+            self.positional_args[1..].to_vec()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn starts_with_recipe(&self) -> bool {
+        self.positional_args
+            .first()
+            .is_some_and(|first| first.ends_with(".js") || first.starts_with('@'))
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -522,6 +924,18 @@ pub struct CacheArgs {
     /// Local cache directory
     #[arg(long, env = "FABRIK_CONFIG_CACHE_DIR")]
     pub config_cache_dir: Option<String>,
+
+    /// Metadata backend of the cache (rocksdb|embedded)
+    #[arg(
+        long,
+        env = "FABRIK_CONFIG_METADATA_BACKEND",
+        default_value = "rocksdb"
+    )]
+    pub config_metadata_backend: String,
+
+    /// Object limit of the embedded metadata backend
+    #[arg(long, env = "FABRIK_CONFIG_EMBEDDED_MAX_OBJECTS", default_value_t = crate::storage::embedded::DEFAULT_MAX_OBJECTS)]
+    pub config_embedded_max_objects: usize,
 }
 
 #[derive(Subcommand, Debug)]
@@ -556,6 +970,47 @@ pub enum CacheCommands {
     /// Show cache statistics
     Stats,
 
+    /// Remove empty shard directories and compact the metadata database
+    Compact {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Copy a cache, with its metadata, into another cache directory or a daemon
+    Migrate {
+        /// Cache directory to migrate from
+        #[arg(long, env = "FABRIK_CACHE_MIGRATE_FROM")]
+        from: String,
+
+        /// Cache directory, or HTTP URL of a daemon or server, to migrate to
+        #[arg(long, env = "FABRIK_CACHE_MIGRATE_TO")]
+        to: String,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Prefetch artifacts from the upstreams into the local cache before a build
+    Warm {
+        /// JSON manifest of the hashes to prefetch (an array, or `{"hashes": [...]}`)
+        #[arg(long, env = "FABRIK_CACHE_WARM_MANIFEST")]
+        from_manifest: String,
+
+        /// Number of parallel fetches
+        #[arg(short = 'j', long, env = "FABRIK_CACHE_WARM_JOBS", default_value = "8")]
+        jobs: usize,
+
+        /// Config file path (default: discovered from the current directory)
+        #[arg(short = 'c', long, env = "FABRIK_CONFIG")]
+        config: Option<String>,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
     /// Get an artifact from the cache by hash
     Get {
         /// Content hash (SHA256) of the artifact
@@ -659,8 +1114,48 @@ pub enum AuthCommand {
     /// Check authentication status
     Status(AuthSubcommandArgs),
 
-    /// Show current access token (for debugging)
-    Token(AuthSubcommandArgs),
+    /// Show current access token (for debugging), or manage machine tokens
+    Token(AuthTokenArgs),
+}
+
+#[derive(Parser, Debug)]
+pub struct AuthTokenArgs {
+    #[command(subcommand)]
+    pub command: Option<AuthTokenCommand>,
+
+    /// Config file path
+    #[arg(short = 'c', long, env = "FABRIK_CONFIG")]
+    pub config: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AuthTokenCommand {
+    /// Mint a machine token (e.g., for CI) through the configured service
+    Create(AuthTokenCreateArgs),
+}
+
+#[derive(Parser, Debug)]
+pub struct AuthTokenCreateArgs {
+    /// Scopes granted to the token (e.g., cache:read, bazel:write)
+    #[arg(
+        long = "scope",
+        env = "FABRIK_AUTH_TOKEN_SCOPE",
+        value_delimiter = ',',
+        required = true
+    )]
+    pub scopes: Vec<String>,
+
+    /// Lifetime of the token (e.g., 30d, 12h)
+    #[arg(long, env = "FABRIK_AUTH_TOKEN_TTL", default_value = "30d")]
+    pub ttl: String,
+
+    /// Name shown in the service's token list (default: fabrik-<hostname>)
+    #[arg(long, env = "FABRIK_AUTH_TOKEN_NAME")]
+    pub name: Option<String>,
+
+    /// Only print the token instead of also storing it in the auth.oauth2 storage backend
+    #[arg(long, env = "FABRIK_AUTH_TOKEN_NO_STORE")]
+    pub no_store: bool,
 }
 
 // ============================================================================
@@ -675,6 +1170,22 @@ pub struct CasArgs {
     /// Local cache directory
     #[arg(long, env = "FABRIK_CONFIG_CACHE_DIR")]
     pub config_cache_dir: Option<String>,
+
+    /// Metadata backend of the cache (rocksdb|embedded)
+    #[arg(
+        long,
+        env = "FABRIK_CONFIG_METADATA_BACKEND",
+        default_value = "rocksdb"
+    )]
+    pub config_metadata_backend: String,
+
+    /// Object limit of the embedded metadata backend
+    #[arg(long, env = "FABRIK_CONFIG_EMBEDDED_MAX_OBJECTS", default_value_t = crate::storage::embedded::DEFAULT_MAX_OBJECTS)]
+    pub config_embedded_max_objects: usize,
+
+    /// Hash algorithm for the IDs computed by `put` (sha256|blake3)
+    #[arg(long, env = "FABRIK_CONFIG_HASH_ALGORITHM", default_value = "sha256")]
+    pub config_hash_algorithm: crate::hashing::HashAlgorithm,
 }
 
 #[derive(Subcommand, Debug)]
@@ -684,7 +1195,7 @@ pub enum CasCommand {
         /// Content hash (SHA256) of the blob
         hash: String,
 
-        /// Output file path
+        /// Output file path ("-" or omitted: stdout)
         #[arg(short, long)]
         output: Option<String>,
 
@@ -697,15 +1208,34 @@ pub enum CasCommand {
         json: bool,
     },
 
+    /// Write a blob to stdout
+    Cat {
+        /// Content hash (SHA256) of the blob
+        hash: String,
+    },
+
     /// Put a file into the cache (returns content hash)
     Put {
-        /// Input file path
+        /// Input file path ("-": stdin, printing only the hash on stdout)
         file: String,
 
         /// Verify against provided hash
         #[arg(long)]
         hash: Option<String>,
 
+        /// Label the blob (repeatable, e.g. --tag branch=main --tag target=ios)
+        #[arg(long = "tag", value_name = "KEY=VALUE")]
+        tags: Vec<String>,
+
+        /// Evict the blob this long after storing it under the TTL policy, instead of
+        /// after the default TTL (e.g. "2h", "30d")
+        #[arg(long, conflicts_with = "expires_at")]
+        ttl: Option<String>,
+
+        /// Evict the blob at this time under the TTL policy (RFC 3339 or Unix seconds)
+        #[arg(long)]
+        expires_at: Option<String>,
+
         /// Verbose output
         #[arg(short, long)]
         verbose: bool,
@@ -715,6 +1245,30 @@ pub enum CasCommand {
         json: bool,
     },
 
+    /// Store a directory as a tree of blobs (returns the hash of its root manifest)
+    PutDir {
+        /// Directory to store
+        dir: String,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Rebuild a directory stored with `put-dir` from its root hash
+    GetTree {
+        /// Hash of the root manifest
+        hash: String,
+
+        /// Directory to rebuild it in (must not exist or be empty)
+        #[arg(short, long)]
+        output: String,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
     /// Check if a blob exists in the cache
     Exists {
         /// Content hash (SHA256) of the blob
@@ -725,10 +1279,15 @@ pub enum CasCommand {
         json: bool,
     },
 
-    /// Delete a blob from the cache
+    /// Delete a blob from the cache, or every blob with the given labels
     Delete {
         /// Content hash (SHA256) of the blob
-        hash: String,
+        #[arg(required_unless_present = "tags", conflicts_with = "tags")]
+        hash: Option<String>,
+
+        /// Delete the blobs carrying all of these labels (repeatable)
+        #[arg(long = "tag", value_name = "KEY=VALUE")]
+        tags: Vec<String>,
 
         /// Force deletion without confirmation
         #[arg(short, long)]
@@ -751,6 +1310,10 @@ pub enum CasCommand {
 
     /// List all cached blobs
     List {
+        /// Only list blobs carrying all of these labels (repeatable)
+        #[arg(long = "tag", value_name = "KEY=VALUE")]
+        tags: Vec<String>,
+
         /// Show detailed information
         #[arg(short, long)]
         verbose: bool,
@@ -766,6 +1329,39 @@ pub enum CasCommand {
         #[arg(long)]
         json: bool,
     },
+
+    /// Export blobs into a compressed bundle file (e.g. for air-gapped caches)
+    Export {
+        /// Content hashes of the blobs to export
+        #[arg(required_unless_present_any = ["all", "since"])]
+        hashes: Vec<String>,
+
+        /// Bundle file to write
+        #[arg(short, long)]
+        output: String,
+
+        /// Export every blob in the cache
+        #[arg(long, conflicts_with_all = ["hashes", "since"])]
+        all: bool,
+
+        /// Export blobs stored within this duration (e.g. 24h, 7d)
+        #[arg(long, conflicts_with = "hashes")]
+        since: Option<String>,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Import the blobs of a bundle file into the cache
+    Import {
+        /// Bundle file written by `fabrik cas export`
+        bundle: String,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 // ============================================================================
@@ -780,6 +1376,18 @@ pub struct KvArgs {
     /// Local cache directory
     #[arg(long, env = "FABRIK_CONFIG_CACHE_DIR")]
     pub config_cache_dir: Option<String>,
+
+    /// Metadata backend of the cache (rocksdb|embedded)
+    #[arg(
+        long,
+        env = "FABRIK_CONFIG_METADATA_BACKEND",
+        default_value = "rocksdb"
+    )]
+    pub config_metadata_backend: String,
+
+    /// Object limit of the embedded metadata backend
+    #[arg(long, env = "FABRIK_CONFIG_EMBEDDED_MAX_OBJECTS", default_value_t = crate::storage::embedded::DEFAULT_MAX_OBJECTS)]
+    pub config_embedded_max_objects: usize,
 }
 
 #[derive(Subcommand, Debug)]
@@ -789,7 +1397,7 @@ pub enum KvCommand {
         /// Key to retrieve
         key: String,
 
-        /// Output file path (if omitted, prints to stdout)
+        /// Output file path ("-" or omitted: stdout)
         #[arg(short, long)]
         output: Option<String>,
 
@@ -811,7 +1419,7 @@ pub enum KvCommand {
         #[arg(group = "input")]
         value: Option<String>,
 
-        /// Read value from file - mutually exclusive with value
+        /// Read value from file ("-": stdin) - mutually exclusive with value
         #[arg(long, group = "input")]
         file: Option<String>,
 
@@ -871,6 +1479,51 @@ pub enum KvCommand {
     },
 }
 
+// ============================================================================
+// Stats Commands
+// ============================================================================
+
+#[derive(Parser, Debug)]
+pub struct StatsArgs {
+    #[command(subcommand)]
+    pub command: Option<StatsCommand>,
+
+    /// Local cache directory
+    #[arg(long, env = "FABRIK_CONFIG_CACHE_DIR")]
+    pub config_cache_dir: Option<String>,
+
+    /// Show the cache traffic of a build invocation (`FABRIK_INVOCATION_ID` of
+    /// `fabrik exec`, or Bazel's invocation ID) from the running daemon
+    #[arg(long, value_name = "ID")]
+    pub invocation: Option<String>,
+
+    /// Config file path of the daemon (discovered from the current directory if omitted)
+    #[arg(short = 'c', long, env = "FABRIK_CONFIG")]
+    pub config: Option<String>,
+
+    /// Output as JSON (with --invocation)
+    #[arg(long, requires = "invocation")]
+    pub json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StatsCommand {
+    /// List the most accessed artifacts (e.g. to pre-seed new caches)
+    Popular {
+        /// Count accesses of the last N days, including today (max 90)
+        #[arg(long, default_value_t = 7, env = "FABRIK_STATS_DAYS")]
+        days: u32,
+
+        /// Number of artifacts to list
+        #[arg(long, default_value_t = 1000, env = "FABRIK_STATS_TOP")]
+        top: usize,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
 // ============================================================================
 // P2P Commands
 // ============================================================================
@@ -898,6 +1551,16 @@ pub enum P2pCommand {
         json: bool,
     },
 
+    /// Show a summary of a peer's shareable cache (requires the peer's consent)
+    Ls {
+        /// Hostname or machine ID of a discovered peer, or an address (host:port)
+        peer: String,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
     /// Show P2P status and statistics
     Status {
         /// Output as JSON
@@ -905,6 +1568,25 @@ pub enum P2pCommand {
         json: bool,
     },
 
+    /// Measure latency and throughput to peers
+    Bench {
+        /// Hostname or machine ID of a discovered peer, or an address (host:port); all
+        /// peers if omitted
+        peer: Option<String>,
+
+        /// Bytes transferred from each peer to measure throughput (e.g., 16MB)
+        #[arg(long, default_value = "16MB", env = "FABRIK_P2P_BENCH_SIZE")]
+        size: String,
+
+        /// Round trips per peer to measure latency
+        #[arg(long, default_value = "5", env = "FABRIK_P2P_BENCH_ROUNDS")]
+        rounds: usize,
+
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
     /// Approve a peer to access your cache
     Approve {
         /// Machine ID or hostname of the peer
@@ -1,5 +1,169 @@
 /// CLI utilities for consistent output formatting
-use std::io::IsTerminal;
+///
+/// Also the output mode of every command (`--output-format`), and how failures are
+/// reported: a stable exit code per error category, with the error printed as text or as
+/// a JSON object on stderr.
+use serde::Serialize;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::OnceLock;
+
+use crate::error::FabrikError;
+use crate::hashing::{HashAlgorithm, HashingReader};
+
+/// File argument standing for stdin or stdout
+pub const STDIO: &str = "-";
+
+/// Output mode of all commands (`--output-format`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    /// One JSON document per line on stdout (as with each command's `--json`), and
+    /// errors as JSON objects on stderr
+    Json,
+}
+
+static OUTPUT_FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+/// Select the output mode of the process (once, at startup)
+pub fn set_output_format(format: OutputFormat) {
+    let _ = OUTPUT_FORMAT.set(format);
+}
+
+pub fn output_format() -> OutputFormat {
+    OUTPUT_FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Whether a command prints JSON: with its own `--json`, or in the JSON output mode
+pub fn json_output(flag: bool) -> bool {
+    flag || output_format() == OutputFormat::Json
+}
+
+/// Category of a failed command, each with a stable exit code for scripts
+///
+/// Exit code 2 is taken by the argument parser, for invalid command-line arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCategory {
+    /// Anything not covered by another category
+    General,
+    /// Missing or invalid configuration
+    Config,
+    /// The artifact, key or resource doesn't exist (a cache miss)
+    Miss,
+    /// Credentials are missing or were rejected
+    Auth,
+    /// A remote cache, peer or daemon couldn't be reached
+    Network,
+    /// A size limit or quota was exceeded, or the cache volume is nearly full
+    Quota,
+    /// Stored or received data is malformed
+    Corrupt,
+    /// Local filesystem or metadata database failure
+    Io,
+    /// The cache is read-only
+    ReadOnly,
+}
+
+impl ErrorCategory {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorCategory::General => 1,
+            ErrorCategory::Config => 3,
+            ErrorCategory::Miss => 4,
+            ErrorCategory::Auth => 5,
+            ErrorCategory::Network => 6,
+            ErrorCategory::Quota => 7,
+            ErrorCategory::Corrupt => 8,
+            ErrorCategory::Io => 9,
+            ErrorCategory::ReadOnly => 10,
+        }
+    }
+
+    /// Category of `error`, from the first typed error in its chain
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<FabrikError>() {
+                return Self::of_fabrik(e);
+            }
+            if let Some(status) = cause.downcast_ref::<tonic::Status>() {
+                return match status.code() {
+                    tonic::Code::NotFound => ErrorCategory::Miss,
+                    tonic::Code::Unauthenticated | tonic::Code::PermissionDenied => {
+                        ErrorCategory::Auth
+                    }
+                    tonic::Code::ResourceExhausted => ErrorCategory::Quota,
+                    tonic::Code::DataLoss => ErrorCategory::Corrupt,
+                    _ => ErrorCategory::Network,
+                };
+            }
+            if cause.downcast_ref::<reqwest::Error>().is_some()
+                || cause.downcast_ref::<tonic::transport::Error>().is_some()
+            {
+                return ErrorCategory::Network;
+            }
+            if cause.downcast_ref::<io::Error>().is_some() {
+                return ErrorCategory::Io;
+            }
+        }
+        ErrorCategory::General
+    }
+
+    fn of_fabrik(error: &FabrikError) -> Self {
+        match error {
+            FabrikError::NotFound(_) => ErrorCategory::Miss,
+            FabrikError::AuthFailed(_) => ErrorCategory::Auth,
+            FabrikError::QuotaExceeded(_) => ErrorCategory::Quota,
+            FabrikError::UpstreamUnavailable { .. } => ErrorCategory::Network,
+            FabrikError::Corrupt(_) => ErrorCategory::Corrupt,
+            FabrikError::Io { .. } => ErrorCategory::Io,
+            FabrikError::Config(_) => ErrorCategory::Config,
+            FabrikError::ReadOnly(_) => ErrorCategory::ReadOnly,
+        }
+    }
+}
+
+/// Error object printed on stderr in the JSON output mode
+#[derive(Serialize)]
+struct ErrorOutput<'a> {
+    error: ErrorDetails<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetails<'a> {
+    category: ErrorCategory,
+    exit_code: u8,
+    message: String,
+    /// Underlying errors, outermost first
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    causes: &'a [String],
+}
+
+/// Report a failed command on stderr, returning its exit code
+pub fn report_error(error: &anyhow::Error) -> ExitCode {
+    let category = ErrorCategory::of(error);
+    match output_format() {
+        OutputFormat::Text => eprintln!("Error: {:?}", error),
+        OutputFormat::Json => {
+            let causes: Vec<String> = error.chain().skip(1).map(|e| e.to_string()).collect();
+            let output = ErrorOutput {
+                error: ErrorDetails {
+                    category,
+                    exit_code: category.exit_code(),
+                    message: error.to_string(),
+                    causes: &causes,
+                },
+            };
+            eprintln!(
+                "{}",
+                serde_json::to_string(&output).expect("error serializes")
+            );
+        }
+    }
+    ExitCode::from(category.exit_code())
+}
 
 /// Get a colored prefix
 ///
@@ -12,3 +176,97 @@ pub fn fabrik_prefix() -> &'static str {
         "[fabrik]"
     }
 }
+
+/// Format a byte count with a binary unit (e.g. "1.5 MB")
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Copy `reader` to stdout in pieces, returning the bytes copied
+///
+/// A reader closing the pipe early (e.g. `head`) isn't an error.
+pub fn copy_to_stdout(reader: &mut dyn Read) -> io::Result<u64> {
+    let mut stdout = io::stdout().lock();
+    let copied = match io::copy(reader, &mut stdout) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(0),
+        result => result?,
+    };
+    match stdout.flush() {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(copied),
+        result => result.map(|()| copied),
+    }
+}
+
+/// Spool stdin into a temporary file in `dir` while hashing it, so it can be stored
+/// under its hash without holding it in memory; returns the file and hex hash
+pub fn spool_stdin(
+    dir: &Path,
+    algorithm: HashAlgorithm,
+) -> io::Result<(tempfile::NamedTempFile, String)> {
+    spool(io::stdin().lock(), dir, algorithm)
+}
+
+fn spool(
+    reader: impl Read,
+    dir: &Path,
+    algorithm: HashAlgorithm,
+) -> io::Result<(tempfile::NamedTempFile, String)> {
+    let mut file = tempfile::Builder::new()
+        .prefix(".stdin.")
+        .tempfile_in(dir)?;
+    let mut reader = HashingReader::new(reader, algorithm);
+    io::copy(&mut reader, &mut file)?;
+    file.flush()?;
+    Ok((file, hex::encode(reader.finalize())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_categories() {
+        let miss = anyhow::Error::from(FabrikError::not_found("Blob not found: abc"));
+        assert_eq!(ErrorCategory::of(&miss), ErrorCategory::Miss);
+        assert_eq!(ErrorCategory::of(&miss).exit_code(), 4);
+
+        // Context added by commands doesn't hide the typed error underneath
+        let auth = anyhow::Error::from(FabrikError::auth_failed("Token rejected"))
+            .context("Failed to fetch artifact");
+        assert_eq!(ErrorCategory::of(&auth), ErrorCategory::Auth);
+        let network = anyhow::Error::from(FabrikError::unavailable("Upstream unreachable"))
+            .context("Failed to fetch artifact");
+        assert_eq!(ErrorCategory::of(&network).exit_code(), 6);
+        let io = anyhow::Error::from(io::Error::other("disk on fire")).context("Failed to read");
+        assert_eq!(ErrorCategory::of(&io), ErrorCategory::Io);
+
+        assert_eq!(
+            ErrorCategory::of(&anyhow::anyhow!("Something else")),
+            ErrorCategory::General
+        );
+        assert_eq!(
+            serde_json::to_string(&ErrorCategory::ReadOnly).unwrap(),
+            "\"read-only\""
+        );
+    }
+
+    #[test]
+    fn test_spool() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (file, hash) = spool(&b"piped data"[..], dir.path(), HashAlgorithm::Sha256).unwrap();
+        assert_eq!(hash, HashAlgorithm::Sha256.hex_digest(b"piped data"));
+        assert_eq!(std::fs::read(file.path()).unwrap(), b"piped data");
+        assert_eq!(file.path().parent(), Some(dir.path()));
+    }
+}
@@ -0,0 +1,182 @@
+use std::future::Future;
+use std::time::Duration;
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Request, Response, Status, Streaming};
+
+use super::proto::cluster_peer_client::ClusterPeerClient;
+use super::proto::{
+    DeleteRequest, ExistsRequest, GetRequest, ListRequest, ListResponse, PutRequest,
+};
+use super::{MAX_MESSAGE_SIZE, SECRET_HEADER};
+use crate::error::{FabrikError, Result, RpcResultExt};
+use crate::grpc_transport::GrpcTransport;
+use crate::retry::{Protocol, RetryPolicy};
+
+/// Longest a request to another node may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Client of another node's cluster protocol (a cluster peer or another region)
+#[derive(Clone)]
+pub struct PeerClient {
+    address: String,
+    client: ClusterPeerClient<Channel>,
+    secret: Option<String>,
+    retry: RetryPolicy,
+}
+
+impl PeerClient {
+    /// Client of the node at `address` (`host:port`, `grpc://host:port` or
+    /// `http://host:port`), connecting on first use
+    pub fn new(
+        address: &str,
+        secret: Option<String>,
+        transport: GrpcTransport,
+    ) -> anyhow::Result<Self> {
+        let authority = address
+            .strip_prefix("grpc://")
+            .or_else(|| address.strip_prefix("http://"))
+            .unwrap_or(address)
+            .trim_end_matches('/');
+        let endpoint = Endpoint::from_shared(format!("http://{}", authority))
+            .map_err(|e| anyhow::anyhow!("Invalid node address {}: {}", address, e))?
+            .connect_timeout(Duration::from_secs(5))
+            .timeout(REQUEST_TIMEOUT);
+        let client = ClusterPeerClient::new(transport.endpoint(endpoint).connect_lazy())
+            .max_decoding_message_size(MAX_MESSAGE_SIZE)
+            .max_encoding_message_size(MAX_MESSAGE_SIZE);
+
+        Ok(Self {
+            address: address.to_string(),
+            client,
+            secret,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    /// Retry failed requests with `retry` (`[retry]` defaults otherwise)
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(value) = self
+            .secret
+            .as_deref()
+            .and_then(|secret| MetadataValue::try_from(secret).ok())
+        {
+            request.metadata_mut().insert(SECRET_HEADER, value);
+        }
+        request
+    }
+
+    fn context(&self) -> String {
+        format!("Node {}", self.address)
+    }
+
+    /// Send `message` with `rpc`, retrying failures the retry policy allows
+    async fn call<M, R, F, Fut>(&self, message: M, rpc: F) -> Result<R>
+    where
+        M: Clone,
+        F: Fn(ClusterPeerClient<Channel>, Request<M>) -> Fut,
+        Fut: Future<Output = std::result::Result<Response<R>, Status>>,
+    {
+        self.retry
+            .grpc(Protocol::Fabrik, || {
+                rpc(self.client.clone(), self.request(message.clone()))
+            })
+            .await
+            .rpc_context(&self.context())
+            .map(Response::into_inner)
+    }
+
+    pub async fn get(&self, id: &[u8]) -> Result<Option<Vec<u8>>> {
+        let response = self
+            .call(
+                GetRequest { id: id.to_vec() },
+                |mut client, request| async move { client.get(request).await },
+            )
+            .await?;
+        Ok(response.found.then_some(response.data))
+    }
+
+    /// Whether the node answers, without retries (heartbeats are retried anyway)
+    pub async fn ping(&self) -> Result<()> {
+        self.client
+            .clone()
+            .exists(self.request(ExistsRequest { ids: vec![] }))
+            .await
+            .rpc_context(&self.context())?;
+        Ok(())
+    }
+
+    /// Whether each of `ids` is stored (no IDs = ping)
+    pub async fn exists(&self, ids: Vec<Vec<u8>>) -> Result<Vec<bool>> {
+        let count = ids.len();
+        let exists = self
+            .call(ExistsRequest { ids }, |mut client, request| async move {
+                client.exists(request).await
+            })
+            .await?
+            .exists;
+        if exists.len() != count {
+            return Err(FabrikError::corrupt(format!(
+                "Node {} answered {} of {} lookups",
+                self.address,
+                exists.len(),
+                count
+            )));
+        }
+        Ok(exists)
+    }
+
+    /// Store a blob on the node, or with `route` on its owners in the node's cluster
+    pub async fn put(
+        &self,
+        id: &[u8],
+        data: &[u8],
+        expires_at: Option<i64>,
+        route: bool,
+    ) -> Result<()> {
+        let message = PutRequest {
+            id: id.to_vec(),
+            data: data.to_vec(),
+            expires_at: expires_at.unwrap_or_default(),
+            route,
+        };
+        self.call(message, |mut client, request| async move {
+            client.put(request).await
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, id: &[u8]) -> Result<()> {
+        self.call(
+            DeleteRequest { id: id.to_vec() },
+            |mut client, request| async move { client.delete(request).await },
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// IDs of the blobs stored on the node, in batches
+    pub async fn list(&self) -> Result<Streaming<ListResponse>> {
+        // Listing a large cache takes longer than a single request may
+        let mut request = self.request(ListRequest {});
+        request.set_timeout(Duration::from_secs(24 * 60 * 60));
+        Ok(self
+            .client
+            .clone()
+            .list(request)
+            .await
+            .rpc_context(&self.context())?
+            .into_inner())
+    }
+}
@@ -0,0 +1,585 @@
+/// Horizontal clustering of Layer 2 servers
+///
+/// With `[cluster] peers`, several `fabrik server` nodes share one regional cache. Each
+/// blob is owned by `replicas` nodes, picked on a consistent hash ring of the live nodes
+/// (see `ring`), so capacity grows with the number of nodes:
+///
+/// - Writes are stored by every owner; a node that receives a blob it doesn't own
+///   forwards it to the owners.
+/// - Reads are answered locally when possible, otherwise by the owners.
+/// - Nodes ping each other every `heartbeat_interval`. When the set of live nodes
+///   changes, blobs are copied to their new owners and nodes hand off the blobs they
+///   no longer own, once every owner has them.
+///
+/// Nodes talk to each other over the internal `fabrik.cluster.ClusterPeer` gRPC service,
+/// served next to the cache services on `grpc_bind`. Tags, labels and pins aren't
+/// replicated. Regions replicate to each other over the same protocol (see
+/// `replication`).
+mod client;
+pub mod replication;
+pub mod ring;
+mod service;
+
+pub use client::PeerClient;
+pub use replication::{ReplicatingStorage, Replicator};
+pub use ring::HashRing;
+pub use service::ClusterPeerService;
+
+use crate::config::ClusterConfig;
+use crate::error::Result;
+use crate::grpc_transport::GrpcTransport;
+use crate::retry::RetryPolicy;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::storage::{EntryTags, Labels, ObjectInfo, Storage, StorageStats};
+
+pub mod proto {
+    tonic::include_proto!("fabrik.cluster");
+
+    /// Encoded descriptors of the cluster protos
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("cluster_descriptor");
+}
+
+/// Largest blob nodes exchange
+pub const MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Metadata carrying the cluster secret
+const SECRET_HEADER: &str = "x-fabrik-cluster-secret";
+
+/// Blobs checked per request when rebalancing
+const REBALANCE_BATCH: usize = 1000;
+
+fn secret_matches(expected: &str, sent: &str) -> bool {
+    // Compare digests so the comparison time doesn't depend on the secret
+    Sha256::digest(expected.as_bytes()) == Sha256::digest(sent.as_bytes())
+}
+
+/// Another node of the cluster
+struct Peer {
+    client: PeerClient,
+    up: AtomicBool,
+}
+
+impl Peer {
+    fn address(&self) -> &str {
+        self.client.address()
+    }
+}
+
+/// Outcome of a rebalance
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RebalanceStats {
+    /// Copies pushed to owners that didn't have them
+    pub replicated: usize,
+    /// Local blobs deleted because this node no longer owns them
+    pub handed_off: usize,
+    /// Blobs that couldn't be copied to every owner
+    pub failed: usize,
+}
+
+/// Membership and routing of a Layer 2 cluster
+pub struct Cluster {
+    node: String,
+    peers: Vec<Peer>,
+    replicas: usize,
+    ring: RwLock<HashRing>,
+    runtime: tokio::runtime::Handle,
+}
+
+impl Cluster {
+    /// Cluster of this node and the configured peers (must be called within a Tokio
+    /// runtime). Peers are assumed live until a heartbeat says otherwise.
+    pub fn new(config: &ClusterConfig, transport: GrpcTransport) -> anyhow::Result<Self> {
+        let node = match (&config.advertise, config.peers.is_empty()) {
+            (Some(advertise), _) => advertise.clone(),
+            (None, true) => "local".to_string(),
+            (None, false) => anyhow::bail!(
+                "[cluster] advertise must be set to this node's address when peers are configured"
+            ),
+        };
+        anyhow::ensure!(
+            config.replicas >= 1,
+            "[cluster] replicas must be at least 1"
+        );
+
+        let peers = config
+            .peers
+            .iter()
+            .filter(|address| **address != node)
+            .map(|address| {
+                Ok(Peer {
+                    client: PeerClient::new(address, config.secret.clone(), transport)?,
+                    up: AtomicBool::new(true),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let cluster = Self {
+            node,
+            peers,
+            replicas: config.replicas,
+            ring: RwLock::new(HashRing::default()),
+            runtime: tokio::runtime::Handle::current(),
+        };
+        cluster.rebuild_ring();
+        Ok(cluster)
+    }
+
+    /// Retry failed requests to the peers with `retry`
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.peers = self
+            .peers
+            .into_iter()
+            .map(|peer| Peer {
+                client: peer.client.with_retry(retry.clone()),
+                up: peer.up,
+            })
+            .collect();
+        self
+    }
+
+    /// Address of this node on the ring
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// Whether other nodes are configured
+    pub fn is_clustered(&self) -> bool {
+        !self.peers.is_empty()
+    }
+
+    /// Addresses of the live nodes
+    pub fn live_nodes(&self) -> Vec<String> {
+        std::iter::once(self.node.clone())
+            .chain(
+                self.peers
+                    .iter()
+                    .filter(|peer| peer.up.load(Ordering::Relaxed))
+                    .map(|peer| peer.address().to_string()),
+            )
+            .collect()
+    }
+
+    /// Live nodes owning `id`
+    pub fn owners(&self, id: &[u8]) -> Vec<String> {
+        if self.peers.is_empty() {
+            return vec![self.node.clone()];
+        }
+        let ring = self.ring.read().unwrap();
+        ring.owners(id, self.replicas)
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn rebuild_ring(&self) {
+        *self.ring.write().unwrap() = HashRing::new(self.live_nodes());
+    }
+
+    /// Other nodes among `owners`
+    fn remote_owners(&self, owners: &[String]) -> Vec<&Peer> {
+        owners
+            .iter()
+            .filter_map(|owner| self.peers.iter().find(|peer| peer.address() == owner))
+            .collect()
+    }
+
+    /// Run `future` from synchronous storage code
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        tokio::task::block_in_place(|| self.runtime.block_on(future))
+    }
+
+    /// Ping every peer and rebuild the ring, returning whether the live nodes changed
+    pub async fn heartbeat(&self) -> bool {
+        let mut changed = false;
+        for peer in &self.peers {
+            let up = peer.client.ping().await.is_ok();
+            if peer.up.swap(up, Ordering::Relaxed) != up {
+                changed = true;
+                match up {
+                    true => info!("Cluster node {} is up", peer.address()),
+                    false => warn!("Cluster node {} is down", peer.address()),
+                }
+            }
+        }
+        if changed {
+            self.rebuild_ring();
+        }
+        changed
+    }
+
+    /// Copy the local blobs to their owners, and delete the ones this node no longer
+    /// owns once every owner has them
+    pub async fn rebalance<S: Storage>(&self, local: &S) -> Result<RebalanceStats> {
+        let mut stats = RebalanceStats::default();
+        let ids = local.list_ids()?;
+
+        for batch in ids.chunks(REBALANCE_BATCH) {
+            let owners: Vec<Vec<String>> = batch.iter().map(|id| self.owners(id)).collect();
+
+            // Which owners already have each blob, one lookup per peer
+            let mut by_peer: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+            for (i, owners) in owners.iter().enumerate() {
+                for peer in self.remote_owners(owners) {
+                    by_peer.entry(peer.address()).or_default().push(i);
+                }
+            }
+            let mut missing: Vec<Vec<&Peer>> = vec![Vec::new(); batch.len()];
+            let mut unknown: HashSet<usize> = HashSet::new();
+            for (address, indices) in by_peer {
+                let peer = self.peers.iter().find(|p| p.address() == address).unwrap();
+                let lookup = indices.iter().map(|&i| batch[i].clone()).collect();
+                match peer.client.exists(lookup).await {
+                    Ok(exists) => {
+                        for (&i, exists) in indices.iter().zip(exists) {
+                            if !exists {
+                                missing[i].push(peer);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Cluster rebalance lookup on {} failed: {}", address, e);
+                        unknown.extend(indices);
+                    }
+                }
+            }
+
+            for (i, id) in batch.iter().enumerate() {
+                let mut complete = !unknown.contains(&i);
+                if !missing[i].is_empty() {
+                    let Some(data) = local.get(id)? else {
+                        continue;
+                    };
+                    for peer in &missing[i] {
+                        match peer.client.put(id, &data, None, false).await {
+                            Ok(()) => stats.replicated += 1,
+                            Err(e) => {
+                                warn!(
+                                    "Failed to copy {} to cluster node {}: {}",
+                                    hex::encode(id),
+                                    peer.address(),
+                                    e
+                                );
+                                complete = false;
+                            }
+                        }
+                    }
+                }
+
+                if !complete {
+                    stats.failed += 1;
+                } else if !owners[i].contains(&self.node) && !owners[i].is_empty() {
+                    local.delete(id)?;
+                    stats.handed_off += 1;
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Ping peers every `interval`, rebalancing `local` at start and whenever the live
+    /// nodes change
+    pub fn spawn<S: Storage + 'static>(self: Arc<Self>, local: Arc<S>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            let mut rebalance = true;
+            loop {
+                ticker.tick().await;
+                rebalance |= self.heartbeat().await;
+                if !rebalance {
+                    continue;
+                }
+                info!(
+                    "Rebalancing cluster cache ({} live node(s))",
+                    self.live_nodes().len()
+                );
+                match self.rebalance(local.as_ref()).await {
+                    Ok(stats) => {
+                        info!(
+                            "Cluster rebalance done: {} cop(ies) pushed, {} blob(s) handed off, {} failed",
+                            stats.replicated, stats.handed_off, stats.failed
+                        );
+                        // Retry on the next heartbeat until every blob reached its owners
+                        rebalance = stats.failed > 0;
+                    }
+                    Err(e) => warn!("Cluster rebalance failed: {}", e),
+                }
+            }
+        });
+    }
+}
+
+/// Storage routing each blob to its owners in the cluster
+///
+/// Wraps the node's local storage. Metadata (sizes, tags, labels, stats) is the local
+/// storage's.
+pub struct ClusterStorage<S: Storage> {
+    local: Arc<S>,
+    cluster: Arc<Cluster>,
+}
+
+impl<S: Storage> ClusterStorage<S> {
+    pub fn new(local: Arc<S>, cluster: Arc<Cluster>) -> Self {
+        Self { local, cluster }
+    }
+}
+
+impl<S: Storage> Storage for ClusterStorage<S> {
+    fn put(&self, id: &[u8], data: &[u8]) -> Result<()> {
+        self.put_with_expiry(id, data, None)
+    }
+
+    fn put_with_expiry(&self, id: &[u8], data: &[u8], expires_at: Option<i64>) -> Result<()> {
+        let owners = self.cluster.owners(id);
+        let mut stored = false;
+        if owners.contains(&self.cluster.node) {
+            self.local.put_with_expiry(id, data, expires_at)?;
+            stored = true;
+        }
+        for peer in self.cluster.remote_owners(&owners) {
+            match self
+                .cluster
+                .block_on(peer.client.put(id, data, expires_at, false))
+            {
+                Ok(()) => stored = true,
+                Err(e) => warn!(
+                    "Failed to store {} on cluster node {}: {}",
+                    hex::encode(id),
+                    peer.address(),
+                    e
+                ),
+            }
+        }
+        if !stored {
+            // Keep it here until a rebalance copies it to its owners
+            self.local.put_with_expiry(id, data, expires_at)?;
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = self.local.get(id)? {
+            return Ok(Some(data));
+        }
+        for peer in self.cluster.remote_owners(&self.cluster.owners(id)) {
+            match self.cluster.block_on(peer.client.get(id)) {
+                Ok(Some(data)) => return Ok(Some(data)),
+                Ok(None) => {}
+                Err(e) => warn!(
+                    "Failed to read {} from cluster node {}: {}",
+                    hex::encode(id),
+                    peer.address(),
+                    e
+                ),
+            }
+        }
+        Ok(None)
+    }
+
+    fn exists(&self, id: &[u8]) -> Result<bool> {
+        Ok(self.exists_many(&[id.to_vec()])?[0])
+    }
+
+    fn exists_many(&self, ids: &[Vec<u8>]) -> Result<Vec<bool>> {
+        let mut exists = self.local.exists_many(ids)?;
+
+        // Ask the owners about the rest, one lookup per node
+        let mut by_peer: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (i, id) in ids.iter().enumerate().filter(|(i, _)| !exists[*i]) {
+            for peer in self.cluster.remote_owners(&self.cluster.owners(id)) {
+                by_peer
+                    .entry(peer.address().to_string())
+                    .or_default()
+                    .push(i);
+            }
+        }
+        for (address, indices) in by_peer {
+            let peer = self.cluster.remote_owners(&[address]).pop().unwrap();
+            let lookup = indices.iter().map(|&i| ids[i].clone()).collect();
+            match self.cluster.block_on(peer.client.exists(lookup)) {
+                Ok(found) => {
+                    for (i, found) in indices.into_iter().zip(found) {
+                        exists[i] |= found;
+                    }
+                }
+                Err(e) => warn!(
+                    "Failed to look up blobs on cluster node {}: {}",
+                    peer.address(),
+                    e
+                ),
+            }
+        }
+        Ok(exists)
+    }
+
+    fn delete(&self, id: &[u8]) -> Result<()> {
+        if self.local.exists(id)? {
+            self.local.delete(id)?;
+        }
+        for peer in self.cluster.remote_owners(&self.cluster.owners(id)) {
+            self.cluster.block_on(peer.client.delete(id))?;
+        }
+        Ok(())
+    }
+
+    fn size(&self, id: &[u8]) -> Result<Option<u64>> {
+        self.local.size(id)
+    }
+
+    fn created_at(&self, id: &[u8]) -> Result<Option<i64>> {
+        self.local.created_at(id)
+    }
+
+    fn info(&self, id: &[u8]) -> Result<Option<ObjectInfo>> {
+        self.local.info(id)
+    }
+
+    fn touch(&self, id: &[u8]) -> Result<()> {
+        self.local.touch(id)
+    }
+
+    fn touch_many(&self, ids: &[Vec<u8>]) -> Result<()> {
+        self.local.touch_many(ids)
+    }
+
+    fn list_ids(&self) -> Result<Vec<Vec<u8>>> {
+        self.local.list_ids()
+    }
+
+    fn stats(&self) -> Result<StorageStats> {
+        self.local.stats()
+    }
+
+    fn put_tags(&self, id: &[u8], tags: &EntryTags) -> Result<()> {
+        self.local.put_tags(id, tags)
+    }
+
+    fn get_tags(&self, id: &[u8]) -> Result<Option<EntryTags>> {
+        self.local.get_tags(id)
+    }
+
+    fn add_labels(&self, id: &[u8], labels: &Labels) -> Result<()> {
+        self.local.add_labels(id, labels)
+    }
+
+    fn get_labels(&self, id: &[u8]) -> Result<Labels> {
+        self.local.get_labels(id)
+    }
+
+    fn find_by_labels(&self, labels: &Labels) -> Result<Vec<Vec<u8>>> {
+        self.local.find_by_labels(labels)
+    }
+
+    fn pin(&self, holder: &[u8], ids: &[Vec<u8>]) -> Result<()> {
+        self.local.pin(holder, ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::FabrikError;
+    use crate::storage::FilesystemStorage;
+    use tempfile::TempDir;
+
+    /// A node serving the cluster protocol on a random port
+    async fn spawn_node(secret: &str) -> (String, Arc<FilesystemStorage>, TempDir) {
+        let temp = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp.path()).unwrap());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let service = ClusterPeerService::new(storage.clone(), Some(secret.to_string()));
+        tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(service.into_server())
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
+                .await
+        });
+        (address, storage, temp)
+    }
+
+    fn config(advertise: &str, peers: Vec<String>, secret: &str) -> ClusterConfig {
+        ClusterConfig {
+            advertise: Some(advertise.to_string()),
+            peers,
+            secret: Some(secret.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_routes_blobs_to_owners() {
+        let (remote, remote_storage, _remote_dir) = spawn_node("s3cret-s3cret-s3cret").await;
+        let local_dir = TempDir::new().unwrap();
+        let local_storage = Arc::new(FilesystemStorage::new(local_dir.path()).unwrap());
+
+        // With one replica, every blob lives on exactly one of the two nodes
+        let cluster = Arc::new(
+            Cluster::new(
+                &ClusterConfig {
+                    replicas: 1,
+                    ..config("local:1", vec![remote.clone()], "s3cret-s3cret-s3cret")
+                },
+                GrpcTransport::default(),
+            )
+            .unwrap(),
+        );
+        let storage = ClusterStorage::new(local_storage.clone(), cluster.clone());
+
+        let ids: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i; 32]).collect();
+        for id in &ids {
+            storage.put(id, id).unwrap();
+        }
+        for id in &ids {
+            let owner = cluster.owners(id).pop().unwrap();
+            assert_eq!(local_storage.exists(id).unwrap(), owner == "local:1");
+            assert_eq!(remote_storage.exists(id).unwrap(), owner == remote);
+            assert_eq!(storage.get(id).unwrap(), Some(id.clone()));
+        }
+        assert!(storage.exists_many(&ids).unwrap().into_iter().all(|e| e));
+        assert!(!storage.exists(&[99; 32]).unwrap());
+
+        // Local copies of blobs the remote node owns are handed off once it has them
+        remote_storage.list_ids().unwrap().iter().for_each(|id| {
+            local_storage.put(id, id).unwrap();
+        });
+        let stats = cluster.rebalance(local_storage.as_ref()).await.unwrap();
+        assert_eq!(stats.failed, 0);
+        assert_eq!(stats.replicated, 0);
+        assert_eq!(stats.handed_off, remote_storage.list_ids().unwrap().len());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rejects_wrong_secret() {
+        let (remote, _remote_storage, _remote_dir) = spawn_node("s3cret-s3cret-s3cret").await;
+        let cluster = Cluster::new(
+            &config("local:1", vec![remote], "wrong"),
+            GrpcTransport::default(),
+        )
+        .unwrap();
+        let error = cluster.peers[0].client.exists(vec![]).await.unwrap_err();
+        assert!(matches!(error, FabrikError::AuthFailed(_)));
+    }
+
+    #[test]
+    fn test_requires_advertise_with_peers() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let config = ClusterConfig {
+            peers: vec!["10.0.0.2:7070".to_string()],
+            ..Default::default()
+        };
+        assert!(Cluster::new(&config, GrpcTransport::default()).is_err());
+        let standalone = Cluster::new(&ClusterConfig::default(), GrpcTransport::default()).unwrap();
+        assert!(!standalone.is_clustered());
+        assert_eq!(standalone.owners(b"id"), vec!["local".to_string()]);
+    }
+}
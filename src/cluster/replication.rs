@@ -0,0 +1,596 @@
+/// Cross-region replication between Layer 2 servers
+///
+/// With `[replication] targets`, a Layer 2 server pushes the artifacts its clients write
+/// to the servers of other regions, so builds there hit on them too. Each target has a
+/// bounded queue drained by a background worker, which retries with backoff while the
+/// target is unreachable; artifacts that don't fit in a full queue are dropped (and
+/// counted) and can be recovered with `fabrik server replicate --from <url>`.
+///
+/// Artifacts are pushed as routed puts of the cluster protocol, so the receiving region
+/// stores them on their owners in its own cluster and never replicates them further.
+/// Copies forwarded between the nodes of a cluster aren't replicated either: only the
+/// node that received a write from a client queues it.
+use anyhow::Context;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+use super::PeerClient;
+use crate::config::{ReplicationConfig, ReplicationTarget};
+use crate::error::{Result, RpcResultExt};
+use crate::events::{CacheEvent, EventBus};
+use crate::eviction::EvictionConfig;
+use crate::grpc_transport::GrpcTransport;
+use crate::retry::RetryPolicy;
+use crate::storage::{EntryTags, Labels, ObjectInfo, Storage, StorageStats};
+use crate::upstream_routing::namespaces;
+
+/// Namespaces served by `fabrik server`, hence replicated
+const NAMESPACES: &[&str] = &[namespaces::XCODE_CAS, namespaces::XCODE_KV];
+
+/// First and longest wait before retrying a target
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Namespace of a stored artifact
+fn namespace(id: &[u8]) -> &'static str {
+    match id.starts_with(b"kv:") {
+        true => namespaces::XCODE_KV,
+        false => namespaces::XCODE_CAS,
+    }
+}
+
+/// Which artifacts a target receives
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TargetFilter {
+    /// Empty = all
+    namespaces: Vec<String>,
+    max_size: Option<u64>,
+}
+
+impl TargetFilter {
+    pub fn parse(namespaces: &[String], max_size: Option<&str>) -> anyhow::Result<Self> {
+        for namespace in namespaces {
+            anyhow::ensure!(
+                NAMESPACES.contains(&namespace.as_str()),
+                "Unknown namespace '{}' (expected one of: {})",
+                namespace,
+                NAMESPACES.join(", ")
+            );
+        }
+        let max_size = max_size
+            .map(|size| {
+                EvictionConfig::parse_size(size)
+                    .with_context(|| format!("Invalid max_size: {}", size))
+            })
+            .transpose()?;
+        Ok(Self {
+            namespaces: namespaces.to_vec(),
+            max_size,
+        })
+    }
+
+    pub fn from_config(target: &ReplicationTarget) -> anyhow::Result<Self> {
+        Self::parse(&target.namespaces, target.max_size.as_deref())
+    }
+
+    fn matches_namespace(&self, id: &[u8]) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|n| n == namespace(id))
+    }
+
+    fn matches_size(&self, size: u64) -> bool {
+        self.max_size.is_none_or(|max| size <= max)
+    }
+
+    pub fn matches(&self, id: &[u8], size: u64) -> bool {
+        self.matches_namespace(id) && self.matches_size(size)
+    }
+}
+
+/// Artifact waiting to be pushed
+struct Pending {
+    id: Vec<u8>,
+    queued_at: Instant,
+}
+
+/// A peer region and its queue
+struct Target {
+    client: PeerClient,
+    filter: TargetFilter,
+    queue: Mutex<VecDeque<Pending>>,
+    notify: Notify,
+    replicated: AtomicU64,
+    failures: AtomicU64,
+    dropped: AtomicU64,
+    events: Option<Arc<EventBus>>,
+}
+
+impl Target {
+    /// How long the oldest pending artifact has been waiting
+    fn lag(&self) -> Duration {
+        self.queue
+            .lock()
+            .unwrap()
+            .front()
+            .map(|pending| pending.queued_at.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Push queued artifacts, read from `source`, until the process exits
+    async fn run<S: Storage>(&self, source: &S) {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            // The artifact stays queued until it's pushed, so the lag covers retries
+            let next = self
+                .queue
+                .lock()
+                .unwrap()
+                .front()
+                .map(|pending| pending.id.clone());
+            let Some(id) = next else {
+                self.notify.notified().await;
+                continue;
+            };
+
+            match self.push(source, &id).await {
+                Ok(()) => {
+                    self.queue.lock().unwrap().pop_front();
+                    backoff = MIN_BACKOFF;
+                }
+                Err(e) => {
+                    self.failures.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "Failed to replicate {} to {} (retrying in {:?}): {}",
+                        hex::encode(&id),
+                        self.client.address(),
+                        backoff,
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    async fn push<S: Storage>(&self, source: &S, id: &[u8]) -> Result<()> {
+        let Some(data) = source.get(id)? else {
+            debug!("{} was evicted before it was replicated", hex::encode(id));
+            return Ok(());
+        };
+        let result = self.client.put(id, &data, None, true).await;
+        if let Some(events) = &self.events {
+            events.publish(CacheEvent::upstream_sync(
+                id,
+                data.len() as u64,
+                self.client.address(),
+                result.as_ref().err().map(|e| e.to_string()),
+            ));
+        }
+        result?;
+        self.replicated.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Queues of the artifacts to push to each peer region
+pub struct Replicator {
+    targets: Vec<Arc<Target>>,
+    queue_size: usize,
+}
+
+impl Replicator {
+    pub fn new(config: &ReplicationConfig, transport: GrpcTransport) -> anyhow::Result<Self> {
+        let targets = config
+            .targets
+            .iter()
+            .map(|target| {
+                Ok(Arc::new(Target {
+                    client: PeerClient::new(&target.url, target.secret.clone(), transport)?,
+                    filter: TargetFilter::from_config(target)
+                        .with_context(|| format!("replication.targets ({})", target.url))?,
+                    queue: Mutex::new(VecDeque::new()),
+                    notify: Notify::new(),
+                    replicated: AtomicU64::new(0),
+                    failures: AtomicU64::new(0),
+                    dropped: AtomicU64::new(0),
+                    events: None,
+                }))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            targets,
+            queue_size: config.queue_size,
+        })
+    }
+
+    /// Retry failed pushes with `retry` before they go back to the queue (must be
+    /// called before `spawn`)
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        for target in &mut self.targets {
+            let target = Arc::get_mut(target).expect("targets aren't shared before spawn");
+            target.client = target.client.clone().with_retry(retry.clone());
+        }
+        self
+    }
+
+    /// Publish each push, and each failed attempt, on `events` (must be called before
+    /// `spawn`)
+    pub fn with_events(mut self, events: Arc<EventBus>) -> Self {
+        for target in &mut self.targets {
+            let target = Arc::get_mut(target).expect("targets aren't shared before spawn");
+            target.events = Some(events.clone());
+        }
+        self
+    }
+
+    /// Queue a newly written artifact for the targets it matches
+    pub fn enqueue(&self, id: &[u8], size: u64) {
+        for target in self.targets.iter().filter(|t| t.filter.matches(id, size)) {
+            let mut queue = target.queue.lock().unwrap();
+            if queue.len() >= self.queue_size {
+                target.dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            queue.push_back(Pending {
+                id: id.to_vec(),
+                queued_at: Instant::now(),
+            });
+            drop(queue);
+            target.notify.notify_one();
+        }
+    }
+
+    /// Push queued artifacts in the background, reading them from `source`
+    pub fn spawn<S: Storage + 'static>(&self, source: Arc<S>) {
+        for target in &self.targets {
+            let target = target.clone();
+            let source = source.clone();
+            tokio::spawn(async move { target.run(source.as_ref()).await });
+        }
+    }
+
+    /// Export metrics in Prometheus format
+    pub fn export_prometheus(&self) -> String {
+        let mut output = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: &dyn Fn(&Target) -> String| {
+            output.push_str(&format!("# HELP {} {}\n", name, help));
+            output.push_str(&format!("# TYPE {} {}\n", name, kind));
+            for target in &self.targets {
+                output.push_str(&format!(
+                    "{}{{target=\"{}\"}} {}\n",
+                    name,
+                    target.client.address(),
+                    value(target)
+                ));
+            }
+        };
+        metric(
+            "fabrik_replication_lag_seconds",
+            "gauge",
+            "Age of the oldest artifact waiting to be replicated",
+            &|t| format!("{:.3}", t.lag().as_secs_f64()),
+        );
+        metric(
+            "fabrik_replication_queue_length",
+            "gauge",
+            "Artifacts waiting to be replicated",
+            &|t| t.queue.lock().unwrap().len().to_string(),
+        );
+        metric(
+            "fabrik_replication_replicated_total",
+            "counter",
+            "Artifacts pushed to the target",
+            &|t| t.replicated.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "fabrik_replication_failures_total",
+            "counter",
+            "Failed pushes (retried)",
+            &|t| t.failures.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "fabrik_replication_dropped_total",
+            "counter",
+            "Artifacts not replicated because the queue was full",
+            &|t| t.dropped.load(Ordering::Relaxed).to_string(),
+        );
+        output
+    }
+}
+
+/// Storage queueing the artifacts written to it for replication
+pub struct ReplicatingStorage<S: Storage> {
+    inner: Arc<S>,
+    replicator: Arc<Replicator>,
+}
+
+impl<S: Storage> ReplicatingStorage<S> {
+    pub fn new(inner: Arc<S>, replicator: Arc<Replicator>) -> Self {
+        Self { inner, replicator }
+    }
+}
+
+impl<S: Storage> Storage for ReplicatingStorage<S> {
+    fn put(&self, id: &[u8], data: &[u8]) -> Result<()> {
+        self.put_with_expiry(id, data, None)
+    }
+
+    fn put_with_expiry(&self, id: &[u8], data: &[u8], expires_at: Option<i64>) -> Result<()> {
+        self.inner.put_with_expiry(id, data, expires_at)?;
+        self.replicator.enqueue(id, data.len() as u64);
+        Ok(())
+    }
+
+    fn get(&self, id: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(id)
+    }
+
+    fn open(&self, id: &[u8]) -> Result<Option<Box<dyn std::io::Read + Send>>> {
+        self.inner.open(id)
+    }
+
+    fn exists(&self, id: &[u8]) -> Result<bool> {
+        self.inner.exists(id)
+    }
+
+    fn exists_many(&self, ids: &[Vec<u8>]) -> Result<Vec<bool>> {
+        self.inner.exists_many(ids)
+    }
+
+    fn delete(&self, id: &[u8]) -> Result<()> {
+        self.inner.delete(id)
+    }
+
+    fn size(&self, id: &[u8]) -> Result<Option<u64>> {
+        self.inner.size(id)
+    }
+
+    fn created_at(&self, id: &[u8]) -> Result<Option<i64>> {
+        self.inner.created_at(id)
+    }
+
+    fn info(&self, id: &[u8]) -> Result<Option<ObjectInfo>> {
+        self.inner.info(id)
+    }
+
+    fn touch(&self, id: &[u8]) -> Result<()> {
+        self.inner.touch(id)
+    }
+
+    fn touch_many(&self, ids: &[Vec<u8>]) -> Result<()> {
+        self.inner.touch_many(ids)
+    }
+
+    fn list_ids(&self) -> Result<Vec<Vec<u8>>> {
+        self.inner.list_ids()
+    }
+
+    fn stats(&self) -> Result<StorageStats> {
+        self.inner.stats()
+    }
+
+    fn put_tags(&self, id: &[u8], tags: &EntryTags) -> Result<()> {
+        self.inner.put_tags(id, tags)
+    }
+
+    fn get_tags(&self, id: &[u8]) -> Result<Option<EntryTags>> {
+        self.inner.get_tags(id)
+    }
+
+    fn add_labels(&self, id: &[u8], labels: &Labels) -> Result<()> {
+        self.inner.add_labels(id, labels)
+    }
+
+    fn get_labels(&self, id: &[u8]) -> Result<Labels> {
+        self.inner.get_labels(id)
+    }
+
+    fn find_by_labels(&self, labels: &Labels) -> Result<Vec<Vec<u8>>> {
+        self.inner.find_by_labels(labels)
+    }
+
+    fn pin(&self, holder: &[u8], ids: &[Vec<u8>]) -> Result<()> {
+        self.inner.pin(holder, ids)
+    }
+}
+
+/// Outcome of a backfill
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BackfillStats {
+    /// Artifacts stored on the source
+    pub listed: usize,
+    /// Artifacts excluded by the namespace or size filter
+    pub skipped: usize,
+    /// Artifacts the target already had
+    pub present: usize,
+    /// Artifacts copied to the target
+    pub copied: usize,
+    /// Artifacts that couldn't be copied
+    pub failed: usize,
+}
+
+/// Copy the artifacts stored on `source` that `target` is missing
+pub async fn backfill(
+    source: &PeerClient,
+    target: &PeerClient,
+    filter: &TargetFilter,
+) -> Result<BackfillStats> {
+    let mut stats = BackfillStats::default();
+    let mut batches = source.list().await?;
+    let context = format!("Node {}", source.address());
+
+    while let Some(batch) = batches.message().await.rpc_context(&context)? {
+        stats.listed += batch.ids.len();
+        let (ids, excluded): (Vec<_>, Vec<_>) = batch
+            .ids
+            .into_iter()
+            .partition(|id| filter.matches_namespace(id));
+        stats.skipped += excluded.len();
+        if ids.is_empty() {
+            continue;
+        }
+
+        let exists = target.exists(ids.clone()).await?;
+        for (id, exists) in ids.into_iter().zip(exists) {
+            if exists {
+                stats.present += 1;
+                continue;
+            }
+            let data = match source.get(&id).await {
+                Ok(Some(data)) => data,
+                // Evicted since it was listed
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Failed to read {}: {}", hex::encode(&id), e);
+                    stats.failed += 1;
+                    continue;
+                }
+            };
+            if !filter.matches_size(data.len() as u64) {
+                stats.skipped += 1;
+                continue;
+            }
+            match target.put(&id, &data, None, true).await {
+                Ok(()) => stats.copied += 1,
+                Err(e) => {
+                    warn!("Failed to copy {}: {}", hex::encode(&id), e);
+                    stats.failed += 1;
+                }
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::ClusterPeerService;
+    use crate::storage::FilesystemStorage;
+    use tempfile::TempDir;
+
+    const SECRET: &str = "region-secret-region-secret";
+
+    /// A region serving the cluster protocol on a random port
+    async fn spawn_region() -> (String, Arc<FilesystemStorage>, TempDir) {
+        let temp = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp.path()).unwrap());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let service = ClusterPeerService::new(storage.clone(), Some(SECRET.to_string()))
+            .with_routing(storage.clone());
+        tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_service(service.into_server())
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
+                .await
+        });
+        (address, storage, temp)
+    }
+
+    fn target(url: &str, namespaces: Vec<String>, max_size: Option<&str>) -> ReplicationTarget {
+        ReplicationTarget {
+            url: url.to_string(),
+            secret: Some(SECRET.to_string()),
+            namespaces,
+            max_size: max_size.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_target_filter() {
+        let filter = TargetFilter::parse(&["xcode-kv".to_string()], Some("1KB")).unwrap();
+        assert!(filter.matches(b"kv:key", 10));
+        assert!(!filter.matches(b"kv:key", 4096));
+        assert!(!filter.matches(&[1; 32], 10));
+        assert!(TargetFilter::default().matches(&[1; 32], u64::MAX));
+        assert!(TargetFilter::parse(&["gradle".to_string()], None).is_err());
+        assert!(TargetFilter::parse(&[], Some("huge")).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pushes_writes_to_targets() {
+        let (region, region_storage, _region_dir) = spawn_region().await;
+        let local_dir = TempDir::new().unwrap();
+        let local = Arc::new(FilesystemStorage::new(local_dir.path()).unwrap());
+
+        let replicator = Arc::new(
+            Replicator::new(
+                &ReplicationConfig {
+                    targets: vec![target(&region, vec![], Some("1KB"))],
+                    ..Default::default()
+                },
+                GrpcTransport::default(),
+            )
+            .unwrap(),
+        );
+        let storage = ReplicatingStorage::new(local.clone(), replicator.clone());
+        replicator.spawn(local.clone());
+
+        storage.put(&[1; 32], b"small").unwrap();
+        storage.put(&[2; 32], &[0; 4096]).unwrap();
+        storage.put(b"kv:key", b"value").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !region_storage.exists(b"kv:key").unwrap() {
+            assert!(Instant::now() < deadline, "artifacts weren't replicated");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(
+            region_storage.get(&[1; 32]).unwrap(),
+            Some(b"small".to_vec())
+        );
+        // Larger than the target's max_size
+        assert!(!region_storage.exists(&[2; 32]).unwrap());
+
+        let metrics = replicator.export_prometheus();
+        assert!(metrics.contains(&format!(
+            "fabrik_replication_replicated_total{{target=\"{}\"}} 2",
+            region
+        )));
+        assert!(metrics.contains(&format!(
+            "fabrik_replication_lag_seconds{{target=\"{}\"}} 0.000",
+            region
+        )));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_backfill_copies_missing_artifacts() {
+        let (source, source_storage, _source_dir) = spawn_region().await;
+        let (target, target_storage, _target_dir) = spawn_region().await;
+        source_storage.put(&[1; 32], b"cas").unwrap();
+        source_storage.put(&[2; 32], b"present").unwrap();
+        source_storage.put(b"kv:key", b"value").unwrap();
+        target_storage.put(&[2; 32], b"present").unwrap();
+
+        let source =
+            PeerClient::new(&source, Some(SECRET.to_string()), GrpcTransport::default()).unwrap();
+        let target = PeerClient::new(
+            &format!("grpc://{}", target),
+            Some(SECRET.to_string()),
+            GrpcTransport::default(),
+        )
+        .unwrap();
+        let filter = TargetFilter::parse(&["xcode-cas".to_string()], None).unwrap();
+        let stats = backfill(&source, &target, &filter).await.unwrap();
+
+        assert_eq!(
+            stats,
+            BackfillStats {
+                listed: 3,
+                skipped: 1,
+                present: 1,
+                copied: 1,
+                failed: 0,
+            }
+        );
+        assert_eq!(target_storage.get(&[1; 32]).unwrap(), Some(b"cas".to_vec()));
+        assert!(!target_storage.exists(b"kv:key").unwrap());
+    }
+}
@@ -0,0 +1,103 @@
+/// Consistent hash ring of the cluster's nodes
+///
+/// Each node is placed at `VNODES` points on the ring (hashes of `address#i`), so blobs
+/// spread evenly and adding or removing a node only moves the blobs of the ranges next
+/// to its points, about 1/N of them. A blob's owners are the first distinct nodes
+/// clockwise from the blob's hash.
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// Points per node
+const VNODES: usize = 128;
+
+#[derive(Debug, Clone, Default)]
+pub struct HashRing {
+    points: BTreeMap<u64, String>,
+    nodes: usize,
+}
+
+impl HashRing {
+    pub fn new<I, S>(nodes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut ring = Self::default();
+        for node in nodes {
+            let node = node.as_ref();
+            for i in 0..VNODES {
+                ring.points.insert(
+                    point(format!("{}#{}", node, i).as_bytes()),
+                    node.to_string(),
+                );
+            }
+            ring.nodes += 1;
+        }
+        ring
+    }
+
+    /// The first `n` distinct nodes clockwise from `id` (all nodes if there are fewer)
+    pub fn owners(&self, id: &[u8], n: usize) -> Vec<&str> {
+        let n = n.min(self.nodes);
+        let mut owners: Vec<&str> = Vec::with_capacity(n);
+        let start = point(id);
+        for node in self
+            .points
+            .range(start..)
+            .chain(self.points.range(..start))
+            .map(|(_, node)| node.as_str())
+        {
+            if owners.len() == n {
+                break;
+            }
+            if !owners.contains(&node) {
+                owners.push(node);
+            }
+        }
+        owners
+    }
+}
+
+fn point(data: &[u8]) -> u64 {
+    let digest = Sha256::digest(data);
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owners() {
+        let ring = HashRing::new(["a:7070", "b:7070", "c:7070"]);
+
+        let owners = ring.owners(b"blob", 2);
+        assert_eq!(owners.len(), 2);
+        assert_ne!(owners[0], owners[1]);
+        // Every node computes the same owners, whatever the order of its peer list
+        assert_eq!(
+            HashRing::new(["c:7070", "a:7070", "b:7070"]).owners(b"blob", 2),
+            owners
+        );
+        assert_eq!(ring.owners(b"blob", 5).len(), 3);
+        assert!(HashRing::default().owners(b"blob", 2).is_empty());
+    }
+
+    #[test]
+    fn test_adding_a_node_moves_few_blobs() {
+        let before = HashRing::new(["a", "b", "c"]);
+        let after = HashRing::new(["a", "b", "c", "d"]);
+
+        let ids: Vec<Vec<u8>> = (0..2000u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        let moved = ids
+            .iter()
+            .filter(|id| before.owners(id, 1) != after.owners(id, 1))
+            .count();
+        // About a quarter of the blobs move to the new node, and only to it
+        assert!((300..700).contains(&moved), "moved {}", moved);
+        assert!(ids
+            .iter()
+            .filter(|id| before.owners(id, 1) != after.owners(id, 1))
+            .all(|id| after.owners(id, 1) == ["d"]));
+    }
+}
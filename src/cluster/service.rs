@@ -0,0 +1,149 @@
+use std::sync::Arc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use super::proto::cluster_peer_server::{ClusterPeer, ClusterPeerServer};
+use super::proto::{
+    DeleteRequest, DeleteResponse, ExistsRequest, ExistsResponse, GetRequest, GetResponse,
+    ListRequest, ListResponse, PutRequest, PutResponse,
+};
+use super::{secret_matches, MAX_MESSAGE_SIZE, SECRET_HEADER};
+use crate::notifications::{NotificationKind, Notifier};
+use crate::storage::Storage;
+
+/// IDs per List response
+const LIST_BATCH: usize = 1000;
+
+/// Cluster protocol server, answering other nodes from this node's local storage
+pub struct ClusterPeerService<S: Storage> {
+    storage: Arc<S>,
+    secret: Option<String>,
+    routed: Option<Arc<dyn Storage>>,
+    notifier: Option<Arc<Notifier>>,
+}
+
+impl<S: Storage + 'static> ClusterPeerService<S> {
+    pub fn new(storage: Arc<S>, secret: Option<String>) -> Self {
+        Self {
+            storage,
+            secret,
+            routed: None,
+            notifier: None,
+        }
+    }
+
+    /// Store routed puts (from other regions) through `storage`, which routes blobs to
+    /// their owners; without it they're stored locally
+    pub fn with_routing(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.routed = Some(storage);
+        self
+    }
+
+    /// Notify `notifier` of requests rejected for an invalid secret
+    pub fn with_notifier(mut self, notifier: Option<Arc<Notifier>>) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    pub fn into_server(self) -> ClusterPeerServer<Self> {
+        ClusterPeerServer::new(self)
+            .max_decoding_message_size(MAX_MESSAGE_SIZE)
+            .max_encoding_message_size(MAX_MESSAGE_SIZE)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn authorize<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let Some(secret) = &self.secret else {
+            return Ok(());
+        };
+        let sent = request
+            .metadata()
+            .get(SECRET_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if secret_matches(secret, sent) {
+            return Ok(());
+        }
+        if let Some(notifier) = &self.notifier {
+            let client = request
+                .remote_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            notifier.notify(
+                NotificationKind::AuthError,
+                format!("Rejected a cluster request from {}: invalid secret", client),
+                serde_json::json!({ "source": "cluster", "client": client }),
+            );
+        }
+        Err(Status::unauthenticated("Invalid cluster secret"))
+    }
+}
+
+#[tonic::async_trait]
+impl<S: Storage + 'static> ClusterPeer for ClusterPeerService<S> {
+    async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
+        self.authorize(&request)?;
+        let response = match self.storage.get(&request.into_inner().id)? {
+            Some(data) => GetResponse { found: true, data },
+            None => GetResponse::default(),
+        };
+        Ok(Response::new(response))
+    }
+
+    async fn exists(
+        &self,
+        request: Request<ExistsRequest>,
+    ) -> Result<Response<ExistsResponse>, Status> {
+        self.authorize(&request)?;
+        let exists = self.storage.exists_many(&request.into_inner().ids)?;
+        Ok(Response::new(ExistsResponse { exists }))
+    }
+
+    async fn put(&self, request: Request<PutRequest>) -> Result<Response<PutResponse>, Status> {
+        self.authorize(&request)?;
+        let request = request.into_inner();
+        let expires_at = (request.expires_at != 0).then_some(request.expires_at);
+        match self.routed.as_ref().filter(|_| request.route) {
+            Some(routed) => routed.put_with_expiry(&request.id, &request.data, expires_at)?,
+            None => self
+                .storage
+                .put_with_expiry(&request.id, &request.data, expires_at)?,
+        }
+        Ok(Response::new(PutResponse {}))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        self.authorize(&request)?;
+        let id = request.into_inner().id;
+        if self.storage.exists(&id)? {
+            self.storage.delete(&id)?;
+        }
+        Ok(Response::new(DeleteResponse {}))
+    }
+
+    type ListStream = ReceiverStream<Result<ListResponse, Status>>;
+
+    async fn list(
+        &self,
+        request: Request<ListRequest>,
+    ) -> Result<Response<Self::ListStream>, Status> {
+        self.authorize(&request)?;
+        let ids = self.storage.list_ids()?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        tokio::spawn(async move {
+            for batch in ids.chunks(LIST_BATCH) {
+                let response = ListResponse {
+                    ids: batch.to_vec(),
+                };
+                if tx.send(Ok(response)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
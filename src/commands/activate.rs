@@ -5,29 +5,63 @@ use std::time::Duration;
 use crate::cli::ActivateArgs;
 use crate::config_discovery::{discover_config, hash_config, DaemonState};
 
+/// Environment variables set by `fabrik activate --status`
+const ACTIVATED_ENV_VARS: &[&str] = &[
+    "FABRIK_HTTP_URL",
+    "FABRIK_HTTP_SOCKET",
+    "FABRIK_GRPC_URL",
+    "FABRIK_S3_URL",
+    "FABRIK_UNIX_SOCKET",
+    "FABRIK_CONFIG_HASH",
+    "FABRIK_DAEMON_PID",
+    "GRADLE_BUILD_CACHE_URL",
+    "NX_SELF_HOSTED_REMOTE_CACHE_SERVER",
+    "XCODE_CACHE_SERVER",
+    "TURBO_API",
+    "TURBO_TEAM",
+    "TURBO_TOKEN",
+];
+
 pub fn run(args: ActivateArgs) -> Result<()> {
-    // If shell specified, output shell integration hook
-    if let Some(shell) = args.shell {
-        output_shell_hook(&shell)?;
+    let shell = args.shell.as_deref().map(normalize_shell);
+
+    // If --status, check/start daemon and output env vars for the shell (bash by default)
+    if args.status {
+        activate_current_directory(shell.unwrap_or("bash"), args.direnv)?;
         return Ok(());
     }
 
-    // If --status, check/start daemon and output env vars
-    if args.status {
-        activate_current_directory()?;
+    if args.direnv {
+        output_direnv_library();
+        return Ok(());
+    }
+
+    // If shell specified, output shell integration hook
+    if let Some(shell) = shell {
+        output_shell_hook(shell)?;
         return Ok(());
     }
 
     // Default: show help
     println!("Usage:");
-    println!("  fabrik activate <shell>    Generate shell integration hook");
-    println!("  fabrik activate --status   Check/start daemon and export env vars");
+    println!("  fabrik activate <shell>            Generate shell integration hook");
+    println!("  fabrik activate [<shell>] --status Check/start daemon and export env vars");
+    println!("  fabrik activate --direnv           Generate direnv library function (use fabrik)");
     println!();
-    println!("Shells: bash, zsh, fish");
+    println!("Shells: bash, zsh, fish, powershell (pwsh), nu (nushell)");
 
     Ok(())
 }
 
+/// Canonical name of a shell that goes by several names
+fn normalize_shell(shell: &str) -> &str {
+    match shell {
+        "pwsh" | "powershell" => "powershell",
+        "nu" | "nushell" => "nu",
+        shell => shell,
+    }
+}
+
 fn output_shell_hook(shell: &str) -> Result<()> {
     match shell {
         "bash" => {
@@ -42,6 +76,22 @@ if [[ -n "${{PROMPT_COMMAND}}" ]]; then
 else
   PROMPT_COMMAND="_fabrik_hook"
 fi
+
+# Complete cache hashes and keys (fabrik cas get/cat/info/delete, fabrik kv get)
+_fabrik_complete() {{
+  local cur="${{COMP_WORDS[COMP_CWORD]}}"
+  if [[ ${{COMP_CWORD}} -eq 3 ]]; then
+    case "${{COMP_WORDS[1]}} ${{COMP_WORDS[2]}}" in
+      "cas get"|"cas cat"|"cas info"|"cas delete")
+        COMPREPLY=($(compgen -W "$(fabrik __complete cas "$cur" 2>/dev/null)" -- "$cur"))
+        ;;
+      "kv get")
+        COMPREPLY=($(compgen -W "$(fabrik __complete kv "$cur" 2>/dev/null)" -- "$cur"))
+        ;;
+    esac
+  fi
+}}
+complete -p fabrik &>/dev/null || complete -o default -F _fabrik_complete fabrik
 "#
             );
         }
@@ -57,6 +107,27 @@ add-zsh-hook chpwd _fabrik_hook
 
 # Run now
 _fabrik_hook
+
+# Complete cache hashes and keys (fabrik cas get/cat/info/delete, fabrik kv get)
+_fabrik_complete() {{
+  local -a candidates
+  if (( CURRENT == 4 )); then
+    case "${{words[2]}} ${{words[3]}}" in
+      "cas get"|"cas cat"|"cas info"|"cas delete")
+        candidates=(${{(f)"$(fabrik __complete cas "${{words[CURRENT]}}" 2>/dev/null)"}})
+        compadd -a candidates
+        return
+        ;;
+      "kv get")
+        candidates=(${{(f)"$(fabrik __complete kv "${{words[CURRENT]}}" 2>/dev/null)"}})
+        compadd -a candidates
+        return
+        ;;
+    esac
+  fi
+  _files
+}}
+(( $+functions[compdef] )) && (( ! $+_comps[fabrik] )) && compdef _fabrik_complete fabrik
 "#
             );
         }
@@ -68,18 +139,121 @@ end
 
 # Run now
 _fabrik_hook
+
+# Complete cache hashes and keys (fabrik cas get/cat/info/delete, fabrik kv get)
+complete -c fabrik -n '__fish_seen_subcommand_from cas; and __fish_seen_subcommand_from get cat info delete' -f -a '(fabrik __complete cas (commandline -ct) 2>/dev/null)'
+complete -c fabrik -n '__fish_seen_subcommand_from kv; and __fish_seen_subcommand_from get' -f -a '(fabrik __complete kv (commandline -ct) 2>/dev/null)'
+"#
+            );
+        }
+        "powershell" => {
+            println!(
+                r#"function global:_fabrik_hook {{
+  $fabrikEnv = & fabrik activate pwsh --status 2>$null | Out-String
+  if ($fabrikEnv) {{ Invoke-Expression $fabrikEnv }}
+}}
+
+# Run on directory change (checked before each prompt)
+if (-not $global:_fabrik_prompt) {{
+  $global:_fabrik_prompt = $function:prompt
+  function global:prompt {{
+    if ($global:_fabrik_dir -ne $PWD.ProviderPath) {{
+      $global:_fabrik_dir = $PWD.ProviderPath
+      _fabrik_hook
+    }}
+    & $global:_fabrik_prompt
+  }}
+}}
+
+# Complete cache hashes and keys (fabrik cas get/cat/info/delete, fabrik kv get)
+Register-ArgumentCompleter -Native -CommandName fabrik -ScriptBlock {{
+  param($wordToComplete, $commandAst, $cursorPosition)
+  $words = @($commandAst.CommandElements | ForEach-Object {{ $_.ToString() }})
+  $position = if ($wordToComplete) {{ $words.Count - 1 }} else {{ $words.Count }}
+  if ($position -ne 3) {{ return }}
+  $kind = switch ("$($words[1]) $($words[2])") {{
+    {{ $_ -in 'cas get', 'cas cat', 'cas info', 'cas delete' }} {{ 'cas' }}
+    'kv get' {{ 'kv' }}
+  }}
+  if (-not $kind) {{ return }}
+  & fabrik __complete $kind $wordToComplete 2>$null | ForEach-Object {{
+    [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+  }}
+}}
+"#
+            );
+        }
+        "nu" => {
+            println!(
+                r#"def --env _fabrik_hook [] {{
+  let result = (do -i {{ ^fabrik activate nu --status }} | complete)
+  if $result.exit_code != 0 or ($result.stdout | is-empty) {{ return }}
+  let fabrik = ($result.stdout | from json)
+  for name in $fabrik.unset {{ hide-env -i $name }}
+  load-env $fabrik.set
+}}
+
+# Run on directory change
+$env.config.hooks.env_change.PWD = (
+  $env.config.hooks.env_change.PWD? | default [] | append {{|before, after| _fabrik_hook }}
+)
+
+# Run now
+_fabrik_hook
+
+# Complete cache hashes and keys (fabrik cas get/cat/info/delete, fabrik kv get)
+def "nu-complete fabrik cas" [context: string] {{
+  ^fabrik __complete cas ($context | split row ' ' | last) | lines
+}}
+def "nu-complete fabrik kv" [context: string] {{
+  ^fabrik __complete kv ($context | split row ' ' | last) | lines
+}}
+extern "fabrik cas get" [hash?: string@"nu-complete fabrik cas", ...rest]
+extern "fabrik cas cat" [hash?: string@"nu-complete fabrik cas", ...rest]
+extern "fabrik cas info" [hash?: string@"nu-complete fabrik cas", ...rest]
+extern "fabrik cas delete" [hash?: string@"nu-complete fabrik cas", ...rest]
+extern "fabrik kv get" [key?: string@"nu-complete fabrik kv", ...rest]
 "#
             );
         }
         _ => {
-            anyhow::bail!("Unsupported shell: {}. Use bash, zsh, or fish", shell);
+            anyhow::bail!(
+                "Unsupported shell: {}. Use bash, zsh, fish, powershell, or nu",
+                shell
+            );
         }
     }
 
     Ok(())
 }
 
-fn activate_current_directory() -> Result<()> {
+/// Library function for direnv, which evaluates .envrc files with bash and restores the
+/// environment when leaving the directory
+fn output_direnv_library() {
+    println!(
+        r#"# Fabrik integration for direnv: save as ~/.config/direnv/lib/fabrik.sh and
+# add `use fabrik` to a project's .envrc
+use_fabrik() {{
+  if ! has fabrik; then
+    log_error "fabrik: command not found"
+    return 1
+  fi
+  eval "$(fabrik activate --status --direnv)"
+}}"#
+    );
+}
+
+/// Print a status note: as a comment for shells that evaluate the output, on stderr for
+/// nushell (whose hook parses stdout as JSON)
+fn note(shell: &str, message: &str) {
+    if shell == "nu" {
+        eprintln!("{}", message);
+    } else {
+        println!("# {}", message);
+    }
+}
+
+fn activate_current_directory(shell: &str, direnv: bool) -> Result<()> {
     let current_dir = env::current_dir().context("Failed to get current directory")?;
 
     // Find config
@@ -87,31 +261,36 @@ fn activate_current_directory() -> Result<()> {
         Some(path) => path,
         None => {
             // No config found, unset variables
-            println!("# No fabrik.toml found");
-            output_unset_env_vars("bash");
+            note(shell, "No fabrik.toml found");
+            output_unset_env_vars(shell);
             return Ok(());
         }
     };
 
+    // Reload the environment when the config changes
+    if direnv {
+        println!(
+            "watch_file '{}'",
+            config_path.display().to_string().replace('\'', "'\\''")
+        );
+    }
+
     // Compute config hash
     let config_hash = hash_config(&config_path)?;
 
-    // Check if daemon already running
-    if let Some(state) = DaemonState::load(&config_hash)? {
-        if state.is_running() {
-            // Daemon running, export env vars
-            println!("{}", state.generate_env_exports("bash"));
-            return Ok(());
-        } else {
-            // Daemon state exists but process is dead, clean it up
-            let _ = state.cleanup();
-        }
+    // Check if daemon already running (state left by a dead daemon is cleaned up)
+    if let Some(state) = DaemonState::load_running(&config_hash)? {
+        println!("{}", state.generate_env_exports(shell));
+        return Ok(());
     }
 
     // Need to start daemon
-    println!(
-        "# Starting Fabrik daemon for config: {}",
-        config_path.display()
+    note(
+        shell,
+        &format!(
+            "Starting Fabrik daemon for config: {}",
+            config_path.display()
+        ),
     );
 
     // Start daemon process in background
@@ -119,9 +298,9 @@ fn activate_current_directory() -> Result<()> {
 
     // Load the state and export env vars
     if let Some(state) = DaemonState::load(&config_hash)? {
-        println!("{}", state.generate_env_exports("bash"));
+        println!("{}", state.generate_env_exports(shell));
     } else {
-        println!("# Warning: Daemon started but state not found");
+        note(shell, "Warning: Daemon started but state not found");
     }
 
     Ok(())
@@ -133,14 +312,26 @@ fn start_daemon_background(config_path: &std::path::Path, config_hash: &str) ->
     // Get the current executable path
     let exe = env::current_exe().context("Failed to get current executable path")?;
 
+    // Capture daemon output in a log file (collected by `fabrik doctor --bundle`)
+    let log_path = DaemonState::log_file(config_hash);
+    if let Some(parent) = log_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create logs dir: {}", parent.display()))?;
+    }
+    let log_file = std::fs::File::create(&log_path)
+        .with_context(|| format!("Failed to create daemon log: {}", log_path.display()))?;
+    let log_file_err = log_file
+        .try_clone()
+        .context("Failed to clone daemon log handle")?;
+
     // Spawn fabrik daemon with the config file
     Command::new(&exe)
         .arg("daemon")
         .arg("--config")
         .arg(config_path)
         .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
+        .stdout(Stdio::from(log_file))
+        .stderr(Stdio::from(log_file_err))
         .spawn()
         .context("Failed to spawn daemon process")?;
 
@@ -176,30 +367,25 @@ fn start_daemon_background(config_path: &std::path::Path, config_hash: &str) ->
 fn output_unset_env_vars(shell: &str) {
     match shell {
         "fish" => {
-            println!("set -e FABRIK_HTTP_URL 2>/dev/null");
-            println!("set -e FABRIK_GRPC_URL 2>/dev/null");
-            println!("set -e FABRIK_UNIX_SOCKET 2>/dev/null");
-            println!("set -e FABRIK_CONFIG_HASH 2>/dev/null");
-            println!("set -e FABRIK_DAEMON_PID 2>/dev/null");
-            println!("set -e GRADLE_BUILD_CACHE_URL 2>/dev/null");
-            println!("set -e NX_SELF_HOSTED_REMOTE_CACHE_SERVER 2>/dev/null");
-            println!("set -e XCODE_CACHE_SERVER 2>/dev/null");
-            println!("set -e TURBO_API 2>/dev/null");
-            println!("set -e TURBO_TEAM 2>/dev/null");
-            println!("set -e TURBO_TOKEN 2>/dev/null");
+            for name in ACTIVATED_ENV_VARS {
+                println!("set -e {} 2>/dev/null", name);
+            }
+        }
+        "powershell" => {
+            for name in ACTIVATED_ENV_VARS {
+                println!("Remove-Item Env:{} -ErrorAction SilentlyContinue", name);
+            }
+        }
+        "nu" => {
+            println!(
+                "{}",
+                serde_json::json!({ "set": {}, "unset": ACTIVATED_ENV_VARS })
+            );
         }
         _ => {
-            println!("unset FABRIK_HTTP_URL");
-            println!("unset FABRIK_GRPC_URL");
-            println!("unset FABRIK_UNIX_SOCKET");
-            println!("unset FABRIK_CONFIG_HASH");
-            println!("unset FABRIK_DAEMON_PID");
-            println!("unset GRADLE_BUILD_CACHE_URL");
-            println!("unset NX_SELF_HOSTED_REMOTE_CACHE_SERVER");
-            println!("unset XCODE_CACHE_SERVER");
-            println!("unset TURBO_API");
-            println!("unset TURBO_TEAM");
-            println!("unset TURBO_TOKEN");
+            for name in ACTIVATED_ENV_VARS {
+                println!("unset {}", name);
+            }
         }
     }
 }
@@ -1,7 +1,9 @@
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 
+use crate::auth::service::{self, TokenRequest};
 use crate::auth::AuthProvider;
+use crate::cli::AuthTokenCreateArgs;
 use crate::cli_utils::fabrik_prefix;
 use crate::config::FabrikConfig;
 
@@ -108,3 +110,74 @@ pub async fn token(config: FabrikConfig) -> Result<()> {
         }
     }
 }
+
+/// Mint a machine token through the configured service
+pub async fn create_token(config: FabrikConfig, args: &AuthTokenCreateArgs) -> Result<()> {
+    let service_url = config
+        .url
+        .clone()
+        .context("No service configured. Set 'url' at root level of fabrik.toml")?;
+
+    let name = args.name.clone().unwrap_or_else(|| {
+        let host = hostname::get()
+            .map(|h| h.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| "unknown".to_string());
+        format!("fabrik-{}", host)
+    });
+    let request = TokenRequest::new(name, args.scopes.clone(), &args.ttl)?;
+
+    let provider = AuthProvider::new(config.auth, config.url)
+        .context("Failed to initialize authentication provider")?;
+    let bearer = provider
+        .get_token()
+        .await
+        .context("Not authenticated. Run `fabrik auth login` first")?;
+
+    let token = service::create_token(&service_url, &bearer, &request)
+        .await
+        .context("Failed to create token")?;
+
+    eprintln!(
+        "{} ✓ Created token '{}' (scopes: {})",
+        fabrik_prefix(),
+        request.name,
+        request.scopes.join(" ")
+    );
+    if let Some(expires_at) = token.expires_at {
+        let dt = DateTime::<Utc>::from_timestamp(expires_at as i64, 0).unwrap_or_else(Utc::now);
+        eprintln!(
+            "{} Expires: {}",
+            fabrik_prefix(),
+            dt.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+    }
+
+    if !args.no_store {
+        match provider.storage_backend() {
+            Some(backend) => {
+                let key = provider
+                    .save_machine_token(&request.name, &token.token, token.expires_at)
+                    .await
+                    .context("Failed to store token")?;
+                eprintln!(
+                    "{} Stored in {} storage as {}",
+                    fabrik_prefix(),
+                    backend,
+                    key
+                );
+            }
+            None => eprintln!(
+                "{} Not stored: no auth.oauth2 storage configured",
+                fabrik_prefix()
+            ),
+        }
+    }
+
+    eprintln!(
+        "{} The token is only shown once. Add it to your CI secrets and export it as FABRIK_TOKEN:",
+        fabrik_prefix()
+    );
+    println!("export FABRIK_TOKEN={}", token.token);
+
+    Ok(())
+}
@@ -6,8 +6,92 @@
 /// - `fabrik kv` - Key-Value storage operations
 /// - `fabrik run --status/--list/--stats` - Script cache management
 ///
-/// This stub prints a deprecation warning.
-use anyhow::Result;
+/// This stub prints a deprecation warning. The exceptions are `fabrik cache compact`,
+/// which maintains the storage itself rather than its contents, and `fabrik cache warm`,
+/// which prefetches artifacts from the upstreams before a build, and
+/// `fabrik cache migrate`, which moves a cache to another directory or daemon.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::cli_utils::{fabrik_prefix, format_size};
+use crate::config_discovery::{
+    discover_config, hash_config, load_config_with_discovery, DaemonState,
+};
+use crate::error::FabrikError;
+use crate::http::ORIGIN_HEADER;
+use crate::recipe::remote::RemoteCache;
+use crate::storage::migrate::{self, MigrateStats};
+use crate::storage::{default_cache_dir, open_storage, FilesystemStorage, Origin, Storage};
+
+/// Hashes per existence check sent to a daemon (its find-missing limit)
+const WARM_BATCH: usize = 10_000;
+
+/// Objects between progress lines of a migration
+const MIGRATE_PROGRESS_EVERY: u64 = 1_000;
+
+#[derive(Serialize)]
+struct CompactOutput {
+    shard_dirs_removed: u64,
+    chunks_removed: u64,
+    metadata_bytes_before: u64,
+    metadata_bytes_after: u64,
+    reclaimed_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct WarmOutput {
+    requested: usize,
+    already_cached: usize,
+    fetched: usize,
+    fetched_bytes: u64,
+    not_found: Vec<String>,
+    failed: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct MigrateOutput {
+    copied: u64,
+    copied_bytes: u64,
+    already_present: u64,
+    missing: u64,
+    corrupt: u64,
+}
+
+/// Warm manifest: a JSON array of hashes, or an object with a `hashes` array
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WarmManifest {
+    Hashes(Vec<String>),
+    Object { hashes: Vec<String> },
+}
+
+#[derive(Deserialize)]
+struct FindMissingResponse {
+    missing: Vec<String>,
+}
+
+/// Cache that prefetched or migrated artifacts are stored into
+#[derive(Clone)]
+enum CacheTarget {
+    /// A daemon or server, through its HTTP artifact API
+    Daemon {
+        client: reqwest::Client,
+        url: String,
+    },
+    /// A local cache directory (no daemon holds it open)
+    Local(Arc<dyn Storage>),
+}
+
+/// Result of prefetching one artifact
+enum Fetched {
+    Stored { bytes: u64, upstream: String },
+    NotFound,
+}
 
 #[allow(dead_code)]
 pub async fn cache_deprecated() -> Result<()> {
@@ -24,3 +108,422 @@ pub async fn cache_deprecated() -> Result<()> {
 
     std::process::exit(1);
 }
+
+/// Remove empty shard directories and compact the metadata database
+pub async fn compact(config_cache_dir: Option<&str>, json: bool) -> Result<()> {
+    let cache_dir = config_cache_dir
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(default_cache_dir);
+
+    let storage = FilesystemStorage::new(&cache_dir).with_context(|| {
+        format!(
+            "Failed to open cache at {} (stop a daemon or server using it first)",
+            cache_dir.display()
+        )
+    })?;
+    let stats = storage.compact().context("Failed to compact storage")?;
+
+    if json {
+        let output = CompactOutput {
+            shard_dirs_removed: stats.shard_dirs_removed,
+            chunks_removed: stats.chunks_removed,
+            metadata_bytes_before: stats.metadata_bytes_before,
+            metadata_bytes_after: stats.metadata_bytes_after,
+            reclaimed_bytes: stats.reclaimed_bytes(),
+        };
+        println!("{}", serde_json::to_string(&output)?);
+    } else {
+        println!(
+            "{} Removed {} empty shard directories",
+            fabrik_prefix(),
+            stats.shard_dirs_removed
+        );
+        if stats.chunks_removed > 0 {
+            println!(
+                "{} Removed {} unreferenced chunks ({})",
+                fabrik_prefix(),
+                stats.chunks_removed,
+                format_size(stats.chunk_bytes)
+            );
+        }
+        println!(
+            "{} Metadata: {} -> {}",
+            fabrik_prefix(),
+            format_size(stats.metadata_bytes_before),
+            format_size(stats.metadata_bytes_after)
+        );
+        println!(
+            "{} Reclaimed {}",
+            fabrik_prefix(),
+            format_size(stats.reclaimed_bytes())
+        );
+    }
+
+    Ok(())
+}
+
+/// Prefetch the artifacts listed in a manifest from the upstreams into the local cache
+pub async fn warm(
+    config_cache_dir: Option<&str>,
+    metadata_backend: &str,
+    embedded_max_objects: usize,
+    manifest: &str,
+    jobs: usize,
+    config: Option<&str>,
+    json: bool,
+) -> Result<()> {
+    let hashes = read_manifest(Path::new(manifest))?;
+
+    let config_path = match config {
+        Some(path) => Some(PathBuf::from(path)),
+        None => discover_config(&std::env::current_dir()?)?,
+    };
+    let file_config = load_config_with_discovery(config)?.unwrap_or_default();
+    let remote = RemoteCache::from_config(&file_config)
+        .await?
+        .context("No HTTP upstreams configured to warm the cache from")?;
+
+    let target = match running_daemon(config_path.as_deref())? {
+        Some(state) if state.http_socket.is_some() => anyhow::bail!(
+            "The daemon (PID {}) serves HTTP on a Unix socket only; stop it or remove \
+             [daemon] http_socket to warm the cache",
+            state.pid
+        ),
+        Some(state) => CacheTarget::Daemon {
+            client: reqwest::Client::builder()
+                .build()
+                .context("Failed to create HTTP client")?,
+            url: format!("http://127.0.0.1:{}", state.http_port),
+        },
+        None => {
+            let cache_dir = config_cache_dir
+                .map(PathBuf::from)
+                .unwrap_or_else(default_cache_dir);
+            let storage = open_storage(
+                &cache_dir,
+                metadata_backend.parse()?,
+                embedded_max_objects,
+                None,
+            )
+            .with_context(|| format!("Failed to open cache at {}", cache_dir.display()))?;
+            CacheTarget::Local(Arc::from(storage))
+        }
+    };
+
+    let missing = target.find_missing(&hashes).await?;
+    let already_cached = hashes.len() - missing.len();
+    if !json {
+        println!(
+            "{} Warming {} artifacts ({} already cached) with {} workers",
+            fabrik_prefix(),
+            missing.len(),
+            already_cached,
+            jobs
+        );
+    }
+
+    let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+    let mut workers = JoinSet::new();
+    for hash in missing.iter().cloned() {
+        let (remote, target) = (remote.clone(), target.clone());
+        let permit = semaphore.clone().acquire_owned().await?;
+        workers.spawn(async move {
+            let result = prefetch(&remote, &target, &hash).await;
+            drop(permit);
+            (hash, result)
+        });
+    }
+
+    let mut output = WarmOutput {
+        requested: hashes.len(),
+        already_cached,
+        fetched: 0,
+        fetched_bytes: 0,
+        not_found: Vec::new(),
+        failed: Vec::new(),
+    };
+    let mut done = 0;
+    while let Some(joined) = workers.join_next().await {
+        let (hash, result) = joined.context("Prefetch worker panicked")?;
+        done += 1;
+        let status = match result {
+            Ok(Fetched::Stored { bytes, upstream }) => {
+                output.fetched += 1;
+                output.fetched_bytes += bytes;
+                format!("{} from {}", format_size(bytes), upstream)
+            }
+            Ok(Fetched::NotFound) => {
+                output.not_found.push(hash.clone());
+                "not found upstream".to_string()
+            }
+            Err(e) => {
+                output.failed.push(hash.clone());
+                format!("failed: {:#}", e)
+            }
+        };
+        if !json {
+            println!(
+                "{} [{}/{}] {} {}",
+                fabrik_prefix(),
+                done,
+                missing.len(),
+                hash,
+                status
+            );
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string(&output)?);
+    } else {
+        println!(
+            "{} Fetched {} artifacts ({}), {} already cached, {} not found upstream, {} failed",
+            fabrik_prefix(),
+            output.fetched,
+            format_size(output.fetched_bytes),
+            output.already_cached,
+            output.not_found.len(),
+            output.failed.len()
+        );
+    }
+
+    if !output.failed.is_empty() {
+        anyhow::bail!("Failed to warm {} artifacts", output.failed.len());
+    }
+    Ok(())
+}
+
+/// Copy a cache into another cache directory, or into a daemon at an HTTP URL
+pub async fn migrate(from: &str, to: &str, json: bool) -> Result<()> {
+    let from = Path::new(from);
+    if !from.join("metadata").is_dir() {
+        anyhow::bail!("No cache at {}", from.display());
+    }
+    let source = FilesystemStorage::new(from).with_context(|| {
+        format!(
+            "Failed to open cache at {} (stop a daemon or server using it first)",
+            from.display()
+        )
+    })?;
+
+    let mut last_report = 0;
+    let progress = |stats: &MigrateStats| {
+        let seen = stats.copied + stats.skipped + stats.missing + stats.corrupt;
+        if !json && seen >= last_report + MIGRATE_PROGRESS_EVERY {
+            last_report = seen;
+            println!(
+                "{} Migrated {} objects ({}), {} already present",
+                fabrik_prefix(),
+                stats.copied,
+                format_size(stats.bytes),
+                stats.skipped
+            );
+        }
+    };
+
+    let stats = if to.starts_with("http://") || to.starts_with("https://") {
+        let target = CacheTarget::Daemon {
+            client: reqwest::Client::builder()
+                .build()
+                .context("Failed to create HTTP client")?,
+            url: to.trim_end_matches('/').to_string(),
+        };
+        let hashes: Vec<String> = source.list_ids()?.iter().map(hex::encode).collect();
+        let missing: HashSet<String> = target.find_missing(&hashes).await?.into_iter().collect();
+
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::block_in_place(|| {
+            source.migrate_with(
+                |object| {
+                    let hash = hex::encode(&object.id);
+                    if !missing.contains(&hash) {
+                        return Ok(false);
+                    }
+                    runtime
+                        .block_on(target.store(
+                            &hash,
+                            object.data.clone(),
+                            object.tags.as_ref().and_then(|tags| tags.origin),
+                        ))
+                        .map_err(|e| FabrikError::unavailable(format!("{:#}", e)))?;
+                    Ok(true)
+                },
+                progress,
+            )
+        })?
+    } else {
+        let to = Path::new(to);
+        migrate::check_distinct(from, to)?;
+        let target = FilesystemStorage::new(to)
+            .with_context(|| format!("Failed to open cache at {}", to.display()))?;
+        tokio::task::block_in_place(|| source.migrate_to(&target, progress))?
+    };
+
+    if json {
+        let output = MigrateOutput {
+            copied: stats.copied,
+            copied_bytes: stats.bytes,
+            already_present: stats.skipped,
+            missing: stats.missing,
+            corrupt: stats.corrupt,
+        };
+        println!("{}", serde_json::to_string(&output)?);
+    } else {
+        println!(
+            "{} Migrated {} objects ({}) to {}, {} already present",
+            fabrik_prefix(),
+            stats.copied,
+            format_size(stats.bytes),
+            to,
+            stats.skipped
+        );
+        if stats.missing + stats.corrupt > 0 {
+            println!(
+                "{} Left behind {} objects missing from the source and {} corrupt ones",
+                fabrik_prefix(),
+                stats.missing,
+                stats.corrupt
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Hashes listed in a warm manifest, deduplicated in order
+fn read_manifest(path: &Path) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
+    let manifest: WarmManifest = serde_json::from_str(&content).with_context(|| {
+        format!(
+            "Invalid manifest {} (expected a JSON array of hashes or {{\"hashes\": [...]}})",
+            path.display()
+        )
+    })?;
+    let (WarmManifest::Hashes(hashes) | WarmManifest::Object { hashes }) = manifest;
+
+    let mut seen = HashSet::new();
+    let mut unique = Vec::with_capacity(hashes.len());
+    for hash in hashes {
+        let hash = hash.trim().to_ascii_lowercase();
+        if hash.is_empty() || hex::decode(&hash).is_err() {
+            anyhow::bail!("Invalid hash in manifest: {:?}", hash);
+        }
+        if seen.insert(hash.clone()) {
+            unique.push(hash);
+        }
+    }
+    Ok(unique)
+}
+
+/// Daemon running for the config at `config_path`, if any
+fn running_daemon(config_path: Option<&Path>) -> Result<Option<DaemonState>> {
+    let Some(config_path) = config_path else {
+        return Ok(None);
+    };
+    DaemonState::load_running(&hash_config(config_path)?)
+}
+
+/// Fetch one artifact from the upstreams and store it in the target
+async fn prefetch(remote: &RemoteCache, target: &CacheTarget, hash: &str) -> Result<Fetched> {
+    let Some((data, upstream)) = remote.fetch_artifact(hash).await? else {
+        return Ok(Fetched::NotFound);
+    };
+    let bytes = data.len() as u64;
+    target.store(hash, data, Some(Origin::Upstream)).await?;
+    Ok(Fetched::Stored { bytes, upstream })
+}
+
+impl CacheTarget {
+    /// Hashes the target doesn't have yet
+    async fn find_missing(&self, hashes: &[String]) -> Result<Vec<String>> {
+        match self {
+            CacheTarget::Daemon { client, url } => {
+                let mut missing = Vec::new();
+                for batch in hashes.chunks(WARM_BATCH) {
+                    let response = client
+                        .post(&format!("{}/api/v1/artifacts/find-missing", url))
+                        .json(&serde_json::json!({ "hashes": batch }))
+                        .send()
+                        .await
+                        .context("Failed to reach the daemon")?;
+                    if !response.status().is_success() {
+                        anyhow::bail!("Daemon returned HTTP {}", response.status().as_u16());
+                    }
+                    let response: FindMissingResponse = response
+                        .json()
+                        .await
+                        .context("Invalid find-missing response from the daemon")?;
+                    missing.extend(response.missing);
+                }
+                Ok(missing)
+            }
+            CacheTarget::Local(storage) => {
+                let ids: Vec<Vec<u8>> = hashes.iter().map(hex::decode).collect::<Result<_, _>>()?;
+                let exists = storage.exists_many(&ids)?;
+                Ok(hashes
+                    .iter()
+                    .zip(exists)
+                    .filter(|(_, exists)| !exists)
+                    .map(|(hash, _)| hash.clone())
+                    .collect())
+            }
+        }
+    }
+
+    /// Store an artifact, tagged with where it came from
+    async fn store(&self, hash: &str, data: Vec<u8>, origin: Option<Origin>) -> Result<()> {
+        match self {
+            CacheTarget::Daemon { client, url } => {
+                let mut request = client
+                    .put(&format!("{}/api/v1/artifacts/{}", url, hash))
+                    .body(data);
+                if let Some(origin) = origin {
+                    request = request.header(ORIGIN_HEADER, origin.to_string());
+                }
+                let response = request.send().await.context("Failed to reach the daemon")?;
+                if !response.status().is_success() {
+                    anyhow::bail!("Daemon returned HTTP {}", response.status().as_u16());
+                }
+                Ok(())
+            }
+            CacheTarget::Local(storage) => {
+                let storage = storage.clone();
+                let id = hex::decode(hash)?;
+                tokio::task::spawn_blocking(move || {
+                    storage.put(&id, &data)?;
+                    if let Some(origin) = origin {
+                        let mut tags = storage.get_tags(&id)?.unwrap_or_default();
+                        tags.origin = Some(origin);
+                        storage.put_tags(&id, &tags)?;
+                    }
+                    Ok::<(), FabrikError>(())
+                })
+                .await?
+                .context("Failed to store artifact")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_manifest() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let manifest = temp.path().join("manifest.json");
+
+        std::fs::write(&manifest, r#"["ABCD", "0123", "abcd"]"#).unwrap();
+        assert_eq!(read_manifest(&manifest).unwrap(), vec!["abcd", "0123"]);
+
+        std::fs::write(&manifest, r#"{"hashes": ["ff"]}"#).unwrap();
+        assert_eq!(read_manifest(&manifest).unwrap(), vec!["ff"]);
+
+        std::fs::write(&manifest, r#"["not-hex"]"#).unwrap();
+        assert!(read_manifest(&manifest).is_err());
+        std::fs::write(&manifest, r#"{"files": []}"#).unwrap();
+        assert!(read_manifest(&manifest).is_err());
+    }
+}
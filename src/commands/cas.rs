@@ -5,9 +5,15 @@ use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::cli::{CasArgs, CasCommand};
-use crate::cli_utils::fabrik_prefix;
+use crate::cli_utils::{
+    copy_to_stdout, fabrik_prefix, json_output, spool_stdin, ErrorCategory, STDIO,
+};
+use crate::error::FabrikError;
 use crate::eviction::EvictionConfig;
-use crate::storage::{default_cache_dir, FilesystemStorage, Storage};
+use crate::hashing::{HashAlgorithm, HashingReader};
+use crate::storage::{
+    bundle, default_cache_dir, labels, open_storage, tree, Labels, ObjectInfo, Storage,
+};
 
 // JSON output structures
 #[derive(Serialize, Deserialize)]
@@ -23,6 +29,30 @@ struct PutOutput {
     hash: String,
     size_bytes: usize,
     success: bool,
+    #[serde(default, skip_serializing_if = "Labels::is_empty")]
+    labels: Labels,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PutDirOutput {
+    root: String,
+    files: u64,
+    directories: u64,
+    symlinks: u64,
+    size_bytes: u64,
+    stored: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GetTreeOutput {
+    root: String,
+    output_path: String,
+    files: u64,
+    directories: u64,
+    symlinks: u64,
+    size_bytes: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -38,9 +68,33 @@ struct DeleteOutput {
 }
 
 #[derive(Serialize, Deserialize)]
+struct DeleteLabelledOutput {
+    labels: Labels,
+    deleted: u64,
+    hashes: Vec<String>,
+}
+
+#[derive(Serialize)]
 struct InfoOutput {
     hash: String,
+    #[serde(flatten)]
+    info: ObjectInfo,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportOutput {
+    output_path: String,
+    blobs: u64,
+    size_bytes: u64,
+    missing: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ImportOutput {
+    bundle_path: String,
+    imported: u64,
     size_bytes: u64,
+    skipped: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -59,7 +113,13 @@ pub async fn run(args: &CasArgs) -> Result<()> {
 
     // Use default eviction config for CLI commands
     let eviction_config = EvictionConfig::default();
-    let storage = FilesystemStorage::with_eviction(&cache_dir, Some(eviction_config))?;
+    let storage = open_storage(
+        &cache_dir,
+        args.config_metadata_backend.parse()?,
+        args.config_embedded_max_objects,
+        Some(eviction_config),
+    )?;
+    let storage = storage.as_ref();
 
     match &args.command {
         CasCommand::Get {
@@ -67,94 +127,208 @@ pub async fn run(args: &CasArgs) -> Result<()> {
             output,
             verbose,
             json,
-        } => get(&storage, hash, output.as_deref(), *verbose, *json).await,
+        } => {
+            get(
+                storage,
+                hash,
+                output.as_deref(),
+                *verbose,
+                json_output(*json),
+            )
+            .await
+        }
+        CasCommand::Cat { hash } => get(storage, hash, None, false, false).await,
         CasCommand::Put {
             file,
             hash,
+            tags,
+            ttl,
+            expires_at,
             verbose,
             json,
-        } => put(&storage, file, hash.as_deref(), *verbose, *json).await,
-        CasCommand::Exists { hash, json } => exists(&storage, hash, *json).await,
-        CasCommand::Delete { hash, force, json } => delete(&storage, hash, *force, *json).await,
-        CasCommand::Info { hash, json } => info(&storage, hash, *json).await,
-        CasCommand::List { verbose, json } => list(&storage, *verbose, *json).await,
-        CasCommand::Stats { json } => stats(&storage, *json).await,
+        } => {
+            let labels = labels::parse_all(tags)?;
+            let expires_at = parse_expiry(ttl.as_deref(), expires_at.as_deref())?;
+            put(
+                storage,
+                &cache_dir,
+                file,
+                args.config_hash_algorithm,
+                hash.as_deref(),
+                &labels,
+                expires_at,
+                *verbose,
+                json_output(*json),
+            )
+            .await
+        }
+        CasCommand::PutDir { dir, json } => {
+            put_dir(storage, dir, args.config_hash_algorithm, json_output(*json)).await
+        }
+        CasCommand::GetTree { hash, output, json } => {
+            get_tree(storage, hash, output, json_output(*json)).await
+        }
+        CasCommand::Exists { hash, json } => exists(storage, hash, json_output(*json)).await,
+        CasCommand::Delete {
+            hash,
+            tags,
+            force,
+            json,
+        } => match hash {
+            Some(hash) => delete(storage, hash, *force, json_output(*json)).await,
+            None => {
+                delete_labelled(
+                    storage,
+                    &labels::parse_all(tags)?,
+                    *force,
+                    json_output(*json),
+                )
+                .await
+            }
+        },
+        CasCommand::Info { hash, json } => info(storage, hash, json_output(*json)).await,
+        CasCommand::List {
+            tags,
+            verbose,
+            json,
+        } => {
+            list(
+                storage,
+                &labels::parse_all(tags)?,
+                *verbose,
+                json_output(*json),
+            )
+            .await
+        }
+        CasCommand::Stats { json } => stats(storage, json_output(*json)).await,
+        CasCommand::Export {
+            hashes,
+            output,
+            all,
+            since,
+            json,
+        } => {
+            export(
+                storage,
+                hashes,
+                output,
+                *all,
+                since.as_deref(),
+                json_output(*json),
+            )
+            .await
+        }
+        CasCommand::Import { bundle, json } => import(storage, bundle, json_output(*json)).await,
     }
 }
 
-/// Get a blob from the cache by content hash
+/// Get a blob from the cache by content hash, streamed to a file or stdout
 async fn get(
-    storage: &FilesystemStorage,
+    storage: &dyn Storage,
     hash: &str,
     output_path: Option<&str>,
     verbose: bool,
     json: bool,
 ) -> Result<()> {
-    use std::fs;
-    use std::io::{self, Write};
+    use std::fs::{self, File};
+    use std::io;
 
+    let output_path = output_path.filter(|path| *path != STDIO);
     if verbose && !json {
-        println!("{} Retrieving blob: {}", fabrik_prefix(), hash);
+        // Keep stdout for the blob itself
+        match output_path {
+            Some(_) => println!("{} Retrieving blob: {}", fabrik_prefix(), hash),
+            None => eprintln!("{} Retrieving blob: {}", fabrik_prefix(), hash),
+        }
     }
 
-    let data = storage
-        .get(hash.as_bytes())
-        .with_context(|| format!("Failed to retrieve blob: {}", hash))?;
-
-    if let Some(data) = data {
-        match output_path {
-            Some(path) => {
-                fs::write(path, &data).with_context(|| format!("Failed to write to: {}", path))?;
-
-                if json {
-                    let output = GetOutput {
-                        hash: hash.to_string(),
-                        output_path: path.to_string(),
-                        size_bytes: data.len(),
-                        success: true,
-                    };
-                    println!("{}", serde_json::to_string(&output)?);
-                } else {
-                    println!(
-                        "{} Blob retrieved: {} ({} bytes)",
-                        fabrik_prefix(),
-                        hash,
-                        data.len()
-                    );
-                    println!("{} Written to: {}", fabrik_prefix(), path);
-                }
-            }
-            None => {
-                // Write to stdout
-                io::stdout().write_all(&data)?;
-                io::stdout().flush()?;
-            }
+    let mut reader = storage
+        .open(hash.as_bytes())
+        .with_context(|| format!("Failed to retrieve blob: {}", hash))?
+        .ok_or_else(|| FabrikError::not_found(format!("Blob not found: {}", hash)))?;
+
+    let Some(path) = output_path else {
+        copy_to_stdout(&mut reader).context("Failed to write to stdout")?;
+        return Ok(());
+    };
+
+    let mut file = File::create(path).with_context(|| format!("Failed to write to: {}", path))?;
+    let size = match io::copy(&mut reader, &mut file) {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = fs::remove_file(path);
+            return Err(e).with_context(|| format!("Failed to write to: {}", path));
         }
-        Ok(())
+    };
+
+    if json {
+        let output = GetOutput {
+            hash: hash.to_string(),
+            output_path: path.to_string(),
+            size_bytes: size as usize,
+            success: true,
+        };
+        println!("{}", serde_json::to_string(&output)?);
     } else {
-        anyhow::bail!("Blob not found: {}", hash);
+        println!(
+            "{} Blob retrieved: {} ({} bytes)",
+            fabrik_prefix(),
+            hash,
+            size
+        );
+        println!("{} Written to: {}", fabrik_prefix(), path);
     }
+    Ok(())
 }
 
-/// Put a file into the cache (returns content hash)
+/// Put a file, or stdin with `-`, into the cache (returns content hash)
+///
+/// The input is streamed into the cache. From stdin, stdout only gets the hash (e.g.
+/// `tar cz dir | fabrik cas put -`).
+#[allow(clippy::too_many_arguments)]
 async fn put(
-    storage: &FilesystemStorage,
+    storage: &dyn Storage,
+    cache_dir: &std::path::Path,
     input_path: &str,
+    hash_algorithm: HashAlgorithm,
     expected_hash: Option<&str>,
+    labels: &Labels,
+    expires_at: Option<i64>,
     verbose: bool,
     json: bool,
 ) -> Result<()> {
-    use sha2::{Digest, Sha256};
-    use std::fs;
-
-    let data =
-        fs::read(input_path).with_context(|| format!("Failed to read file: {}", input_path))?;
-    let data_len = data.len();
-
-    // Compute hash
-    let mut hasher = Sha256::new();
-    hasher.update(&data);
-    let computed_hash = format!("{:x}", hasher.finalize());
+    use std::fs::{self, File};
+    use std::io;
+    use std::path::Path;
+
+    let stdin = input_path == STDIO;
+    // Progress goes to stderr when stdout is reserved for the hash
+    let status = |line: String| {
+        if stdin {
+            eprintln!("{}", line)
+        } else {
+            println!("{}", line)
+        }
+    };
+
+    // Stdin is spooled to a file first: its hash is only known once it's all read
+    let spooled;
+    let (path, computed_hash) = if stdin {
+        let (file, hash) =
+            spool_stdin(cache_dir, hash_algorithm).context("Failed to read stdin")?;
+        spooled = file;
+        (spooled.path(), hash)
+    } else {
+        let file = File::open(input_path)
+            .with_context(|| format!("Failed to read file: {}", input_path))?;
+        let mut reader = HashingReader::new(file, hash_algorithm);
+        io::copy(&mut reader, &mut io::sink())
+            .with_context(|| format!("Failed to read file: {}", input_path))?;
+        (Path::new(input_path), hex::encode(reader.finalize()))
+    };
+    let data_len = fs::metadata(path)
+        .with_context(|| format!("Failed to read file: {}", input_path))?
+        .len();
 
     // Verify if hash was provided
     if let Some(expected) = expected_hash {
@@ -167,37 +341,130 @@ async fn put(
         }
 
         if verbose && !json {
-            println!("{} Hash verified: {}", fabrik_prefix(), expected);
+            status(format!("{} Hash verified: {}", fabrik_prefix(), expected));
         }
     } else if verbose && !json {
-        println!("{} Computed hash: {}", fabrik_prefix(), computed_hash);
+        status(format!(
+            "{} Computed hash: {}",
+            fabrik_prefix(),
+            computed_hash
+        ));
     }
 
     if verbose && !json {
-        println!("{} Storing blob: {}", fabrik_prefix(), computed_hash);
+        status(format!(
+            "{} Storing blob: {}",
+            fabrik_prefix(),
+            computed_hash
+        ));
     }
 
     storage
-        .put(computed_hash.as_bytes(), &data)
+        .put_file(computed_hash.as_bytes(), path, expires_at)
         .with_context(|| format!("Failed to store blob: {}", computed_hash))?;
+    if !labels.is_empty() {
+        storage
+            .add_labels(computed_hash.as_bytes(), labels)
+            .with_context(|| format!("Failed to label blob: {}", computed_hash))?;
+    }
 
     if json {
         let output = PutOutput {
             hash: computed_hash,
-            size_bytes: data_len,
+            size_bytes: data_len as usize,
             success: true,
+            labels: labels.clone(),
+            expires_at,
         };
         println!("{}", serde_json::to_string(&output)?);
+    } else if stdin {
+        println!("{}", computed_hash);
+        if verbose {
+            status(format!("{} Size: {} bytes", fabrik_prefix(), data_len));
+        }
     } else {
         println!("{} Blob stored: {}", fabrik_prefix(), computed_hash);
         println!("{} Size: {} bytes", fabrik_prefix(), data_len);
+        if let Some(expires_at) = expires_at {
+            println!("{} Expires: {}", fabrik_prefix(), format_time(expires_at));
+        }
+        if !labels.is_empty() {
+            println!("{} Labels: {}", fabrik_prefix(), format_labels(labels));
+        }
+    }
+
+    Ok(())
+}
+
+/// Store a directory as a tree of blobs
+async fn put_dir(
+    storage: &dyn Storage,
+    dir: &str,
+    hash_algorithm: HashAlgorithm,
+    json: bool,
+) -> Result<()> {
+    let stats = tree::put_dir(storage, std::path::Path::new(dir), hash_algorithm)
+        .with_context(|| format!("Failed to store directory: {}", dir))?;
+
+    if json {
+        let output = PutDirOutput {
+            root: stats.root,
+            files: stats.files,
+            directories: stats.directories,
+            symlinks: stats.symlinks,
+            size_bytes: stats.bytes,
+            stored: stats.stored,
+        };
+        println!("{}", serde_json::to_string(&output)?);
+    } else {
+        println!("{} Directory stored: {}", fabrik_prefix(), stats.root);
+        println!(
+            "{} {} files, {} directories, {} symlinks ({:.2} MB, {} new blobs)",
+            fabrik_prefix(),
+            stats.files,
+            stats.directories,
+            stats.symlinks,
+            stats.bytes as f64 / 1_000_000.0,
+            stats.stored
+        );
+    }
+
+    Ok(())
+}
+
+/// Rebuild a directory stored with `put-dir`
+async fn get_tree(storage: &dyn Storage, hash: &str, output_path: &str, json: bool) -> Result<()> {
+    let stats = tree::get_tree(storage, hash, std::path::Path::new(output_path))
+        .with_context(|| format!("Failed to rebuild tree: {}", hash))?;
+
+    if json {
+        let output = GetTreeOutput {
+            root: hash.to_string(),
+            output_path: output_path.to_string(),
+            files: stats.files,
+            directories: stats.directories,
+            symlinks: stats.symlinks,
+            size_bytes: stats.bytes,
+        };
+        println!("{}", serde_json::to_string(&output)?);
+    } else {
+        println!(
+            "{} Tree rebuilt: {} ({} files, {} directories, {} symlinks, {:.2} MB)",
+            fabrik_prefix(),
+            hash,
+            stats.files,
+            stats.directories,
+            stats.symlinks,
+            stats.bytes as f64 / 1_000_000.0
+        );
+        println!("{} Written to: {}", fabrik_prefix(), output_path);
     }
 
     Ok(())
 }
 
 /// Check if a blob exists in the cache
-async fn exists(storage: &FilesystemStorage, hash: &str, json: bool) -> Result<()> {
+async fn exists(storage: &dyn Storage, hash: &str, json: bool) -> Result<()> {
     let exists = storage
         .exists(hash.as_bytes())
         .with_context(|| format!("Failed to check existence: {}", hash))?;
@@ -208,18 +475,22 @@ async fn exists(storage: &FilesystemStorage, hash: &str, json: bool) -> Result<(
             exists,
         };
         println!("{}", serde_json::to_string(&output)?);
-        std::process::exit(if exists { 0 } else { 1 });
+        std::process::exit(if exists {
+            0
+        } else {
+            ErrorCategory::Miss.exit_code().into()
+        });
     } else if exists {
         println!("{} Blob exists: {}", fabrik_prefix(), hash);
         std::process::exit(0);
     } else {
         println!("{} Blob not found: {}", fabrik_prefix(), hash);
-        std::process::exit(1);
+        std::process::exit(ErrorCategory::Miss.exit_code().into());
     }
 }
 
 /// Delete a blob from the cache
-async fn delete(storage: &FilesystemStorage, hash: &str, force: bool, json: bool) -> Result<()> {
+async fn delete(storage: &dyn Storage, hash: &str, force: bool, json: bool) -> Result<()> {
     use std::io::{self, Write};
 
     if !force && !json {
@@ -252,27 +523,82 @@ async fn delete(storage: &FilesystemStorage, hash: &str, force: bool, json: bool
     Ok(())
 }
 
-/// Show information about a cached blob
-async fn info(storage: &FilesystemStorage, hash: &str, json: bool) -> Result<()> {
-    // Check if blob exists
-    let exists = storage
-        .exists(hash.as_bytes())
-        .with_context(|| format!("Failed to check existence: {}", hash))?;
+/// Delete every blob carrying all of `labels`
+async fn delete_labelled(
+    storage: &dyn Storage,
+    labels: &Labels,
+    force: bool,
+    json: bool,
+) -> Result<()> {
+    use std::io::{self, Write};
 
-    if !exists {
-        anyhow::bail!("Blob not found: {}", hash);
+    let ids = storage
+        .find_by_labels(labels)
+        .context("Failed to find labelled blobs")?;
+    let hashes: Vec<String> = ids.iter().map(hex::encode).collect();
+
+    if ids.is_empty() && !json {
+        println!(
+            "{} No blobs labelled {}",
+            fabrik_prefix(),
+            format_labels(labels)
+        );
+        return Ok(());
     }
 
-    // Get size
-    let size = storage
-        .size(hash.as_bytes())
-        .with_context(|| format!("Failed to get size: {}", hash))?
-        .ok_or_else(|| anyhow::anyhow!("Blob not found: {}", hash))?;
+    if !force && !json {
+        print!(
+            "Delete {} blobs labelled {}? [y/N]: ",
+            ids.len(),
+            format_labels(labels)
+        );
+        io::stdout().flush()?;
+
+        let mut response = String::new();
+        io::stdin().read_line(&mut response)?;
+
+        if !response.trim().eq_ignore_ascii_case("y") {
+            println!("{} Deletion cancelled.", fabrik_prefix());
+            return Ok(());
+        }
+    }
+
+    for (id, hash) in ids.iter().zip(&hashes) {
+        storage
+            .delete(id)
+            .with_context(|| format!("Failed to delete blob: {}", hash))?;
+    }
+
+    if json {
+        let output = DeleteLabelledOutput {
+            labels: labels.clone(),
+            deleted: ids.len() as u64,
+            hashes,
+        };
+        println!("{}", serde_json::to_string(&output)?);
+    } else {
+        println!(
+            "{} Deleted {} blobs labelled {}",
+            fabrik_prefix(),
+            ids.len(),
+            format_labels(labels)
+        );
+    }
+
+    Ok(())
+}
+
+/// Show information about a cached blob
+async fn info(storage: &dyn Storage, hash: &str, json: bool) -> Result<()> {
+    let info = storage
+        .info(hash.as_bytes())
+        .with_context(|| format!("Failed to get info: {}", hash))?
+        .ok_or_else(|| FabrikError::not_found(format!("Blob not found: {}", hash)))?;
 
     if json {
         let output = InfoOutput {
             hash: hash.to_string(),
-            size_bytes: size,
+            info,
         };
         println!("{}", serde_json::to_string(&output)?);
     } else {
@@ -280,17 +606,102 @@ async fn info(storage: &FilesystemStorage, hash: &str, json: bool) -> Result<()>
         println!(
             "{} Size: {} bytes ({:.2} MB)",
             fabrik_prefix(),
-            size,
-            size as f64 / 1_000_000.0
+            info.size,
+            info.size as f64 / 1_000_000.0
         );
+        if let Some(created_at) = info.created_at {
+            println!("{} Created: {}", fabrik_prefix(), format_time(created_at));
+        }
+        if let Some(accessed_at) = info.accessed_at {
+            println!(
+                "{} Last access: {}",
+                fabrik_prefix(),
+                format_time(accessed_at)
+            );
+        }
+        if let Some(access_count) = info.access_count {
+            println!("{} Access count: {}", fabrik_prefix(), access_count);
+        }
+        if let Some(expires_at) = info.expires_at {
+            println!("{} Expires: {}", fabrik_prefix(), format_time(expires_at));
+        }
+        println!("{} Compression: {}", fabrik_prefix(), info.compression);
+        println!("{} Origin: {}", fabrik_prefix(), info.origin);
+        if let Some(tags) = info.tags {
+            if let Some(build_system) = tags.build_system {
+                println!("{} Build system: {}", fabrik_prefix(), build_system);
+            }
+            if let Some(machine) = tags.machine {
+                println!("{} Machine: {}", fabrik_prefix(), machine);
+            }
+        }
+        if !info.labels.is_empty() {
+            println!(
+                "{} Labels: {}",
+                fabrik_prefix(),
+                format_labels(&info.labels)
+            );
+        }
+        println!(
+            "{} Pinned: {}",
+            fabrik_prefix(),
+            if info.pinned { "yes" } else { "no" }
+        );
+        if info.pins > 0 {
+            println!("{} Pins: {} blobs", fabrik_prefix(), info.pins);
+        }
     }
 
     Ok(())
 }
 
-/// List all cached blobs
-async fn list(storage: &FilesystemStorage, verbose: bool, json: bool) -> Result<()> {
-    let ids = storage.list_ids()?;
+/// Expiry of a blob stored with `--ttl` or `--expires-at` (Unix seconds)
+fn parse_expiry(ttl: Option<&str>, expires_at: Option<&str>) -> Result<Option<i64>> {
+    if let Some(ttl) = ttl {
+        let ttl =
+            EvictionConfig::parse_ttl(ttl).with_context(|| format!("Invalid TTL: {}", ttl))?;
+        return Ok(Some(
+            chrono::Utc::now().timestamp().saturating_add(ttl as i64),
+        ));
+    }
+    let Some(expires_at) = expires_at else {
+        return Ok(None);
+    };
+    if let Ok(seconds) = expires_at.trim().parse::<i64>() {
+        return Ok(Some(seconds));
+    }
+    let time = chrono::DateTime::parse_from_rfc3339(expires_at.trim()).with_context(|| {
+        format!(
+            "Invalid expiry time: {} (expected RFC 3339 or Unix seconds)",
+            expires_at
+        )
+    })?;
+    Ok(Some(time.timestamp()))
+}
+
+/// Format Unix seconds as an RFC 3339 timestamp
+fn format_time(seconds: i64) -> String {
+    chrono::DateTime::from_timestamp(seconds, 0)
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_else(|| seconds.to_string())
+}
+
+/// Format labels as `key=value, key=value`
+fn format_labels(labels: &Labels) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// List all cached blobs, or those carrying all of `labels`
+async fn list(storage: &dyn Storage, labels: &Labels, verbose: bool, json: bool) -> Result<()> {
+    let ids = if labels.is_empty() {
+        storage.list_ids()?
+    } else {
+        storage.find_by_labels(labels)?
+    };
 
     if json {
         let blobs: Vec<_> = ids
@@ -334,7 +745,7 @@ async fn list(storage: &FilesystemStorage, verbose: bool, json: bool) -> Result<
 }
 
 /// Show CAS storage statistics
-async fn stats(storage: &FilesystemStorage, json: bool) -> Result<()> {
+async fn stats(storage: &dyn Storage, json: bool) -> Result<()> {
     let stats = storage.stats()?;
 
     if json {
@@ -364,3 +775,104 @@ async fn stats(storage: &FilesystemStorage, json: bool) -> Result<()> {
 
     Ok(())
 }
+
+/// Export blobs into a bundle file
+async fn export(
+    storage: &dyn Storage,
+    hashes: &[String],
+    output_path: &str,
+    all: bool,
+    since: Option<&str>,
+    json: bool,
+) -> Result<()> {
+    use std::fs::{self, File};
+    use std::io::BufWriter;
+
+    let ids: Vec<Vec<u8>> = if let Some(since) = since {
+        let window = EvictionConfig::parse_ttl(since)
+            .with_context(|| format!("Invalid --since duration: {}", since))?;
+        let cutoff = chrono::Utc::now().timestamp() - window as i64;
+        let mut ids = Vec::new();
+        for id in storage.list_ids()? {
+            if storage
+                .created_at(&id)?
+                .is_some_and(|created| created >= cutoff)
+            {
+                ids.push(id);
+            }
+        }
+        ids
+    } else if all {
+        storage.list_ids()?
+    } else {
+        hashes.iter().map(|hash| hash.as_bytes().to_vec()).collect()
+    };
+
+    let file =
+        File::create(output_path).with_context(|| format!("Failed to create: {}", output_path))?;
+    let stats = match bundle::export(storage, &ids, BufWriter::new(file)) {
+        Ok(stats) => stats,
+        Err(e) => {
+            let _ = fs::remove_file(output_path);
+            return Err(e).context("Failed to export blobs");
+        }
+    };
+
+    if json {
+        let output = ExportOutput {
+            output_path: output_path.to_string(),
+            blobs: stats.blobs,
+            size_bytes: stats.bytes,
+            missing: stats.missing,
+        };
+        println!("{}", serde_json::to_string(&output)?);
+    } else {
+        println!(
+            "{} Exported {} blobs ({:.2} MB) to {}",
+            fabrik_prefix(),
+            stats.blobs,
+            stats.bytes as f64 / 1_000_000.0,
+            output_path
+        );
+        if stats.missing > 0 {
+            println!(
+                "{} {} requested blobs were not in the cache",
+                fabrik_prefix(),
+                stats.missing
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Import the blobs of a bundle file
+async fn import(storage: &dyn Storage, bundle_path: &str, json: bool) -> Result<()> {
+    use std::fs::File;
+    use std::io::BufReader;
+
+    let file =
+        File::open(bundle_path).with_context(|| format!("Failed to open: {}", bundle_path))?;
+    let stats = bundle::import(storage, BufReader::new(file))
+        .with_context(|| format!("Failed to import bundle: {}", bundle_path))?;
+
+    if json {
+        let output = ImportOutput {
+            bundle_path: bundle_path.to_string(),
+            imported: stats.imported,
+            size_bytes: stats.bytes,
+            skipped: stats.skipped,
+        };
+        println!("{}", serde_json::to_string(&output)?);
+    } else {
+        println!(
+            "{} Imported {} blobs ({:.2} MB), {} already cached",
+            fabrik_prefix(),
+            stats.imported,
+            stats.bytes as f64 / 1_000_000.0,
+            stats.skipped
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,31 @@
+/// `fabrik __complete` command implementation
+///
+/// Prints cache hashes or keys matching a prefix, one per line, for the shell
+/// completion functions installed by `fabrik activate <shell>`.
+use anyhow::Result;
+
+use crate::cli::CompleteArgs;
+use crate::completion::{complete_from_daemon, complete_from_store};
+use crate::storage::default_cache_dir;
+
+pub fn run(args: CompleteArgs) -> Result<()> {
+    // Prefer the daemon's view of the cache; fall back to the local store. Errors are
+    // swallowed so a broken cache never breaks tab completion.
+    let candidates = match complete_from_daemon(args.kind, &args.prefix, args.limit) {
+        Ok(Some(candidates)) => candidates,
+        _ => {
+            let cache_dir = args
+                .config_cache_dir
+                .as_deref()
+                .map(std::path::PathBuf::from)
+                .unwrap_or_else(default_cache_dir);
+            complete_from_store(&cache_dir, args.kind, &args.prefix, args.limit).unwrap_or_default()
+        }
+    };
+
+    for candidate in candidates {
+        println!("{}", candidate);
+    }
+
+    Ok(())
+}
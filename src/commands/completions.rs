@@ -0,0 +1,75 @@
+/// `fabrik completions` and `fabrik man` command implementations
+///
+/// Generate shell completion scripts and man pages from the command-line definition, so
+/// packages can ship them straight from the binary.
+use anyhow::{Context, Result};
+use clap::CommandFactory;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cli::{Cli, CompletionsArgs, ManArgs};
+use crate::cli_utils::{copy_to_stdout, fabrik_prefix};
+use crate::completion::CompletionShell;
+
+pub fn completions(args: CompletionsArgs) -> Result<()> {
+    // Generated in memory: the generator panics on write errors, such as a closed pipe
+    let script = completion_script(args.shell);
+    copy_to_stdout(&mut script.as_slice()).context("Failed to write completions")?;
+    Ok(())
+}
+
+fn completion_script(shell: CompletionShell) -> Vec<u8> {
+    let mut cmd = Cli::command();
+    let name = cmd.get_name().to_string();
+    let mut script = Vec::new();
+    clap_complete::generate(shell.generator(), &mut cmd, name, &mut script);
+    script
+}
+
+pub fn man(args: ManArgs) -> Result<()> {
+    match &args.output {
+        Some(dir) => {
+            write_man_pages(dir)
+                .with_context(|| format!("Failed to write man pages to {}", dir.display()))?;
+            eprintln!("{} Wrote man pages to {}", fabrik_prefix(), dir.display());
+        }
+        None => {
+            let mut page = Vec::new();
+            clap_mangen::Man::new(Cli::command()).render(&mut page)?;
+            copy_to_stdout(&mut page.as_slice()).context("Failed to write man page")?;
+        }
+    }
+    Ok(())
+}
+
+/// Write `fabrik.1` and a page per subcommand (`fabrik-cas.1`, `fabrik-cas-put.1`, ...)
+fn write_man_pages(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    clap_mangen::generate_to(Cli::command(), dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+
+    #[test]
+    fn test_completions() {
+        for shell in CompletionShell::value_variants() {
+            let script = String::from_utf8(completion_script(*shell)).unwrap();
+            assert!(script.contains("put-dir"), "{:?}", shell);
+        }
+    }
+
+    #[test]
+    fn test_man_pages() {
+        let temp = tempfile::TempDir::new().unwrap();
+        write_man_pages(temp.path()).unwrap();
+
+        assert!(temp.path().join("fabrik.1").exists());
+        assert!(temp.path().join("fabrik-cas-put.1").exists());
+        // Hidden commands don't get pages
+        assert!(!temp.path().join("fabrik-__complete.1").exists());
+    }
+}
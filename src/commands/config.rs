@@ -1,48 +1,126 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
 use tracing::info;
 
 use crate::cli::ConfigCommands;
-use crate::config::FabrikConfig;
+use crate::config::{ConfigFormat, FabrikConfig};
+use crate::config_discovery;
+use crate::config_edit;
+use crate::config_layers::ConfigLayers;
+use crate::config_schema;
+use crate::upstream_routing::{namespaces, UpstreamRouter};
 
 pub fn run(command: ConfigCommands) -> Result<()> {
     match command {
-        ConfigCommands::Validate { path } => validate(&path),
-        ConfigCommands::Generate { template } => generate(&template),
-        ConfigCommands::Show { config } => show(config),
+        ConfigCommands::Validate { path, strict } => validate(&path, strict),
+        ConfigCommands::Schema => schema(),
+        ConfigCommands::Generate { template, format } => generate(&template, &format),
+        ConfigCommands::Show { config, origin } => show(config, origin),
+        ConfigCommands::Get { key, config } => get(&key, config.as_deref()),
+        ConfigCommands::Set { key, value, config } => set(&key, &value, config.as_deref()),
     }
 }
 
-fn validate(path: &str) -> Result<()> {
+fn validate(path: &str, strict: bool) -> Result<()> {
     info!("Validating config file: {}", path);
 
-    let config = FabrikConfig::from_file(path)?;
+    if strict {
+        let issues = config_schema::check_strict(Path::new(path))?;
+        for issue in &issues {
+            match issue.line {
+                Some(_) => eprintln!("{}:{}", path, issue),
+                None => eprintln!("{}: {}", path, issue),
+            }
+        }
+        if !issues.is_empty() {
+            anyhow::bail!(
+                "Configuration file has {} issue(s) in strict mode: {}",
+                issues.len(),
+                path
+            );
+        }
+    }
+
+    let layers = ConfigLayers::load(&[PathBuf::from(path)])?;
+    let config = layers.config()?;
     config.validate()?;
 
+    // Every profile must give a valid configuration too
+    let profiles: Vec<String> = layers.profiles().map(str::to_string).collect();
+    for profile in &profiles {
+        let mut layers = layers.clone();
+        layers.apply_profile(profile)?;
+        layers
+            .config()
+            .and_then(|config| config.validate())
+            .with_context(|| format!("Invalid profile '{}'", profile))?;
+    }
+
     println!("✓ Configuration file is valid: {}", path);
     println!("\nSummary:");
     println!("  - Cache directory: {}", config.cache.dir);
     println!("  - Max cache size: {}", config.cache.max_size);
     println!("  - Eviction policy: {}", config.cache.eviction_policy);
     println!("  - Upstream layers: {}", config.upstream.len());
+    if !profiles.is_empty() {
+        println!("  - Profiles: {}", profiles.join(", "));
+    }
 
     for (i, upstream) in config.upstream.iter().enumerate() {
-        println!(
-            "    {}. {} (timeout: {})",
-            i + 1,
-            upstream.url,
-            upstream.timeout
-        );
+        if upstream.match_patterns.is_empty() {
+            println!(
+                "    {}. {} (timeout: {})",
+                i + 1,
+                upstream.url,
+                upstream.timeout
+            );
+        } else {
+            println!(
+                "    {}. {} (timeout: {}, match: {})",
+                i + 1,
+                upstream.url,
+                upstream.timeout,
+                upstream.match_patterns.join(", ")
+            );
+        }
+    }
+
+    if config.upstream.iter().any(|u| !u.match_patterns.is_empty()) {
+        let router = UpstreamRouter::new(&config.upstream)?;
+        println!("  - Routing by namespace:");
+        for namespace in namespaces::ALL {
+            let upstreams: Vec<&str> = router
+                .route(namespace, "")
+                .iter()
+                .map(|u| u.url.as_str())
+                .collect();
+            let target = if upstreams.is_empty() {
+                "local cache only".to_string()
+            } else {
+                upstreams.join(", ")
+            };
+            println!("    {} → {}", namespace, target);
+        }
     }
 
     Ok(())
 }
 
-fn generate(template: &str) -> Result<()> {
+fn schema() -> Result<()> {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&config_schema::schema())?
+    );
+    Ok(())
+}
+
+fn generate(template: &str, format: &str) -> Result<()> {
     info!("Generating example config for template: {}", template);
 
-    let config_toml = match template {
-        "exec" | "daemon" => FabrikConfig::example_exec(),
-        "server" => FabrikConfig::example_server(),
+    let format: ConfigFormat = format.parse()?;
+    let config_text = match template {
+        "exec" | "daemon" => FabrikConfig::example_exec(format),
+        "server" => FabrikConfig::example_server(format),
         _ => {
             anyhow::bail!(
                 "Unknown template: {}. Valid templates: exec, daemon, server",
@@ -51,20 +129,86 @@ fn generate(template: &str) -> Result<()> {
         }
     };
 
-    println!("{}", config_toml);
+    println!("{}", config_text);
 
     Ok(())
 }
 
-fn show(config_path: Option<String>) -> Result<()> {
-    use crate::config_discovery::load_config_with_discovery;
+fn show(config_path: Option<String>, origin: bool) -> Result<()> {
+    use crate::config_discovery::load_config_layers;
 
     info!("Showing effective configuration");
 
-    let config = load_config_with_discovery(config_path.as_deref())?.unwrap_or_default();
+    let mut layers = load_config_layers(config_path.as_deref())?.unwrap_or_default();
+    if !origin {
+        let config = layers.config()?;
+        println!("Effective Configuration:\n");
+        println!("{}", toml::to_string_pretty(&config)?);
+        return Ok(());
+    }
+
+    // Environment variables override the files for every command
+    layers.apply_env()?;
+    let values = layers.values()?;
+    let lines: Vec<String> = values
+        .iter()
+        .map(|(key, value, _)| format!("{} = {}", key, value))
+        .collect();
+    let width = lines.iter().map(String::len).max().unwrap_or(0);
 
     println!("Effective Configuration:\n");
-    println!("{}", toml::to_string_pretty(&config)?);
+    for (line, (_, _, origin)) in lines.iter().zip(&values) {
+        println!("{:<width$}  # {}", line, origin, width = width);
+    }
+
+    Ok(())
+}
+
+fn get(key: &str, config_path: Option<&str>) -> Result<()> {
+    let layers = config_discovery::load_config_layers(config_path)?.unwrap_or_default();
+    match config_edit::get(&layers.config()?, key)? {
+        Some(value) => {
+            println!("{}", config_edit::format(&value)?);
+            Ok(())
+        }
+        None => anyhow::bail!("'{}' is not set", key),
+    }
+}
+
+fn set(key: &str, value: &str, config_path: Option<&str>) -> Result<()> {
+    let path = match config_path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let current_dir = std::env::current_dir().context("Failed to get current directory")?;
+            config_discovery::discover_project_config(&current_dir)
+                .unwrap_or_else(|| current_dir.join("fabrik.toml"))
+        }
+    };
+    if ConfigFormat::from_path(&path) != ConfigFormat::Toml {
+        anyhow::bail!(
+            "Only TOML config files can be edited; edit {} by hand",
+            path.display()
+        );
+    }
+
+    // With --profile, the override goes to the profile's table
+    let key = match config_discovery::selected_profile() {
+        Some(profile) => format!("profile.{}.{}", profile, key),
+        None => key.to_string(),
+    };
+
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read config file: {}", path.display()))
+        }
+    };
+    let edited = config_edit::set(&content, &key, value)?;
+    std::fs::write(&path, edited)
+        .with_context(|| format!("Failed to write config file: {}", path.display()))?;
 
+    println!("✓ Set {} = {} in {}", key, value, path.display());
     Ok(())
 }
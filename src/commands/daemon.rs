@@ -1,8 +1,11 @@
 use anyhow::Result;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::signal;
 use tracing::info;
 
+use super::dry_run::Plan;
+use crate::auth::layer::{AuthLayer, TokenValidator};
 use crate::bazel::proto::bytestream::byte_stream_server::ByteStreamServer;
 use crate::bazel::proto::remote_execution::action_cache_server::ActionCacheServer;
 use crate::bazel::proto::remote_execution::capabilities_server::CapabilitiesServer;
@@ -11,32 +14,31 @@ use crate::bazel::{
     BazelActionCacheService, BazelByteStreamService, BazelCapabilitiesService, BazelCasService,
 };
 use crate::cli::DaemonArgs;
-use crate::config::FabrikConfig;
+use crate::config::{DaemonConfig, FabrikConfig};
+use crate::daemon_stats::{LiveStats, RequestStatsLayer};
+use crate::events::{EventBus, EventStorage};
 use crate::eviction::{spawn_background_eviction, BackgroundEvictionConfig, EvictionConfig};
-use crate::http::HttpServer;
+use crate::grpc_introspection;
+use crate::grpc_transport::GrpcTransport;
+use crate::http::{HttpServer, S3Credentials, S3Server};
+use crate::idle::{ActivityLayer, ActivityTracker};
 use crate::merger::MergedExecConfig;
+use crate::notifications::Notifier;
+use crate::quota::UploadLimits;
+use crate::retry::RetryPolicy;
 use crate::storage;
-use tonic::transport::Server;
+use crate::unix_socket;
 
 pub async fn run(args: DaemonArgs) -> Result<()> {
-    use crate::config_discovery::{discover_config, hash_config, DaemonState};
+    use crate::config_discovery::{
+        discover_config, hash_config, load_config_with_discovery, DaemonState,
+    };
 
     // Load config file with auto-discovery and track the path for daemon state
-    let (file_config, config_path_opt) = if let Some(config_path_str) = &args.config {
-        // Explicit path provided
-        let path = std::path::PathBuf::from(config_path_str);
-        (Some(FabrikConfig::from_file(&path)?), Some(path))
-    } else {
-        // Auto-discover by traversing up directory tree
-        let current_dir = std::env::current_dir()?;
-        if let Some(discovered_path) = discover_config(&current_dir)? {
-            (
-                Some(FabrikConfig::from_file(&discovered_path)?),
-                Some(discovered_path),
-            )
-        } else {
-            (None, None)
-        }
+    let file_config = load_config_with_discovery(args.config.as_deref())?;
+    let config_path_opt = match &args.config {
+        Some(path) => Some(std::path::PathBuf::from(path)),
+        None => discover_config(&std::env::current_dir()?)?,
     };
 
     // If we have a config file, compute hash for daemon identification
@@ -63,18 +65,64 @@ pub async fn run(args: DaemonArgs) -> Result<()> {
         config_write_through: args.config_write_through,
         config_read_through: args.config_read_through,
         config_offline: args.config_offline,
+        config_read_only: args.config_read_only,
+        config_hash_algorithm: args.config_hash_algorithm,
         config_log_level: args.config_log_level,
         config_metrics_port: args.config_metrics_port,
+        config_build_metadata: false,
+        config_build_metadata_file: None,
         export_env: false,
         env_prefix: String::new(),
+        in_process: false,
+        dry_run: false,
+        invocation_id: None,
         command: vec![],
     };
 
     let config = MergedExecConfig::merge(&exec_args, file_config.clone());
 
+    // Claim the config hash before opening storage, so two daemons never serve (and save
+    // state for) the same config. Held until the daemon exits.
+    let _lock = match (&daemon_state_info, args.dry_run) {
+        (Some((config_hash, _)), false) => match DaemonState::try_lock(config_hash)? {
+            Some(lock) => {
+                // Any state left behind belongs to a daemon that is gone
+                if let Some(stale) = DaemonState::load(config_hash)? {
+                    info!("Removing stale state of daemon PID {}", stale.pid);
+                    stale.cleanup()?;
+                }
+                Some(lock)
+            }
+            None => {
+                let holder = DaemonState::load(config_hash)
+                    .ok()
+                    .flatten()
+                    .map(|state| format!(" (PID {})", state.pid))
+                    .unwrap_or_default();
+                anyhow::bail!("A daemon is already running for this config{}", holder);
+            }
+        },
+        _ => None,
+    };
+
     // Check if Unix socket is configured (for Xcode)
     let socket_path = file_config.as_ref().and_then(|fc| fc.daemon.socket.clone());
 
+    // HTTP and gRPC listen on Unix sockets instead of TCP ports when configured
+    let (http_socket, grpc_socket) = adapter_sockets(
+        file_config.as_ref(),
+        daemon_state_info.as_ref().map(|(_, path)| path.as_path()),
+    );
+
+    if args.dry_run {
+        return dry_run(
+            &config,
+            file_config.as_ref(),
+            socket_path.as_deref(),
+            daemon_state_info,
+        );
+    }
+
     info!("Starting daemon mode");
     info!("Configuration:");
     info!("  Cache directory: {}", config.cache_dir);
@@ -82,12 +130,22 @@ pub async fn run(args: DaemonArgs) -> Result<()> {
     info!("  Eviction policy: {}", config.eviction_policy);
     info!("  Default TTL: {}", config.default_ttl);
     info!("  Upstream: {:?}", config.upstream);
+    if config.read_only {
+        info!("  Read-only: puts and deletes are rejected");
+    }
+    info!("  Hash algorithm: {}", config.hash_algorithm);
 
     if let Some(ref socket) = socket_path {
         info!("  Mode: Unix socket (Xcode)");
         info!("  Socket path: {}", socket);
     } else {
         info!("  Mode: TCP (HTTP + gRPC)");
+        if let Some(ref socket) = http_socket {
+            info!("  HTTP socket: {}", socket.display());
+        }
+        if let Some(ref socket) = grpc_socket {
+            info!("  gRPC socket: {}", socket.display());
+        }
     }
 
     // Initialize eviction configuration from merged config
@@ -95,25 +153,130 @@ pub async fn run(args: DaemonArgs) -> Result<()> {
         &config.max_cache_size,
         &config.eviction_policy,
         &config.default_ttl,
+    )?
+    .with_min_free_disk(
+        file_config
+            .as_ref()
+            .and_then(|fc| fc.cache.min_free_disk.as_deref()),
     )?;
 
-    // Initialize shared storage backend with eviction
-    let storage =
-        storage::create_storage_with_eviction(&config.cache_dir, eviction_config.clone())?;
+    // Initialize shared storage backend, with eviction unless it's served read-only
+    let storage = if config.read_only {
+        storage::open_read_only(&config.cache_dir)?
+    } else {
+        storage::create_storage_with_eviction(&config.cache_dir, eviction_config.clone())?
+    }
+    .with_hash_algorithm(config.hash_algorithm)
+    .with_dedup(file_config.as_ref().is_some_and(|fc| fc.cache.dedup));
+    // Keep large and cold objects in a second directory, e.g. on a bigger, slower disk
+    let storage = match &file_config {
+        Some(fc) => match fc.cache.tier_config()? {
+            Some(tiers) => storage.with_cold_tier(tiers)?,
+            None => storage,
+        },
+        None => storage,
+    };
     let storage = Arc::new(storage);
 
-    // Spawn background eviction task
-    let eviction_handle = {
-        let bg_config = BackgroundEvictionConfig::from_eviction_config(eviction_config);
-        spawn_background_eviction(storage.clone(), bg_config)
+    // Warm the metadata block cache so the first builds after a restart don't wait on disk
+    if let Some(cache) = file_config
+        .as_ref()
+        .map(|fc| &fc.cache)
+        .filter(|c| c.warmup)
+    {
+        let warmup = storage::WarmupConfig::parse(&cache.warmup_window, &cache.warmup_rate)?;
+        info!(
+            "Warming metadata cache in the background (window: {}, rate: {}/s)",
+            cache.warmup_window, cache.warmup_rate
+        );
+        storage.spawn_warmup(warmup)?;
+    }
+
+    // Re-hash stored objects in the background so bit rot is caught before it's served
+    if let Some(cache) = file_config
+        .as_ref()
+        .map(|fc| &fc.cache)
+        .filter(|c| c.scrub && !config.read_only)
+    {
+        let scrub = storage::ScrubConfig::parse(&cache.scrub_rate, &cache.scrub_bandwidth)?;
+        info!(
+            "Scrubbing cache in the background (rate: {}/hour, bandwidth: {}/s)",
+            cache.scrub_rate, cache.scrub_bandwidth
+        );
+        storage.spawn_scrub(scrub, Arc::new(storage::ScrubMetrics::new()))?;
+    }
+
+    // Reclaim the shard directories and metadata space left behind by eviction
+    if let Some(interval) = file_config
+        .as_ref()
+        .and_then(|fc| fc.cache.compact_interval.as_deref())
+        .filter(|_| !config.read_only)
+    {
+        info!("Compacting storage every {}", interval);
+        storage.spawn_compaction(storage::compaction::parse_interval(interval)?)?;
+    }
+
+    // Writes, reads and evictions, streamed to the subscribers of /api/v1/events
+    let events = Arc::new(EventBus::new());
+    let served_storage = Arc::new(EventStorage::new(storage.clone(), events.clone()));
+
+    // Spawn background eviction task (a read-only cache is never evicted)
+    let max_size_bytes = match config.read_only {
+        true => 0,
+        false => eviction_config.max_size_bytes,
+    };
+
+    // Webhook notifications of a nearly full cache and eviction storms
+    let mut notifier = None;
+    if let Some(fc) = &file_config {
+        if let Some(webhook) = Notifier::from_config(&fc.notifications)? {
+            let webhook = Arc::new(webhook.with_retry(RetryPolicy::from_config(&fc.retry)?));
+            webhook
+                .clone()
+                .spawn_monitor(storage.clone(), max_size_bytes, &events);
+            notifier = Some(webhook);
+        }
+    }
+
+    // JWT authentication of every server, when verification keys are configured
+    let validator = match &file_config {
+        Some(fc) => TokenValidator::from_config(&fc.auth, notifier)
+            .await?
+            .map(Arc::new),
+        None => None,
     };
-    info!("Background eviction task started");
+
+    let eviction_handle = (!config.read_only).then(|| {
+        let bg_config = BackgroundEvictionConfig::from_eviction_config(eviction_config)
+            .with_events(events.clone());
+        info!("Background eviction task started");
+        spawn_background_eviction(storage.clone(), bg_config)
+    });
+
+    // Requests of every server, the cache size and evictions, served to `fabrik top`
+    let stats = Arc::new(
+        LiveStats::new(max_size_bytes)
+            .with_evictions(eviction_handle.as_ref().map(|handle| handle.stats())),
+    );
+
+    // Upload size limits and daily quotas
+    let upload_limits = Arc::new(match &file_config {
+        Some(fc) => UploadLimits::from_config(&fc.limits)?,
+        None => UploadLimits::unlimited(),
+    });
+
+    // Health checking, reflection and transport tuning of the gRPC servers
+    let grpc_config = file_config
+        .as_ref()
+        .map(|fc| fc.grpc.clone())
+        .unwrap_or_default();
+    let grpc_transport = GrpcTransport::from_config(&grpc_config)?;
 
     // Initialize P2P manager if enabled
     let p2p_manager = if let Some(ref fc) = file_config {
         if fc.p2p.enabled {
             info!("P2P cache sharing is enabled");
-            let p2p = crate::p2p::P2PManager::new(fc.p2p.clone()).await?;
+            let p2p = crate::p2p::P2PManager::new(fc.p2p.clone(), grpc_config.clone()).await?;
             p2p.start().await?;
             info!("P2P services started successfully");
             Some(Arc::new(p2p))
@@ -124,56 +287,71 @@ pub async fn run(args: DaemonArgs) -> Result<()> {
         None
     };
 
+    // Requests on every server count as activity; the daemon shuts down once idle
+    let idle_timeout = match file_config {
+        Some(ref fc) => fc.daemon.idle_timeout()?,
+        None => DaemonConfig::default().idle_timeout()?,
+    };
+    let activity = Arc::new(ActivityTracker::new());
+
     // Start servers based on mode
     let mut handles = vec![];
     let mut actual_http_port = 0u16;
     let mut actual_grpc_port = 0u16;
+    let mut actual_s3_port: Option<u16> = None;
     let mut actual_socket_path: Option<std::path::PathBuf> = None;
 
     // Check if we should use Unix socket mode (for Xcode)
     #[cfg(unix)]
     if let Some(ref socket_path_str) = socket_path {
         // Unix socket mode: Create ONLY Unix socket gRPC server
-        use crate::xcode::proto::cas::casdb_service_server::CasdbServiceServer;
-        use crate::xcode::proto::keyvalue::key_value_db_server::KeyValueDbServer;
-        use crate::xcode::{CasService, KeyValueService};
-
-        // Resolve relative path to absolute (relative to config file directory)
-        let socket_path = if let Some((_, ref config_path)) = daemon_state_info {
-            let config_dir = config_path.parent().unwrap_or(std::path::Path::new("."));
-            config_dir.join(socket_path_str)
-        } else {
-            std::path::PathBuf::from(socket_path_str)
-        };
+        use crate::xcode::{cas_server, keyvalue_server, CasService, KeyValueService};
 
-        // Remove stale socket file if it exists
-        if socket_path.exists() {
-            info!("Removing stale socket file: {}", socket_path.display());
-            std::fs::remove_file(&socket_path)?;
-        }
+        let socket_path = unix_socket::resolve(
+            socket_path_str,
+            daemon_state_info.as_ref().map(|(_, path)| path.as_path()),
+        );
 
         info!(
             "Creating Unix socket server for Xcode at: {}",
             socket_path.display()
         );
 
-        // Create Unix socket listener
-        let unix_listener = tokio::net::UnixListener::bind(&socket_path)?;
+        // Create Unix socket listener (replacing a stale socket file)
+        let unix_listener = unix_socket::bind(&socket_path)?;
         actual_socket_path = Some(socket_path.clone());
 
         // Create Xcode gRPC services
-        let cas_service = CasService::new(storage.clone());
-        let keyvalue_service = KeyValueService::new(storage.clone());
+        let cas_service =
+            CasService::new(served_storage.clone()).with_upload_limits(upload_limits.clone());
+        let keyvalue_service =
+            KeyValueService::new(served_storage.clone()).with_upload_limits(upload_limits.clone());
 
         info!("Unix socket server listening on {}", socket_path.display());
 
         // Start Unix socket gRPC server
+        let grpc_config = grpc_config.clone();
+        let activity = activity.clone();
+        let validator = validator.clone();
+        let requests = stats.requests();
         handles.push(tokio::spawn(async move {
             use tokio_stream::wrappers::UnixListenerStream;
 
-            Server::builder()
-                .add_service(CasdbServiceServer::new(cas_service))
-                .add_service(KeyValueDbServer::new(keyvalue_service))
+            let introspection = grpc_introspection::routes(
+                &grpc_config,
+                crate::xcode::SERVICE_NAMES,
+                &[crate::xcode::proto::FILE_DESCRIPTOR_SET],
+            )
+            .await?;
+
+            grpc_transport
+                .server()
+                .layer(ActivityLayer::new(activity))
+                .layer(RequestStatsLayer::new(requests))
+                .layer(AuthLayer::new(validator))
+                .add_routes(introspection)
+                .add_service(cas_server(cas_service))
+                .add_service(keyvalue_server(keyvalue_service))
                 .serve_with_incoming(UnixListenerStream::new(unix_listener))
                 .await
                 .map_err(|e| anyhow::anyhow!("Unix socket gRPC server error: {}", e))
@@ -183,9 +361,10 @@ pub async fn run(args: DaemonArgs) -> Result<()> {
     }
 
     #[cfg(not(unix))]
-    if socket_path.is_some() {
+    if socket_path.is_some() || http_socket.is_some() || grpc_socket.is_some() {
         anyhow::bail!(
-            "Unix sockets are not supported on Windows. Remove [daemon] socket from config."
+            "Unix sockets are not supported on Windows. Remove [daemon] socket, http_socket \
+             and grpc_socket from config."
         );
     }
 
@@ -201,57 +380,173 @@ pub async fn run(args: DaemonArgs) -> Result<()> {
         // 1. HTTP server (for Metro, Gradle, Nx, TurboRepo)
         // Always start HTTP server in TCP mode
         {
-            let http_storage = storage.clone();
-
-            // Bind to port 0 to get an available port (or use config port if specified)
-            let (http_server, http_port, http_listener) =
-                HttpServer::new_with_port_zero(http_storage).await?;
-
-            actual_http_port = http_port;
-            info!("HTTP cache server bound to port {}", actual_http_port);
-
-            handles.push(tokio::spawn(async move {
-                http_server.run_with_listener(http_listener).await
-            }));
+            let http_storage = served_storage.clone();
+
+            let http_server = HttpServer::new(0, http_storage)
+                .with_upload_limits(upload_limits.clone())
+                .with_gradle_policy(
+                    file_config
+                        .as_ref()
+                        .map(|fc| fc.build_systems.gradle_machine_specific())
+                        .unwrap_or_default(),
+                )
+                .with_webdav(
+                    file_config
+                        .as_ref()
+                        .is_some_and(|fc| fc.http.webdav_enabled),
+                )
+                .with_activity(activity.clone())
+                .with_stats(stats.clone())
+                .with_events(events.clone())
+                .with_auth(validator.clone());
+
+            match http_socket {
+                #[cfg(unix)]
+                Some(ref socket) => {
+                    let listener = unix_socket::bind(socket)?;
+                    info!("HTTP cache server bound to {}", socket.display());
+                    handles.push(tokio::spawn(async move {
+                        http_server.run_with_listener(listener).await
+                    }));
+                }
+                _ => {
+                    // Bind to port 0 to get an available port
+                    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+                    actual_http_port = listener.local_addr()?.port();
+                    info!("HTTP cache server bound to port {}", actual_http_port);
+                    handles.push(tokio::spawn(async move {
+                        http_server.run_with_listener(listener).await
+                    }));
+                }
+            }
         }
 
         // 2. gRPC server (for Bazel, Fabrik protocol)
         // Always start gRPC server in daemon mode
         {
-            let grpc_storage = storage.clone();
-
-            // Bind to find an available port
-            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
-            actual_grpc_port = listener.local_addr()?.port();
+            let grpc_storage = served_storage.clone();
+            let grpc_limits = upload_limits.clone();
+            let grpc_activity = activity.clone();
+            let grpc_requests = stats.requests();
+            let grpc_validator = validator.clone();
+            let validate_outputs = file_config
+                .as_ref()
+                .is_none_or(|fc| fc.build_systems.bazel_validate_outputs());
+            let bazel_limits = Arc::new(
+                file_config
+                    .as_ref()
+                    .map(|fc| fc.build_systems.bazel_limits(config.hash_algorithm))
+                    .transpose()?
+                    .unwrap_or_default(),
+            );
+
+            // Bind the Unix socket, or find an available port
+            #[cfg(unix)]
+            let grpc_listener = grpc_socket.as_deref().map(unix_socket::bind).transpose()?;
+            if grpc_socket.is_none() {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+                actual_grpc_port = listener.local_addr()?.port();
+                // Drop the listener since tonic will bind again
+                drop(listener);
+            }
 
             // We need to convert TcpListener to the address for tonic
             // tonic doesn't support pre-bound listeners easily, so we'll use the port
-            let addr = format!("127.0.0.1:{}", actual_grpc_port).parse().unwrap();
+            let addr: std::net::SocketAddr =
+                format!("127.0.0.1:{}", actual_grpc_port).parse().unwrap();
 
-            // Drop the listener since tonic will bind again
-            drop(listener);
-
-            info!("Starting gRPC cache server on port {}", actual_grpc_port);
+            match grpc_socket {
+                Some(ref socket) => info!("Starting gRPC cache server on {}", socket.display()),
+                None => info!("Starting gRPC cache server on port {}", actual_grpc_port),
+            }
 
             handles.push(tokio::spawn(async move {
                 // Create Bazel gRPC services
-                let action_cache = BazelActionCacheService::new(grpc_storage.clone());
-                let cas = BazelCasService::new(grpc_storage.clone());
-                let bytestream = BazelByteStreamService::new(grpc_storage.clone());
-                let capabilities = BazelCapabilitiesService::new();
-
-                info!("gRPC server listening on {}", addr);
-
-                Server::builder()
+                let action_cache = BazelActionCacheService::new(grpc_storage.clone())
+                    .with_output_validation(validate_outputs)
+                    .with_limits(bazel_limits.clone());
+                let cas = BazelCasService::new(grpc_storage.clone())
+                    .with_upload_limits(grpc_limits.clone())
+                    .with_limits(bazel_limits.clone());
+                let bytestream = BazelByteStreamService::new(grpc_storage.clone())
+                    .with_upload_limits(grpc_limits.clone())
+                    .with_limits(bazel_limits.clone());
+                let capabilities = BazelCapabilitiesService::new()
+                    .with_limits(bazel_limits)
+                    .with_upload_limits(grpc_limits);
+
+                let introspection = grpc_introspection::routes(
+                    &grpc_config,
+                    crate::bazel::SERVICE_NAMES,
+                    &[crate::bazel::proto::FILE_DESCRIPTOR_SET],
+                )
+                .await?;
+
+                let router = grpc_transport
+                    .server()
+                    .layer(ActivityLayer::new(grpc_activity))
+                    .layer(RequestStatsLayer::new(grpc_requests))
+                    .layer(AuthLayer::new(grpc_validator))
+                    .add_routes(introspection)
                     .add_service(CapabilitiesServer::new(capabilities))
                     .add_service(ActionCacheServer::new(action_cache))
                     .add_service(ContentAddressableStorageServer::new(cas))
-                    .add_service(ByteStreamServer::new(bytestream))
+                    .add_service(ByteStreamServer::new(bytestream));
+
+                #[cfg(unix)]
+                if let Some(listener) = grpc_listener {
+                    use tokio_stream::wrappers::UnixListenerStream;
+                    return router
+                        .serve_with_incoming(UnixListenerStream::new(listener))
+                        .await
+                        .map_err(|e| anyhow::anyhow!("gRPC server error: {}", e));
+                }
+
+                info!("gRPC server listening on {}", addr);
+                router
                     .serve(addr)
                     .await
                     .map_err(|e| anyhow::anyhow!("gRPC server error: {}", e))
             }));
         }
+
+        // 3. S3-compatible API (for tools that only speak S3), when a port is configured
+        if let Some(s3_port) = config.s3_port {
+            let daemon_config = file_config
+                .as_ref()
+                .map(|fc| fc.daemon.clone())
+                .unwrap_or_default();
+            let credentials = match (daemon_config.s3_access_key, daemon_config.s3_secret_key) {
+                (Some(access_key), Some(secret_key)) => Some(S3Credentials {
+                    access_key,
+                    secret_key,
+                }),
+                (None, None) => None,
+                _ => anyhow::bail!("[daemon] s3_access_key and s3_secret_key must be set together"),
+            };
+
+            let listener = tokio::net::TcpListener::bind(("127.0.0.1", s3_port)).await?;
+            let port = listener.local_addr()?.port();
+            actual_s3_port = Some(port);
+            info!(
+                "S3 API bound to port {} ({})",
+                port,
+                if credentials.is_some() {
+                    "SigV4 authentication"
+                } else {
+                    "unauthenticated"
+                }
+            );
+
+            let s3_server = S3Server::new(served_storage.clone())
+                .with_upload_limits(upload_limits.clone())
+                .with_credentials(credentials)
+                .with_activity(activity.clone())
+                .with_request_stats(stats.requests());
+            handles.push(tokio::spawn(async move {
+                s3_server.run_with_listener(listener).await
+            }));
+        }
     } // End of TCP mode
 
     // Save daemon state with actual bound ports/socket BEFORE starting servers
@@ -262,7 +557,10 @@ pub async fn run(args: DaemonArgs) -> Result<()> {
             http_port: actual_http_port,
             grpc_port: actual_grpc_port,
             metrics_port: config.metrics_port,
+            s3_port: actual_s3_port,
             unix_socket: actual_socket_path,
+            http_socket: http_socket.filter(|_| !socket_configured),
+            grpc_socket: grpc_socket.filter(|_| !socket_configured),
             config_path,
         };
 
@@ -271,8 +569,17 @@ pub async fn run(args: DaemonArgs) -> Result<()> {
             None
         } else {
             info!("Daemon state saved with hash: {}", state.config_hash);
-            info!("  HTTP port: {}", state.http_port);
-            info!("  gRPC port: {}", state.grpc_port);
+            match state.http_socket {
+                Some(ref socket) => info!("  HTTP socket: {}", socket.display()),
+                None => info!("  HTTP port: {}", state.http_port),
+            }
+            match state.grpc_socket {
+                Some(ref socket) => info!("  gRPC socket: {}", socket.display()),
+                None => info!("  gRPC port: {}", state.grpc_port),
+            }
+            if let Some(s3_port) = state.s3_port {
+                info!("  S3 port: {}", s3_port);
+            }
             Some(state)
         }
     } else {
@@ -280,8 +587,20 @@ pub async fn run(args: DaemonArgs) -> Result<()> {
     };
 
     info!("Daemon started - waiting for shutdown signal");
+    match idle_timeout {
+        Some(timeout) => info!("Shutting down after {:?} without requests", timeout),
+        None => info!("Idle shutdown disabled"),
+    }
 
-    // Wait for shutdown signal (Ctrl+C or SIGTERM)
+    // Resolves once the daemon has been idle for the timeout (never when disabled)
+    let idle = async {
+        match idle_timeout {
+            Some(timeout) => activity.wait_until_idle(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    // Wait for shutdown signal (Ctrl+C or SIGTERM) or the idle timeout
     #[cfg(unix)]
     {
         tokio::select! {
@@ -295,18 +614,29 @@ pub async fn run(args: DaemonArgs) -> Result<()> {
             } => {
                 info!("Received SIGTERM, shutting down gracefully...");
             }
+            _ = idle => {
+                info!("Idle for {:?}, shutting down gracefully...", activity.idle_for());
+            }
         }
     }
 
     #[cfg(not(unix))]
     {
-        signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
-        info!("Received Ctrl+C, shutting down gracefully...");
+        tokio::select! {
+            _ = signal::ctrl_c() => {
+                info!("Received Ctrl+C, shutting down gracefully...");
+            }
+            _ = idle => {
+                info!("Idle for {:?}, shutting down gracefully...", activity.idle_for());
+            }
+        }
     }
 
     // Shutdown background eviction task first
     info!("Shutting down background eviction task...");
-    eviction_handle.shutdown().await;
+    if let Some(eviction_handle) = eviction_handle {
+        eviction_handle.shutdown().await;
+    }
 
     // Shutdown P2P services
     if let Some(p2p) = p2p_manager {
@@ -327,14 +657,13 @@ pub async fn run(args: DaemonArgs) -> Result<()> {
 
     // Cleanup daemon state
     if let Some(state) = state_opt {
-        // Remove Unix socket file if it exists
-        if let Some(ref socket_path) = state.unix_socket {
-            if socket_path.exists() {
-                if let Err(e) = std::fs::remove_file(socket_path) {
-                    tracing::warn!("Failed to remove socket file: {}", e);
-                } else {
-                    info!("Removed Unix socket: {}", socket_path.display());
-                }
+        // Remove Unix socket files if they exist
+        let sockets = [&state.unix_socket, &state.http_socket, &state.grpc_socket];
+        for socket_path in sockets.into_iter().flatten() {
+            if let Err(e) = unix_socket::remove(socket_path) {
+                tracing::warn!("Failed to remove socket file: {}", e);
+            } else {
+                info!("Removed Unix socket: {}", socket_path.display());
             }
         }
 
@@ -348,3 +677,129 @@ pub async fn run(args: DaemonArgs) -> Result<()> {
     info!("Daemon stopped");
     Ok(())
 }
+
+/// Sockets the HTTP and gRPC adapters listen on instead of TCP ports
+/// (`[daemon] http_socket`, `grpc_socket`)
+fn adapter_sockets(
+    file_config: Option<&FabrikConfig>,
+    config_path: Option<&Path>,
+) -> (Option<PathBuf>, Option<PathBuf>) {
+    let daemon = file_config.map(|fc| &fc.daemon);
+    let resolve =
+        |socket: Option<&String>| socket.map(|socket| unix_socket::resolve(socket, config_path));
+    (
+        resolve(daemon.and_then(|daemon| daemon.http_socket.as_ref())),
+        resolve(daemon.and_then(|daemon| daemon.grpc_socket.as_ref())),
+    )
+}
+
+/// Print what the daemon would serve and what `fabrik activate` would export for it
+/// (`--dry-run`)
+fn dry_run(
+    config: &MergedExecConfig,
+    file_config: Option<&FabrikConfig>,
+    socket_path: Option<&str>,
+    daemon_state_info: Option<(String, PathBuf)>,
+) -> Result<()> {
+    use crate::config_discovery::{populate_build_tool_env_vars, DaemonState, Endpoints};
+
+    let mut plan = Plan {
+        upstreams: config.upstream.clone(),
+        build_systems: config.build_systems.clone(),
+        ..Default::default()
+    };
+    let config_path = daemon_state_info.as_ref().map(|(_, path)| path.as_path());
+
+    // A configured socket replaces the TCP servers (see `run`)
+    let socket = socket_path.map(|socket| unix_socket::resolve(socket, config_path));
+    let (http_socket, grpc_socket) = match socket {
+        Some(_) => (None, None),
+        None => adapter_sockets(file_config, config_path),
+    };
+    match socket {
+        Some(ref socket) => plan
+            .listeners
+            .push(("unix socket", socket.display().to_string())),
+        None => {
+            plan.listeners.push(match http_socket {
+                Some(ref socket) => ("http socket", socket.display().to_string()),
+                None => ("http", Plan::local_address(0)),
+            });
+            plan.listeners.push(match grpc_socket {
+                Some(ref socket) => ("grpc socket", socket.display().to_string()),
+                None => ("grpc", Plan::local_address(0)),
+            });
+            if let Some(s3_port) = config.s3_port {
+                plan.listeners.push(("s3", Plan::local_address(s3_port)));
+            }
+        }
+    }
+    if let Some(p2p) = file_config
+        .map(|fc| &fc.p2p)
+        .filter(|p2p| p2p.enabled && p2p.advertise)
+    {
+        plan.listeners
+            .push(("p2p", format!("0.0.0.0:{}", p2p.bind_port)));
+    }
+
+    // Variables `fabrik activate` exports once the daemon runs
+    let address = Plan::local_address(0);
+    plan.env_vars = populate_build_tool_env_vars(&Endpoints {
+        http_url: http_socket.is_none().then(|| format!("http://{}", address)),
+        grpc_url: match grpc_socket {
+            Some(ref socket) => unix_socket::grpc_url(socket),
+            None => format!("grpc://{}", address),
+        },
+        http_socket,
+        s3_url: None,
+        unix_socket: socket.clone(),
+    })
+    .into_iter()
+    .collect();
+    if socket.is_none() && config.s3_port.is_some() {
+        let s3_address = Plan::local_address(config.s3_port.unwrap_or_default());
+        plan.env_vars.insert(
+            "FABRIK_S3_URL".to_string(),
+            format!("http://{}", s3_address),
+        );
+    }
+
+    let idle_timeout = match file_config {
+        Some(fc) => fc.daemon.idle_timeout()?,
+        None => DaemonConfig::default().idle_timeout()?,
+    };
+    plan.notes.push(match idle_timeout {
+        Some(timeout) => format!("Would shut down after {:?} without requests", timeout),
+        None => "Idle shutdown is disabled".to_string(),
+    });
+
+    match daemon_state_info {
+        Some((config_hash, config_path)) => {
+            let state_dir = DaemonState::state_dir_for(&config_hash);
+            plan.env_vars.insert(
+                "BAZELRC".to_string(),
+                state_dir.join("bazelrc").display().to_string(),
+            );
+            plan.env_vars
+                .insert("FABRIK_CONFIG_HASH".to_string(), config_hash.clone());
+            plan.notes
+                .push(format!("State would be saved in {}", state_dir.display()));
+            if let Some(state) = DaemonState::load(&config_hash)?.filter(|s| s.is_running()) {
+                plan.notes.push(format!(
+                    "A daemon is already running for this config (PID {})",
+                    state.pid
+                ));
+            }
+            plan.config_path = Some(config_path);
+            plan.config_hash = Some(config_hash);
+        }
+        None => plan.notes.push(
+            "Without a config file no state is saved, so `fabrik activate` and `fabrik exec` \
+             won't find the daemon"
+                .to_string(),
+        ),
+    }
+
+    plan.print();
+    Ok(())
+}
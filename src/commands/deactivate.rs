@@ -20,6 +20,7 @@ fn output_unset_commands() {
     if shell.contains("fish") {
         println!("set -e FABRIK_HTTP_URL 2>/dev/null");
         println!("set -e FABRIK_GRPC_URL 2>/dev/null");
+        println!("set -e FABRIK_S3_URL 2>/dev/null");
         println!("set -e FABRIK_UNIX_SOCKET 2>/dev/null");
         println!("set -e FABRIK_CONFIG_HASH 2>/dev/null");
         println!("set -e FABRIK_DAEMON_PID 2>/dev/null");
@@ -30,6 +31,7 @@ fn output_unset_commands() {
         // bash/zsh
         println!("unset FABRIK_HTTP_URL");
         println!("unset FABRIK_GRPC_URL");
+        println!("unset FABRIK_S3_URL");
         println!("unset FABRIK_UNIX_SOCKET");
         println!("unset FABRIK_CONFIG_HASH");
         println!("unset FABRIK_DAEMON_PID");
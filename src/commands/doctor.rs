@@ -1,196 +1,1187 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use serde_json::json;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use crate::auth::AuthProvider;
 use crate::cli::DoctorArgs;
+use crate::cli_utils::{fabrik_prefix, json_output};
+use crate::config::{AuthProvider as ConfigAuthProvider, FabrikConfig, UpstreamConfig};
+use crate::config_discovery::DaemonState;
+use crate::http::upstream_address;
 
-pub fn run(args: DoctorArgs) -> Result<()> {
-    println!("🔍 Fabrik Doctor - System Configuration Check\n");
+/// Maximum number of bytes collected from the end of each log file
+const MAX_LOG_BYTES: u64 = 256 * 1024;
 
-    let mut all_ok = true;
+/// Timeout for protocol probes against running daemons
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
 
-    // Check 1: Fabrik binary
-    if let Ok(exe_path) = env::current_exe() {
-        println!("✅ Fabrik binary found: {}", exe_path.display());
-        if args.verbose {
-            println!("   Version: {}", env!("CARGO_PKG_VERSION"));
+/// Longest a system clock may differ from the service's before JWTs are rejected as
+/// expired or not yet valid
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// Outcome of a diagnostic check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum CheckStatus {
+    Ok,
+    Info,
+    Warning,
+    Failed,
+}
+
+/// Result of one diagnostic check
+#[derive(Debug, Serialize)]
+struct Check {
+    name: String,
+    status: CheckStatus,
+    message: String,
+    /// What to do about a warning or failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fix: Option<String>,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: CheckStatus, message: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            message: message.into(),
+            fix: None,
         }
-    } else {
-        println!("❌ Could not determine Fabrik binary path");
-        all_ok = false;
     }
 
-    // Check 2: Shell detection
-    let shell = detect_shell();
-    match &shell {
-        Some(shell_name) => {
-            println!("✅ Shell detected: {}", shell_name);
-        }
-        None => {
-            println!("⚠️  Could not detect shell (SHELL env var not set)");
-        }
+    fn ok(name: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Ok, message)
     }
 
-    // Check 3: Shell integration
-    if let Some(shell_name) = &shell {
-        let integration_status = check_shell_integration(shell_name, args.verbose);
-        if integration_status {
-            println!("✅ Shell integration configured");
-        } else {
-            println!("❌ Shell integration NOT configured");
-            println!("   Run this command to set it up:");
-            println!();
-            match shell_name.as_str() {
-                "bash" => {
-                    println!("   echo 'eval \"$(fabrik activate bash)\"' >> ~/.bashrc");
-                    println!("   source ~/.bashrc");
-                }
-                "zsh" => {
-                    println!("   echo 'eval \"$(fabrik activate zsh)\"' >> ~/.zshrc");
-                    println!("   source ~/.zshrc");
-                }
-                "fish" => {
-                    println!(
-                        "   echo 'fabrik activate fish | source' >> ~/.config/fish/config.fish"
-                    );
-                    println!("   source ~/.config/fish/config.fish");
-                }
-                _ => {
-                    println!("   fabrik activate --help");
-                }
-            }
-            all_ok = false;
-        }
+    fn info(name: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Info, message)
     }
 
-    // Check 4: State directory
-    let state_dir = fabrik::xdg::daemon_state_dir();
+    fn warning(name: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Warning, message)
+    }
 
-    if state_dir.exists() {
-        let daemon_count = fs::read_dir(&state_dir)
-            .map(|entries| entries.count())
-            .unwrap_or(0);
-        println!("✅ State directory exists: {}", state_dir.display());
-        if args.verbose {
-            println!("   Active daemons: {}", daemon_count);
+    fn failed(name: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Failed, message)
+    }
+
+    fn with_fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+
+    /// `fix` as the fix of a failure that doesn't have one yet
+    #[cfg(unix)]
+    fn with_fix_if_failed(self, fix: &str) -> Self {
+        match (self.status, &self.fix) {
+            (CheckStatus::Failed, None) => self.with_fix(fix),
+            _ => self,
         }
-    } else {
+    }
+}
+
+pub async fn run(args: DoctorArgs) -> Result<()> {
+    if let Some(output) = &args.bundle {
+        return create_bundle(&args, output.as_deref());
+    }
+
+    let json = json_output(args.json);
+    if !json {
+        println!("🔍 Fabrik Doctor - System Configuration Check\n");
+    }
+
+    let checks = run_checks(&args).await;
+    let all_ok = !checks.iter().any(|c| c.status == CheckStatus::Failed);
+
+    if json {
         println!(
-            "ℹ️  State directory not yet created: {}",
-            state_dir.display()
+            "{}",
+            serde_json::to_string(&json!({ "ok": all_ok, "checks": checks }))?
         );
-        println!("   (Will be created when first daemon starts)");
-    }
-
-    // Check 5: Current directory config
-    if let Ok(current_dir) = env::current_dir() {
-        if let Ok(Some(config_path)) = crate::config_discovery::discover_config(&current_dir) {
-            println!("✅ Configuration found: {}", config_path.display());
-            if args.verbose {
-                if let Ok(config_hash) = crate::config_discovery::hash_config(&config_path) {
-                    println!("   Config hash: {}", config_hash);
-
-                    // Check if daemon is running for this config
-                    if let Ok(Some(state)) =
-                        crate::config_discovery::DaemonState::load(&config_hash)
-                    {
-                        if state.is_running() {
-                            println!("   Daemon status: ✅ Running");
-                            println!("   HTTP port: {}", state.http_port);
-                            println!("   gRPC port: {}", state.grpc_port);
-                            println!("   PID: {}", state.pid);
-                        } else {
-                            println!("   Daemon status: ⚠️  State exists but process not running");
-                        }
-                    } else {
-                        println!("   Daemon status: ⚠️  Not running");
-                    }
-                }
+    } else {
+        for check in &checks {
+            let icon = match check.status {
+                CheckStatus::Ok => "✅",
+                CheckStatus::Info => "ℹ️ ",
+                CheckStatus::Warning => "⚠️ ",
+                CheckStatus::Failed => "❌",
+            };
+            println!("{} {}", icon, check.message);
+            if let Some(fix) = &check.fix {
+                println!("   Fix: {}", fix);
             }
+        }
+        if args.verbose {
+            print_environment();
+        }
+
+        println!();
+        if all_ok {
+            println!("✅ All checks passed! Fabrik is properly configured.");
         } else {
-            println!("ℹ️  No fabrik.toml found in current directory");
-            if args.verbose {
-                println!("   Run 'fabrik init' to create fabrik.toml");
-            }
+            println!("⚠️  Some issues detected. Please fix the items marked with ❌ above.");
         }
     }
 
-    // Check 6: Environment variables
-    if args.verbose {
-        println!("\n📋 Environment Variables:");
-        let env_vars = [
-            "FABRIK_HTTP_URL",
-            "FABRIK_GRPC_URL",
-            "FABRIK_CONFIG_HASH",
-            "FABRIK_DAEMON_PID",
-            "GRADLE_BUILD_CACHE_URL",
-            "NX_SELF_HOSTED_REMOTE_CACHE_SERVER",
-        ];
-
-        let mut any_set = false;
-        for var in &env_vars {
-            if let Ok(value) = env::var(var) {
-                println!("   {} = {}", var, value);
-                any_set = true;
-            }
-        }
+    if !all_ok {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn run_checks(args: &DoctorArgs) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    // Fabrik binary
+    match env::current_exe() {
+        Ok(exe_path) => checks.push(Check::ok(
+            "binary",
+            format!(
+                "Fabrik binary found: {} (version {})",
+                exe_path.display(),
+                env!("CARGO_PKG_VERSION")
+            ),
+        )),
+        Err(e) => checks.push(Check::failed(
+            "binary",
+            format!("Could not determine Fabrik binary path: {}", e),
+        )),
+    }
 
-        if !any_set {
-            println!("   (None set - daemon may not be active in this directory)");
+    // Shell and its hook
+    match detect_shell() {
+        Some(shell) => {
+            checks.push(Check::ok("shell", format!("Shell detected: {}", shell)));
+            checks.push(check_shell_hook(&shell));
         }
+        None => checks.push(Check::warning(
+            "shell",
+            "Could not detect shell (SHELL env var not set)",
+        )),
     }
 
-    // Summary
-    println!();
-    if all_ok {
-        println!("✅ All checks passed! Fabrik is properly configured.");
+    // State directory
+    let state_dir = crate::xdg::daemon_state_dir();
+    if state_dir.exists() {
+        checks.push(Check::ok(
+            "state_dir",
+            format!("State directory exists: {}", state_dir.display()),
+        ));
     } else {
-        println!("⚠️  Some issues detected. Please fix the items marked with ❌ above.");
+        checks.push(Check::info(
+            "state_dir",
+            format!(
+                "State directory not yet created: {} (will be created when the first daemon starts)",
+                state_dir.display()
+            ),
+        ));
     }
 
-    if !all_ok {
-        std::process::exit(1);
+    // Configuration
+    let config = match crate::config_discovery::load_config_layers(args.config.as_deref())
+        .and_then(|layers| layers.map(|layers| layers.config()).transpose())
+    {
+        Ok(Some(config)) => {
+            checks.push(Check::ok("config", "Configuration loaded"));
+            Some(config)
+        }
+        Ok(None) => {
+            checks.push(
+                Check::info("config", "No fabrik.toml found in current directory")
+                    .with_fix("Run `fabrik init` to create fabrik.toml"),
+            );
+            None
+        }
+        Err(e) => {
+            checks.push(
+                Check::failed("config", format!("Invalid configuration: {:#}", e))
+                    .with_fix("Run `fabrik config validate --strict <path>` for details"),
+            );
+            None
+        }
+    };
+
+    // Daemons, per protocol
+    let daemons = DaemonState::load_all().unwrap_or_default();
+    for state in &daemons {
+        checks.extend(check_daemon(state));
     }
 
-    Ok(())
+    if let Some(config) = &config {
+        #[cfg(unix)]
+        checks.push(check_rocksdb_lock(
+            &Path::new(&config.cache.dir).join("metadata"),
+            &daemons,
+        ));
+        checks.extend(check_ports(
+            &configured_ports(config, |var| env::var(var).ok()),
+            &daemons,
+        ));
+        for upstream in &config.upstream {
+            checks.extend(check_upstream(upstream).await);
+        }
+        checks.extend(check_auth(config).await);
+        checks.extend(check_clock_skew(config).await);
+    }
+
+    checks
+}
+
+fn print_environment() {
+    println!("\n📋 Environment Variables:");
+    let env_vars = [
+        "FABRIK_HTTP_URL",
+        "FABRIK_GRPC_URL",
+        "FABRIK_CONFIG_HASH",
+        "FABRIK_DAEMON_PID",
+        "GRADLE_BUILD_CACHE_URL",
+        "NX_SELF_HOSTED_REMOTE_CACHE_SERVER",
+    ];
+
+    let mut any_set = false;
+    for var in &env_vars {
+        if let Ok(value) = env::var(var) {
+            println!("   {} = {}", var, value);
+            any_set = true;
+        }
+    }
+
+    if !any_set {
+        println!("   (None set - daemon may not be active in this directory)");
+    }
 }
 
 fn detect_shell() -> Option<String> {
-    env::var("SHELL").ok().and_then(|shell_path| {
+    let from_path = env::var("SHELL").ok().and_then(|shell_path| {
         PathBuf::from(shell_path)
             .file_name()
             .and_then(|name| name.to_str())
-            .map(|s| s.to_string())
+            .map(|s| s.trim_end_matches(".exe").to_string())
+    });
+    // SHELL is usually unset in nushell and PowerShell sessions on Windows
+    from_path.or_else(|| {
+        if env::var_os("NU_VERSION").is_some() {
+            Some("nu".to_string())
+        } else if env::var_os("PSModulePath").is_some() {
+            Some("pwsh".to_string())
+        } else {
+            None
+        }
     })
 }
 
-fn check_shell_integration(shell: &str, verbose: bool) -> bool {
-    let config_file = match shell {
-        "bash" => dirs::home_dir().map(|h| h.join(".bashrc")),
-        "zsh" => dirs::home_dir().map(|h| h.join(".zshrc")),
-        "fish" => dirs::home_dir().map(|h| h.join(".config/fish/config.fish")),
+/// Startup file of `shell` and the command that installs Fabrik's hook in it
+fn shell_hook(shell: &str) -> Option<(PathBuf, String)> {
+    let home = dirs::home_dir()?;
+    let append = |path: PathBuf, hook: &str| {
+        let fix = format!(
+            "echo '{}' >> {} && source {}",
+            hook,
+            path.display(),
+            path.display()
+        );
+        Some((path, fix))
+    };
+    match shell {
+        "bash" => append(home.join(".bashrc"), "eval \"$(fabrik activate bash)\""),
+        "zsh" => append(home.join(".zshrc"), "eval \"$(fabrik activate zsh)\""),
+        "fish" => append(
+            home.join(".config/fish/config.fish"),
+            "fabrik activate fish | source",
+        ),
+        "pwsh" | "powershell" => {
+            let profile_dir = if cfg!(windows) {
+                dirs::document_dir()?.join("PowerShell")
+            } else {
+                home.join(".config/powershell")
+            };
+            let fix = "Add-Content -Path $PROFILE -Value 'Invoke-Expression (& fabrik activate pwsh | Out-String)'; . $PROFILE";
+            Some((
+                profile_dir.join("Microsoft.PowerShell_profile.ps1"),
+                fix.to_string(),
+            ))
+        }
+        "nu" | "nushell" => {
+            // Nushell can't source generated code, so the hook goes in an autoload file
+            let autoload = dirs::data_dir()?.join("nushell/vendor/autoload/fabrik.nu");
+            let fix = format!(
+                "mkdir {} ; fabrik activate nu | save -f {}",
+                autoload.parent()?.display(),
+                autoload.display()
+            );
+            Some((autoload, fix))
+        }
         _ => None,
+    }
+}
+
+fn check_shell_hook(shell: &str) -> Check {
+    let Some((config_path, fix)) = shell_hook(shell) else {
+        return Check::warning(
+            "shell_hook",
+            format!("Shell integration isn't supported for {}", shell),
+        )
+        .with_fix("Use bash, zsh, fish, PowerShell or nushell, or see `fabrik activate --help`");
     };
 
-    if let Some(config_path) = config_file {
-        if config_path.exists() {
-            if let Ok(contents) = fs::read_to_string(&config_path) {
-                let has_integration = contents.contains("fabrik activate");
+    let installed = fs::read_to_string(&config_path)
+        .map(|contents| contents.contains("fabrik activate"))
+        .unwrap_or(false);
+    if installed {
+        Check::ok(
+            "shell_hook",
+            format!("Shell integration configured in {}", config_path.display()),
+        )
+    } else {
+        Check::failed(
+            "shell_hook",
+            format!(
+                "Shell integration NOT configured in {}",
+                config_path.display()
+            ),
+        )
+        .with_fix(fix)
+    }
+}
+
+/// Reachability of each protocol a daemon serves
+fn check_daemon(state: &DaemonState) -> Vec<Check> {
+    let name = format!(
+        "daemon:{}",
+        &state.config_hash[..state.config_hash.len().min(8)]
+    );
+    let restart = format!(
+        "Restart the daemon: `kill {}`, then run `fabrik activate --status` in {}",
+        state.pid,
+        state
+            .config_path
+            .parent()
+            .unwrap_or(&state.config_path)
+            .display()
+    );
+
+    if !state.is_running() {
+        return vec![Check::warning(
+            name,
+            format!(
+                "Daemon for {} isn't running (PID {}) but left its state behind",
+                state.config_path.display(),
+                state.pid
+            ),
+        )
+        .with_fix(format!("rm -r {}", state.state_dir().display()))];
+    }
+
+    let mut checks = Vec::new();
+    let protocol = |protocol: &str, result: String, ok: bool| {
+        let message = format!(
+            "Daemon for {} (PID {}): {} {}",
+            state.config_path.display(),
+            state.pid,
+            protocol,
+            result
+        );
+        match ok {
+            true => Check::ok(format!("{}:{}", name, protocol), message),
+            false => Check::failed(format!("{}:{}", name, protocol), message).with_fix(&restart),
+        }
+    };
+
+    let http = probe_http_health(state);
+    let http_ok = http.contains(" 200");
+    let http_location = match state.http_socket {
+        Some(ref socket) => socket.display().to_string(),
+        None => format!("port {}", state.http_port),
+    };
+    checks.push(protocol(
+        "http",
+        format!("on {}: {}", http_location, http),
+        http_ok,
+    ));
 
-                if verbose {
-                    println!("   Config file: {}", config_path.display());
-                    if has_integration {
-                        println!("   Found 'fabrik activate' in config");
-                    }
+    let (grpc, grpc_location) = match state.grpc_socket {
+        Some(ref socket) => (probe_unix(socket), socket.display().to_string()),
+        None => (
+            probe_tcp(state.grpc_port),
+            format!("port {}", state.grpc_port),
+        ),
+    };
+    checks.push(protocol(
+        "grpc",
+        format!("on {}: {}", grpc_location, grpc),
+        grpc == "ok",
+    ));
+
+    if let Some(s3_port) = state.s3_port {
+        let s3 = probe_tcp(s3_port);
+        checks.push(protocol(
+            "s3",
+            format!("on port {}: {}", s3_port, s3),
+            s3 == "ok",
+        ));
+    }
+
+    #[cfg(unix)]
+    if let Some(socket) = &state.unix_socket {
+        checks.push(check_unix_socket(&name, socket).with_fix_if_failed(&restart));
+    }
+
+    checks
+}
+
+/// A daemon's Unix socket exists, accepts connections and isn't open to other users
+#[cfg(unix)]
+fn check_unix_socket(daemon: &str, socket: &Path) -> Check {
+    use std::os::unix::fs::PermissionsExt;
+
+    let name = format!("{}:unix_socket", daemon);
+    let metadata = match fs::metadata(socket) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            return Check::failed(
+                name,
+                format!("Unix socket {} is missing: {}", socket.display(), e),
+            )
+        }
+    };
+
+    let mode = metadata.permissions().mode();
+    if let Err(e) = std::os::unix::net::UnixStream::connect(socket) {
+        let check = Check::failed(
+            name,
+            format!(
+                "Unix socket {} refuses connections: {}",
+                socket.display(),
+                e
+            ),
+        );
+        return match e.kind() {
+            std::io::ErrorKind::PermissionDenied => check.with_fix(format!(
+                "chmod u+rw {} (or restart the daemon as the current user)",
+                socket.display()
+            )),
+            _ => check,
+        };
+    }
+    if mode & 0o002 != 0 {
+        return Check::warning(
+            name,
+            format!(
+                "Unix socket {} is writable by every user (mode {:o})",
+                socket.display(),
+                mode & 0o777
+            ),
+        )
+        .with_fix(format!("chmod o-w {}", socket.display()));
+    }
+    Check::ok(
+        name,
+        format!("Unix socket {} accepts connections", socket.display()),
+    )
+}
+
+/// PID of the process holding the RocksDB lock in `db_dir`, None if it's free
+///
+/// RocksDB takes a POSIX record lock on `LOCK`; `F_GETLK` reports the holder without
+/// taking the lock.
+#[cfg(unix)]
+fn rocksdb_lock_holder(db_dir: &Path) -> std::io::Result<Option<i32>> {
+    use nix::fcntl::{fcntl, FcntlArg};
+    use nix::libc;
+
+    let file = match fs::File::open(db_dir.join("LOCK")) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    // SAFETY: flock is a plain C struct, valid when zeroed
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = libc::F_WRLCK as _;
+    lock.l_whence = libc::SEEK_SET as _;
+    fcntl(&file, FcntlArg::F_GETLK(&mut lock)).map_err(std::io::Error::from)?;
+    Ok((i32::from(lock.l_type) != libc::F_UNLCK).then_some(lock.l_pid))
+}
+
+#[cfg(unix)]
+fn check_rocksdb_lock(db_dir: &Path, daemons: &[DaemonState]) -> Check {
+    let name = "rocksdb_lock";
+    match rocksdb_lock_holder(db_dir) {
+        Ok(None) => Check::ok(
+            name,
+            format!("Metadata database isn't locked: {}", db_dir.display()),
+        ),
+        Ok(Some(pid)) if daemons.iter().any(|d| d.pid as i32 == pid && d.is_running()) => {
+            Check::ok(
+                name,
+                format!("Metadata database is in use by the daemon (PID {})", pid),
+            )
+        }
+        Ok(Some(pid)) => Check::failed(
+            name,
+            format!(
+                "Metadata database {} is locked by another process (PID {})",
+                db_dir.display(),
+                pid
+            ),
+        )
+        .with_fix(format!(
+            "Only one Fabrik process can use a cache directory: stop PID {} (`kill {}`) or set another cache.dir",
+            pid, pid
+        )),
+        Err(e) => Check::warning(
+            name,
+            format!("Could not check the lock of {}: {}", db_dir.display(), e),
+        ),
+    }
+}
+
+/// Fixed ports a daemon for `config` listens on, by what configures them
+fn configured_ports(
+    config: &FabrikConfig,
+    env_var: impl Fn(&str) -> Option<String>,
+) -> Vec<(String, u16)> {
+    let mut ports = Vec::new();
+    for var in ["FABRIK_CONFIG_S3_PORT", "FABRIK_CONFIG_METRICS_PORT"] {
+        if let Some(port) = env_var(var).and_then(|port| port.parse::<u16>().ok()) {
+            ports.push((var.to_string(), port));
+        }
+    }
+    if config.p2p.enabled {
+        ports.push(("p2p.bind_port".to_string(), config.p2p.bind_port));
+    }
+    let adapters = [
+        ("gradle", &config.build_systems.gradle),
+        ("bazel", &config.build_systems.bazel),
+        ("nx", &config.build_systems.nx),
+        ("turborepo", &config.build_systems.turborepo),
+        ("sccache", &config.build_systems.sccache),
+    ];
+    for (build_system, adapter) in adapters {
+        if let Some(port) = adapter.as_ref().and_then(|adapter| adapter.port) {
+            ports.push((format!("build_systems.{}.port", build_system), port));
+        }
+    }
+    // Port 0 picks a free port
+    ports.retain(|(_, port)| *port != 0);
+    ports
+}
+
+/// Configured ports are distinct and free, unless a daemon already serves them
+fn check_ports(ports: &[(String, u16)], daemons: &[DaemonState]) -> Vec<Check> {
+    let mut checks = Vec::new();
+    for (i, (source, port)) in ports.iter().enumerate() {
+        let name = format!("port:{}", port);
+        if let Some((other, _)) = ports[..i].iter().find(|(_, p)| p == port) {
+            checks.push(
+                Check::failed(
+                    name,
+                    format!(
+                        "Port {} is configured by both {} and {}",
+                        port, other, source
+                    ),
+                )
+                .with_fix(format!("Change {} to another port", source)),
+            );
+            continue;
+        }
+
+        let served_by_daemon = daemons
+            .iter()
+            .any(|d| d.is_running() && (d.metrics_port == *port || d.s3_port == Some(*port)));
+        if served_by_daemon {
+            checks.push(Check::ok(
+                name,
+                format!("Port {} ({}) is served by the daemon", port, source),
+            ));
+            continue;
+        }
+
+        match std::net::TcpListener::bind(("0.0.0.0", *port)) {
+            Ok(_) => checks.push(Check::ok(
+                name,
+                format!("Port {} ({}) is free", port, source),
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => checks.push(
+                Check::failed(
+                    name,
+                    format!("Port {} ({}) is in use by another process", port, source),
+                )
+                .with_fix(format!(
+                    "Stop the process listening on it (`lsof -i :{}`) or change {}",
+                    port, source
+                )),
+            ),
+            Err(e) => checks.push(Check::warning(
+                name,
+                format!("Could not check port {} ({}): {}", port, source, e),
+            )),
+        }
+    }
+    checks
+}
+
+/// The upstream accepts connections and, for S3, has credentials
+async fn check_upstream(upstream: &UpstreamConfig) -> Vec<Check> {
+    let name = format!("upstream:{}", upstream.url);
+    let mut checks = Vec::new();
+
+    let address = if upstream.url.starts_with("s3://") {
+        let has_credentials = (upstream.access_key.is_some() && upstream.secret_key.is_some())
+            || env::var("AWS_ACCESS_KEY_ID").is_ok();
+        if !has_credentials {
+            checks.push(
+                Check::failed(
+                    format!("upstream_auth:{}", upstream.url),
+                    format!("No S3 credentials for upstream {}", upstream.url),
+                )
+                .with_fix(
+                    "Set access_key and secret_key in its [[upstream]] table, or AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY",
+                ),
+            );
+        }
+        match &upstream.endpoint {
+            Some(endpoint) => upstream_address(endpoint),
+            None => Some(format!(
+                "s3.{}.amazonaws.com:443",
+                upstream.region.as_deref().unwrap_or("us-east-1")
+            )),
+        }
+    } else {
+        upstream_address(&upstream.url)
+    };
+    let Some(address) = address else {
+        return checks;
+    };
+
+    let started = std::time::Instant::now();
+    let connected =
+        tokio::time::timeout(PROBE_TIMEOUT, tokio::net::TcpStream::connect(&address)).await;
+    let check = match connected {
+        Ok(Ok(_)) => Check::ok(
+            name,
+            format!(
+                "Upstream {} is reachable ({} ms)",
+                upstream.url,
+                started.elapsed().as_millis()
+            ),
+        ),
+        Ok(Err(e)) => Check::failed(
+            name,
+            format!("Upstream {} is unreachable: {}", upstream.url, e),
+        ),
+        Err(_) => Check::failed(
+            name,
+            format!(
+                "Upstream {} didn't accept a connection within {:?}",
+                upstream.url, PROBE_TIMEOUT
+            ),
+        ),
+    };
+    checks.push(match check.status {
+        CheckStatus::Failed => check.with_fix(format!(
+            "Check that {} is reachable from this machine (network, VPN, firewall, DNS) or fix the upstream url",
+            address
+        )),
+        _ => check,
+    });
+    checks
+}
+
+/// A token is available for the configured authentication provider and hasn't expired
+async fn check_auth(config: &FabrikConfig) -> Option<Check> {
+    let provider = config.auth.provider.as_ref()?;
+    let login = match provider {
+        ConfigAuthProvider::OAuth2 => "Run `fabrik auth login`",
+        ConfigAuthProvider::Token => {
+            "Set the token in the environment variable or file configured in [auth.token]"
+        }
+    };
+
+    let status = match AuthProvider::new(config.auth.clone(), config.url.clone()) {
+        Ok(auth) => auth.status().await.map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    Some(match status {
+        Ok(status) if !status.authenticated => {
+            Check::failed("auth", format!("Not authenticated ({})", status.provider))
+                .with_fix(login)
+        }
+        Ok(status) => {
+            let now = chrono::Utc::now().timestamp().max(0) as u64;
+            match status.expires_at {
+                Some(expires_at) if expires_at <= now => {
+                    Check::failed("auth", format!("Token ({}) has expired", status.provider))
+                        .with_fix(login)
                 }
+                _ => Check::ok("auth", format!("Authenticated ({})", status.provider)),
+            }
+        }
+        Err(e) => Check::failed("auth", format!("Authentication check failed: {:#}", e))
+            .with_fix("Check the [auth] section of the configuration"),
+    })
+}
+
+/// The system clock agrees with the service's, so JWTs validate
+///
+/// Compares against the `Date` header of the first HTTP(S) service or upstream.
+async fn check_clock_skew(config: &FabrikConfig) -> Option<Check> {
+    let url = config
+        .url
+        .iter()
+        .chain(config.upstream.iter().map(|u| &u.url))
+        .find(|url| url.starts_with("http://") || url.starts_with("https://"))?;
+
+    let client = reqwest::Client::builder()
+        .timeout(PROBE_TIMEOUT)
+        .build()
+        .ok()?;
+    let response = client.head(url).send().await.ok()?;
+    let date = response.headers().get("date")?.to_str().ok()?;
+    let server_time = chrono::DateTime::parse_from_rfc2822(date).ok()?;
+    Some(clock_skew_check(
+        url,
+        chrono::Utc::now() - server_time.with_timezone(&chrono::Utc),
+    ))
+}
+
+fn clock_skew_check(url: &str, skew: chrono::Duration) -> Check {
+    let seconds = skew.num_seconds();
+    let message = format!("System clock is {}s off from {}", seconds, url);
+    if seconds.unsigned_abs() > MAX_CLOCK_SKEW.as_secs() {
+        Check::failed("clock_skew", message).with_fix(
+            "Sync the system clock (`sudo timedatectl set-ntp true` on Linux, Settings > General > Date & Time on macOS); tokens are rejected as expired or not yet valid otherwise",
+        )
+    } else {
+        Check::ok("clock_skew", message)
+    }
+}
+
+/// Gather diagnostics into a tar.gz support bundle
+///
+/// Secrets in the configuration are masked; everything else (daemon state, logs,
+/// storage stats, probe results) is collected as-is.
+fn create_bundle(args: &DoctorArgs, output: Option<&str>) -> Result<()> {
+    let output = output.map(PathBuf::from).unwrap_or_else(|| {
+        PathBuf::from(format!(
+            "fabrik-bundle-{}.tar.gz",
+            chrono::Utc::now().format("%Y%m%d-%H%M%S")
+        ))
+    });
+
+    let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+
+    entries.push((
+        "version.json".to_string(),
+        to_json(&json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "os": env::consts::OS,
+            "arch": env::consts::ARCH,
+            "created_at": chrono::Utc::now().to_rfc3339(),
+        }))?,
+    ));
+
+    // Configuration (secrets masked)
+    let config_path = match &args.config {
+        Some(path) => Some(PathBuf::from(path)),
+        None => env::current_dir().ok().and_then(|dir| {
+            crate::config_discovery::discover_config(&dir)
+                .ok()
+                .flatten()
+        }),
+    };
+    let config = config_path
+        .as_ref()
+        .map(|path| FabrikConfig::from_file(path).map(|config| config.redacted()));
+    match &config {
+        Some(Ok(config)) => entries.push((
+            "config.toml".to_string(),
+            toml::to_string_pretty(config)
+                .context("Failed to serialize config")?
+                .into_bytes(),
+        )),
+        Some(Err(e)) => entries.push((
+            "config-error.txt".to_string(),
+            format!("{:#}\n", e).into_bytes(),
+        )),
+        None => {}
+    }
+
+    // Daemon state and protocol probes
+    let daemons = DaemonState::load_all().unwrap_or_default();
+    let daemon_info: Vec<_> = daemons
+        .iter()
+        .map(|state| {
+            json!({
+                "config_hash": state.config_hash,
+                "pid": state.pid,
+                "running": state.is_running(),
+                "http_port": state.http_port,
+                "grpc_port": state.grpc_port,
+                "metrics_port": state.metrics_port,
+                "unix_socket": state.unix_socket,
+                "http_socket": state.http_socket,
+                "grpc_socket": state.grpc_socket,
+                "config_path": state.config_path,
+            })
+        })
+        .collect();
+    entries.push(("daemons.json".to_string(), to_json(&daemon_info)?));
+
+    let probes: Vec<_> = daemons
+        .iter()
+        .filter(|state| state.is_running())
+        .map(probe_daemon)
+        .collect();
+    entries.push(("probes.json".to_string(), to_json(&probes)?));
+
+    // Storage stats
+    if let Some(Ok(config)) = &config {
+        entries.push((
+            "storage.json".to_string(),
+            to_json(&storage_stats(Path::new(&config.cache.dir)))?,
+        ));
+    }
+
+    // Recent logs
+    let logs_dir = crate::xdg::logs_dir();
+    if let Ok(logs) = fs::read_dir(&logs_dir) {
+        for log in logs.flatten() {
+            let path = log.path();
+            if !path.is_file() {
+                continue;
+            }
+            if let Ok(tail) = read_tail(&path, MAX_LOG_BYTES) {
+                entries.push((format!("logs/{}", log.file_name().to_string_lossy()), tail));
+            }
+        }
+    }
+
+    write_bundle(&output, &entries)?;
+
+    eprintln!(
+        "{} Support bundle written to {} ({} files)",
+        fabrik_prefix(),
+        output.display(),
+        entries.len()
+    );
+    eprintln!(
+        "{} Review it before sharing; secrets in the config are masked but logs are included as-is",
+        fabrik_prefix()
+    );
+
+    Ok(())
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<Vec<u8>> {
+    serde_json::to_vec_pretty(value).context("Failed to serialize bundle entry")
+}
 
-                return has_integration;
+/// Write bundle entries into a gzip-compressed tarball
+fn write_bundle(output: &Path, entries: &[(String, Vec<u8>)]) -> Result<()> {
+    let file = fs::File::create(output)
+        .with_context(|| format!("Failed to create bundle: {}", output.display()))?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let mtime = chrono::Utc::now().timestamp().max(0) as u64;
+
+    for (name, data) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        header.set_cksum();
+        builder
+            .append_data(
+                &mut header,
+                format!("fabrik-bundle/{}", name),
+                data.as_slice(),
+            )
+            .with_context(|| format!("Failed to add {} to bundle", name))?;
+    }
+
+    builder
+        .into_inner()
+        .context("Failed to finish bundle archive")?
+        .finish()
+        .context("Failed to finish bundle compression")?
+        .flush()
+        .context("Failed to flush bundle")?;
+
+    Ok(())
+}
+
+/// Read at most `max_bytes` from the end of a file
+fn read_tail(path: &Path, max_bytes: u64) -> Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(max_bytes)))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Count files and bytes per top-level directory of the cache
+fn storage_stats(cache_dir: &Path) -> serde_json::Value {
+    if !cache_dir.exists() {
+        return json!({ "dir": cache_dir, "exists": false });
+    }
+
+    let mut areas: std::collections::BTreeMap<String, (u64, u64)> = Default::default();
+    for entry in walkdir::WalkDir::new(cache_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+    {
+        let area = entry
+            .path()
+            .strip_prefix(cache_dir)
+            .ok()
+            .and_then(|rel| rel.components().next())
+            .filter(|_| entry.depth() > 1)
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .unwrap_or_else(|| ".".to_string());
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        let stats = areas.entry(area).or_default();
+        stats.0 += 1;
+        stats.1 += size;
+    }
+
+    let total_files: u64 = areas.values().map(|(files, _)| files).sum();
+    let total_bytes: u64 = areas.values().map(|(_, bytes)| bytes).sum();
+    let areas: serde_json::Map<_, _> = areas
+        .into_iter()
+        .map(|(area, (files, bytes))| (area, json!({ "files": files, "bytes": bytes })))
+        .collect();
+
+    json!({
+        "dir": cache_dir,
+        "exists": true,
+        "total_files": total_files,
+        "total_bytes": total_bytes,
+        "areas": areas,
+    })
+}
+
+/// Check that a daemon's listeners accept connections
+fn probe_daemon(state: &DaemonState) -> serde_json::Value {
+    let http = probe_http_health(state);
+    let grpc = match state.grpc_socket {
+        Some(ref socket) => probe_unix(socket),
+        None => probe_tcp(state.grpc_port),
+    };
+    let unix_socket = state.unix_socket.as_deref().map(probe_unix);
+
+    json!({
+        "config_hash": state.config_hash,
+        "http_health": http,
+        "grpc_connect": grpc,
+        "unix_socket_connect": unix_socket,
+    })
+}
+
+fn probe_tcp(port: u16) -> String {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    match TcpStream::connect_timeout(&addr, PROBE_TIMEOUT) {
+        Ok(_) => "ok".to_string(),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Issue `GET /health` and return the response status line
+/// Connect to a Unix socket ("ok", or the error)
+fn probe_unix(socket: &Path) -> String {
+    #[cfg(unix)]
+    let result = crate::unix_socket::connect(socket).map(|_| ());
+    #[cfg(not(unix))]
+    let result: std::io::Result<()> = Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("Unix sockets aren't supported ({})", socket.display()),
+    ));
+    match result {
+        Ok(()) => "ok".to_string(),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Status line of the daemon's `GET /health`, on its HTTP port or socket
+fn probe_http_health(state: &DaemonState) -> String {
+    const REQUEST: &[u8] = b"GET /health HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\r\n";
+    let result = (|| -> std::io::Result<String> {
+        let mut response = Vec::new();
+        match state.http_socket {
+            #[cfg(unix)]
+            Some(ref socket) => {
+                let mut stream = crate::unix_socket::connect(socket)?;
+                stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+                stream.write_all(REQUEST)?;
+                stream.take(1024).read_to_end(&mut response)?;
+            }
+            _ => {
+                let addr = SocketAddr::from(([127, 0, 0, 1], state.http_port));
+                let mut stream = TcpStream::connect_timeout(&addr, PROBE_TIMEOUT)?;
+                stream.set_read_timeout(Some(PROBE_TIMEOUT))?;
+                stream.write_all(REQUEST)?;
+                stream.take(1024).read_to_end(&mut response)?;
             }
-        } else if verbose {
-            println!("   Config file not found: {}", config_path.display());
         }
+        Ok(String::from_utf8_lossy(&response)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string())
+    })();
+
+    result.unwrap_or_else(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_bundle_roundtrip() {
+        let temp = TempDir::new().unwrap();
+        let output = temp.path().join("bundle.tar.gz");
+
+        write_bundle(
+            &output,
+            &[
+                ("version.json".to_string(), b"{}".to_vec()),
+                ("logs/daemon-abc.log".to_string(), b"started".to_vec()),
+            ],
+        )
+        .unwrap();
+
+        let mut archive = tar::Archive::new(GzDecoder::new(fs::File::open(&output).unwrap()));
+        let names: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "fabrik-bundle/version.json",
+                "fabrik-bundle/logs/daemon-abc.log"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_tail_limits_size() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("daemon.log");
+        fs::write(&path, b"0123456789").unwrap();
+
+        assert_eq!(read_tail(&path, 4).unwrap(), b"6789");
+        assert_eq!(read_tail(&path, 100).unwrap(), b"0123456789");
+    }
+
+    #[test]
+    fn test_configured_ports() {
+        let config: FabrikConfig = toml::from_str(
+            r#"
+[cache]
+dir = "/tmp/fabrik"
+max_size = "1GB"
+
+[build_systems.gradle]
+port = 5071
+
+[build_systems.bazel]
+port = 0
+"#,
+        )
+        .unwrap();
+
+        let ports = configured_ports(&config, |var| {
+            (var == "FABRIK_CONFIG_S3_PORT").then(|| "9000".to_string())
+        });
+        assert_eq!(
+            ports,
+            vec![
+                ("FABRIK_CONFIG_S3_PORT".to_string(), 9000),
+                ("build_systems.gradle.port".to_string(), 5071),
+            ]
+        );
     }
 
-    false
+    #[test]
+    fn test_check_ports_finds_collisions() {
+        let listener = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let taken = listener.local_addr().unwrap().port();
+
+        let checks = check_ports(
+            &[
+                ("build_systems.gradle.port".to_string(), taken),
+                ("FABRIK_CONFIG_S3_PORT".to_string(), taken),
+            ],
+            &[],
+        );
+        assert_eq!(checks.len(), 2);
+        assert_eq!(checks[0].status, CheckStatus::Failed);
+        assert!(checks[0].message.contains("in use by another process"));
+        assert!(checks[0]
+            .fix
+            .as_ref()
+            .unwrap()
+            .contains("build_systems.gradle.port"));
+        assert_eq!(checks[1].status, CheckStatus::Failed);
+        assert!(checks[1].message.contains("configured by both"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rocksdb_lock_holder_without_lock() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(rocksdb_lock_holder(temp.path()).unwrap(), None);
+
+        // A lock file nobody holds
+        fs::write(temp.path().join("LOCK"), b"").unwrap();
+        assert_eq!(rocksdb_lock_holder(temp.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_clock_skew_check() {
+        let check = clock_skew_check("https://tuist.dev", chrono::Duration::seconds(5));
+        assert_eq!(check.status, CheckStatus::Ok);
+
+        let check = clock_skew_check("https://tuist.dev", chrono::Duration::seconds(-300));
+        assert_eq!(check.status, CheckStatus::Failed);
+        assert_eq!(
+            check.message,
+            "System clock is -300s off from https://tuist.dev"
+        );
+        assert!(check.fix.is_some());
+
+        let json = serde_json::to_value(&check).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["name"], "clock_skew");
+    }
+
+    #[test]
+    fn test_storage_stats_groups_by_area() {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir_all(temp.path().join("objects/ab")).unwrap();
+        fs::write(temp.path().join("objects/ab/abcd"), b"1234").unwrap();
+        fs::create_dir_all(temp.path().join("scripts")).unwrap();
+        fs::write(temp.path().join("scripts/entry.json"), b"12").unwrap();
+
+        let stats = storage_stats(temp.path());
+        assert_eq!(stats["total_files"], 2);
+        assert_eq!(stats["total_bytes"], 6);
+        assert_eq!(stats["areas"]["objects"]["bytes"], 4);
+        assert_eq!(stats["areas"]["scripts"]["files"], 1);
+    }
 }
@@ -0,0 +1,98 @@
+//! `--dry-run` of `fabrik exec` and `fabrik daemon`: what they would bind, export and
+//! use, printed without starting any server
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Stands in for the port of a listener bound to port 0
+pub const RANDOM_PORT: &str = "<random>";
+
+/// What a command would do with the resolved configuration
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub config_path: Option<PathBuf>,
+    pub config_hash: Option<String>,
+    /// Listeners by protocol, with their address or socket path
+    pub listeners: Vec<(&'static str, String)>,
+    pub upstreams: Vec<String>,
+    pub build_systems: Vec<String>,
+    pub env_vars: BTreeMap<String, String>,
+    /// The command as it would be run (`fabrik exec` only)
+    pub command: Option<Vec<String>>,
+    pub notes: Vec<String>,
+}
+
+impl Plan {
+    /// `127.0.0.1:<port>`, with [`RANDOM_PORT`] for port 0
+    pub fn local_address(port: u16) -> String {
+        match port {
+            0 => format!("127.0.0.1:{}", RANDOM_PORT),
+            port => format!("127.0.0.1:{}", port),
+        }
+    }
+
+    pub fn print(&self) {
+        println!("Dry run: nothing is started");
+        println!();
+
+        match self.config_path {
+            Some(ref path) => println!("Config: {}", path.display()),
+            None => println!("Config: none found, using defaults"),
+        }
+        if let Some(ref hash) = self.config_hash {
+            println!("Config hash: {}", hash);
+        }
+
+        println!();
+        println!("Listeners:");
+        for (protocol, address) in &self.listeners {
+            println!("  {:<12} {}", protocol, address);
+        }
+
+        println!();
+        if self.upstreams.is_empty() {
+            println!("Upstreams: none (local cache only)");
+        } else {
+            println!("Upstreams:");
+            for upstream in &self.upstreams {
+                println!("  {}", upstream);
+            }
+        }
+
+        println!();
+        if self.build_systems.is_empty() {
+            println!("Build systems: none");
+        } else {
+            println!("Build systems: {}", self.build_systems.join(", "));
+        }
+
+        println!();
+        println!("Environment:");
+        for (key, value) in &self.env_vars {
+            println!("  {}={}", key, value);
+        }
+
+        if let Some(ref command) = self.command {
+            println!();
+            println!("Command: {}", command.join(" "));
+        }
+
+        if !self.notes.is_empty() {
+            println!();
+            for note in &self.notes {
+                println!("Note: {}", note);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_address() {
+        assert_eq!(Plan::local_address(0), "127.0.0.1:<random>");
+        assert_eq!(Plan::local_address(7070), "127.0.0.1:7070");
+    }
+}
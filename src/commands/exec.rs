@@ -1,9 +1,13 @@
 use anyhow::{Context, Result};
-use std::process::Stdio;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
 use std::sync::Arc;
 use tokio::process::Command;
 use tracing::info;
 
+use super::dry_run::Plan;
+use crate::bazel::build_metadata::{ActionCacheStats, BuildMetadataReport, CacheTopology};
 use crate::bazel::proto::bytestream::byte_stream_server::ByteStreamServer;
 use crate::bazel::proto::remote_execution::action_cache_server::ActionCacheServer;
 use crate::bazel::proto::remote_execution::capabilities_server::CapabilitiesServer;
@@ -12,181 +16,959 @@ use crate::bazel::{
     BazelActionCacheService, BazelByteStreamService, BazelCapabilitiesService, BazelCasService,
 };
 use crate::cli::ExecArgs;
-use crate::config_discovery::populate_build_tool_env_vars;
+use crate::cli_utils::format_size;
+use crate::config::{FabrikConfig, GrpcConfig};
+use crate::config_discovery::{
+    default_turbo_team, discover_config, generate_turbo_token, hash_config,
+    load_config_with_discovery, populate_build_tool_env_vars, DaemonState, Endpoints,
+};
+use crate::daemon_stats::{LiveStats, RequestStatsLayer, INVOCATION_HEADER};
 use crate::eviction::{spawn_background_eviction, BackgroundEvictionConfig, EvictionConfig};
+use crate::grpc_introspection;
+use crate::grpc_transport::GrpcTransport;
 use crate::http::HttpServer;
 use crate::merger::MergedExecConfig;
+use crate::quota::UploadLimits;
 use crate::storage;
-use tonic::transport::Server;
+use crate::unix_socket;
 
 pub async fn run(args: ExecArgs) -> Result<()> {
-    use crate::config_discovery::load_config_with_discovery;
-
     if args.command.is_empty() {
         anyhow::bail!("No command specified. Usage: fabrik exec -- <command>");
     }
 
-    // Load config file with auto-discovery
+    // Load config file with auto-discovery and keep its path to find a running daemon
+    let config_path = match &args.config {
+        Some(path) => Some(PathBuf::from(path)),
+        None => discover_config(&std::env::current_dir()?)?,
+    };
     let file_config = load_config_with_discovery(args.config.as_deref())?;
 
     // Merge configuration
-    let config = MergedExecConfig::merge(&args, file_config);
+    let config = MergedExecConfig::merge(&args, file_config.clone());
+
+    // Reuse the daemon already serving this config (e.g. started by shell activation).
+    // Build metadata needs the action cache statistics of this process, so it always
+    // serves the cache itself.
+    let needs_own_server =
+        args.in_process || config.build_metadata || config.build_metadata_file.is_some();
+    if args.dry_run {
+        return dry_run(
+            &args,
+            &config,
+            config_path,
+            file_config.as_ref(),
+            needs_own_server,
+        );
+    }
+    if !needs_own_server {
+        if let Some(state) = running_daemon(config_path.as_deref())? {
+            return run_with_daemon(&args, &config, &state).await;
+        }
+    }
+
+    run_in_process(&args, &config, file_config.as_ref()).await
+}
+
+/// Variable with the ID tagging the command's cache traffic, set for the command
+const INVOCATION_ID_VAR: &str = "FABRIK_INVOCATION_ID";
+
+/// ID tagging the command's cache traffic: `--invocation-id`, or a new one
+fn invocation_id(args: &ExecArgs) -> String {
+    args.invocation_id
+        .clone()
+        .unwrap_or_else(|| hex::encode(rand::random::<[u8; 16]>()))
+}
+
+/// Daemon running for the config at `config_path`, if any
+fn running_daemon(config_path: Option<&Path>) -> Result<Option<DaemonState>> {
+    let Some(config_path) = config_path else {
+        return Ok(None);
+    };
+    DaemonState::load_running(&hash_config(config_path)?)
+}
+
+/// Run the command against an already running daemon
+async fn run_with_daemon(
+    args: &ExecArgs,
+    config: &MergedExecConfig,
+    state: &DaemonState,
+) -> Result<()> {
+    info!(
+        "Using running daemon (PID {}) for {}",
+        state.pid,
+        state.config_path.display()
+    );
+
+    let invocation_id = invocation_id(args);
+    let mut env_vars = HashMap::new();
+    env_vars.insert(
+        "BAZELRC".to_string(),
+        state.bazelrc_file().display().to_string(),
+    );
+    env_vars.insert(INVOCATION_ID_VAR.to_string(), invocation_id.clone());
+    let endpoints = state.endpoints();
+    if args.export_env {
+        export_cache_env(&mut env_vars, &args.env_prefix, &endpoints);
+    }
+
+    let bazelrc = config
+        .build_systems
+        .iter()
+        .any(|name| name == "bazel")
+        .then(|| exec_bazelrc(&endpoints.grpc_url, &invocation_id, None));
+    let files = ExecFiles::write(&config.build_systems, bazelrc)?;
+    let command = inject_build_systems(
+        &config.build_systems,
+        &endpoints,
+        &files,
+        |name| std::env::var(name).ok(),
+        &mut env_vars,
+        &args.command,
+    );
+
+    let status = run_command(&command, &env_vars).await;
+    files.remove();
+    let status = status?;
+    info!(
+        "Cache traffic of this command: fabrik stats --invocation {}",
+        invocation_id
+    );
+    if !status.success() {
+        std::process::exit(status.code().unwrap_or(1));
+    }
+
+    Ok(())
+}
+
+/// Print what the command would be run with (`--dry-run`): the daemon it would use or
+/// the servers it would start, its environment and its arguments
+fn dry_run(
+    args: &ExecArgs,
+    config: &MergedExecConfig,
+    config_path: Option<PathBuf>,
+    file_config: Option<&FabrikConfig>,
+    needs_own_server: bool,
+) -> Result<()> {
+    let mut plan = Plan {
+        config_hash: config_path.as_deref().map(hash_config).transpose()?,
+        upstreams: config.upstream.clone(),
+        build_systems: config.build_systems.clone(),
+        ..Default::default()
+    };
+    let daemon = if needs_own_server {
+        None
+    } else {
+        running_daemon(config_path.as_deref())?
+    };
+    plan.config_path = config_path;
+
+    let mut env_vars = HashMap::new();
+    // Generated when the command runs, unless given
+    if let Some(ref id) = args.invocation_id {
+        env_vars.insert(INVOCATION_ID_VAR.to_string(), id.clone());
+    }
+    let (endpoints, files) = match daemon {
+        Some(state) => {
+            plan.notes.push(format!(
+                "Reusing the daemon running for this config (PID {}); --config-* options \
+                 other than --config-build-systems don't apply to it",
+                state.pid
+            ));
+            plan.listeners.push(match state.http_socket {
+                Some(ref socket) => ("http socket", socket.display().to_string()),
+                None => ("http", Plan::local_address(state.http_port)),
+            });
+            plan.listeners.push(match state.grpc_socket {
+                Some(ref socket) => ("grpc socket", socket.display().to_string()),
+                None => ("grpc", Plan::local_address(state.grpc_port)),
+            });
+            if let Some(s3_port) = state.s3_port {
+                plan.listeners.push(("s3", Plan::local_address(s3_port)));
+            }
+            if let Some(ref socket) = state.unix_socket {
+                plan.listeners
+                    .push(("unix socket", socket.display().to_string()));
+            }
+            env_vars.insert(
+                "BAZELRC".to_string(),
+                state.bazelrc_file().display().to_string(),
+            );
+            let bazel = config.build_systems.iter().any(|name| name == "bazel");
+            (
+                state.endpoints(),
+                ExecFiles::paths(&config.build_systems, bazel),
+            )
+        }
+        None => {
+            let address = Plan::local_address(0);
+            let (http_socket, grpc_socket) = exec_sockets(file_config);
+            plan.listeners.push(match http_socket {
+                Some(ref socket) => ("http socket", socket.display().to_string()),
+                None => ("http", address.clone()),
+            });
+            plan.listeners.push(match grpc_socket {
+                Some(ref socket) => ("grpc socket", socket.display().to_string()),
+                None => ("grpc", address.clone()),
+            });
+            let unix_socket = file_config
+                .and_then(|fc| fc.daemon.socket.as_ref())
+                .filter(|_| cfg!(unix))
+                .map(|_| temp_file("sock"));
+            if let Some(ref socket) = unix_socket {
+                plan.listeners
+                    .push(("unix socket", socket.display().to_string()));
+            }
+            if config.s3_port.is_some() {
+                plan.notes
+                    .push("The S3 API is only served by daemons, not in-process".to_string());
+            }
+            let files = ExecFiles::paths(&config.build_systems, true);
+            if let Some(ref bazelrc) = files.bazelrc {
+                env_vars.insert("BAZELRC".to_string(), bazelrc.display().to_string());
+            }
+            let endpoints = Endpoints {
+                http_url: http_socket.is_none().then(|| format!("http://{}", address)),
+                grpc_url: match grpc_socket {
+                    Some(ref socket) => unix_socket::grpc_url(socket),
+                    None => format!("grpc://{}", address),
+                },
+                http_socket,
+                s3_url: None,
+                unix_socket,
+            };
+            (endpoints, files)
+        }
+    };
+
+    if args.export_env {
+        export_cache_env(&mut env_vars, &args.env_prefix, &endpoints);
+    }
+    plan.command = Some(inject_build_systems(
+        &config.build_systems,
+        &endpoints,
+        &files,
+        |name| std::env::var(name).ok(),
+        &mut env_vars,
+        &args.command,
+    ));
+    plan.env_vars = env_vars.into_iter().collect();
+
+    plan.print();
+    Ok(())
+}
 
+/// Serve the build-system adapters from this process for the duration of the command
+///
+/// Nothing is written to the daemon state directory: the servers bind random ports,
+/// their sockets (Xcode's, and HTTP's and gRPC's when the config serves them on
+/// sockets) and bazelrc are per-process temporary files, and everything is shut down
+/// when the command exits.
+async fn run_in_process(
+    args: &ExecArgs,
+    config: &MergedExecConfig,
+    file_config: Option<&FabrikConfig>,
+) -> Result<()> {
     info!("Starting Fabrik exec mode");
     info!("Configuration:");
     info!("  Cache directory: {}", config.cache_dir);
     info!("  Max cache size: {}", config.max_cache_size);
     info!("  Upstream: {:?}", config.upstream);
+    if config.read_only {
+        info!("  Read-only: puts and deletes are rejected");
+    }
+    info!("  Hash algorithm: {}", config.hash_algorithm);
 
     // Initialize eviction configuration from merged config
     let eviction_config = EvictionConfig::from_cache_config(
         &config.max_cache_size,
         &config.eviction_policy,
         &config.default_ttl,
-    )?;
-
-    // Initialize shared storage backend with eviction
-    let storage =
-        storage::create_storage_with_eviction(&config.cache_dir, eviction_config.clone())?;
+    )?
+    .with_min_free_disk(file_config.and_then(|fc| fc.cache.min_free_disk.as_deref()))?;
+
+    // Initialize shared storage backend, with eviction unless it's served read-only
+    let storage = if config.read_only {
+        storage::open_read_only(&config.cache_dir)?
+    } else {
+        storage::create_storage_with_eviction(&config.cache_dir, eviction_config.clone())?
+    }
+    .with_hash_algorithm(config.hash_algorithm)
+    .with_dedup(file_config.is_some_and(|fc| fc.cache.dedup));
+    // Keep large and cold objects in a second directory, e.g. on a bigger, slower disk
+    let storage = match file_config {
+        Some(fc) => match fc.cache.tier_config()? {
+            Some(tiers) => storage.with_cold_tier(tiers)?,
+            None => storage,
+        },
+        None => storage,
+    };
     let storage = Arc::new(storage);
 
-    // Spawn background eviction task
-    let eviction_handle = {
+    // Spawn background eviction task (a read-only cache is never evicted)
+    let max_size_bytes = match config.read_only {
+        true => 0,
+        false => eviction_config.max_size_bytes,
+    };
+    let eviction_handle = (!config.read_only).then(|| {
         let bg_config = BackgroundEvictionConfig::from_eviction_config(eviction_config);
+        info!("Background eviction task started");
         spawn_background_eviction(storage.clone(), bg_config)
-    };
-    info!("Background eviction task started");
+    });
 
-    // Start HTTP server (for Metro, Gradle, Nx, TurboRepo)
-    let http_storage = storage.clone();
-    let (http_server, http_port, http_listener) =
-        HttpServer::new_with_port_zero(http_storage).await?;
+    // Upload size limits and daily quotas
+    let upload_limits = Arc::new(match file_config {
+        Some(fc) => UploadLimits::from_config(&fc.limits)?,
+        None => UploadLimits::unlimited(),
+    });
+
+    // Health checking, reflection and transport tuning of the gRPC servers
+    let grpc_config = file_config.map(|fc| fc.grpc.clone()).unwrap_or_default();
+    let grpc_transport = GrpcTransport::from_config(&grpc_config)?;
 
-    info!("HTTP cache server bound to port {}", http_port);
+    // HTTP and gRPC listen on per-process sockets when the config serves them on sockets
+    let (http_socket, grpc_socket) = exec_sockets(file_config);
 
-    let http_handle =
-        tokio::spawn(async move { http_server.run_with_listener(http_listener).await });
+    // All requests to this process's servers are part of the command's invocation
+    let invocation_id = invocation_id(args);
+    let stats = Arc::new(LiveStats::new(max_size_bytes).with_invocation(invocation_id.clone()));
 
-    // Start gRPC server (for Bazel)
+    // Start HTTP server (for Metro, Gradle, Nx, TurboRepo)
+    let http_storage = storage.clone();
+    let http_server = HttpServer::new(0, http_storage)
+        .with_stats(stats.clone())
+        .with_upload_limits(upload_limits.clone())
+        .with_gradle_policy(
+            file_config
+                .map(|fc| fc.build_systems.gradle_machine_specific())
+                .unwrap_or_default(),
+        )
+        .with_webdav(file_config.is_some_and(|fc| fc.http.webdav_enabled));
+
+    let (http_url, http_handle) = match http_socket {
+        #[cfg(unix)]
+        Some(ref socket) => {
+            let listener = unix_socket::bind(socket)
+                .with_context(|| format!("Failed to bind HTTP socket: {}", socket.display()))?;
+            info!("HTTP cache server bound to {}", socket.display());
+            let handle = tokio::spawn(async move { http_server.run_with_listener(listener).await });
+            (None, handle)
+        }
+        _ => {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            let http_port = listener.local_addr()?.port();
+            info!("HTTP cache server bound to port {}", http_port);
+            let handle = tokio::spawn(async move { http_server.run_with_listener(listener).await });
+            (Some(format!("http://127.0.0.1:{}", http_port)), handle)
+        }
+    };
+
+    // Start gRPC server (for Bazel), on its socket or an available port
     let grpc_storage = storage.clone();
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
-    let grpc_port = listener.local_addr()?.port();
+    #[cfg(unix)]
+    let grpc_listener = match grpc_socket {
+        Some(ref socket) => Some(
+            unix_socket::bind(socket)
+                .with_context(|| format!("Failed to bind gRPC socket: {}", socket.display()))?,
+        ),
+        None => None,
+    };
+    let grpc_port = match grpc_socket {
+        Some(_) => 0,
+        None => {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            listener.local_addr()?.port()
+        }
+    };
     let addr: std::net::SocketAddr = format!("127.0.0.1:{}", grpc_port).parse().unwrap();
-    drop(listener);
-
-    info!("Starting gRPC cache server on port {}", grpc_port);
 
+    let grpc_url_str = match grpc_socket {
+        Some(ref socket) => {
+            info!("Starting gRPC cache server on {}", socket.display());
+            unix_socket::grpc_url(socket)
+        }
+        None => {
+            info!("Starting gRPC cache server on port {}", grpc_port);
+            format!("grpc://127.0.0.1:{}", grpc_port)
+        }
+    };
+    let topology = CacheTopology::new(&grpc_url_str, config.upstream.clone());
+    let action_cache_stats = Arc::new(ActionCacheStats::default());
+
+    let grpc_stats = action_cache_stats.clone();
+    let grpc_limits = upload_limits.clone();
+    let validate_outputs = file_config.is_none_or(|fc| fc.build_systems.bazel_validate_outputs());
+    let bazel_limits = Arc::new(
+        file_config
+            .map(|fc| fc.build_systems.bazel_limits(config.hash_algorithm))
+            .transpose()?
+            .unwrap_or_default(),
+    );
+    let bazel_grpc_config = grpc_config.clone();
+    let grpc_requests = stats.requests();
     let grpc_handle = tokio::spawn(async move {
-        let action_cache = BazelActionCacheService::new(grpc_storage.clone());
-        let cas = BazelCasService::new(grpc_storage.clone());
-        let bytestream = BazelByteStreamService::new(grpc_storage.clone());
-        let capabilities = BazelCapabilitiesService::new();
-
-        info!("gRPC server listening on 127.0.0.1:{}", addr.port());
-
-        Server::builder()
+        let action_cache = BazelActionCacheService::new(grpc_storage.clone())
+            .with_stats(grpc_stats)
+            .with_output_validation(validate_outputs)
+            .with_limits(bazel_limits.clone());
+        let cas = BazelCasService::new(grpc_storage.clone())
+            .with_upload_limits(grpc_limits.clone())
+            .with_limits(bazel_limits.clone());
+        let bytestream = BazelByteStreamService::new(grpc_storage.clone())
+            .with_upload_limits(grpc_limits.clone())
+            .with_limits(bazel_limits.clone());
+        let capabilities = BazelCapabilitiesService::new()
+            .with_limits(bazel_limits)
+            .with_upload_limits(grpc_limits);
+
+        let introspection = grpc_introspection::routes(
+            &bazel_grpc_config,
+            crate::bazel::SERVICE_NAMES,
+            &[crate::bazel::proto::FILE_DESCRIPTOR_SET],
+        )
+        .await?;
+
+        let router = grpc_transport
+            .server()
+            .layer(RequestStatsLayer::new(grpc_requests))
+            .add_routes(introspection)
             .add_service(ActionCacheServer::new(action_cache))
             .add_service(ContentAddressableStorageServer::new(cas))
             .add_service(ByteStreamServer::new(bytestream))
-            .add_service(CapabilitiesServer::new(capabilities))
+            .add_service(CapabilitiesServer::new(capabilities));
+
+        #[cfg(unix)]
+        if let Some(listener) = grpc_listener {
+            use tokio_stream::wrappers::UnixListenerStream;
+            return router
+                .serve_with_incoming(UnixListenerStream::new(listener))
+                .await
+                .map_err(|e| anyhow::anyhow!("gRPC server error: {}", e));
+        }
+
+        info!("gRPC server listening on 127.0.0.1:{}", addr.port());
+        router
             .serve(addr)
             .await
             .map_err(|e| anyhow::anyhow!("gRPC server error: {}", e))
     });
 
-    // Build environment variables
-    let mut env_vars = std::collections::HashMap::new();
+    // Unix socket gRPC server for Xcode, when the config asks for one. The socket is
+    // private to this process so it never clashes with a daemon serving the same config.
+    #[cfg(unix)]
+    let xcode_socket = match file_config.and_then(|fc| fc.daemon.socket.as_ref()) {
+        Some(_) => {
+            let path = temp_file("sock");
+            let handle =
+                spawn_xcode_server(&path, storage.clone(), upload_limits.clone(), grpc_config)?;
+            Some((path, handle))
+        }
+        None => None,
+    };
+    #[cfg(unix)]
+    let unix_socket = xcode_socket.as_ref().map(|(path, _)| path.clone());
+    #[cfg(not(unix))]
+    let unix_socket = None;
 
-    // Generate temporary bazelrc file for zero-config Bazel support
-    let bazelrc_path =
-        std::env::temp_dir().join(format!("fabrik-exec-{}.bazelrc", std::process::id()));
-    let grpc_url_str = format!("grpc://127.0.0.1:{}", grpc_port);
-    let bazelrc_content = format!(
-        "# Auto-generated by Fabrik exec\n\
-         # Temporary file for this execution\n\
-         #\n\
-         # Remote cache configuration\n\
-         build --remote_cache={}\n\
-         test --remote_cache={}\n",
-        grpc_url_str, grpc_url_str
-    );
-    std::fs::write(&bazelrc_path, bazelrc_content)
-        .with_context(|| format!("Failed to write bazelrc: {}", bazelrc_path.display()))?;
+    // Build environment variables
+    let mut env_vars = HashMap::new();
+    env_vars.insert(INVOCATION_ID_VAR.to_string(), invocation_id.clone());
+
+    // Generate temporary bazelrc file for zero-config Bazel support (and Gradle's init
+    // script when it's enabled)
+    let topology_lines = config.build_metadata.then(|| topology.bazelrc_lines());
+    let files = ExecFiles::write(
+        &config.build_systems,
+        Some(exec_bazelrc(
+            &grpc_url_str,
+            &invocation_id,
+            topology_lines.as_deref(),
+        )),
+    )?;
 
     // Always export BAZELRC for zero-config Bazel support
-    env_vars.insert("BAZELRC".to_string(), bazelrc_path.display().to_string());
+    if let Some(ref bazelrc_path) = files.bazelrc {
+        env_vars.insert("BAZELRC".to_string(), bazelrc_path.display().to_string());
+    }
 
+    let endpoints = Endpoints {
+        http_url,
+        http_socket: http_socket.clone(),
+        grpc_url: grpc_url_str.clone(),
+        // The S3 API is only served by daemons
+        s3_url: None,
+        unix_socket,
+    };
     if args.export_env {
-        let prefix = &args.env_prefix;
-        env_vars.insert(
-            format!("{}HTTP_URL", prefix),
-            format!("http://127.0.0.1:{}", http_port),
+        export_cache_env(&mut env_vars, &args.env_prefix, &endpoints);
+    }
+
+    let command = inject_build_systems(
+        &config.build_systems,
+        &endpoints,
+        &files,
+        |name| std::env::var(name).ok(),
+        &mut env_vars,
+        &args.command,
+    );
+
+    let status = run_command(&command, &env_vars).await;
+    files.remove();
+    let status = status?;
+
+    if let Some(invocation) = stats.requests().invocation_stats(&invocation_id) {
+        let counters = &invocation.counters;
+        info!(
+            "Cache traffic of invocation {}: {} hits, {} misses, {} writes, {} downloaded, {} uploaded",
+            invocation_id,
+            counters.hits,
+            counters.misses,
+            counters.writes,
+            format_size(invocation.bytes_downloaded),
+            format_size(invocation.bytes_uploaded)
         );
+    }
+
+    if let Some(ref path) = config.build_metadata_file {
+        let report = BuildMetadataReport::new(&topology, &action_cache_stats);
+        match report.write(std::path::Path::new(path)) {
+            Ok(()) => info!("Wrote build metadata to {}", path),
+            Err(e) => tracing::warn!("Failed to write build metadata: {:#}", e),
+        }
+    }
+
+    // Shutdown servers
+    info!("Shutting down cache servers...");
+    http_handle.abort();
+    grpc_handle.abort();
+    #[cfg(unix)]
+    if let Some((path, handle)) = xcode_socket {
+        handle.abort();
+        let _ = std::fs::remove_file(path);
+    }
+    // Shutdown background eviction task
+    info!("Shutting down background eviction task...");
+    if let Some(eviction_handle) = eviction_handle {
+        eviction_handle.shutdown().await;
+    }
+
+    // Give them a moment to cleanup
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    // Only once the servers have stopped listening on them
+    for socket in [http_socket, grpc_socket].into_iter().flatten() {
+        let _ = unix_socket::remove(&socket);
+    }
+
+    if !status.success() {
+        std::process::exit(status.code().unwrap_or(1));
+    }
+
+    Ok(())
+}
+
+/// Add the cache URLs and build tool variables to `env_vars`
+fn export_cache_env(env_vars: &mut HashMap<String, String>, prefix: &str, endpoints: &Endpoints) {
+    if let Some(ref http_url) = endpoints.http_url {
+        env_vars.insert(format!("{}HTTP_URL", prefix), http_url.clone());
+    }
+    if let Some(ref socket) = endpoints.http_socket {
         env_vars.insert(
-            format!("{}GRPC_URL", prefix),
-            format!("grpc://127.0.0.1:{}", grpc_port),
+            format!("{}HTTP_SOCKET", prefix),
+            socket.display().to_string(),
         );
+    }
+    env_vars.insert(format!("{}GRPC_URL", prefix), endpoints.grpc_url.clone());
 
-        // Build tool environment variables (Gradle, Nx, Xcode, TurboRepo, etc.)
-        let build_tool_vars = populate_build_tool_env_vars(
-            format!("http://127.0.0.1:{}", http_port),
-            format!("grpc://127.0.0.1:{}", grpc_port),
-            None, // exec mode doesn't use unix sockets
-        );
-        for (key, value) in build_tool_vars {
-            if key == "TURBO_TEAM" || key == "TURBO_TOKEN" {
-                info!("Auto-generated {} for local development", key);
-            }
-            env_vars.insert(key, value);
+    // Build tool environment variables (Gradle, Nx, Xcode, TurboRepo, etc.)
+    let build_tool_vars = populate_build_tool_env_vars(endpoints);
+    for (key, value) in build_tool_vars {
+        if key == "TURBO_TEAM" || key == "TURBO_TOKEN" {
+            info!("Auto-generated {} for local development", key);
+        }
+        env_vars.insert(key, value);
+    }
+
+    info!("Exported environment variables:");
+    for (key, value) in env_vars.iter() {
+        info!("  {}={}", key, value);
+    }
+}
+
+/// Per-process sockets of the HTTP and gRPC servers of `fabrik exec`, for the protocols
+/// the config serves on sockets (`[daemon] http_socket`, `grpc_socket`)
+///
+/// The configured sockets belong to the daemon, so the command gets its own.
+fn exec_sockets(file_config: Option<&FabrikConfig>) -> (Option<PathBuf>, Option<PathBuf>) {
+    let daemon = file_config.map(|fc| &fc.daemon).filter(|_| cfg!(unix));
+    (
+        daemon
+            .and_then(|daemon| daemon.http_socket.as_ref())
+            .map(|_| temp_file("http.sock")),
+        daemon
+            .and_then(|daemon| daemon.grpc_socket.as_ref())
+            .map(|_| temp_file("grpc.sock")),
+    )
+}
+
+/// Path of a temporary file of this `fabrik exec` process
+fn temp_file(extension: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("fabrik-exec-{}.{}", std::process::id(), extension))
+}
+
+/// Temporary files generated for the command, removed when it exits
+struct ExecFiles {
+    bazelrc: Option<PathBuf>,
+    gradle_init_script: Option<PathBuf>,
+}
+
+impl ExecFiles {
+    /// Paths of the files, without writing them: a bazelrc if `bazelrc` is set, and
+    /// Gradle's init script if Gradle is enabled
+    fn paths(build_systems: &[String], bazelrc: bool) -> Self {
+        let gradle = build_systems.iter().any(|name| name == "gradle");
+        Self {
+            bazelrc: bazelrc.then(|| temp_file("bazelrc")),
+            gradle_init_script: gradle.then(|| temp_file("init.gradle")),
         }
+    }
 
-        info!("Exported environment variables:");
-        for (key, value) in &env_vars {
-            info!("  {}={}", key, value);
+    /// Write the bazelrc (when given) and, if Gradle is enabled, its init script
+    fn write(build_systems: &[String], bazelrc: Option<String>) -> Result<Self> {
+        let files = Self::paths(build_systems, bazelrc.is_some());
+        if let (Some(path), Some(content)) = (&files.bazelrc, bazelrc) {
+            std::fs::write(path, content)
+                .with_context(|| format!("Failed to write bazelrc: {}", path.display()))?;
         }
+        if let Some(ref path) = files.gradle_init_script {
+            std::fs::write(path, super::init::GRADLE_INIT_SCRIPT).with_context(|| {
+                format!("Failed to write Gradle init script: {}", path.display())
+            })?;
+        }
+        Ok(files)
     }
 
-    // Execute user command
-    info!("Executing command: {}", args.command.join(" "));
+    fn remove(&self) {
+        for path in [&self.bazelrc, &self.gradle_init_script]
+            .into_iter()
+            .flatten()
+        {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
 
-    let mut cmd = Command::new(&args.command[0]);
-    cmd.args(&args.command[1..])
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit());
+/// Contents of the bazelrc pointing Bazel at `grpc_url` and tagging its requests with
+/// `invocation_id`, plus `extra` lines
+fn exec_bazelrc(grpc_url: &str, invocation_id: &str, extra: Option<&str>) -> String {
+    let mut content = String::from(
+        "# Auto-generated by Fabrik exec\n\
+         # Temporary file for this execution\n",
+    );
+    // Passing --bazelrc makes Bazel skip the user's ~/.bazelrc
+    if let Some(home) = dirs::home_dir() {
+        content.push_str(&format!(
+            "#\n# User configuration\ntry-import {}\n",
+            home.join(".bazelrc").display()
+        ));
+    }
+    content.push_str(&format!(
+        "#\n\
+         # Remote cache configuration\n\
+         build --remote_cache={}\n\
+         test --remote_cache={}\n",
+        grpc_url, grpc_url
+    ));
+    // Bazel's own invocation ID changes with every Bazel command the command runs
+    content.push_str(&format!(
+        "#\n\
+         # Cache traffic of this command (fabrik stats --invocation)\n\
+         build --remote_header={}={}\n",
+        INVOCATION_HEADER, invocation_id
+    ));
+    if let Some(extra) = extra {
+        // Published in the BuildMetadata event of the Build Event Stream
+        content.push_str("#\n# Cache topology for build dashboards\n");
+        content.push_str(extra);
+    }
+    content
+}
+
+/// Add the variables of the enabled build systems to `env_vars`, returning `command`
+/// with their flags when it runs the build tool itself (Bazel's `--bazelrc`, Gradle's
+/// `--init-script`)
+///
+/// `env` reads the environment the command inherits, so user settings such as
+/// `GRADLE_OPTS` are extended and `TURBO_TEAM` is kept.
+fn inject_build_systems(
+    build_systems: &[String],
+    endpoints: &Endpoints,
+    files: &ExecFiles,
+    env: impl Fn(&str) -> Option<String>,
+    env_vars: &mut HashMap<String, String>,
+    command: &[String],
+) -> Vec<String> {
+    let program = Path::new(&command[0])
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let mut flags = Vec::new();
+    let mut set = |key: &str, value: String| {
+        env_vars.insert(key.to_string(), value);
+    };
 
-    // Set environment variables
-    for (key, value) in &env_vars {
-        cmd.env(key, value);
+    for build_system in build_systems {
+        match build_system.as_str() {
+            "gradle" => {
+                if let Some(url) = http_cache_url(endpoints, "Gradle") {
+                    set("GRADLE_BUILD_CACHE_URL", url);
+                }
+                let opts = env("GRADLE_OPTS").unwrap_or_default();
+                if !opts.contains("org.gradle.caching") {
+                    let caching = "-Dorg.gradle.caching=true";
+                    set(
+                        "GRADLE_OPTS",
+                        format!("{} {}", opts, caching).trim().to_string(),
+                    );
+                }
+                if let Some(ref script) = files.gradle_init_script {
+                    if matches!(program, "gradle" | "gradlew") {
+                        flags.push("--init-script".to_string());
+                        flags.push(script.display().to_string());
+                    }
+                }
+            }
+            "bazel" => {
+                if let Some(ref bazelrc) = files.bazelrc {
+                    // A startup option, so it goes before the Bazel command
+                    if matches!(program, "bazel" | "bazelisk") {
+                        flags.push(format!("--bazelrc={}", bazelrc.display()));
+                    }
+                }
+            }
+            "nx" => {
+                if let Some(url) = http_cache_url(endpoints, "Nx") {
+                    set("NX_SELF_HOSTED_REMOTE_CACHE_SERVER", url);
+                }
+            }
+            "turborepo" => {
+                let Some(url) = http_cache_url(endpoints, "TurboRepo") else {
+                    continue;
+                };
+                set("TURBO_API", url);
+                if env("TURBO_TEAM").is_none() {
+                    set("TURBO_TEAM", default_turbo_team().to_string());
+                }
+                if env("TURBO_TOKEN").is_none() {
+                    set("TURBO_TOKEN", generate_turbo_token());
+                }
+            }
+            "sccache" => match endpoints.s3_url {
+                Some(ref s3_url) => {
+                    set("SCCACHE_ENDPOINT", s3_url.clone());
+                    set("SCCACHE_S3_USE_SSL", "false".to_string());
+                    set("SCCACHE_S3_NO_CREDENTIALS", "true".to_string());
+                    if env("SCCACHE_BUCKET").is_none() {
+                        set("SCCACHE_BUCKET", "sccache".to_string());
+                    }
+                    if env("SCCACHE_REGION").is_none() {
+                        set("SCCACHE_REGION", "us-east-1".to_string());
+                    }
+                }
+                None => tracing::warn!(
+                    "sccache needs the S3 API; set [daemon] s3_port and run a daemon"
+                ),
+            },
+            _ => {}
+        }
+    }
+
+    // Xcode compilation caching, when the Xcode socket is served
+    if let Some(ref socket) = endpoints.unix_socket {
+        set("COMPILATION_CACHE_ENABLE_CACHING", "YES".to_string());
+        set("COMPILATION_CACHE_ENABLE_PLUGIN", "YES".to_string());
+        set(
+            "COMPILATION_CACHE_REMOTE_SERVICE_PATH",
+            socket.display().to_string(),
+        );
+    }
+
+    let mut command = command.to_vec();
+    command.splice(1..1, flags);
+    command
+}
+
+/// HTTP cache URL for `tool`, None (with a warning) when HTTP is only served on a Unix
+/// socket, which the HTTP build tools can't connect to
+fn http_cache_url(endpoints: &Endpoints, tool: &str) -> Option<String> {
+    if endpoints.http_url.is_none() {
+        tracing::warn!(
+            "{} can't use the HTTP cache on a Unix socket; remove [daemon] http_socket to cache its builds",
+            tool
+        );
     }
+    endpoints.http_url.clone()
+}
+
+/// Run the user command with `env_vars` set and wait for it
+async fn run_command(command: &[String], env_vars: &HashMap<String, String>) -> Result<ExitStatus> {
+    info!("Executing command: {}", command.join(" "));
 
-    let status = cmd
+    let status = Command::new(&command[0])
+        .args(&command[1..])
+        .envs(env_vars)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
         .status()
         .await
-        .with_context(|| format!("Failed to execute command: {}", args.command[0]))?;
+        .with_context(|| format!("Failed to execute command: {}", command[0]))?;
 
     info!("Command completed with status: {}", status);
+    Ok(status)
+}
 
-    // Cleanup temporary bazelrc file
-    if bazelrc_path.exists() {
-        let _ = std::fs::remove_file(&bazelrc_path);
+/// Serve the Xcode CAS and key-value services on a Unix socket at `path`
+#[cfg(unix)]
+fn spawn_xcode_server(
+    path: &Path,
+    storage: Arc<storage::FilesystemStorage>,
+    upload_limits: Arc<UploadLimits>,
+    grpc_config: GrpcConfig,
+) -> Result<tokio::task::JoinHandle<Result<()>>> {
+    use crate::xcode::{cas_server, keyvalue_server, CasService, KeyValueService};
+    use tokio_stream::wrappers::UnixListenerStream;
+
+    if path.exists() {
+        std::fs::remove_file(path)?;
     }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind Xcode socket: {}", path.display()))?;
+    info!("Xcode cache server listening on {}", path.display());
+
+    let cas = CasService::new(storage.clone()).with_upload_limits(upload_limits.clone());
+    let keyvalue = KeyValueService::new(storage).with_upload_limits(upload_limits);
+    let mut server = GrpcTransport::from_config(&grpc_config)?.server();
+
+    Ok(tokio::spawn(async move {
+        let introspection = grpc_introspection::routes(
+            &grpc_config,
+            crate::xcode::SERVICE_NAMES,
+            &[crate::xcode::proto::FILE_DESCRIPTOR_SET],
+        )
+        .await?;
+
+        server
+            .add_routes(introspection)
+            .add_service(cas_server(cas))
+            .add_service(keyvalue_server(keyvalue))
+            .serve_with_incoming(UnixListenerStream::new(listener))
+            .await
+            .map_err(|e| anyhow::anyhow!("Unix socket gRPC server error: {}", e))
+    }))
+}
 
-    // Shutdown servers
-    info!("Shutting down cache servers...");
-    http_handle.abort();
-    grpc_handle.abort();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Shutdown background eviction task
-    info!("Shutting down background eviction task...");
-    eviction_handle.shutdown().await;
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
 
-    // Give them a moment to cleanup
-    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    #[test]
+    fn test_inject_build_systems() {
+        let endpoints = Endpoints {
+            http_url: Some("http://127.0.0.1:8080".to_string()),
+            http_socket: None,
+            grpc_url: "grpc://127.0.0.1:9090".to_string(),
+            s3_url: Some("http://127.0.0.1:9000".to_string()),
+            unix_socket: None,
+        };
+        let files = ExecFiles {
+            bazelrc: Some(PathBuf::from("/tmp/exec.bazelrc")),
+            gradle_init_script: Some(PathBuf::from("/tmp/exec.init.gradle")),
+        };
+        let env = |name: &str| match name {
+            "GRADLE_OPTS" => Some("-Xmx2g".to_string()),
+            "TURBO_TEAM" => Some("acme".to_string()),
+            _ => None,
+        };
+
+        let mut env_vars = HashMap::new();
+        let command = inject_build_systems(
+            &strings(&["gradle", "bazel", "turborepo", "sccache"]),
+            &endpoints,
+            &files,
+            env,
+            &mut env_vars,
+            &strings(&["./gradlew", "build"]),
+        );
+        assert_eq!(
+            command,
+            strings(&[
+                "./gradlew",
+                "--init-script",
+                "/tmp/exec.init.gradle",
+                "build"
+            ])
+        );
+        assert_eq!(env_vars["GRADLE_OPTS"], "-Xmx2g -Dorg.gradle.caching=true");
+        assert_eq!(env_vars["GRADLE_BUILD_CACHE_URL"], "http://127.0.0.1:8080");
+        assert_eq!(env_vars["TURBO_API"], "http://127.0.0.1:8080");
+        assert!(!env_vars.contains_key("TURBO_TEAM"), "user's team is kept");
+        assert_eq!(env_vars["SCCACHE_ENDPOINT"], "http://127.0.0.1:9000");
+        assert!(!env_vars.contains_key("NX_SELF_HOSTED_REMOTE_CACHE_SERVER"));
+        assert!(!env_vars.contains_key("COMPILATION_CACHE_REMOTE_SERVICE_PATH"));
+
+        // Bazel's startup option goes before the command; disabled systems get nothing
+        let mut env_vars = HashMap::new();
+        let command = inject_build_systems(
+            &strings(&["bazel"]),
+            &Endpoints {
+                unix_socket: Some(PathBuf::from("/tmp/xcode.sock")),
+                ..endpoints.clone()
+            },
+            &files,
+            |_| None,
+            &mut env_vars,
+            &strings(&["bazel", "build", "//..."]),
+        );
+        assert_eq!(
+            command,
+            strings(&["bazel", "--bazelrc=/tmp/exec.bazelrc", "build", "//..."])
+        );
+        assert!(!env_vars.contains_key("GRADLE_OPTS"));
+        assert_eq!(
+            env_vars["COMPILATION_CACHE_REMOTE_SERVICE_PATH"],
+            "/tmp/xcode.sock"
+        );
 
-    if !status.success() {
-        std::process::exit(status.code().unwrap_or(1));
+        // HTTP tools can't reach a cache served on a socket
+        let mut env_vars = HashMap::new();
+        inject_build_systems(
+            &strings(&["gradle", "nx", "turborepo"]),
+            &Endpoints {
+                http_url: None,
+                http_socket: Some(PathBuf::from("/tmp/http.sock")),
+                unix_socket: None,
+                ..endpoints
+            },
+            &files,
+            |_| None,
+            &mut env_vars,
+            &strings(&["nx", "build"]),
+        );
+        assert!(!env_vars.contains_key("GRADLE_BUILD_CACHE_URL"));
+        assert!(!env_vars.contains_key("NX_SELF_HOSTED_REMOTE_CACHE_SERVER"));
+        assert!(!env_vars.contains_key("TURBO_TOKEN"));
+        assert_eq!(env_vars["GRADLE_OPTS"], "-Dorg.gradle.caching=true");
     }
 
-    Ok(())
+    #[test]
+    fn test_bazelrc_tags_invocation() {
+        let bazelrc = exec_bazelrc("grpc://127.0.0.1:9000", "ci-42", None);
+        assert!(bazelrc.contains("build --remote_cache=grpc://127.0.0.1:9000\n"));
+        assert!(bazelrc.contains("build --remote_header=x-fabrik-invocation-id=ci-42\n"));
+    }
 }
@@ -1,8 +1,98 @@
 use anyhow::{Context, Result};
 use std::fs;
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 use crate::cli::InitArgs;
+use crate::config_discovery::BAZELRC_IMPORT;
+
+/// Build systems `fabrik init` recognizes in a project
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BuildSystem {
+    Gradle,
+    Bazel,
+    Nx,
+    Turborepo,
+    Xcode,
+    Cargo,
+}
+
+impl BuildSystem {
+    const ALL: [BuildSystem; 6] = [
+        BuildSystem::Gradle,
+        BuildSystem::Bazel,
+        BuildSystem::Nx,
+        BuildSystem::Turborepo,
+        BuildSystem::Xcode,
+        BuildSystem::Cargo,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            BuildSystem::Gradle => "Gradle",
+            BuildSystem::Bazel => "Bazel",
+            BuildSystem::Nx => "Nx",
+            BuildSystem::Turborepo => "TurboRepo",
+            BuildSystem::Xcode => "Xcode",
+            BuildSystem::Cargo => "Cargo",
+        }
+    }
+
+    /// Entry of `build_systems.enabled` serving it; Xcode talks to a Unix socket instead
+    fn adapter(self) -> Option<&'static str> {
+        match self {
+            BuildSystem::Gradle => Some("gradle"),
+            BuildSystem::Bazel => Some("bazel"),
+            BuildSystem::Nx => Some("nx"),
+            BuildSystem::Turborepo => Some("turborepo"),
+            BuildSystem::Xcode => None,
+            BuildSystem::Cargo => Some("sccache"),
+        }
+    }
+
+    /// Whether `file`, in the project root, marks a project of this build system
+    fn is_marker(self, file: &str) -> bool {
+        match self {
+            BuildSystem::Gradle => matches!(
+                file,
+                "build.gradle" | "build.gradle.kts" | "settings.gradle" | "settings.gradle.kts"
+            ),
+            BuildSystem::Bazel => matches!(file, "WORKSPACE" | "WORKSPACE.bazel" | "MODULE.bazel"),
+            BuildSystem::Nx => file == "nx.json",
+            BuildSystem::Turborepo => file == "turbo.json",
+            BuildSystem::Xcode => {
+                file == "Package.swift"
+                    || file.ends_with(".xcodeproj")
+                    || file.ends_with(".xcworkspace")
+            }
+            BuildSystem::Cargo => file == "Cargo.toml",
+        }
+    }
+
+    fn from_adapter(name: &str) -> Option<Self> {
+        match name {
+            "xcode" => Some(BuildSystem::Xcode),
+            "cargo" => Some(BuildSystem::Cargo),
+            _ => Self::ALL.into_iter().find(|bs| bs.adapter() == Some(name)),
+        }
+    }
+}
+
+/// Build systems of the project in `dir`, from the files in its root
+fn detect_build_systems(dir: &Path) -> Vec<BuildSystem> {
+    let files: Vec<String> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    BuildSystem::ALL
+        .into_iter()
+        .filter(|bs| files.iter().any(|file| bs.is_marker(file)))
+        .collect()
+}
 
 pub fn run(args: InitArgs) -> Result<()> {
     println!("🚀 Fabrik Initialization\n");
@@ -22,6 +112,31 @@ pub fn run(args: InitArgs) -> Result<()> {
         }
     }
 
+    // Detect the project's build systems, unless they're given
+    let build_systems = match &args.build_systems {
+        Some(names) => names
+            .iter()
+            .map(|name| {
+                BuildSystem::from_adapter(name).with_context(|| {
+                    format!(
+                        "Unknown build system '{}' (use gradle, bazel, nx, turborepo, xcode or cargo)",
+                        name
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => {
+            let detected = detect_build_systems(Path::new("."));
+            if detected.is_empty() {
+                println!("🔎 No build system detected; keeping the default adapters\n");
+            } else {
+                let names: Vec<&str> = detected.iter().map(|bs| bs.name()).collect();
+                println!("🔎 Detected: {}\n", names.join(", "));
+            }
+            detected
+        }
+    };
+
     // Get configuration values
     let cache_dir = if let Some(dir) = args.cache_dir {
         dir
@@ -59,7 +174,77 @@ pub fn run(args: InitArgs) -> Result<()> {
         }
     };
 
+    let has_snippets = build_systems
+        .iter()
+        .any(|bs| matches!(bs, BuildSystem::Bazel | BuildSystem::Gradle));
+    let write_snippets = if args.write_snippets || !has_snippets || args.non_interactive {
+        args.write_snippets && has_snippets
+    } else {
+        print!("Configure Bazel/Gradle to use the daemon (.bazelrc, Gradle init script)? [y/N] ");
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        input.trim().eq_ignore_ascii_case("y")
+    };
+
     // Generate fabrik.toml content
+    let config = render_config(
+        &cache_dir,
+        &max_cache_size,
+        upstream_url.as_deref(),
+        &build_systems,
+    );
+
+    // Write fabrik.toml
+    fs::write("fabrik.toml", &config).context("Failed to write fabrik.toml")?;
+
+    println!("\n✅ Created fabrik.toml");
+    println!("\n📄 Configuration:");
+    println!("   Cache directory: {}", cache_dir);
+    println!("   Max cache size: {}", max_cache_size);
+    if let Some(ref url) = upstream_url {
+        println!("   Remote cache: {}", url);
+    }
+    if !build_systems.is_empty() {
+        let names: Vec<&str> = build_systems.iter().map(|bs| bs.name()).collect();
+        println!("   Build systems: {}", names.join(", "));
+    }
+
+    if write_snippets {
+        if build_systems.contains(&BuildSystem::Bazel) {
+            let bazelrc = Path::new(".bazelrc");
+            if add_bazelrc_import(bazelrc)? {
+                println!("\n✅ Added `{}` to .bazelrc", BAZELRC_IMPORT);
+            }
+        }
+        if build_systems.contains(&BuildSystem::Gradle) {
+            let init_script = write_gradle_init_script(&gradle_user_home()?)?;
+            println!("\n✅ Wrote Gradle init script: {}", init_script.display());
+        }
+    }
+
+    // Show next steps
+    println!("\n🎯 Next Steps:");
+    println!("   1. Verify your configuration:");
+    println!("      fabrik doctor");
+    println!();
+    println!("   2. Navigate to your project and start building:");
+    println!("      cd ~/your-project");
+    println!("      gradle build  # or your build command");
+    println!();
+    println!("   3. The daemon will start automatically and cache your builds!");
+
+    Ok(())
+}
+
+/// Contents of the generated fabrik.toml
+fn render_config(
+    cache_dir: &str,
+    max_cache_size: &str,
+    upstream_url: Option<&str>,
+    build_systems: &[BuildSystem],
+) -> String {
     let mut config = format!(
         r#"# Fabrik configuration
 # See https://github.com/tuist/fabrik for more information
@@ -71,7 +256,7 @@ max_size = "{}"
         cache_dir, max_cache_size
     );
 
-    if let Some(ref url) = upstream_url {
+    if let Some(url) = upstream_url {
         config.push_str(&format!(
             r#"
 # Upstream cache configuration
@@ -83,30 +268,105 @@ timeout = "30s"
         ));
     }
 
-    // Write fabrik.toml
-    fs::write("fabrik.toml", &config).context("Failed to write fabrik.toml")?;
+    let adapters: Vec<String> = build_systems
+        .iter()
+        .filter_map(|bs| bs.adapter())
+        .map(|adapter| format!("\"{}\"", adapter))
+        .collect();
+    if !adapters.is_empty() {
+        config.push_str(&format!(
+            r#"
+# Adapters for the build systems detected in this project
+[build_systems]
+enabled = [{}]
+"#,
+            adapters.join(", ")
+        ));
+    }
 
-    println!("\n✅ Created fabrik.toml");
-    println!("\n📄 Configuration:");
-    println!("   Cache directory: {}", cache_dir);
-    println!("   Max cache size: {}", max_cache_size);
-    if let Some(ref url) = upstream_url {
-        println!("   Remote cache: {}", url);
+    if build_systems.contains(&BuildSystem::Xcode) {
+        // The socket replaces the HTTP and gRPC servers, so it's only on by default when
+        // Xcode is the project's only build system
+        let comment = if adapters.is_empty() { "" } else { "# " };
+        config.push_str(&format!(
+            r#"
+# Xcode compilation cache (Xcode 16+). The daemon then serves ONLY this Unix socket,
+# without the HTTP and gRPC servers other build systems use
+{}[daemon]
+{}socket = ".fabrik/xcode.sock"
+"#,
+            comment, comment
+        ));
     }
 
-    // Show next steps
-    println!("\n🎯 Next Steps:");
-    println!("   1. Verify your configuration:");
-    println!("      fabrik doctor");
-    println!();
-    println!("   2. Navigate to your project and start building:");
-    println!("      cd ~/your-project");
-    println!("      gradle build  # or your build command");
-    println!();
-    println!("   3. The daemon will start automatically and cache your builds!");
+    config
+}
 
-    Ok(())
+/// Append the import of the daemon's bazelrc to `bazelrc`; false if it's already there
+fn add_bazelrc_import(bazelrc: &Path) -> Result<bool> {
+    let existing = match fs::read_to_string(bazelrc) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", bazelrc.display())),
+    };
+    if existing.lines().any(|line| line.trim() == BAZELRC_IMPORT) {
+        return Ok(false);
+    }
+
+    let mut content = existing;
+    if !content.is_empty() {
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push('\n');
+    }
+    content.push_str("# Fabrik remote cache (written by the daemon when it starts)\n");
+    content.push_str(BAZELRC_IMPORT);
+    content.push('\n');
+    fs::write(bazelrc, content)
+        .with_context(|| format!("Failed to write {}", bazelrc.display()))?;
+    Ok(true)
+}
+
+/// `GRADLE_USER_HOME`, or `~/.gradle`
+fn gradle_user_home() -> Result<PathBuf> {
+    match std::env::var_os("GRADLE_USER_HOME") {
+        Some(home) => Ok(PathBuf::from(home)),
+        None => dirs::home_dir()
+            .map(|home| home.join(".gradle"))
+            .context("Failed to determine home directory"),
+    }
+}
+
+/// Write an init script making every Gradle build use the daemon's cache while
+/// `GRADLE_BUILD_CACHE_URL` is exported (by `fabrik activate`)
+fn write_gradle_init_script(gradle_home: &Path) -> Result<PathBuf> {
+    let init_dir = gradle_home.join("init.d");
+    fs::create_dir_all(&init_dir)
+        .with_context(|| format!("Failed to create {}", init_dir.display()))?;
+
+    let path = init_dir.join("fabrik.init.gradle");
+    fs::write(&path, GRADLE_INIT_SCRIPT)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Also passed with `--init-script` to Gradle builds run by `fabrik exec`
+pub(crate) const GRADLE_INIT_SCRIPT: &str = r#"// Written by Fabrik: use the Fabrik cache as remote build cache while
+// GRADLE_BUILD_CACHE_URL is exported (by `fabrik activate` or `fabrik exec`)
+def fabrikUrl = System.getenv("GRADLE_BUILD_CACHE_URL")
+if (fabrikUrl) {
+    gradle.settingsEvaluated { settings ->
+        settings.buildCache {
+            remote(HttpBuildCache) {
+                url = "${fabrikUrl}/cache/"
+                allowInsecureProtocol = true
+                push = true
+            }
+        }
+    }
 }
+"#;
 
 fn prompt(message: &str) -> Result<String> {
     print!("{}: ", message);
@@ -132,3 +392,77 @@ fn prompt_with_default(message: &str, default: &str) -> Result<String> {
         Ok(trimmed.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FabrikConfig;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_detect_build_systems() {
+        let temp = TempDir::new().unwrap();
+        assert!(detect_build_systems(temp.path()).is_empty());
+
+        fs::write(temp.path().join("MODULE.bazel"), "").unwrap();
+        fs::write(temp.path().join("settings.gradle.kts"), "").unwrap();
+        fs::create_dir(temp.path().join("App.xcodeproj")).unwrap();
+        fs::write(temp.path().join("README.md"), "").unwrap();
+
+        assert_eq!(
+            detect_build_systems(temp.path()),
+            vec![BuildSystem::Gradle, BuildSystem::Bazel, BuildSystem::Xcode]
+        );
+    }
+
+    #[test]
+    fn test_render_config_enables_detected_adapters() {
+        let content = render_config(
+            ".fabrik/cache",
+            "5GB",
+            Some("grpc://cache.example.com:7070"),
+            &[BuildSystem::Nx, BuildSystem::Cargo, BuildSystem::Xcode],
+        );
+        let config: FabrikConfig = toml::from_str(&content).unwrap();
+        assert_eq!(config.build_systems.enabled, vec!["nx", "sccache"]);
+        assert_eq!(config.upstream.len(), 1);
+        // Xcode's socket would turn off the other adapters' servers
+        assert!(config.daemon.socket.is_none());
+        assert!(content.contains("# socket = \".fabrik/xcode.sock\""));
+
+        let content = render_config(".fabrik/cache", "5GB", None, &[BuildSystem::Xcode]);
+        let config: FabrikConfig = toml::from_str(&content).unwrap();
+        assert_eq!(config.daemon.socket.as_deref(), Some(".fabrik/xcode.sock"));
+
+        // Nothing detected keeps the defaults
+        let content = render_config(".fabrik/cache", "5GB", None, &[]);
+        assert!(!content.contains("[build_systems]"));
+    }
+
+    #[test]
+    fn test_add_bazelrc_import_once() {
+        let temp = TempDir::new().unwrap();
+        let bazelrc = temp.path().join(".bazelrc");
+        fs::write(&bazelrc, "build --jobs=8").unwrap();
+
+        assert!(add_bazelrc_import(&bazelrc).unwrap());
+        assert!(!add_bazelrc_import(&bazelrc).unwrap());
+        assert_eq!(
+            fs::read_to_string(&bazelrc).unwrap(),
+            format!(
+                "build --jobs=8\n\n# Fabrik remote cache (written by the daemon when it starts)\n{}\n",
+                BAZELRC_IMPORT
+            )
+        );
+    }
+
+    #[test]
+    fn test_write_gradle_init_script() {
+        let temp = TempDir::new().unwrap();
+        let path = write_gradle_init_script(temp.path()).unwrap();
+        assert_eq!(path, temp.path().join("init.d/fabrik.init.gradle"));
+        assert!(fs::read_to_string(path)
+            .unwrap()
+            .contains("System.getenv(\"GRADLE_BUILD_CACHE_URL\")"));
+    }
+}
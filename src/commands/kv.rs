@@ -5,9 +5,13 @@ use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::cli::{KvArgs, KvCommand};
-use crate::cli_utils::fabrik_prefix;
+use crate::cli_utils::{
+    copy_to_stdout, fabrik_prefix, json_output, spool_stdin, ErrorCategory, STDIO,
+};
+use crate::error::FabrikError;
 use crate::eviction::EvictionConfig;
-use crate::storage::{default_cache_dir, FilesystemStorage, Storage};
+use crate::hashing::HashAlgorithm;
+use crate::storage::{default_cache_dir, open_storage, Storage};
 
 // JSON output structures
 #[derive(Serialize, Deserialize)]
@@ -51,7 +55,13 @@ pub async fn run(args: &KvArgs) -> Result<()> {
 
     // Use default eviction config for CLI commands
     let eviction_config = EvictionConfig::default();
-    let storage = FilesystemStorage::with_eviction(&cache_dir, Some(eviction_config))?;
+    let storage = open_storage(
+        &cache_dir,
+        args.config_metadata_backend.parse()?,
+        args.config_embedded_max_objects,
+        Some(eviction_config),
+    )?;
+    let storage = storage.as_ref();
 
     match &args.command {
         KvCommand::Get {
@@ -59,7 +69,16 @@ pub async fn run(args: &KvArgs) -> Result<()> {
             output,
             verbose,
             json,
-        } => get(&storage, key, output.as_deref(), *verbose, *json).await,
+        } => {
+            get(
+                storage,
+                key,
+                output.as_deref(),
+                *verbose,
+                json_output(*json),
+            )
+            .await
+        }
         KvCommand::Put {
             key,
             value,
@@ -68,23 +87,26 @@ pub async fn run(args: &KvArgs) -> Result<()> {
             json,
         } => {
             put(
-                &storage,
+                storage,
+                &cache_dir,
                 key,
                 value.as_deref(),
                 file.as_deref(),
                 *verbose,
-                *json,
+                json_output(*json),
             )
             .await
         }
-        KvCommand::Exists { key, json } => exists(&storage, key, *json).await,
-        KvCommand::Delete { key, force, json } => delete(&storage, key, *force, *json).await,
+        KvCommand::Exists { key, json } => exists(storage, key, json_output(*json)).await,
+        KvCommand::Delete { key, force, json } => {
+            delete(storage, key, *force, json_output(*json)).await
+        }
         KvCommand::List {
             prefix,
             verbose,
             json,
-        } => list(&storage, prefix.as_deref(), *verbose, *json).await,
-        KvCommand::Stats { json } => stats(&storage, *json).await,
+        } => list(storage, prefix.as_deref(), *verbose, json_output(*json)).await,
+        KvCommand::Stats { json } => stats(storage, json_output(*json)).await,
     }
 }
 
@@ -99,62 +121,68 @@ fn bytes_to_key(bytes: &[u8]) -> Result<String> {
     Ok(s.strip_prefix("kv:").unwrap_or(&s).to_string())
 }
 
-/// Get a value by key
+/// Get a value by key, streamed to a file or stdout
 async fn get(
-    storage: &FilesystemStorage,
+    storage: &dyn Storage,
     key: &str,
     output_path: Option<&str>,
     verbose: bool,
     json: bool,
 ) -> Result<()> {
-    use std::fs;
-    use std::io::{self, Write};
+    use std::fs::{self, File};
+    use std::io;
 
+    let output_path = output_path.filter(|path| *path != STDIO);
     if verbose && !json {
-        println!("{} Retrieving key: {}", fabrik_prefix(), key);
+        // Keep stdout for the value itself
+        match output_path {
+            Some(_) => println!("{} Retrieving key: {}", fabrik_prefix(), key),
+            None => eprintln!("{} Retrieving key: {}", fabrik_prefix(), key),
+        }
     }
 
-    let data = storage
-        .get(&key_to_bytes(key))
-        .with_context(|| format!("Failed to retrieve key: {}", key))?;
+    let mut reader = storage
+        .open(&key_to_bytes(key))
+        .with_context(|| format!("Failed to retrieve key: {}", key))?
+        .ok_or_else(|| FabrikError::not_found(format!("Key not found: {}", key)))?;
 
-    if let Some(data) = data {
-        match output_path {
-            Some(path) => {
-                fs::write(path, &data).with_context(|| format!("Failed to write to: {}", path))?;
-
-                if json {
-                    let output = GetOutput {
-                        key: key.to_string(),
-                        value_bytes: data.len(),
-                        success: true,
-                    };
-                    println!("{}", serde_json::to_string(&output)?);
-                } else {
-                    println!(
-                        "{} Value retrieved: {} ({} bytes)",
-                        fabrik_prefix(),
-                        key,
-                        data.len()
-                    );
-                    println!("{} Written to: {}", fabrik_prefix(), path);
-                }
-            }
-            None => {
-                // Write to stdout
-                io::stdout().write_all(&data)?;
-                io::stdout().flush()?;
-            }
+    let Some(path) = output_path else {
+        copy_to_stdout(&mut reader).context("Failed to write to stdout")?;
+        return Ok(());
+    };
+
+    let mut file = File::create(path).with_context(|| format!("Failed to write to: {}", path))?;
+    let size = match io::copy(&mut reader, &mut file) {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = fs::remove_file(path);
+            return Err(e).with_context(|| format!("Failed to write to: {}", path));
         }
-        Ok(())
+    };
+
+    if json {
+        let output = GetOutput {
+            key: key.to_string(),
+            value_bytes: size as usize,
+            success: true,
+        };
+        println!("{}", serde_json::to_string(&output)?);
     } else {
-        anyhow::bail!("Key not found: {}", key);
+        println!(
+            "{} Value retrieved: {} ({} bytes)",
+            fabrik_prefix(),
+            key,
+            size
+        );
+        println!("{} Written to: {}", fabrik_prefix(), path);
     }
+    Ok(())
 }
 
-/// Put a key-value pair
+/// Put a key-value pair, the value given inline or streamed from a file or stdin (`-`)
 async fn put(
-    storage: &FilesystemStorage,
+    storage: &dyn Storage,
+    cache_dir: &std::path::Path,
     key: &str,
     value: Option<&str>,
     file: Option<&str>,
@@ -162,29 +190,43 @@ async fn put(
     json: bool,
 ) -> Result<()> {
     use std::fs;
-
-    let data = if let Some(value_str) = value {
-        value_str.as_bytes().to_vec()
-    } else if let Some(file_path) = file {
-        fs::read(file_path).with_context(|| format!("Failed to read file: {}", file_path))?
-    } else {
-        anyhow::bail!("Either value or --file must be provided");
-    };
-
-    let data_len = data.len();
+    use std::path::Path;
 
     if verbose && !json {
         println!("{} Storing key: {}", fabrik_prefix(), key);
     }
 
-    storage
-        .put(&key_to_bytes(key), &data)
-        .with_context(|| format!("Failed to store key: {}", key))?;
+    let data_len = if let Some(value_str) = value {
+        storage
+            .put(&key_to_bytes(key), value_str.as_bytes())
+            .with_context(|| format!("Failed to store key: {}", key))?;
+        value_str.len() as u64
+    } else if let Some(file_path) = file {
+        // Stdin is spooled to a file first, so it can be streamed in like one
+        let spooled;
+        let path = if file_path == STDIO {
+            spooled = spool_stdin(cache_dir, HashAlgorithm::default())
+                .context("Failed to read stdin")?
+                .0;
+            spooled.path()
+        } else {
+            Path::new(file_path)
+        };
+        let size = fs::metadata(path)
+            .with_context(|| format!("Failed to read file: {}", file_path))?
+            .len();
+        storage
+            .put_file(&key_to_bytes(key), path, None)
+            .with_context(|| format!("Failed to store key: {}", key))?;
+        size
+    } else {
+        anyhow::bail!("Either value or --file must be provided");
+    };
 
     if json {
         let output = PutOutput {
             key: key.to_string(),
-            value_bytes: data_len,
+            value_bytes: data_len as usize,
             success: true,
         };
         println!("{}", serde_json::to_string(&output)?);
@@ -197,7 +239,7 @@ async fn put(
 }
 
 /// Check if a key exists
-async fn exists(storage: &FilesystemStorage, key: &str, json: bool) -> Result<()> {
+async fn exists(storage: &dyn Storage, key: &str, json: bool) -> Result<()> {
     let exists = storage
         .exists(&key_to_bytes(key))
         .with_context(|| format!("Failed to check existence: {}", key))?;
@@ -208,18 +250,22 @@ async fn exists(storage: &FilesystemStorage, key: &str, json: bool) -> Result<()
             exists,
         };
         println!("{}", serde_json::to_string(&output)?);
-        std::process::exit(if exists { 0 } else { 1 });
+        std::process::exit(if exists {
+            0
+        } else {
+            ErrorCategory::Miss.exit_code().into()
+        });
     } else if exists {
         println!("{} Key exists: {}", fabrik_prefix(), key);
         std::process::exit(0);
     } else {
         println!("{} Key not found: {}", fabrik_prefix(), key);
-        std::process::exit(1);
+        std::process::exit(ErrorCategory::Miss.exit_code().into());
     }
 }
 
 /// Delete a key-value pair
-async fn delete(storage: &FilesystemStorage, key: &str, force: bool, json: bool) -> Result<()> {
+async fn delete(storage: &dyn Storage, key: &str, force: bool, json: bool) -> Result<()> {
     use std::io::{self, Write};
 
     if !force && !json {
@@ -254,7 +300,7 @@ async fn delete(storage: &FilesystemStorage, key: &str, force: bool, json: bool)
 
 /// List all keys (optionally filtered by prefix)
 async fn list(
-    storage: &FilesystemStorage,
+    storage: &dyn Storage,
     prefix: Option<&str>,
     verbose: bool,
     json: bool,
@@ -328,7 +374,7 @@ async fn list(
 }
 
 /// Show KV storage statistics
-async fn stats(storage: &FilesystemStorage, json: bool) -> Result<()> {
+async fn stats(storage: &dyn Storage, json: bool) -> Result<()> {
     let all_ids = storage.list_ids()?;
 
     // Filter for KV entries
@@ -2,10 +2,13 @@ pub mod activate;
 pub mod auth;
 pub mod cache; // Deprecated - kept for backward compat during migration
 pub mod cas;
+pub mod complete;
+pub mod completions; // Shell completion scripts and man pages
 pub mod config;
 pub mod daemon;
 pub mod deactivate;
 pub mod doctor;
+pub mod dry_run;
 pub mod exec;
 pub mod health;
 pub mod init;
@@ -13,3 +16,5 @@ pub mod kv;
 pub mod p2p;
 pub mod run;
 pub mod server;
+pub mod stats;
+pub mod top; // Live dashboard of a daemon
@@ -1,10 +1,16 @@
 use crate::cli::{P2pArgs, P2pCommand};
+use crate::cli_utils::{format_size, json_output};
 use crate::config::FabrikConfig;
 use crate::config_discovery::load_config_with_discovery;
+use crate::eviction::EvictionConfig;
+use crate::grpc_transport::GrpcTransport;
 use crate::p2p::consent::ConsentManager;
-use crate::p2p::P2PManager;
+use crate::p2p::stats::PeerStats;
+use crate::p2p::{P2PClient, P2PManager, Peer, PeerInfo};
 use anyhow::{Context, Result};
 use rand::Rng;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 pub async fn run(args: P2pArgs) -> Result<()> {
@@ -25,8 +31,17 @@ pub async fn run(args: P2pArgs) -> Result<()> {
     }
 
     match args.command {
-        P2pCommand::List { verbose, json } => list_peers(&config, verbose, json).await,
-        P2pCommand::Status { json } => show_status(&config, json).await,
+        P2pCommand::List { verbose, json } => list_peers(&config, verbose, json_output(json)).await,
+        P2pCommand::Ls { peer, json } => {
+            list_peer_namespaces(&config, &peer, json_output(json)).await
+        }
+        P2pCommand::Status { json } => show_status(&config, json_output(json)).await,
+        P2pCommand::Bench {
+            peer,
+            size,
+            rounds,
+            json,
+        } => bench_peers(&config, peer.as_deref(), &size, rounds, json_output(json)).await,
         P2pCommand::Approve { peer, permanent } => approve_peer(&config, &peer, permanent).await,
         P2pCommand::Deny { peer } => deny_peer(&config, &peer).await,
         P2pCommand::Clear { force } => clear_consents(&config, force).await,
@@ -36,7 +51,7 @@ pub async fn run(args: P2pArgs) -> Result<()> {
 
 async fn list_peers(config: &FabrikConfig, verbose: bool, json: bool) -> Result<()> {
     // Initialize P2P manager
-    let p2p = P2PManager::new(config.p2p.clone()).await?;
+    let p2p = P2PManager::new(config.p2p.clone(), config.grpc.clone()).await?;
     p2p.start().await?;
 
     // Wait a moment for discovery
@@ -57,7 +72,7 @@ async fn list_peers(config: &FabrikConfig, verbose: bool, json: bool) -> Result<
                 })
             })
             .collect();
-        println!("{}", serde_json::to_string_pretty(&peers_json)?);
+        println!("{}", serde_json::to_string(&peers_json)?);
     } else if peers.is_empty() {
         println!("No P2P peers discovered");
         println!("Make sure other instances are running with P2P enabled");
@@ -78,42 +93,292 @@ async fn list_peers(config: &FabrikConfig, verbose: bool, json: bool) -> Result<
     Ok(())
 }
 
+async fn list_peer_namespaces(config: &FabrikConfig, peer: &str, json: bool) -> Result<()> {
+    if config.p2p.secret.is_none() {
+        anyhow::bail!("P2P secret not configured. Set p2p.secret in your fabrik.toml");
+    }
+
+    let peer = resolve_peer(config, peer).await?;
+    let client = P2PClient::new(
+        Arc::new(config.p2p.clone()),
+        GrpcTransport::from_config(&config.grpc)?,
+    );
+    let response = client
+        .list_namespaces(&peer)
+        .await
+        .with_context(|| format!("Failed to list namespaces on {}", peer.display_name()))?;
+
+    if response.consent_required {
+        anyhow::bail!(
+            "{} has not approved access to its cache. Ask them to run:\n  fabrik p2p approve {}",
+            peer.display_name(),
+            client.machine_id()
+        );
+    }
+
+    if json {
+        let namespaces: Vec<serde_json::Value> = response
+            .namespaces
+            .iter()
+            .map(|ns| {
+                serde_json::json!({
+                    "name": ns.name,
+                    "artifact_count": ns.artifact_count,
+                    "approximate_size": ns.approximate_size,
+                    "last_modified": ns.last_modified,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string(&namespaces)?);
+    } else if response.namespaces.is_empty() {
+        println!("{} has no shared artifacts", peer.display_name());
+    } else {
+        println!("Cache on {}:\n", peer.display_name());
+        println!(
+            "  {:<16} {:>10} {:>10}  LAST MODIFIED",
+            "NAMESPACE", "ARTIFACTS", "SIZE"
+        );
+        for ns in &response.namespaces {
+            let last_modified = chrono::DateTime::from_timestamp(ns.last_modified, 0)
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "-".to_string());
+            println!(
+                "  {:<16} {:>10} {:>10}  {}",
+                ns.name,
+                ns.artifact_count,
+                format_size(ns.approximate_size),
+                last_modified
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolve a peer by address (host:port), or by hostname/machine ID among discovered peers
+async fn resolve_peer(config: &FabrikConfig, peer: &str) -> Result<Peer> {
+    if let Ok(addr) = peer.parse::<SocketAddr>() {
+        return Ok(Peer::new(PeerInfo {
+            machine_id: String::new(),
+            hostname: addr.ip().to_string(),
+            address: addr.ip(),
+            port: addr.port(),
+            last_seen: std::time::SystemTime::now(),
+            accepting_requests: true,
+        }));
+    }
+
+    let p2p = P2PManager::new(config.p2p.clone(), config.grpc.clone()).await?;
+    p2p.start().await?;
+
+    // Wait a moment for discovery
+    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+
+    let found = p2p
+        .get_peers()
+        .await
+        .into_iter()
+        .find(|p| p.info.hostname == peer || p.info.machine_id == peer);
+
+    p2p.shutdown().await?;
+
+    found.with_context(|| {
+        format!(
+            "Peer '{}' not found. Run 'fabrik p2p list' to see discovered peers",
+            peer
+        )
+    })
+}
+
 async fn show_status(config: &FabrikConfig, json: bool) -> Result<()> {
     // Initialize P2P manager
-    let p2p = P2PManager::new(config.p2p.clone()).await?;
+    let p2p = P2PManager::new(config.p2p.clone(), config.grpc.clone()).await?;
     p2p.start().await?;
 
     // Wait for discovery
     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
 
     let peers = p2p.get_peers().await;
+    let peer_stats = p2p.client().stats().snapshot()?;
 
     if json {
         let status = serde_json::json!({
             "enabled": config.p2p.enabled,
             "advertise": config.p2p.advertise,
             "discovery": config.p2p.discovery,
+            "static_peers": config.p2p.static_peers,
+            "relay_url": config.p2p.relay_url,
             "bind_port": config.p2p.bind_port,
             "consent_mode": config.p2p.consent_mode,
             "peers_discovered": peers.len(),
             "max_peers": config.p2p.max_peers,
+            "peer_stats": peer_stats_json(&peer_stats),
         });
-        println!("{}", serde_json::to_string_pretty(&status)?);
+        println!("{}", serde_json::to_string(&status)?);
     } else {
         println!("P2P Cache Sharing Status\n");
         println!("  Enabled: {}", config.p2p.enabled);
         println!("  Advertise: {}", config.p2p.advertise);
         println!("  Discovery: {}", config.p2p.discovery);
+        if !config.p2p.static_peers.is_empty() {
+            println!("  Static peers: {}", config.p2p.static_peers.join(", "));
+        }
+        if let Some(relay_url) = &config.p2p.relay_url {
+            println!("  Relay: {}", relay_url);
+        }
         println!("  Port: {}", config.p2p.bind_port);
         println!("  Consent mode: {}", config.p2p.consent_mode);
         println!("  Max peers: {}", config.p2p.max_peers);
         println!("\n  Peers discovered: {}", peers.len());
+
+        if !peer_stats.is_empty() {
+            println!("\nPeer statistics:\n");
+            println!(
+                "  {:<24} {:>8} {:>8} {:>10} {:>8} {:>9} {:>12}",
+                "PEER", "REQUESTS", "HIT RATE", "FETCHED", "FAILURES", "LATENCY", "THROUGHPUT"
+            );
+            for stats in peer_stats.values() {
+                let (latency, throughput) = match &stats.bench {
+                    Some(bench) => (
+                        format!("{:.1} ms", bench.latency_ms),
+                        format!("{}/s", format_size(bench.throughput_bytes_per_sec as u64)),
+                    ),
+                    None => ("-".to_string(), "-".to_string()),
+                };
+                println!(
+                    "  {:<24} {:>8} {:>7.0}% {:>10} {:>8} {:>9} {:>12}",
+                    stats.hostname,
+                    stats.requests,
+                    stats.hit_rate() * 100.0,
+                    format_size(stats.bytes_fetched),
+                    stats.failures,
+                    latency,
+                    throughput
+                );
+            }
+        }
     }
 
     p2p.shutdown().await?;
     Ok(())
 }
 
+/// Per-peer statistics keyed by machine ID, with the derived hit rate
+fn peer_stats_json(peer_stats: &BTreeMap<String, PeerStats>) -> serde_json::Value {
+    peer_stats
+        .iter()
+        .map(|(machine_id, stats)| {
+            let mut value = serde_json::to_value(stats).unwrap_or_default();
+            value["hit_rate"] = serde_json::json!(stats.hit_rate());
+            (machine_id.clone(), value)
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+async fn bench_peers(
+    config: &FabrikConfig,
+    peer: Option<&str>,
+    size: &str,
+    rounds: usize,
+    json: bool,
+) -> Result<()> {
+    if config.p2p.secret.is_none() {
+        anyhow::bail!("P2P secret not configured. Set p2p.secret in your fabrik.toml");
+    }
+    let size_bytes = EvictionConfig::parse_size(size).context("Invalid --size")?;
+
+    let client = P2PClient::new(
+        Arc::new(config.p2p.clone()),
+        GrpcTransport::from_config(&config.grpc)?,
+    );
+    let peers = match peer {
+        Some(peer) => vec![identify_peer(&client, resolve_peer(config, peer).await?).await],
+        None => {
+            let p2p = P2PManager::new(config.p2p.clone(), config.grpc.clone()).await?;
+            p2p.start().await?;
+
+            // Wait a moment for discovery
+            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+
+            let peers = p2p.get_peers().await;
+            p2p.shutdown().await?;
+            peers
+        }
+    };
+
+    if peers.is_empty() {
+        anyhow::bail!(
+            "No P2P peers discovered. Make sure other instances are running with P2P enabled"
+        );
+    }
+
+    if !json {
+        println!(
+            "Benchmarking {} peer(s) ({} rounds, {} transfer)\n",
+            peers.len(),
+            rounds,
+            format_size(size_bytes)
+        );
+        println!("  {:<24} {:>9} {:>12}", "PEER", "LATENCY", "THROUGHPUT");
+    }
+
+    // One peer at a time so measurements don't compete for bandwidth
+    let mut results = Vec::new();
+    for peer in &peers {
+        let result = client.bench(peer, size_bytes, rounds).await;
+
+        if !json {
+            match &result {
+                Ok(bench) => println!(
+                    "  {:<24} {:>6.1} ms {:>10}/s",
+                    peer.display_name(),
+                    bench.latency_ms,
+                    format_size(bench.throughput_bytes_per_sec as u64)
+                ),
+                Err(e) => println!("  {:<24} failed: {:#}", peer.display_name(), e),
+            }
+        }
+
+        results.push(match result {
+            Ok(bench) => serde_json::json!({
+                "machine_id": peer.info.machine_id,
+                "hostname": peer.info.hostname,
+                "address": peer.info.address.to_string(),
+                "port": peer.info.port,
+                "latency_ms": bench.latency_ms,
+                "throughput_bytes_per_sec": bench.throughput_bytes_per_sec,
+            }),
+            Err(e) => serde_json::json!({
+                "machine_id": peer.info.machine_id,
+                "hostname": peer.info.hostname,
+                "address": peer.info.address.to_string(),
+                "port": peer.info.port,
+                "error": format!("{:#}", e),
+            }),
+        });
+    }
+
+    if json {
+        println!("{}", serde_json::to_string(&results)?);
+    }
+
+    client.stats().save()?;
+    Ok(())
+}
+
+/// Learn the machine ID of a peer given by address, so its results can be recorded
+async fn identify_peer(client: &P2PClient, peer: Peer) -> Peer {
+    if !peer.info.machine_id.is_empty() {
+        return peer;
+    }
+    let address = SocketAddr::new(peer.info.address, peer.info.port);
+    match client.probe(address).await {
+        Ok(info) => Peer::new(info),
+        Err(_) => peer,
+    }
+}
+
 async fn approve_peer(config: &FabrikConfig, peer: &str, permanent: bool) -> Result<()> {
     let consent_manager = Arc::new(ConsentManager::new(Arc::new(config.p2p.clone()))?);
 
@@ -4,19 +4,31 @@
 /// or runs portable recipes (QuickJS) from local or remote sources.
 use anyhow::{Context, Result};
 use std::path::Path;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::cli::RunArgs;
 use crate::cli_utils::fabrik_prefix;
+use crate::config::RecipesConfig;
+use crate::eviction::EvictionConfig;
 use crate::recipe::{
+    affected::{affecting_changes, changed_files},
     annotations::parse_annotations,
-    cache::{create_metadata, ScriptCache},
-    cache_key::compute_cache_key,
-    dependencies::DependencyResolver,
-    executor::ScriptExecutor,
-    outputs::{archive_outputs, extract_outputs},
+    cache::{create_metadata, CacheEntry, ScriptCache},
+    cache_key::{compute_cache_key, explain_cache_key, FileChange, KeyChange},
+    dependencies::{run_dependencies, DependencyResolver, DependencyRun, ResolvedDependency},
+    executor::{ExecutionResult, ScriptExecutor},
+    hermetic::undeclared_env_reads,
+    inputs::InputHasher,
+    lock::{LockOptions, LockOutcome},
+    memo::HashMemo,
+    outputs::{archive_outputs, extract_outputs, RestoreOptions},
+    remote::RemoteCache,
+    watch::{wait_for_change, Snapshot},
+};
+use crate::recipe_portable::{
+    ExecLimits, FetchEvent, FetchOptions, Mirror, PermissionMode, RecipeExecutor, RemoteRecipe,
+    SignaturePolicy, Verification,
 };
-use crate::recipe_portable::{RecipeExecutor, RemoteRecipe};
 use crate::storage::default_cache_dir;
 
 pub async fn run(args: &RunArgs) -> Result<()> {
@@ -37,9 +49,23 @@ pub async fn run(args: &RunArgs) -> Result<()> {
         })
         .unwrap_or_else(default_cache_dir);
 
+    // Hash algorithm of script cache keys (CLI arg > config file > SHA-256)
+    let hash_algorithm = args
+        .config_hash_algorithm
+        .or_else(|| file_config.as_ref().map(|c| c.cache.hash_algorithm))
+        .unwrap_or_default();
+    // Input content hashes are memoized across runs, unless another run has the memo open
+    let input_hasher = || {
+        let hasher = InputHasher::new(hash_algorithm);
+        match HashMemo::open(&cache_dir) {
+            Ok(memo) => hasher.with_memo(memo),
+            Err(_) => hasher,
+        }
+    };
+
     // Handle script management operations
     if args.status {
-        return run_status(args, &cache_dir).await;
+        return run_status(args, &cache_dir, &input_hasher()).await;
     }
     if args.list {
         return run_list(args, &cache_dir).await;
@@ -57,7 +83,11 @@ pub async fn run(args: &RunArgs) -> Result<()> {
 
     // Check if this is a remote recipe (starts with @)
     if script.starts_with('@') {
-        return run_remote_recipe(&script, args).await;
+        if args.watch || args.affected_base.is_some() {
+            anyhow::bail!("--watch and --affected-base are only supported for annotated scripts");
+        }
+        let recipes_config = file_config.map(|c| c.recipes).unwrap_or_default();
+        return run_remote_recipe(&script, args, &recipes_config, &cache_dir).await;
     }
 
     let script_path = Path::new(&script);
@@ -77,11 +107,176 @@ pub async fn run(args: &RunArgs) -> Result<()> {
     {
         // Check shebang to determine if this is a standard recipe or portable recipe
         if !has_fabrik_run_shebang(script_path)? {
-            return run_local_portable_recipe(script_path, args).await;
+            if args.watch || args.affected_base.is_some() {
+                anyhow::bail!(
+                    "--watch and --affected-base are only supported for annotated scripts"
+                );
+            }
+            return run_local_portable_recipe(script_path, args, &cache_dir).await;
         }
         // Otherwise, fall through to standard recipe execution
     }
 
+    // Skip scripts whose inputs didn't change relative to the base ref
+    if let Some(base) = &args.affected_base {
+        if !is_affected(script_path, base, args.verbose)? {
+            return Ok(());
+        }
+    }
+
+    // Share script outputs through the upstreams of the config file
+    let remote = match (&file_config, args.no_upstream) {
+        (Some(config), false) => RemoteCache::from_config(config)
+            .await
+            .context("Failed to initialize upstream script cache")?,
+        _ => None,
+    };
+    if args.verbose && remote.is_some() {
+        eprintln!(
+            "{} Sharing script outputs through upstreams",
+            fabrik_prefix()
+        );
+    }
+
+    // Scripts run synchronously; the remote cache blocks on this runtime
+    let input_hasher = input_hasher();
+    if args.watch {
+        return tokio::task::block_in_place(|| {
+            watch_script(
+                script_path,
+                cli_runtime,
+                args,
+                &input_hasher,
+                remote.as_ref(),
+            )
+        });
+    }
+
+    let exit_code = tokio::task::block_in_place(|| {
+        run_script(
+            script_path,
+            cli_runtime,
+            args,
+            &input_hasher,
+            remote.as_ref(),
+        )
+    })?;
+    std::process::exit(exit_code);
+}
+
+/// Warn about environment variables a script reads without declaring them
+fn check_env(script_path: &Path, annotations: &crate::recipe::ScriptAnnotations) -> Result<()> {
+    let undeclared = undeclared_env_reads(script_path, annotations)?;
+    if undeclared.is_empty() {
+        eprintln!(
+            "{} No undeclared environment variables read",
+            fabrik_prefix()
+        );
+        return Ok(());
+    }
+
+    for var in &undeclared {
+        eprintln!(
+            "{} Warning: reads {} without declaring it with `env` (not in the cache key)",
+            fabrik_prefix(),
+            var
+        );
+    }
+    if annotations.exec_hermetic {
+        eprintln!(
+            "{} Running hermetically: these variables are unset for the script",
+            fabrik_prefix()
+        );
+    }
+    Ok(())
+}
+
+/// Whether a script's inputs changed relative to `base`, reporting why
+fn is_affected(script_path: &Path, base: &str, verbose: bool) -> Result<bool> {
+    let dir = script_path
+        .canonicalize()?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let changed = changed_files(&dir, base)?;
+    let affecting = affecting_changes(script_path, &changed)?;
+
+    if affecting.is_empty() {
+        eprintln!(
+            "{} Not affected by changes since {} ({} changed files) - skipping",
+            fabrik_prefix(),
+            base,
+            changed.len()
+        );
+        return Ok(false);
+    }
+
+    eprintln!(
+        "{} Affected by {} changed files since {}",
+        fabrik_prefix(),
+        affecting.len(),
+        base
+    );
+    if verbose {
+        for path in &affecting {
+            eprintln!("{}   {}", fabrik_prefix(), path.display());
+        }
+    }
+    Ok(true)
+}
+
+/// Re-run a script whenever its script, inputs or dependencies change
+fn watch_script(
+    script_path: &Path,
+    cli_runtime: Option<String>,
+    args: &RunArgs,
+    input_hasher: &InputHasher,
+    remote: Option<&RemoteCache>,
+) -> Result<()> {
+    let debounce = Duration::from_millis(args.watch_debounce_ms);
+
+    loop {
+        // Errors (e.g. invalid annotations while editing) don't end the watch
+        match run_script(script_path, cli_runtime.clone(), args, input_hasher, remote) {
+            Ok(0) => {}
+            Ok(exit_code) => {
+                eprintln!("{} Script failed (exit: {})", fabrik_prefix(), exit_code)
+            }
+            Err(e) => eprintln!("{} Error: {:#}", fabrik_prefix(), e),
+        }
+
+        // Snapshot after the run, so outputs it wrote to watched paths don't retrigger it
+        let baseline = Snapshot::take(script_path);
+        eprintln!(
+            "{} Watching {} files for changes (Ctrl+C to stop)",
+            fabrik_prefix(),
+            baseline.file_count()
+        );
+
+        let (changes, _) = wait_for_change(script_path, &baseline, debounce);
+        let more = match changes.len() {
+            1 => String::new(),
+            n => format!(" and {} more", n - 1),
+        };
+        eprintln!(
+            "{} Changed: {}{} - re-running",
+            fabrik_prefix(),
+            changes[0].display(),
+            more
+        );
+    }
+}
+
+/// Run an annotated script with caching, returning its exit code
+fn run_script(
+    script_path: &Path,
+    cli_runtime: Option<String>,
+    args: &RunArgs,
+    input_hasher: &InputHasher,
+    remote: Option<&RemoteCache>,
+) -> Result<i32> {
+    let script = script_path.display();
+
     // Parse annotations
     if args.verbose {
         eprintln!("{} Parsing annotations from {}", fabrik_prefix(), script);
@@ -95,8 +290,16 @@ pub async fn run(args: &RunArgs) -> Result<()> {
         annotations.runtime = runtime;
     }
 
+    if args.hermetic {
+        annotations.exec_hermetic = true;
+    }
+    annotations.env_allow.extend(args.env_allow.iter().cloned());
+    if args.check_env {
+        check_env(script_path, &annotations)?;
+    }
+
     // Check if caching is disabled
-    if annotations.cache_disabled || args.no_cache {
+    if (annotations.cache_disabled || args.no_cache) && !args.explain {
         eprintln!(
             "{} Caching disabled - executing script directly",
             fabrik_prefix()
@@ -125,15 +328,20 @@ pub async fn run(args: &RunArgs) -> Result<()> {
         &dependencies,
     );
 
-    // Compute cache key
-    let cache_key =
-        compute_cache_key(script_path, &annotations).context("Failed to compute cache key")?;
-
-    if args.verbose {
-        eprintln!("{} Cache key: {}", fabrik_prefix(), cache_key);
-    }
+    // The script itself comes last
+    let dependencies = &dependencies[..dependencies.len() - 1];
 
     if args.dry_run {
+        // Without running dependencies, their outputs may not exist yet
+        let cache_key = compute_cache_key(script_path, &annotations, input_hasher)
+            .context("Failed to compute cache key")?;
+        if !dependencies.is_empty() {
+            eprintln!(
+                "{} Dry run - would run {} dependencies first",
+                fabrik_prefix(),
+                dependencies.len()
+            );
+        }
         eprintln!(
             "{} Dry run - would check cache with key: {}",
             fabrik_prefix(),
@@ -149,7 +357,7 @@ pub async fn run(args: &RunArgs) -> Result<()> {
             fabrik_prefix(),
             annotations.outputs.len()
         );
-        return Ok(());
+        return Ok(0);
     }
 
     // Initialize cache
@@ -161,6 +369,42 @@ pub async fn run(args: &RunArgs) -> Result<()> {
     let cache =
         ScriptCache::new(cache_dir.to_path_buf()).context("Failed to initialize script cache")?;
 
+    if args.explain {
+        explain(
+            &cache,
+            script_path,
+            &annotations,
+            dependencies.len(),
+            input_hasher,
+        )?;
+        return Ok(0);
+    }
+    let cache = cache.with_remote(remote.cloned());
+
+    // Run dependencies (each cached on its own), independent ones in parallel
+    if !dependencies.is_empty() {
+        let parallel = annotations.exec_parallel.unwrap_or_else(num_cpus::get);
+        if args.verbose {
+            eprintln!(
+                "{} Running {} dependencies ({} at a time)",
+                fabrik_prefix(),
+                dependencies.len(),
+                parallel
+            );
+        }
+        run_dependencies(dependencies, parallel, |dep| {
+            run_dependency(dep, dependencies, &cache, input_hasher)
+        })?;
+    }
+
+    // Compute cache key (after dependencies produced the outputs it may include)
+    let cache_key = compute_cache_key(script_path, &annotations, input_hasher)
+        .context("Failed to compute cache key")?;
+
+    if args.verbose {
+        eprintln!("{} Cache key: {}", fabrik_prefix(), cache_key);
+    }
+
     if args.clean {
         if args.verbose {
             eprintln!("{} Cleaning cache for this script", fabrik_prefix());
@@ -176,48 +420,15 @@ pub async fn run(args: &RunArgs) -> Result<()> {
     let start = Instant::now();
 
     if let Some(entry) = cache.get(&cache_key)? {
-        // Cache hit!
-        let duration = start.elapsed();
-
-        if args.verbose {
-            eprintln!("{} Cache HIT ✓", fabrik_prefix());
-            eprintln!("{} Restoring outputs from cache", fabrik_prefix());
-            for output in &entry.metadata.outputs {
-                eprintln!(
-                    "{}   {} ({} bytes, {} files)",
-                    fabrik_prefix(),
-                    output.path,
-                    output.size_bytes,
-                    output.file_count
-                );
-            }
-        }
-
-        // Extract outputs
-        let base_dir = script_path
-            .parent()
-            .and_then(|p| {
-                if p == std::path::Path::new("") {
-                    None
-                } else {
-                    Some(p)
-                }
-            })
-            .unwrap_or_else(|| std::path::Path::new("."));
-
-        extract_outputs(&entry.archive_path, base_dir)
-            .context("Failed to extract cached outputs")?;
-
-        // Compact single-line output
-        eprintln!(
-            "{} Cache key: {} | HIT ✓ | {:.2}s (exit: {})",
-            fabrik_prefix(),
-            cache_key,
-            duration.as_secs_f64(),
-            entry.metadata.execution.exit_code
+        return restore_cache_hit(
+            &cache,
+            &entry,
+            script_path,
+            annotations.cache_restore,
+            &cache_key,
+            start,
+            args.verbose,
         );
-
-        std::process::exit(entry.metadata.execution.exit_code);
     }
 
     // Cache miss
@@ -229,6 +440,55 @@ pub async fn run(args: &RunArgs) -> Result<()> {
         anyhow::bail!("Cache miss and --cache-only flag set");
     }
 
+    // Lock the cache key so concurrent runs of the same script wait for this
+    // execution instead of duplicating it
+    let lock = if args.no_lock {
+        None
+    } else {
+        let options = LockOptions {
+            wait_timeout: Duration::from_secs(
+                EvictionConfig::parse_ttl(&args.lock_timeout).context("Invalid --lock-timeout")?,
+            ),
+            stale_after: Duration::from_secs(
+                EvictionConfig::parse_ttl(&args.lock_stale_after)
+                    .context("Invalid --lock-stale-after")?,
+            ),
+            ..Default::default()
+        };
+
+        match cache.lock(&cache_key, &options)? {
+            LockOutcome::Acquired { lock, waited } => {
+                if waited {
+                    // Another process executed while we waited - use its result if cached
+                    if let Some(entry) = cache.get(&cache_key)? {
+                        drop(lock);
+                        return restore_cache_hit(
+                            &cache,
+                            &entry,
+                            script_path,
+                            annotations.cache_restore,
+                            &cache_key,
+                            start,
+                            args.verbose,
+                        );
+                    }
+                }
+                Some(lock)
+            }
+            LockOutcome::TimedOut { holder } => {
+                eprintln!(
+                    "{} Timed out waiting for lock on {}{} - executing anyway",
+                    fabrik_prefix(),
+                    cache_key,
+                    holder
+                        .map(|h| format!(" (held by pid {} on {})", h.pid, h.hostname))
+                        .unwrap_or_default()
+                );
+                None
+            }
+        }
+    };
+
     // Execute script
     if args.verbose {
         eprintln!(
@@ -268,61 +528,14 @@ pub async fn run(args: &RunArgs) -> Result<()> {
             eprintln!("{} Archiving outputs...", fabrik_prefix());
         }
 
-        let base_dir = script_path
-            .parent()
-            .and_then(|p| {
-                if p == std::path::Path::new("") {
-                    None
-                } else {
-                    Some(p)
-                }
-            })
-            .unwrap_or_else(|| std::path::Path::new("."));
-
-        let temp_archive =
-            tempfile::NamedTempFile::new().context("Failed to create temporary archive")?;
-
-        let archived_outputs = archive_outputs(&annotations.outputs, base_dir, temp_archive.path())
-            .context("Failed to archive outputs")?;
-
-        if args.verbose {
-            eprintln!(
-                "{} Archived {} outputs",
-                fabrik_prefix(),
-                archived_outputs.len()
-            );
-            for output in &archived_outputs {
-                eprintln!(
-                    "{}   {} ({} bytes, {} files)",
-                    fabrik_prefix(),
-                    output.path,
-                    output.size_bytes,
-                    output.file_count
-                );
-            }
-        }
-
-        // Create metadata
-        let metadata = create_metadata(crate::recipe::CreateMetadataParams {
-            cache_key: cache_key.clone(),
+        store_outputs(
+            &cache,
+            &cache_key,
             script_path,
-            exit_code: result.exit_code,
-            duration: result.duration,
-            runtime: annotations.runtime.clone(),
-            runtime_version: if annotations.runtime_version {
-                crate::recipe::inputs::get_runtime_version(&annotations.runtime).ok()
-            } else {
-                None
-            },
-            outputs: archived_outputs,
-            env_vars: &annotations.env_vars,
-            ttl: annotations.cache_ttl,
-        });
-
-        // Store in cache
-        cache
-            .put(&cache_key, metadata, temp_archive.path())
-            .context("Failed to store in cache")?;
+            &annotations,
+            &result,
+            args.verbose,
+        )?;
 
         if args.verbose {
             eprintln!("{} Cached as: {}", fabrik_prefix(), cache_key);
@@ -335,6 +548,8 @@ pub async fn run(args: &RunArgs) -> Result<()> {
         );
     }
 
+    drop(lock);
+
     let total_duration = start.elapsed();
 
     // Compact single-line output
@@ -346,7 +561,279 @@ pub async fn run(args: &RunArgs) -> Result<()> {
         result.exit_code
     );
 
-    std::process::exit(result.exit_code);
+    Ok(result.exit_code)
+}
+
+/// Print the components of a script's cache key and what changed since the last explain
+fn explain(
+    cache: &ScriptCache,
+    script_path: &Path,
+    annotations: &crate::recipe::ScriptAnnotations,
+    dependency_count: usize,
+    input_hasher: &InputHasher,
+) -> Result<()> {
+    let explanation = explain_cache_key(script_path, annotations, input_hasher)
+        .context("Failed to compute cache key")?;
+    let status = match cache.get(&explanation.key)? {
+        Some(_) => "CACHED ✓",
+        None => "NOT CACHED",
+    };
+
+    println!("Script: {}", script_path.display());
+    println!("Cache key: {} ({})", explanation.key, status);
+    if dependency_count > 0 {
+        // Dependencies aren't run, so their outputs may not exist yet
+        println!("Dependencies: {} (not run)", dependency_count);
+    }
+    println!();
+
+    let width = explanation
+        .components
+        .iter()
+        .map(|component| component.name.len())
+        .max()
+        .unwrap_or(0);
+    println!("Components:");
+    for component in &explanation.components {
+        println!(
+            "  {:width$}  {:16}  {}",
+            component.name,
+            component.summary,
+            &component.hash[..12],
+            width = width
+        );
+    }
+
+    println!();
+    match cache.previous_explanation(script_path)? {
+        None => println!("No previous explanation to compare with"),
+        Some(previous) if previous.key == explanation.key => {
+            println!("Unchanged since last explain")
+        }
+        Some(previous) => {
+            println!("Changes since last explain ({}):", previous.key);
+            for change in explanation.changes_since(&previous) {
+                match change {
+                    KeyChange::Added(name) => println!("  + {}", name),
+                    KeyChange::Removed(name) => println!("  - {}", name),
+                    KeyChange::Changed {
+                        name,
+                        from,
+                        to,
+                        files,
+                    } => {
+                        if from == to {
+                            println!("  ~ {}", name);
+                        } else {
+                            println!("  ~ {}: {} -> {}", name, from, to);
+                        }
+                        for file in files {
+                            match file {
+                                FileChange::Added(path) => println!("      + {}", path),
+                                FileChange::Removed(path) => println!("      - {}", path),
+                                FileChange::Modified(path) => println!("      ~ {}", path),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    cache.save_explanation(script_path, &explanation)
+}
+
+/// Restore outputs from a cache hit, returning the cached exit code
+fn restore_cache_hit(
+    cache: &ScriptCache,
+    entry: &CacheEntry,
+    script_path: &Path,
+    restore: RestoreOptions,
+    cache_key: &str,
+    start: Instant,
+    verbose: bool,
+) -> Result<i32> {
+    let duration = start.elapsed();
+
+    let hit = match &entry.upstream {
+        Some(_) => "HIT ✓ (upstream)",
+        None => "HIT ✓",
+    };
+
+    if verbose {
+        if let Some(upstream) = &entry.upstream {
+            eprintln!("{} Fetched from upstream {}", fabrik_prefix(), upstream);
+        }
+        eprintln!("{} Cache {}", fabrik_prefix(), hit);
+        eprintln!("{} Restoring outputs from cache", fabrik_prefix());
+        for output in &entry.metadata.outputs {
+            eprintln!(
+                "{}   {} ({} bytes, {} files)",
+                fabrik_prefix(),
+                output.path,
+                output.size_bytes,
+                output.file_count
+            );
+        }
+        for image in &entry.metadata.docker_images {
+            eprintln!(
+                "{}   docker image {} ({} bytes)",
+                fabrik_prefix(),
+                image.image,
+                image.size_bytes
+            );
+        }
+    }
+
+    // Extract outputs
+    let archive = cache
+        .read_archive(entry)
+        .context("Failed to read cached outputs")?;
+    extract_outputs(&archive, base_dir(script_path), restore)
+        .context("Failed to extract cached outputs")?;
+
+    // Compact single-line output
+    eprintln!(
+        "{} Cache key: {} | {} | {:.2}s (exit: {})",
+        fabrik_prefix(),
+        cache_key,
+        hit,
+        duration.as_secs_f64(),
+        entry.metadata.execution.exit_code
+    );
+
+    Ok(entry.metadata.execution.exit_code)
+}
+
+/// Archive the outputs of a successful run and store them under `cache_key`
+fn store_outputs(
+    cache: &ScriptCache,
+    cache_key: &str,
+    script_path: &Path,
+    annotations: &crate::recipe::ScriptAnnotations,
+    result: &ExecutionResult,
+    verbose: bool,
+) -> Result<()> {
+    let temp_archive =
+        tempfile::NamedTempFile::new().context("Failed to create temporary archive")?;
+
+    let (archived_outputs, images) = archive_outputs(
+        &annotations.outputs,
+        &annotations.image_outputs,
+        base_dir(script_path),
+        temp_archive.path(),
+    )
+    .context("Failed to archive outputs")?;
+
+    if verbose {
+        eprintln!(
+            "{} Archived {} outputs",
+            fabrik_prefix(),
+            archived_outputs.len()
+        );
+        for output in &archived_outputs {
+            eprintln!(
+                "{}   {} ({} bytes, {} files)",
+                fabrik_prefix(),
+                output.path,
+                output.size_bytes,
+                output.file_count
+            );
+        }
+        for image in &images {
+            eprintln!(
+                "{}   docker image {} ({} bytes)",
+                fabrik_prefix(),
+                image.image,
+                image.size_bytes
+            );
+        }
+    }
+
+    // Create metadata
+    let mut metadata = create_metadata(crate::recipe::CreateMetadataParams {
+        cache_key: cache_key.to_string(),
+        script_path,
+        exit_code: result.exit_code,
+        duration: result.duration,
+        runtime: annotations.runtime.clone(),
+        runtime_version: if annotations.runtime_version {
+            crate::recipe::inputs::get_runtime_version(&annotations.runtime).ok()
+        } else {
+            None
+        },
+        outputs: archived_outputs,
+        env_vars: &annotations.env_vars,
+        ttl: annotations.cache_ttl,
+    });
+    metadata.docker_images = images;
+
+    // Store in cache
+    cache
+        .put(cache_key, metadata, temp_archive.path())
+        .context("Failed to store in cache")
+}
+
+/// Run a dependency script, or restore its outputs from cache
+///
+/// Output is captured so parallel dependencies can be printed one after the other.
+fn run_dependency(
+    dep: &ResolvedDependency,
+    all: &[ResolvedDependency],
+    cache: &ScriptCache,
+    input_hasher: &InputHasher,
+) -> Result<DependencyRun> {
+    let start = Instant::now();
+    let script_path = dep.script_path.as_path();
+    let mut annotations = dep.annotations.clone();
+    DependencyResolver::augment_with_dependency_outputs(script_path, &mut annotations, all);
+
+    let cache_key = compute_cache_key(script_path, &annotations, input_hasher)
+        .context("Failed to compute cache key")?;
+
+    if !annotations.cache_disabled {
+        if let Some(entry) = cache.get(&cache_key)? {
+            let archive = cache
+                .read_archive(&entry)
+                .context("Failed to read cached outputs")?;
+            extract_outputs(&archive, base_dir(script_path), annotations.cache_restore)
+                .context("Failed to extract cached outputs")?;
+            return Ok(DependencyRun {
+                cache_key,
+                cached: true,
+                exit_code: entry.metadata.execution.exit_code,
+                duration: start.elapsed(),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            });
+        }
+    }
+
+    let result = ScriptExecutor::new(false)
+        .capture_output()
+        .execute(script_path, &annotations, &[])
+        .context("Script execution failed")?;
+
+    if result.exit_code == 0 && !annotations.cache_disabled {
+        store_outputs(cache, &cache_key, script_path, &annotations, &result, false)?;
+    }
+
+    Ok(DependencyRun {
+        cache_key,
+        cached: false,
+        exit_code: result.exit_code,
+        duration: start.elapsed(),
+        stdout: result.stdout,
+        stderr: result.stderr,
+    })
+}
+
+/// Directory a script's outputs are relative to
+fn base_dir(script_path: &Path) -> &Path {
+    script_path
+        .parent()
+        .filter(|p| *p != Path::new(""))
+        .unwrap_or_else(|| Path::new("."))
 }
 
 /// Execute script without caching
@@ -355,7 +842,7 @@ fn execute_script_no_cache(
     annotations: &crate::recipe::ScriptAnnotations,
     args: &[String],
     verbose: bool,
-) -> Result<()> {
+) -> Result<i32> {
     let executor = ScriptExecutor::new(verbose);
     let result = executor
         .execute(script_path, annotations, args)
@@ -370,7 +857,7 @@ fn execute_script_no_cache(
         eprintln!("{} Exit code: {}", fabrik_prefix(), result.exit_code);
     }
 
-    std::process::exit(result.exit_code);
+    Ok(result.exit_code)
 }
 
 // ============================================================================
@@ -378,7 +865,11 @@ fn execute_script_no_cache(
 // ============================================================================
 
 /// Show cache status for a script (`fabrik run --status script.sh`)
-async fn run_status(args: &RunArgs, cache_dir: &std::path::Path) -> Result<()> {
+async fn run_status(
+    args: &RunArgs,
+    cache_dir: &std::path::Path,
+    input_hasher: &InputHasher,
+) -> Result<()> {
     if args.positional_args.is_empty() {
         anyhow::bail!("Script path required for --status");
     }
@@ -398,7 +889,8 @@ async fn run_status(args: &RunArgs, cache_dir: &std::path::Path) -> Result<()> {
         .with_context(|| format!("Failed to parse script annotations: {}", script_path))?;
 
     // Compute cache key
-    let cache_key = compute_cache_key(path, &annotations).context("Failed to compute cache key")?;
+    let cache_key = compute_cache_key(path, &annotations, input_hasher)
+        .context("Failed to compute cache key")?;
 
     println!("Script: {}", script_path);
     println!("Cache key: {}", cache_key);
@@ -430,7 +922,7 @@ async fn run_status(args: &RunArgs, cache_dir: &std::path::Path) -> Result<()> {
             entry.metadata.execution.duration_ms as f64 / 1000.0
         );
 
-        if !entry.metadata.outputs.is_empty() {
+        if !entry.metadata.outputs.is_empty() || !entry.metadata.docker_images.is_empty() {
             println!("  Outputs:");
             for output in &entry.metadata.outputs {
                 println!(
@@ -440,6 +932,13 @@ async fn run_status(args: &RunArgs, cache_dir: &std::path::Path) -> Result<()> {
                     output.file_count
                 );
             }
+            for image in &entry.metadata.docker_images {
+                println!(
+                    "    docker image {} ({:.2} MB)",
+                    image.image,
+                    image.size_bytes as f64 / 1_000_000.0
+                );
+            }
         }
 
         if args.verbose {
@@ -516,6 +1015,10 @@ async fn run_stats(cache_dir: &std::path::Path) -> Result<()> {
         stats.total_size_bytes as f64 / 1_000_000.0
     );
     println!("Total files: {}", stats.total_files);
+    println!(
+        "Stored archives: {:.2} MB (compressed, deduplicated in CAS)",
+        stats.archive_size_bytes as f64 / 1_000_000.0
+    );
 
     if stats.total_entries > 0 {
         println!(
@@ -528,7 +1031,12 @@ async fn run_stats(cache_dir: &std::path::Path) -> Result<()> {
 }
 
 /// Execute a remote recipe (from Git repository)
-async fn run_remote_recipe(recipe_ref: &str, args: &RunArgs) -> Result<()> {
+async fn run_remote_recipe(
+    recipe_ref: &str,
+    args: &RunArgs,
+    recipes_config: &RecipesConfig,
+    cache_dir: &Path,
+) -> Result<()> {
     if args.verbose {
         eprintln!("{} Parsing remote recipe: {}", fabrik_prefix(), recipe_ref);
     }
@@ -553,8 +1061,42 @@ async fn run_remote_recipe(recipe_ref: &str, args: &RunArgs) -> Result<()> {
         eprintln!("{} Fetching from {}", fabrik_prefix(), remote.git_url());
     }
 
+    let options = fetch_options(args)?;
     let script_path = remote
-        .fetch()
+        .fetch(&options, |event| match event {
+            FetchEvent::Failover {
+                url,
+                next_url,
+                error,
+            } => {
+                eprintln!(
+                    "{} Fetching from {} failed: {}",
+                    fabrik_prefix(),
+                    url,
+                    first_line(error)
+                );
+                eprintln!("{} Trying mirror {}", fabrik_prefix(), next_url);
+            }
+            FetchEvent::Retry {
+                attempt,
+                attempts,
+                delay,
+                error,
+            } => {
+                eprintln!(
+                    "{} Fetch failed (attempt {}/{}): {}",
+                    fabrik_prefix(),
+                    attempt,
+                    attempts,
+                    first_line(error)
+                );
+                eprintln!(
+                    "{} Retrying in {}s...",
+                    fabrik_prefix(),
+                    delay.as_secs_f64()
+                );
+            }
+        })
         .await
         .with_context(|| format!("Failed to fetch remote recipe: {}", recipe_ref))?;
 
@@ -566,8 +1108,47 @@ async fn run_remote_recipe(recipe_ref: &str, args: &RunArgs) -> Result<()> {
         );
     }
 
+    // Check the signature before any of the recipe runs
+    let policy = SignaturePolicy::new(&recipes_config.trusted_keys, recipes_config.require_signed)
+        .context("Invalid [recipes] configuration")?;
+    match policy
+        .check(&script_path)
+        .with_context(|| format!("Refusing to run remote recipe: {}", recipe_ref))?
+    {
+        Verification::Verified {
+            key_id,
+            trusted_comment,
+        } => {
+            if args.verbose {
+                eprintln!(
+                    "{} Signature verified (key {}): {}",
+                    fabrik_prefix(),
+                    key_id,
+                    trusted_comment
+                );
+            }
+        }
+        Verification::Unsigned => {
+            eprintln!(
+                "{} Warning: remote recipe {} is not signed",
+                fabrik_prefix(),
+                recipe_ref
+            );
+        }
+        Verification::NotChecked => {}
+    }
+
     // Execute recipe with RecipeExecutor
-    let executor = RecipeExecutor::new(script_path);
+    let executor = RecipeExecutor::new(script_path)
+        .with_exec_limits(exec_limits(args)?)
+        .with_permission_mode(
+            PermissionMode::parse(&args.recipe_permissions)
+                .context("Invalid --recipe-permissions")?,
+        )
+        .with_cache_dir(cache_dir.to_path_buf())
+        .with_args(args.script_args.clone())
+        .with_targets(args.recipe_targets())
+        .with_task_concurrency(args.task_concurrency);
 
     if args.verbose {
         eprintln!("{} Executing recipe at root level", fabrik_prefix());
@@ -581,6 +1162,36 @@ async fn run_remote_recipe(recipe_ref: &str, args: &RunArgs) -> Result<()> {
     Ok(())
 }
 
+/// Remote recipe fetch options from CLI flags / environment
+fn fetch_options(args: &RunArgs) -> Result<FetchOptions> {
+    let mirrors = args
+        .recipe_mirror
+        .iter()
+        .map(|mirror| Mirror::parse(mirror))
+        .collect::<Result<Vec<_>>>()
+        .context("Invalid --recipe-mirror")?;
+
+    Ok(FetchOptions {
+        retries: args.fetch_retries,
+        mirrors,
+        ..FetchOptions::default()
+    })
+}
+
+/// First line of a (possibly multi-line) git error
+fn first_line(error: &str) -> &str {
+    error.lines().next().unwrap_or_default()
+}
+
+/// Fabrik.exec limits for portable recipes from CLI flags / environment
+fn exec_limits(args: &RunArgs) -> Result<ExecLimits> {
+    ExecLimits::from_args(
+        args.exec_concurrency,
+        args.exec_cpu_budget.as_deref(),
+        args.exec_time_budget.as_deref(),
+    )
+}
+
 /// Check if a script file has a `fabrik run <runtime>` shebang
 ///
 /// This is used to distinguish between:
@@ -607,7 +1218,11 @@ fn has_fabrik_run_shebang(script_path: &Path) -> Result<bool> {
 }
 
 /// Execute a local portable recipe (.js file with QuickJS runtime)
-async fn run_local_portable_recipe(script_path: &Path, args: &RunArgs) -> Result<()> {
+async fn run_local_portable_recipe(
+    script_path: &Path,
+    args: &RunArgs,
+    cache_dir: &Path,
+) -> Result<()> {
     if args.verbose {
         eprintln!(
             "{} Running local portable recipe: {}",
@@ -624,7 +1239,16 @@ async fn run_local_portable_recipe(script_path: &Path, args: &RunArgs) -> Result
     };
 
     // Execute recipe with RecipeExecutor (QuickJS runtime)
-    let executor = RecipeExecutor::new(absolute_path);
+    let executor = RecipeExecutor::new(absolute_path)
+        .with_exec_limits(exec_limits(args)?)
+        .with_permission_mode(
+            PermissionMode::parse(&args.recipe_permissions)
+                .context("Invalid --recipe-permissions")?,
+        )
+        .with_cache_dir(cache_dir.to_path_buf())
+        .with_args(args.script_args.clone())
+        .with_targets(args.recipe_targets())
+        .with_task_concurrency(args.task_concurrency);
 
     if args.verbose {
         eprintln!("{} Executing recipe with QuickJS runtime", fabrik_prefix());
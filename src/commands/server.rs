@@ -1,15 +1,34 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal;
-use tracing::info;
+use tracing::{info, warn};
 
-use crate::cli::ServerArgs;
+use crate::auth::keys::{self, KeyRing, KeyRotation, KeySource};
+use crate::auth::layer::{AuthLayer, TokenValidator};
+use crate::auth::scopes::Grants;
+use crate::backup::{self, BackupStore};
+use crate::cli::{
+    ServerArgs, ServerBackupArgs, ServerCommand, ServerReplicateArgs, ServerRestoreArgs,
+};
+use crate::cli_utils::{fabrik_prefix, format_size, json_output};
+use crate::cluster::replication::{self, TargetFilter};
+use crate::cluster::{
+    Cluster, ClusterPeerService, ClusterStorage, PeerClient, ReplicatingStorage, Replicator,
+};
+use crate::events::{EventBus, EventStorage};
 use crate::eviction::{spawn_background_eviction, BackgroundEvictionConfig, EvictionConfig};
+use crate::grpc_introspection;
+use crate::grpc_transport::GrpcTransport;
+use crate::http::HealthChecks;
 use crate::merger::MergedServerConfig;
-use crate::storage::FilesystemStorage;
-use crate::xcode::proto::cas::casdb_service_server::CasdbServiceServer;
-use crate::xcode::proto::keyvalue::key_value_db_server::KeyValueDbServer;
-use crate::xcode::{CasService, KeyValueService};
+use crate::notifications::Notifier;
+use crate::p2p::RelayService;
+use crate::quota::UploadLimits;
+use crate::rate_limit::{RateLimitLayer, RateLimiter};
+use crate::retry::{RetryMetrics, RetryPolicy};
+use crate::storage::{compaction, FilesystemStorage, ScrubConfig, ScrubMetrics, WarmupConfig};
+use crate::xcode::{cas_server, keyvalue_server, CasService, KeyValueService};
 
 pub async fn run(args: ServerArgs) -> Result<()> {
     use crate::config_discovery::load_config_with_discovery;
@@ -17,6 +36,52 @@ pub async fn run(args: ServerArgs) -> Result<()> {
     // Load config file with auto-discovery
     let file_config = load_config_with_discovery(args.config.as_deref())?;
 
+    match &args.command {
+        Some(ServerCommand::Replicate(replicate_args)) => {
+            return replicate(&args, replicate_args, file_config).await;
+        }
+        Some(ServerCommand::Backup(backup_args)) => {
+            return backup(&args, backup_args, file_config).await;
+        }
+        Some(ServerCommand::Restore(restore_args)) => {
+            return restore(&args, restore_args, file_config).await;
+        }
+        None => {}
+    }
+
+    // P2P relay, cache warm-up and gRPC introspection and transport settings only come
+    // from the config file
+    let grpc_config = file_config
+        .as_ref()
+        .map(|c| c.grpc.clone())
+        .unwrap_or_default();
+    let grpc_transport = GrpcTransport::from_config(&grpc_config)?;
+    let retry = retry_policy(file_config.as_ref())?;
+    let p2p_config = file_config
+        .as_ref()
+        .map(|c| c.p2p.clone())
+        .unwrap_or_default();
+    let cache_config = file_config
+        .as_ref()
+        .map(|c| c.cache.clone())
+        .unwrap_or_default();
+    let cluster_config = file_config
+        .as_ref()
+        .map(|c| c.cluster.clone())
+        .unwrap_or_default();
+    let replication_config = file_config
+        .as_ref()
+        .map(|c| c.replication.clone())
+        .unwrap_or_default();
+    let backup_config = file_config
+        .as_ref()
+        .map(|c| c.backup.clone())
+        .unwrap_or_default();
+    let notifications_config = file_config
+        .as_ref()
+        .map(|c| c.notifications.clone())
+        .unwrap_or_default();
+
     // Merge configuration
     let config = MergedServerConfig::merge(&args, file_config);
 
@@ -28,31 +93,190 @@ pub async fn run(args: ServerArgs) -> Result<()> {
     info!("  Default TTL: {}", config.default_ttl);
     info!("  Upstream: {:?}", config.upstream);
     info!("  gRPC bind: {}", config.grpc_bind);
+    if config.rate_limit.is_enabled() {
+        info!(
+            "  Rate limit per client: {} req/s (burst {}), {} in flight",
+            config.rate_limit.requests_per_second,
+            config.rate_limit.burst,
+            config.rate_limit.max_in_flight
+        );
+    }
 
     // Initialize eviction configuration
     let eviction_config = EvictionConfig::from_cache_config(
         &config.max_cache_size,
         &config.eviction_policy,
         &config.default_ttl,
-    )?;
+    )?
+    .with_min_free_disk(cache_config.min_free_disk.as_deref())?;
 
-    // Initialize filesystem storage with eviction
+    // Initialize filesystem storage, with eviction unless it's served read-only
     info!("Initializing storage at {}", config.cache_dir);
-    let storage = Arc::new(FilesystemStorage::with_eviction(
-        &config.cache_dir,
-        Some(eviction_config.clone()),
-    )?);
-
-    // Spawn background eviction task
-    let eviction_handle = {
-        let bg_config = BackgroundEvictionConfig::from_eviction_config(eviction_config);
-        spawn_background_eviction(storage.clone(), bg_config)
+    let storage = if config.read_only {
+        info!("  Read-only: puts and deletes are rejected");
+        FilesystemStorage::read_only(&config.cache_dir)?
+    } else {
+        FilesystemStorage::with_eviction(&config.cache_dir, Some(eviction_config.clone()))?
+    }
+    .with_hash_algorithm(config.hash_algorithm)
+    .with_dedup(cache_config.dedup);
+    // Keep large and cold objects in a second directory, e.g. on a bigger, slower disk
+    let storage = Arc::new(match cache_config.tier_config()? {
+        Some(tiers) => storage.with_cold_tier(tiers)?,
+        None => storage,
+    });
+
+    // Warm the metadata block cache so the first requests after a restart don't wait on disk
+    if cache_config.warmup {
+        let warmup = WarmupConfig::parse(&cache_config.warmup_window, &cache_config.warmup_rate)?;
+        info!(
+            "Warming metadata cache in the background (window: {}, rate: {}/s)",
+            cache_config.warmup_window, cache_config.warmup_rate
+        );
+        storage.spawn_warmup(warmup)?;
+    }
+
+    // Re-hash stored objects in the background so bit rot is caught before it's served
+    let scrub_metrics = if cache_config.scrub && !config.read_only {
+        let scrub = ScrubConfig::parse(&cache_config.scrub_rate, &cache_config.scrub_bandwidth)?;
+        info!(
+            "Scrubbing cache in the background (rate: {}/hour, bandwidth: {}/s)",
+            cache_config.scrub_rate, cache_config.scrub_bandwidth
+        );
+        let metrics = Arc::new(ScrubMetrics::new());
+        storage.spawn_scrub(scrub, metrics.clone())?;
+        Some(metrics)
+    } else {
+        None
     };
-    info!("Background eviction task started");
+
+    // Reclaim the shard directories and metadata space left behind by eviction
+    if let Some(interval) = cache_config
+        .compact_interval
+        .as_ref()
+        .filter(|_| !config.read_only)
+    {
+        info!("Compacting storage every {}", interval);
+        storage.spawn_compaction(compaction::parse_interval(interval)?)?;
+    }
+
+    // Snapshot the cache to a bucket or directory so a replacement can start warm
+    if let (Some(url), Some(interval)) = (&backup_config.url, &backup_config.interval) {
+        if config.read_only {
+            warn!("Scheduled backups are disabled: a read-only cache can't be snapshotted");
+        } else {
+            let store = Arc::new(BackupStore::open(url, &config)?.with_retry(retry.clone()));
+            info!(
+                "Backing up storage to {} every {}",
+                store.describe(),
+                interval
+            );
+            backup::spawn_scheduled(
+                storage.clone(),
+                config.cache_dir.clone().into(),
+                store,
+                compaction::parse_interval(interval)?,
+                backup_config.jobs,
+            );
+        }
+    }
+
+    // Writes, reads, evictions and replication pushes, watched by the webhook notifier
+    let events = Arc::new(EventBus::new());
+
+    // Webhook notifications of a nearly full cache, eviction storms, failed replication
+    // pushes and authentication errors
+    let notifier = Notifier::from_config(&notifications_config)?
+        .map(|notifier| Arc::new(notifier.with_retry(retry.clone())));
+    if let Some(notifier) = &notifier {
+        let max_size_bytes = match config.read_only {
+            true => 0,
+            false => eviction_config.max_size_bytes,
+        };
+        notifier
+            .clone()
+            .spawn_monitor(storage.clone(), max_size_bytes, &events);
+    }
+
+    // Spawn background eviction task (a read-only cache is never evicted)
+    let eviction_handle = (!config.read_only).then(|| {
+        let bg_config = BackgroundEvictionConfig::from_eviction_config(eviction_config)
+            .with_events(events.clone());
+        info!("Background eviction task started");
+        spawn_background_eviction(storage.clone(), bg_config)
+    });
+
+    // Upload size limits and daily quotas
+    let upload_limits = Arc::new(UploadLimits::from_config(&config.limits)?);
+    if upload_limits.is_enabled() {
+        info!("Upload limits: {:?}", config.limits);
+    }
+
+    // JWT verification keys (loaded below); with them, every protocol must authenticate
+    let key_sources = KeySource::from_config(
+        config.jwt_public_key_file.as_deref(),
+        config.jwt_public_key.as_deref(),
+        config.jwt_jwks_url.as_deref(),
+    );
+
+    // Route blobs to their owners when clustered with other Layer 2 servers
+    let cluster =
+        Arc::new(Cluster::new(&cluster_config, grpc_transport)?.with_retry(retry.clone()));
+    if cluster.is_clustered() {
+        info!(
+            "Cluster node {} with {} peer(s), {} replica(s) per blob",
+            cluster.node(),
+            cluster_config.peers.len(),
+            cluster_config.replicas
+        );
+        if cluster_config.secret.is_none() {
+            // The cluster protocol isn't subject to JWT scopes and upload quotas
+            if !key_sources.is_empty() {
+                anyhow::bail!(
+                    "[cluster] secret must be set when [auth] is configured: without it any client can read and write through the cluster protocol"
+                );
+            }
+            warn!("[cluster] secret is not set: any client can read and write through the cluster protocol");
+        }
+        let heartbeat = EvictionConfig::parse_ttl(&cluster_config.heartbeat_interval)?;
+        cluster
+            .clone()
+            .spawn(storage.clone(), Duration::from_secs(heartbeat));
+    }
+    let cluster_storage = Arc::new(ClusterStorage::new(storage.clone(), cluster.clone()));
+
+    // Other nodes and regions reach this one over the cluster protocol; a standalone
+    // server only serves it to receive replication with a secret set
+    let cluster_service = (cluster.is_clustered() || cluster_config.secret.is_some()).then(|| {
+        ClusterPeerService::new(storage.clone(), cluster_config.secret.clone())
+            .with_notifier(notifier.clone())
+            .with_routing(cluster_storage.clone())
+            .into_server()
+    });
+
+    // Push newly written artifacts to the other regions (a no-op without targets)
+    let replicator = Arc::new(
+        Replicator::new(&replication_config, grpc_transport)?
+            .with_retry(retry.clone())
+            .with_events(events.clone()),
+    );
+    for target in &replication_config.targets {
+        info!("Replicating artifacts to {}", target.url);
+    }
+    replicator.spawn(cluster_storage.clone());
+    let cache_storage = Arc::new(EventStorage::new(
+        Arc::new(ReplicatingStorage::new(
+            cluster_storage.clone(),
+            replicator.clone(),
+        )),
+        events.clone(),
+    ));
 
     // Create gRPC services
-    let cas_service = CasService::new(storage.clone());
-    let keyvalue_service = KeyValueService::new(storage.clone());
+    let cas_service =
+        CasService::new(cache_storage.clone()).with_upload_limits(upload_limits.clone());
+    let keyvalue_service =
+        KeyValueService::new(cache_storage.clone()).with_upload_limits(upload_limits.clone());
 
     // Parse gRPC bind address
     let addr = config
@@ -64,10 +288,97 @@ pub async fn run(args: ServerArgs) -> Result<()> {
     info!("  - CAS (Content-Addressable Storage) service");
     info!("  - KeyValue database service");
 
+    // Peer rendezvous and relay for P2P across subnets
+    let relay_service = if p2p_config.relay_server {
+        let secret = p2p_config
+            .secret
+            .filter(|secret| secret.len() >= 16)
+            .context("p2p.secret (at least 16 characters) must be set to serve the P2P relay")?;
+        info!("  - P2P relay service");
+        Some(RelayService::new(secret).into_server())
+    } else {
+        None
+    };
+
+    // JWT verification keys, reloaded in the background so they can be rotated live
+    let key_ring = if key_sources.is_empty() {
+        None
+    } else {
+        let rotation = KeyRotation::parse(&config.jwt_key_refresh, &config.jwt_key_grace_period)?;
+        info!(
+            "JWT authentication enabled (keys refreshed every {}, grace period {})",
+            config.jwt_key_refresh, config.jwt_key_grace_period
+        );
+        Some(keys::start(key_sources, rotation, notifier.clone()).await)
+    };
+    let anonymous_grants = Grants::from_scopes(&config.jwt_anonymous_scopes)
+        .context("Invalid auth.anonymous_scopes")?;
+    let validator = key_ring.clone().map(|ring| {
+        Arc::new(
+            TokenValidator::new(ring, config.jwt_default_scopes.clone())
+                .with_required(config.jwt_required)
+                .with_anonymous_grants(anonymous_grants),
+        )
+    });
+
+    // Per-client rate limiting (pass-through when no limits are configured)
+    let rate_limiter = Arc::new(RateLimiter::new(config.rate_limit));
+
+    // Liveness and readiness probes for orchestrators
+    if config.health_enabled {
+        let upstreams = match config.readiness_check_upstreams {
+            true => config.upstream.clone(),
+            false => vec![],
+        };
+        HealthChecks::new(storage.clone())
+            .with_key_ring(key_ring.clone())
+            .with_upstreams(upstreams)
+            .serve(&config.health_bind)
+            .await?;
+    }
+
+    if config.metrics_enabled {
+        spawn_metrics_server(
+            &config.api_bind,
+            rate_limiter.clone(),
+            scrub_metrics,
+            key_ring,
+            (!replication_config.targets.is_empty()).then_some(replicator),
+            retry.metrics(),
+        )
+        .await?;
+    }
+
+    // Health checking and reflection of the served services
+    let mut service_names = crate::xcode::SERVICE_NAMES.to_vec();
+    let mut descriptor_sets = vec![crate::xcode::proto::FILE_DESCRIPTOR_SET];
+    if relay_service.is_some() {
+        service_names.push(crate::p2p::proto::p2p_relay_server::SERVICE_NAME);
+        descriptor_sets.push(crate::p2p::proto::FILE_DESCRIPTOR_SET);
+    }
+    if cluster_service.is_some() {
+        service_names.push(crate::cluster::proto::cluster_peer_server::SERVICE_NAME);
+        descriptor_sets.push(crate::cluster::proto::FILE_DESCRIPTOR_SET);
+    }
+    let introspection =
+        grpc_introspection::routes(&grpc_config, &service_names, &descriptor_sets).await?;
+
+    // The cluster protocol and the P2P relay authenticate with their own secrets
+    let auth = AuthLayer::new(validator).with_exempt_services(&[
+        crate::cluster::proto::cluster_peer_server::SERVICE_NAME,
+        crate::p2p::proto::p2p_relay_server::SERVICE_NAME,
+    ]);
+
     // Start gRPC server with graceful shutdown
-    let server = tonic::transport::Server::builder()
-        .add_service(CasdbServiceServer::new(cas_service))
-        .add_service(KeyValueDbServer::new(keyvalue_service))
+    let server = grpc_transport
+        .server()
+        .layer(auth)
+        .layer(RateLimitLayer::new(rate_limiter.clone()))
+        .add_routes(introspection)
+        .add_service(cas_server(cas_service))
+        .add_service(keyvalue_server(keyvalue_service))
+        .add_optional_service(relay_service)
+        .add_optional_service(cluster_service)
         .serve_with_shutdown(addr, async {
             // Wait for shutdown signal
             #[cfg(unix)]
@@ -96,8 +407,256 @@ pub async fn run(args: ServerArgs) -> Result<()> {
 
     // Shutdown background eviction task
     info!("Shutting down background eviction task...");
-    eviction_handle.shutdown().await;
+    if let Some(eviction_handle) = eviction_handle {
+        eviction_handle.shutdown().await;
+    }
 
     info!("Server shutdown complete");
     Ok(())
 }
+
+/// Retries of requests to other nodes, regions and backup buckets
+fn retry_policy(file_config: Option<&crate::config::FabrikConfig>) -> Result<RetryPolicy> {
+    match file_config {
+        Some(c) => RetryPolicy::from_config(&c.retry),
+        None => Ok(RetryPolicy::default()),
+    }
+}
+
+/// Serve Prometheus metrics on the API bind address
+async fn spawn_metrics_server(
+    bind: &str,
+    rate_limiter: Arc<RateLimiter>,
+    scrub_metrics: Option<Arc<ScrubMetrics>>,
+    key_ring: Option<Arc<KeyRing>>,
+    replicator: Option<Arc<Replicator>>,
+    retry_metrics: Arc<RetryMetrics>,
+) -> Result<()> {
+    use axum::{routing::get, Router};
+
+    let app = Router::new().route(
+        "/metrics",
+        get(move || {
+            let rate_limiter = rate_limiter.clone();
+            let scrub_metrics = scrub_metrics.clone();
+            let key_ring = key_ring.clone();
+            let replicator = replicator.clone();
+            let retry_metrics = retry_metrics.clone();
+            async move {
+                let mut output = rate_limiter.metrics().export_prometheus();
+                if let Some(scrub_metrics) = scrub_metrics {
+                    output.push_str(&scrub_metrics.export_prometheus());
+                }
+                if let Some(key_ring) = key_ring {
+                    output.push_str(&key_ring.export_prometheus());
+                }
+                if let Some(replicator) = replicator {
+                    output.push_str(&replicator.export_prometheus());
+                }
+                output.push_str(&retry_metrics.export_prometheus());
+                output
+            }
+        }),
+    );
+
+    let listener = tokio::net::TcpListener::bind(bind)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to bind metrics API to {}: {}", bind, e))?;
+    info!("Metrics available at http://{}/metrics", bind);
+
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("Metrics server failed: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+/// Copy the artifacts another region's server has and the target is missing
+async fn replicate(
+    args: &ServerArgs,
+    replicate_args: &ServerReplicateArgs,
+    file_config: Option<crate::config::FabrikConfig>,
+) -> Result<()> {
+    let cluster_secret = file_config.as_ref().and_then(|c| c.cluster.secret.clone());
+    let transport = match &file_config {
+        Some(c) => GrpcTransport::from_config(&c.grpc)?,
+        None => GrpcTransport::default(),
+    };
+    let retry = retry_policy(file_config.as_ref())?;
+    let config = MergedServerConfig::merge(args, file_config);
+
+    let filter = TargetFilter::parse(
+        &replicate_args.namespaces,
+        replicate_args.max_size.as_deref(),
+    )?;
+    let to = replicate_args
+        .to
+        .clone()
+        .unwrap_or_else(|| config.grpc_bind.replace("0.0.0.0", "127.0.0.1"));
+    let secret = replicate_args.secret.clone().or(cluster_secret);
+    let from_secret = replicate_args.from_secret.clone().or(secret.clone());
+
+    let source =
+        PeerClient::new(&replicate_args.from, from_secret, transport)?.with_retry(retry.clone());
+    let target = PeerClient::new(&to, secret, transport)?.with_retry(retry);
+    let json = json_output(replicate_args.json);
+    if !json {
+        println!(
+            "{} Copying missing artifacts from {} to {}",
+            fabrik_prefix(),
+            replicate_args.from,
+            to
+        );
+    }
+    let stats = replication::backfill(&source, &target, &filter)
+        .await
+        .context("Replication failed")?;
+
+    if json {
+        println!("{}", serde_json::to_string(&stats)?);
+    } else {
+        println!(
+            "{} Copied {} artifact(s), {} already present, {} skipped by the filters, {} failed ({} listed)",
+            fabrik_prefix(),
+            stats.copied,
+            stats.present,
+            stats.skipped,
+            stats.failed,
+            stats.listed
+        );
+    }
+
+    match stats.failed {
+        0 => Ok(()),
+        n => anyhow::bail!("{} artifact(s) couldn't be copied", n),
+    }
+}
+
+/// Back up the cache as a consistent snapshot
+async fn backup(
+    args: &ServerArgs,
+    backup_args: &ServerBackupArgs,
+    file_config: Option<crate::config::FabrikConfig>,
+) -> Result<()> {
+    let cache_config = file_config
+        .as_ref()
+        .map(|c| c.cache.clone())
+        .unwrap_or_default();
+    let backup_config = file_config
+        .as_ref()
+        .map(|c| c.backup.clone())
+        .unwrap_or_default();
+    let retry = retry_policy(file_config.as_ref())?;
+    let config = MergedServerConfig::merge(args, file_config);
+
+    let url = backup_args
+        .to
+        .clone()
+        .or(backup_config.url)
+        .context("No backup location: pass --to or set [backup] url")?;
+    let store = Arc::new(BackupStore::open(&url, &config)?.with_retry(retry));
+    let jobs = backup_args.jobs.unwrap_or(backup_config.jobs);
+
+    // The running server holds the metadata database; it backs itself up with
+    // [backup] interval
+    let storage = FilesystemStorage::new(&config.cache_dir)
+        .context(
+            "Failed to open the cache (a running server backs itself up with [backup] interval)",
+        )?
+        .with_hash_algorithm(config.hash_algorithm);
+    let storage = Arc::new(match cache_config.tier_config()? {
+        Some(tiers) => storage.with_cold_tier(tiers)?,
+        None => storage,
+    });
+
+    let json = json_output(backup_args.json);
+    if !json {
+        println!(
+            "{} Backing up {} to {}",
+            fabrik_prefix(),
+            config.cache_dir,
+            store.describe()
+        );
+    }
+    let stats = backup::backup(storage, config.cache_dir.as_ref(), store, jobs)
+        .await
+        .context("Backup failed")?;
+
+    if json {
+        println!("{}", serde_json::to_string(&stats)?);
+    } else {
+        println!(
+            "{} Snapshot {}: {} object(s), {} uploaded ({}), {} unchanged, {} missing, {} corrupt",
+            fabrik_prefix(),
+            stats.snapshot,
+            stats.objects,
+            stats.uploaded,
+            format_size(stats.uploaded_bytes),
+            stats.unchanged,
+            stats.missing,
+            stats.corrupt
+        );
+    }
+    Ok(())
+}
+
+/// Restore a snapshot into an empty cache directory
+async fn restore(
+    args: &ServerArgs,
+    restore_args: &ServerRestoreArgs,
+    file_config: Option<crate::config::FabrikConfig>,
+) -> Result<()> {
+    let cache_config = file_config
+        .as_ref()
+        .map(|c| c.cache.clone())
+        .unwrap_or_default();
+    let backup_config = file_config
+        .as_ref()
+        .map(|c| c.backup.clone())
+        .unwrap_or_default();
+    let retry = retry_policy(file_config.as_ref())?;
+    let config = MergedServerConfig::merge(args, file_config);
+
+    let url = restore_args
+        .from
+        .clone()
+        .or(backup_config.url)
+        .context("No backup location: pass --from or set [backup] url")?;
+    let store = Arc::new(BackupStore::open(&url, &config)?.with_retry(retry));
+    let jobs = restore_args.jobs.unwrap_or(backup_config.jobs);
+
+    let json = json_output(restore_args.json);
+    if !json {
+        println!(
+            "{} Restoring {} from {}",
+            fabrik_prefix(),
+            config.cache_dir,
+            store.describe()
+        );
+    }
+    let stats = backup::restore(
+        config.cache_dir.as_ref(),
+        cache_config.tier_config()?,
+        store,
+        restore_args.snapshot.as_deref(),
+        jobs,
+    )
+    .await
+    .context("Restore failed")?;
+
+    if json {
+        println!("{}", serde_json::to_string(&stats)?);
+    } else {
+        println!(
+            "{} Restored snapshot {}: {} object(s) ({}), {} missing from the backup",
+            fabrik_prefix(),
+            stats.snapshot,
+            stats.restored,
+            format_size(stats.restored_bytes),
+            stats.missing
+        );
+    }
+    Ok(())
+}
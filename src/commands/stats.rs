@@ -0,0 +1,173 @@
+/// `fabrik stats` command implementation
+///
+/// Reports on how the local cache is used, e.g. the most accessed artifacts to pre-seed
+/// caches for new offices or CI pools, or the cache traffic of a single build invocation
+/// (from the running daemon, see `daemon_stats`).
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::top::running_daemon;
+use crate::cli::{StatsArgs, StatsCommand};
+use crate::cli_utils::{fabrik_prefix, format_size, json_output};
+use crate::daemon_stats::InvocationStats;
+use crate::storage::default_cache_dir;
+use crate::storage::popularity::{read_popular, PopularArtifact, RETENTION_DAYS};
+
+// JSON output structures
+#[derive(Serialize, Deserialize)]
+struct PopularOutput {
+    days: u32,
+    artifacts: Vec<PopularEntry>,
+    total_bytes_served: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PopularEntry {
+    hash: String,
+    hits: u64,
+    bytes_served: u64,
+    /// Null if the artifact has been evicted since
+    size_bytes: Option<u64>,
+}
+
+impl From<PopularArtifact> for PopularEntry {
+    fn from(artifact: PopularArtifact) -> Self {
+        Self {
+            hash: hex::encode(&artifact.id),
+            hits: artifact.hits,
+            bytes_served: artifact.bytes_served,
+            size_bytes: artifact.size,
+        }
+    }
+}
+
+pub async fn run(args: &StatsArgs) -> Result<()> {
+    let cache_dir = args
+        .config_cache_dir
+        .as_deref()
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(default_cache_dir);
+
+    match (&args.command, &args.invocation) {
+        (None, Some(id)) => invocation(args.config.as_deref(), id, json_output(args.json)),
+        (Some(StatsCommand::Popular { days, top, json }), None) => {
+            popular(&cache_dir, *days, *top, json_output(*json)).await
+        }
+        (Some(_), Some(_)) => anyhow::bail!("--invocation can't be combined with a subcommand"),
+        (None, None) => {
+            anyhow::bail!("Specify a subcommand (e.g. `fabrik stats popular`) or --invocation")
+        }
+    }
+}
+
+/// Show the cache traffic of build invocation `id`, as recorded by the running daemon
+fn invocation(config: Option<&str>, id: &str, json: bool) -> Result<()> {
+    let state = running_daemon(config)?;
+    let path = format!(
+        "/api/v1/invocations/{}",
+        percent_encoding::utf8_percent_encode(id, percent_encoding::NON_ALPHANUMERIC)
+    );
+    let body = state
+        .http_get(&path, Duration::from_secs(2))
+        .with_context(|| {
+            format!(
+                "Failed to read invocation {} from daemon {} (the daemon only knows \
+                 invocations since it started)",
+                id, state.pid
+            )
+        })?;
+    let stats: InvocationStats =
+        serde_json::from_str(&body).context("Malformed invocation statistics from daemon")?;
+
+    if json {
+        println!("{}", serde_json::to_string(&stats)?);
+        return Ok(());
+    }
+
+    let counters = &stats.counters;
+    let duration = (stats.last_request - stats.first_request).max(0) / 1000;
+    println!("{} Invocation {}:", fabrik_prefix(), stats.id);
+    println!();
+    println!("  Requests:    {}", counters.requests);
+    println!(
+        "  Hits:        {} ({} hit ratio)",
+        counters.hits,
+        counters
+            .hit_ratio()
+            .map(|ratio| format!("{:.1}%", ratio * 100.0))
+            .unwrap_or_else(|| "no".to_string())
+    );
+    println!("  Misses:      {}", counters.misses);
+    println!("  Writes:      {}", counters.writes);
+    println!("  Errors:      {}", counters.errors);
+    println!("  Downloaded:  {}", format_size(stats.bytes_downloaded));
+    println!("  Uploaded:    {}", format_size(stats.bytes_uploaded));
+    println!("  Active for:  {}s", duration);
+
+    Ok(())
+}
+
+/// List the most accessed artifacts
+async fn popular(cache_dir: &std::path::Path, days: u32, top: usize, json: bool) -> Result<()> {
+    if days == 0 || days > RETENTION_DAYS {
+        anyhow::bail!(
+            "--days must be between 1 and {} (access counters are kept for {} days)",
+            RETENTION_DAYS,
+            RETENTION_DAYS
+        );
+    }
+
+    let artifacts = read_popular(cache_dir, days, top)
+        .with_context(|| format!("Failed to read access counters in {}", cache_dir.display()))?;
+    let output = PopularOutput {
+        days,
+        total_bytes_served: artifacts.iter().map(|a| a.bytes_served).sum(),
+        artifacts: artifacts.into_iter().map(PopularEntry::from).collect(),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string(&output)?);
+        return Ok(());
+    }
+
+    if output.artifacts.is_empty() {
+        println!(
+            "{} No artifacts accessed in the last {} days.",
+            fabrik_prefix(),
+            days
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{} Top {} artifacts of the last {} days:",
+        fabrik_prefix(),
+        output.artifacts.len(),
+        days
+    );
+    println!();
+    println!(
+        "  {:<64}  {:>8}  {:>12}  {:>12}",
+        "HASH", "HITS", "SERVED", "SIZE"
+    );
+    for entry in &output.artifacts {
+        println!(
+            "  {:<64}  {:>8}  {:>12}  {:>12}",
+            entry.hash,
+            entry.hits,
+            format_size(entry.bytes_served),
+            entry
+                .size_bytes
+                .map(format_size)
+                .unwrap_or_else(|| "evicted".to_string())
+        );
+    }
+    println!();
+    println!(
+        "Total served: {} (listed artifacts)",
+        format_size(output.total_bytes_served)
+    );
+
+    Ok(())
+}
@@ -0,0 +1,424 @@
+/// `fabrik top` command implementation
+///
+/// A live dashboard of the daemon serving the current directory: request rates and hit
+/// ratios per protocol, cache size against its limit, evictions, and the most recent
+/// requests, polled from the daemon's `GET /api/v1/stats` (see `daemon_stats`).
+use anyhow::{Context, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::cli::TopArgs;
+use crate::cli_utils::{format_size, json_output};
+use crate::config_discovery::{discover_config, hash_config, DaemonState};
+use crate::daemon_stats::{DaemonStats, Outcome, ProtocolCounters};
+use crate::error::FabrikError;
+
+/// How long to wait for the daemon's statistics
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn run(args: TopArgs) -> Result<()> {
+    let interval = Duration::try_from_secs_f64(args.interval)
+        .ok()
+        .filter(|interval| !interval.is_zero())
+        .with_context(|| format!("Invalid refresh interval: {}", args.interval))?;
+    let state = running_daemon(args.config.as_deref())?;
+    let stats = fetch(&state)?;
+
+    if json_output(args.json) {
+        println!("{}", serde_json::to_string(&stats)?);
+        return Ok(());
+    }
+
+    let mut dashboard = Dashboard::default();
+    dashboard.update(Ok(stats), Instant::now());
+
+    let mut terminal = ratatui::init();
+    let result = run_dashboard(&mut terminal, &state, &mut dashboard, interval);
+    ratatui::restore();
+    result
+}
+
+/// Daemon serving the config at `config`, or the one discovered from the current
+/// directory
+pub(crate) fn running_daemon(config: Option<&str>) -> Result<DaemonState> {
+    let config_path = match config {
+        Some(path) => Some(PathBuf::from(path)),
+        None => discover_config(&std::env::current_dir()?)?,
+    };
+    let config_path = config_path.ok_or_else(|| {
+        FabrikError::config("No fabrik.toml found in this directory or its parents")
+    })?;
+    let state = DaemonState::load_running(&hash_config(&config_path)?)?.ok_or_else(|| {
+        FabrikError::unavailable(format!(
+            "No daemon is running for {}",
+            config_path.display()
+        ))
+    })?;
+
+    if state.http_port == 0 && state.http_socket.is_none() {
+        return Err(FabrikError::unavailable(format!(
+            "The daemon (PID {}) only serves Xcode on a Unix socket, without statistics",
+            state.pid
+        ))
+        .into());
+    }
+    Ok(state)
+}
+
+fn fetch(state: &DaemonState) -> Result<DaemonStats> {
+    let body = state
+        .http_get("/api/v1/stats", REQUEST_TIMEOUT)
+        .with_context(|| format!("Failed to read statistics of daemon {}", state.pid))?;
+    serde_json::from_str(&body).context("Malformed statistics from daemon")
+}
+
+/// Redraw and refresh every `interval` until `q`, Esc or Ctrl+C
+fn run_dashboard(
+    terminal: &mut DefaultTerminal,
+    state: &DaemonState,
+    dashboard: &mut Dashboard,
+    interval: Duration,
+) -> Result<()> {
+    let mut next_refresh = Instant::now() + interval;
+    loop {
+        terminal.draw(|frame| render(frame, dashboard))?;
+
+        if event::poll(next_refresh.saturating_duration_since(Instant::now()))? {
+            if let Event::Key(key) = event::read()? {
+                let quit = matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                    || (key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL));
+                if quit && key.kind == KeyEventKind::Press {
+                    return Ok(());
+                }
+            }
+            // Other keys and resizes just redraw
+            continue;
+        }
+
+        dashboard.update(fetch(state), Instant::now());
+        next_refresh = Instant::now() + interval;
+    }
+}
+
+/// What the dashboard shows: the latest statistics and rates since the refresh before
+#[derive(Default)]
+struct Dashboard {
+    stats: Option<DaemonStats>,
+    fetched_at: Option<Instant>,
+    /// Requests per second of each protocol
+    rates: BTreeMap<String, f64>,
+    /// Objects evicted since the previous refresh
+    new_evictions: u64,
+    /// Why the last refresh failed (the previous statistics stay on screen)
+    error: Option<String>,
+}
+
+impl Dashboard {
+    fn update(&mut self, result: Result<DaemonStats>, now: Instant) {
+        let stats = match result {
+            Ok(stats) => stats,
+            Err(e) => {
+                self.error = Some(format!("{:#}", e));
+                return;
+            }
+        };
+        self.error = None;
+
+        // Counters start over when the daemon restarts, so rates bottom out at zero
+        if let (Some(previous), Some(fetched_at)) = (&self.stats, self.fetched_at) {
+            let elapsed = now.duration_since(fetched_at).as_secs_f64();
+            if elapsed > 0.0 {
+                self.rates = stats
+                    .protocols
+                    .iter()
+                    .map(|(protocol, counters)| {
+                        let before = previous.protocols.get(protocol).map_or(0, |c| c.requests);
+                        let delta = counters.requests.saturating_sub(before);
+                        (protocol.clone(), delta as f64 / elapsed)
+                    })
+                    .collect();
+            }
+            self.new_evictions = stats
+                .evictions
+                .objects
+                .saturating_sub(previous.evictions.objects);
+        }
+        self.stats = Some(stats);
+        self.fetched_at = Some(now);
+    }
+}
+
+fn render(frame: &mut Frame, dashboard: &Dashboard) {
+    let Some(stats) = &dashboard.stats else {
+        return;
+    };
+
+    let [header, usage, protocols, recent, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(5),
+        Constraint::Length(stats.protocols.len().max(1) as u16 + 3),
+        Constraint::Min(4),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    frame.render_widget(
+        Line::from(format!(
+            "fabrik top - daemon {} (v{}), up {}",
+            stats.pid,
+            stats.version,
+            format_uptime(stats.uptime_seconds)
+        ))
+        .bold(),
+        header,
+    );
+
+    let [cache, activity] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(usage);
+    frame.render_widget(cache_gauge(stats), cache);
+    frame.render_widget(activity_summary(stats, dashboard.new_evictions), activity);
+
+    frame.render_widget(protocol_table(stats, &dashboard.rates), protocols);
+    frame.render_widget(recent_table(stats), recent);
+
+    let status = match &dashboard.error {
+        Some(error) => Line::from(error.as_str()).red(),
+        None => Line::from("q: quit").dark_gray(),
+    };
+    frame.render_widget(status, footer);
+}
+
+fn cache_gauge(stats: &DaemonStats) -> Gauge<'_> {
+    let cache = &stats.cache;
+    let (ratio, label) = match cache.max_bytes {
+        0 => (
+            0.0,
+            format!("{} in {} objects", format_size(cache.bytes), cache.objects),
+        ),
+        max => (
+            (cache.bytes as f64 / max as f64).min(1.0),
+            format!(
+                "{} of {} in {} objects",
+                format_size(cache.bytes),
+                format_size(max),
+                cache.objects
+            ),
+        ),
+    };
+    Gauge::default()
+        .block(Block::bordered().title("Cache"))
+        .gauge_style(Style::default().fg(Color::Cyan))
+        .ratio(ratio)
+        .label(label)
+}
+
+fn activity_summary(stats: &DaemonStats, new_evictions: u64) -> Paragraph<'_> {
+    let total =
+        stats
+            .protocols
+            .values()
+            .fold(ProtocolCounters::default(), |mut total, counters| {
+                total.hits += counters.hits;
+                total.misses += counters.misses;
+                total
+            });
+    let evictions = &stats.evictions;
+    let lines = vec![
+        Line::from(format!(
+            "Hit ratio: {} ({} hits, {} misses)",
+            format_ratio(total.hit_ratio()),
+            total.hits,
+            total.misses
+        )),
+        Line::from(format!(
+            "Evicted: {} objects ({}) in {} runs, {} since last refresh",
+            evictions.objects,
+            format_size(evictions.bytes),
+            evictions.runs,
+            new_evictions
+        )),
+        Line::from(format!(
+            "Upstream queue: {}",
+            stats
+                .upstream_queue
+                .map_or_else(|| "-".to_string(), |depth| depth.to_string())
+        )),
+    ];
+    Paragraph::new(lines).block(Block::bordered().title("Activity"))
+}
+
+fn protocol_table<'a>(stats: &'a DaemonStats, rates: &BTreeMap<String, f64>) -> Table<'a> {
+    let rows = stats.protocols.iter().map(|(protocol, counters)| {
+        Row::new(vec![
+            protocol.clone(),
+            format!("{:.1}", rates.get(protocol).copied().unwrap_or_default()),
+            counters.requests.to_string(),
+            counters.hits.to_string(),
+            counters.misses.to_string(),
+            counters.writes.to_string(),
+            counters.errors.to_string(),
+            format_ratio(counters.hit_ratio()),
+        ])
+    });
+    Table::new(rows, [Constraint::Length(10); 8])
+        .header(
+            Row::new([
+                "Protocol",
+                "Req/s",
+                "Requests",
+                "Hits",
+                "Misses",
+                "Writes",
+                "Errors",
+                "Hit ratio",
+            ])
+            .add_modifier(Modifier::BOLD),
+        )
+        .block(Block::bordered().title("Requests"))
+}
+
+fn recent_table(stats: &DaemonStats) -> Table<'_> {
+    let rows = stats.recent.iter().map(|request| {
+        let time = chrono::DateTime::from_timestamp_millis(request.timestamp)
+            .map(|time| {
+                time.with_timezone(&chrono::Local)
+                    .format("%H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        let (outcome, color) = match request.outcome {
+            Outcome::Hit => ("hit", Color::Green),
+            Outcome::Miss => ("miss", Color::Yellow),
+            Outcome::Write => ("write", Color::Blue),
+            Outcome::Error => ("error", Color::Red),
+            Outcome::Other => ("-", Color::Reset),
+        };
+        Row::new(vec![
+            time.into(),
+            request.protocol.as_str().into(),
+            request.method.as_str().into(),
+            outcome.fg(color),
+            format!("{} ms", request.duration_ms).into(),
+            request.path.as_str().into(),
+        ])
+    });
+    Table::new(
+        rows,
+        [
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(6),
+            Constraint::Length(5),
+            Constraint::Length(8),
+            Constraint::Fill(1),
+        ],
+    )
+    .header(
+        Row::new(["Time", "Protocol", "Method", "Result", "Duration", "Path"])
+            .add_modifier(Modifier::BOLD),
+    )
+    .block(Block::bordered().title("Recent requests"))
+}
+
+fn format_ratio(ratio: Option<f64>) -> String {
+    ratio.map_or_else(|| "-".to_string(), |ratio| format!("{:.1}%", ratio * 100.0))
+}
+
+fn format_uptime(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        3600..=86399 => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+        _ => format!("{}d {}h", seconds / 86400, seconds % 86400 / 3600),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon_stats::{CacheUsage, EvictionCounters, RecentRequest};
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn stats(requests: u64, evicted: u64) -> DaemonStats {
+        DaemonStats {
+            version: "2.0.0".to_string(),
+            pid: 42,
+            uptime_seconds: 3725,
+            protocols: BTreeMap::from([(
+                "gradle".to_string(),
+                ProtocolCounters {
+                    requests,
+                    hits: requests / 2,
+                    misses: requests / 2,
+                    ..Default::default()
+                },
+            )]),
+            cache: CacheUsage {
+                objects: 3,
+                bytes: 512 * 1024 * 1024,
+                max_bytes: 1024 * 1024 * 1024,
+            },
+            evictions: EvictionCounters {
+                objects: evicted,
+                bytes: evicted * 1024,
+                runs: 1,
+            },
+            upstream_queue: None,
+            recent: vec![RecentRequest {
+                timestamp: 0,
+                protocol: "gradle".to_string(),
+                method: "GET".to_string(),
+                path: "/cache/abc123".to_string(),
+                outcome: Outcome::Miss,
+                duration_ms: 3,
+                invocation: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_rates_since_previous_refresh() {
+        let mut dashboard = Dashboard::default();
+        let start = Instant::now();
+        dashboard.update(Ok(stats(10, 1)), start);
+        dashboard.update(Ok(stats(30, 4)), start + Duration::from_secs(2));
+
+        assert_eq!(dashboard.rates["gradle"], 10.0);
+        assert_eq!(dashboard.new_evictions, 3);
+
+        // A failed refresh keeps the statistics on screen
+        dashboard.update(Err(anyhow::anyhow!("daemon gone")), start);
+        assert_eq!(dashboard.error.as_deref(), Some("daemon gone"));
+        assert!(dashboard.stats.is_some());
+    }
+
+    #[test]
+    fn test_render() {
+        let mut dashboard = Dashboard::default();
+        dashboard.update(Ok(stats(10, 0)), Instant::now());
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 24)).unwrap();
+        terminal.draw(|frame| render(frame, &dashboard)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+
+        assert!(screen.contains("daemon 42 (v2.0.0), up 1h 02m"));
+        assert!(screen.contains("512.0 MB of 1.0 GB"));
+        assert!(screen.contains("Hit ratio: 50.0%"));
+        assert!(screen.contains("/cache/abc123"));
+    }
+}
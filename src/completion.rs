@@ -0,0 +1,211 @@
+/// Dynamic shell completion of cache hashes and keys
+///
+/// `fabrik cas get <TAB>` and `fabrik kv get <TAB>` complete from what is actually in
+/// the cache. The shell hooks installed by `fabrik activate <shell>` call the hidden
+/// `fabrik __complete <kind> <prefix>` command, which asks the daemon serving the
+/// current directory for matching entries and falls back to reading the local store
+/// directly when no daemon is running. Completion must never get in the way of the
+/// shell, so every failure results in no candidates rather than an error.
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config_discovery::{discover_config, hash_config, DaemonState};
+use crate::eviction::EvictionConfig;
+use crate::storage::{FilesystemStorage, Storage};
+
+/// Maximum number of candidates returned (shells get sluggish with more)
+pub const DEFAULT_LIMIT: usize = 100;
+
+/// How long to wait for the daemon before falling back to the local store
+const DAEMON_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Namespace prefix of KV entries in the store (see `fabrik kv`)
+const KV_PREFIX: &[u8] = b"kv:";
+
+/// Shell of a completion script generated by `fabrik completions`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+    #[value(name = "pwsh", alias = "powershell")]
+    PowerShell,
+    Elvish,
+}
+
+impl CompletionShell {
+    pub fn generator(self) -> clap_complete::Shell {
+        match self {
+            CompletionShell::Bash => clap_complete::Shell::Bash,
+            CompletionShell::Zsh => clap_complete::Shell::Zsh,
+            CompletionShell::Fish => clap_complete::Shell::Fish,
+            CompletionShell::PowerShell => clap_complete::Shell::PowerShell,
+            CompletionShell::Elvish => clap_complete::Shell::Elvish,
+        }
+    }
+}
+
+/// What is being completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CompletionKind {
+    /// Content hashes of CAS blobs (hex)
+    Cas,
+    /// Keys of KV entries
+    Kv,
+}
+
+impl CompletionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompletionKind::Cas => "cas",
+            CompletionKind::Kv => "kv",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "cas" => Some(CompletionKind::Cas),
+            "kv" => Some(CompletionKind::Kv),
+            _ => None,
+        }
+    }
+
+    /// Display form of a store id, or None if the id is of another kind
+    fn candidate(&self, id: &[u8]) -> Option<String> {
+        match self {
+            CompletionKind::Cas if !id.starts_with(KV_PREFIX) => Some(hex::encode(id)),
+            CompletionKind::Kv => id
+                .strip_prefix(KV_PREFIX)
+                .and_then(|key| String::from_utf8(key.to_vec()).ok()),
+            _ => None,
+        }
+    }
+}
+
+/// Store entries of `kind` starting with `prefix`, sorted, at most `limit` of them
+pub fn matching_ids(
+    ids: &[Vec<u8>],
+    kind: CompletionKind,
+    prefix: &str,
+    limit: usize,
+) -> Vec<String> {
+    let mut matches: Vec<String> = ids
+        .iter()
+        .filter_map(|id| kind.candidate(id))
+        .filter(|candidate| candidate.starts_with(prefix))
+        .collect();
+    matches.sort();
+    matches.truncate(limit);
+    matches
+}
+
+/// Candidates from the local store in `cache_dir`
+pub fn complete_from_store(
+    cache_dir: &Path,
+    kind: CompletionKind,
+    prefix: &str,
+    limit: usize,
+) -> Result<Vec<String>> {
+    if !cache_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let storage = FilesystemStorage::with_eviction(cache_dir, Some(EvictionConfig::default()))?;
+    Ok(matching_ids(&storage.list_ids()?, kind, prefix, limit))
+}
+
+/// Candidates from the daemon serving the current directory, if one is running
+pub fn complete_from_daemon(
+    kind: CompletionKind,
+    prefix: &str,
+    limit: usize,
+) -> Result<Option<Vec<String>>> {
+    let current_dir = std::env::current_dir().context("Failed to get current directory")?;
+    let Some(config_path) = discover_config(&current_dir)? else {
+        return Ok(None);
+    };
+    let Some(state) = DaemonState::load_running(&hash_config(&config_path)?)? else {
+        return Ok(None);
+    };
+
+    query_daemon(&state, kind, prefix, limit).map(Some)
+}
+
+/// `GET /api/v1/complete/{kind}` on the daemon's HTTP port (or socket)
+fn query_daemon(
+    state: &DaemonState,
+    kind: CompletionKind,
+    prefix: &str,
+    limit: usize,
+) -> Result<Vec<String>> {
+    let path = format!(
+        "/api/v1/complete/{}?prefix={}&limit={}",
+        kind.as_str(),
+        encode_query_value(prefix),
+        limit
+    );
+    let body = state.http_get(&path, DAEMON_TIMEOUT)?;
+
+    Ok(body
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Percent-encode a query parameter value
+fn encode_query_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids() -> Vec<Vec<u8>> {
+        vec![
+            vec![0xab, 0xcd, 0x01],
+            vec![0xab, 0x12],
+            vec![0x12, 0x34],
+            b"kv:build/linux".to_vec(),
+            b"kv:build/macos".to_vec(),
+            b"kv:test".to_vec(),
+        ]
+    }
+
+    #[test]
+    fn test_matching_cas_hashes() {
+        assert_eq!(
+            matching_ids(&ids(), CompletionKind::Cas, "ab", DEFAULT_LIMIT),
+            vec!["ab12", "abcd01"]
+        );
+        assert_eq!(
+            matching_ids(&ids(), CompletionKind::Cas, "", 1),
+            vec!["1234"]
+        );
+    }
+
+    #[test]
+    fn test_matching_kv_keys() {
+        assert_eq!(
+            matching_ids(&ids(), CompletionKind::Kv, "build/", DEFAULT_LIMIT),
+            vec!["build/linux", "build/macos"]
+        );
+        assert!(matching_ids(&ids(), CompletionKind::Kv, "deploy", DEFAULT_LIMIT).is_empty());
+    }
+
+    #[test]
+    fn test_encode_query_value() {
+        assert_eq!(encode_query_value("abc-123_.~"), "abc-123_.~");
+        assert_eq!(encode_query_value("build/linux x86"), "build%2Flinux%20x86");
+        assert_eq!(encode_query_value("100%"), "100%25");
+    }
+}
@@ -3,74 +3,134 @@ use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
 
-use crate::xdg;
+use crate::config_layers::ConfigLayers;
+use crate::{unix_socket, xdg};
 
-/// Discovers Fabrik configuration by traversing up the directory tree
-pub fn discover_config(start_dir: &Path) -> Result<Option<PathBuf>> {
-    let mut current = start_dir.to_path_buf();
-
-    loop {
-        let config_path = current.join("fabrik.toml");
-        if config_path.exists() {
-            return Ok(Some(config_path));
-        }
+/// Profile selected with `--profile` (or `FABRIK_PROFILE`)
+static PROFILE: OnceLock<String> = OnceLock::new();
 
-        // Try to go up one level
-        match current.parent() {
-            Some(parent) => current = parent.to_path_buf(),
-            None => break,
-        }
+/// Select the `[profile.<name>]` applied to every config loaded afterwards
+pub fn select_profile(name: Option<String>) {
+    if let Some(name) = name {
+        let _ = PROFILE.set(name);
     }
+}
 
+/// Profile applied to loaded configs, if any
+pub fn selected_profile() -> Option<&'static str> {
+    PROFILE.get().map(String::as_str)
+}
+
+/// Line of a project's `.bazelrc` that loads the cache settings the daemon writes into
+/// the project (for Bazel versions without `BAZELRC` support)
+pub const BAZELRC_IMPORT: &str = "try-import %workspace%/.fabrik/bazelrc";
+
+/// Extensions of config files, in order of preference when a directory has several
+const CONFIG_EXTENSIONS: [&str; 4] = ["toml", "yaml", "yml", "json"];
+
+/// Config file named `name` (with any config extension) in `dir`, if there's one
+fn find_config_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    CONFIG_EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{}.{}", name, ext)))
+        .find(|path| path.exists())
+}
+
+/// Path of the user-level config (`~/.config/fabrik/config.toml`), if it exists
+pub fn user_config() -> Option<PathBuf> {
+    find_config_file(&dirs::home_dir()?.join(".config/fabrik"), "config")
+}
+
+/// Finds the project's `fabrik.toml` (or `.yaml`, `.yml`, `.json`) by traversing up the
+/// directory tree
+pub fn discover_project_config(start_dir: &Path) -> Option<PathBuf> {
+    start_dir
+        .ancestors()
+        .find_map(|dir| find_config_file(dir, "fabrik"))
+}
+
+/// Discovers Fabrik configuration by traversing up the directory tree
+pub fn discover_config(start_dir: &Path) -> Result<Option<PathBuf>> {
     // Fallback to global config
-    if let Some(home) = dirs::home_dir() {
-        let global_config = home.join(".config/fabrik/config.toml");
-        if global_config.exists() {
-            return Ok(Some(global_config));
-        }
-    }
+    Ok(discover_project_config(start_dir).or_else(user_config))
+}
 
-    Ok(None)
+/// Config files layered for `explicit_path` or, without one, discovered from `start_dir`:
+/// the user config, then the project config
+pub fn config_files(explicit_path: Option<&str>, start_dir: &Path) -> Vec<PathBuf> {
+    if let Some(path) = explicit_path {
+        return vec![PathBuf::from(path)];
+    }
+    let user = user_config();
+    let project = discover_project_config(start_dir).filter(|path| Some(path) != user.as_ref());
+    user.into_iter().chain(project).collect()
 }
 
 /// Computes a hash of the configuration file for daemon identification
+///
+/// The user config and the selected profile are part of the hash, so a daemon started
+/// with other settings isn't reused.
 pub fn hash_config(config_path: &Path) -> Result<String> {
     let content = fs::read_to_string(config_path)
         .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
 
     let mut hasher = Sha256::new();
     hasher.update(content.as_bytes());
+    if let Some(user) = user_config().filter(|user| user != config_path) {
+        if let Ok(user_content) = fs::read_to_string(user) {
+            hasher.update(b"\0user\0");
+            hasher.update(user_content.as_bytes());
+        }
+    }
+    if let Some(profile) = selected_profile() {
+        hasher.update(b"\0profile\0");
+        hasher.update(profile.as_bytes());
+    }
     let result = hasher.finalize();
 
     Ok(format!("{:x}", result)[..16].to_string())
 }
 
-/// Loads configuration with auto-discovery support
+/// Loads the config layers with auto-discovery support, with the selected profile applied
 ///
-/// If `explicit_path` is provided, loads config from that path.
-/// Otherwise, auto-discovers config by traversing up directory tree from cwd.
+/// If `explicit_path` is provided, loads config from that path only. Otherwise, layers the
+/// user config and the project config discovered by traversing up the directory tree
+/// from cwd (see `config_layers`).
+///
+/// Returns Ok(None) if no config is found (neither explicit nor discovered).
+pub fn load_config_layers(explicit_path: Option<&str>) -> Result<Option<ConfigLayers>> {
+    let current_dir =
+        std::env::current_dir().context("Failed to get current directory for config discovery")?;
+    let files = config_files(explicit_path, &current_dir);
+    if files.is_empty() {
+        if let Some(profile) = selected_profile() {
+            anyhow::bail!(
+                "Profile '{}' is selected but no config file was found",
+                profile
+            );
+        }
+        return Ok(None);
+    }
+
+    let mut layers = ConfigLayers::load(&files)?;
+    if let Some(profile) = selected_profile() {
+        layers.apply_profile(profile)?;
+    }
+    Ok(Some(layers))
+}
+
+/// Loads configuration with auto-discovery support
 ///
 /// Returns Ok(None) if no config is found (neither explicit nor discovered).
 pub fn load_config_with_discovery(
     explicit_path: Option<&str>,
 ) -> Result<Option<crate::config::FabrikConfig>> {
-    use crate::config::FabrikConfig;
-
-    if let Some(config_path) = explicit_path {
-        // Explicit path provided - load it
-        Ok(Some(FabrikConfig::from_file(config_path)?))
-    } else {
-        // Auto-discover by traversing up directory tree
-        let current_dir = std::env::current_dir()
-            .context("Failed to get current directory for config discovery")?;
-
-        if let Some(discovered_path) = discover_config(&current_dir)? {
-            Ok(Some(FabrikConfig::from_file(&discovered_path)?))
-        } else {
-            Ok(None)
-        }
-    }
+    load_config_layers(explicit_path)?
+        .map(|layers| layers.config())
+        .transpose()
 }
 
 /// Daemon state information
@@ -81,7 +141,10 @@ pub struct DaemonState {
     pub http_port: u16,
     pub grpc_port: u16,
     pub metrics_port: u16,
+    pub s3_port: Option<u16>,         // When the S3 API is enabled
     pub unix_socket: Option<PathBuf>, // For Xcode integration
+    pub http_socket: Option<PathBuf>, // When HTTP is served on a socket instead of http_port
+    pub grpc_socket: Option<PathBuf>, // When gRPC is served on a socket instead of grpc_port
     pub config_path: PathBuf,
 }
 
@@ -97,7 +160,12 @@ impl DaemonState {
     }
 
     pub fn state_dir(&self) -> PathBuf {
-        Self::state_base_dir().join(&self.config_hash)
+        Self::state_dir_for(&self.config_hash)
+    }
+
+    /// State directory of the daemon serving the config with `config_hash`
+    pub fn state_dir_for(config_hash: &str) -> PathBuf {
+        Self::state_base_dir().join(config_hash)
     }
 
     pub fn pid_file(&self) -> PathBuf {
@@ -113,13 +181,36 @@ impl DaemonState {
         self.state_dir().join("env")
     }
 
+    /// Lock file claiming `config_hash` for one daemon
+    ///
+    /// It lives next to the state directory, not in it, so removing stale state never
+    /// deletes a lock that is held.
+    fn lock_file(config_hash: &str) -> PathBuf {
+        Self::state_base_dir().join(format!("{}.lock", config_hash))
+    }
+
+    /// Claim `config_hash` for this process, None if another daemon holds it
+    pub fn try_lock(config_hash: &str) -> Result<Option<DaemonLock>> {
+        let base_dir = Self::state_base_dir();
+        fs::create_dir_all(&base_dir)
+            .with_context(|| format!("Failed to create state dir: {}", base_dir.display()))?;
+        DaemonLock::try_acquire(&Self::lock_file(config_hash))
+    }
+
+    /// Whether a daemon holds the lock of `config_hash` (assumed when it can't be checked)
+    fn is_locked(config_hash: &str) -> bool {
+        let path = Self::lock_file(config_hash);
+        path.exists() && !matches!(DaemonLock::try_acquire(&path), Ok(Some(_)))
+    }
+
     pub fn save(&self) -> Result<()> {
         let state_dir = self.state_dir();
         fs::create_dir_all(&state_dir)
             .with_context(|| format!("Failed to create state dir: {}", state_dir.display()))?;
 
-        // Save PID
-        fs::write(self.pid_file(), self.pid.to_string()).context("Failed to write PID file")?;
+        // Save PID (each file is replaced atomically, so readers never see partial state)
+        write_atomic(&self.pid_file(), self.pid.to_string().as_bytes())
+            .context("Failed to write PID file")?;
 
         // Save ports and socket
         let mut ports = serde_json::json!({
@@ -128,16 +219,29 @@ impl DaemonState {
             "metrics": self.metrics_port,
         });
 
+        if let Some(s3_port) = self.s3_port {
+            ports["s3"] = serde_json::json!(s3_port);
+        }
+
         if let Some(ref socket) = self.unix_socket {
             ports["unix_socket"] = serde_json::json!(socket.to_string_lossy());
         }
+        if let Some(ref socket) = self.http_socket {
+            ports["http_socket"] = serde_json::json!(socket.to_string_lossy());
+        }
+        if let Some(ref socket) = self.grpc_socket {
+            ports["grpc_socket"] = serde_json::json!(socket.to_string_lossy());
+        }
 
-        fs::write(self.ports_file(), serde_json::to_string_pretty(&ports)?)
-            .context("Failed to write ports file")?;
+        write_atomic(
+            &self.ports_file(),
+            serde_json::to_string_pretty(&ports)?.as_bytes(),
+        )
+        .context("Failed to write ports file")?;
 
         // Save config path
         let config_file = state_dir.join("config_path.txt");
-        fs::write(config_file, self.config_path.to_string_lossy().as_bytes())
+        write_atomic(&config_file, self.config_path.to_string_lossy().as_bytes())
             .context("Failed to write config path")?;
 
         // Write .fabrik/bazelrc for automatic Bazel configuration
@@ -153,7 +257,7 @@ impl DaemonState {
         let state_dir = self.state_dir();
 
         // Generate bazelrc content
-        let grpc_url = format!("grpc://127.0.0.1:{}", self.grpc_port);
+        let grpc_url = self.endpoints().grpc_url;
         let bazelrc_content = format!(
             "# Auto-generated by Fabrik daemon\n\
              # This file is updated automatically when the daemon starts\n\
@@ -170,9 +274,22 @@ impl DaemonState {
 
         // Write bazelrc file to state directory
         let bazelrc_path = state_dir.join("bazelrc");
-        fs::write(&bazelrc_path, bazelrc_content)
+        fs::write(&bazelrc_path, &bazelrc_content)
             .with_context(|| format!("Failed to write bazelrc: {}", bazelrc_path.display()))?;
 
+        // Projects whose .bazelrc imports .fabrik/bazelrc (see `fabrik init`) get a copy
+        let project_dir = self.config_path.parent().unwrap_or(Path::new("."));
+        let imports = fs::read_to_string(project_dir.join(".bazelrc"))
+            .is_ok_and(|bazelrc| bazelrc.lines().any(|line| line.trim() == BAZELRC_IMPORT));
+        if imports {
+            let project_bazelrc = project_dir.join(".fabrik/bazelrc");
+            fs::create_dir_all(project_dir.join(".fabrik"))
+                .and_then(|()| fs::write(&project_bazelrc, &bazelrc_content))
+                .with_context(|| {
+                    format!("Failed to write bazelrc: {}", project_bazelrc.display())
+                })?;
+        }
+
         Ok(())
     }
 
@@ -191,6 +308,37 @@ impl DaemonState {
         Ok(())
     }
 
+    /// Remove the state if no daemon holds the config's lock, so the state of a daemon
+    /// that is starting up is kept; returns whether it was removed
+    pub fn cleanup_stale(&self) -> Result<bool> {
+        match Self::try_lock(&self.config_hash)? {
+            Some(_lock) => {
+                self.cleanup()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// State of the live daemon serving `config_hash`, removing the state a crashed
+    /// daemon left behind
+    pub fn load_running(config_hash: &str) -> Result<Option<Self>> {
+        let Some(state) = Self::load(config_hash)? else {
+            return Ok(None);
+        };
+        if state.is_running() {
+            return Ok(Some(state));
+        }
+        if state.cleanup_stale()? {
+            tracing::debug!(
+                "Removed stale state of daemon {} (PID {})",
+                config_hash,
+                state.pid
+            );
+        }
+        Ok(None)
+    }
+
     pub fn load(config_hash: &str) -> Result<Option<Self>> {
         let state_dir = Self::state_base_dir().join(config_hash);
 
@@ -230,18 +378,125 @@ impl DaemonState {
             http_port: ports["http"].as_u64().unwrap() as u16,
             grpc_port: ports["grpc"].as_u64().unwrap() as u16,
             metrics_port: ports["metrics"].as_u64().unwrap() as u16,
+            s3_port: ports["s3"].as_u64().map(|port| port as u16),
             unix_socket,
+            http_socket: ports["http_socket"].as_str().map(PathBuf::from),
+            grpc_socket: ports["grpc_socket"].as_str().map(PathBuf::from),
             config_path,
         }))
     }
 
+    /// Load the state of every daemon that has a state directory
+    ///
+    /// Unreadable or partially written state directories are skipped.
+    pub fn load_all() -> Result<Vec<Self>> {
+        let base_dir = Self::state_base_dir();
+        if !base_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut states = Vec::new();
+        for entry in fs::read_dir(&base_dir)
+            .with_context(|| format!("Failed to read state dir: {}", base_dir.display()))?
+        {
+            let entry = entry?;
+            if !entry.path().is_dir() {
+                continue;
+            }
+            if let Some(config_hash) = entry.file_name().to_str() {
+                if let Ok(Some(state)) = Self::load(config_hash) {
+                    states.push(state);
+                }
+            }
+        }
+
+        Ok(states)
+    }
+
+    /// Path of the log file for the daemon serving `config_hash`
+    pub fn log_file(config_hash: &str) -> PathBuf {
+        xdg::logs_dir().join(format!("daemon-{}.log", config_hash))
+    }
+
+    /// Whether the daemon is alive: its process exists and it holds the config's lock or
+    /// answers on its endpoint (a process that reused the PID of a crashed daemon does
+    /// neither)
     pub fn is_running(&self) -> bool {
-        is_process_running(self.pid)
+        is_process_running(self.pid) && (Self::is_locked(&self.config_hash) || self.responds())
+    }
+
+    /// Whether the daemon accepts connections on its Unix socket or HTTP port
+    fn responds(&self) -> bool {
+        #[cfg(unix)]
+        if let Some(socket) = self.unix_socket.as_ref().or(self.http_socket.as_ref()) {
+            return unix_socket::connect(socket).is_ok();
+        }
+        let address = std::net::SocketAddr::from(([127, 0, 0, 1], self.http_port));
+        self.http_port != 0
+            && std::net::TcpStream::connect_timeout(&address, Duration::from_millis(200)).is_ok()
+    }
+
+    /// Body of `GET <path>` on the daemon's HTTP port (or socket), failing on any status
+    /// but 200
+    pub fn http_get(&self, path: &str, timeout: Duration) -> Result<String> {
+        use std::io::{Read, Write};
+
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\r\n",
+            path
+        );
+        let mut response = Vec::new();
+        match self.http_socket {
+            #[cfg(unix)]
+            Some(ref socket) => {
+                let mut stream = unix_socket::connect(socket)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                stream.write_all(request.as_bytes())?;
+                stream.read_to_end(&mut response)?;
+            }
+            _ => {
+                let addr = std::net::SocketAddr::from(([127, 0, 0, 1], self.http_port));
+                let mut stream = std::net::TcpStream::connect_timeout(&addr, timeout)?;
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                stream.write_all(request.as_bytes())?;
+                stream.read_to_end(&mut response)?;
+            }
+        }
+        let response = String::from_utf8_lossy(&response);
+
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .context("Malformed response from daemon")?;
+        let status = head.lines().next().unwrap_or_default();
+        if !status.contains(" 200 ") {
+            anyhow::bail!("Daemon returned {}", status);
+        }
+        Ok(body.to_string())
+    }
+
+    /// Endpoints the daemon serves the cache on
+    pub fn endpoints(&self) -> Endpoints {
+        Endpoints {
+            http_url: self
+                .http_socket
+                .is_none()
+                .then(|| format!("http://127.0.0.1:{}", self.http_port)),
+            http_socket: self.http_socket.clone(),
+            grpc_url: match self.grpc_socket {
+                Some(ref socket) => unix_socket::grpc_url(socket),
+                None => format!("grpc://127.0.0.1:{}", self.grpc_port),
+            },
+            s3_url: self
+                .s3_port
+                .map(|port| format!("http://127.0.0.1:{}", port)),
+            unix_socket: self.unix_socket.clone(),
+        }
     }
 
     pub fn generate_env_exports(&self, shell: &str) -> String {
-        let http_url = format!("http://127.0.0.1:{}", self.http_port);
-        let grpc_url = format!("grpc://127.0.0.1:{}", self.grpc_port);
+        let endpoints = self.endpoints();
         let bazelrc_path = self.bazelrc_file();
 
         let mut exports = Vec::new();
@@ -249,8 +504,16 @@ impl DaemonState {
         match shell {
             "fish" => {
                 // Fabrik-specific variables
-                exports.push(format!("set -gx FABRIK_HTTP_URL {}", http_url));
-                exports.push(format!("set -gx FABRIK_GRPC_URL {}", grpc_url));
+                if let Some(ref http_url) = endpoints.http_url {
+                    exports.push(format!("set -gx FABRIK_HTTP_URL {}", http_url));
+                }
+                if let Some(ref socket) = endpoints.http_socket {
+                    exports.push(format!("set -gx FABRIK_HTTP_SOCKET {}", socket.display()));
+                }
+                exports.push(format!("set -gx FABRIK_GRPC_URL {}", endpoints.grpc_url));
+                if let Some(ref s3_url) = endpoints.s3_url {
+                    exports.push(format!("set -gx FABRIK_S3_URL {}", s3_url));
+                }
                 exports.push(format!("set -gx FABRIK_CONFIG_HASH {}", self.config_hash));
                 exports.push(format!("set -gx FABRIK_DAEMON_PID {}", self.pid));
 
@@ -264,16 +527,82 @@ impl DaemonState {
 
                 // Build tool variables
                 exports.extend(generate_build_tool_shell_exports(
-                    &http_url,
+                    endpoints.http_url.as_deref(),
                     self.unix_socket.as_deref(),
                     "fish",
                 ));
             }
+            "powershell" => {
+                // Fabrik-specific variables
+                if let Some(ref http_url) = endpoints.http_url {
+                    exports.push(powershell_export("FABRIK_HTTP_URL", http_url));
+                }
+                if let Some(ref socket) = endpoints.http_socket {
+                    exports.push(powershell_export(
+                        "FABRIK_HTTP_SOCKET",
+                        &socket.display().to_string(),
+                    ));
+                }
+                exports.push(powershell_export("FABRIK_GRPC_URL", &endpoints.grpc_url));
+                if let Some(ref s3_url) = endpoints.s3_url {
+                    exports.push(powershell_export("FABRIK_S3_URL", s3_url));
+                }
+                exports.push(powershell_export("FABRIK_CONFIG_HASH", &self.config_hash));
+                exports.push(powershell_export(
+                    "FABRIK_DAEMON_PID",
+                    &self.pid.to_string(),
+                ));
+
+                // Bazel configuration file
+                exports.push(powershell_export(
+                    "BAZELRC",
+                    &bazelrc_path.display().to_string(),
+                ));
+
+                // Unix socket for Xcode
+                if let Some(ref socket) = self.unix_socket {
+                    exports.push(powershell_export(
+                        "FABRIK_UNIX_SOCKET",
+                        &socket.display().to_string(),
+                    ));
+                }
+
+                // Build tool variables
+                exports.extend(generate_build_tool_shell_exports(
+                    endpoints.http_url.as_deref(),
+                    self.unix_socket.as_deref(),
+                    "powershell",
+                ));
+            }
+            "nu" => {
+                // Nushell can't evaluate generated code, so its hook loads a JSON record
+                // instead (TURBO_TEAM/TURBO_TOKEN are only included when unset)
+                let mut env_vars: std::collections::BTreeMap<String, String> =
+                    populate_build_tool_env_vars(&endpoints)
+                        .into_iter()
+                        .collect();
+                if let Some(s3_url) = endpoints.s3_url {
+                    env_vars.insert("FABRIK_S3_URL".to_string(), s3_url);
+                }
+                env_vars.insert("FABRIK_CONFIG_HASH".to_string(), self.config_hash.clone());
+                env_vars.insert("FABRIK_DAEMON_PID".to_string(), self.pid.to_string());
+                env_vars.insert("BAZELRC".to_string(), bazelrc_path.display().to_string());
+
+                return serde_json::json!({ "set": env_vars, "unset": [] }).to_string();
+            }
             _ => {
                 // bash/zsh
                 // Fabrik-specific variables
-                exports.push(format!("export FABRIK_HTTP_URL={}", http_url));
-                exports.push(format!("export FABRIK_GRPC_URL={}", grpc_url));
+                if let Some(ref http_url) = endpoints.http_url {
+                    exports.push(format!("export FABRIK_HTTP_URL={}", http_url));
+                }
+                if let Some(ref socket) = endpoints.http_socket {
+                    exports.push(format!("export FABRIK_HTTP_SOCKET={}", socket.display()));
+                }
+                exports.push(format!("export FABRIK_GRPC_URL={}", endpoints.grpc_url));
+                if let Some(ref s3_url) = endpoints.s3_url {
+                    exports.push(format!("export FABRIK_S3_URL={}", s3_url));
+                }
                 exports.push(format!("export FABRIK_CONFIG_HASH={}", self.config_hash));
                 exports.push(format!("export FABRIK_DAEMON_PID={}", self.pid));
 
@@ -287,7 +616,7 @@ impl DaemonState {
 
                 // Build tool variables
                 exports.extend(generate_build_tool_shell_exports(
-                    &http_url,
+                    endpoints.http_url.as_deref(),
                     self.unix_socket.as_deref(),
                     "bash",
                 ));
@@ -298,6 +627,39 @@ impl DaemonState {
     }
 }
 
+/// Exclusive claim of a config hash by a daemon, released when dropped (or when the
+/// process exits, however it exits)
+#[derive(Debug)]
+pub struct DaemonLock {
+    _file: fs::File,
+}
+
+impl DaemonLock {
+    /// Lock `path`, None if another process (or another lock in this one) holds it
+    fn try_acquire(path: &Path) -> Result<Option<Self>> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open lock file: {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Self { _file: file })),
+            Err(fs::TryLockError::WouldBlock) => Ok(None),
+            Err(fs::TryLockError::Error(e)) => {
+                Err(e).with_context(|| format!("Failed to lock {}", path.display()))
+            }
+        }
+    }
+}
+
+/// Write `contents` to a temporary file next to `path` and rename it into place
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let temp = path.with_extension(format!("tmp.{}", std::process::id()));
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)
+}
+
 /// Generate a unique token for TurboRepo local development
 /// Uses PID XOR timestamp for uniqueness across process restarts
 pub fn generate_turbo_token() -> String {
@@ -316,27 +678,63 @@ pub fn default_turbo_team() -> &'static str {
     "fabrik-local"
 }
 
+/// Cache endpoints build tools are pointed at, by a daemon or `fabrik exec`
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    /// None when HTTP is only served on `http_socket`
+    pub http_url: Option<String>,
+    pub http_socket: Option<PathBuf>,
+    /// `grpc://host:port`, or `unix:<path>` when gRPC is served on a socket
+    pub grpc_url: String,
+    pub s3_url: Option<String>,
+    /// Socket of the Xcode services
+    pub unix_socket: Option<PathBuf>,
+}
+
 /// Populate all build tool environment variables
 /// Returns a HashMap with environment variables for Gradle, Nx, Xcode, TurboRepo, etc.
+///
+/// Gradle, Nx and TurboRepo only speak HTTP over TCP, so they're left out when HTTP is
+/// served on a socket.
 pub fn populate_build_tool_env_vars(
-    http_url: String,
-    grpc_url: String,
-    unix_socket: Option<std::path::PathBuf>,
+    endpoints: &Endpoints,
 ) -> std::collections::HashMap<String, String> {
     let mut env_vars = std::collections::HashMap::new();
 
     // Generic Fabrik URLs
-    env_vars.insert("FABRIK_HTTP_URL".to_string(), http_url.clone());
-    env_vars.insert("FABRIK_GRPC_URL".to_string(), grpc_url);
+    if let Some(ref http_url) = endpoints.http_url {
+        env_vars.insert("FABRIK_HTTP_URL".to_string(), http_url.clone());
+    }
+    if let Some(ref socket) = endpoints.http_socket {
+        env_vars.insert(
+            "FABRIK_HTTP_SOCKET".to_string(),
+            socket.display().to_string(),
+        );
+    }
+    env_vars.insert("FABRIK_GRPC_URL".to_string(), endpoints.grpc_url.clone());
 
     // Unix socket for Xcode (if available)
-    if let Some(ref socket) = unix_socket {
+    if let Some(ref socket) = endpoints.unix_socket {
         env_vars.insert(
             "FABRIK_UNIX_SOCKET".to_string(),
             socket.display().to_string(),
         );
     }
 
+    // Xcode (prefer Unix socket if available)
+    if let Some(ref socket) = endpoints.unix_socket {
+        env_vars.insert(
+            "XCODE_CACHE_SERVER".to_string(),
+            socket.display().to_string(),
+        );
+    } else if let Some(ref http_url) = endpoints.http_url {
+        env_vars.insert("XCODE_CACHE_SERVER".to_string(), http_url.clone());
+    }
+
+    let Some(http_url) = endpoints.http_url.clone() else {
+        return env_vars;
+    };
+
     // Gradle
     env_vars.insert("GRADLE_BUILD_CACHE_URL".to_string(), http_url.clone());
 
@@ -346,16 +744,6 @@ pub fn populate_build_tool_env_vars(
         http_url.clone(),
     );
 
-    // Xcode (prefer Unix socket if available)
-    if let Some(socket) = unix_socket {
-        env_vars.insert(
-            "XCODE_CACHE_SERVER".to_string(),
-            socket.display().to_string(),
-        );
-    } else {
-        env_vars.insert("XCODE_CACHE_SERVER".to_string(), http_url.clone());
-    }
-
     // TurboRepo
     env_vars.insert("TURBO_API".to_string(), http_url);
     // Auto-generate TURBO_TEAM if not already set
@@ -371,16 +759,29 @@ pub fn populate_build_tool_env_vars(
 }
 
 /// Generate shell export statements for all build tool environment variables
-/// For use in shell activation hooks
+/// For use in shell activation hooks (see `populate_build_tool_env_vars`)
 fn generate_build_tool_shell_exports(
-    http_url: &str,
+    http_url: Option<&str>,
     unix_socket: Option<&std::path::Path>,
     shell: &str,
 ) -> Vec<String> {
     let mut exports = Vec::new();
 
+    // Xcode (prefer Unix socket if available)
+    let xcode_cache_server = match unix_socket {
+        Some(socket) => Some(socket.display().to_string()),
+        None => http_url.map(str::to_string),
+    };
+
     match shell {
         "fish" => {
+            if let Some(server) = xcode_cache_server {
+                exports.push(format!("set -gx XCODE_CACHE_SERVER {}", server));
+            }
+            let Some(http_url) = http_url else {
+                return exports;
+            };
+
             // Gradle
             exports.push(format!("set -gx GRADLE_BUILD_CACHE_URL {}", http_url));
 
@@ -390,13 +791,6 @@ fn generate_build_tool_shell_exports(
                 http_url
             ));
 
-            // Xcode (prefer Unix socket if available)
-            if let Some(socket) = unix_socket {
-                exports.push(format!("set -gx XCODE_CACHE_SERVER {}", socket.display()));
-            } else {
-                exports.push(format!("set -gx XCODE_CACHE_SERVER {}", http_url));
-            }
-
             // TurboRepo
             exports.push(format!("set -gx TURBO_API {}", http_url));
             exports.push(format!(
@@ -408,8 +802,43 @@ fn generate_build_tool_shell_exports(
                 generate_turbo_token()
             ));
         }
+        "powershell" => {
+            if let Some(server) = xcode_cache_server {
+                exports.push(powershell_export("XCODE_CACHE_SERVER", &server));
+            }
+            let Some(http_url) = http_url else {
+                return exports;
+            };
+
+            // Gradle
+            exports.push(powershell_export("GRADLE_BUILD_CACHE_URL", http_url));
+
+            // Nx
+            exports.push(powershell_export(
+                "NX_SELF_HOSTED_REMOTE_CACHE_SERVER",
+                http_url,
+            ));
+
+            // TurboRepo
+            exports.push(powershell_export("TURBO_API", http_url));
+            exports.push(format!(
+                "if (-not $env:TURBO_TEAM) {{ {} }}",
+                powershell_export("TURBO_TEAM", default_turbo_team())
+            ));
+            exports.push(format!(
+                "if (-not $env:TURBO_TOKEN) {{ {} }}",
+                powershell_export("TURBO_TOKEN", &generate_turbo_token())
+            ));
+        }
         _ => {
             // bash/zsh
+            if let Some(server) = xcode_cache_server {
+                exports.push(format!("export XCODE_CACHE_SERVER={}", server));
+            }
+            let Some(http_url) = http_url else {
+                return exports;
+            };
+
             // Gradle
             exports.push(format!("export GRADLE_BUILD_CACHE_URL={}", http_url));
 
@@ -419,13 +848,6 @@ fn generate_build_tool_shell_exports(
                 http_url
             ));
 
-            // Xcode (prefer Unix socket if available)
-            if let Some(socket) = unix_socket {
-                exports.push(format!("export XCODE_CACHE_SERVER={}", socket.display()));
-            } else {
-                exports.push(format!("export XCODE_CACHE_SERVER={}", http_url));
-            }
-
             // TurboRepo
             exports.push(format!("export TURBO_API={}", http_url));
             exports.push(format!(
@@ -442,9 +864,15 @@ fn generate_build_tool_shell_exports(
     exports
 }
 
+/// PowerShell statement setting the environment variable `name` to `value`
+fn powershell_export(name: &str, value: &str) -> String {
+    // Single-quoted strings are literal; a quote is escaped by doubling it
+    format!("$env:{} = '{}'", name, value.replace('\'', "''"))
+}
+
 /// Check if a process is running
 #[cfg(unix)]
-fn is_process_running(pid: u32) -> bool {
+pub(crate) fn is_process_running(pid: u32) -> bool {
     use nix::sys::signal::{kill, Signal};
     use nix::unistd::Pid;
 
@@ -452,7 +880,7 @@ fn is_process_running(pid: u32) -> bool {
 }
 
 #[cfg(windows)]
-fn is_process_running(pid: u32) -> bool {
+pub(crate) fn is_process_running(pid: u32) -> bool {
     use winapi::um::handleapi::CloseHandle;
     use winapi::um::processthreadsapi::OpenProcess;
     use winapi::um::winnt::PROCESS_QUERY_INFORMATION;
@@ -491,6 +919,26 @@ mod tests {
         // Search from subdir should find project config
         let found = discover_config(&subdir).unwrap();
         assert_eq!(found, Some(config_path));
+
+        // YAML and JSON configs are discovered too
+        let yaml_path = subdir.join("fabrik.yaml");
+        fs::write(&yaml_path, "cache: {}").unwrap();
+        assert_eq!(discover_project_config(&subdir), Some(yaml_path));
+    }
+
+    #[test]
+    fn test_config_files() {
+        let temp = TempDir::new().unwrap();
+        let project = temp.path().join("project");
+        fs::create_dir_all(project.join("subdir")).unwrap();
+        fs::write(project.join("fabrik.toml"), "# project config").unwrap();
+
+        let files = config_files(None, &project.join("subdir"));
+        assert_eq!(files.last(), Some(&project.join("fabrik.toml")));
+
+        // An explicit config is the only layer
+        let files = config_files(Some("/etc/fabrik/server.toml"), &project);
+        assert_eq!(files, vec![PathBuf::from("/etc/fabrik/server.toml")]);
     }
 
     #[test]
@@ -505,4 +953,74 @@ mod tests {
         assert_eq!(hash1, hash2);
         assert_eq!(hash1.len(), 16);
     }
+
+    #[test]
+    fn test_generate_env_exports_powershell_and_nu() {
+        let state = DaemonState {
+            config_hash: "abc123".to_string(),
+            pid: 42,
+            http_port: 8080,
+            grpc_port: 9090,
+            metrics_port: 9091,
+            s3_port: None,
+            unix_socket: None,
+            http_socket: None,
+            grpc_socket: None,
+            config_path: PathBuf::from("/tmp/it's/fabrik.toml"),
+        };
+
+        let powershell = state.generate_env_exports("powershell");
+        assert!(powershell.contains("$env:FABRIK_HTTP_URL = 'http://127.0.0.1:8080'\n"));
+        assert!(powershell.contains("$env:GRADLE_BUILD_CACHE_URL = 'http://127.0.0.1:8080'\n"));
+        assert!(
+            powershell.contains("if (-not $env:TURBO_TEAM) { $env:TURBO_TEAM = 'fabrik-local' }")
+        );
+        assert_eq!(powershell_export("X", "it's"), "$env:X = 'it''s'");
+
+        let nu: serde_json::Value =
+            serde_json::from_str(&state.generate_env_exports("nu")).unwrap();
+        assert_eq!(nu["set"]["FABRIK_GRPC_URL"], "grpc://127.0.0.1:9090");
+        assert_eq!(nu["set"]["XCODE_CACHE_SERVER"], "http://127.0.0.1:8080");
+        assert_eq!(nu["set"]["FABRIK_DAEMON_PID"], "42");
+        assert_eq!(nu["unset"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_generate_env_exports_with_sockets() {
+        let state = DaemonState {
+            config_hash: "abc123".to_string(),
+            pid: 42,
+            http_port: 0,
+            grpc_port: 0,
+            metrics_port: 9091,
+            s3_port: None,
+            unix_socket: None,
+            http_socket: Some(PathBuf::from("/project/.fabrik/http.sock")),
+            grpc_socket: Some(PathBuf::from("@fabrik-ci")),
+            config_path: PathBuf::from("/project/fabrik.toml"),
+        };
+
+        let bash = state.generate_env_exports("bash");
+        assert!(bash.contains("export FABRIK_HTTP_SOCKET=/project/.fabrik/http.sock\n"));
+        assert!(bash.contains("export FABRIK_GRPC_URL=unix-abstract:fabrik-ci\n"));
+        assert!(!bash.contains("FABRIK_HTTP_URL"));
+        assert!(!bash.contains("GRADLE_BUILD_CACHE_URL"));
+        assert!(!bash.contains("TURBO_API"));
+    }
+
+    #[test]
+    fn test_daemon_lock_is_exclusive() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("abc123.lock");
+
+        let lock = DaemonLock::try_acquire(&path).unwrap();
+        assert!(lock.is_some());
+        assert!(
+            DaemonLock::try_acquire(&path).unwrap().is_none(),
+            "a second daemon can't claim the same config"
+        );
+
+        drop(lock);
+        assert!(DaemonLock::try_acquire(&path).unwrap().is_some());
+    }
 }
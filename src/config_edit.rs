@@ -0,0 +1,242 @@
+//! Reading and writing single keys of config files (`fabrik config get/set`)
+//!
+//! Keys are dotted paths checked against the config's JSON Schema (see `config_schema`),
+//! which also decides how a value given on the command line is typed. TOML files are
+//! edited with `toml_edit`, so their comments and formatting are kept.
+
+use anyhow::{Context, Result};
+use serde_json::Value as Schema;
+use toml_edit::{DocumentMut, Item, TableLike};
+
+use crate::config::FabrikConfig;
+use crate::config_schema;
+
+/// Table of a config file holding its profiles (see `config_layers`)
+const PROFILE_TABLE: &str = "profile";
+
+/// Schema of `key`, which may be inside a profile (`profile.<name>.<key>`)
+fn key_schema(key: &str) -> Result<Schema> {
+    let config_key = key
+        .strip_prefix(PROFILE_TABLE)
+        .and_then(|rest| rest.strip_prefix('.'))
+        .and_then(|rest| rest.split_once('.'))
+        .map_or(key, |(_, key)| key);
+    config_schema::schema_at(config_key).with_context(|| format!("Unknown config key '{}'", key))
+}
+
+/// Value of `key` in `config`, None if it isn't set
+pub fn get(config: &FabrikConfig, key: &str) -> Result<Option<toml::Value>> {
+    key_schema(key)?;
+    let mut value = toml::Value::try_from(config).context("Failed to serialize configuration")?;
+    for segment in key.split('.') {
+        match value {
+            toml::Value::Table(mut table) => match table.remove(segment) {
+                Some(child) => value = child,
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(value))
+}
+
+/// `value` as printed by `fabrik config get`: strings bare, tables as TOML, other values
+/// in their TOML notation
+pub fn format(value: &toml::Value) -> Result<String> {
+    Ok(match value {
+        toml::Value::String(string) => string.clone(),
+        toml::Value::Table(table) => toml::to_string_pretty(table)?.trim_end().to_string(),
+        value => value.to_string(),
+    })
+}
+
+/// `content` (a TOML config file) with `key` set to `raw`, typed after the key's schema
+///
+/// Missing tables are created; comments and formatting elsewhere are kept, as is the
+/// comment of a value that is replaced.
+pub fn set(content: &str, key: &str, raw: &str) -> Result<String> {
+    let value = parse_value(key, raw, &key_schema(key)?)?;
+
+    let mut document: DocumentMut = content.parse().context("Failed to parse config file")?;
+    let segments: Vec<&str> = key.split('.').collect();
+    let (last, parents) = segments.split_last().expect("split yields a segment");
+
+    let mut table: &mut dyn TableLike = document.as_table_mut();
+    for (depth, segment) in parents.iter().enumerate() {
+        let item = table.entry(segment).or_insert_with(|| {
+            let mut child = toml_edit::Table::new();
+            // Only the innermost table gets a header (`[profile.ci.cache]`, not
+            // `[profile]` and `[profile.ci]` too)
+            child.set_implicit(depth + 1 < parents.len());
+            Item::Table(child)
+        });
+        let path = segments[..=depth].join(".");
+        table = item
+            .as_table_like_mut()
+            .with_context(|| format!("'{}' is not a table in the config file", path))?;
+    }
+
+    match table.get_mut(last) {
+        Some(Item::Value(existing)) => {
+            let decor = existing.decor().clone();
+            *existing = value;
+            *existing.decor_mut() = decor;
+        }
+        Some(Item::None) | None => {
+            table.insert(last, Item::Value(value));
+        }
+        Some(_) => anyhow::bail!("'{}' is a table; set its keys individually", key),
+    }
+    Ok(document.to_string())
+}
+
+/// JSON Schema types `schema` allows, without `null`
+fn types(schema: &Schema) -> Vec<&str> {
+    match schema.get("type") {
+        Some(Schema::String(ty)) => vec![ty.as_str()],
+        Some(Schema::Array(types)) => types.iter().filter_map(Schema::as_str).collect(),
+        _ => Vec::new(),
+    }
+    .into_iter()
+    .filter(|ty| *ty != "null")
+    .collect()
+}
+
+/// `raw` from the command line as a TOML value of the type `schema` describes
+fn parse_value(key: &str, raw: &str, schema: &Schema) -> Result<toml_edit::Value> {
+    let types = types(schema);
+    let invalid = |expected: &str| anyhow::anyhow!("'{}' must be {}, got '{}'", key, expected, raw);
+
+    if types.contains(&"boolean") {
+        return raw
+            .parse::<bool>()
+            .map(toml_edit::Value::from)
+            .map_err(|_| invalid("true or false"));
+    }
+    if types.contains(&"integer") {
+        return raw
+            .parse::<i64>()
+            .map(toml_edit::Value::from)
+            .map_err(|_| invalid("an integer"));
+    }
+    if types.contains(&"number") {
+        return raw
+            .parse::<f64>()
+            .map(toml_edit::Value::from)
+            .map_err(|_| invalid("a number"));
+    }
+    if types.contains(&"array") {
+        // A TOML array (`["a", "b"]`), or a comma-separated list of strings
+        if raw.trim_start().starts_with('[') {
+            return raw
+                .parse::<toml_edit::Value>()
+                .ok()
+                .filter(toml_edit::Value::is_array)
+                .ok_or_else(|| invalid("an array"));
+        }
+        let items = raw
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty());
+        return Ok(toml_edit::Value::Array(items.collect()));
+    }
+    if types.contains(&"string") {
+        if let Some(allowed) = schema.get("enum").and_then(Schema::as_array) {
+            let allowed: Vec<&str> = allowed.iter().filter_map(Schema::as_str).collect();
+            if !allowed.contains(&raw) {
+                return Err(invalid(&format!("one of {}", allowed.join(", "))));
+            }
+        }
+        return Ok(toml_edit::Value::from(raw));
+    }
+    if types.contains(&"object") {
+        anyhow::bail!("'{}' is a table; set its keys individually", key);
+    }
+    anyhow::bail!(
+        "'{}' can't be set from the command line; edit the config file instead",
+        key
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"# Project cache
+[cache]
+dir = ".fabrik/cache"
+max_size = "5GB" # grows with the monorepo
+
+[[upstream]]
+url = "grpc://cache.example.com:7070"
+"#;
+
+    #[test]
+    fn test_set_keeps_comments() {
+        let edited = set(CONFIG, "cache.max_size", "20GB").unwrap();
+        assert_eq!(
+            edited,
+            CONFIG.replace("\"5GB\" #", "\"20GB\" #"),
+            "only the value changes"
+        );
+
+        let edited = set(CONFIG, "cache.read_only", "true").unwrap();
+        assert!(edited.starts_with("# Project cache\n"));
+        assert!(edited.contains("max_size = \"5GB\" # grows with the monorepo\nread_only = true\n"));
+    }
+
+    #[test]
+    fn test_set_creates_tables() {
+        let edited = set(CONFIG, "daemon.socket", "/tmp/fabrik.sock").unwrap();
+        assert!(
+            edited.ends_with("\n[daemon]\nsocket = \"/tmp/fabrik.sock\"\n"),
+            "{}",
+            edited
+        );
+
+        let edited = set(CONFIG, "profile.ci.cache.max_size", "50GB").unwrap();
+        assert!(
+            edited.ends_with("\n[profile.ci.cache]\nmax_size = \"50GB\"\n"),
+            "{}",
+            edited
+        );
+
+        let edited = set("", "build_systems.enabled", "gradle, bazel").unwrap();
+        assert_eq!(
+            edited,
+            "[build_systems]\nenabled = [\"gradle\", \"bazel\"]\n"
+        );
+        let config: FabrikConfig = toml::from_str(&format!("{}{}", CONFIG, edited)).unwrap();
+        assert_eq!(config.build_systems.enabled, vec!["gradle", "bazel"]);
+    }
+
+    #[test]
+    fn test_set_checks_keys_and_types() {
+        let err = set(CONFIG, "cache.max_sise", "20GB").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown config key 'cache.max_sise'");
+
+        let err = set(CONFIG, "cache.read_only", "yes").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'cache.read_only' must be true or false, got 'yes'"
+        );
+
+        let err = set(CONFIG, "replication.queue_size", "many").unwrap_err();
+        assert!(err.to_string().contains("an integer"), "{}", err);
+
+        let err = set(CONFIG, "cache", "20GB").unwrap_err();
+        assert!(err.to_string().contains("is a table"), "{}", err);
+    }
+
+    #[test]
+    fn test_get() {
+        let config: FabrikConfig = toml::from_str(CONFIG).unwrap();
+        let max_size = get(&config, "cache.max_size").unwrap().unwrap();
+        assert_eq!(format(&max_size).unwrap(), "5GB");
+        // Defaults are included
+        let read_only = get(&config, "cache.read_only").unwrap().unwrap();
+        assert_eq!(format(&read_only).unwrap(), "false");
+        assert!(get(&config, "daemon.socket").unwrap().is_none());
+        assert!(get(&config, "cache.nope").is_err());
+    }
+}
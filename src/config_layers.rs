@@ -0,0 +1,408 @@
+//! Layered configuration: user config, project config and profiles
+//!
+//! Config files are merged in order, later files overriding earlier ones: tables are
+//! merged key by key, and any other value (including arrays such as `[[upstream]]`)
+//! replaces the one below it. Without `--config`, the layers are the user config
+//! (`~/.config/fabrik/config.toml`) and the project's discovered `fabrik.toml`.
+//!
+//! A `[profile.<name>]` table in any of the files holds overrides applied on top of the
+//! merged files when the profile is selected with `--profile` or `FABRIK_PROFILE`.
+//! Environment variables and CLI flags still override the result.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+use crate::config::{ConfigFormat, FabrikConfig};
+use crate::config_expansion;
+
+/// Table of a config file holding its profiles
+const PROFILE_TABLE: &str = "profile";
+
+/// How an environment variable overriding a config value is parsed
+#[derive(Debug, Clone, Copy)]
+enum EnvKind {
+    String,
+    Bool,
+}
+
+/// Environment variables that override a config value, for every command reading it
+const ENV_OVERRIDES: &[(&str, &str, EnvKind)] = &[
+    ("FABRIK_CONFIG_CACHE_DIR", "cache.dir", EnvKind::String),
+    (
+        "FABRIK_CONFIG_MAX_CACHE_SIZE",
+        "cache.max_size",
+        EnvKind::String,
+    ),
+    (
+        "FABRIK_CONFIG_EVICTION_POLICY",
+        "cache.eviction_policy",
+        EnvKind::String,
+    ),
+    (
+        "FABRIK_CONFIG_DEFAULT_TTL",
+        "cache.default_ttl",
+        EnvKind::String,
+    ),
+    ("FABRIK_CONFIG_READ_ONLY", "cache.read_only", EnvKind::Bool),
+    (
+        "FABRIK_CONFIG_HASH_ALGORITHM",
+        "cache.hash_algorithm",
+        EnvKind::String,
+    ),
+    (
+        "FABRIK_CONFIG_LOG_LEVEL",
+        "observability.log_level",
+        EnvKind::String,
+    ),
+    (
+        "FABRIK_CONFIG_LOG_FORMAT",
+        "observability.log_format",
+        EnvKind::String,
+    ),
+];
+
+/// Where an effective config value comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    File(PathBuf),
+    Profile { path: PathBuf, name: String },
+    Env(&'static str),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(path) => write!(f, "{}", path.display()),
+            Origin::Profile { path, name } => {
+                write!(f, "{} [{}.{}]", path.display(), PROFILE_TABLE, name)
+            }
+            Origin::Env(var) => write!(f, "env {}", var),
+        }
+    }
+}
+
+/// Config files merged into one table, remembering where each value comes from
+#[derive(Debug, Clone, Default)]
+pub struct ConfigLayers {
+    table: Table,
+    /// Origin of each value set by a layer, by dotted key
+    origins: BTreeMap<String, Origin>,
+    /// Overrides of each profile, in the order of the files defining them
+    profiles: BTreeMap<String, Vec<(PathBuf, Table)>>,
+}
+
+impl ConfigLayers {
+    /// Merge the config files at `paths`, later files overriding earlier ones
+    pub fn load(paths: &[PathBuf]) -> Result<Self> {
+        let mut layers = Self::default();
+        for path in paths {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+            layers.add(path, &content)?;
+        }
+        Ok(layers)
+    }
+
+    /// Merge the contents of the config file at `path` over the layers so far
+    fn add(&mut self, path: &Path, content: &str) -> Result<()> {
+        let expanded = config_expansion::expand_env_vars(content).with_context(|| {
+            format!(
+                "Failed to expand environment variables in config file: {}",
+                path.display()
+            )
+        })?;
+        let mut table: Table = ConfigFormat::from_path(path)
+            .parse(&expanded)
+            .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+
+        match table.remove(PROFILE_TABLE) {
+            None => {}
+            Some(Value::Table(profiles)) => {
+                for (name, overrides) in profiles {
+                    let Value::Table(overrides) = overrides else {
+                        anyhow::bail!(
+                            "[{}.{}] in {} must be a table",
+                            PROFILE_TABLE,
+                            name,
+                            path.display()
+                        );
+                    };
+                    self.profiles
+                        .entry(name)
+                        .or_default()
+                        .push((path.to_path_buf(), overrides));
+                }
+            }
+            Some(_) => anyhow::bail!("[{}] in {} must be a table", PROFILE_TABLE, path.display()),
+        }
+
+        merge(
+            &mut self.table,
+            table,
+            "",
+            &Origin::File(path.to_path_buf()),
+            &mut self.origins,
+        );
+        Ok(())
+    }
+
+    /// Names of the profiles the files define
+    pub fn profiles(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    /// Apply the overrides of profile `name`
+    pub fn apply_profile(&mut self, name: &str) -> Result<()> {
+        let Some(overrides) = self.profiles.get(name) else {
+            let available: Vec<&str> = self.profiles().collect();
+            anyhow::bail!(
+                "Unknown profile '{}' (defined: {})",
+                name,
+                if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                }
+            );
+        };
+        for (path, table) in overrides.clone() {
+            let origin = Origin::Profile {
+                path,
+                name: name.to_string(),
+            };
+            merge(&mut self.table, table, "", &origin, &mut self.origins);
+        }
+        Ok(())
+    }
+
+    /// Apply the `FABRIK_CONFIG_*` environment variables that override config values
+    pub fn apply_env(&mut self) -> Result<()> {
+        for (var, key, kind) in ENV_OVERRIDES {
+            let Ok(raw) = std::env::var(var) else {
+                continue;
+            };
+            let value = match kind {
+                EnvKind::String => Value::String(raw),
+                EnvKind::Bool => Value::Boolean(match raw.to_lowercase().as_str() {
+                    "1" | "true" | "yes" | "on" => true,
+                    "0" | "false" | "no" | "off" | "" => false,
+                    _ => anyhow::bail!("{} must be true or false, got '{}'", var, raw),
+                }),
+            };
+
+            let (tables, leaf) = key.rsplit_once('.').unwrap_or(("", key));
+            let mut overlay = Table::new();
+            overlay.insert(leaf.to_string(), value);
+            for part in tables.rsplit('.').filter(|part| !part.is_empty()) {
+                let mut parent = Table::new();
+                parent.insert(part.to_string(), Value::Table(overlay));
+                overlay = parent;
+            }
+            merge(
+                &mut self.table,
+                overlay,
+                "",
+                &Origin::Env(var),
+                &mut self.origins,
+            );
+        }
+        Ok(())
+    }
+
+    /// The effective configuration
+    pub fn config(&self) -> Result<FabrikConfig> {
+        Value::Table(self.table.clone())
+            .try_into()
+            .context("Failed to parse merged configuration")
+    }
+
+    /// Every effective value (defaults included) by dotted key, with where it comes from
+    pub fn values(&self) -> Result<Vec<(String, Value, Origin)>> {
+        let effective =
+            Value::try_from(self.config()?).context("Failed to serialize merged configuration")?;
+        let mut values = Vec::new();
+        if let Value::Table(effective) = effective {
+            self.collect(&effective, "", &mut values);
+        }
+        Ok(values)
+    }
+
+    fn collect(&self, table: &Table, prefix: &str, values: &mut Vec<(String, Value, Origin)>) {
+        for (key, value) in table {
+            let key = join_key(prefix, key);
+            match value {
+                Value::Table(table) if !table.is_empty() => self.collect(table, &key, values),
+                _ => {
+                    let origin = self.origin(&key);
+                    values.push((key, value.clone(), origin));
+                }
+            }
+        }
+    }
+
+    /// Origin of the value at `key`, or of the nearest table above it a layer replaced
+    fn origin(&self, key: &str) -> Origin {
+        let mut key = key;
+        loop {
+            if let Some(origin) = self.origins.get(key) {
+                return origin.clone();
+            }
+            match key.rsplit_once('.') {
+                Some((parent, _)) => key = parent,
+                None => return Origin::Default,
+            }
+        }
+    }
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+/// Merge `overlay` into `base`: tables key by key, other values replaced
+fn merge(
+    base: &mut Table,
+    overlay: Table,
+    prefix: &str,
+    origin: &Origin,
+    origins: &mut BTreeMap<String, Origin>,
+) {
+    for (key, value) in overlay {
+        let path = join_key(prefix, &key);
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => {
+                merge(existing, table, &path, origin, origins);
+            }
+            (None, Value::Table(table)) => {
+                let mut existing = Table::new();
+                merge(&mut existing, table, &path, origin, origins);
+                base.insert(key, Value::Table(existing));
+            }
+            (_, value) => {
+                // Values below a replaced table come from this layer too
+                let nested = format!("{}.", path);
+                origins.retain(|key, _| !key.starts_with(&nested));
+                origins.insert(path, origin.clone());
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layers(files: &[(&str, &str)]) -> ConfigLayers {
+        let mut layers = ConfigLayers::default();
+        for (path, content) in files {
+            layers.add(Path::new(path), content).unwrap();
+        }
+        layers
+    }
+
+    #[test]
+    fn test_project_overrides_user_config() {
+        let layers = layers(&[
+            (
+                "user.toml",
+                r#"
+[cache]
+dir = "/home/me/.cache/fabrik"
+max_size = "20GB"
+
+[[upstream]]
+url = "grpc://personal.example.com:7070"
+"#,
+            ),
+            (
+                "fabrik.toml",
+                r#"
+[cache]
+max_size = "5GB"
+
+[[upstream]]
+url = "grpc://team.example.com:7070"
+"#,
+            ),
+        ]);
+
+        let config = layers.config().unwrap();
+        assert_eq!(config.cache.dir, "/home/me/.cache/fabrik");
+        assert_eq!(config.cache.max_size, "5GB");
+        // Arrays are replaced, not appended
+        assert_eq!(config.upstream.len(), 1);
+        assert_eq!(config.upstream[0].url, "grpc://team.example.com:7070");
+
+        let values = layers.values().unwrap();
+        let origin = |key: &str| {
+            values
+                .iter()
+                .find(|(k, _, _)| k == key)
+                .map(|(_, _, origin)| origin.to_string())
+                .unwrap()
+        };
+        assert_eq!(origin("cache.dir"), "user.toml");
+        assert_eq!(origin("cache.max_size"), "fabrik.toml");
+        assert_eq!(origin("upstream"), "fabrik.toml");
+        assert_eq!(origin("cache.eviction_policy"), "default");
+    }
+
+    #[test]
+    fn test_profiles() {
+        let mut layers = layers(&[(
+            "fabrik.toml",
+            r#"
+[cache]
+dir = ".fabrik/cache"
+max_size = "5GB"
+
+[profile.ci.cache]
+max_size = "50GB"
+read_only = true
+"#,
+        )]);
+        assert_eq!(layers.profiles().collect::<Vec<_>>(), vec!["ci"]);
+        assert!(layers.apply_profile("release").is_err());
+
+        layers.apply_profile("ci").unwrap();
+        let config = layers.config().unwrap();
+        assert_eq!(config.cache.dir, ".fabrik/cache");
+        assert_eq!(config.cache.max_size, "50GB");
+        assert!(config.cache.read_only);
+        assert_eq!(
+            layers.origin("cache.max_size").to_string(),
+            "fabrik.toml [profile.ci]"
+        );
+
+        let invalid = ConfigLayers::default().add(Path::new("bad.toml"), "profile = 1");
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_replaced_table_origins() {
+        let mut origins = BTreeMap::new();
+        let mut base: Table = toml::from_str("[auth]\nprovider = \"oauth2\"").unwrap();
+        origins.insert("auth.provider".to_string(), Origin::File("a.toml".into()));
+        let overlay: Table = toml::from_str("auth = 1").unwrap();
+        merge(
+            &mut base,
+            overlay,
+            "",
+            &Origin::File("b.toml".into()),
+            &mut origins,
+        );
+        assert_eq!(origins.len(), 1);
+        assert_eq!(origins["auth"], Origin::File("b.toml".into()));
+    }
+}
@@ -0,0 +1,390 @@
+//! JSON Schema of the config file and strict validation against it
+//!
+//! `fabrik config schema` prints the schema for editors and CI, and
+//! `fabrik config validate --strict` rejects keys the schema doesn't know (typos that
+//! Fabrik would otherwise silently ignore), reporting the line and column of each.
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::fmt;
+use std::path::Path;
+
+use crate::config::{ConfigFormat, FabrikConfig};
+use crate::config_expansion;
+
+/// Table of a config file holding its profiles (see `config_layers`)
+const PROFILE_TABLE: &str = "profile";
+
+/// JSON Schema of the config file
+pub fn schema() -> Value {
+    serde_json::to_value(schemars::schema_for!(FabrikConfig)).expect("JSON Schema is serializable")
+}
+
+/// Schema of the value at the dotted `key` (e.g. `cache.max_size`), None if no such key
+/// exists
+pub fn schema_at(key: &str) -> Option<Value> {
+    let root = schema();
+    let mut schema = &root;
+    for segment in key.split('.') {
+        schema = object_variant(schema, &root);
+        schema = match schema.get("properties").and_then(|p| p.get(segment)) {
+            Some(property) => property,
+            None => match schema.get("additionalProperties") {
+                Some(additional) if additional.is_object() => additional,
+                _ => return None,
+            },
+        };
+    }
+    Some(non_null_variant(schema, &root).clone())
+}
+
+/// `schema` without its `null` alternative (for options), following `$ref`s
+fn non_null_variant<'a>(schema: &'a Value, root: &'a Value) -> &'a Value {
+    let schema = resolve(schema, root);
+    for keyword in ["anyOf", "oneOf"] {
+        let Some(variants) = schema.get(keyword).and_then(Value::as_array) else {
+            continue;
+        };
+        let non_null: Vec<&Value> = variants
+            .iter()
+            .filter(|v| v.get("type").and_then(Value::as_str) != Some("null"))
+            .collect();
+        if let [variant] = non_null[..] {
+            return non_null_variant(variant, root);
+        }
+    }
+    schema
+}
+
+/// Variant of `schema` describing a table, if it has one (e.g. the `Some` of an option)
+fn object_variant<'a>(schema: &'a Value, root: &'a Value) -> &'a Value {
+    variant(&Value::Object(Default::default()), schema, root)
+}
+
+/// A problem found by strict validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub message: String,
+    /// 1-based position, when it could be located
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}: {}", line, column, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Segment of the path to a value in a config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+fn display_path(path: &[Segment]) -> String {
+    let mut display = String::new();
+    for segment in path {
+        match segment {
+            Segment::Key(key) if display.is_empty() => display.push_str(key),
+            Segment::Key(key) => {
+                display.push('.');
+                display.push_str(key);
+            }
+            Segment::Index(index) => display.push_str(&format!("[{}]", index)),
+        }
+    }
+    display
+}
+
+/// Strictly validate the config file at `path`: it must parse, have no unknown keys, and
+/// every profile must only override known keys
+pub fn check_strict(path: &Path) -> Result<Vec<ConfigIssue>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+    let content = config_expansion::expand_env_vars(&content).with_context(|| {
+        format!(
+            "Failed to expand environment variables in config file: {}",
+            path.display()
+        )
+    })?;
+    Ok(check_content(ConfigFormat::from_path(path), &content))
+}
+
+fn check_content(format: ConfigFormat, content: &str) -> Vec<ConfigIssue> {
+    // Parse with the format's own deserializer, whose errors carry their position
+    let parsed = match format {
+        ConfigFormat::Toml => toml::from_str::<FabrikConfig>(content).map_err(|e| e.to_string()),
+        ConfigFormat::Yaml => {
+            serde_yaml::from_str::<FabrikConfig>(content).map_err(|e| e.to_string())
+        }
+        ConfigFormat::Json => {
+            serde_json::from_str::<FabrikConfig>(content).map_err(|e| e.to_string())
+        }
+    };
+    if let Err(message) = parsed {
+        return vec![ConfigIssue {
+            message: message.trim().to_string(),
+            line: None,
+            column: None,
+        }];
+    }
+    let Ok(Value::Object(mut document)) = format.parse::<Value>(content) else {
+        return Vec::new();
+    };
+
+    let schema = schema();
+    let mut unknown = Vec::new();
+    let profiles = document.remove(PROFILE_TABLE);
+    check_value(
+        &Value::Object(document),
+        &schema,
+        &schema,
+        &mut Vec::new(),
+        &mut unknown,
+    );
+    if let Some(Value::Object(profiles)) = profiles {
+        for (name, overrides) in profiles {
+            let mut path = vec![Segment::Key(PROFILE_TABLE.to_string()), Segment::Key(name)];
+            check_value(&overrides, &schema, &schema, &mut path, &mut unknown);
+        }
+    }
+
+    unknown
+        .into_iter()
+        .map(|path| {
+            let position = locate(format, content, &path);
+            ConfigIssue {
+                message: format!("unknown key '{}'", display_path(&path)),
+                line: position.map(|(line, _)| line),
+                column: position.map(|(_, column)| column),
+            }
+        })
+        .collect()
+}
+
+/// The schema `schema` refers to, following `$ref`s into the root's definitions
+fn resolve<'a>(schema: &'a Value, root: &'a Value) -> &'a Value {
+    let Some(reference) = schema.get("$ref").and_then(Value::as_str) else {
+        return schema;
+    };
+    let target = reference
+        .strip_prefix("#/")
+        .map(|pointer| format!("/{}", pointer))
+        .and_then(|pointer| root.pointer(&pointer));
+    match target {
+        Some(target) => resolve(target, root),
+        None => schema,
+    }
+}
+
+/// Variant of `schema` that describes `value` (for `anyOf`, `oneOf` and `allOf`)
+fn variant<'a>(value: &Value, schema: &'a Value, root: &'a Value) -> &'a Value {
+    let schema = resolve(schema, root);
+    for keyword in ["anyOf", "oneOf", "allOf"] {
+        let Some(variants) = schema.get(keyword).and_then(Value::as_array) else {
+            continue;
+        };
+        let matching = variants.iter().map(|v| resolve(v, root)).find(|v| {
+            (value.is_object()
+                && (v.get("properties").is_some() || v.get("additionalProperties").is_some()))
+                || (value.is_array() && v.get("items").is_some())
+        });
+        if let Some(matching) = matching {
+            return variant(value, matching, root);
+        }
+    }
+    schema
+}
+
+/// Collect the paths of the keys of `value` that `schema` doesn't allow
+fn check_value(
+    value: &Value,
+    schema: &Value,
+    root: &Value,
+    path: &mut Vec<Segment>,
+    unknown: &mut Vec<Vec<Segment>>,
+) {
+    let schema = variant(value, schema, root);
+    match value {
+        Value::Object(object) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            let additional = schema.get("additionalProperties");
+            for (key, child) in object {
+                path.push(Segment::Key(key.clone()));
+                match (properties.and_then(|p| p.get(key)), additional) {
+                    (Some(child_schema), _) => {
+                        check_value(child, child_schema, root, path, unknown)
+                    }
+                    (None, Some(Value::Bool(false))) => unknown.push(path.clone()),
+                    (None, Some(Value::Bool(true))) => {}
+                    (None, Some(child_schema)) => {
+                        check_value(child, child_schema, root, path, unknown)
+                    }
+                    (None, None) if properties.is_some() => unknown.push(path.clone()),
+                    (None, None) => {}
+                }
+                path.pop();
+            }
+        }
+        Value::Array(items) => {
+            if let Some(items_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    path.push(Segment::Index(index));
+                    check_value(item, items_schema, root, path, unknown);
+                    path.pop();
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Line and column (1-based) of the key at `path` in `content`
+fn locate(format: ConfigFormat, content: &str, path: &[Segment]) -> Option<(usize, usize)> {
+    let offset = match format {
+        ConfigFormat::Toml => locate_toml(content, path)?,
+        ConfigFormat::Yaml | ConfigFormat::Json => locate_text(format, content, path)?,
+    };
+    let before = &content[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    Some((line, column))
+}
+
+fn locate_toml(content: &str, path: &[Segment]) -> Option<usize> {
+    let document = toml::de::DeTable::parse(content).ok()?;
+    let mut table = document.get_ref();
+    let mut array: Option<&toml::de::DeArray> = None;
+    for (i, segment) in path.iter().enumerate() {
+        let last = i == path.len() - 1;
+        let value = match (segment, array.take()) {
+            (Segment::Index(index), Some(items)) => items.get(*index)?,
+            (Segment::Key(key), None) => {
+                let (name, value) = table.iter().find(|(name, _)| name.get_ref() == key)?;
+                if last {
+                    return Some(name.span().start);
+                }
+                value
+            }
+            _ => return None,
+        };
+        match value.get_ref() {
+            toml::de::DeValue::Table(child) => table = child,
+            toml::de::DeValue::Array(items) => array = Some(items),
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Offset of the key at `path`, found by searching for each key after the previous one
+fn locate_text(format: ConfigFormat, content: &str, path: &[Segment]) -> Option<usize> {
+    let mut offset = 0;
+    for segment in path {
+        let Segment::Key(key) = segment else {
+            continue;
+        };
+        let found = match format {
+            ConfigFormat::Json => {
+                let quoted = serde_json::to_string(key).ok()?;
+                content[offset..]
+                    .match_indices(&quoted)
+                    .find_map(|(i, _)| {
+                        let after = content[offset + i + quoted.len()..].trim_start();
+                        after.starts_with(':').then_some(offset + i)
+                    })?
+            }
+            _ => {
+                let mut line_start = offset;
+                let mut found = None;
+                for line in content[offset..].split_inclusive('\n') {
+                    let trimmed = line.trim_start().trim_start_matches("- ").trim_start();
+                    if trimmed
+                        .strip_prefix(key.as_str())
+                        .is_some_and(|rest| rest.starts_with(':'))
+                    {
+                        found = Some(line_start + (line.len() - trimmed.len()));
+                        break;
+                    }
+                    line_start += line.len();
+                }
+                found?
+            }
+        };
+        offset = found;
+    }
+    Some(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_describes_config() {
+        let schema = schema();
+        let cache = resolve(&schema["properties"]["cache"], &schema);
+        assert!(cache["properties"]["max_size"].is_object());
+        let upstream = resolve(&schema["properties"]["upstream"]["items"], &schema);
+        // Serde renames are reflected
+        assert!(upstream["properties"]["match"].is_object());
+    }
+
+    #[test]
+    fn test_strict_rejects_unknown_keys() {
+        let toml = r#"
+[cache]
+dir = "/tmp/fabrik"
+max_size = "5GB"
+max_sise = "5GB"
+
+[[upstream]]
+url = "grpc://cache.example.com:7070"
+match = ["bazel-cas/*"]
+
+[[upstream]]
+url = "s3://bucket/"
+regoin = "us-east-1"
+
+[limits.max_artifact_size_by_protocol]
+bazel = "1GB"
+
+[profile.ci.cache]
+read_onyl = true
+"#;
+        let issues = check_content(ConfigFormat::Toml, toml);
+        let messages: Vec<String> = issues.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "5:1: unknown key 'cache.max_sise'",
+                "13:1: unknown key 'upstream[1].regoin'",
+                "19:1: unknown key 'profile.ci.cache.read_onyl'",
+            ]
+        );
+
+        let valid = "[cache]\ndir = \"/tmp/fabrik\"\nmax_size = \"5GB\"\n";
+        assert!(check_content(ConfigFormat::Toml, valid).is_empty());
+    }
+
+    #[test]
+    fn test_strict_yaml_and_json() {
+        let yaml = "cache:\n  dir: /tmp/fabrik\n  max_size: 5GB\n  evict: lru\n";
+        let issues = check_content(ConfigFormat::Yaml, yaml);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].to_string(), "4:3: unknown key 'cache.evict'");
+
+        let json = "{\n  \"cache\": {\"dir\": \"/tmp\", \"max_size\": \"5GB\"},\n  \"observabilty\": {}\n}";
+        let issues = check_content(ConfigFormat::Json, json);
+        assert_eq!(issues[0].to_string(), "3:3: unknown key 'observabilty'");
+
+        // Type errors come with the parser's position
+        let issues = check_content(ConfigFormat::Toml, "[cache]\ndir = 1\nmax_size = \"5GB\"\n");
+        assert!(issues[0].message.contains("line 2"), "{}", issues[0]);
+    }
+}
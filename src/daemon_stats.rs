@@ -0,0 +1,772 @@
+//! Live statistics of daemons (`GET /api/v1/stats`, `fabrik top`)
+//!
+//! Every server of a daemon (HTTP, gRPC, S3 and the Xcode socket) is wrapped in a
+//! [`RequestStatsLayer`] sharing one [`RequestStats`], which counts requests per protocol
+//! by outcome and keeps the most recent ones. [`LiveStats`] adds the cache size and the
+//! eviction counters, and is served by the daemon's HTTP server.
+//!
+//! Requests naming the build invocation they're part of (`X-Fabrik-Invocation-Id`, or
+//! the `tool_invocation_id` of Bazel's request metadata) are also counted per
+//! invocation, with the bytes of their bodies, so the cache benefit of a single CI build
+//! can be read back (`GET /api/v1/invocations/{id}`, `fabrik stats --invocation`).
+
+use axum::http::{HeaderMap, Method, Request, Response, StatusCode};
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use bytes::Buf;
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tower::{Layer, Service};
+
+use crate::auth::scopes::services;
+use crate::bazel::proto::remote_execution::RequestMetadata;
+use crate::error::Result;
+use crate::eviction::EvictionStats;
+use crate::storage::Storage;
+
+/// Number of recent requests kept
+pub const RECENT_REQUESTS: usize = 50;
+
+/// Number of invocations kept; the least recently active one makes room for new ones
+pub const MAX_INVOCATIONS: usize = 1000;
+
+/// Request header naming the build invocation a request is part of
+pub const INVOCATION_HEADER: &str = "x-fabrik-invocation-id";
+
+/// Request header of Bazel's `RequestMetadata`, base64-encoded
+const BAZEL_METADATA_HEADER: &str = "build.bazel.remote.execution.v2.requestmetadata-bin";
+
+/// Longest invocation ID accepted from a request
+const MAX_INVOCATION_ID_LEN: usize = 128;
+
+/// Binary gRPC headers, whose padding clients may omit
+const GRPC_BINARY_HEADER: GeneralPurpose = GeneralPurpose::new(
+    &base64::alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Response extension marking a successful response as a cache miss, for protocols
+/// reporting misses in the response body (Xcode)
+#[derive(Debug, Clone, Copy)]
+pub struct CacheMiss;
+
+/// What a request did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// A read answered from the cache
+    Hit,
+    /// A read of something the cache doesn't have
+    Miss,
+    /// An upload
+    Write,
+    /// Rejected or failed
+    Error,
+    /// Anything else (existence checks, listings, capabilities)
+    Other,
+}
+
+/// Requests of one protocol, by outcome
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolCounters {
+    pub requests: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub writes: u64,
+    pub errors: u64,
+}
+
+impl ProtocolCounters {
+    fn record(&mut self, outcome: Outcome) {
+        self.requests += 1;
+        match outcome {
+            Outcome::Hit => self.hits += 1,
+            Outcome::Miss => self.misses += 1,
+            Outcome::Write => self.writes += 1,
+            Outcome::Error => self.errors += 1,
+            Outcome::Other => {}
+        }
+    }
+
+    /// Share of reads that hit, None before the first read
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let reads = self.hits + self.misses;
+        (reads > 0).then(|| self.hits as f64 / reads as f64)
+    }
+}
+
+/// A finished request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecentRequest {
+    /// When the request finished (Unix milliseconds)
+    pub timestamp: i64,
+    pub protocol: String,
+    pub method: String,
+    pub path: String,
+    pub outcome: Outcome,
+    pub duration_ms: u64,
+    /// Build invocation the request is part of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invocation: Option<String>,
+}
+
+/// Cache traffic of a build invocation, as served at `GET /api/v1/invocations/{id}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InvocationStats {
+    pub id: String,
+    /// When its first and latest requests started (Unix milliseconds)
+    pub first_request: i64,
+    pub last_request: i64,
+    #[serde(flatten)]
+    pub counters: ProtocolCounters,
+    /// Bytes of request bodies, i.e. uploads
+    pub bytes_uploaded: u64,
+    /// Bytes of response bodies, i.e. downloads
+    pub bytes_downloaded: u64,
+}
+
+/// Counters of a build invocation
+#[derive(Debug)]
+struct Invocation {
+    first_request: i64,
+    last_request: AtomicI64,
+    counters: Mutex<ProtocolCounters>,
+    /// Shared with the bodies of its requests, which are read after the request is
+    /// recorded
+    bytes_uploaded: Arc<AtomicU64>,
+    bytes_downloaded: Arc<AtomicU64>,
+}
+
+impl Invocation {
+    fn new(now: i64) -> Self {
+        Self {
+            first_request: now,
+            last_request: AtomicI64::new(now),
+            counters: Mutex::new(ProtocolCounters::default()),
+            bytes_uploaded: Arc::new(AtomicU64::new(0)),
+            bytes_downloaded: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn stats(&self, id: &str) -> InvocationStats {
+        InvocationStats {
+            id: id.to_string(),
+            first_request: self.first_request,
+            last_request: self.last_request.load(Ordering::Relaxed),
+            counters: self.counters.lock().unwrap().clone(),
+            bytes_uploaded: self.bytes_uploaded.load(Ordering::Relaxed),
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Request counters per protocol and invocation, and the most recent requests of a
+/// daemon
+#[derive(Debug, Default)]
+pub struct RequestStats {
+    protocols: Mutex<BTreeMap<String, ProtocolCounters>>,
+    recent: Mutex<VecDeque<RecentRequest>>,
+    invocations: Mutex<HashMap<String, Arc<Invocation>>>,
+    /// Invocation of the requests that don't name one
+    default_invocation: Option<String>,
+}
+
+impl RequestStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count the requests that don't name their invocation as part of `id`, for servers
+    /// of a single build (`fabrik exec`)
+    pub fn with_invocation(mut self, id: String) -> Self {
+        self.default_invocation = Some(id);
+        self
+    }
+
+    pub fn record(&self, request: RecentRequest) {
+        self.protocols
+            .lock()
+            .unwrap()
+            .entry(request.protocol.clone())
+            .or_default()
+            .record(request.outcome);
+        if let Some(ref id) = request.invocation {
+            let invocation = self.invocation(id, request.timestamp);
+            invocation.counters.lock().unwrap().record(request.outcome);
+        }
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_REQUESTS {
+            recent.pop_back();
+        }
+        recent.push_front(request);
+    }
+
+    pub fn protocols(&self) -> BTreeMap<String, ProtocolCounters> {
+        self.protocols.lock().unwrap().clone()
+    }
+
+    /// Most recent requests, newest first
+    pub fn recent(&self) -> Vec<RecentRequest> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Traffic of invocation `id`, None if no request of it was recorded (or it was
+    /// dropped for newer ones)
+    pub fn invocation_stats(&self, id: &str) -> Option<InvocationStats> {
+        let invocations = self.invocations.lock().unwrap();
+        invocations.get(id).map(|invocation| invocation.stats(id))
+    }
+
+    /// Traffic of the known invocations, most recently active first
+    pub fn invocations(&self) -> Vec<InvocationStats> {
+        let mut invocations: Vec<_> = self
+            .invocations
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, invocation)| invocation.stats(id))
+            .collect();
+        invocations.sort_by_key(|invocation| std::cmp::Reverse(invocation.last_request));
+        invocations
+    }
+
+    /// Invocation a request is part of: the one it names, or the default one
+    fn invocation_of(&self, headers: &HeaderMap) -> Option<String> {
+        invocation_id(headers).or_else(|| self.default_invocation.clone())
+    }
+
+    /// Counters of invocation `id`, active at `now`
+    ///
+    /// New invocations replace the least recently active one once there are
+    /// [`MAX_INVOCATIONS`].
+    fn invocation(&self, id: &str, now: i64) -> Arc<Invocation> {
+        let mut invocations = self.invocations.lock().unwrap();
+        if let Some(invocation) = invocations.get(id) {
+            invocation.last_request.fetch_max(now, Ordering::Relaxed);
+            return invocation.clone();
+        }
+        if invocations.len() >= MAX_INVOCATIONS {
+            let oldest = invocations
+                .iter()
+                .min_by_key(|(_, invocation)| invocation.last_request.load(Ordering::Relaxed))
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                invocations.remove(&oldest);
+            }
+        }
+        let invocation = Arc::new(Invocation::new(now));
+        invocations.insert(id.to_string(), invocation.clone());
+        invocation
+    }
+}
+
+/// Build invocation named by a request: its `X-Fabrik-Invocation-Id` header, or the
+/// `tool_invocation_id` of Bazel's request metadata
+fn invocation_id(headers: &HeaderMap) -> Option<String> {
+    let id = match headers.get(INVOCATION_HEADER) {
+        Some(value) => value.to_str().ok()?.trim().to_string(),
+        None => {
+            let metadata = GRPC_BINARY_HEADER
+                .decode(headers.get(BAZEL_METADATA_HEADER)?.as_bytes())
+                .ok()?;
+            RequestMetadata::decode(metadata.as_slice())
+                .ok()?
+                .tool_invocation_id
+        }
+    };
+    (!id.is_empty() && id.len() <= MAX_INVOCATION_ID_LEN).then_some(id)
+}
+
+/// Statistics of a daemon, as served at `GET /api/v1/stats`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonStats {
+    pub version: String,
+    pub pid: u32,
+    pub uptime_seconds: u64,
+    pub protocols: BTreeMap<String, ProtocolCounters>,
+    pub cache: CacheUsage,
+    pub evictions: EvictionCounters,
+    /// Uploads waiting to be sent upstream, None if the daemon doesn't queue them
+    pub upstream_queue: Option<u64>,
+    /// Newest first
+    pub recent: Vec<RecentRequest>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheUsage {
+    pub objects: u64,
+    pub bytes: u64,
+    /// Size the cache is evicted down to (`[cache] max_size`), 0 for a read-only cache
+    pub max_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EvictionCounters {
+    pub objects: u64,
+    pub bytes: u64,
+    pub runs: u64,
+}
+
+/// Sources of a daemon's [`DaemonStats`]
+pub struct LiveStats {
+    started: Instant,
+    requests: Arc<RequestStats>,
+    max_size_bytes: u64,
+    evictions: Option<Arc<EvictionStats>>,
+}
+
+impl LiveStats {
+    pub fn new(max_size_bytes: u64) -> Self {
+        Self {
+            started: Instant::now(),
+            requests: Arc::new(RequestStats::new()),
+            max_size_bytes,
+            evictions: None,
+        }
+    }
+
+    /// Report the evictions of the daemon's background eviction task
+    pub fn with_evictions(mut self, evictions: Option<Arc<EvictionStats>>) -> Self {
+        self.evictions = evictions;
+        self
+    }
+
+    /// Count the requests that don't name their invocation as part of `id`, see
+    /// [`RequestStats::with_invocation`]
+    pub fn with_invocation(mut self, id: String) -> Self {
+        self.requests = Arc::new(RequestStats::new().with_invocation(id));
+        self
+    }
+
+    /// Recorder of the daemon's requests, for a [`RequestStatsLayer`]
+    pub fn requests(&self) -> Arc<RequestStats> {
+        self.requests.clone()
+    }
+
+    pub fn snapshot(&self, storage: &dyn Storage) -> Result<DaemonStats> {
+        let storage_stats = storage.stats()?;
+        let evictions = self
+            .evictions
+            .as_ref()
+            .map(|stats| EvictionCounters {
+                objects: stats.get_evictions_total(),
+                bytes: stats.get_bytes_evicted(),
+                runs: stats.get_eviction_runs(),
+            })
+            .unwrap_or_default();
+
+        Ok(DaemonStats {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            pid: std::process::id(),
+            uptime_seconds: self.started.elapsed().as_secs(),
+            protocols: self.requests.protocols(),
+            cache: CacheUsage {
+                objects: storage_stats.total_objects,
+                bytes: storage_stats.total_bytes,
+                max_bytes: self.max_size_bytes,
+            },
+            evictions,
+            upstream_queue: None,
+            recent: self.requests.recent(),
+        })
+    }
+}
+
+/// Protocol of a cache request path, None for requests that aren't cache traffic
+/// (health checks, completion, statistics, gRPC reflection)
+fn protocol_for_path(path: &str) -> Option<&'static str> {
+    const PROTOCOLS: [(&str, &str); 9] = [
+        ("/api/v1/artifacts/", services::METRO),
+        ("/v8/artifacts/", services::TURBOREPO),
+        ("/v1/cache/", services::NX),
+        ("/cache/", services::GRADLE),
+        ("/dav/", services::WEBDAV),
+        ("/build.bazel.remote.execution.", services::BAZEL),
+        ("/google.bytestream.", services::BAZEL),
+        ("/compilation_cache_service.", services::XCODE),
+        ("/fabrik.v1.", services::FABRIK),
+    ];
+    PROTOCOLS
+        .iter()
+        .find(|(prefix, _)| path.starts_with(prefix))
+        .map(|(_, protocol)| *protocol)
+}
+
+/// gRPC methods reading cached entries and uploading them; other methods are `Other`
+const GRPC_READS: [&str; 6] = [
+    "GetActionResult",
+    "BatchReadBlobs",
+    "Read",
+    "Get",
+    "Load",
+    "GetValue",
+];
+const GRPC_WRITES: [&str; 6] = [
+    "UpdateActionResult",
+    "BatchUpdateBlobs",
+    "Write",
+    "Put",
+    "Save",
+    "PutValue",
+];
+
+/// Outcome of a request from its response
+fn outcome<B>(method: &Method, path: &str, response: &Response<B>) -> Outcome {
+    let grpc = response
+        .headers()
+        .get("content-type")
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/grpc"));
+
+    if grpc {
+        // Failed calls carry their status in the headers; successful ones in trailers
+        match response.headers().get("grpc-status").map(|v| v.as_bytes()) {
+            None | Some(b"0") => {}
+            Some(b"5") => return Outcome::Miss,
+            Some(_) => return Outcome::Error,
+        }
+        if response.extensions().get::<CacheMiss>().is_some() {
+            return Outcome::Miss;
+        }
+        let name = path.rsplit('/').next().unwrap_or_default();
+        return if GRPC_READS.contains(&name) {
+            Outcome::Hit
+        } else if GRPC_WRITES.contains(&name) {
+            Outcome::Write
+        } else {
+            Outcome::Other
+        };
+    }
+
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        Outcome::Miss
+    } else if status.is_client_error() || status.is_server_error() {
+        Outcome::Error
+    } else if method == Method::GET || method == Method::HEAD {
+        Outcome::Hit
+    } else if method == Method::PUT {
+        Outcome::Write
+    } else {
+        Outcome::Other
+    }
+}
+
+/// Body adding the bytes of its data frames to a counter, if any
+pub struct CountingBody<B> {
+    inner: Pin<Box<B>>,
+    bytes: Option<Arc<AtomicU64>>,
+}
+
+impl<B> CountingBody<B> {
+    fn new(inner: B, bytes: Option<Arc<AtomicU64>>) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            bytes,
+        }
+    }
+}
+
+impl<B: HttpBody> HttpBody for CountingBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = std::task::ready!(self.inner.as_mut().poll_frame(cx));
+        if let (Some(Ok(frame)), Some(bytes)) = (&frame, &self.bytes) {
+            if let Some(data) = frame.data_ref() {
+                bytes.fetch_add(data.remaining() as u64, Ordering::Relaxed);
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Tower layer recording the requests of gRPC (tonic) and HTTP (axum) services in a
+/// [`RequestStats`]
+///
+/// Request and response bodies are wrapped in [`CountingBody`], counting the bytes of
+/// requests that are part of an invocation.
+#[derive(Clone)]
+pub struct RequestStatsLayer {
+    stats: Arc<RequestStats>,
+    protocol: Option<&'static str>,
+}
+
+impl RequestStatsLayer {
+    pub fn new(stats: Arc<RequestStats>) -> Self {
+        Self {
+            stats,
+            protocol: None,
+        }
+    }
+
+    /// Count all requests as `protocol`, instead of telling protocols apart by path
+    pub fn with_protocol(mut self, protocol: &'static str) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+}
+
+impl<S> Layer<S> for RequestStatsLayer {
+    type Service = RequestStatsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestStatsService {
+            inner,
+            stats: self.stats.clone(),
+            protocol: self.protocol,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestStatsService<S> {
+    inner: S,
+    stats: Arc<RequestStats>,
+    protocol: Option<&'static str>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RequestStatsService<S>
+where
+    S: Service<Request<CountingBody<ReqBody>>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<CountingBody<ResBody>>;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = std::result::Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let path = request.uri().path().to_string();
+        let protocol = self.protocol.or_else(|| protocol_for_path(&path));
+        let method = request.method().clone();
+        // Only cache traffic counts towards invocations
+        let invocation = protocol.and_then(|_| self.stats.invocation_of(request.headers()));
+        let (uploaded, downloaded) = match invocation {
+            Some(ref id) => {
+                let counters = self
+                    .stats
+                    .invocation(id, chrono::Utc::now().timestamp_millis());
+                (
+                    Some(counters.bytes_uploaded.clone()),
+                    Some(counters.bytes_downloaded.clone()),
+                )
+            }
+            None => (None, None),
+        };
+        let future = self
+            .inner
+            .call(request.map(|body| CountingBody::new(body, uploaded)));
+
+        let Some(protocol) = protocol else {
+            return Box::pin(async move {
+                let response = future.await?;
+                Ok(response.map(|body| CountingBody::new(body, None)))
+            });
+        };
+        let stats = self.stats.clone();
+        let started = Instant::now();
+        Box::pin(async move {
+            let response = future.await;
+            let outcome = match &response {
+                Ok(response) => outcome(&method, &path, response),
+                Err(_) => Outcome::Error,
+            };
+            stats.record(RecentRequest {
+                timestamp: chrono::Utc::now().timestamp_millis(),
+                protocol: protocol.to_string(),
+                method: method.to_string(),
+                path,
+                outcome,
+                duration_ms: started.elapsed().as_millis() as u64,
+                invocation,
+            });
+            response.map(|response| response.map(|body| CountingBody::new(body, downloaded)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: StatusCode, grpc_status: Option<&str>) -> Response<()> {
+        let mut builder = Response::builder().status(status);
+        if let Some(code) = grpc_status {
+            builder = builder
+                .header("content-type", "application/grpc")
+                .header("grpc-status", code);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_outcomes() {
+        let get = Method::GET;
+        let post = Method::POST;
+        assert_eq!(
+            outcome(&get, "/cache/abc", &response(StatusCode::OK, None)),
+            Outcome::Hit
+        );
+        assert_eq!(
+            outcome(&get, "/cache/abc", &response(StatusCode::NOT_FOUND, None)),
+            Outcome::Miss
+        );
+        assert_eq!(
+            outcome(
+                &Method::PUT,
+                "/cache/abc",
+                &response(StatusCode::PAYLOAD_TOO_LARGE, None)
+            ),
+            Outcome::Error
+        );
+
+        let action_result = "/build.bazel.remote.execution.v2.ActionCache/GetActionResult";
+        assert_eq!(
+            outcome(&post, action_result, &response(StatusCode::OK, Some("5"))),
+            Outcome::Miss
+        );
+        let mut ok = response(StatusCode::OK, None);
+        ok.headers_mut()
+            .insert("content-type", "application/grpc".parse().unwrap());
+        assert_eq!(outcome(&post, action_result, &ok), Outcome::Hit);
+
+        // Xcode reports misses in the response body
+        ok.extensions_mut().insert(CacheMiss);
+        assert_eq!(
+            outcome(
+                &post,
+                "/compilation_cache_service.cas.v1.CASDBService/Load",
+                &ok
+            ),
+            Outcome::Miss
+        );
+    }
+
+    #[tokio::test]
+    async fn test_layer_records_requests() {
+        let stats = Arc::new(RequestStats::new());
+        let mut service = RequestStatsLayer::new(stats.clone()).layer(tower::service_fn(
+            |request: Request<CountingBody<()>>| async move {
+                let status = match request.uri().path() {
+                    "/cache/hit" | "/health" => StatusCode::OK,
+                    _ => StatusCode::NOT_FOUND,
+                };
+                Ok::<_, std::convert::Infallible>(response(status, None))
+            },
+        ));
+
+        for path in ["/cache/hit", "/cache/miss", "/health", "/v1/cache/miss"] {
+            let request = Request::get(path).body(()).unwrap();
+            service.call(request).await.unwrap();
+        }
+
+        // Health checks aren't cache traffic
+        let protocols = stats.protocols();
+        assert_eq!(protocols.len(), 2);
+        let gradle = &protocols["gradle"];
+        assert_eq!((gradle.requests, gradle.hits, gradle.misses), (2, 1, 1));
+        assert_eq!(gradle.hit_ratio(), Some(0.5));
+        assert_eq!(protocols["nx"].misses, 1);
+
+        let recent = stats.recent();
+        assert_eq!(recent.len(), 3);
+        assert_eq!(recent[0].path, "/v1/cache/miss");
+        assert_eq!(recent[0].outcome, Outcome::Miss);
+    }
+
+    #[tokio::test]
+    async fn test_invocations() {
+        use http_body_util::BodyExt;
+
+        let stats = Arc::new(RequestStats::new().with_invocation("exec".to_string()));
+        let mut service = RequestStatsLayer::new(stats.clone()).layer(tower::service_fn(
+            |request: Request<CountingBody<String>>| async move {
+                let uploaded = request.into_body().collect().await.unwrap().to_bytes();
+                let status = match uploaded.is_empty() {
+                    true => StatusCode::OK,
+                    false => StatusCode::CREATED,
+                };
+                let response = Response::builder()
+                    .status(status)
+                    .body("cached".to_string())
+                    .unwrap();
+                Ok::<_, std::convert::Infallible>(response)
+            },
+        ));
+
+        let metadata = RequestMetadata {
+            tool_invocation_id: "bazel-1".to_string(),
+            ..Default::default()
+        };
+        let requests = [
+            Request::put("/cache/abc")
+                .header(INVOCATION_HEADER, "ci-42")
+                .body("artifact".to_string()),
+            Request::get("/cache/abc")
+                .header(INVOCATION_HEADER, "ci-42")
+                .body(String::new()),
+            Request::get("/cache/abc")
+                .header(
+                    BAZEL_METADATA_HEADER,
+                    GRPC_BINARY_HEADER.encode(metadata.encode_to_vec()),
+                )
+                .body(String::new()),
+            Request::get("/cache/abc").body(String::new()),
+            // Not cache traffic
+            Request::get("/health")
+                .header(INVOCATION_HEADER, "ci-42")
+                .body(String::new()),
+        ];
+        for request in requests {
+            let response = service.call(request.unwrap()).await.unwrap();
+            response.into_body().collect().await.unwrap();
+        }
+
+        let ci = stats.invocation_stats("ci-42").unwrap();
+        assert_eq!((ci.counters.requests, ci.counters.writes), (2, 1));
+        assert_eq!(ci.counters.hits, 1);
+        assert_eq!((ci.bytes_uploaded, ci.bytes_downloaded), (8, 12));
+        assert_eq!(stats.invocation_stats("bazel-1").unwrap().counters.hits, 1);
+        assert_eq!(stats.invocation_stats("exec").unwrap().bytes_downloaded, 6);
+        assert_eq!(stats.invocations().len(), 3);
+        assert!(stats.invocation_stats("unknown").is_none());
+        assert_eq!(stats.recent()[0].invocation.as_deref(), Some("exec"));
+    }
+
+    #[test]
+    fn test_invocation_limit() {
+        let stats = RequestStats::new();
+        for i in 0..=MAX_INVOCATIONS {
+            stats.invocation(&i.to_string(), i as i64);
+        }
+        assert_eq!(stats.invocations().len(), MAX_INVOCATIONS);
+        assert!(stats.invocation_stats("0").is_none());
+        assert_eq!(stats.invocations()[0].id, MAX_INVOCATIONS.to_string());
+    }
+}
@@ -0,0 +1,274 @@
+//! Typed errors for the library API
+//!
+//! Storage, the eviction hooks, and the P2P and relay clients return [`FabrikError`]
+//! so that embedders can react to the kind of failure: retry when an upstream is
+//! unavailable, re-authenticate when credentials are rejected, back off when a quota
+//! is exhausted. The CLI converts them into `anyhow::Error` with `?` for reporting.
+
+use std::error::Error as StdError;
+use std::io;
+use thiserror::Error;
+
+use crate::quota::QuotaError;
+
+/// Boxed underlying error of a [`FabrikError`]
+pub type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Result of a library operation
+pub type Result<T, E = FabrikError> = std::result::Result<T, E>;
+
+#[derive(Error, Debug)]
+pub enum FabrikError {
+    /// The requested artifact or resource doesn't exist
+    #[error("{0}")]
+    NotFound(String),
+
+    /// Credentials are missing, or were rejected by the other side
+    #[error("{0}")]
+    AuthFailed(String),
+
+    /// An upload exceeded a size limit or a daily quota, locally or on a remote cache, or
+    /// the local cache volume is nearly full
+    #[error("{0}")]
+    QuotaExceeded(String),
+
+    /// A remote cache, peer or relay couldn't be reached, or failed to answer
+    #[error("{context}")]
+    UpstreamUnavailable {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+
+    /// Stored or received data is malformed
+    #[error("{0}")]
+    Corrupt(String),
+
+    /// Local filesystem or metadata database failure
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The operation needs configuration that is missing or invalid
+    #[error("{0}")]
+    Config(String),
+
+    /// The operation would change a cache opened read-only
+    #[error("{0}")]
+    ReadOnly(String),
+}
+
+impl FabrikError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::NotFound(message.into())
+    }
+
+    pub fn auth_failed(message: impl Into<String>) -> Self {
+        Self::AuthFailed(message.into())
+    }
+
+    /// An upstream failure without an underlying error
+    pub fn unavailable(context: impl Into<String>) -> Self {
+        Self::UpstreamUnavailable {
+            context: context.into(),
+            source: None,
+        }
+    }
+
+    pub fn corrupt(message: impl Into<String>) -> Self {
+        Self::Corrupt(message.into())
+    }
+
+    pub fn config(message: impl Into<String>) -> Self {
+        Self::Config(message.into())
+    }
+
+    pub fn read_only(message: impl Into<String>) -> Self {
+        Self::ReadOnly(message.into())
+    }
+
+    /// Classify a gRPC error returned by a peer, relay or remote cache
+    pub fn from_status(context: &str, status: tonic::Status) -> Self {
+        let message = format!("{}: {}", context, status.message());
+        match status.code() {
+            tonic::Code::NotFound => Self::NotFound(message),
+            tonic::Code::Unauthenticated | tonic::Code::PermissionDenied => {
+                Self::AuthFailed(message)
+            }
+            tonic::Code::ResourceExhausted => Self::QuotaExceeded(message),
+            tonic::Code::DataLoss => Self::Corrupt(message),
+            _ => Self::UpstreamUnavailable {
+                context: context.to_string(),
+                source: Some(Box::new(status)),
+            },
+        }
+    }
+
+    /// Whether retrying the same operation later may succeed
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::UpstreamUnavailable { .. } | Self::Io { .. })
+    }
+}
+
+impl From<QuotaError> for FabrikError {
+    fn from(e: QuotaError) -> Self {
+        Self::QuotaExceeded(e.to_string())
+    }
+}
+
+impl From<io::Error> for FabrikError {
+    fn from(source: io::Error) -> Self {
+        Self::Io {
+            context: "I/O error".to_string(),
+            source,
+        }
+    }
+}
+
+impl From<rocksdb::Error> for FabrikError {
+    fn from(e: rocksdb::Error) -> Self {
+        Self::Io {
+            context: "Metadata database error".to_string(),
+            source: io::Error::other(e),
+        }
+    }
+}
+
+impl From<FabrikError> for tonic::Status {
+    fn from(e: FabrikError) -> Self {
+        let message = e.to_string();
+        match e {
+            FabrikError::NotFound(_) => tonic::Status::not_found(message),
+            FabrikError::AuthFailed(_) => tonic::Status::unauthenticated(message),
+            FabrikError::QuotaExceeded(_) => tonic::Status::resource_exhausted(message),
+            FabrikError::UpstreamUnavailable { .. } => tonic::Status::unavailable(message),
+            FabrikError::Corrupt(_) => tonic::Status::data_loss(message),
+            FabrikError::Io { .. } => tonic::Status::internal(message),
+            FabrikError::Config(_) => tonic::Status::failed_precondition(message),
+            FabrikError::ReadOnly(_) => tonic::Status::permission_denied(message),
+        }
+    }
+}
+
+/// Attach context to errors while classifying them, like `anyhow::Context`
+pub trait ResultExt<T> {
+    /// Classify the error as a local I/O failure
+    fn io_context(self, context: &str) -> Result<T>;
+
+    /// Classify the error as an unreachable or failing upstream
+    fn upstream_context(self, context: &str) -> Result<T>;
+}
+
+impl<T, E: Into<BoxError>> ResultExt<T> for std::result::Result<T, E> {
+    fn io_context(self, context: &str) -> Result<T> {
+        self.map_err(|e| FabrikError::Io {
+            context: context.to_string(),
+            source: match e.into().downcast::<io::Error>() {
+                Ok(e) => *e,
+                Err(e) => io::Error::other(e),
+            },
+        })
+    }
+
+    fn upstream_context(self, context: &str) -> Result<T> {
+        self.map_err(|e| FabrikError::UpstreamUnavailable {
+            context: context.to_string(),
+            source: Some(e.into()),
+        })
+    }
+}
+
+/// Classify the gRPC error of a request, see [`FabrikError::from_status`]
+pub trait RpcResultExt<T> {
+    fn rpc_context(self, context: &str) -> Result<T>;
+}
+
+impl<T> RpcResultExt<T> for std::result::Result<T, tonic::Status> {
+    fn rpc_context(self, context: &str) -> Result<T> {
+        self.map_err(|status| FabrikError::from_status(context, status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_context_keeps_the_io_error() {
+        let result: std::result::Result<(), io::Error> =
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "denied"));
+        let err = result.io_context("Failed to write data").unwrap_err();
+
+        assert_eq!(err.to_string(), "Failed to write data");
+        let FabrikError::Io { source, .. } = &err else {
+            panic!("expected an I/O error, got {:?}", err);
+        };
+        assert_eq!(source.kind(), io::ErrorKind::PermissionDenied);
+        assert!(err.is_transient());
+    }
+
+    #[test]
+    fn test_upstream_context_keeps_the_source() {
+        let result: std::result::Result<(), tonic::Status> =
+            Err(tonic::Status::unavailable("connection refused"));
+        let err = result.upstream_context("Hello request failed").unwrap_err();
+
+        assert!(matches!(err, FabrikError::UpstreamUnavailable { .. }));
+        assert!(format!("{:#}", anyhow::Error::from(err)).contains("connection refused"));
+    }
+
+    #[test]
+    fn test_status_codes() {
+        let code = |e: FabrikError| tonic::Status::from(e).code();
+
+        assert_eq!(
+            code(FabrikError::not_found("Artifact")),
+            tonic::Code::NotFound
+        );
+        assert_eq!(
+            code(FabrikError::auth_failed("bad token")),
+            tonic::Code::Unauthenticated
+        );
+        assert_eq!(
+            code(QuotaError::QuotaExceeded { used: 2, quota: 1 }.into()),
+            tonic::Code::ResourceExhausted
+        );
+        assert_eq!(
+            code(FabrikError::unavailable("No peers available")),
+            tonic::Code::Unavailable
+        );
+        assert_eq!(
+            code(FabrikError::corrupt("short read")),
+            tonic::Code::DataLoss
+        );
+        assert!(!FabrikError::corrupt("short read").is_transient());
+    }
+
+    #[test]
+    fn test_from_status() {
+        let rpc = |status: tonic::Status| {
+            Err::<(), _>(status)
+                .rpc_context("Exists request failed")
+                .unwrap_err()
+        };
+
+        let err = rpc(tonic::Status::unauthenticated("Invalid signature"));
+        assert!(matches!(err, FabrikError::AuthFailed(_)));
+        assert_eq!(err.to_string(), "Exists request failed: Invalid signature");
+
+        assert!(matches!(
+            rpc(tonic::Status::resource_exhausted(
+                "Daily upload quota exceeded"
+            )),
+            FabrikError::QuotaExceeded(_)
+        ));
+        assert!(matches!(
+            rpc(tonic::Status::not_found("no such blob")),
+            FabrikError::NotFound(_)
+        ));
+        assert!(rpc(tonic::Status::deadline_exceeded("timeout")).is_transient());
+    }
+}
@@ -0,0 +1,408 @@
+//! Cache event stream of daemons (`GET /api/v1/events`)
+//!
+//! Writes, hits and misses (through [`EventStorage`]), evictions and replication pushes
+//! are published on an [`EventBus`] as they happen. Subscribers, such as dashboards or
+//! CI annotations, receive them as Server-Sent Events; nothing is buffered for them
+//! beyond [`CAPACITY`] events, and publishing without subscribers costs nothing.
+//! Servers publish events for their webhook notifications but don't serve the stream.
+
+use axum::extract::Query;
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::error::{FabrikError, Result};
+use crate::storage::{EntryTags, Labels, ObjectInfo, Storage, StorageStats};
+
+/// Events kept for subscribers that fall behind, before they miss some
+pub const CAPACITY: usize = 1024;
+
+/// Path of the event stream
+pub const PATH: &str = "/api/v1/events";
+
+/// What happened in the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A blob was stored
+    Put,
+    /// A read was answered from the cache
+    Hit,
+    /// A read of something the cache doesn't have
+    Miss,
+    /// A blob was deleted by a client
+    Delete,
+    /// A blob was evicted to make room
+    Evict,
+    /// A blob was pushed to another region (or failed to, see `error`)
+    UpstreamSync,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Put => "put",
+            Self::Hit => "hit",
+            Self::Miss => "miss",
+            Self::Delete => "delete",
+            Self::Evict => "evict",
+            Self::UpstreamSync => "upstream_sync",
+        }
+    }
+}
+
+impl std::str::FromStr for EventKind {
+    type Err = FabrikError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "put" => Ok(Self::Put),
+            "hit" => Ok(Self::Hit),
+            "miss" => Ok(Self::Miss),
+            "delete" => Ok(Self::Delete),
+            "evict" => Ok(Self::Evict),
+            "upstream_sync" => Ok(Self::UpstreamSync),
+            other => Err(FabrikError::config(format!(
+                "Unknown event type '{}' (expected put, hit, miss, delete, evict or upstream_sync)",
+                other
+            ))),
+        }
+    }
+}
+
+/// An event of the stream
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheEvent {
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    /// When it happened (Unix milliseconds)
+    pub timestamp: i64,
+    /// Hex-encoded ID of the blob
+    pub hash: String,
+    /// Size of the blob in bytes, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Server the blob was pushed to (`upstream_sync`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+    /// Why the push failed (`upstream_sync`, retried later)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl CacheEvent {
+    pub fn new(kind: EventKind, id: &[u8], size: Option<u64>) -> Self {
+        Self {
+            kind,
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            hash: hex::encode(id),
+            size,
+            upstream: None,
+            error: None,
+        }
+    }
+
+    /// Push of a blob to `upstream`, failed with `error` if any
+    pub fn upstream_sync(id: &[u8], size: u64, upstream: &str, error: Option<String>) -> Self {
+        Self {
+            upstream: Some(upstream.to_string()),
+            error,
+            ..Self::new(EventKind::UpstreamSync, id, Some(size))
+        }
+    }
+}
+
+/// Publishes cache events to the current subscribers
+#[derive(Debug)]
+pub struct EventBus {
+    sender: broadcast::Sender<CacheEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self { sender }
+    }
+
+    /// Whether anyone is subscribed, to skip building events nobody receives
+    pub fn is_watched(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub fn publish(&self, event: CacheEvent) {
+        // Fails only without subscribers
+        let _ = self.sender.send(event);
+    }
+
+    /// Events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<CacheEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// Query parameters of the event stream
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    /// Comma-separated event types to receive (all by default)
+    types: Option<String>,
+}
+
+/// Routes of the event stream, for the HTTP server of a daemon
+pub fn router<S: Clone + Send + Sync + 'static>(bus: Arc<EventBus>) -> Router<S> {
+    Router::new().route(
+        PATH,
+        get(move |Query(query): Query<EventsQuery>| {
+            let bus = bus.clone();
+            async move { subscribe(&bus, query) }
+        }),
+    )
+}
+
+/// Stream events to a client, one SSE event per cache event named after its type
+///
+/// A client that falls more than [`CAPACITY`] events behind receives a `lagged` event
+/// with the number of events it missed.
+fn subscribe(bus: &EventBus, query: EventsQuery) -> Response {
+    let kinds = match query.types.as_deref().map(parse_kinds).transpose() {
+        Ok(kinds) => kinds,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    let mut events = bus.subscribe();
+    let (tx, rx) = mpsc::channel::<std::result::Result<Event, Infallible>>(64);
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) if kinds.as_ref().is_some_and(|k| !k.contains(&event.kind)) => {
+                    continue;
+                }
+                Ok(event) => Event::default()
+                    .event(event.kind.as_str())
+                    .json_data(&event)
+                    .expect("events serialize to JSON"),
+                Err(broadcast::error::RecvError::Lagged(missed)) => Event::default()
+                    .event("lagged")
+                    .data(format!("{{\"missed\":{}}}", missed)),
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            // The client disconnected
+            if tx.send(Ok(event)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Sse::new(ReceiverStream::new(rx))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+fn parse_kinds(types: &str) -> Result<Vec<EventKind>> {
+    types
+        .split(',')
+        .map(str::trim)
+        .filter(|kind| !kind.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Storage publishing the writes, reads and deletes of its clients
+pub struct EventStorage<S: Storage> {
+    inner: Arc<S>,
+    events: Arc<EventBus>,
+}
+
+impl<S: Storage> EventStorage<S> {
+    pub fn new(inner: Arc<S>, events: Arc<EventBus>) -> Self {
+        Self { inner, events }
+    }
+
+    fn publish(&self, kind: EventKind, id: &[u8], size: impl FnOnce() -> Option<u64>) {
+        if self.events.is_watched() {
+            self.events.publish(CacheEvent::new(kind, id, size()));
+        }
+    }
+}
+
+impl<S: Storage> Clone for EventStorage<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl<S: Storage> Storage for EventStorage<S> {
+    fn put(&self, id: &[u8], data: &[u8]) -> Result<()> {
+        self.put_with_expiry(id, data, None)
+    }
+
+    fn put_with_expiry(&self, id: &[u8], data: &[u8], expires_at: Option<i64>) -> Result<()> {
+        self.inner.put_with_expiry(id, data, expires_at)?;
+        self.publish(EventKind::Put, id, || Some(data.len() as u64));
+        Ok(())
+    }
+
+    fn put_file(&self, id: &[u8], path: &Path, expires_at: Option<i64>) -> Result<()> {
+        self.inner.put_file(id, path, expires_at)?;
+        self.publish(EventKind::Put, id, || {
+            std::fs::metadata(path).ok().map(|m| m.len())
+        });
+        Ok(())
+    }
+
+    fn get(&self, id: &[u8]) -> Result<Option<Vec<u8>>> {
+        let data = self.inner.get(id)?;
+        match &data {
+            Some(data) => self.publish(EventKind::Hit, id, || Some(data.len() as u64)),
+            None => self.publish(EventKind::Miss, id, || None),
+        }
+        Ok(data)
+    }
+
+    fn open(&self, id: &[u8]) -> Result<Option<Box<dyn Read + Send>>> {
+        let reader = self.inner.open(id)?;
+        match reader {
+            Some(_) => self.publish(EventKind::Hit, id, || self.inner.size(id).ok().flatten()),
+            None => self.publish(EventKind::Miss, id, || None),
+        }
+        Ok(reader)
+    }
+
+    fn exists(&self, id: &[u8]) -> Result<bool> {
+        self.inner.exists(id)
+    }
+
+    fn exists_many(&self, ids: &[Vec<u8>]) -> Result<Vec<bool>> {
+        self.inner.exists_many(ids)
+    }
+
+    fn delete(&self, id: &[u8]) -> Result<()> {
+        self.inner.delete(id)?;
+        self.publish(EventKind::Delete, id, || None);
+        Ok(())
+    }
+
+    fn size(&self, id: &[u8]) -> Result<Option<u64>> {
+        self.inner.size(id)
+    }
+
+    fn created_at(&self, id: &[u8]) -> Result<Option<i64>> {
+        self.inner.created_at(id)
+    }
+
+    fn info(&self, id: &[u8]) -> Result<Option<ObjectInfo>> {
+        self.inner.info(id)
+    }
+
+    fn touch(&self, id: &[u8]) -> Result<()> {
+        self.inner.touch(id)
+    }
+
+    fn touch_many(&self, ids: &[Vec<u8>]) -> Result<()> {
+        self.inner.touch_many(ids)
+    }
+
+    fn list_ids(&self) -> Result<Vec<Vec<u8>>> {
+        self.inner.list_ids()
+    }
+
+    fn stats(&self) -> Result<StorageStats> {
+        self.inner.stats()
+    }
+
+    fn put_tags(&self, id: &[u8], tags: &EntryTags) -> Result<()> {
+        self.inner.put_tags(id, tags)
+    }
+
+    fn get_tags(&self, id: &[u8]) -> Result<Option<EntryTags>> {
+        self.inner.get_tags(id)
+    }
+
+    fn add_labels(&self, id: &[u8], labels: &Labels) -> Result<()> {
+        self.inner.add_labels(id, labels)
+    }
+
+    fn get_labels(&self, id: &[u8]) -> Result<Labels> {
+        self.inner.get_labels(id)
+    }
+
+    fn find_by_labels(&self, labels: &Labels) -> Result<Vec<Vec<u8>>> {
+        self.inner.find_by_labels(labels)
+    }
+
+    fn pin(&self, holder: &[u8], ids: &[Vec<u8>]) -> Result<()> {
+        self.inner.pin(holder, ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FilesystemStorage;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_storage_publishes_events() {
+        let temp_dir = TempDir::new().unwrap();
+        let bus = Arc::new(EventBus::new());
+        let storage = EventStorage::new(
+            Arc::new(FilesystemStorage::new(temp_dir.path()).unwrap()),
+            bus.clone(),
+        );
+
+        // Nobody is listening yet
+        storage.put(b"before", b"data").unwrap();
+
+        let mut events = bus.subscribe();
+        storage.get(b"missing").unwrap();
+        storage.put(b"blob", b"hello").unwrap();
+        storage.get(b"blob").unwrap();
+        storage.delete(b"blob").unwrap();
+
+        let received: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|e| (e.kind, e.hash, e.size))
+            .collect();
+        assert_eq!(
+            received,
+            vec![
+                (EventKind::Miss, hex::encode(b"missing"), None),
+                (EventKind::Put, hex::encode(b"blob"), Some(5)),
+                (EventKind::Hit, hex::encode(b"blob"), Some(5)),
+                (EventKind::Delete, hex::encode(b"blob"), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_event_json() {
+        let event = CacheEvent::upstream_sync(b"ab", 3, "https://eu.cache:7070", None);
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "upstream_sync");
+        assert_eq!(json["hash"], "6162");
+        assert_eq!(json["upstream"], "https://eu.cache:7070");
+        assert!(json.get("error").is_none());
+
+        assert_eq!(
+            parse_kinds("put, miss").unwrap(),
+            vec![EventKind::Put, EventKind::Miss]
+        );
+        assert!(parse_kinds("put,bogus").is_err());
+    }
+}
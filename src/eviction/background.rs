@@ -1,8 +1,9 @@
 //! Background eviction task
 //!
 //! Runs eviction asynchronously in a background tokio task, avoiding
-//! blocking `put()` operations. The task periodically checks cache size
-//! and evicts objects according to the configured policy.
+//! blocking `put()` operations. The task periodically checks cache size, and
+//! the free space of the cache volume when `min_free_disk` is set, and evicts
+//! objects according to the configured policy.
 
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -10,20 +11,36 @@ use std::time::{Duration, Instant};
 use tokio::sync::Notify;
 use tracing::{debug, info, warn};
 
-use super::{EvictionConfig, EvictionManager, EvictionPolicyType, LfuPolicy, LruPolicy, TtlPolicy};
+use super::{
+    EvictionConfig, EvictionManager, EvictionPolicyType, EvictionStats, LfuPolicy, LruPolicy,
+    TtlPolicy,
+};
+use crate::error::Result;
+use crate::events::{CacheEvent, EventBus, EventKind};
 use crate::eviction::policy::EvictionPolicy;
 use crate::eviction::EvictionCandidate;
 
 /// Trait for storage backends that support background eviction
 pub trait EvictableStorage: Send + Sync + 'static {
     /// Get current cache size in bytes
-    fn current_size(&self) -> anyhow::Result<u64>;
+    fn current_size(&self) -> Result<u64>;
 
     /// Get all eviction candidates with their metadata
-    fn get_eviction_candidates(&self) -> anyhow::Result<Vec<EvictionCandidate>>;
+    fn get_eviction_candidates(&self) -> Result<Vec<EvictionCandidate>>;
 
     /// Delete an object by ID
-    fn delete_object(&self, id: &[u8]) -> anyhow::Result<()>;
+    fn delete_object(&self, id: &[u8]) -> Result<()>;
+
+    /// Free space of the volume holding the cache, if it can be read
+    fn free_disk_space(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Move objects out of the hot tier of tiered storage while it's over its size, in
+    /// `policy` order; returns the number of objects and bytes moved
+    fn demote_hot_tier(&self, _policy: &dyn EvictionPolicy) -> Result<(usize, u64)> {
+        Ok((0, 0))
+    }
 }
 
 /// Configuration for background eviction task
@@ -33,6 +50,8 @@ pub struct BackgroundEvictionConfig {
     pub check_interval: Duration,
     /// Eviction configuration (max_size, policy, etc.)
     pub eviction_config: EvictionConfig,
+    /// Where to publish evictions (see `events`)
+    pub events: Option<Arc<EventBus>>,
 }
 
 impl Default for BackgroundEvictionConfig {
@@ -40,6 +59,7 @@ impl Default for BackgroundEvictionConfig {
         Self {
             check_interval: Duration::from_secs(30),
             eviction_config: EvictionConfig::default(),
+            events: None,
         }
     }
 }
@@ -50,6 +70,7 @@ impl BackgroundEvictionConfig {
         Self {
             check_interval: Duration::from_secs(30),
             eviction_config,
+            events: None,
         }
     }
 
@@ -59,6 +80,12 @@ impl BackgroundEvictionConfig {
         self.check_interval = interval;
         self
     }
+
+    /// Publish each evicted object on `events`
+    pub fn with_events(mut self, events: Arc<EventBus>) -> Self {
+        self.events = Some(events);
+        self
+    }
 }
 
 /// Handle to control the background eviction task
@@ -69,6 +96,8 @@ pub struct BackgroundEvictionHandle {
     notify: Arc<Notify>,
     /// Join handle for the background task
     join_handle: Option<tokio::task::JoinHandle<()>>,
+    /// Counters of the task's evictions
+    stats: Arc<EvictionStats>,
 }
 
 impl BackgroundEvictionHandle {
@@ -99,6 +128,11 @@ impl BackgroundEvictionHandle {
         }
     }
 
+    /// Evictions performed by the task so far
+    pub fn stats(&self) -> Arc<EvictionStats> {
+        Arc::clone(&self.stats)
+    }
+
     /// Check if the background task is still running
     #[allow(dead_code)]
     pub fn is_running(&self) -> bool {
@@ -115,6 +149,8 @@ pub fn spawn_background_eviction<S: EvictableStorage>(
 ) -> BackgroundEvictionHandle {
     let shutdown = Arc::new(AtomicBool::new(false));
     let notify = Arc::new(Notify::new());
+    let eviction_manager = EvictionManager::new(config.eviction_config.clone());
+    let stats = eviction_manager.stats();
 
     let shutdown_clone = Arc::clone(&shutdown);
     let notify_clone = Arc::clone(&notify);
@@ -123,7 +159,14 @@ pub fn spawn_background_eviction<S: EvictableStorage>(
     let check_interval = config.check_interval;
 
     let join_handle = tokio::spawn(async move {
-        run_eviction_loop(storage, config, shutdown_clone, notify_clone).await;
+        run_eviction_loop(
+            storage,
+            config,
+            eviction_manager,
+            shutdown_clone,
+            notify_clone,
+        )
+        .await;
     });
 
     info!(
@@ -135,6 +178,7 @@ pub fn spawn_background_eviction<S: EvictableStorage>(
         shutdown,
         notify,
         join_handle: Some(join_handle),
+        stats,
     }
 }
 
@@ -142,11 +186,10 @@ pub fn spawn_background_eviction<S: EvictableStorage>(
 async fn run_eviction_loop<S: EvictableStorage>(
     storage: Arc<S>,
     config: BackgroundEvictionConfig,
+    eviction_manager: EvictionManager,
     shutdown: Arc<AtomicBool>,
     notify: Arc<Notify>,
 ) {
-    let eviction_manager = EvictionManager::new(config.eviction_config.clone());
-
     loop {
         // Wait for either the interval or a manual trigger
         tokio::select! {
@@ -166,7 +209,12 @@ async fn run_eviction_loop<S: EvictableStorage>(
         }
 
         // Run eviction check
-        if let Err(e) = run_eviction_cycle(&storage, &eviction_manager, &config.eviction_config) {
+        if let Err(e) = run_eviction_cycle(
+            &storage,
+            &eviction_manager,
+            &config.eviction_config,
+            config.events.as_deref(),
+        ) {
             warn!("Background eviction cycle failed: {}", e);
         }
     }
@@ -179,10 +227,34 @@ fn run_eviction_cycle<S: EvictableStorage>(
     storage: &Arc<S>,
     eviction_manager: &EvictionManager,
     config: &EvictionConfig,
-) -> anyhow::Result<()> {
+    events: Option<&EventBus>,
+) -> Result<()> {
+    let policy: Box<dyn EvictionPolicy> = match config.policy {
+        EvictionPolicyType::Lru => Box::new(LruPolicy),
+        EvictionPolicyType::Lfu => Box::new(LfuPolicy),
+        EvictionPolicyType::Ttl => Box::new(TtlPolicy::new(config.default_ttl_secs)),
+    };
+
+    // Make room in the hot tier first; demoted objects still count towards max_size
+    match storage.demote_hot_tier(policy.as_ref()) {
+        Ok((0, _)) => {}
+        Ok((demoted, bytes)) => info!(
+            "Moved {} objects ({}MB) from the hot tier to the cold tier",
+            demoted,
+            bytes / (1024 * 1024)
+        ),
+        Err(e) => warn!("Failed to move objects to the cold tier: {}", e),
+    }
+
     let current_size = storage.current_size()?;
+    let free_disk = if config.min_free_disk_bytes > 0 {
+        storage.free_disk_space()?
+    } else {
+        None
+    };
+    let disk_low = eviction_manager.disk_low(free_disk);
 
-    if !eviction_manager.needs_eviction(current_size) {
+    if !eviction_manager.needs_eviction(current_size) && !disk_low {
         debug!(
             "Cache size {}MB is under limit {}MB, no eviction needed",
             current_size / (1024 * 1024),
@@ -191,13 +263,22 @@ fn run_eviction_cycle<S: EvictableStorage>(
         return Ok(());
     }
 
-    let bytes_to_evict = eviction_manager.bytes_to_evict(current_size);
-    info!(
-        "Cache size {}MB exceeds limit {}MB, evicting {}MB",
-        current_size / (1024 * 1024),
-        config.max_size_bytes / (1024 * 1024),
-        bytes_to_evict / (1024 * 1024)
-    );
+    let bytes_to_evict = eviction_manager.bytes_to_evict_with_disk(current_size, free_disk);
+    if disk_low {
+        warn!(
+            "Cache volume has {}MB free, under min_free_disk {}MB, evicting {}MB",
+            free_disk.unwrap_or_default() / (1024 * 1024),
+            config.min_free_disk_bytes / (1024 * 1024),
+            bytes_to_evict / (1024 * 1024)
+        );
+    } else {
+        info!(
+            "Cache size {}MB exceeds limit {}MB, evicting {}MB",
+            current_size / (1024 * 1024),
+            config.max_size_bytes / (1024 * 1024),
+            bytes_to_evict / (1024 * 1024)
+        );
+    }
 
     let start = Instant::now();
 
@@ -205,12 +286,6 @@ fn run_eviction_cycle<S: EvictableStorage>(
     let candidates = storage.get_eviction_candidates()?;
 
     // Select candidates to evict using the configured policy
-    let policy: Box<dyn EvictionPolicy> = match config.policy {
-        EvictionPolicyType::Lru => Box::new(LruPolicy),
-        EvictionPolicyType::Lfu => Box::new(LfuPolicy),
-        EvictionPolicyType::Ttl => Box::new(TtlPolicy::new(config.default_ttl_secs)),
-    };
-
     let mut sorted_candidates = candidates;
     policy.sort_candidates(&mut sorted_candidates);
 
@@ -222,7 +297,8 @@ fn run_eviction_cycle<S: EvictableStorage>(
         if total_size >= bytes_to_evict && !to_evict.is_empty() {
             break;
         }
-        if to_evict.len() >= config.max_evictions_per_run {
+        // A nearly full volume can't wait for the next run
+        if to_evict.len() >= config.max_evictions_per_run && !disk_low {
             break;
         }
 
@@ -240,6 +316,13 @@ fn run_eviction_cycle<S: EvictableStorage>(
                 evicted_count += 1;
                 evicted_bytes += candidate.size;
                 eviction_manager.record_eviction(candidate.size);
+                if let Some(events) = events {
+                    events.publish(CacheEvent::new(
+                        EventKind::Evict,
+                        &candidate.id,
+                        Some(candidate.size),
+                    ));
+                }
                 debug!(
                     "Evicted object {} ({} bytes)",
                     hex::encode(&candidate.id),
@@ -276,6 +359,8 @@ mod tests {
     struct MockStorage {
         objects: Mutex<HashMap<Vec<u8>, ObjectMetadata>>,
         total_size: Mutex<u64>,
+        /// Free space of the volume besides the cache objects
+        free_disk: Mutex<Option<u64>>,
     }
 
     impl MockStorage {
@@ -283,6 +368,7 @@ mod tests {
             Self {
                 objects: Mutex::new(HashMap::new()),
                 total_size: Mutex::new(0),
+                free_disk: Mutex::new(None),
             }
         }
 
@@ -299,11 +385,11 @@ mod tests {
     }
 
     impl EvictableStorage for MockStorage {
-        fn current_size(&self) -> anyhow::Result<u64> {
+        fn current_size(&self) -> Result<u64> {
             Ok(*self.total_size.lock().unwrap())
         }
 
-        fn get_eviction_candidates(&self) -> anyhow::Result<Vec<EvictionCandidate>> {
+        fn get_eviction_candidates(&self) -> Result<Vec<EvictionCandidate>> {
             let objects = self.objects.lock().unwrap();
             Ok(objects
                 .iter()
@@ -314,19 +400,27 @@ mod tests {
                         accessed_at: *accessed_at,
                         access_count: *access_count,
                         created_at: *created_at,
+                        expires_at: None,
                     },
                 )
                 .collect())
         }
 
-        fn delete_object(&self, id: &[u8]) -> anyhow::Result<()> {
+        fn delete_object(&self, id: &[u8]) -> Result<()> {
             let mut objects = self.objects.lock().unwrap();
             let mut total = self.total_size.lock().unwrap();
             if let Some((size, _, _, _)) = objects.remove(id) {
                 *total -= size;
+                if let Some(free) = self.free_disk.lock().unwrap().as_mut() {
+                    *free += size;
+                }
             }
             Ok(())
         }
+
+        fn free_disk_space(&self) -> Result<Option<u64>> {
+            Ok(*self.free_disk.lock().unwrap())
+        }
     }
 
     #[tokio::test]
@@ -351,9 +445,13 @@ mod tests {
                 max_evictions_per_run: 100,
                 ..Default::default()
             },
+            events: None,
         };
 
-        let handle = spawn_background_eviction(storage.clone(), config);
+        let events = Arc::new(EventBus::new());
+        let mut evictions = events.subscribe();
+
+        let handle = spawn_background_eviction(storage.clone(), config.with_events(events));
 
         // Wait for eviction to run
         tokio::time::sleep(Duration::from_millis(100)).await;
@@ -362,6 +460,48 @@ mod tests {
         // LRU will evict object 1 (oldest access) first, then object 2
         let size = storage.current_size().unwrap();
         assert!(size <= 900, "Expected size <= 900, got {}", size);
+        assert_eq!(handle.stats().get_bytes_evicted(), 1500 - size);
+        let evicted = evictions.try_recv().unwrap();
+        assert_eq!(evicted.kind, EventKind::Evict);
+        assert_eq!(evicted.hash, "01");
+        assert_eq!(evicted.size, Some(500));
+
+        handle.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_background_eviction_keeps_disk_space_free() {
+        let storage = Arc::new(MockStorage::new());
+        for i in 0..5 {
+            storage.add_object(vec![i], 100, i as i64, 1);
+        }
+        *storage.free_disk.lock().unwrap() = Some(150);
+
+        // The cache is far under max_size, but the volume is nearly full. Eviction
+        // restores min_free_disk / 0.9 = 400 bytes free, past the per-run cap.
+        let config = BackgroundEvictionConfig {
+            check_interval: Duration::from_millis(50),
+            eviction_config: EvictionConfig {
+                max_size_bytes: 10_000,
+                policy: EvictionPolicyType::Lru,
+                target_ratio: 0.9,
+                max_evictions_per_run: 1,
+                min_free_disk_bytes: 360,
+                ..Default::default()
+            },
+            events: None,
+        };
+
+        let handle = spawn_background_eviction(storage.clone(), config);
+        handle.trigger_eviction();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        assert_eq!(storage.object_count(), 2);
+        assert_eq!(storage.free_disk_space().unwrap(), Some(450));
+        {
+            let objects = storage.objects.lock().unwrap();
+            assert!(objects.contains_key(&vec![3]) && objects.contains_key(&vec![4]));
+        }
 
         handle.shutdown().await;
     }
@@ -382,6 +522,7 @@ mod tests {
                 max_evictions_per_run: 100,
                 ..Default::default()
             },
+            events: None,
         };
 
         let handle = spawn_background_eviction(storage.clone(), config);
@@ -412,6 +553,7 @@ mod tests {
         let config = BackgroundEvictionConfig {
             check_interval: Duration::from_millis(10),
             eviction_config: EvictionConfig::default(),
+            events: None,
         };
 
         let handle = spawn_background_eviction(storage, config);
@@ -440,6 +582,7 @@ mod tests {
                 max_evictions_per_run: 100,
                 ..Default::default()
             },
+            events: None,
         };
 
         let handle = spawn_background_eviction(storage.clone(), config);
@@ -472,6 +615,7 @@ mod tests {
                 max_evictions_per_run: 100,
                 ..Default::default()
             },
+            events: None,
         };
 
         let handle = spawn_background_eviction(storage.clone(), config);
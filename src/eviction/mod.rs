@@ -20,6 +20,7 @@
 //! max_size = "5GB"
 //! eviction_policy = "lfu"  # lru, lfu, or ttl
 //! default_ttl = "7d"       # Used by TTL policy
+//! min_free_disk = "10GB"   # Also evict while the volume has less free space
 //! ```
 
 use anyhow::{Context, Result};
@@ -62,17 +63,14 @@ impl EvictionStats {
         self.eviction_runs.fetch_add(1, Ordering::Relaxed);
     }
 
-    #[allow(dead_code)]
     pub fn get_evictions_total(&self) -> u64 {
         self.evictions_total.load(Ordering::Relaxed)
     }
 
-    #[allow(dead_code)]
     pub fn get_bytes_evicted(&self) -> u64 {
         self.bytes_evicted.load(Ordering::Relaxed)
     }
 
-    #[allow(dead_code)]
     pub fn get_eviction_runs(&self) -> u64 {
         self.eviction_runs.load(Ordering::Relaxed)
     }
@@ -90,8 +88,10 @@ pub struct EvictionConfig {
     /// Target size after eviction (percentage of max_size)
     /// Default: 0.9 (evict until 90% of max_size)
     pub target_ratio: f64,
-    /// Maximum objects to evict per run
+    /// Maximum objects to evict per run (not applied while the disk is short on space)
     pub max_evictions_per_run: usize,
+    /// Free space to keep on the cache volume, in bytes (0 = don't watch the disk)
+    pub min_free_disk_bytes: u64,
 }
 
 impl Default for EvictionConfig {
@@ -102,6 +102,7 @@ impl Default for EvictionConfig {
             default_ttl_secs: 7 * 24 * 60 * 60, // 7 days
             target_ratio: 0.9,
             max_evictions_per_run: 1000,
+            min_free_disk_bytes: 0,
         }
     }
 }
@@ -169,6 +170,15 @@ impl EvictionConfig {
         })
     }
 
+    /// Also keep `min_free_disk` (e.g. "10GB") free on the cache volume
+    pub fn with_min_free_disk(mut self, min_free_disk: Option<&str>) -> Result<Self> {
+        if let Some(min_free_disk) = min_free_disk {
+            self.min_free_disk_bytes =
+                Self::parse_size(min_free_disk).context("Invalid min_free_disk")?;
+        }
+        Ok(self)
+    }
+
     /// Get target size in bytes (after eviction)
     pub fn target_size_bytes(&self) -> u64 {
         (self.max_size_bytes as f64 * self.target_ratio) as u64
@@ -233,7 +243,6 @@ impl EvictionManager {
     }
 
     /// Get eviction statistics
-    #[allow(dead_code)]
     pub fn stats(&self) -> Arc<EvictionStats> {
         Arc::clone(&self.stats)
     }
@@ -257,6 +266,37 @@ impl EvictionManager {
         current_size_bytes - self.config.target_size_bytes()
     }
 
+    /// Whether the cache volume has less free space than `min_free_disk`
+    pub fn disk_low(&self, free_disk_bytes: Option<u64>) -> bool {
+        free_disk_bytes.is_some_and(|free| free < self.config.min_free_disk_bytes)
+    }
+
+    /// Calculate how many bytes need to be evicted, for both the cache size and the
+    /// free space of its volume
+    ///
+    /// Like the cache size, free space is restored with some headroom: to
+    /// `min_free_disk / target_ratio`.
+    pub fn bytes_to_evict_with_disk(
+        &self,
+        current_size_bytes: u64,
+        free_disk_bytes: Option<u64>,
+    ) -> u64 {
+        let for_size = if self.needs_eviction(current_size_bytes) {
+            self.bytes_to_evict(current_size_bytes)
+        } else {
+            0
+        };
+        let for_disk = match free_disk_bytes {
+            Some(free) if self.disk_low(Some(free)) => {
+                let target =
+                    (self.config.min_free_disk_bytes as f64 / self.config.target_ratio) as u64;
+                target.saturating_sub(free)
+            }
+            _ => 0,
+        };
+        for_size.max(for_disk)
+    }
+
     /// Select candidates for eviction using the configured policy
     ///
     /// Returns a list of (id, size) tuples to evict, ordered by eviction priority
@@ -401,6 +441,31 @@ mod tests {
         assert_eq!(manager.bytes_to_evict(1200), 300);
     }
 
+    #[test]
+    fn test_bytes_to_evict_with_disk() {
+        let config = EvictionConfig {
+            max_size_bytes: 1000,
+            target_ratio: 0.9,
+            ..Default::default()
+        }
+        .with_min_free_disk(Some("900"))
+        .unwrap();
+        let manager = EvictionManager::new(config);
+
+        // Only the cache size counts while the disk has room (or can't be read)
+        assert_eq!(manager.bytes_to_evict_with_disk(1100, Some(5000)), 200);
+        assert_eq!(manager.bytes_to_evict_with_disk(1100, None), 200);
+        assert_eq!(manager.bytes_to_evict_with_disk(500, Some(900)), 0);
+
+        // A nearly full volume evicts up to min_free_disk / target_ratio free
+        assert!(manager.disk_low(Some(400)));
+        assert_eq!(manager.bytes_to_evict_with_disk(500, Some(400)), 600);
+        assert_eq!(manager.bytes_to_evict_with_disk(1100, Some(850)), 200);
+        assert!(EvictionConfig::default()
+            .with_min_free_disk(Some("lots"))
+            .is_err());
+    }
+
     #[test]
     fn test_select_candidates_lru() {
         let config = EvictionConfig {
@@ -417,6 +482,7 @@ mod tests {
                 accessed_at: 1000,
                 access_count: 5,
                 created_at: 500,
+                expires_at: None,
             },
             EvictionCandidate {
                 id: vec![2],
@@ -424,6 +490,7 @@ mod tests {
                 accessed_at: 500, // Older access - should be evicted first
                 access_count: 10,
                 created_at: 400,
+                expires_at: None,
             },
             EvictionCandidate {
                 id: vec![3],
@@ -431,6 +498,7 @@ mod tests {
                 accessed_at: 2000,
                 access_count: 1,
                 created_at: 600,
+                expires_at: None,
             },
         ];
 
@@ -458,6 +526,7 @@ mod tests {
                 accessed_at: 1000,
                 access_count: 5,
                 created_at: 500,
+                expires_at: None,
             },
             EvictionCandidate {
                 id: vec![2],
@@ -465,6 +534,7 @@ mod tests {
                 accessed_at: 500,
                 access_count: 1, // Lowest access count - should be evicted first
                 created_at: 400,
+                expires_at: None,
             },
             EvictionCandidate {
                 id: vec![3],
@@ -472,6 +542,7 @@ mod tests {
                 accessed_at: 2000,
                 access_count: 10,
                 created_at: 600,
+                expires_at: None,
             },
         ];
 
@@ -3,7 +3,8 @@
 //! Each policy defines how candidates are sorted for eviction:
 //! - **LRU**: Sort by `accessed_at` (oldest first)
 //! - **LFU**: Sort by `access_count` (lowest first)
-//! - **TTL**: Sort by `created_at`, only select expired objects
+//! - **TTL**: Sort by expiry (`expires_at`, or `created_at` plus the default TTL), only
+//!   select expired objects
 
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -20,6 +21,16 @@ pub struct EvictionCandidate {
     pub access_count: u64,
     /// Creation timestamp (Unix seconds)
     pub created_at: i64,
+    /// Expiry of objects stored with their own TTL (Unix seconds)
+    pub expires_at: Option<i64>,
+}
+
+impl EvictionCandidate {
+    /// When the candidate expires: its own expiry, or `ttl_secs` after its creation
+    pub fn expiry(&self, ttl_secs: u64) -> i64 {
+        self.expires_at
+            .unwrap_or_else(|| self.created_at.saturating_add(ttl_secs as i64))
+    }
 }
 
 /// Trait for eviction policy implementations
@@ -70,8 +81,8 @@ impl EvictionPolicy for LfuPolicy {
 
 /// TTL (Time To Live) eviction policy
 ///
-/// Evicts objects older than the configured TTL.
-/// Objects that haven't expired are never evicted (unless forced).
+/// Evicts objects older than the configured TTL, or past the expiry they were stored
+/// with. Objects that haven't expired are never evicted (unless forced).
 #[derive(Debug)]
 pub struct TtlPolicy {
     /// TTL in seconds
@@ -92,24 +103,22 @@ impl TtlPolicy {
     }
 
     #[allow(dead_code)]
-    fn is_expired(&self, created_at: i64) -> bool {
-        let now = Self::current_timestamp();
-        let age = now - created_at;
-        age > self.ttl_secs as i64
+    fn is_expired(&self, candidate: &EvictionCandidate) -> bool {
+        Self::current_timestamp() > candidate.expiry(self.ttl_secs)
     }
 }
 
 impl EvictionPolicy for TtlPolicy {
     fn sort_candidates(&self, candidates: &mut [EvictionCandidate]) {
-        // Sort by created_at ascending (oldest first = most likely expired)
-        candidates.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        // Sort by expiry ascending (earliest first = most likely expired)
+        candidates.sort_by_key(|c| c.expiry(self.ttl_secs));
     }
 
     fn filter_candidates(&self, candidates: &[EvictionCandidate]) -> Vec<EvictionCandidate> {
         // Only consider expired objects
         candidates
             .iter()
-            .filter(|c| self.is_expired(c.created_at))
+            .filter(|c| self.is_expired(c))
             .cloned()
             .collect()
     }
@@ -147,10 +156,8 @@ impl TtlWithFallbackPolicy {
             .as_secs() as i64
     }
 
-    fn is_expired(&self, created_at: i64) -> bool {
-        let now = Self::current_timestamp();
-        let age = now - created_at;
-        age > self.ttl_secs as i64
+    fn is_expired(&self, candidate: &EvictionCandidate) -> bool {
+        Self::current_timestamp() > candidate.expiry(self.ttl_secs)
     }
 }
 
@@ -158,16 +165,16 @@ impl EvictionPolicy for TtlWithFallbackPolicy {
     fn sort_candidates(&self, candidates: &mut [EvictionCandidate]) {
         // Sort with expired objects first, then by fallback policy
         candidates.sort_by(|a, b| {
-            let a_expired = self.is_expired(a.created_at);
-            let b_expired = self.is_expired(b.created_at);
+            let a_expired = self.is_expired(a);
+            let b_expired = self.is_expired(b);
 
             // Expired objects come first
             match (a_expired, b_expired) {
                 (true, false) => std::cmp::Ordering::Less,
                 (false, true) => std::cmp::Ordering::Greater,
                 (true, true) => {
-                    // Both expired: sort by age (oldest first)
-                    a.created_at.cmp(&b.created_at)
+                    // Both expired: earliest expiry first
+                    a.expiry(self.ttl_secs).cmp(&b.expiry(self.ttl_secs))
                 }
                 (false, false) => {
                     // Neither expired: use fallback policy
@@ -200,6 +207,7 @@ mod tests {
             accessed_at,
             access_count,
             created_at,
+            expires_at: None,
         }
     }
 
@@ -252,6 +260,36 @@ mod tests {
         assert_eq!(filtered[0].id, vec![2]); // Only expired object
     }
 
+    #[test]
+    fn test_ttl_policy_honors_object_expiry() {
+        let now = TtlPolicy::current_timestamp();
+        let policy = TtlPolicy::new(3600); // 1 hour TTL
+
+        let mut candidates = vec![
+            // Old, but stored to be kept for a day
+            EvictionCandidate {
+                expires_at: Some(now + 86400),
+                ..make_candidate(1, now - 7200, 1, now - 7200)
+            },
+            // Fresh, but stored to expire after a minute
+            EvictionCandidate {
+                expires_at: Some(now - 60),
+                ..make_candidate(2, now - 120, 1, now - 120)
+            },
+            make_candidate(3, now - 4000, 1, now - 4000), // Expired (default TTL)
+        ];
+
+        let filtered = policy.filter_candidates(&candidates);
+        let mut expired: Vec<u8> = filtered.iter().map(|c| c.id[0]).collect();
+        expired.sort();
+        assert_eq!(expired, vec![2, 3]);
+
+        policy.sort_candidates(&mut candidates);
+        assert_eq!(candidates[0].id, vec![3]); // Expired 400s ago
+        assert_eq!(candidates[1].id, vec![2]); // Expired 60s ago
+        assert_eq!(candidates[2].id, vec![1]);
+    }
+
     #[test]
     fn test_ttl_with_fallback_lru() {
         let now = TtlPolicy::current_timestamp();
@@ -0,0 +1,103 @@
+/// Standard health checking and reflection services of the gRPC servers
+///
+/// Each gRPC server (Bazel, Xcode, Fabrik protocol and P2P) also serves, per `[grpc]`:
+///
+/// - `grpc.health.v1.Health` (on by default), so load balancers and orchestrators can
+///   probe it. The server (`""`) and each of its services report SERVING while it runs.
+/// - `grpc.reflection.v1` and `grpc.reflection.v1alpha` (off by default), so tools like
+///   grpcurl and grpcui can list and call its services without their proto files.
+use anyhow::{Context, Result};
+use tonic::service::Routes;
+use tonic_health::ServingStatus;
+
+use crate::config::GrpcConfig;
+
+/// Health and reflection routes of a server serving `services` (full gRPC service
+/// names), whose protos are described by the encoded `descriptor_sets`
+pub async fn routes(
+    config: &GrpcConfig,
+    services: &[&str],
+    descriptor_sets: &[&'static [u8]],
+) -> Result<Routes> {
+    let mut routes = Routes::builder();
+
+    if config.health {
+        let (reporter, health_service) = tonic_health::server::health_reporter();
+        for service in services {
+            reporter
+                .set_service_status(*service, ServingStatus::Serving)
+                .await;
+        }
+        routes.add_service(health_service);
+    }
+
+    if config.reflection {
+        let health_descriptors = config
+            .health
+            .then_some(tonic_health::pb::FILE_DESCRIPTOR_SET);
+        let mut v1 = tonic_reflection::server::Builder::configure();
+        let mut v1alpha = tonic_reflection::server::Builder::configure();
+        for set in descriptor_sets.iter().copied().chain(health_descriptors) {
+            v1 = v1.register_encoded_file_descriptor_set(set);
+            v1alpha = v1alpha.register_encoded_file_descriptor_set(set);
+        }
+        routes.add_service(
+            v1.build_v1()
+                .context("Failed to build gRPC reflection service")?,
+        );
+        routes.add_service(
+            v1alpha
+                .build_v1alpha()
+                .context("Failed to build gRPC reflection service")?,
+        );
+    }
+
+    Ok(routes.routes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic_health::pb::health_check_response::ServingStatus as Status;
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+
+    #[tokio::test]
+    async fn test_health_reports_services() {
+        let config = GrpcConfig::default();
+        let routes = routes(&config, &["fabrik.p2p.P2PCache"], &[])
+            .await
+            .unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            tonic::transport::Server::builder()
+                .add_routes(routes)
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
+                .await
+        });
+
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = HealthClient::new(channel);
+        for service in ["", "fabrik.p2p.P2PCache"] {
+            let response = client
+                .check(HealthCheckRequest {
+                    service: service.to_string(),
+                })
+                .await
+                .unwrap();
+            assert_eq!(response.into_inner().status, Status::Serving as i32);
+        }
+        let unknown = client
+            .check(HealthCheckRequest {
+                service: "unknown.Service".to_string(),
+            })
+            .await;
+        assert_eq!(unknown.unwrap_err().code(), tonic::Code::NotFound);
+    }
+}
@@ -0,0 +1,156 @@
+//! HTTP/2 and keepalive tuning of the gRPC servers and clients (`[grpc]`)
+//!
+//! Applied to every gRPC server (Bazel, Xcode, Fabrik protocol, P2P, relay and
+//! cluster) and to the clients of P2P peers, the relay and cluster nodes. Settings left
+//! unset keep tonic's defaults (and each client's own connect timeout).
+
+use anyhow::{Context, Result};
+use std::time::Duration;
+use tonic::transport::{Endpoint, Server};
+
+use crate::config::GrpcConfig;
+use crate::eviction::EvictionConfig;
+
+/// Largest HTTP/2 flow-control window (2^31 - 1 bytes)
+const MAX_WINDOW_SIZE: u64 = (1 << 31) - 1;
+
+/// Parsed transport settings of `[grpc]`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GrpcTransport {
+    pub keepalive_interval: Option<Duration>,
+    pub keepalive_timeout: Option<Duration>,
+    pub max_concurrent_streams: Option<u32>,
+    pub initial_stream_window_size: Option<u32>,
+    pub initial_connection_window_size: Option<u32>,
+    pub connect_timeout: Option<Duration>,
+}
+
+impl GrpcTransport {
+    pub fn from_config(config: &GrpcConfig) -> Result<Self> {
+        Ok(Self {
+            keepalive_interval: parse_duration("keepalive_interval", &config.keepalive_interval)?,
+            keepalive_timeout: parse_duration("keepalive_timeout", &config.keepalive_timeout)?,
+            max_concurrent_streams: config.max_concurrent_streams,
+            initial_stream_window_size: parse_window_size(
+                "initial_stream_window_size",
+                &config.initial_stream_window_size,
+            )?,
+            initial_connection_window_size: parse_window_size(
+                "initial_connection_window_size",
+                &config.initial_connection_window_size,
+            )?,
+            connect_timeout: parse_duration("connect_timeout", &config.connect_timeout)?,
+        })
+    }
+
+    /// Server builder with these settings
+    pub fn server(&self) -> Server {
+        let mut server = Server::builder();
+        if let Some(interval) = self.keepalive_interval {
+            server = server.http2_keepalive_interval(Some(interval));
+        }
+        if let Some(timeout) = self.keepalive_timeout {
+            server = server.http2_keepalive_timeout(Some(timeout));
+        }
+        if let Some(max) = self.max_concurrent_streams {
+            server = server.max_concurrent_streams(max);
+        }
+        if let Some(size) = self.initial_stream_window_size {
+            server = server.initial_stream_window_size(size);
+        }
+        if let Some(size) = self.initial_connection_window_size {
+            server = server.initial_connection_window_size(size);
+        }
+        server
+    }
+
+    /// `endpoint` with these settings; keepalive pings are also sent on idle
+    /// connections, so pooled connections to busy servers aren't dropped by middleboxes
+    pub fn endpoint(&self, mut endpoint: Endpoint) -> Endpoint {
+        if let Some(interval) = self.keepalive_interval {
+            endpoint = endpoint
+                .http2_keep_alive_interval(interval)
+                .keep_alive_while_idle(true);
+        }
+        if let Some(timeout) = self.keepalive_timeout {
+            endpoint = endpoint.keep_alive_timeout(timeout);
+        }
+        if let Some(size) = self.initial_stream_window_size {
+            endpoint = endpoint.initial_stream_window_size(size);
+        }
+        if let Some(size) = self.initial_connection_window_size {
+            endpoint = endpoint.initial_connection_window_size(size);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        endpoint
+    }
+}
+
+fn parse_duration(key: &str, value: &Option<String>) -> Result<Option<Duration>> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let secs = EvictionConfig::parse_ttl(value)
+        .ok()
+        .filter(|secs| *secs > 0)
+        .with_context(|| format!("grpc.{}: invalid duration '{}' (e.g. 30s)", key, value))?;
+    Ok(Some(Duration::from_secs(secs)))
+}
+
+fn parse_window_size(key: &str, value: &Option<String>) -> Result<Option<u32>> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let size = EvictionConfig::parse_size(value)
+        .with_context(|| format!("grpc.{}: invalid size '{}' (e.g. 1MB)", key, value))?;
+    anyhow::ensure!(
+        (1..=MAX_WINDOW_SIZE).contains(&size),
+        "grpc.{}: {} is outside 1 byte..2GB",
+        key,
+        value
+    );
+    Ok(Some(size as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_config() {
+        let config = GrpcConfig {
+            keepalive_interval: Some("30s".to_string()),
+            keepalive_timeout: Some("10s".to_string()),
+            max_concurrent_streams: Some(512),
+            initial_stream_window_size: Some("1MB".to_string()),
+            connect_timeout: Some("2m".to_string()),
+            ..Default::default()
+        };
+        let transport = GrpcTransport::from_config(&config).unwrap();
+        assert_eq!(transport.keepalive_interval, Some(Duration::from_secs(30)));
+        assert_eq!(transport.keepalive_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(transport.max_concurrent_streams, Some(512));
+        assert_eq!(transport.initial_stream_window_size, Some(1024 * 1024));
+        assert_eq!(transport.initial_connection_window_size, None);
+        assert_eq!(transport.connect_timeout, Some(Duration::from_secs(120)));
+
+        assert_eq!(
+            GrpcTransport::from_config(&GrpcConfig::default()).unwrap(),
+            GrpcTransport::default()
+        );
+
+        let invalid = GrpcConfig {
+            keepalive_interval: Some("often".to_string()),
+            ..Default::default()
+        };
+        assert!(GrpcTransport::from_config(&invalid).is_err());
+
+        let too_large = GrpcConfig {
+            initial_connection_window_size: Some("4GB".to_string()),
+            ..Default::default()
+        };
+        assert!(GrpcTransport::from_config(&too_large).is_err());
+    }
+}
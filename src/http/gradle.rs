@@ -0,0 +1,206 @@
+/// Machine-specific Gradle build cache entries
+///
+/// Gradle cache keys cover task inputs, but some outputs still depend on the machine
+/// that produced them (native tooling, absolute paths leaking into generated files).
+/// The `[build_systems.gradle] machine_specific` setting decides what to do with them:
+///
+/// - `share`: entries are shared, tagged with their origin
+/// - `partition`: entries are stored per machine fingerprint, so only similar machines
+///   (same OS, architecture and JVM) read each other's entries
+/// - `reject`: uploads embedding absolute paths into the uploader's home directory are
+///   not stored
+///
+/// The machine fingerprint comes from Gradle's `User-Agent`
+/// (`Gradle/8.5 (Mac OS X;14.1;aarch64) (Eclipse Adoptium;17.0.9;17.0.9+9)`), or from
+/// the `X-Fabrik-Machine` header when a client sets one.
+use axum::http::HeaderMap;
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+
+use crate::config::MachineSpecificPolicy;
+
+/// Header overriding the machine fingerprint derived from the User-Agent
+pub const MACHINE_HEADER: &str = "x-fabrik-machine";
+
+/// Entry of a Gradle cache archive describing its origin (Java properties)
+const METADATA_ENTRY: &str = "METADATA";
+
+/// Fingerprint of the machine sending a request, if it can be told
+pub fn machine_fingerprint(headers: &HeaderMap) -> Option<String> {
+    if let Some(machine) = header(headers, MACHINE_HEADER) {
+        return Some(machine.to_string());
+    }
+
+    // Gradle/<version> (<os>;<os version>;<arch>) (<jvm vendor>;<jvm version>;<build>)
+    let agent = header(headers, "user-agent")?.strip_prefix("Gradle/")?;
+    let mut groups = agent
+        .split('(')
+        .skip(1)
+        .map(|group| group.trim_end().trim_end_matches(')'));
+    let os: Vec<&str> = groups.next()?.split(';').collect();
+    let jvm: Vec<&str> = groups.next()?.split(';').collect();
+    let (os_name, arch) = (os.first()?, os.get(2)?);
+    let (vendor, version) = (jvm.first()?, jvm.get(1)?);
+    let major = version.split('.').next()?;
+
+    Some(format!("{}/{}/{} {}", os_name, arch, vendor, major))
+}
+
+/// Storage key of a Gradle entry under `policy`
+pub fn storage_key(hash: &str, policy: MachineSpecificPolicy, machine: Option<&str>) -> Vec<u8> {
+    match (policy, machine) {
+        (MachineSpecificPolicy::Partition, Some(machine)) => {
+            let digest = hex::encode(Sha256::digest(machine.as_bytes()));
+            format!("{}@{}", hash, &digest[..16]).into_bytes()
+        }
+        _ => hash.as_bytes().to_vec(),
+    }
+}
+
+/// Whether a Gradle cache entry embeds paths into the home directory of its uploader
+///
+/// The uploader is the `userName` of the entry metadata. Entries that can't be read
+/// as Gradle archives are not considered machine-specific.
+pub fn is_machine_specific(entry: &[u8]) -> bool {
+    let mut contents = Vec::new();
+    let read = if entry.starts_with(&[0x1f, 0x8b]) {
+        GzDecoder::new(entry).read_to_end(&mut contents)
+    } else {
+        contents.extend_from_slice(entry);
+        Ok(contents.len())
+    };
+    if read.is_err() {
+        return false;
+    }
+
+    let mut user = None;
+    let mut files = Vec::new();
+    let mut archive = tar::Archive::new(contents.as_slice());
+    let Ok(entries) = archive.entries() else {
+        return false;
+    };
+    for file in entries {
+        let Ok(mut file) = file else {
+            return false;
+        };
+        let mut data = Vec::new();
+        if file.read_to_end(&mut data).is_err() {
+            return false;
+        }
+        let is_metadata = file
+            .path()
+            .map(|path| path.to_str() == Some(METADATA_ENTRY))
+            .unwrap_or(false);
+        if is_metadata {
+            user = String::from_utf8_lossy(&data)
+                .lines()
+                .find_map(|line| line.strip_prefix("userName="))
+                .map(|name| name.trim().to_string());
+        } else {
+            files.push(data);
+        }
+    }
+
+    let Some(user) = user.filter(|user| !user.is_empty()) else {
+        return false;
+    };
+    let homes = [
+        format!("/Users/{}/", user),
+        format!("/home/{}/", user),
+        format!("\\Users\\{}\\", user),
+    ];
+    files
+        .iter()
+        .any(|data| homes.iter().any(|home| contains(data, home.as_bytes())))
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+
+    fn gradle_entry(metadata: &str, output: &str) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Default::default()));
+        for (name, data) in [
+            (METADATA_ENTRY, metadata),
+            ("tree-classes/Gen.java", output),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, data.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn test_machine_fingerprint() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "user-agent",
+            "Gradle/8.5 (Mac OS X;14.1;aarch64) (Eclipse Adoptium;17.0.9;17.0.9+9)"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            machine_fingerprint(&headers).as_deref(),
+            Some("Mac OS X/aarch64/Eclipse Adoptium 17")
+        );
+
+        headers.insert(MACHINE_HEADER, "ci-linux-x64".parse().unwrap());
+        assert_eq!(
+            machine_fingerprint(&headers).as_deref(),
+            Some("ci-linux-x64")
+        );
+
+        headers.clear();
+        headers.insert("user-agent", "curl/8.4.0".parse().unwrap());
+        assert_eq!(machine_fingerprint(&headers), None);
+    }
+
+    #[test]
+    fn test_storage_key() {
+        let shared = storage_key("abc", MachineSpecificPolicy::Share, Some("linux"));
+        assert_eq!(shared, b"abc");
+        let linux = storage_key("abc", MachineSpecificPolicy::Partition, Some("linux"));
+        let mac = storage_key("abc", MachineSpecificPolicy::Partition, Some("mac"));
+        assert_ne!(linux, mac);
+        assert!(linux.starts_with(b"abc@"));
+        assert_eq!(
+            storage_key("abc", MachineSpecificPolicy::Partition, None),
+            b"abc"
+        );
+    }
+
+    #[test]
+    fn test_is_machine_specific() {
+        let metadata = "type=org.gradle.api.tasks.JavaExec\nuserName=alice\nhostName=box\n";
+        assert!(is_machine_specific(&gradle_entry(
+            metadata,
+            "// Generated from /Users/alice/src/app/schema.json"
+        )));
+        assert!(!is_machine_specific(&gradle_entry(
+            metadata,
+            "// Generated from schema.json"
+        )));
+        assert!(!is_machine_specific(b"not a gradle entry"));
+    }
+}
@@ -0,0 +1,383 @@
+/// Liveness and readiness endpoints of `fabrik server`
+///
+/// Rolling deploys of Layer 2 need to know when a replica can take traffic, not just
+/// that its process is up. Served on `[observability] health_bind`:
+///
+/// - GET /livez - 200 while the process serves requests
+/// - GET /readyz - 200 when every check passes, 503 otherwise, with JSON diagnostics:
+///   - `storage`: the RocksDB metadata database serves reads
+///   - `disk`: the cache directory is writable (skipped on read-only caches)
+///   - `jwt_keys`: JWT verification keys are loaded (skipped without JWT auth)
+///   - `upstream:<url>`: the upstream accepts connections
+///     (with `[observability] readiness_check_upstreams`)
+/// - GET /health - status, uptime and version
+use anyhow::Result;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Serialize;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+use crate::api::types::HealthResponse;
+use crate::auth::keys::KeyRing;
+use crate::storage::FilesystemStorage;
+
+/// Longest a single readiness check may take before it fails
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Outcome of a readiness check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+    Skipped,
+}
+
+/// Result of one readiness check
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Body of /readyz
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    /// `ready` or `not_ready`
+    pub status: &'static str,
+    pub checks: Vec<Check>,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.checks.iter().all(|c| c.status != CheckStatus::Failed)
+    }
+}
+
+/// Dependencies checked by /readyz
+#[derive(Clone)]
+pub struct HealthChecks {
+    storage: Arc<FilesystemStorage>,
+    key_ring: Option<Arc<KeyRing>>,
+    upstreams: Vec<String>,
+    started_at: Instant,
+}
+
+impl HealthChecks {
+    pub fn new(storage: Arc<FilesystemStorage>) -> Self {
+        Self {
+            storage,
+            key_ring: None,
+            upstreams: Vec::new(),
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Require loaded JWT verification keys
+    pub fn with_key_ring(mut self, key_ring: Option<Arc<KeyRing>>) -> Self {
+        self.key_ring = key_ring;
+        self
+    }
+
+    /// Require connectivity to these upstream URLs
+    pub fn with_upstreams(mut self, upstreams: Vec<String>) -> Self {
+        self.upstreams = upstreams;
+        self
+    }
+
+    /// Run every check
+    pub async fn readiness(&self) -> Readiness {
+        let mut checks = vec![
+            self.check_storage().await,
+            self.check_disk().await,
+            self.check_jwt_keys(),
+        ];
+        for upstream in &self.upstreams {
+            checks.push(check_upstream(upstream).await);
+        }
+
+        let mut readiness = Readiness {
+            status: "ready",
+            checks,
+        };
+        if !readiness.is_ready() {
+            readiness.status = "not_ready";
+        }
+        readiness
+    }
+
+    async fn check_storage(&self) -> Check {
+        let storage = self.storage.clone();
+        timed("storage", async move {
+            tokio::task::spawn_blocking(move || storage.check_metadata())
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| e.to_string())?;
+            Ok(None)
+        })
+        .await
+    }
+
+    async fn check_disk(&self) -> Check {
+        if self.storage.is_read_only() {
+            return skipped("disk", "Cache is read-only");
+        }
+        // Next to the objects directory, so scrubbing and compaction never see the probe
+        let cache_dir = self
+            .storage
+            .objects_dir()
+            .parent()
+            .unwrap_or(Path::new("."));
+        let probe = cache_dir.join(format!(".readyz-{}", std::process::id()));
+        timed("disk", async move {
+            tokio::task::spawn_blocking(move || {
+                let result = std::fs::File::create(&probe).and_then(|mut file| {
+                    use std::io::Write;
+                    file.write_all(b"ok")?;
+                    file.sync_all()
+                });
+                let _ = std::fs::remove_file(&probe);
+                result
+            })
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| format!("Cache directory isn't writable: {}", e))?;
+            Ok(None)
+        })
+        .await
+    }
+
+    fn check_jwt_keys(&self) -> Check {
+        let Some(key_ring) = &self.key_ring else {
+            return skipped("jwt_keys", "JWT authentication isn't configured");
+        };
+        let started = Instant::now();
+        let count = key_ring.keys().len();
+        let (status, message) = match count {
+            0 => (
+                CheckStatus::Failed,
+                "No verification keys loaded".to_string(),
+            ),
+            n => (CheckStatus::Ok, format!("{} verification key(s) loaded", n)),
+        };
+        Check {
+            name: "jwt_keys".to_string(),
+            status,
+            message: Some(message),
+            duration_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+
+    pub fn router(self) -> Router {
+        let readyz_checks = self.clone();
+        let started_at = self.started_at;
+        Router::new()
+            .route(
+                "/livez",
+                get(|| async { Json(serde_json::json!({ "status": "ok" })) }),
+            )
+            .route(
+                "/readyz",
+                get(move || {
+                    let checks = readyz_checks.clone();
+                    async move { readyz_response(checks.readiness().await) }
+                }),
+            )
+            .route(
+                "/health",
+                get(move || async move {
+                    Json(HealthResponse {
+                        status: "healthy".to_string(),
+                        uptime_seconds: started_at.elapsed().as_secs(),
+                        version: env!("CARGO_PKG_VERSION").to_string(),
+                    })
+                }),
+            )
+    }
+
+    /// Serve the endpoints on `bind` in the background
+    pub async fn serve(self, bind: &str) -> Result<()> {
+        let listener = tokio::net::TcpListener::bind(bind)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to bind health API to {}: {}", bind, e))?;
+        info!("Health checks available at http://{}/readyz", bind);
+
+        let app = self.router();
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                error!("Health server failed: {}", e);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+fn readyz_response(readiness: Readiness) -> Response {
+    let status = match readiness.is_ready() {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(readiness)).into_response()
+}
+
+fn skipped(name: &str, reason: &str) -> Check {
+    Check {
+        name: name.to_string(),
+        status: CheckStatus::Skipped,
+        message: Some(reason.to_string()),
+        duration_ms: 0,
+    }
+}
+
+/// Run `check` with `CHECK_TIMEOUT`; it resolves to an optional message or an error
+async fn timed(
+    name: &str,
+    check: impl Future<Output = std::result::Result<Option<String>, String>>,
+) -> Check {
+    let started = Instant::now();
+    let (status, message) = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(message)) => (CheckStatus::Ok, message),
+        Ok(Err(e)) => (CheckStatus::Failed, Some(e)),
+        Err(_) => (
+            CheckStatus::Failed,
+            Some(format!("Timed out after {:?}", CHECK_TIMEOUT)),
+        ),
+    };
+    Check {
+        name: name.to_string(),
+        status,
+        message,
+        duration_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+async fn check_upstream(url: &str) -> Check {
+    let name = format!("upstream:{}", url);
+    let Some(address) = upstream_address(url) else {
+        return skipped(
+            &name,
+            "Only grpc(s):// and http(s):// upstreams are checked",
+        );
+    };
+    timed(&name, async move {
+        tokio::net::TcpStream::connect(&address)
+            .await
+            .map_err(|e| format!("Failed to connect to {}: {}", address, e))?;
+        Ok(Some(format!("Connected to {}", address)))
+    })
+    .await
+}
+
+/// `host:port` an upstream URL connects to, None for schemes without a fixed address
+pub fn upstream_address(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let default_port = match scheme {
+        "http" => 80,
+        "https" | "grpcs" => 443,
+        "grpc" => 7070,
+        _ => return None,
+    };
+    let authority = rest.split('/').next()?;
+    let host = authority.rsplit('@').next()?;
+    if host.is_empty() {
+        return None;
+    }
+
+    let has_port = host
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()));
+    match has_port && !host.ends_with(']') {
+        true => Some(host.to_string()),
+        false => Some(format!("{}:{}", host, default_port)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_upstream_address() {
+        assert_eq!(
+            upstream_address("grpc://cache.example.com:7070"),
+            Some("cache.example.com:7070".to_string())
+        );
+        assert_eq!(
+            upstream_address("https://user@cache.example.com/v1"),
+            Some("cache.example.com:443".to_string())
+        );
+        assert_eq!(
+            upstream_address("grpc://[::1]"),
+            Some("[::1]:7070".to_string())
+        );
+        assert_eq!(
+            upstream_address("http://[::1]:8080"),
+            Some("[::1]:8080".to_string())
+        );
+        assert_eq!(upstream_address("s3://bucket/prefix"), None);
+    }
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let temp = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp.path()).unwrap());
+
+        let readiness = HealthChecks::new(storage.clone()).readiness().await;
+        assert!(readiness.is_ready());
+        assert_eq!(readiness.status, "ready");
+        let statuses: Vec<_> = readiness
+            .checks
+            .iter()
+            .map(|c| (c.name.as_str(), c.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("storage", CheckStatus::Ok),
+                ("disk", CheckStatus::Ok),
+                ("jwt_keys", CheckStatus::Skipped),
+            ]
+        );
+
+        // A key ring without keys and an unreachable upstream fail readiness
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = format!("grpc://{}", closed.local_addr().unwrap());
+        drop(closed);
+        let readiness = HealthChecks::new(storage)
+            .with_key_ring(Some(Arc::new(KeyRing::new(
+                vec![],
+                Duration::from_secs(60),
+            ))))
+            .with_upstreams(vec![upstream.clone()])
+            .readiness()
+            .await;
+        assert!(!readiness.is_ready());
+        assert_eq!(readiness.status, "not_ready");
+        let failed: Vec<_> = readiness
+            .checks
+            .iter()
+            .filter(|c| c.status == CheckStatus::Failed)
+            .map(|c| c.name.clone())
+            .collect();
+        assert_eq!(
+            failed,
+            vec!["jwt_keys".to_string(), format!("upstream:{}", upstream)]
+        );
+
+        let response = readyz_response(readiness);
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
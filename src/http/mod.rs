@@ -1,3 +1,9 @@
+mod gradle;
+mod health;
+mod s3;
 mod server;
+mod webdav;
 
-pub use server::HttpServer;
+pub use health::{upstream_address, HealthChecks};
+pub use s3::{sign_request, uri_encode, S3Credentials, S3Server};
+pub use server::{HttpServer, ORIGIN_HEADER};
@@ -1,23 +1,48 @@
 use anyhow::Result;
 use axum::{
     body::Bytes,
-    extract::{Path, Query, State},
-    http::{HeaderMap, StatusCode},
+    extract::{Path, Query, Request, State},
+    http::{HeaderMap, Method, StatusCode, Uri},
+    middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::{get, put},
-    Router,
+    routing::{any, get, post, put},
+    Json, Router,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
 use tower_http::trace::TraceLayer;
 use tracing::{info, warn};
 
-use crate::storage::Storage;
+use crate::auth::layer::{AuthLayer, TokenValidator};
+use crate::auth::scopes::{authorize, services, Grants, Permission};
+use crate::chunking::{self, ChunkManifest};
+use crate::completion::{self, CompletionKind};
+use crate::config::MachineSpecificPolicy;
+use crate::daemon_stats::{LiveStats, RequestStatsLayer};
+use crate::error::FabrikError;
+use crate::events::{self, EventBus};
+use crate::eviction::EvictionConfig;
+use crate::idle::{ActivityLayer, ActivityTracker};
+use crate::quota::{QuotaError, UploadLimits};
+use crate::rate_limit::client_identity;
+use crate::storage::{EntryTags, Origin, Storage};
+
+use super::{gradle, webdav};
+
+/// Header marking an uploaded artifact as fetched from elsewhere (`upstream`, `peer`)
+pub const ORIGIN_HEADER: &str = "x-fabrik-origin";
+
+/// Header setting the TTL of an uploaded artifact (e.g. `2h`, `30d`), overriding the
+/// default TTL of the TTL eviction policy
+pub const TTL_HEADER: &str = "x-fabrik-ttl";
 
 /// HTTP server state
 #[derive(Clone)]
 struct AppState<S: Storage + Clone> {
     storage: Arc<S>,
+    gradle_policy: MachineSpecificPolicy,
+    stats: Option<Arc<LiveStats>>,
 }
 
 /// Query parameters for TurboRepo v8 API
@@ -28,49 +53,133 @@ struct TurboRepoQuery {
     slug: Option<String>,
 }
 
+/// Query parameters for shell completion
+#[derive(Debug, Deserialize)]
+struct CompleteQuery {
+    #[serde(default)]
+    prefix: String,
+    limit: Option<usize>,
+}
+
+/// Most hashes a single find-missing request may ask about
+const MAX_FIND_MISSING: usize = 10_000;
+
+/// Body of a find-missing request: hex-encoded artifact hashes
+#[derive(Debug, Deserialize)]
+struct FindMissingRequest {
+    hashes: Vec<String>,
+}
+
+/// Hashes of a find-missing request that aren't stored, in request order
+#[derive(Debug, Serialize)]
+struct FindMissingResponse {
+    missing: Vec<String>,
+}
+
 /// HTTP cache server for Metro, Gradle, Nx, TurboRepo, etc.
 ///
 /// Implements a simple HTTP API:
 /// - GET /api/v1/artifacts/{hash} - Retrieve artifact (Metro) - hex-encoded
 /// - PUT /api/v1/artifacts/{hash} - Store artifact (Metro) - hex-encoded
+/// - POST /api/v1/artifacts/find-missing - Which of `{"hashes": [...]}` aren't stored,
+///   so upstream clients skip redundant uploads
+/// - GET/PUT /api/v1/artifacts/{hash}/chunks - Chunk manifest of a blob stored as
+///   content-defined chunks (see `chunking`)
 /// - GET /v8/artifacts/{hash}?slug=team&teamId=id - Retrieve artifact (TurboRepo v8)
 /// - PUT /v8/artifacts/{hash}?slug=team&teamId=id - Store artifact (TurboRepo v8)
 /// - GET /v1/cache/{hash} - Retrieve artifact (Nx) - raw string
 /// - PUT /v1/cache/{hash} - Store artifact (Nx) - raw string
 /// - GET /cache/{hash} - Retrieve artifact (Gradle) - raw string
 /// - PUT /cache/{hash} - Store artifact (Gradle) - raw string
+/// - GET /api/v1/complete/{cas|kv}?prefix=ab - Matching hashes/keys, one per line
+/// - GET /api/v1/stats - Request counters, cache size and recent requests of the daemon
+///   (see `daemon_stats`), when enabled
+/// - GET /api/v1/invocations[/{id}] - Cache traffic of build invocations, when enabled
+/// - GET /api/v1/events?types=put,miss - Server-Sent Events of the cache (see `events`),
+///   when enabled; needs the `fabrik:admin` scope
+/// - /dav/{cas|kv}/... - WebDAV access to the CAS and KV stores, when enabled
 /// - GET /health - Health check
 pub struct HttpServer<S: Storage + Clone> {
     #[allow(dead_code)]
     port: u16,
     storage: Arc<S>,
+    limits: Arc<UploadLimits>,
+    gradle_policy: MachineSpecificPolicy,
+    webdav: bool,
+    activity: Option<Arc<ActivityTracker>>,
+    stats: Option<Arc<LiveStats>>,
+    events: Option<Arc<EventBus>>,
+    auth: Option<Arc<TokenValidator>>,
 }
 
 impl<S: Storage + Clone + 'static> HttpServer<S> {
-    #[allow(dead_code)]
     pub fn new(port: u16, storage: Arc<S>) -> Self {
-        Self { port, storage }
-    }
-
-    /// Create a new HTTP server with automatic port allocation (port 0)
-    /// Returns the server, actual assigned port, and the pre-bound listener
-    pub async fn new_with_port_zero(
-        storage: Arc<S>,
-    ) -> Result<(Self, u16, tokio::net::TcpListener)> {
-        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
-        let actual_port = listener.local_addr()?.port();
-        let server = Self {
-            port: actual_port,
+        Self {
+            port,
             storage,
-        };
-        Ok((server, actual_port, listener))
+            limits: Arc::new(UploadLimits::unlimited()),
+            gradle_policy: MachineSpecificPolicy::default(),
+            webdav: false,
+            activity: None,
+            stats: None,
+            events: None,
+            auth: None,
+        }
+    }
+
+    /// Enforce upload size limits and quotas
+    pub fn with_upload_limits(mut self, limits: Arc<UploadLimits>) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Handle machine-specific Gradle entries according to `policy`
+    pub fn with_gradle_policy(mut self, policy: MachineSpecificPolicy) -> Self {
+        self.gradle_policy = policy;
+        self
+    }
+
+    /// Serve the CAS and KV stores over WebDAV under /dav/
+    pub fn with_webdav(mut self, enabled: bool) -> Self {
+        self.webdav = enabled;
+        self
+    }
+
+    /// Record requests in `tracker`, for the daemon's idle shutdown
+    pub fn with_activity(mut self, tracker: Arc<ActivityTracker>) -> Self {
+        self.activity = Some(tracker);
+        self
+    }
+
+    /// Record requests in `stats` and serve them at /api/v1/stats and
+    /// /api/v1/invocations
+    pub fn with_stats(mut self, stats: Arc<LiveStats>) -> Self {
+        self.stats = Some(stats);
+        self
     }
 
-    /// Run the server with a pre-bound listener
+    /// Stream the events published on `events` at /api/v1/events
+    pub fn with_events(mut self, events: Arc<EventBus>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Authenticate requests with `validator` (JWT bearer tokens), so their scopes
+    /// are enforced
+    pub fn with_auth(mut self, validator: Option<Arc<TokenValidator>>) -> Self {
+        self.auth = validator;
+        self
+    }
+
+    /// Run the server with a pre-bound listener (TCP, or a Unix socket)
     /// This is useful when you need to know the actual port before starting the server
-    pub async fn run_with_listener(self, listener: tokio::net::TcpListener) -> Result<()> {
+    pub async fn run_with_listener<L>(self, listener: L) -> Result<()>
+    where
+        L: axum::serve::Listener,
+        L::Addr: std::fmt::Debug,
+    {
         let app = self.router();
-        info!("HTTP server listening on {}", listener.local_addr()?);
+        info!("HTTP server listening on {:?}", listener.local_addr()?);
         axum::serve(listener, app).await?;
         Ok(())
     }
@@ -79,13 +188,23 @@ impl<S: Storage + Clone + 'static> HttpServer<S> {
     pub fn router(self) -> Router {
         let state = AppState {
             storage: self.storage,
+            gradle_policy: self.gradle_policy,
+            stats: self.stats.clone(),
         };
 
-        Router::new()
-            .route("/health", get(health_handler))
+        let router = Router::new()
             // Metro routes (hex-encoded)
             .route("/api/v1/artifacts/{hash}", get(get_metro_artifact))
             .route("/api/v1/artifacts/{hash}", put(put_metro_artifact))
+            .route("/api/v1/artifacts/{hash}/info", get(metro_artifact_info))
+            .route(
+                "/api/v1/artifacts/{hash}/chunks",
+                get(get_artifact_chunks).put(put_artifact_chunks),
+            )
+            .route(
+                "/api/v1/artifacts/find-missing",
+                post(find_missing_artifacts),
+            )
             // TurboRepo v8 routes (raw string hashes)
             .route("/v8/artifacts/{hash}", get(get_turborepo_artifact))
             .route("/v8/artifacts/{hash}", put(put_turborepo_artifact))
@@ -95,8 +214,46 @@ impl<S: Storage + Clone + 'static> HttpServer<S> {
             // Gradle routes (raw string)
             .route("/cache/{hash}", get(get_gradle_artifact))
             .route("/cache/{hash}", put(put_gradle_artifact))
+            // Shell completion of CAS hashes and KV keys
+            .route("/api/v1/complete/{kind}", get(complete_handler))
+            .route("/api/v1/stats", get(stats_handler))
+            .route("/api/v1/invocations", get(invocations_handler))
+            .route("/api/v1/invocations/{id}", get(invocation_handler));
+        // WebDAV (generic HTTP cache clients); methods such as MKCOL need `any`
+        let router = match self.webdav {
+            true => router
+                .route(webdav::PREFIX, any(webdav_handler))
+                .route(&format!("{}/", webdav::PREFIX), any(webdav_handler))
+                .route(
+                    &format!("{}/{{*path}}", webdav::PREFIX),
+                    any(webdav_handler),
+                ),
+            false => router,
+        };
+        let router = match self.events {
+            Some(bus) => router.merge(events::router(bus)),
+            None => router,
+        };
+
+        let router = router
+            .route_layer(middleware::from_fn_with_state(
+                self.limits,
+                enforce_upload_limits,
+            ))
+            .route_layer(middleware::from_fn(authorize_request))
+            .layer(AuthLayer::new(self.auth))
+            // Health checks don't need a token
+            .route("/health", get(health_handler))
             .layer(TraceLayer::new_for_http())
-            .with_state(state)
+            .with_state(state);
+        let router = match self.stats {
+            Some(stats) => router.layer(RequestStatsLayer::new(stats.requests())),
+            None => router,
+        };
+        match self.activity {
+            Some(tracker) => router.layer(ActivityLayer::new(tracker)),
+            None => router,
+        }
     }
 
     /// Start the HTTP server
@@ -115,11 +272,265 @@ impl<S: Storage + Clone + 'static> HttpServer<S> {
     }
 }
 
+/// Enforce scope-based authorization on cache endpoints
+///
+/// Grants are attached to the request by the authentication layer ([`AuthLayer`]);
+/// requests without grants (authentication disabled) pass through.
+async fn authorize_request(request: Request, next: Next) -> Response {
+    let Some(service) = service_for_path(request.uri().path()) else {
+        return next.run(request).await;
+    };
+
+    // The event stream reveals the keys written and read through every service
+    let permission = match request.uri().path() {
+        events::PATH => Permission::Admin,
+        _ => Permission::for_http_method(request.method()),
+    };
+    if let Err(e) = authorize(request.extensions().get::<Grants>(), service, permission) {
+        warn!(service = service, permission = %permission, "Request denied: {}", e);
+        return (StatusCode::FORBIDDEN, e.to_string()).into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Enforce the artifact size limit and upload quota on uploads
+///
+/// Uploads are rejected from `Content-Length` when possible; otherwise the body is
+/// read up to the limit.
+async fn enforce_upload_limits(
+    State(limits): State<Arc<UploadLimits>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !limits.is_enabled() || request.method() != axum::http::Method::PUT {
+        return next.run(request).await;
+    }
+    let Some(service) = service_for_path(request.uri().path()) else {
+        return next.run(request).await;
+    };
+
+    let client = client_identity(&request);
+    let max_size = limits.max_artifact_size(service);
+    let declared_size = request
+        .headers()
+        .get(axum::http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+
+    if let (Some(max), Some(size)) = (max_size, declared_size) {
+        if size > max {
+            return upload_rejected(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                service,
+                &client,
+                &format!(
+                    "Artifact of {} bytes exceeds the {} byte limit for {}",
+                    size, max, service
+                ),
+            );
+        }
+    }
+
+    let (parts, body) = request.into_parts();
+    let limit = max_size.map_or(usize::MAX, |max| max.saturating_add(1) as usize);
+    let body = match axum::body::to_bytes(body, limit).await {
+        Ok(body) => body,
+        Err(_) => {
+            return upload_rejected(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                service,
+                &client,
+                &format!(
+                    "Artifact exceeds the {} byte limit for {}",
+                    max_size.unwrap_or_default(),
+                    service
+                ),
+            );
+        }
+    };
+
+    if let Err(e) = limits.check_upload(service, &client, body.len() as u64) {
+        let status = match e {
+            QuotaError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            QuotaError::QuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+        };
+        return upload_rejected(status, service, &client, &e.to_string());
+    }
+
+    next.run(Request::from_parts(parts, axum::body::Body::from(body)))
+        .await
+}
+
+fn upload_rejected(status: StatusCode, service: &str, client: &str, reason: &str) -> Response {
+    warn!(
+        service = service,
+        client = client,
+        "Upload rejected: {}",
+        reason
+    );
+    (status, reason.to_string()).into_response()
+}
+
+/// Service (for authorization and limits) serving a request path
+fn service_for_path(path: &str) -> Option<&'static str> {
+    if path.starts_with("/api/v1/artifacts/") {
+        Some(services::METRO)
+    } else if path.starts_with("/v8/artifacts/") {
+        Some(services::TURBOREPO)
+    } else if path.starts_with("/v1/cache/") {
+        Some(services::NX)
+    } else if path.starts_with("/cache/") {
+        Some(services::GRADLE)
+    } else if path.starts_with("/api/v1/complete/")
+        || path == "/api/v1/stats"
+        || path == "/api/v1/invocations"
+        || path.starts_with("/api/v1/invocations/")
+        || path == events::PATH
+    {
+        Some(services::FABRIK)
+    } else if path == webdav::PREFIX || path.starts_with(&format!("{}/", webdav::PREFIX)) {
+        Some(services::WEBDAV)
+    } else {
+        None
+    }
+}
+
 /// Health check handler
 async fn health_handler() -> impl IntoResponse {
     (StatusCode::OK, "OK")
 }
 
+/// WebDAV request on the CAS and KV stores
+async fn webdav_handler<S: Storage + Clone>(
+    State(state): State<AppState<S>>,
+    method: Method,
+    uri: Uri,
+    body: Bytes,
+) -> Response {
+    webdav::handle(state.storage.as_ref(), method, uri, body).await
+}
+
+/// List CAS hashes or KV keys starting with a prefix (used by shell completion)
+async fn complete_handler<S: Storage + Clone>(
+    Path(kind): Path<String>,
+    Query(params): Query<CompleteQuery>,
+    State(state): State<AppState<S>>,
+) -> Response {
+    let Some(kind) = CompletionKind::parse(&kind) else {
+        return (StatusCode::NOT_FOUND, "Unknown completion kind").into_response();
+    };
+
+    match state.storage.list_ids() {
+        Ok(ids) => {
+            let limit = params.limit.unwrap_or(completion::DEFAULT_LIMIT);
+            let mut body = completion::matching_ids(&ids, kind, &params.prefix, limit).join("\n");
+            body.push('\n');
+            (StatusCode::OK, [("Content-Type", "text/plain")], body).into_response()
+        }
+        Err(e) => {
+            warn!(kind = kind.as_str(), error = %e, "Failed to list cache entries");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+        }
+    }
+}
+
+/// Live statistics of the daemon (for `fabrik top`)
+async fn stats_handler<S: Storage + Clone>(State(state): State<AppState<S>>) -> Response {
+    let Some(stats) = state.stats else {
+        return (StatusCode::NOT_FOUND, "Statistics are not enabled").into_response();
+    };
+    match stats.snapshot(state.storage.as_ref()) {
+        Ok(snapshot) => Json(snapshot).into_response(),
+        Err(e) => {
+            warn!(error = %e, "Failed to read cache statistics");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+        }
+    }
+}
+
+/// Cache traffic of the build invocations the daemon knows, most recently active first
+async fn invocations_handler<S: Storage + Clone>(State(state): State<AppState<S>>) -> Response {
+    match state.stats {
+        Some(stats) => Json(stats.requests().invocations()).into_response(),
+        None => (StatusCode::NOT_FOUND, "Statistics are not enabled").into_response(),
+    }
+}
+
+/// Cache traffic of a build invocation
+async fn invocation_handler<S: Storage + Clone>(
+    State(state): State<AppState<S>>,
+    Path(id): Path<String>,
+) -> Response {
+    let Some(stats) = state.stats else {
+        return (StatusCode::NOT_FOUND, "Statistics are not enabled").into_response();
+    };
+    match stats.requests().invocation_stats(&id) {
+        Some(invocation) => Json(invocation).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Unknown invocation: {}", id)).into_response(),
+    }
+}
+
+/// Hashes of the request that aren't stored
+///
+/// Upload negotiation: clients ask before uploading, so artifacts another client
+/// already uploaded don't cross the network again. Like the uploads it negotiates, it
+/// requires write access.
+async fn find_missing_artifacts<S: Storage + Clone>(
+    State(state): State<AppState<S>>,
+    Json(request): Json<FindMissingRequest>,
+) -> Response {
+    if request.hashes.len() > MAX_FIND_MISSING {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("At most {} hashes per request", MAX_FIND_MISSING),
+        )
+            .into_response();
+    }
+    let Ok(ids) = request
+        .hashes
+        .iter()
+        .map(hex::decode)
+        .collect::<Result<Vec<_>, _>>()
+    else {
+        return (StatusCode::BAD_REQUEST, "Invalid hash format").into_response();
+    };
+
+    let missing = state.storage.exists_many(&ids).and_then(|exists| {
+        // Blobs stored as chunks are only missing without a manifest
+        let unstored: Vec<String> = request
+            .hashes
+            .into_iter()
+            .zip(exists)
+            .filter_map(|(hash, exists)| (!exists).then_some(hash))
+            .collect();
+        let manifest_ids: Vec<Vec<u8>> = unstored
+            .iter()
+            .map(|hash| hex::decode(chunking::manifest_id(hash)).expect("hex"))
+            .collect();
+        let chunked = state.storage.exists_many(&manifest_ids)?;
+        Ok(unstored
+            .into_iter()
+            .zip(chunked)
+            .filter_map(|(hash, chunked)| (!chunked).then_some(hash))
+            .collect::<Vec<_>>())
+    });
+    match missing {
+        Ok(missing) => {
+            info!(
+                requested = ids.len(),
+                missing = missing.len(),
+                "Find missing artifacts"
+            );
+            Json(FindMissingResponse { missing }).into_response()
+        }
+        Err(e) => {
+            warn!(error = %e, "Storage error");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+        }
+    }
+}
+
 /// Get artifact handler for Metro
 /// Metro uses hex-encoded hashes via /api/v1/artifacts/{hash}
 async fn get_metro_artifact<S: Storage + Clone>(
@@ -141,12 +552,124 @@ async fn get_metro_artifact<S: Storage + Clone>(
             info!(build_system = "metro", hash = %hash, size = data.len(), "Cache HIT");
             (StatusCode::OK, data).into_response()
         }
+        Ok(None) => match chunked_artifact(state.storage.as_ref(), &hash) {
+            Ok(Some(data)) => {
+                info!(build_system = "metro", hash = %hash, size = data.len(), "Cache HIT (chunked)");
+                (StatusCode::OK, data).into_response()
+            }
+            Ok(None) => {
+                info!(build_system = "metro", hash = %hash, "Cache MISS");
+                (StatusCode::NOT_FOUND, "Not found").into_response()
+            }
+            Err(e) => {
+                warn!(build_system = "metro", hash = %hash, error = %e, "Storage error");
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+            }
+        },
+        Err(e) => {
+            warn!(build_system = "metro", hash = %hash, error = %e, "Storage error");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+        }
+    }
+}
+
+/// Blob `hash` assembled from its chunks, None if it isn't stored as chunks or a
+/// chunk was evicted
+fn chunked_artifact<S: Storage>(storage: &S, hash: &str) -> Result<Option<Vec<u8>>, FabrikError> {
+    let Some(manifest) = chunking::load_manifest(storage, hash)? else {
+        return Ok(None);
+    };
+    let data = chunking::assemble(storage, &manifest)?;
+    if data.is_none() {
+        warn!(hash = %hash, "Chunked artifact is missing chunks");
+    }
+    Ok(data)
+}
+
+/// Chunk manifest of a blob stored as chunks
+async fn get_artifact_chunks<S: Storage + Clone>(
+    Path(hash): Path<String>,
+    State(state): State<AppState<S>>,
+) -> Response {
+    if hex::decode(&hash).is_err() {
+        return (StatusCode::BAD_REQUEST, "Invalid hash format").into_response();
+    }
+    match chunking::load_manifest(state.storage.as_ref(), &hash) {
+        Ok(Some(manifest)) => Json(manifest).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Not found").into_response(),
+        Err(e) => {
+            warn!(hash = %hash, error = %e, "Storage error");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+        }
+    }
+}
+
+/// Store a blob as the chunks of its manifest
+///
+/// The chunks must have been uploaded first: while some are missing, the response is
+/// 409 with the missing chunks (`{"missing": [...]}`). The assembled blob must match
+/// `hash`, its SHA256.
+async fn put_artifact_chunks<S: Storage + Clone>(
+    Path(hash): Path<String>,
+    State(state): State<AppState<S>>,
+    Json(manifest): Json<ChunkManifest>,
+) -> Response {
+    if hex::decode(&hash).is_err() {
+        return (StatusCode::BAD_REQUEST, "Invalid hash format").into_response();
+    }
+    let storage = state.storage.as_ref();
+
+    let data = match chunking::assemble(storage, &manifest) {
+        Ok(Some(data)) => data,
         Ok(None) => {
-            info!(build_system = "metro", hash = %hash, "Cache MISS");
-            (StatusCode::NOT_FOUND, "Not found").into_response()
+            return match chunking::missing_chunks(storage, &manifest) {
+                Ok(missing) => {
+                    (StatusCode::CONFLICT, Json(FindMissingResponse { missing })).into_response()
+                }
+                Err(e) => (StatusCode::BAD_REQUEST, format!("Error: {}", e)).into_response(),
+            };
+        }
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Error: {}", e)).into_response(),
+    };
+    if data.len() as u64 != manifest.size || hex::encode(Sha256::digest(&data)) != hash {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Chunks don't match the artifact hash",
+        )
+            .into_response();
+    }
+
+    match chunking::store_manifest(storage, &hash, &manifest) {
+        Ok(()) => {
+            info!(
+                hash = %hash,
+                size = manifest.size,
+                chunks = manifest.chunks.len(),
+                "Chunked artifact stored"
+            );
+            (StatusCode::OK, "Stored").into_response()
         }
         Err(e) => {
-            warn!(build_system = "metro", hash = %hash, error = %e, "Storage error");
+            warn!(hash = %hash, error = %e, "Storage error");
+            (store_error_status(&e), format!("Error: {}", e)).into_response()
+        }
+    }
+}
+
+/// Artifact info handler: what the cache knows about an artifact, as JSON
+async fn metro_artifact_info<S: Storage + Clone>(
+    Path(hash): Path<String>,
+    State(state): State<AppState<S>>,
+) -> Response {
+    let Ok(hash_bytes) = hex::decode(&hash) else {
+        return (StatusCode::BAD_REQUEST, "Invalid hash format").into_response();
+    };
+
+    match state.storage.info(&hash_bytes) {
+        Ok(Some(info)) => Json(info).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Not found").into_response(),
+        Err(e) => {
+            warn!(hash = %hash, error = %e, "Storage error");
             (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
         }
     }
@@ -157,6 +680,7 @@ async fn get_metro_artifact<S: Storage + Clone>(
 async fn put_metro_artifact<S: Storage + Clone>(
     Path(hash): Path<String>,
     State(state): State<AppState<S>>,
+    headers: HeaderMap,
     body: Bytes,
 ) -> Response {
     // Decode hex hash to bytes
@@ -168,19 +692,64 @@ async fn put_metro_artifact<S: Storage + Clone>(
         }
     };
 
+    let origin = match headers.get(ORIGIN_HEADER).map(|value| {
+        value
+            .to_str()
+            .ok()
+            .and_then(|value| value.parse::<Origin>().ok())
+    }) {
+        Some(Some(origin)) => Some(origin),
+        Some(None) => {
+            return (StatusCode::BAD_REQUEST, "Invalid origin header").into_response();
+        }
+        None => None,
+    };
+
+    let expires_at = match headers.get(TTL_HEADER).map(|value| {
+        value
+            .to_str()
+            .ok()
+            .and_then(|value| EvictionConfig::parse_ttl(value).ok())
+    }) {
+        Some(Some(ttl)) => Some(chrono::Utc::now().timestamp().saturating_add(ttl as i64)),
+        Some(None) => {
+            return (StatusCode::BAD_REQUEST, "Invalid TTL header").into_response();
+        }
+        None => None,
+    };
+
     // Store in cache
-    match state.storage.put(&hash_bytes, &body) {
+    let stored = state
+        .storage
+        .put_with_expiry(&hash_bytes, &body, expires_at)
+        .and_then(|()| match origin {
+            Some(origin) => {
+                let mut tags = state.storage.get_tags(&hash_bytes)?.unwrap_or_default();
+                tags.origin = Some(origin);
+                state.storage.put_tags(&hash_bytes, &tags)
+            }
+            None => Ok(()),
+        });
+    match stored {
         Ok(()) => {
             info!(build_system = "metro", hash = %hash, size = body.len(), "Artifact stored");
             (StatusCode::OK, "Stored").into_response()
         }
         Err(e) => {
             warn!(build_system = "metro", hash = %hash, error = %e, "Storage error");
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+            (store_error_status(&e), format!("Error: {}", e)).into_response()
         }
     }
 }
 
+/// Status of a failed store: 403 on a read-only cache, 500 otherwise
+pub(super) fn store_error_status(e: &FabrikError) -> StatusCode {
+    match e {
+        FabrikError::ReadOnly(_) => StatusCode::FORBIDDEN,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 /// Get artifact handler for Nx/TurboRepo
 /// Nx/TurboRepo use raw string hashes (numeric) via /v1/cache/{hash}
 async fn get_nx_artifact<S: Storage + Clone>(
@@ -232,7 +801,7 @@ async fn put_nx_artifact<S: Storage + Clone>(
         }
         Err(e) => {
             warn!(build_system = "nx", hash = %hash, error = %e, "Storage error");
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+            (store_error_status(&e), format!("Error: {}", e)).into_response()
         }
     }
 }
@@ -242,12 +811,14 @@ async fn put_nx_artifact<S: Storage + Clone>(
 async fn get_gradle_artifact<S: Storage + Clone>(
     Path(hash): Path<String>,
     State(state): State<AppState<S>>,
+    headers: HeaderMap,
 ) -> Response {
-    // Use hash string directly as bytes (no hex decoding)
-    let hash_bytes = hash.as_bytes();
+    // Raw hash string, suffixed by the machine when entries are partitioned
+    let machine = gradle::machine_fingerprint(&headers);
+    let key = gradle::storage_key(&hash, state.gradle_policy, machine.as_deref());
 
     // Get from storage
-    match state.storage.get(hash_bytes) {
+    match state.storage.get(&key) {
         Ok(Some(data)) => {
             info!(build_system = "gradle", hash = %hash, size = data.len(), "Cache HIT");
             (StatusCode::OK, data).into_response()
@@ -268,20 +839,37 @@ async fn get_gradle_artifact<S: Storage + Clone>(
 async fn put_gradle_artifact<S: Storage + Clone>(
     Path(hash): Path<String>,
     State(state): State<AppState<S>>,
+    headers: HeaderMap,
     body: Bytes,
 ) -> Response {
-    // Use hash string directly as bytes (no hex decoding)
-    let hash_bytes = hash.as_bytes();
+    // Raw hash string, suffixed by the machine when entries are partitioned
+    let machine = gradle::machine_fingerprint(&headers);
+    let key = gradle::storage_key(&hash, state.gradle_policy, machine.as_deref());
+
+    // Gradle disables the remote cache for the build after a failed upload, so
+    // rejected entries are acknowledged without being stored
+    if state.gradle_policy == MachineSpecificPolicy::Reject && gradle::is_machine_specific(&body) {
+        warn!(build_system = "gradle", hash = %hash, "Machine-specific entry not stored");
+        return (StatusCode::OK, "Not stored: machine-specific entry").into_response();
+    }
 
     // Store in cache
-    match state.storage.put(hash_bytes, &body) {
+    match state.storage.put(&key, &body) {
         Ok(()) => {
+            let tags = EntryTags {
+                build_system: Some(services::GRADLE.to_string()),
+                machine,
+                origin: None,
+            };
+            if let Err(e) = state.storage.put_tags(&key, &tags) {
+                warn!(build_system = "gradle", hash = %hash, error = %e, "Failed to tag entry");
+            }
             info!(build_system = "gradle", hash = %hash, size = body.len(), "Artifact stored");
             (StatusCode::OK, "Stored").into_response()
         }
         Err(e) => {
             warn!(build_system = "gradle", hash = %hash, error = %e, "Storage error");
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+            (store_error_status(&e), format!("Error: {}", e)).into_response()
         }
     }
 }
@@ -390,7 +978,7 @@ async fn put_turborepo_artifact<S: Storage + Clone>(
                 error = %e,
                 "Storage error"
             );
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+            (store_error_status(&e), format!("Error: {}", e)).into_response()
         }
     }
 }
@@ -398,6 +986,7 @@ async fn put_turborepo_artifact<S: Storage + Clone>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hashing::HashAlgorithm;
     use crate::storage::FilesystemStorage;
     use tempfile::TempDir;
 
@@ -410,4 +999,513 @@ mod tests {
         // Just test that we can create the server
         assert_eq!(server.port, 0);
     }
+
+    #[tokio::test]
+    async fn test_read_only_grants_reject_writes() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().to_str().unwrap()).unwrap());
+        let grants = Grants::from_scopes(["cache:read"]).unwrap();
+        let app = HttpServer::new(0, storage)
+            .router()
+            .layer(axum::Extension(grants));
+
+        let put = axum::http::Request::put("/cache/abc123")
+            .body(Body::from("data"))
+            .unwrap();
+        let response = app.clone().oneshot(put).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let get = axum::http::Request::get("/cache/abc123")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(get).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_token_scopes_enforced() {
+        use crate::auth::layer::test_support::{token, validator};
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().to_str().unwrap()).unwrap());
+        let app = HttpServer::new(0, storage)
+            .with_auth(Some(validator(true)))
+            .router();
+        let put = |token: Option<String>| {
+            let mut request = axum::http::Request::put("/cache/abc123");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.body(Body::from("data")).unwrap()
+        };
+
+        let response = app.clone().oneshot(put(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let read_only = token("fork-pr", "cache:read");
+        let response = app.clone().oneshot(put(Some(read_only))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let writer = token("main", "gradle:write");
+        let response = app.clone().oneshot(put(Some(writer))).await.unwrap();
+        assert!(response.status().is_success());
+
+        let health = axum::http::Request::get("/health")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(health).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_requests_limited_to_anonymous_scopes() {
+        use crate::auth::layer::test_support::{anonymous_validator, token};
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().to_str().unwrap()).unwrap());
+        let app = HttpServer::new(0, storage)
+            .with_webdav(true)
+            .with_auth(Some(anonymous_validator(&["cache:read"])))
+            .router();
+        let request = |method: &str, token: Option<String>| {
+            let mut request = axum::http::Request::builder()
+                .method(method)
+                .uri("/dav/kv/ccache/ab/cdef");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.body(Body::from("data")).unwrap()
+        };
+
+        let response = app.clone().oneshot(request("GET", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = app.clone().oneshot(request("PUT", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = app.clone().oneshot(request("DELETE", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let admin = token("ops", "cache:admin");
+        let response = app.oneshot(request("DELETE", Some(admin))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_requires_admin() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().to_str().unwrap()).unwrap());
+        let bus = Arc::new(EventBus::new());
+        let subscribe = |scope: &str| {
+            let grants = Grants::from_scopes([scope]).unwrap();
+            let app = HttpServer::new(0, storage.clone())
+                .with_events(bus.clone())
+                .router()
+                .layer(axum::Extension(grants));
+            let request = axum::http::Request::get(events::PATH)
+                .body(Body::empty())
+                .unwrap();
+            app.oneshot(request)
+        };
+
+        let response = subscribe("cache:write").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = subscribe("fabrik:admin").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_stats_and_invocations_require_fabrik_grants() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().to_str().unwrap()).unwrap());
+        let stats = Arc::new(LiveStats::new(0));
+        let get = |path: &str, scope: &str| {
+            let grants = Grants::from_scopes([scope]).unwrap();
+            let app = HttpServer::new(0, storage.clone())
+                .with_stats(stats.clone())
+                .router()
+                .layer(axum::Extension(grants));
+            let request = axum::http::Request::get(path).body(Body::empty()).unwrap();
+            app.oneshot(request)
+        };
+
+        for path in ["/api/v1/stats", "/api/v1/invocations"] {
+            let response = get(path, "gradle:read").await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            let response = get(path, "fabrik:read").await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = get("/api/v1/invocations/ci-1234", "gradle:read")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_cache_rejects_writes() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        FilesystemStorage::new(temp_dir.path())
+            .unwrap()
+            .put(&[0xab, 0xcd], b"golden")
+            .unwrap();
+        let storage = Arc::new(FilesystemStorage::read_only(temp_dir.path()).unwrap());
+        let app = HttpServer::new(0, storage).router();
+
+        let put = axum::http::Request::put("/api/v1/artifacts/ef01")
+            .body(Body::from("data"))
+            .unwrap();
+        let response = app.clone().oneshot(put).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let get = axum::http::Request::get("/api/v1/artifacts/abcd")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(get).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_gradle_entries_partitioned_by_machine() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().to_str().unwrap()).unwrap());
+        let app = HttpServer::new(0, storage.clone())
+            .with_gradle_policy(MachineSpecificPolicy::Partition)
+            .router();
+        let mac = "Gradle/8.5 (Mac OS X;14.1;aarch64) (Eclipse Adoptium;17.0.9;17.0.9+9)";
+        let linux = "Gradle/8.5 (Linux;6.5;amd64) (Eclipse Adoptium;17.0.9;17.0.9+9)";
+
+        let put = axum::http::Request::put("/cache/abc123")
+            .header("user-agent", mac)
+            .body(Body::from("data"))
+            .unwrap();
+        let response = app.clone().oneshot(put).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let get = |agent: &'static str| {
+            axum::http::Request::get("/cache/abc123")
+                .header("user-agent", agent)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = app.clone().oneshot(get(mac)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.oneshot(get(linux)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Stored entries are tagged with their origin
+        let key = gradle::storage_key(
+            "abc123",
+            MachineSpecificPolicy::Partition,
+            Some("Mac OS X/aarch64/Eclipse Adoptium 17"),
+        );
+        let tags = storage.get_tags(&key).unwrap().unwrap();
+        assert_eq!(tags.build_system.as_deref(), Some("gradle"));
+        assert_eq!(
+            tags.machine.as_deref(),
+            Some("Mac OS X/aarch64/Eclipse Adoptium 17")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upload_limits_reject_oversized_artifacts() {
+        use crate::config::LimitsConfig;
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().to_str().unwrap()).unwrap());
+        let limits = UploadLimits::from_config(&LimitsConfig {
+            max_artifact_size: Some("8".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let app = HttpServer::new(0, storage)
+            .with_upload_limits(Arc::new(limits))
+            .router();
+
+        let small = axum::http::Request::put("/v1/cache/123")
+            .body(Body::from("data"))
+            .unwrap();
+        let response = app.clone().oneshot(small).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let large = axum::http::Request::put("/v1/cache/456")
+            .body(Body::from("way too much data"))
+            .unwrap();
+        let response = app.oneshot(large).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_find_missing_artifacts() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().to_str().unwrap()).unwrap());
+        storage.put(&[0xab, 0xcd], b"stored").unwrap();
+        let app = HttpServer::new(0, storage).router();
+
+        let find_missing = |body: &'static str| {
+            axum::http::Request::post("/api/v1/artifacts/find-missing")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(find_missing(r#"{"hashes": ["abcd", "ef01"]}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], br#"{"missing":["ef01"]}"#);
+
+        let response = app
+            .oneshot(find_missing(r#"{"hashes": ["not hex"]}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_artifacts() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().to_str().unwrap()).unwrap());
+        let app = HttpServer::new(0, storage.clone()).router();
+
+        let blob = b"first chunk, second chunk";
+        let hash = hex::encode(Sha256::digest(blob));
+        let manifest = ChunkManifest::new(&[&blob[..13], &blob[13..]]);
+        let put_manifest = |manifest: &ChunkManifest| {
+            axum::http::Request::put(format!("/api/v1/artifacts/{}/chunks", hash))
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::to_vec(manifest).unwrap()))
+                .unwrap()
+        };
+
+        // Chunks go first
+        storage
+            .put(&hex::decode(&manifest.chunks[0].hash).unwrap(), &blob[..13])
+            .unwrap();
+        let response = app.clone().oneshot(put_manifest(&manifest)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            body,
+            format!(r#"{{"missing":["{}"]}}"#, manifest.chunks[1].hash)
+        );
+
+        storage
+            .put(&hex::decode(&manifest.chunks[1].hash).unwrap(), &blob[13..])
+            .unwrap();
+        let mut wrong = manifest.clone();
+        wrong.chunks.reverse();
+        let response = app.clone().oneshot(put_manifest(&wrong)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = app.clone().oneshot(put_manifest(&manifest)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Served whole, and no longer missing
+        let get = axum::http::Request::get(format!("/api/v1/artifacts/{}", hash))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(get).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], blob);
+
+        let find_missing = axum::http::Request::post("/api/v1/artifacts/find-missing")
+            .header("Content-Type", "application/json")
+            .body(Body::from(format!(r#"{{"hashes": ["{}"]}}"#, hash)))
+            .unwrap();
+        let response = app.oneshot(find_missing).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], br#"{"missing":[]}"#);
+    }
+
+    #[tokio::test]
+    async fn test_artifact_info_reports_origin() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().to_str().unwrap()).unwrap());
+        let app = HttpServer::new(0, storage).router();
+
+        let response = app
+            .clone()
+            .oneshot(
+                axum::http::Request::put("/api/v1/artifacts/abcd")
+                    .header(ORIGIN_HEADER, "upstream")
+                    .header(TTL_HEADER, "1h")
+                    .body(Body::from("fetched"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(
+                axum::http::Request::get("/api/v1/artifacts/abcd/info")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info["size_bytes"], 7);
+        assert_eq!(info["origin"], "upstream");
+        let expires_in = info["expires_at"].as_i64().unwrap() - chrono::Utc::now().timestamp();
+        assert!((3590..=3600).contains(&expires_in));
+        assert_eq!(info["pinned"], false);
+
+        let response = app
+            .oneshot(
+                axum::http::Request::get("/api/v1/artifacts/ef01/info")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_complete_lists_matching_keys() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().to_str().unwrap()).unwrap());
+        storage.put(b"kv:build/linux x86", b"1").unwrap();
+        storage.put(b"kv:build/macos", b"2").unwrap();
+        storage.put(b"kv:test", b"3").unwrap();
+        let app = HttpServer::new(0, storage).router();
+
+        let request = axum::http::Request::get("/api/v1/complete/kv?prefix=build%2F")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"build/linux x86\nbuild/macos\n");
+
+        let request = axum::http::Request::get("/api/v1/complete/objects")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_webdav_cas_and_kv() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().to_str().unwrap()).unwrap());
+        let request = |method: &str, uri: &str, body: &'static str| {
+            axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        // Disabled by default
+        let app = HttpServer::new(0, storage.clone()).router();
+        let response = app.oneshot(request("GET", "/dav/kv/a", "")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let app = HttpServer::new(0, storage.clone())
+            .with_webdav(true)
+            .router();
+        let put = request("PUT", "/dav/kv/ccache/ab/cdef", "object");
+        let response = app.clone().oneshot(put).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let put = request("PUT", "/dav/kv/ccache/ab/cdef", "object");
+        let response = app.clone().oneshot(put).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        // Same entries as `fabrik kv`
+        assert_eq!(
+            storage.get(b"kv:ccache/ab/cdef").unwrap().as_deref(),
+            Some(&b"object"[..])
+        );
+
+        let head = request("HEAD", "/dav/kv/ccache/ab/cdef", "");
+        let response = app.clone().oneshot(head).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-length"], "6");
+
+        let mkcol = request("MKCOL", "/dav/kv/ccache/ab/", "");
+        let response = app.clone().oneshot(mkcol).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // CAS uploads must hash to their name
+        let hash = HashAlgorithm::Sha256.hex_digest(b"blob");
+        let put = request("PUT", &format!("/dav/cas/{}", hash), "blob");
+        let response = app.clone().oneshot(put).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let put = request("PUT", &format!("/dav/cas/{}", hash), "other");
+        let response = app.clone().oneshot(put).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let get = request("GET", &format!("/dav/cas/{}", hash), "");
+        let response = app.clone().oneshot(get).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let delete = request("DELETE", "/dav/kv/ccache/ab/cdef", "");
+        let response = app.clone().oneshot(delete).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let delete = request("DELETE", "/dav/kv/ccache/ab/cdef", "");
+        let response = app.clone().oneshot(delete).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Scopes apply as service `webdav`
+        let grants = Grants::from_scopes(["webdav:read"]).unwrap();
+        let app = HttpServer::new(0, storage)
+            .with_webdav(true)
+            .router()
+            .layer(axum::Extension(grants));
+        let put = request("PUT", "/dav/kv/key", "data");
+        let response = app.oneshot(put).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
 }
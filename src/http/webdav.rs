@@ -0,0 +1,238 @@
+/// WebDAV access to the CAS and KV stores
+///
+/// Generic HTTP cache clients (ccache's `http` remote storage, curl in scripts) speak
+/// plain WebDAV rather than a build system's protocol. With `[http] webdav_enabled`, the
+/// HTTP server also serves:
+///
+/// - /dav/cas/{hash} - CAS blobs by hex content hash, with the IDs of `fabrik cas`.
+///   Uploads must hash (SHA-256 or BLAKE3) to their name.
+/// - /dav/kv/{key} - KV entries of `fabrik kv`; keys may contain `/`
+///
+/// Methods are OPTIONS, GET, HEAD, PUT, DELETE and MKCOL. Collections are implicit, so
+/// MKCOL succeeds on any collection path. PROPFIND isn't supported, so clients that
+/// browse collections (file managers, davfs) can't list them. Requests go through the
+/// server's authorization and upload limits as service `webdav`.
+use axum::{
+    body::Bytes,
+    http::{header, HeaderValue, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use tracing::{info, warn};
+
+use crate::auth::scopes::services;
+use crate::hashing::HashAlgorithm;
+use crate::storage::{EntryTags, Storage};
+
+use super::server::store_error_status;
+
+/// Path prefix of the WebDAV endpoint
+pub(super) const PREFIX: &str = "/dav";
+
+/// Methods of `Allow` and OPTIONS responses
+const ALLOWED_METHODS: &str = "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL";
+
+/// What a WebDAV path names
+#[derive(Debug, PartialEq, Eq)]
+enum Resource {
+    /// `/dav/`, `/dav/cas/`, `/dav/kv/` and directories of KV keys
+    Collection,
+    /// CAS blob by lowercase hex hash
+    Cas(String),
+    /// KV entry by key
+    Kv(String),
+}
+
+impl Resource {
+    /// Resource of a request path, None if it's outside the stores
+    fn resolve(path: &str) -> Option<Self> {
+        let path = percent_encoding::percent_decode_str(path.strip_prefix(PREFIX)?)
+            .decode_utf8()
+            .ok()?;
+        let path = path.strip_prefix('/').unwrap_or(&path);
+        let (store, name) = path.split_once('/').unwrap_or((path, ""));
+        match (store, name) {
+            ("", "") | ("cas" | "kv", "") => Some(Resource::Collection),
+            ("kv", key) if key.ends_with('/') => Some(Resource::Collection),
+            ("kv", key) => Some(Resource::Kv(key.to_string())),
+            ("cas", hash) => Some(Resource::Cas(hash.to_ascii_lowercase())),
+            _ => None,
+        }
+    }
+
+    /// Storage ID (`fabrik cas` stores hex hashes as text, `fabrik kv` prefixes keys)
+    fn id(&self) -> Option<Vec<u8>> {
+        match self {
+            Resource::Collection => None,
+            Resource::Cas(hash) => Some(hash.as_bytes().to_vec()),
+            Resource::Kv(key) => Some(format!("kv:{}", key).into_bytes()),
+        }
+    }
+}
+
+/// Whether `data` hashes to `hash` with one of the supported algorithms
+fn matches_hash(hash: &str, data: &[u8]) -> bool {
+    [HashAlgorithm::Sha256, HashAlgorithm::Blake3]
+        .iter()
+        .any(|algorithm| algorithm.hex_digest(data) == hash)
+}
+
+fn method_not_allowed() -> Response {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        [(header::ALLOW, ALLOWED_METHODS)],
+    )
+        .into_response()
+}
+
+/// Handle a WebDAV request for `uri`
+pub(super) async fn handle<S: Storage>(
+    storage: &S,
+    method: Method,
+    uri: Uri,
+    body: Bytes,
+) -> Response {
+    let Some(resource) = Resource::resolve(uri.path()) else {
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    };
+
+    match method.as_str() {
+        "OPTIONS" => (
+            StatusCode::OK,
+            [
+                (header::ALLOW, ALLOWED_METHODS),
+                (header::HeaderName::from_static("dav"), "1"),
+            ],
+        )
+            .into_response(),
+        // Collections exist as soon as they're named
+        "MKCOL" if resource == Resource::Collection => StatusCode::CREATED.into_response(),
+        _ => match resource.id() {
+            Some(id) => handle_entry(storage, &resource, &id, method, body),
+            None => method_not_allowed(),
+        },
+    }
+}
+
+fn handle_entry<S: Storage>(
+    storage: &S,
+    resource: &Resource,
+    id: &[u8],
+    method: Method,
+    body: Bytes,
+) -> Response {
+    let name = match resource {
+        Resource::Cas(hash) => hash.as_str(),
+        Resource::Kv(key) => key.as_str(),
+        Resource::Collection => "",
+    };
+
+    match method {
+        Method::GET | Method::HEAD => match storage.get(id) {
+            Ok(Some(data)) => {
+                info!(build_system = "webdav", name = %name, size = data.len(), "Cache HIT");
+                let length = HeaderValue::from(data.len());
+                let mut response = (
+                    StatusCode::OK,
+                    [(header::CONTENT_TYPE, "application/octet-stream")],
+                    data,
+                )
+                    .into_response();
+                // Explicit, so HEAD responses (served without body) carry it too
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_LENGTH, length);
+                if let Resource::Cas(hash) = resource {
+                    if let Ok(etag) = HeaderValue::from_str(&format!("\"{}\"", hash)) {
+                        response.headers_mut().insert(header::ETAG, etag);
+                    }
+                }
+                response
+            }
+            Ok(None) => {
+                info!(build_system = "webdav", name = %name, "Cache MISS");
+                (StatusCode::NOT_FOUND, "Not found").into_response()
+            }
+            Err(e) => {
+                warn!(build_system = "webdav", name = %name, error = %e, "Storage error");
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+            }
+        },
+        Method::PUT => {
+            if let Resource::Cas(hash) = resource {
+                if !matches_hash(hash, &body) {
+                    warn!(build_system = "webdav", hash = %hash, "Content doesn't match hash");
+                    return (StatusCode::BAD_REQUEST, "Content doesn't match its hash")
+                        .into_response();
+                }
+            }
+
+            let existed = storage.exists(id).unwrap_or(false);
+            match storage.put(id, &body) {
+                Ok(()) => {
+                    let tags = EntryTags {
+                        build_system: Some(services::WEBDAV.to_string()),
+                        ..Default::default()
+                    };
+                    if let Err(e) = storage.put_tags(id, &tags) {
+                        warn!(build_system = "webdav", name = %name, error = %e, "Failed to tag entry");
+                    }
+                    info!(build_system = "webdav", name = %name, size = body.len(), "Artifact stored");
+                    match existed {
+                        true => StatusCode::NO_CONTENT.into_response(),
+                        false => StatusCode::CREATED.into_response(),
+                    }
+                }
+                Err(e) => {
+                    warn!(build_system = "webdav", name = %name, error = %e, "Storage error");
+                    (store_error_status(&e), format!("Error: {}", e)).into_response()
+                }
+            }
+        }
+        Method::DELETE => match storage.exists(id) {
+            Ok(false) => (StatusCode::NOT_FOUND, "Not found").into_response(),
+            Ok(true) => match storage.delete(id) {
+                Ok(()) => {
+                    info!(build_system = "webdav", name = %name, "Entry deleted");
+                    StatusCode::NO_CONTENT.into_response()
+                }
+                Err(e) => {
+                    warn!(build_system = "webdav", name = %name, error = %e, "Storage error");
+                    (store_error_status(&e), format!("Error: {}", e)).into_response()
+                }
+            },
+            Err(e) => {
+                warn!(build_system = "webdav", name = %name, error = %e, "Storage error");
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("Error: {}", e)).into_response()
+            }
+        },
+        _ => method_not_allowed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(Resource::resolve("/dav"), Some(Resource::Collection));
+        assert_eq!(Resource::resolve("/dav/kv/"), Some(Resource::Collection));
+        assert_eq!(
+            Resource::resolve("/dav/kv/ccache/ab/"),
+            Some(Resource::Collection)
+        );
+        assert_eq!(
+            Resource::resolve("/dav/kv/ccache/ab/cd%20ef"),
+            Some(Resource::Kv("ccache/ab/cd ef".to_string()))
+        );
+        assert_eq!(
+            Resource::resolve("/dav/cas/ABC123"),
+            Some(Resource::Cas("abc123".to_string()))
+        );
+        assert_eq!(Resource::resolve("/dav/other/key"), None);
+        assert_eq!(
+            Resource::Kv("a/b".to_string()).id(),
+            Some(b"kv:a/b".to_vec())
+        );
+    }
+}
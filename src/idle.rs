@@ -0,0 +1,178 @@
+//! Idle tracking for daemons (`[daemon] idle_timeout`)
+//!
+//! Every server of a daemon (HTTP, gRPC, S3 and the Xcode socket) is wrapped in an
+//! [`ActivityLayer`] sharing one [`ActivityTracker`]. The daemon shuts itself down once
+//! no request has been in flight for the idle timeout.
+
+use axum::http::Request;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower::{Layer, Service};
+
+/// Longest pause between two idle checks
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time of the last request and number of requests in flight
+#[derive(Debug)]
+pub struct ActivityTracker {
+    started: Instant,
+    /// Milliseconds since `started` at which the last request started or finished
+    last_activity: AtomicU64,
+    in_flight: AtomicUsize,
+}
+
+impl ActivityTracker {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last_activity: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let now = self.started.elapsed().as_millis() as u64;
+        self.last_activity.fetch_max(now, Ordering::Relaxed);
+    }
+
+    fn begin(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+    }
+
+    fn end(&self) {
+        self.touch();
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// How long no request has been in flight (zero while one is)
+    pub fn idle_for(&self) -> Duration {
+        if self.in_flight.load(Ordering::Relaxed) > 0 {
+            return Duration::ZERO;
+        }
+        let last = Duration::from_millis(self.last_activity.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last)
+    }
+
+    /// Resolve once the tracker has been idle for `timeout`
+    pub async fn wait_until_idle(&self, timeout: Duration) {
+        loop {
+            let idle = self.idle_for();
+            if idle >= timeout {
+                return;
+            }
+            tokio::time::sleep((timeout - idle).min(MAX_CHECK_INTERVAL)).await;
+        }
+    }
+}
+
+impl Default for ActivityTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tower layer recording requests of gRPC (tonic) and HTTP (axum) services in an
+/// [`ActivityTracker`]
+#[derive(Clone)]
+pub struct ActivityLayer {
+    tracker: Arc<ActivityTracker>,
+}
+
+impl ActivityLayer {
+    pub fn new(tracker: Arc<ActivityTracker>) -> Self {
+        Self { tracker }
+    }
+}
+
+impl<S> Layer<S> for ActivityLayer {
+    type Service = ActivityService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ActivityService {
+            inner,
+            tracker: self.tracker.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ActivityService<S> {
+    inner: S,
+    tracker: Arc<ActivityTracker>,
+}
+
+/// Marks a request finished when dropped, so cancelled requests are counted too
+struct InFlight(Arc<ActivityTracker>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.end();
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for ActivityService<S>
+where
+    S: Service<Request<ReqBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        self.tracker.begin();
+        let in_flight = InFlight(self.tracker.clone());
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response = future.await;
+            drop(in_flight);
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requests_reset_idle_time() {
+        let tracker = Arc::new(ActivityTracker::new());
+        let mut service = ActivityLayer::new(tracker.clone()).layer(tower::service_fn(
+            |_request: Request<()>| async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok::<_, std::convert::Infallible>(())
+            },
+        ));
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(tracker.idle_for() >= Duration::from_millis(20));
+
+        let request = tokio::spawn(service.call(Request::new(())));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(
+            tracker.idle_for(),
+            Duration::ZERO,
+            "not idle while a request is in flight"
+        );
+
+        request.await.unwrap().unwrap();
+        assert!(tracker.idle_for() < Duration::from_millis(20));
+
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            tracker.wait_until_idle(Duration::from_millis(30)),
+        )
+        .await
+        .expect("idle after the timeout");
+    }
+}
@@ -2,24 +2,43 @@
 // This allows integration tests and external code to use Fabrik's modules
 
 pub mod auth;
+pub mod bandwidth; // Bandwidth limits of upstream and P2P transfers
 pub mod bazel;
 pub mod capi; // C API (FFI) for external integrations
+pub mod chunking; // Content-defined chunking of large artifacts
 pub mod cli_utils;
+pub mod cluster; // Horizontal clustering of Layer 2 servers
+pub mod completion; // Dynamic shell completion of cache hashes and keys
 pub mod config;
 pub mod config_discovery;
+pub mod config_edit; // Reading and writing single keys of config files
 pub mod config_expansion; // Environment variable expansion for config files
+pub mod config_layers; // Layered config files and profiles
+pub mod config_schema; // JSON Schema and strict validation of config files
+pub mod error; // Typed errors returned by the library API
+pub mod events; // Cache event stream of daemons and servers
 pub mod eviction; // Cache eviction policies (LRU, LFU, TTL)
+pub mod grpc_introspection; // gRPC health checking and reflection
+pub mod grpc_transport; // HTTP/2 and keepalive tuning of gRPC servers and clients
+pub mod hashing; // Content hashing (SHA-256, BLAKE3)
 pub mod logging;
+pub mod notifications; // Webhook notifications of significant cache events
 pub mod p2p; // P2P cache sharing
+pub mod quota; // Upload size limits and daily quotas
+pub mod rate_limit; // Per-client rate limiting (Layer 2)
 pub mod recipe; // Script recipes with content-addressed caching (bash, node, python, etc.)
 pub mod recipe_portable; // Portable recipes executed in Fabrik's embedded JS runtime
+pub mod retry; // Retries with exponential backoff of requests to upstreams
 pub mod storage;
+pub mod unix_socket; // Unix domain sockets of daemons (paths and Linux abstract names)
+pub mod upstream_routing; // Per-upstream routing rules by artifact namespace
 pub mod xdg;
 
 // Re-export commonly used types
 pub use auth::AuthProvider;
 pub use config::FabrikConfig;
 pub use config_discovery::{discover_config, hash_config, DaemonState};
+pub use error::FabrikError;
 pub use eviction::{EvictionConfig, EvictionManager, EvictionPolicyType};
 pub use recipe_portable::RecipeExecutor;
 pub use storage::{
@@ -1,38 +1,70 @@
 mod api;
 mod auth;
+mod backup; // Snapshot backups of Layer 2 caches
+mod bandwidth; // Bandwidth limits of upstream and P2P transfers
 mod bazel;
+mod chunking; // Content-defined chunking of large artifacts
 mod cli;
 mod cli_utils;
+mod cluster; // Horizontal clustering of Layer 2 servers
 mod commands;
+mod completion; // Dynamic shell completion of cache hashes and keys
 mod config;
 mod config_discovery;
+mod config_edit; // Reading and writing single keys of config files
 mod config_expansion; // Environment variable expansion for config files
+mod config_layers; // Layered config files and profiles
+mod config_schema; // JSON Schema and strict validation of config files
+mod daemon_stats; // Live request and cache statistics of daemons (fabrik top)
+mod error; // Typed errors returned by the library API
+mod events; // Cache event stream of daemons and servers
 mod eviction; // Cache eviction policies (LRU, LFU, TTL)
+mod grpc_introspection; // gRPC health checking and reflection
+mod grpc_transport; // HTTP/2 and keepalive tuning of gRPC servers and clients
+mod hashing; // Content hashing (SHA-256, BLAKE3)
 mod http;
+mod idle; // Idle tracking and auto-shutdown of daemons
 mod logging;
 mod merger;
+mod notifications; // Webhook notifications of significant cache events
 mod p2p; // P2P cache sharing
+mod quota; // Upload size limits and daily quotas
+mod rate_limit; // Per-client rate limiting (Layer 2)
 mod recipe; // Standard recipes (script caching with KDL annotations)
 mod recipe_portable; // Portable recipes (QuickJS/JavaScript)
+mod retry; // Retries with exponential backoff of requests to upstreams
 mod storage;
+mod unix_socket; // Unix domain sockets of daemons (paths and Linux abstract names)
+mod upstream_routing; // Per-upstream routing rules by artifact namespace
 mod xcode;
 mod xdg;
 
 use anyhow::Result;
 use clap::Parser;
+use std::process::ExitCode;
 
 use cli::{Cli, Commands};
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     // Initialize structured logging
     logging::init();
 
-    // Parse CLI arguments
+    // Parse CLI arguments (invalid arguments exit with the usage code, 2)
     let cli = Cli::parse();
+    config_discovery::select_profile(cli.profile);
+    cli_utils::set_output_format(cli.output_format);
 
-    // Dispatch to appropriate command handler
-    match cli.command {
+    // Failures exit with the code of their category (see cli_utils::ErrorCategory)
+    match run(cli.command).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => cli_utils::report_error(&e),
+    }
+}
+
+/// Dispatch to appropriate command handler
+async fn run(command: Commands) -> Result<()> {
+    match command {
         Commands::Activate(args) => commands::activate::run(args),
         Commands::Exec(args) => commands::exec::run(args).await,
         Commands::Daemon(args) => commands::daemon::run(args).await,
@@ -40,15 +72,49 @@ async fn main() -> Result<()> {
         Commands::Server(args) => commands::server::run(*args).await,
         Commands::Config(args) => commands::config::run(args.command),
         Commands::Health(args) => commands::health::run(args),
-        Commands::Doctor(args) => commands::doctor::run(args),
+        Commands::Doctor(args) => commands::doctor::run(args).await,
         Commands::Init(args) => commands::init::run(args),
         Commands::Run(args) => commands::run::run(&args).await,
-        Commands::Cache(_args) => commands::cache::cache_deprecated().await,
+        Commands::Cache(args) => match args.command {
+            cli::CacheCommands::Compact { json } => {
+                commands::cache::compact(
+                    args.config_cache_dir.as_deref(),
+                    cli_utils::json_output(json),
+                )
+                .await
+            }
+            cli::CacheCommands::Migrate { from, to, json } => {
+                commands::cache::migrate(&from, &to, cli_utils::json_output(json)).await
+            }
+            cli::CacheCommands::Warm {
+                from_manifest,
+                jobs,
+                config,
+                json,
+            } => {
+                commands::cache::warm(
+                    args.config_cache_dir.as_deref(),
+                    &args.config_metadata_backend,
+                    args.config_embedded_max_objects,
+                    &from_manifest,
+                    jobs,
+                    config.as_deref(),
+                    cli_utils::json_output(json),
+                )
+                .await
+            }
+            _ => commands::cache::cache_deprecated().await,
+        },
         Commands::Cas(args) => commands::cas::run(&args).await,
         Commands::Kv(args) => commands::kv::run(&args).await,
+        Commands::Stats(args) => commands::stats::run(&args).await,
+        Commands::Top(args) => commands::top::run(args),
         Commands::P2p(args) => commands::p2p::run(args).await,
+        Commands::Completions(args) => commands::completions::completions(args),
+        Commands::Man(args) => commands::completions::man(args),
+        Commands::Complete(args) => commands::complete::run(args),
         Commands::Auth(args) => {
-            use cli::AuthCommand;
+            use cli::{AuthCommand, AuthTokenCommand};
             use config_discovery::load_config_with_discovery;
 
             // Load config with auto-discovery
@@ -59,7 +125,12 @@ async fn main() -> Result<()> {
                 AuthCommand::Login(_) => commands::auth::login(config).await,
                 AuthCommand::Logout(_) => commands::auth::logout(config).await,
                 AuthCommand::Status(_) => commands::auth::status(config).await,
-                AuthCommand::Token(_) => commands::auth::token(config).await,
+                AuthCommand::Token(token_args) => match &token_args.command {
+                    None => commands::auth::token(config).await,
+                    Some(AuthTokenCommand::Create(create_args)) => {
+                        commands::auth::create_token(config, create_args).await
+                    }
+                },
             }
         }
     }
@@ -6,7 +6,9 @@
 /// 3. Configuration file
 /// 4. Built-in defaults (lowest priority)
 use crate::cli::{ExecArgs, ServerArgs};
-use crate::config::FabrikConfig;
+use crate::config::{FabrikConfig, LimitsConfig};
+use crate::hashing::HashAlgorithm;
+use crate::rate_limit::RateLimitConfig;
 
 /// Merged configuration for exec/daemon commands
 #[derive(Debug, Clone)]
@@ -21,13 +23,17 @@ pub struct MergedExecConfig {
     pub jwt_token: Option<String>,
     pub http_port: u16,
     pub grpc_port: u16,
-    pub s3_port: u16,
+    pub s3_port: Option<u16>,
     pub build_systems: Vec<String>,
     pub write_through: bool,
     pub read_through: bool,
     pub offline: bool,
+    pub read_only: bool,
+    pub hash_algorithm: HashAlgorithm,
     pub log_level: String,
     pub metrics_port: u16,
+    pub build_metadata: bool,
+    pub build_metadata_file: Option<String>,
 }
 
 /// Merged configuration for server command
@@ -49,15 +55,21 @@ pub struct MergedServerConfig {
     pub jwt_public_key: Option<String>,
     pub jwt_jwks_url: Option<String>,
     pub jwt_key_refresh: String,
+    pub jwt_key_grace_period: String,
     pub jwt_required: bool,
+    pub jwt_default_scopes: Vec<String>,
+    pub jwt_anonymous_scopes: Vec<String>,
     pub eviction_policy: String,
     pub default_ttl: String,
     pub write_through: bool,
+    pub read_only: bool,
+    pub hash_algorithm: HashAlgorithm,
     pub upstream_workers: u32,
     pub log_level: String,
     pub log_format: String,
     pub health_bind: String,
     pub health_enabled: bool,
+    pub readiness_check_upstreams: bool,
     pub api_bind: String,
     pub metrics_enabled: bool,
     pub cache_query_api_enabled: bool,
@@ -67,6 +79,8 @@ pub struct MergedServerConfig {
     pub tracing_enabled: bool,
     pub tracing_endpoint: Option<String>,
     pub graceful_shutdown: String,
+    pub rate_limit: RateLimitConfig,
+    pub limits: LimitsConfig,
 }
 
 impl MergedExecConfig {
@@ -101,7 +115,7 @@ impl MergedExecConfig {
             }),
             http_port: args.config_http_port.unwrap_or(0),
             grpc_port: args.config_grpc_port.unwrap_or(0),
-            s3_port: args.config_s3_port.unwrap_or(0),
+            s3_port: args.config_s3_port.or(file.daemon.s3_port),
             build_systems: args
                 .config_build_systems
                 .clone()
@@ -109,11 +123,20 @@ impl MergedExecConfig {
             write_through: args.config_write_through,
             read_through: args.config_read_through,
             offline: args.config_offline,
+            read_only: args.config_read_only || file.cache.read_only,
+            hash_algorithm: args
+                .config_hash_algorithm
+                .unwrap_or(file.cache.hash_algorithm),
             log_level: args
                 .config_log_level
                 .clone()
                 .unwrap_or_else(|| file.observability.log_level.clone()),
             metrics_port: args.config_metrics_port.unwrap_or(0),
+            build_metadata: args.config_build_metadata || file.observability.build_metadata,
+            build_metadata_file: args
+                .config_build_metadata_file
+                .clone()
+                .or_else(|| file.observability.build_metadata_file.clone()),
         }
     }
 }
@@ -161,14 +184,29 @@ impl MergedServerConfig {
             grpc_bind: args.config_grpc_bind.clone(),
             s3_bind: args.config_s3_bind.clone(),
             fabrik_bind: args.config_fabrik_bind.clone(),
-            jwt_public_key_file: args.config_jwt_public_key_file.clone(),
-            jwt_public_key: args.config_jwt_public_key.clone(),
-            jwt_jwks_url: args.config_jwt_jwks_url.clone(),
+            jwt_public_key_file: args
+                .config_jwt_public_key_file
+                .clone()
+                .or_else(|| file.auth.public_key_file.clone()),
+            jwt_public_key: args
+                .config_jwt_public_key
+                .clone()
+                .or_else(|| file.auth.public_key.clone()),
+            jwt_jwks_url: args
+                .config_jwt_jwks_url
+                .clone()
+                .or_else(|| file.auth.jwks_url.clone()),
             jwt_key_refresh: args
                 .config_jwt_key_refresh
                 .clone()
                 .unwrap_or_else(|| file.auth.key_refresh_interval.clone()),
+            jwt_key_grace_period: args
+                .config_jwt_key_grace_period
+                .clone()
+                .unwrap_or_else(|| file.auth.key_grace_period.clone()),
             jwt_required: args.config_jwt_required.unwrap_or(file.auth.required),
+            jwt_default_scopes: file.auth.default_scopes.clone(),
+            jwt_anonymous_scopes: file.auth.anonymous_scopes.clone(),
             eviction_policy: args
                 .config_eviction_policy
                 .clone()
@@ -178,6 +216,10 @@ impl MergedServerConfig {
                 .clone()
                 .unwrap_or_else(|| file.cache.default_ttl.clone()),
             write_through: args.config_write_through,
+            read_only: args.config_read_only || file.cache.read_only,
+            hash_algorithm: args
+                .config_hash_algorithm
+                .unwrap_or(file.cache.hash_algorithm),
             upstream_workers: args.config_upstream_workers.unwrap_or(10),
             log_level: args
                 .config_log_level
@@ -194,6 +236,7 @@ impl MergedServerConfig {
             health_enabled: args
                 .config_health_enabled
                 .unwrap_or(file.observability.health_enabled),
+            readiness_check_upstreams: file.observability.readiness_check_upstreams,
             api_bind: args
                 .config_api_bind
                 .clone()
@@ -225,6 +268,28 @@ impl MergedServerConfig {
                 .config_graceful_shutdown
                 .clone()
                 .unwrap_or_else(|| file.runtime.graceful_shutdown_timeout.clone()),
+            rate_limit: RateLimitConfig {
+                requests_per_second: args
+                    .config_rate_limit_per_client
+                    .unwrap_or(file.runtime.rate_limit_per_client),
+                burst: args
+                    .config_rate_limit_burst
+                    .unwrap_or(file.runtime.rate_limit_burst),
+                max_in_flight: args
+                    .config_max_in_flight_per_client
+                    .unwrap_or(file.runtime.max_in_flight_per_client),
+            },
+            limits: LimitsConfig {
+                max_artifact_size: args
+                    .config_max_artifact_size
+                    .clone()
+                    .or_else(|| file.limits.max_artifact_size.clone()),
+                max_artifact_size_by_protocol: file.limits.max_artifact_size_by_protocol.clone(),
+                daily_upload_quota: args
+                    .config_daily_upload_quota
+                    .clone()
+                    .or_else(|| file.limits.daily_upload_quota.clone()),
+            },
         }
     }
 }
@@ -0,0 +1,504 @@
+//! Webhook notifications of significant cache events (`[notifications]`)
+//!
+//! Alerting without a Prometheus stack: daemons and servers POST a JSON payload to
+//! `webhook_url` when the cache is nearly full, during eviction storms, when pushes to
+//! other regions fail, and on authentication errors. Each event is sent at most once
+//! per `cooldown`, so a condition that persists doesn't flood the receiver.
+//!
+//! With a `secret`, payloads are signed with HMAC-SHA256 of the raw body, sent as
+//! `X-Fabrik-Signature: sha256=<hex>`. Receivers recompute it to check the payload came
+//! from Fabrik, and can reject stale `timestamp`s to stop replays.
+
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+use crate::config::NotificationsConfig;
+use crate::events::{CacheEvent, EventBus, EventKind};
+use crate::eviction::EvictionConfig;
+use crate::retry::{Protocol, RetryPolicy};
+use crate::storage::Storage;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header carrying the HMAC-SHA256 signature of the payload
+pub const SIGNATURE_HEADER: &str = "x-fabrik-signature";
+
+/// Header naming the event of the payload
+pub const EVENT_HEADER: &str = "x-fabrik-event";
+
+/// Timeout of webhook requests
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the cache size is compared to `nearly_full_ratio`
+const SIZE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Window evictions are counted in for `eviction_storm_threshold`
+const EVICTION_WINDOW: Duration = Duration::from_secs(60);
+
+/// Events notified about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// The cache reached `nearly_full_ratio` of its max_size
+    CacheNearlyFull,
+    /// More than `eviction_storm_threshold` objects were evicted within a minute
+    EvictionStorm,
+    /// Pushing an artifact to another region failed
+    UpstreamFailure,
+    /// A request was rejected for its credentials, or the JWT keys couldn't be reloaded
+    AuthError,
+}
+
+impl NotificationKind {
+    pub const ALL: [NotificationKind; 4] = [
+        Self::CacheNearlyFull,
+        Self::EvictionStorm,
+        Self::UpstreamFailure,
+        Self::AuthError,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CacheNearlyFull => "cache_nearly_full",
+            Self::EvictionStorm => "eviction_storm",
+            Self::UpstreamFailure => "upstream_failure",
+            Self::AuthError => "auth_error",
+        }
+    }
+}
+
+impl std::str::FromStr for NotificationKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s.to_lowercase())
+            .with_context(|| {
+                format!(
+                    "Unknown notification event '{}' (expected cache_nearly_full, \
+                     eviction_storm, upstream_failure or auth_error)",
+                    s
+                )
+            })
+    }
+}
+
+/// Payload POSTed to the webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub event: NotificationKind,
+    /// When it happened (Unix seconds)
+    pub timestamp: i64,
+    /// Machine the daemon or server runs on
+    pub host: String,
+    /// Human-readable summary, e.g. for chat channels
+    pub message: String,
+    /// Event-specific fields
+    pub details: serde_json::Value,
+}
+
+/// Which events are notified, and when they count as happening
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotificationRules {
+    pub events: Vec<NotificationKind>,
+    pub nearly_full_ratio: f64,
+    pub eviction_storm_threshold: u64,
+    pub cooldown: Duration,
+}
+
+impl NotificationRules {
+    pub fn from_config(config: &NotificationsConfig) -> Result<Self> {
+        let events = config
+            .events
+            .iter()
+            .map(|event| event.parse())
+            .collect::<Result<Vec<_>>>()
+            .context("Invalid notifications.events")?;
+        if config.nearly_full_ratio.is_nan() || config.nearly_full_ratio <= 0.0 {
+            anyhow::bail!(
+                "notifications.nearly_full_ratio must be positive, got {}",
+                config.nearly_full_ratio
+            );
+        }
+        if config.eviction_storm_threshold == 0 {
+            anyhow::bail!("notifications.eviction_storm_threshold must be at least 1");
+        }
+        let cooldown = EvictionConfig::parse_ttl(&config.cooldown)
+            .context("Invalid notifications.cooldown")?;
+        if let Some(url) = &config.webhook_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                anyhow::bail!(
+                    "notifications.webhook_url must start with http:// or https://: {}",
+                    url
+                );
+            }
+        }
+
+        Ok(Self {
+            events,
+            nearly_full_ratio: config.nearly_full_ratio,
+            eviction_storm_threshold: config.eviction_storm_threshold,
+            cooldown: Duration::from_secs(cooldown),
+        })
+    }
+}
+
+/// Sends notifications to the configured webhook
+pub struct Notifier {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+    rules: NotificationRules,
+    host: String,
+    retry: RetryPolicy,
+    /// When each event was last sent, for the cooldown
+    last_sent: Mutex<HashMap<NotificationKind, Instant>>,
+}
+
+impl Notifier {
+    /// Notifier of `[notifications]`, None without a webhook_url
+    pub fn from_config(config: &NotificationsConfig) -> Result<Option<Self>> {
+        let rules = NotificationRules::from_config(config)?;
+        let Some(url) = config.webhook_url.clone() else {
+            return Ok(None);
+        };
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("Failed to create HTTP client")?;
+        let host = hostname::get()
+            .ok()
+            .and_then(|h| h.into_string().ok())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        Ok(Some(Self {
+            client,
+            url,
+            secret: config.secret.clone(),
+            rules,
+            host,
+            retry: RetryPolicy::default(),
+            last_sent: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Retry failed deliveries with `retry` (`[retry]` defaults otherwise)
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Send a notification in the background, unless its event isn't enabled or was
+    /// sent within the cooldown
+    pub fn notify(&self, kind: NotificationKind, message: String, details: serde_json::Value) {
+        if !self.should_send(kind, Instant::now()) {
+            debug!("Not sending {} notification: {}", kind.as_str(), message);
+            return;
+        }
+
+        let (body, signature) = self.payload(kind, message, details);
+        let client = self.client.clone();
+        let url = self.url.clone();
+        let retry = self.retry.clone();
+        tokio::spawn(async move {
+            let result = retry
+                .http(Protocol::Http, || {
+                    let request = client
+                        .post(&url)
+                        .header("content-type", "application/json")
+                        .header(EVENT_HEADER, kind.as_str())
+                        .body(body.clone());
+                    match &signature {
+                        Some(signature) => request.header(SIGNATURE_HEADER, signature),
+                        None => request,
+                    }
+                    .send()
+                })
+                .await;
+            match result {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Sent {} notification", kind.as_str())
+                }
+                Ok(response) => warn!(
+                    "Webhook rejected {} notification: HTTP {}",
+                    kind.as_str(),
+                    response.status().as_u16()
+                ),
+                Err(e) => warn!("Failed to send {} notification: {}", kind.as_str(), e),
+            }
+        });
+    }
+
+    /// JSON body of a notification, and its signature with a `secret`
+    fn payload(
+        &self,
+        kind: NotificationKind,
+        message: String,
+        details: serde_json::Value,
+    ) -> (Vec<u8>, Option<String>) {
+        let notification = Notification {
+            event: kind,
+            timestamp: chrono::Utc::now().timestamp(),
+            host: self.host.clone(),
+            message,
+            details,
+        };
+        let body = serde_json::to_vec(&notification).expect("notifications serialize to JSON");
+        let signature = self.secret.as_deref().map(|secret| sign(secret, &body));
+        (body, signature)
+    }
+
+    fn should_send(&self, kind: NotificationKind, now: Instant) -> bool {
+        if !self.rules.events.contains(&kind) {
+            return false;
+        }
+        let mut last_sent = self.last_sent.lock().unwrap();
+        if let Some(sent) = last_sent.get(&kind) {
+            if now.duration_since(*sent) < self.rules.cooldown {
+                return false;
+            }
+        }
+        last_sent.insert(kind, now);
+        true
+    }
+
+    /// Watch the cache in the background: its size against `max_size_bytes` (0 for
+    /// caches that are never evicted), and the evictions and failed pushes published on
+    /// `events`
+    pub fn spawn_monitor<S: Storage + 'static>(
+        self: Arc<Self>,
+        storage: Arc<S>,
+        max_size_bytes: u64,
+        events: &EventBus,
+    ) -> tokio::task::JoinHandle<()> {
+        let mut events = events.subscribe();
+        tokio::spawn(async move {
+            let mut size_check = tokio::time::interval(SIZE_CHECK_INTERVAL);
+            let mut evictions = EvictionWindow::new(Instant::now());
+            loop {
+                tokio::select! {
+                    _ = size_check.tick() => {
+                        if max_size_bytes > 0 {
+                            self.check_size(storage.as_ref(), max_size_bytes);
+                        }
+                    }
+                    event = events.recv() => match event {
+                        Ok(event) => self.observe(&event, &mut evictions, Instant::now()),
+                        // Eviction storms are still counted from the events that arrive
+                        Err(broadcast::error::RecvError::Lagged(_)) => {}
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                }
+            }
+        })
+    }
+
+    fn check_size<S: Storage>(&self, storage: &S, max_size_bytes: u64) {
+        let bytes = match storage.stats() {
+            Ok(stats) => stats.total_bytes,
+            Err(e) => {
+                warn!("Failed to read the cache size: {}", e);
+                return;
+            }
+        };
+        let ratio = bytes as f64 / max_size_bytes as f64;
+        if ratio >= self.rules.nearly_full_ratio {
+            self.notify(
+                NotificationKind::CacheNearlyFull,
+                format!(
+                    "Cache is {:.0}% full ({} of {} bytes)",
+                    ratio * 100.0,
+                    bytes,
+                    max_size_bytes
+                ),
+                serde_json::json!({
+                    "bytes": bytes,
+                    "max_bytes": max_size_bytes,
+                    "ratio": ratio,
+                }),
+            );
+        }
+    }
+
+    fn observe(&self, event: &CacheEvent, evictions: &mut EvictionWindow, now: Instant) {
+        match event.kind {
+            EventKind::Evict => {
+                let count = evictions.record(now);
+                if count == self.rules.eviction_storm_threshold {
+                    self.notify(
+                        NotificationKind::EvictionStorm,
+                        format!(
+                            "{} objects evicted within {} seconds",
+                            count,
+                            EVICTION_WINDOW.as_secs()
+                        ),
+                        serde_json::json!({
+                            "evicted_objects": count,
+                            "window_seconds": EVICTION_WINDOW.as_secs(),
+                        }),
+                    );
+                }
+            }
+            EventKind::UpstreamSync => {
+                let (Some(upstream), Some(error)) = (&event.upstream, &event.error) else {
+                    return;
+                };
+                self.notify(
+                    NotificationKind::UpstreamFailure,
+                    format!("Failed to push {} to {}: {}", event.hash, upstream, error),
+                    serde_json::json!({
+                        "upstream": upstream,
+                        "hash": event.hash,
+                        "error": error,
+                    }),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Evictions of the current window
+struct EvictionWindow {
+    started: Instant,
+    count: u64,
+}
+
+impl EvictionWindow {
+    fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            count: 0,
+        }
+    }
+
+    /// Count an eviction, returning the evictions of the window so far
+    fn record(&mut self, now: Instant) -> u64 {
+        if now.duration_since(self.started) >= EVICTION_WINDOW {
+            *self = Self::new(now);
+        }
+        self.count += 1;
+        self.count
+    }
+}
+
+/// `X-Fabrik-Signature` value of a payload: `sha256=` and the hex-encoded HMAC-SHA256
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(url: &str) -> NotificationsConfig {
+        NotificationsConfig {
+            webhook_url: Some(url.to_string()),
+            secret: Some("webhook-secret".to_string()),
+            eviction_storm_threshold: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules = NotificationRules::from_config(&NotificationsConfig::default()).unwrap();
+        assert_eq!(rules.events, NotificationKind::ALL);
+        assert_eq!(rules.cooldown, Duration::from_secs(15 * 60));
+
+        let mut config = NotificationsConfig {
+            events: vec!["auth_error".to_string(), "bogus".to_string()],
+            ..Default::default()
+        };
+        assert!(NotificationRules::from_config(&config).is_err());
+        config.events = vec![];
+        config.webhook_url = Some("hooks.example.com".to_string());
+        assert!(NotificationRules::from_config(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cooldown_and_filter() {
+        let mut config = config("http://127.0.0.1:1/hook");
+        config.events = vec!["eviction_storm".to_string()];
+        let notifier = Notifier::from_config(&config).unwrap().unwrap();
+        let now = Instant::now();
+
+        assert!(!notifier.should_send(NotificationKind::AuthError, now));
+        assert!(notifier.should_send(NotificationKind::EvictionStorm, now));
+        assert!(!notifier.should_send(
+            NotificationKind::EvictionStorm,
+            now + Duration::from_secs(60)
+        ));
+        assert!(notifier.should_send(
+            NotificationKind::EvictionStorm,
+            now + Duration::from_secs(15 * 60)
+        ));
+    }
+
+    #[test]
+    fn test_eviction_window() {
+        let start = Instant::now();
+        let mut window = EvictionWindow::new(start);
+        assert_eq!(window.record(start), 1);
+        assert_eq!(window.record(start + Duration::from_secs(59)), 2);
+        assert_eq!(window.record(start + Duration::from_secs(61)), 1);
+    }
+
+    #[test]
+    fn test_signed_payload() {
+        let notifier = Notifier::from_config(&config("http://127.0.0.1:1/hook"))
+            .unwrap()
+            .unwrap();
+        let (body, signature) = notifier.payload(
+            NotificationKind::EvictionStorm,
+            "3 objects evicted".to_string(),
+            serde_json::json!({ "evicted_objects": 3 }),
+        );
+
+        let mut mac = HmacSha256::new_from_slice(b"webhook-secret").unwrap();
+        mac.update(&body);
+        let expected = hex::decode(signature.unwrap().strip_prefix("sha256=").unwrap()).unwrap();
+        assert!(mac.verify_slice(&expected).is_ok());
+
+        let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(payload["event"], "eviction_storm");
+        assert_eq!(payload["message"], "3 objects evicted");
+        assert_eq!(payload["details"]["evicted_objects"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_eviction_storm() {
+        let notifier = Notifier::from_config(&config("http://127.0.0.1:1/hook"))
+            .unwrap()
+            .unwrap();
+        let start = Instant::now();
+        let mut evictions = EvictionWindow::new(start);
+        for id in 0u8..2 {
+            let event = CacheEvent::new(EventKind::Evict, &[id], Some(10));
+            notifier.observe(&event, &mut evictions, start);
+        }
+        assert!(notifier.should_send(NotificationKind::EvictionStorm, start));
+
+        let notifier = Notifier::from_config(&config("http://127.0.0.1:1/hook"))
+            .unwrap()
+            .unwrap();
+        let mut evictions = EvictionWindow::new(start);
+        for id in 0u8..3 {
+            let event = CacheEvent::new(EventKind::Evict, &[id], Some(10));
+            notifier.observe(&event, &mut evictions, start);
+        }
+        // Sent, so the cooldown holds back the next one
+        assert!(!notifier.should_send(NotificationKind::EvictionStorm, start));
+    }
+}
@@ -1,6 +1,8 @@
 use anyhow::{anyhow, Result};
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 type HmacSha256 = Hmac<Sha256>;
@@ -8,6 +10,15 @@ type HmacSha256 = Hmac<Sha256>;
 /// Maximum allowed time skew for replay protection (5 minutes)
 const MAX_TIME_SKEW_SECS: u64 = 300;
 
+/// Signed subject for namespace listing requests (in place of an artifact hash)
+pub const LIST_NAMESPACES_SUBJECT: &str = "namespaces";
+
+/// Signed subject for content digest requests
+pub const DIGEST_SUBJECT: &str = "digest";
+
+/// Signed subject for throughput benchmark requests
+pub const BENCH_SUBJECT: &str = "bench";
+
 /// Compute HMAC-SHA256 signature
 #[allow(dead_code)] // Used in tests and will be used for future P2P features
 pub fn compute_signature(secret: &str, message: &str) -> Vec<u8> {
@@ -51,23 +62,70 @@ pub fn verify_timestamp(timestamp: i64) -> Result<()> {
     Ok(())
 }
 
-/// Sign a request with hash and timestamp
+/// Generate a random request nonce (replay protection)
+pub fn generate_nonce() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+/// Sign a request with hash, timestamp and nonce
 #[allow(dead_code)] // Will be used when P2P client is fully integrated
-pub fn sign_request(secret: &str, hash: &str, timestamp: i64) -> Vec<u8> {
-    let message = format!("{}:{}", hash, timestamp);
+pub fn sign_request(secret: &str, hash: &str, timestamp: i64, nonce: &str) -> Vec<u8> {
+    let message = format!("{}:{}:{}", hash, timestamp, nonce);
     compute_signature(secret, &message)
 }
 
 /// Verify a request signature
-pub fn verify_request(secret: &str, hash: &str, timestamp: i64, signature: &[u8]) -> Result<()> {
+pub fn verify_request(
+    secret: &str,
+    hash: &str,
+    timestamp: i64,
+    nonce: &str,
+    signature: &[u8],
+) -> Result<()> {
     // First verify timestamp (replay protection)
     verify_timestamp(timestamp)?;
 
     // Then verify signature
-    let message = format!("{}:{}", hash, timestamp);
+    let message = format!("{}:{}:{}", hash, timestamp, nonce);
     verify_signature(secret, &message, signature)
 }
 
+/// Rejects requests whose nonce has already been seen
+///
+/// Timestamps alone leave a five minute window in which a captured request can be
+/// replayed. Nonces are remembered for as long as their timestamp is accepted, so any
+/// replay is caught either here or by the timestamp check.
+#[derive(Debug, Default)]
+pub struct ReplayGuard {
+    seen: Mutex<HashMap<String, i64>>,
+}
+
+impl ReplayGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a nonce, failing if it is missing or was already used
+    ///
+    /// Call only after the request signature has been verified, so forged requests
+    /// can't fill the cache.
+    pub fn check(&self, nonce: &str, timestamp: i64) -> Result<()> {
+        if nonce.is_empty() {
+            return Err(anyhow!("Missing request nonce"));
+        }
+
+        let now = current_timestamp();
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, ts| (now - *ts).unsigned_abs() <= MAX_TIME_SKEW_SECS);
+
+        if seen.contains_key(nonce) {
+            return Err(anyhow!("Replayed request (nonce already used)"));
+        }
+        seen.insert(nonce.to_string(), timestamp);
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -106,8 +164,13 @@ mod tests {
         let hash = "abc123def456";
         let timestamp = current_timestamp();
 
-        let signature = sign_request(secret, hash, timestamp);
-        assert!(verify_request(secret, hash, timestamp, &signature).is_ok());
+        let nonce = generate_nonce();
+
+        let signature = sign_request(secret, hash, timestamp, &nonce);
+        assert!(verify_request(secret, hash, timestamp, &nonce, &signature).is_ok());
+
+        // The nonce is covered by the signature
+        assert!(verify_request(secret, hash, timestamp, "other", &signature).is_err());
     }
 
     #[test]
@@ -116,7 +179,29 @@ mod tests {
         let hash = "abc123def456";
         let old_timestamp = current_timestamp() - 400; // 6 minutes ago
 
-        let signature = sign_request(secret, hash, old_timestamp);
-        assert!(verify_request(secret, hash, old_timestamp, &signature).is_err());
+        let signature = sign_request(secret, hash, old_timestamp, "nonce");
+        assert!(verify_request(secret, hash, old_timestamp, "nonce", &signature).is_err());
+    }
+
+    #[test]
+    fn test_replay_guard_rejects_reused_nonce() {
+        let guard = ReplayGuard::new();
+        let timestamp = current_timestamp();
+
+        assert!(guard.check("nonce-1", timestamp).is_ok());
+        assert!(guard.check("nonce-1", timestamp).is_err());
+        assert!(guard.check("nonce-2", timestamp).is_ok());
+        assert!(guard.check("", timestamp).is_err());
+    }
+
+    #[test]
+    fn test_replay_guard_forgets_expired_nonces() {
+        let guard = ReplayGuard::new();
+        guard
+            .check("old", current_timestamp() - MAX_TIME_SKEW_SECS as i64 - 1)
+            .unwrap();
+        guard.check("new", current_timestamp()).unwrap();
+
+        assert_eq!(guard.seen.lock().unwrap().len(), 1);
     }
 }
@@ -1,42 +1,90 @@
 #![allow(dead_code)] // P2P feature not fully integrated yet
+use crate::bandwidth::Throttle;
 use crate::config::P2PConfig;
+use crate::error::{FabrikError, Result, ResultExt, RpcResultExt};
+use crate::grpc_transport::GrpcTransport;
 use crate::p2p::auth;
 use crate::p2p::proto::p2p_cache_client::P2pCacheClient as GrpcP2pCacheClient;
-use crate::p2p::proto::{ExistsRequest, GetRequest};
-use crate::p2p::Peer;
-use anyhow::{anyhow, Context, Result};
+use crate::p2p::proto::{
+    BenchRequest, DigestRequest, ExistsRequest, GetRequest, HelloRequest, ListNamespacesRequest,
+    ListNamespacesResponse,
+};
+use crate::p2p::relay::RelayClient;
+use crate::p2p::selection::{ContentDigest, PeerSelector};
+use crate::p2p::stats::{BenchStats, PeerStatsStore};
+use crate::p2p::transport;
+use crate::p2p::{Peer, PeerInfo};
 use bytes::Bytes;
+use hyper_util::rt::TokioIo;
+use std::io;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
-use tonic::transport::Channel;
+use std::time::{Duration, Instant, SystemTime};
+use tonic::transport::{Channel, Endpoint};
 
 /// P2P client for fetching artifacts from peers
 pub struct P2PClient {
     config: Arc<P2PConfig>,
     machine_id: String,
     hostname: String,
+    selector: Arc<PeerSelector>,
+    stats: Arc<PeerStatsStore>,
+    relay: Option<Arc<RelayClient>>,
+    transport: GrpcTransport,
+    /// Limit of the bytes fetched from peers (`max_download_rate`)
+    download_throttle: Option<Arc<Throttle>>,
 }
 
 impl P2PClient {
     /// Create a new P2P client
-    pub fn new(config: Arc<P2PConfig>) -> Self {
+    pub fn new(config: Arc<P2PConfig>, transport: GrpcTransport) -> Self {
         let machine_id = Self::get_machine_id().unwrap_or_else(|_| "unknown".to_string());
         let hostname = hostname::get()
             .map(|h| h.to_string_lossy().to_string())
             .unwrap_or_else(|_| "unknown".to_string());
 
+        let selector = Arc::new(PeerSelector::new(config.max_fanout));
+        let stats = Arc::new(PeerStatsStore::open());
+
+        let relay = config.relay_url.as_ref().and_then(|_| {
+            RelayClient::new(&config, machine_id.clone(), hostname.clone(), transport)
+                .inspect_err(|e| tracing::warn!("P2P relay disabled: {:#}", e))
+                .ok()
+                .map(Arc::new)
+        });
+
+        let download_throttle = Throttle::from_config(config.max_download_rate.as_deref())
+            .inspect_err(|e| tracing::warn!("P2P download rate not limited: {:#}", e))
+            .ok()
+            .flatten();
+
         Self {
             config,
             machine_id,
             hostname,
+            selector,
+            stats,
+            relay,
+            transport,
+            download_throttle,
         }
     }
 
-    /// Fetch artifact from peers (races all peers in parallel)
+    /// Fetch artifact from peers
+    ///
+    /// Only the best-ranked peers (likely to have the artifact, lowest latency) are
+    /// queried, in parallel; the first to answer wins.
     #[allow(dead_code)] // Will be used when integrated with daemon storage layer
     pub async fn fetch_from_peers(&self, peers: &[Peer], hash: &str) -> Result<Bytes> {
         if peers.is_empty() {
-            return Err(anyhow!("No peers available"));
+            return Err(FabrikError::unavailable("No peers available"));
+        }
+
+        let peers = self.selector.select(peers, hash);
+        if peers.is_empty() {
+            return Err(FabrikError::not_found(
+                "No peer is likely to have this artifact",
+            ));
         }
 
         tracing::info!(
@@ -47,7 +95,7 @@ impl P2PClient {
 
         let (tx, mut rx) = tokio::sync::mpsc::channel::<Result<(String, Bytes)>>(peers.len());
 
-        // Query all peers in parallel
+        // Query selected peers in parallel
         for peer in peers {
             let tx = tx.clone();
             let hash = hash.to_string();
@@ -75,66 +123,424 @@ impl P2PClient {
             return Ok(data);
         }
 
-        Err(anyhow!("All P2P peers failed or timed out"))
+        Err(FabrikError::unavailable(
+            "All P2P peers failed or timed out",
+        ))
     }
 
-    /// Fetch artifact from a specific peer
-    async fn fetch_from_peer(&self, peer: &Peer, hash: &str) -> Result<Bytes> {
-        // Connect to peer with timeout
-        let endpoint = peer.endpoint();
-        let timeout = Duration::from_secs(
+    /// List a peer's shareable cache namespaces (subject to the peer's consent)
+    pub async fn list_namespaces(&self, peer: &Peer) -> Result<ListNamespacesResponse> {
+        let mut client = self.connect(peer).await?;
+
+        let timestamp = auth::current_timestamp();
+        let nonce = auth::generate_nonce();
+        let request = ListNamespacesRequest {
+            timestamp,
+            signature: self.sign_request(auth::LIST_NAMESPACES_SUBJECT, timestamp, &nonce),
+            requester_id: self.machine_id.clone(),
+            requester_hostname: self.hostname.clone(),
+            nonce,
+        };
+
+        Ok(client
+            .list_namespaces(request)
+            .await
+            .rpc_context("ListNamespaces request failed")?
+            .into_inner())
+    }
+
+    /// Refresh latency measurements and content digests for `peers`
+    ///
+    /// Called periodically (gossip); peers that fail to answer sink in the ranking.
+    pub async fn refresh_peers(&self, peers: &[Peer]) {
+        self.selector.retain(peers);
+
+        let mut refreshes = tokio::task::JoinSet::new();
+        for peer in peers {
+            let peer = peer.clone();
+            let client = self.clone();
+
+            refreshes.spawn(async move {
+                if let Err(e) = client.refresh_peer(&peer).await {
+                    tracing::debug!("P2P refresh of {} failed: {}", peer.info.hostname, e);
+                    client.selector.record_failure(&peer.info.machine_id);
+                }
+            });
+        }
+        while refreshes.join_next().await.is_some() {}
+    }
+
+    async fn refresh_peer(&self, peer: &Peer) -> Result<()> {
+        let mut client = self.connect(peer).await?;
+
+        // Round-trip time of a Hello is the peer's latency
+        let timestamp = auth::current_timestamp();
+        let nonce = auth::generate_nonce();
+        let start = Instant::now();
+        client
+            .hello(HelloRequest {
+                machine_id: self.machine_id.clone(),
+                hostname: self.hostname.clone(),
+                version: "1.0".to_string(),
+                timestamp,
+                signature: self.sign_request(&self.machine_id, timestamp, &nonce),
+                nonce,
+            })
+            .await
+            .rpc_context("Hello request failed")?;
+        self.selector
+            .record_latency(&peer.info.machine_id, start.elapsed());
+
+        let timestamp = auth::current_timestamp();
+        let nonce = auth::generate_nonce();
+        let response = client
+            .get_digest(DigestRequest {
+                timestamp,
+                signature: self.sign_request(auth::DIGEST_SUBJECT, timestamp, &nonce),
+                requester_id: self.machine_id.clone(),
+                requester_hostname: self.hostname.clone(),
+                nonce,
+            })
+            .await
+            .rpc_context("GetDigest request failed")?
+            .into_inner();
+
+        if response.consent_required {
+            // Without a digest the peer is still asked, just ranked below likely peers
+            return Ok(());
+        }
+
+        let digest = ContentDigest::from_proto(response)
+            .ok_or_else(|| FabrikError::corrupt("Peer sent a malformed digest"))?;
+        tracing::debug!(
+            "P2P digest from {}: {} artifact(s), {} bytes",
+            peer.info.hostname,
+            digest.item_count(),
+            digest.size_bytes()
+        );
+        self.selector.update_digest(&peer.info.machine_id, digest);
+
+        Ok(())
+    }
+
+    /// Say Hello to the peer listening on `address` and learn who it is
+    ///
+    /// Used to health-check statically configured peers, whose machine ID and hostname
+    /// aren't known until they answer.
+    pub async fn probe(&self, address: SocketAddr) -> Result<PeerInfo> {
+        let peer = Peer::new(PeerInfo {
+            machine_id: String::new(),
+            hostname: address.ip().to_string(),
+            address: address.ip(),
+            port: address.port(),
+            last_seen: SystemTime::now(),
+            accepting_requests: true,
+        });
+        let mut client = self.connect(&peer).await?;
+
+        let timestamp = auth::current_timestamp();
+        let nonce = auth::generate_nonce();
+        let start = Instant::now();
+        let response = client
+            .hello(HelloRequest {
+                machine_id: self.machine_id.clone(),
+                hostname: self.hostname.clone(),
+                version: "1.0".to_string(),
+                timestamp,
+                signature: self.sign_request(&self.machine_id, timestamp, &nonce),
+                nonce,
+            })
+            .await
+            .rpc_context("Hello request failed")?
+            .into_inner();
+
+        if response.machine_id.is_empty() {
+            return Err(FabrikError::corrupt("Peer did not identify itself"));
+        }
+        self.selector
+            .record_latency(&response.machine_id, start.elapsed());
+
+        Ok(PeerInfo {
+            machine_id: response.machine_id,
+            hostname: response.hostname,
+            last_seen: SystemTime::now(),
+            accepting_requests: response.accepting_requests,
+            ..peer.info
+        })
+    }
+
+    /// Peer ranking state shared by this client's clones
+    pub fn selector(&self) -> Arc<PeerSelector> {
+        self.selector.clone()
+    }
+
+    /// Per-peer transfer statistics shared by this client's clones
+    pub fn stats(&self) -> Arc<PeerStatsStore> {
+        self.stats.clone()
+    }
+
+    /// Measure latency (median Hello round trip over `rounds`) and throughput (a
+    /// `size_bytes` transfer of filler bytes) to a peer
+    pub async fn bench(&self, peer: &Peer, size_bytes: u64, rounds: usize) -> Result<BenchStats> {
+        let mut client = self.connect(peer).await?;
+
+        let mut round_trips = Vec::with_capacity(rounds);
+        for _ in 0..rounds.max(1) {
+            let timestamp = auth::current_timestamp();
+            let nonce = auth::generate_nonce();
+            let start = Instant::now();
+            client
+                .hello(HelloRequest {
+                    machine_id: self.machine_id.clone(),
+                    hostname: self.hostname.clone(),
+                    version: "1.0".to_string(),
+                    timestamp,
+                    signature: self.sign_request(&self.machine_id, timestamp, &nonce),
+                    nonce,
+                })
+                .await
+                .rpc_context("Hello request failed")?;
+            round_trips.push(start.elapsed());
+        }
+        round_trips.sort();
+        let latency = round_trips[round_trips.len() / 2];
+
+        let timestamp = auth::current_timestamp();
+        let nonce = auth::generate_nonce();
+        let start = Instant::now();
+        let mut stream = client
+            .bench(BenchRequest {
+                size_bytes,
+                timestamp,
+                signature: self.sign_request(auth::BENCH_SUBJECT, timestamp, &nonce),
+                requester_id: self.machine_id.clone(),
+                requester_hostname: self.hostname.clone(),
+                nonce,
+            })
+            .await
+            .rpc_context("Bench request failed")?
+            .into_inner();
+
+        let mut received = 0u64;
+        while let Some(response) = stream
+            .message()
+            .await
+            .rpc_context("Bench transfer failed")?
+        {
+            if let Some(throttle) = &self.download_throttle {
+                throttle.consume(response.chunk.len()).await;
+            }
+            received += response.chunk.len() as u64;
+        }
+        let elapsed = start.elapsed();
+
+        if received == 0 {
+            return Err(FabrikError::corrupt("Peer sent no data"));
+        }
+
+        let bench = BenchStats {
+            latency_ms: latency.as_secs_f64() * 1000.0,
+            throughput_bytes_per_sec: received as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            measured_at: auth::current_timestamp(),
+        };
+        self.stats
+            .record_bench(&peer.info.machine_id, &peer.info.hostname, bench.clone());
+
+        Ok(bench)
+    }
+
+    /// Relay used to reach peers on other subnets, if configured
+    pub fn relay(&self) -> Option<Arc<RelayClient>> {
+        self.relay.clone()
+    }
+
+    /// Machine ID this client identifies itself with (used by peers for consent)
+    pub fn machine_id(&self) -> &str {
+        &self.machine_id
+    }
+
+    /// Connect to a peer with the configured request timeout
+    ///
+    /// The connection is encrypted with a key derived from the shared secret. Peers
+    /// registered with the relay are reached through it when a direct connection fails.
+    async fn connect(&self, peer: &Peer) -> Result<GrpcP2pCacheClient<Channel>> {
+        let machine_id = &peer.info.machine_id;
+        let relay = self
+            .relay
+            .as_ref()
+            .filter(|relay| relay.reaches(machine_id));
+
+        if let Some(relay) = relay.filter(|relay| relay.is_relayed(machine_id)) {
+            return self.connect_via_relay(relay, peer).await;
+        }
+
+        match self.connect_direct(peer).await {
+            Ok(client) => Ok(client),
+            Err(e) => match relay {
+                Some(relay) => {
+                    tracing::debug!(
+                        "Direct P2P connection to {} failed ({:#}), going through the relay",
+                        peer.display_name(),
+                        e
+                    );
+                    relay.mark_relayed(machine_id);
+                    self.connect_via_relay(relay, peer).await
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    async fn connect_direct(&self, peer: &Peer) -> Result<GrpcP2pCacheClient<Channel>> {
+        let timeout = self.request_timeout();
+        let psk = self.psk()?;
+
+        let endpoint = Endpoint::from_shared(peer.endpoint())
+            .map_err(|e| FabrikError::config(format!("Invalid endpoint: {}", e)))?
+            .timeout(timeout)
+            .connect_timeout(timeout);
+        let channel = self
+            .transport
+            .endpoint(endpoint)
+            .connect_with_connector(tower::service_fn(move |uri| {
+                transport::connect_uri(uri, psk)
+            }))
+            .await
+            .upstream_context("Failed to connect to peer")?;
+
+        Ok(GrpcP2pCacheClient::new(channel))
+    }
+
+    /// Connect to a peer through a relay tunnel (same Noise session as a direct connection)
+    async fn connect_via_relay(
+        &self,
+        relay: &Arc<RelayClient>,
+        peer: &Peer,
+    ) -> Result<GrpcP2pCacheClient<Channel>> {
+        let timeout = self.request_timeout();
+        let psk = self.psk()?;
+        let relay = relay.clone();
+        let machine_id = peer.info.machine_id.clone();
+
+        let endpoint = Endpoint::from_shared(peer.endpoint())
+            .map_err(|e| FabrikError::config(format!("Invalid endpoint: {}", e)))?
+            .timeout(timeout);
+        let channel = self
+            .transport
+            .endpoint(endpoint)
+            .connect_with_connector(tower::service_fn(move |_uri| {
+                let relay = relay.clone();
+                let machine_id = machine_id.clone();
+                async move {
+                    let tunnel = relay
+                        .tunnel_to(&machine_id)
+                        .await
+                        .map_err(io::Error::other)?;
+                    transport::connect(tunnel, &psk)
+                        .await
+                        .map(TokioIo::new)
+                        .map_err(io::Error::other)
+                }
+            }))
+            .await
+            .upstream_context("Failed to connect to peer through the relay")?;
+
+        Ok(GrpcP2pCacheClient::new(channel))
+    }
+
+    fn request_timeout(&self) -> Duration {
+        Duration::from_secs(
             self.config
                 .request_timeout
                 .trim_end_matches('s')
                 .parse()
                 .unwrap_or(5),
-        );
+        )
+    }
 
-        let channel = Channel::from_shared(endpoint.clone())
-            .context("Invalid endpoint")?
-            .timeout(timeout)
-            .connect()
-            .await
-            .context("Failed to connect to peer")?;
+    fn psk(&self) -> Result<[u8; 32]> {
+        let secret = self
+            .config
+            .secret
+            .as_ref()
+            .ok_or_else(|| FabrikError::config("P2P secret not configured"))?;
+        Ok(transport::derive_psk(secret))
+    }
+
+    /// Fetch artifact from a specific peer, recording the outcome in the peer statistics
+    async fn fetch_from_peer(&self, peer: &Peer, hash: &str) -> Result<Bytes> {
+        let (machine_id, hostname) = (&peer.info.machine_id, &peer.info.hostname);
+        match self.try_fetch_from_peer(peer, hash).await {
+            Ok(Some(data)) => {
+                self.stats
+                    .record_hit(machine_id, hostname, data.len() as u64);
+                Ok(data)
+            }
+            Ok(None) => {
+                self.stats.record_miss(machine_id, hostname);
+                Err(FabrikError::not_found("Artifact not found on peer"))
+            }
+            Err(e) => {
+                self.stats.record_failure(machine_id, hostname);
+                Err(e)
+            }
+        }
+    }
 
-        let mut client = GrpcP2pCacheClient::new(channel);
+    /// Fetch artifact from a specific peer (None if the peer doesn't have it)
+    async fn try_fetch_from_peer(&self, peer: &Peer, hash: &str) -> Result<Option<Bytes>> {
+        let machine_id = &peer.info.machine_id;
+        let mut client = self.connect(peer).await.inspect_err(|_| {
+            self.selector.record_failure(machine_id);
+        })?;
 
         // Check if artifact exists first
         let exists_req = self.create_exists_request(hash);
+        let start = Instant::now();
         let exists_resp = client
             .exists(exists_req)
             .await
-            .context("Exists request failed")?
+            .inspect_err(|_| self.selector.record_failure(machine_id))
+            .rpc_context("Exists request failed")?
             .into_inner();
+        self.selector.record_latency(machine_id, start.elapsed());
 
         if !exists_resp.found {
-            return Err(anyhow!("Artifact not found on peer"));
+            return Ok(None);
         }
 
         if exists_resp.consent_required {
-            return Err(anyhow!("Consent required but not granted"));
+            return Err(FabrikError::auth_failed("Consent required but not granted"));
         }
 
         // Fetch artifact
         let get_req = self.create_get_request(hash);
-        let mut stream = client.get(get_req).await?.into_inner();
+        let mut stream = client
+            .get(get_req)
+            .await
+            .rpc_context("Get request failed")?
+            .into_inner();
 
         let mut data = Vec::new();
-        while let Some(response) = stream.message().await? {
+        while let Some(response) = stream.message().await.rpc_context("Transfer failed")? {
             if response.consent_denied {
-                return Err(anyhow!("Consent denied by peer"));
+                return Err(FabrikError::auth_failed("Consent denied by peer"));
             }
 
+            if let Some(throttle) = &self.download_throttle {
+                throttle.consume(response.chunk.len()).await;
+            }
             data.extend_from_slice(&response.chunk);
         }
 
-        Ok(Bytes::from(data))
+        Ok(Some(Bytes::from(data)))
     }
 
     /// Create exists request with authentication
     fn create_exists_request(&self, hash: &str) -> ExistsRequest {
         let timestamp = auth::current_timestamp();
-        let signature = self.sign_request(hash, timestamp);
+        let nonce = auth::generate_nonce();
+        let signature = self.sign_request(hash, timestamp, &nonce);
 
         ExistsRequest {
             hash: hash.to_string(),
@@ -142,13 +548,15 @@ impl P2PClient {
             signature,
             requester_id: self.machine_id.clone(),
             requester_hostname: self.hostname.clone(),
+            nonce,
         }
     }
 
     /// Create get request with authentication
     fn create_get_request(&self, hash: &str) -> GetRequest {
         let timestamp = auth::current_timestamp();
-        let signature = self.sign_request(hash, timestamp);
+        let nonce = auth::generate_nonce();
+        let signature = self.sign_request(hash, timestamp, &nonce);
 
         GetRequest {
             hash: hash.to_string(),
@@ -156,17 +564,18 @@ impl P2PClient {
             signature,
             requester_id: self.machine_id.clone(),
             requester_hostname: self.hostname.clone(),
+            nonce,
         }
     }
 
     /// Sign a request
-    fn sign_request(&self, hash: &str, timestamp: i64) -> Vec<u8> {
+    fn sign_request(&self, hash: &str, timestamp: i64, nonce: &str) -> Vec<u8> {
         let secret = self
             .config
             .secret
             .as_ref()
             .expect("P2P secret must be configured");
-        auth::sign_request(secret, hash, timestamp)
+        auth::sign_request(secret, hash, timestamp, nonce)
     }
 
     fn get_machine_id() -> Result<String> {
@@ -194,7 +603,7 @@ impl P2PClient {
         }
 
         let hostname = hostname::get()
-            .context("Failed to get hostname")?
+            .io_context("Failed to get hostname")?
             .to_string_lossy()
             .to_string();
         let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
@@ -208,6 +617,11 @@ impl Clone for P2PClient {
             config: self.config.clone(),
             machine_id: self.machine_id.clone(),
             hostname: self.hostname.clone(),
+            selector: self.selector.clone(),
+            stats: self.stats.clone(),
+            relay: self.relay.clone(),
+            transport: self.transport,
+            download_throttle: self.download_throttle.clone(),
         }
     }
 }
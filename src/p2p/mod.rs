@@ -1,29 +1,41 @@
 /// P2P cache sharing module
 ///
 /// This module implements peer-to-peer cache sharing on local networks.
-/// It uses mDNS for discovery, gRPC for communication, HMAC for authentication,
-/// and system notifications for user consent.
+/// It uses mDNS for discovery (plus explicitly configured static peers and peers registered
+/// with a relay server), gRPC over a Noise-encrypted transport for communication, HMAC for
+/// request authentication, and system notifications for user consent.
 pub mod auth;
 pub mod client;
 pub mod consent;
 pub mod discovery;
 pub mod metrics;
 pub mod peer;
+pub mod relay;
+pub mod selection;
 pub mod server;
+pub mod static_peers;
+pub mod stats;
+pub mod transport;
 
 pub use client::P2PClient;
 pub use discovery::DiscoveryService;
 pub use metrics::P2PMetrics;
 pub use peer::{Peer, PeerInfo};
+pub use relay::{RelayClient, RelayService};
 pub use server::P2PServer;
+pub use static_peers::StaticPeers;
 
-use crate::config::P2PConfig;
+use crate::config::{GrpcConfig, P2PConfig};
+use crate::grpc_transport::GrpcTransport;
 use anyhow::Result;
 use std::sync::Arc;
 
 // Re-export generated proto types
 pub mod proto {
     tonic::include_proto!("fabrik.p2p");
+
+    /// Encoded descriptors of the P2P protos
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("p2p_descriptor");
 }
 
 /// P2P manager coordinates discovery, server, and client
@@ -31,17 +43,21 @@ pub struct P2PManager {
     #[allow(dead_code)] // Kept for future use (e.g., runtime config inspection)
     config: Arc<P2PConfig>,
     discovery: Option<Arc<DiscoveryService>>,
+    static_peers: Option<Arc<StaticPeers>>,
     server: Option<Arc<P2PServer>>,
     #[allow(dead_code)] // Will be used when integrated with daemon storage layer
     client: Arc<P2PClient>,
     #[allow(dead_code)] // Will be exposed via metrics endpoint
     metrics: Arc<P2PMetrics>,
+    grpc_config: GrpcConfig,
 }
 
 impl P2PManager {
-    /// Create a new P2P manager
-    pub async fn new(config: P2PConfig) -> Result<Self> {
+    /// Create a new P2P manager; `grpc_config` sets health checking and reflection of
+    /// the P2P server, and the transport of the server and clients
+    pub async fn new(config: P2PConfig, grpc_config: GrpcConfig) -> Result<Self> {
         let config = Arc::new(config);
+        let transport = GrpcTransport::from_config(&grpc_config)?;
 
         // Initialize metrics
         let metrics = Arc::new(P2PMetrics::new());
@@ -54,6 +70,14 @@ impl P2PManager {
             None
         };
 
+        // Explicitly configured peers, mixed with discovered ones
+        let static_peers = if config.static_peers.is_empty() {
+            None
+        } else {
+            tracing::info!("Using {} static P2P peer(s)", config.static_peers.len());
+            Some(Arc::new(StaticPeers::new(config.static_peers.clone())))
+        };
+
         // Initialize P2P server if advertising
         let server = if config.advertise {
             tracing::info!("Initializing P2P server on port {}", config.bind_port);
@@ -63,14 +87,16 @@ impl P2PManager {
         };
 
         // Initialize P2P client (always needed for fetching from peers)
-        let client = Arc::new(P2PClient::new(config.clone()));
+        let client = Arc::new(P2PClient::new(config.clone(), transport));
 
         Ok(Self {
             config,
             discovery,
+            static_peers,
             server,
             client,
             metrics,
+            grpc_config,
         })
     }
 
@@ -83,7 +109,66 @@ impl P2PManager {
 
         // Start server if advertising
         if let Some(server) = &self.server {
-            server.start().await?;
+            server.start(&self.grpc_config).await?;
+        }
+
+        // Register with the relay, and accept tunnels from peers that can't reach this
+        // machine directly when serving artifacts
+        if let Some(relay) = self.client.relay() {
+            tracing::info!("Using P2P relay {}", relay.url());
+            let registration = relay.clone();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(relay::REGISTER_INTERVAL);
+                loop {
+                    ticker.tick().await;
+                    match registration.register().await {
+                        Ok(address) => tracing::debug!(
+                            "Registered with P2P relay as {} ({} peer(s))",
+                            address,
+                            registration.get_peers().len()
+                        ),
+                        Err(e) => tracing::warn!("P2P relay registration failed: {:#}", e),
+                    }
+                }
+            });
+
+            if self.server.is_some() {
+                tokio::spawn(relay.listen(self.config.bind_port));
+            }
+        }
+
+        // Periodically health-check static peers and refresh peer latency and content
+        // digests
+        if self.discovery.is_some() || self.static_peers.is_some() || self.client.relay().is_some()
+        {
+            let interval = crate::eviction::EvictionConfig::parse_ttl(&self.config.gossip_interval)
+                .map(std::time::Duration::from_secs)
+                .unwrap_or(std::time::Duration::from_secs(30))
+                .max(std::time::Duration::from_secs(1));
+            let discovery = self.discovery.clone();
+            let static_peers = self.static_peers.clone();
+            let client = self.client.clone();
+            let relay = client.relay();
+
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(interval);
+                loop {
+                    ticker.tick().await;
+                    if let Some(static_peers) = &static_peers {
+                        static_peers.health_check(&client).await;
+                    }
+                    let peers = collect_peers(
+                        discovery.as_deref(),
+                        static_peers.as_deref(),
+                        relay.as_deref(),
+                    )
+                    .await;
+                    client.refresh_peers(&peers).await;
+                    if let Err(e) = client.stats().save() {
+                        tracing::debug!("Failed to save P2P peer statistics: {}", e);
+                    }
+                }
+            });
         }
 
         tracing::info!("P2P services started successfully");
@@ -91,18 +176,19 @@ impl P2PManager {
     }
 
     /// Get the P2P client for making requests to peers
-    #[allow(dead_code)] // Will be used when integrated with daemon storage layer
     pub fn client(&self) -> Arc<P2PClient> {
         self.client.clone()
     }
 
-    /// Get discovered peers
+    /// Get discovered peers, healthy static peers and peers registered with the relay
     pub async fn get_peers(&self) -> Vec<Peer> {
-        if let Some(discovery) = &self.discovery {
-            discovery.get_peers().await
-        } else {
-            vec![]
-        }
+        let relay = self.client.relay();
+        collect_peers(
+            self.discovery.as_deref(),
+            self.static_peers.as_deref(),
+            relay.as_deref(),
+        )
+        .await
     }
 
     /// Get P2P metrics
@@ -115,6 +201,10 @@ impl P2PManager {
     pub async fn shutdown(&self) -> Result<()> {
         tracing::info!("Shutting down P2P services");
 
+        if let Err(e) = self.client.stats().save() {
+            tracing::warn!("Failed to save P2P peer statistics: {}", e);
+        }
+
         if let Some(server) = &self.server {
             server.shutdown().await?;
         }
@@ -126,3 +216,27 @@ impl P2PManager {
         Ok(())
     }
 }
+
+/// Discovered peers followed by healthy static peers and relay peers not known already
+async fn collect_peers(
+    discovery: Option<&DiscoveryService>,
+    static_peers: Option<&StaticPeers>,
+    relay: Option<&RelayClient>,
+) -> Vec<Peer> {
+    let discovered = match discovery {
+        Some(discovery) => discovery.get_peers().await,
+        None => vec![],
+    };
+    let configured = match static_peers {
+        Some(static_peers) => static_peers.get_peers().await,
+        None => vec![],
+    };
+    let registered = match relay {
+        Some(relay) => relay.get_peers(),
+        None => vec![],
+    };
+    static_peers::merge_peers(
+        static_peers::merge_peers(discovered, configured),
+        registered,
+    )
+}
@@ -0,0 +1,736 @@
+#![allow(dead_code)] // P2P feature not fully integrated yet
+/// Peer rendezvous and relay for peers on different subnets
+///
+/// mDNS doesn't cross VLANs, and segmented networks often block direct connections
+/// between them too. A Layer 2 server can act as a rendezvous (`p2p.relay_server`):
+/// peers pointed at it (`p2p.relay_url`) register their P2P endpoint periodically and
+/// get the endpoints of the other registered peers back, which join the peer table like
+/// discovered peers. When a direct connection to such a peer fails, the requester opens
+/// a tunnel to it through the server instead.
+///
+/// A tunnel is an opaque byte stream between two peers, carried by two gRPC streams
+/// that the server pipes into each other. Peers speak the regular P2P protocol over it,
+/// Noise handshake included, so the server never sees artifacts or requests in the
+/// clear and the serving peer's authentication and consent apply unchanged. The
+/// serving peer keeps a `Listen` stream open to learn about incoming tunnels and
+/// bridges each of them to its own P2P server.
+use crate::config::P2PConfig;
+use crate::error::{FabrikError, Result, ResultExt, RpcResultExt};
+use crate::grpc_transport::GrpcTransport;
+use crate::p2p::auth;
+use crate::p2p::proto::p2p_relay_client::P2pRelayClient;
+use crate::p2p::proto::p2p_relay_server::{P2pRelay, P2pRelayServer};
+use crate::p2p::proto::{
+    ListenRequest, RegisterRequest, RegisterResponse, RegisteredPeer, RelayFrame, RelayInvite,
+    RelayOpen,
+};
+use crate::p2p::{Peer, PeerInfo};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Request, Response, Status, Streaming};
+
+/// How often peers renew their registration
+pub const REGISTER_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a registration is kept without being renewed
+const REGISTRATION_TTL: Duration = Duration::from_secs(90);
+
+/// How long an invited peer has to accept a tunnel
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait before listening again after the relay dropped the stream
+const LISTEN_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Frames buffered per tunnel direction
+const TUNNEL_BUFFER: usize = 16;
+
+/// Most tunnel bytes carried by one frame
+const FRAME_SIZE: usize = 64 * 1024;
+
+/// Server half of a tunnel handed from `AcceptTunnel` to the waiting `OpenTunnel`
+type TunnelEnd = (
+    Streaming<RelayFrame>,
+    mpsc::Sender<Result<RelayFrame, Status>>,
+);
+
+/// Registered peers, listeners and tunnels waiting to be accepted
+#[derive(Default)]
+struct RelayState {
+    /// Registered peers by machine ID, with when they last registered
+    registrations: HashMap<String, (RegisteredPeer, Instant)>,
+
+    /// Invite channels of listening peers, by machine ID
+    listeners: HashMap<String, mpsc::Sender<Result<RelayInvite, Status>>>,
+
+    /// Tunnels waiting for the invited peer, by session ID
+    sessions: HashMap<String, oneshot::Sender<TunnelEnd>>,
+}
+
+impl RelayState {
+    fn register(&mut self, peer: RegisteredPeer, now: Instant) {
+        self.registrations
+            .insert(peer.machine_id.clone(), (peer, now));
+    }
+
+    /// Live registrations other than `machine_id`'s, dropping expired ones
+    fn peers(&mut self, machine_id: &str, now: Instant) -> Vec<RegisteredPeer> {
+        self.registrations
+            .retain(|_, (_, registered_at)| now.duration_since(*registered_at) < REGISTRATION_TTL);
+        self.listeners.retain(|_, listener| !listener.is_closed());
+
+        let mut peers: Vec<RegisteredPeer> = self
+            .registrations
+            .values()
+            .filter(|(peer, _)| peer.machine_id != machine_id)
+            .map(|(peer, _)| RegisteredPeer {
+                listening: self.listeners.contains_key(&peer.machine_id),
+                ..peer.clone()
+            })
+            .collect();
+        peers.sort_by(|a, b| a.machine_id.cmp(&b.machine_id));
+        peers
+    }
+}
+
+/// Rendezvous and relay service, served by `fabrik server`
+pub struct RelayService {
+    secret: String,
+    state: Arc<Mutex<RelayState>>,
+    /// Nonces of recently accepted requests
+    replay_guard: Arc<auth::ReplayGuard>,
+}
+
+impl RelayService {
+    pub fn new(secret: String) -> Self {
+        Self {
+            secret,
+            state: Arc::new(Mutex::new(RelayState::default())),
+            replay_guard: Arc::new(auth::ReplayGuard::new()),
+        }
+    }
+
+    /// gRPC service to add to a tonic server
+    pub fn into_server(self) -> P2pRelayServer<Self> {
+        P2pRelayServer::new(self)
+    }
+
+    fn verify_auth(
+        &self,
+        subject: &str,
+        timestamp: i64,
+        nonce: &str,
+        signature: &[u8],
+    ) -> Result<(), Status> {
+        auth::verify_request(&self.secret, subject, timestamp, nonce, signature)
+            .and_then(|_| self.replay_guard.check(nonce, timestamp))
+            .map_err(|e| {
+                tracing::warn!("P2P relay auth failed: {}", e);
+                Status::unauthenticated(format!("Authentication failed: {}", e))
+            })
+    }
+
+    /// Read and authenticate the opening frame of a tunnel
+    async fn open(&self, inbound: &mut Streaming<RelayFrame>) -> Result<RelayOpen, Status> {
+        let open = inbound
+            .message()
+            .await?
+            .and_then(|frame| frame.open)
+            .ok_or_else(|| Status::invalid_argument("Tunnel must start with an open frame"))?;
+        self.verify_auth(&open.target, open.timestamp, &open.nonce, &open.signature)?;
+        Ok(open)
+    }
+}
+
+#[tonic::async_trait]
+impl P2pRelay for RelayService {
+    async fn register(
+        &self,
+        request: Request<RegisterRequest>,
+    ) -> Result<Response<RegisterResponse>, Status> {
+        let observed_address = request
+            .remote_addr()
+            .map(|addr| addr.ip().to_canonical().to_string())
+            .unwrap_or_default();
+        let req = request.into_inner();
+        self.verify_auth(&req.machine_id, req.timestamp, &req.nonce, &req.signature)?;
+
+        if req.machine_id.is_empty() {
+            return Err(Status::invalid_argument("Missing machine ID"));
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if !state.registrations.contains_key(&req.machine_id) {
+            tracing::info!(
+                "P2P relay: {} registered from {}",
+                req.hostname,
+                observed_address
+            );
+        }
+        state.register(
+            RegisteredPeer {
+                machine_id: req.machine_id.clone(),
+                hostname: req.hostname,
+                address: observed_address.clone(),
+                port: req.port,
+                listening: false,
+            },
+            now,
+        );
+
+        Ok(Response::new(RegisterResponse {
+            observed_address,
+            peers: state.peers(&req.machine_id, now),
+        }))
+    }
+
+    type ListenStream = ReceiverStream<Result<RelayInvite, Status>>;
+
+    async fn listen(
+        &self,
+        request: Request<ListenRequest>,
+    ) -> Result<Response<Self::ListenStream>, Status> {
+        let req = request.into_inner();
+        self.verify_auth(&req.machine_id, req.timestamp, &req.nonce, &req.signature)?;
+
+        let (tx, rx) = mpsc::channel(TUNNEL_BUFFER);
+        self.state
+            .lock()
+            .unwrap()
+            .listeners
+            .insert(req.machine_id, tx);
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type OpenTunnelStream = ReceiverStream<Result<RelayFrame, Status>>;
+
+    async fn open_tunnel(
+        &self,
+        request: Request<Streaming<RelayFrame>>,
+    ) -> Result<Response<Self::OpenTunnelStream>, Status> {
+        let mut inbound = request.into_inner();
+        let open = self.open(&mut inbound).await?;
+
+        let session_id = auth::generate_nonce();
+        let (accepted_tx, accepted_rx) = oneshot::channel();
+        let listener = {
+            let mut state = self.state.lock().unwrap();
+            let listener = state
+                .listeners
+                .get(&open.target)
+                .filter(|listener| !listener.is_closed())
+                .cloned()
+                .ok_or_else(|| Status::unavailable("Peer is not listening on this relay"))?;
+            state.sessions.insert(session_id.clone(), accepted_tx);
+            listener
+        };
+
+        let invite = RelayInvite {
+            session_id: session_id.clone(),
+            requester_hostname: open.hostname.clone(),
+        };
+        let accepted = if listener.send(Ok(invite)).await.is_ok() {
+            tokio::time::timeout(ACCEPT_TIMEOUT, accepted_rx)
+                .await
+                .ok()
+                .and_then(Result::ok)
+        } else {
+            None
+        };
+        self.state.lock().unwrap().sessions.remove(&session_id);
+
+        let Some((target_inbound, target_outbound)) = accepted else {
+            return Err(Status::unavailable("Peer did not accept the tunnel"));
+        };
+
+        tracing::debug!(
+            "P2P relay: tunnel from {} to {}",
+            open.hostname,
+            open.target
+        );
+
+        let (tx, rx) = mpsc::channel(TUNNEL_BUFFER);
+        tokio::spawn(pipe(inbound, target_outbound));
+        tokio::spawn(pipe(target_inbound, tx));
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type AcceptTunnelStream = ReceiverStream<Result<RelayFrame, Status>>;
+
+    async fn accept_tunnel(
+        &self,
+        request: Request<Streaming<RelayFrame>>,
+    ) -> Result<Response<Self::AcceptTunnelStream>, Status> {
+        let mut inbound = request.into_inner();
+        let open = self.open(&mut inbound).await?;
+
+        let session = self
+            .state
+            .lock()
+            .unwrap()
+            .sessions
+            .remove(&open.target)
+            .ok_or_else(|| Status::not_found("Unknown or expired relay session"))?;
+
+        let (tx, rx) = mpsc::channel(TUNNEL_BUFFER);
+        session
+            .send((inbound, tx))
+            .map_err(|_| Status::not_found("Relay session expired"))?;
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Forward frames from one peer's stream to the other's until either side closes
+async fn pipe(mut from: Streaming<RelayFrame>, to: mpsc::Sender<Result<RelayFrame, Status>>) {
+    loop {
+        match from.message().await {
+            Ok(Some(frame)) => {
+                if to.send(Ok(frame)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(status) => {
+                let _ = to.send(Err(status)).await;
+                break;
+            }
+        }
+    }
+}
+
+/// Peers learned from the relay at the last registration
+#[derive(Default)]
+struct Registered {
+    peers: Vec<Peer>,
+
+    /// Peers listening for tunnels
+    listening: HashSet<String>,
+
+    /// Peers a direct connection failed to since the last registration
+    relayed: HashSet<String>,
+}
+
+/// Client side of the relay: registration, tunnels to other peers, and accepting
+/// tunnels to the local P2P server
+pub struct RelayClient {
+    /// Relay endpoint (http://host:port)
+    url: String,
+    secret: String,
+    machine_id: String,
+    hostname: String,
+    /// P2P port registered with the relay (0 when not serving artifacts)
+    port: u16,
+    registered: Mutex<Registered>,
+    transport: GrpcTransport,
+}
+
+impl RelayClient {
+    pub fn new(
+        config: &P2PConfig,
+        machine_id: String,
+        hostname: String,
+        transport: GrpcTransport,
+    ) -> Result<Self> {
+        let relay_url = config
+            .relay_url
+            .as_deref()
+            .ok_or_else(|| FabrikError::config("P2P relay URL not configured"))?;
+        let secret = config
+            .secret
+            .clone()
+            .ok_or_else(|| FabrikError::config("P2P secret not configured"))?;
+
+        Ok(Self {
+            url: endpoint_url(relay_url)?,
+            secret,
+            machine_id,
+            hostname,
+            port: if config.advertise {
+                config.bind_port
+            } else {
+                0
+            },
+            registered: Mutex::new(Registered::default()),
+            transport,
+        })
+    }
+
+    /// Relay endpoint
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Register with the relay and refresh the list of peers registered with it
+    ///
+    /// Returns the address the relay sees this machine at. Peers that needed the relay
+    /// are tried directly again afterwards, in case the network changed.
+    pub async fn register(&self) -> Result<String> {
+        let mut client = self.connect().await?;
+
+        let timestamp = auth::current_timestamp();
+        let nonce = auth::generate_nonce();
+        let response = client
+            .register(RegisterRequest {
+                machine_id: self.machine_id.clone(),
+                hostname: self.hostname.clone(),
+                port: self.port as u32,
+                timestamp,
+                signature: auth::sign_request(&self.secret, &self.machine_id, timestamp, &nonce),
+                nonce,
+            })
+            .await
+            .rpc_context("Register request failed")?
+            .into_inner();
+
+        let mut registered = self.registered.lock().unwrap();
+        registered.listening = response
+            .peers
+            .iter()
+            .filter(|peer| peer.listening)
+            .map(|peer| peer.machine_id.clone())
+            .collect();
+        registered.peers = response
+            .peers
+            .into_iter()
+            .filter_map(|peer| self.to_peer(peer))
+            .collect();
+        registered.relayed.clear();
+
+        Ok(response.observed_address)
+    }
+
+    fn to_peer(&self, peer: RegisteredPeer) -> Option<Peer> {
+        if peer.machine_id == self.machine_id || peer.port == 0 {
+            return None;
+        }
+        let address: IpAddr = peer.address.parse().ok()?;
+        Some(Peer::new(PeerInfo {
+            machine_id: peer.machine_id,
+            hostname: peer.hostname,
+            address,
+            port: u16::try_from(peer.port).ok()?,
+            last_seen: SystemTime::now(),
+            accepting_requests: true,
+        }))
+    }
+
+    /// Serving peers registered with the relay at the last registration
+    pub fn get_peers(&self) -> Vec<Peer> {
+        self.registered.lock().unwrap().peers.clone()
+    }
+
+    /// Whether `machine_id` can be reached through the relay
+    pub fn reaches(&self, machine_id: &str) -> bool {
+        self.registered
+            .lock()
+            .unwrap()
+            .listening
+            .contains(machine_id)
+    }
+
+    /// Whether a direct connection to `machine_id` failed since the last registration
+    pub fn is_relayed(&self, machine_id: &str) -> bool {
+        self.registered.lock().unwrap().relayed.contains(machine_id)
+    }
+
+    /// Go through the relay for `machine_id` until the next registration
+    pub fn mark_relayed(&self, machine_id: &str) {
+        self.registered
+            .lock()
+            .unwrap()
+            .relayed
+            .insert(machine_id.to_string());
+    }
+
+    /// Open a tunnel to the peer `machine_id`
+    pub async fn tunnel_to(&self, machine_id: &str) -> Result<DuplexStream> {
+        self.tunnel(machine_id, false).await
+    }
+
+    /// Accept tunnels from other peers and bridge them to the local P2P server on
+    /// `local_port`, reconnecting whenever the relay drops the stream
+    pub async fn listen(self: Arc<Self>, local_port: u16) {
+        loop {
+            if let Err(e) = self.listen_once(local_port).await {
+                tracing::debug!("P2P relay listener stopped: {:#}", e);
+            }
+            tokio::time::sleep(LISTEN_RETRY_DELAY).await;
+        }
+    }
+
+    async fn listen_once(self: &Arc<Self>, local_port: u16) -> Result<()> {
+        let mut client = self.connect().await?;
+
+        let timestamp = auth::current_timestamp();
+        let nonce = auth::generate_nonce();
+        let mut invites = client
+            .listen(ListenRequest {
+                machine_id: self.machine_id.clone(),
+                timestamp,
+                signature: auth::sign_request(&self.secret, &self.machine_id, timestamp, &nonce),
+                nonce,
+            })
+            .await
+            .rpc_context("Listen request failed")?
+            .into_inner();
+
+        tracing::info!("Accepting relayed P2P connections via {}", self.url);
+
+        while let Some(invite) = invites.message().await.rpc_context("Relay stream failed")? {
+            let relay = self.clone();
+            tokio::spawn(async move {
+                tracing::debug!("Relayed P2P connection from {}", invite.requester_hostname);
+                if let Err(e) = relay.accept(&invite.session_id, local_port).await {
+                    tracing::debug!(
+                        "Relayed P2P connection from {} failed: {:#}",
+                        invite.requester_hostname,
+                        e
+                    );
+                }
+            });
+        }
+
+        Err(FabrikError::unavailable("Relay closed the stream"))
+    }
+
+    /// Accept a tunnel and bridge it to the local P2P server
+    async fn accept(&self, session_id: &str, local_port: u16) -> Result<()> {
+        let mut local = TcpStream::connect(("127.0.0.1", local_port))
+            .await
+            .io_context("Failed to connect to the local P2P server")?;
+        let mut tunnel = self.tunnel(session_id, true).await?;
+
+        tokio::io::copy_bidirectional(&mut tunnel, &mut local)
+            .await
+            .io_context("Relayed connection failed")?;
+        Ok(())
+    }
+
+    /// Open (`target` is a machine ID) or accept (`target` is a session ID) a tunnel
+    async fn tunnel(&self, target: &str, accept: bool) -> Result<DuplexStream> {
+        let mut client = self.connect().await?;
+
+        let timestamp = auth::current_timestamp();
+        let nonce = auth::generate_nonce();
+        let open = RelayOpen {
+            machine_id: self.machine_id.clone(),
+            hostname: self.hostname.clone(),
+            target: target.to_string(),
+            timestamp,
+            signature: auth::sign_request(&self.secret, target, timestamp, &nonce),
+            nonce,
+        };
+
+        let (tx, rx) = mpsc::channel(TUNNEL_BUFFER);
+        tx.send(RelayFrame {
+            open: Some(open),
+            data: Vec::new(),
+        })
+        .await
+        .map_err(|_| FabrikError::unavailable("Relay tunnel closed"))?;
+
+        let outbound = ReceiverStream::new(rx);
+        let inbound = if accept {
+            client.accept_tunnel(outbound).await
+        } else {
+            client.open_tunnel(outbound).await
+        }
+        .rpc_context("Relay refused the tunnel")?
+        .into_inner();
+
+        Ok(bridge(inbound, tx))
+    }
+
+    async fn connect(&self) -> Result<P2pRelayClient<Channel>> {
+        let endpoint = Endpoint::from_shared(self.url.clone())
+            .map_err(|e| FabrikError::config(format!("Invalid relay URL: {}", e)))?
+            .connect_timeout(Duration::from_secs(5));
+        let channel = self
+            .transport
+            .endpoint(endpoint)
+            .connect()
+            .await
+            .upstream_context(&format!("Failed to connect to relay {}", self.url))?;
+        Ok(P2pRelayClient::new(channel))
+    }
+}
+
+/// Expose a tunnel's gRPC streams as a byte stream
+fn bridge(mut inbound: Streaming<RelayFrame>, outbound: mpsc::Sender<RelayFrame>) -> DuplexStream {
+    let (local, remote) = tokio::io::duplex(FRAME_SIZE);
+    let (mut reader, mut writer) = tokio::io::split(remote);
+
+    tokio::spawn(async move {
+        let upload = async {
+            let mut buf = vec![0u8; FRAME_SIZE];
+            loop {
+                let n = reader.read(&mut buf).await?;
+                if n == 0 {
+                    return Ok(());
+                }
+                let frame = RelayFrame {
+                    open: None,
+                    data: buf[..n].to_vec(),
+                };
+                if outbound.send(frame).await.is_err() {
+                    return Ok(());
+                }
+            }
+        };
+        let download = async {
+            while let Some(frame) = inbound.message().await? {
+                writer.write_all(&frame.data).await?;
+            }
+            writer.shutdown().await?;
+            Ok(())
+        };
+
+        // Either side closing ends the tunnel
+        let result: anyhow::Result<()> = tokio::select! {
+            result = upload => result,
+            result = download => result,
+        };
+        if let Err(e) = result {
+            tracing::debug!("P2P relay tunnel closed: {:#}", e);
+        }
+    });
+
+    local
+}
+
+/// gRPC endpoint for a relay URL (grpc://host:port or http://host:port)
+fn endpoint_url(relay_url: &str) -> Result<String> {
+    let address = relay_url
+        .strip_prefix("grpc://")
+        .or_else(|| relay_url.strip_prefix("http://"))
+        .ok_or_else(|| FabrikError::config("Relay URL must start with grpc:// or http://"))?;
+    Ok(format!("http://{}", address.trim_end_matches('/')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registered(machine_id: &str, port: u32) -> RegisteredPeer {
+        RegisteredPeer {
+            machine_id: machine_id.to_string(),
+            hostname: machine_id.to_string(),
+            address: "10.1.0.5".to_string(),
+            port,
+            listening: false,
+        }
+    }
+
+    #[test]
+    fn test_registrations_expire() {
+        let mut state = RelayState::default();
+        let start = Instant::now();
+        state.register(registered("a", 7071), start);
+        state.register(registered("b", 7071), start + Duration::from_secs(60));
+
+        let ids = |peers: Vec<RegisteredPeer>| -> Vec<String> {
+            peers.into_iter().map(|p| p.machine_id).collect()
+        };
+        assert_eq!(ids(state.peers("c", start)), vec!["a", "b"]);
+        assert_eq!(ids(state.peers("a", start)), vec!["b"]);
+        assert_eq!(
+            ids(state.peers("c", start + REGISTRATION_TTL + Duration::from_secs(1))),
+            vec!["b"]
+        );
+    }
+
+    #[test]
+    fn test_endpoint_url() {
+        assert_eq!(
+            endpoint_url("grpc://cache.internal:7070").unwrap(),
+            "http://cache.internal:7070"
+        );
+        assert_eq!(
+            endpoint_url("http://10.0.0.1:7070/").unwrap(),
+            "http://10.0.0.1:7070"
+        );
+        assert!(endpoint_url("cache.internal:7070").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_through_relay() {
+        let secret = "p2p-shared-secret".to_string();
+
+        // Relay
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(RelayService::new(secret.clone()).into_server())
+                .serve_with_incoming(tonic::transport::server::TcpIncoming::from(listener)),
+        );
+
+        // Stand-in for the serving peer's P2P server: echoes what it reads
+        let echo = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let echo_port = echo.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = echo.accept().await.unwrap();
+            let (mut reader, mut writer) = stream.split();
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        });
+
+        let config = P2PConfig {
+            secret: Some(secret),
+            relay_url: Some(format!("grpc://{}", relay_addr)),
+            bind_port: echo_port,
+            ..Default::default()
+        };
+        let serving = Arc::new(
+            RelayClient::new(
+                &config,
+                "serving".to_string(),
+                "serving-host".to_string(),
+                GrpcTransport::default(),
+            )
+            .unwrap(),
+        );
+        let requesting = RelayClient::new(
+            &config,
+            "requesting".to_string(),
+            "requesting-host".to_string(),
+            GrpcTransport::default(),
+        )
+        .unwrap();
+
+        serving.register().await.unwrap();
+        tokio::spawn(serving.clone().listen(echo_port));
+
+        // Wait for the listener to be registered
+        let mut attempts = 0;
+        while !requesting.reaches("serving") {
+            attempts += 1;
+            assert!(attempts < 50, "serving peer never started listening");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert_eq!(requesting.register().await.unwrap(), "127.0.0.1");
+        }
+
+        let peers = requesting.get_peers();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].info.hostname, "serving-host");
+        assert_eq!(peers[0].info.port, echo_port);
+
+        let mut tunnel = requesting.tunnel_to("serving").await.unwrap();
+        tunnel.write_all(b"hello through the relay").await.unwrap();
+        let mut buf = vec![0u8; 23];
+        tunnel.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hello through the relay");
+
+        // Peers that aren't listening can't be reached
+        assert!(requesting.tunnel_to("unknown").await.is_err());
+    }
+}
@@ -0,0 +1,343 @@
+#![allow(dead_code)] // P2P feature not fully integrated yet
+/// Peer selection by content availability and latency
+///
+/// Asking every peer for every miss doesn't scale past a handful of machines. Instead,
+/// peers periodically exchange a compact digest of the artifacts they hold (a Bloom
+/// filter over artifact hashes) and measure each other's round-trip time. On a miss,
+/// the client only asks the fastest few peers whose digest says they may have the blob.
+use crate::p2p::proto::DigestResponse;
+use crate::p2p::Peer;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Target false-positive rate of content digests
+const DIGEST_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// Upper bound on digest size (8 MiB of bits, ~7M artifacts at 1% false positives)
+const MAX_DIGEST_BITS: u64 = 64 * 1024 * 1024;
+
+/// Digests older than this are ignored (the peer may have evicted or gained artifacts)
+const DIGEST_MAX_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Weight of the newest sample in the latency moving average
+const LATENCY_SMOOTHING: f64 = 0.3;
+
+/// Bloom filter over the artifact hashes a peer holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentDigest {
+    bits: Vec<u8>,
+    num_hashes: u32,
+    item_count: u64,
+}
+
+impl ContentDigest {
+    /// Create an empty digest sized for `expected_items`
+    pub fn with_capacity(expected_items: u64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+
+        let num_bits = ((-n * DIGEST_FALSE_POSITIVE_RATE.ln()) / (ln2 * ln2)).ceil() as u64;
+        let num_bits = num_bits.clamp(64, MAX_DIGEST_BITS).next_multiple_of(8);
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().clamp(1.0, 16.0) as u32;
+
+        Self {
+            bits: vec![0; (num_bits / 8) as usize],
+            num_hashes,
+            item_count: 0,
+        }
+    }
+
+    /// Build a digest from a set of artifact hashes
+    pub fn from_hashes<I, T>(hashes: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+        T: AsRef<str>,
+    {
+        let hashes = hashes.into_iter();
+        let mut digest = Self::with_capacity(hashes.len() as u64);
+        for hash in hashes {
+            digest.insert(hash.as_ref());
+        }
+        digest
+    }
+
+    pub fn insert(&mut self, hash: &str) {
+        for index in self.bit_indices(hash) {
+            self.bits[(index / 8) as usize] |= 1 << (index % 8);
+        }
+        self.item_count += 1;
+    }
+
+    /// Whether the artifact may be present (false positives possible, no false negatives)
+    pub fn might_contain(&self, hash: &str) -> bool {
+        self.bit_indices(hash)
+            .all(|index| self.bits[(index / 8) as usize] & (1 << (index % 8)) != 0)
+    }
+
+    pub fn item_count(&self) -> u64 {
+        self.item_count
+    }
+
+    /// Size of the digest on the wire
+    pub fn size_bytes(&self) -> usize {
+        self.bits.len()
+    }
+
+    /// Bit positions for a hash (Kirsch-Mitzenmacher double hashing)
+    fn bit_indices(&self, hash: &str) -> impl Iterator<Item = u64> {
+        let digest = Sha256::digest(hash.as_bytes());
+        let h1 = u64::from_le_bytes(digest[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().unwrap()) | 1;
+        let num_bits = self.bits.len() as u64 * 8;
+
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    pub fn to_proto(&self, generated_at: i64) -> DigestResponse {
+        DigestResponse {
+            consent_required: false,
+            bits: self.bits.clone(),
+            num_hashes: self.num_hashes,
+            item_count: self.item_count,
+            generated_at,
+        }
+    }
+
+    /// Parse a digest received from a peer, rejecting malformed ones
+    pub fn from_proto(response: DigestResponse) -> Option<Self> {
+        if response.bits.is_empty() || response.num_hashes == 0 || response.num_hashes > 16 {
+            return None;
+        }
+        Some(Self {
+            bits: response.bits,
+            num_hashes: response.num_hashes,
+            item_count: response.item_count,
+        })
+    }
+}
+
+/// What we know about a peer
+#[derive(Debug, Clone, Default)]
+struct PeerState {
+    /// Smoothed round-trip time
+    latency: Option<Duration>,
+    digest: Option<(Instant, ContentDigest)>,
+    consecutive_failures: u32,
+}
+
+impl PeerState {
+    fn fresh_digest(&self) -> Option<&ContentDigest> {
+        self.digest
+            .as_ref()
+            .filter(|(received, _)| received.elapsed() < DIGEST_MAX_AGE)
+            .map(|(_, digest)| digest)
+    }
+}
+
+/// Likelihood that a peer has an artifact, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Availability {
+    /// Digest says the peer may have it
+    Likely,
+    /// No usable digest for the peer
+    Unknown,
+    /// Digest says the peer doesn't have it
+    Absent,
+}
+
+/// Ranks peers by content availability and latency
+#[derive(Debug)]
+pub struct PeerSelector {
+    peers: RwLock<HashMap<String, PeerState>>,
+    max_fanout: usize,
+}
+
+impl PeerSelector {
+    /// `max_fanout` bounds how many peers are asked per miss (0 = no limit)
+    pub fn new(max_fanout: usize) -> Self {
+        Self {
+            peers: RwLock::new(HashMap::new()),
+            max_fanout,
+        }
+    }
+
+    /// Record a successful round trip to a peer
+    pub fn record_latency(&self, machine_id: &str, rtt: Duration) {
+        let mut peers = self.peers.write().unwrap();
+        let state = peers.entry(machine_id.to_string()).or_default();
+        state.latency = Some(match state.latency {
+            Some(previous) => {
+                previous.mul_f64(1.0 - LATENCY_SMOOTHING) + rtt.mul_f64(LATENCY_SMOOTHING)
+            }
+            None => rtt,
+        });
+        state.consecutive_failures = 0;
+    }
+
+    /// Record a failed request (unreachable peers sink in the ranking)
+    pub fn record_failure(&self, machine_id: &str) {
+        let mut peers = self.peers.write().unwrap();
+        let state = peers.entry(machine_id.to_string()).or_default();
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+    }
+
+    /// Store the latest content digest received from a peer
+    pub fn update_digest(&self, machine_id: &str, digest: ContentDigest) {
+        let mut peers = self.peers.write().unwrap();
+        peers.entry(machine_id.to_string()).or_default().digest = Some((Instant::now(), digest));
+    }
+
+    /// Forget peers that are no longer discovered
+    pub fn retain(&self, peers: &[Peer]) {
+        self.peers
+            .write()
+            .unwrap()
+            .retain(|id, _| peers.iter().any(|p| &p.info.machine_id == id));
+    }
+
+    /// Smoothed latency of a peer, if measured
+    pub fn latency(&self, machine_id: &str) -> Option<Duration> {
+        self.peers.read().unwrap().get(machine_id)?.latency
+    }
+
+    /// Peers worth asking for `hash`, best first
+    ///
+    /// Peers whose digest rules the artifact out are skipped. The rest are ordered by
+    /// availability (digest match before no digest), then by failures and latency
+    /// (unmeasured peers last), and capped at the configured fan-out.
+    pub fn select(&self, peers: &[Peer], hash: &str) -> Vec<Peer> {
+        let states = self.peers.read().unwrap();
+
+        let mut ranked: Vec<_> = peers
+            .iter()
+            .filter(|peer| peer.info.accepting_requests)
+            .map(|peer| {
+                let state = states.get(&peer.info.machine_id);
+                let availability = match state.and_then(PeerState::fresh_digest) {
+                    Some(digest) if digest.might_contain(hash) => Availability::Likely,
+                    Some(_) => Availability::Absent,
+                    None => Availability::Unknown,
+                };
+                let failures = state.map(|s| s.consecutive_failures).unwrap_or(0);
+                let latency = state.and_then(|s| s.latency).unwrap_or(Duration::MAX);
+                (availability, failures, latency, peer)
+            })
+            .filter(|(availability, ..)| *availability != Availability::Absent)
+            .collect();
+
+        ranked.sort_by_key(|(availability, failures, latency, _)| {
+            (*availability, *failures, *latency)
+        });
+
+        let limit = if self.max_fanout == 0 {
+            ranked.len()
+        } else {
+            self.max_fanout
+        };
+
+        ranked
+            .into_iter()
+            .take(limit)
+            .map(|(.., peer)| peer.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::PeerInfo;
+    use std::time::SystemTime;
+
+    fn peer(machine_id: &str) -> Peer {
+        Peer::new(PeerInfo {
+            machine_id: machine_id.to_string(),
+            hostname: machine_id.to_string(),
+            address: "127.0.0.1".parse().unwrap(),
+            port: 7071,
+            last_seen: SystemTime::now(),
+            accepting_requests: true,
+        })
+    }
+
+    fn hash(i: usize) -> String {
+        hex::encode(Sha256::digest(i.to_string().as_bytes()))
+    }
+
+    fn ids(peers: &[Peer]) -> Vec<&str> {
+        peers.iter().map(|p| p.info.machine_id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_digest_membership() {
+        let hashes: Vec<String> = (0..1000).map(hash).collect();
+        let digest = ContentDigest::from_hashes(&hashes);
+
+        assert_eq!(digest.item_count(), 1000);
+        assert!(hashes.iter().all(|h| digest.might_contain(h)));
+
+        let false_positives = (1000..11000)
+            .filter(|i| digest.might_contain(&hash(*i)))
+            .count();
+        assert!(
+            false_positives < 300,
+            "false positive rate too high: {}/10000",
+            false_positives
+        );
+    }
+
+    #[test]
+    fn test_digest_proto_roundtrip() {
+        let digest = ContentDigest::from_hashes([hash(1), hash(2)]);
+        let parsed = ContentDigest::from_proto(digest.to_proto(0)).unwrap();
+        assert_eq!(parsed, digest);
+
+        assert!(ContentDigest::from_proto(DigestResponse::default()).is_none());
+    }
+
+    #[test]
+    fn test_select_skips_peers_without_content() {
+        let selector = PeerSelector::new(0);
+        let peers = vec![peer("a"), peer("b"), peer("c")];
+
+        selector.update_digest("a", ContentDigest::from_hashes([hash(1)]));
+        selector.update_digest("b", ContentDigest::from_hashes([hash(2)]));
+
+        // "a" has it, "b" doesn't, "c" hasn't sent a digest yet
+        assert_eq!(ids(&selector.select(&peers, &hash(1))), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_select_prefers_low_latency_and_caps_fanout() {
+        let selector = PeerSelector::new(2);
+        let peers = vec![
+            peer("slow"),
+            peer("fast"),
+            peer("unmeasured"),
+            peer("flaky"),
+        ];
+
+        selector.record_latency("slow", Duration::from_millis(80));
+        selector.record_latency("fast", Duration::from_millis(5));
+        selector.record_latency("flaky", Duration::from_millis(1));
+        selector.record_failure("flaky");
+
+        assert_eq!(
+            ids(&selector.select(&peers, &hash(1))),
+            vec!["fast", "slow"]
+        );
+    }
+
+    #[test]
+    fn test_latency_is_smoothed() {
+        let selector = PeerSelector::new(0);
+        selector.record_latency("a", Duration::from_millis(10));
+        selector.record_latency("a", Duration::from_millis(110));
+
+        let latency = selector.latency("a").unwrap();
+        assert!(latency > Duration::from_millis(10) && latency < Duration::from_millis(110));
+    }
+}
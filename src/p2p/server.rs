@@ -1,18 +1,40 @@
 #![allow(dead_code)] // P2P feature not fully integrated yet
-use crate::config::P2PConfig;
+use crate::bandwidth::Throttle;
+use crate::config::{GrpcConfig, P2PConfig};
+use crate::grpc_transport::GrpcTransport;
 use crate::p2p::auth;
 use crate::p2p::consent::ConsentManager;
 use crate::p2p::proto::p2p_cache_server::{P2pCache, P2pCacheServer};
 use crate::p2p::proto::{
-    ExistsRequest, ExistsResponse, GetRequest, GetResponse, HelloRequest, HelloResponse,
+    BenchRequest, BenchResponse, DigestRequest, DigestResponse, ExistsRequest, ExistsResponse,
+    GetRequest, GetResponse, HelloRequest, HelloResponse, ListNamespacesRequest,
+    ListNamespacesResponse, NamespaceSummary,
 };
+use crate::p2p::selection::ContentDigest;
+use crate::p2p::transport;
 use crate::p2p::PeerInfo;
 use anyhow::{Context, Result};
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::{transport::Server, Request, Response, Status};
+use tonic::{Request, Response, Status};
+
+/// How long a built content digest is served before the cache is scanned again
+const DIGEST_REBUILD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a namespace summary is served before the cache is scanned again
+const NAMESPACES_REBUILD_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Directories of the cache's own bookkeeping (RocksDB metadata, deduplicated chunks,
+/// quarantined objects), which aren't artifacts
+const INTERNAL_DIRS: &[&str] = &["metadata", "chunks", "quarantine"];
+
+/// Most filler bytes sent for one benchmark request
+pub const MAX_BENCH_BYTES: u64 = 256 * 1024 * 1024;
 
 /// P2P gRPC server
 pub struct P2PServer {
@@ -22,6 +44,8 @@ pub struct P2PServer {
     bind_addr: SocketAddr,
     machine_id: String,
     hostname: String,
+    /// Limit of the bytes served to peers (`max_upload_rate`)
+    upload_throttle: Option<Arc<Throttle>>,
 }
 
 impl P2PServer {
@@ -38,6 +62,8 @@ impl P2PServer {
             .context("Failed to get hostname")?
             .to_string_lossy()
             .to_string();
+        let upload_throttle = Throttle::from_config(config.max_upload_rate.as_deref())
+            .context("Invalid p2p.max_upload_rate")?;
 
         Ok(Self {
             config,
@@ -46,6 +72,7 @@ impl P2PServer {
             bind_addr,
             machine_id,
             hostname,
+            upload_throttle,
         })
     }
 
@@ -55,24 +82,50 @@ impl P2PServer {
         *dir = cache_dir;
     }
 
-    /// Start the P2P server
-    pub async fn start(&self) -> Result<()> {
+    /// Start the P2P server, with the health and reflection services and the transport
+    /// tuning of `grpc_config`
+    pub async fn start(&self, grpc_config: &GrpcConfig) -> Result<()> {
         let service = P2PCacheService {
             config: self.config.clone(),
             consent_manager: self.consent_manager.clone(),
             cache_dir: self.cache_dir.clone(),
             machine_id: self.machine_id.clone(),
             hostname: self.hostname.clone(),
+            digest: Arc::new(RwLock::new(None)),
+            namespaces: Arc::new(RwLock::new(None)),
+            replay_guard: Arc::new(auth::ReplayGuard::new()),
+            upload_throttle: self.upload_throttle.clone(),
         };
 
-        let bind_addr = self.bind_addr;
+        let secret = self
+            .config
+            .secret
+            .as_ref()
+            .context("P2P secret not configured")?;
+        let psk = transport::derive_psk(secret);
+
+        let introspection = crate::grpc_introspection::routes(
+            grpc_config,
+            &[crate::p2p::proto::p2p_cache_server::SERVICE_NAME],
+            &[crate::p2p::proto::FILE_DESCRIPTOR_SET],
+        )
+        .await?;
 
-        tracing::info!("P2P gRPC server listening on {}", bind_addr);
+        let listener = tokio::net::TcpListener::bind(self.bind_addr)
+            .await
+            .with_context(|| format!("Failed to bind P2P server to {}", self.bind_addr))?;
 
+        tracing::info!(
+            "P2P gRPC server listening on {} (encrypted)",
+            listener.local_addr()?
+        );
+
+        let mut server = GrpcTransport::from_config(grpc_config)?.server();
         tokio::spawn(async move {
-            Server::builder()
+            server
+                .add_routes(introspection)
                 .add_service(P2pCacheServer::new(service))
-                .serve(bind_addr)
+                .serve_with_incoming(transport::incoming(listener, psk))
                 .await
                 .expect("P2P server failed");
         });
@@ -127,6 +180,13 @@ struct P2PCacheService {
     cache_dir: Arc<RwLock<String>>,
     machine_id: String,
     hostname: String,
+    /// Last built content digest (when it was built, wire form)
+    digest: Arc<RwLock<Option<(Instant, DigestResponse)>>>,
+    /// Last built namespace summary (when it was built)
+    namespaces: Arc<RwLock<Option<(Instant, Vec<NamespaceSummary>)>>>,
+    /// Nonces of recently accepted requests
+    replay_guard: Arc<auth::ReplayGuard>,
+    upload_throttle: Option<Arc<Throttle>>,
 }
 
 #[tonic::async_trait]
@@ -138,7 +198,7 @@ impl P2pCache for P2PCacheService {
         let req = request.into_inner();
 
         // Verify authentication
-        if let Err(e) = self.verify_auth(&req.hash, req.timestamp, &req.signature) {
+        if let Err(e) = self.verify_auth(&req.hash, req.timestamp, &req.nonce, &req.signature) {
             tracing::warn!("P2P auth failed: {}", e);
             return Err(Status::unauthenticated(format!(
                 "Authentication failed: {}",
@@ -200,7 +260,7 @@ impl P2pCache for P2PCacheService {
         let req = request.into_inner();
 
         // Verify authentication
-        if let Err(e) = self.verify_auth(&req.hash, req.timestamp, &req.signature) {
+        if let Err(e) = self.verify_auth(&req.hash, req.timestamp, &req.nonce, &req.signature) {
             tracing::warn!("P2P auth failed: {}", e);
             return Err(Status::unauthenticated(format!(
                 "Authentication failed: {}",
@@ -247,6 +307,7 @@ impl P2pCache for P2PCacheService {
         let hostname = req.requester_hostname.clone();
 
         let (tx, rx) = tokio::sync::mpsc::channel(128);
+        let throttle = self.upload_throttle.clone();
 
         tokio::spawn(async move {
             let artifact_path = std::path::Path::new(&cache_dir).join(&hash);
@@ -270,6 +331,9 @@ impl P2pCache for P2PCacheService {
                     // Send in chunks (32KB)
                     const CHUNK_SIZE: usize = 32 * 1024;
                     for (i, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+                        if let Some(throttle) = &throttle {
+                            throttle.consume(chunk.len()).await;
+                        }
                         let response = GetResponse {
                             chunk: chunk.to_vec(),
                             total_size: if i == 0 { total_size } else { 0 },
@@ -306,16 +370,355 @@ impl P2pCache for P2PCacheService {
             accepting_requests: true,
         }))
     }
+
+    async fn list_namespaces(
+        &self,
+        request: Request<ListNamespacesRequest>,
+    ) -> Result<Response<ListNamespacesResponse>, Status> {
+        let req = request.into_inner();
+
+        // Verify authentication
+        if let Err(e) = self.verify_auth(
+            auth::LIST_NAMESPACES_SUBJECT,
+            req.timestamp,
+            &req.nonce,
+            &req.signature,
+        ) {
+            tracing::warn!("P2P auth failed: {}", e);
+            return Err(Status::unauthenticated(format!(
+                "Authentication failed: {}",
+                e
+            )));
+        }
+
+        let peer_info = PeerInfo {
+            machine_id: req.requester_id.clone(),
+            hostname: req.requester_hostname.clone(),
+            address: "0.0.0.0".parse().unwrap(),
+            port: 0,
+            last_seen: std::time::SystemTime::now(),
+            accepting_requests: true,
+        };
+
+        // Browsing is gated by the same consent as fetching
+        let has_consent = self
+            .consent_manager
+            .check_consent(&peer_info, auth::LIST_NAMESPACES_SUBJECT)
+            .await
+            .unwrap_or(false);
+
+        if !has_consent {
+            tracing::info!(
+                "P2P namespace listing denied (no consent) from {}",
+                req.requester_hostname
+            );
+            return Ok(Response::new(ListNamespacesResponse {
+                consent_required: true,
+                namespaces: vec![],
+            }));
+        }
+
+        let cached = self
+            .namespaces
+            .read()
+            .await
+            .as_ref()
+            .filter(|(built, _)| built.elapsed() < NAMESPACES_REBUILD_INTERVAL)
+            .map(|(_, namespaces)| namespaces.clone());
+        let namespaces = match cached {
+            Some(namespaces) => namespaces,
+            None => {
+                let cache_dir = self.cache_dir.read().await.clone();
+                let namespaces = tokio::task::spawn_blocking(move || {
+                    summarize_namespaces(Path::new(&cache_dir))
+                })
+                .await
+                .map_err(|e| Status::internal(format!("Failed to summarize cache: {}", e)))?;
+                *self.namespaces.write().await = Some((Instant::now(), namespaces.clone()));
+                namespaces
+            }
+        };
+
+        tracing::info!(
+            "P2P namespace listing for {}: {} namespace(s)",
+            req.requester_hostname,
+            namespaces.len()
+        );
+
+        Ok(Response::new(ListNamespacesResponse {
+            consent_required: false,
+            namespaces,
+        }))
+    }
+
+    async fn get_digest(
+        &self,
+        request: Request<DigestRequest>,
+    ) -> Result<Response<DigestResponse>, Status> {
+        let req = request.into_inner();
+
+        // Verify authentication
+        if let Err(e) = self.verify_auth(
+            auth::DIGEST_SUBJECT,
+            req.timestamp,
+            &req.nonce,
+            &req.signature,
+        ) {
+            tracing::warn!("P2P auth failed: {}", e);
+            return Err(Status::unauthenticated(format!(
+                "Authentication failed: {}",
+                e
+            )));
+        }
+
+        let peer_info = PeerInfo {
+            machine_id: req.requester_id.clone(),
+            hostname: req.requester_hostname.clone(),
+            address: "0.0.0.0".parse().unwrap(),
+            port: 0,
+            last_seen: std::time::SystemTime::now(),
+            accepting_requests: true,
+        };
+
+        // The digest reveals (probabilistically) which artifacts we hold, so it is
+        // gated by the same consent as fetching
+        let has_consent = self
+            .consent_manager
+            .check_consent(&peer_info, auth::DIGEST_SUBJECT)
+            .await
+            .unwrap_or(false);
+
+        if !has_consent {
+            return Ok(Response::new(DigestResponse {
+                consent_required: true,
+                ..Default::default()
+            }));
+        }
+
+        if let Some((built, digest)) = self.digest.read().await.as_ref() {
+            if built.elapsed() < DIGEST_REBUILD_INTERVAL {
+                return Ok(Response::new(digest.clone()));
+            }
+        }
+
+        let cache_dir = self.cache_dir.read().await.clone();
+        let digest =
+            tokio::task::spawn_blocking(move || build_content_digest(Path::new(&cache_dir)))
+                .await
+                .map_err(|e| Status::internal(format!("Failed to build digest: {}", e)))?
+                .to_proto(auth::current_timestamp());
+
+        tracing::debug!(
+            "P2P digest for {}: {} artifact(s), {} bytes",
+            req.requester_hostname,
+            digest.item_count,
+            digest.bits.len()
+        );
+
+        *self.digest.write().await = Some((Instant::now(), digest.clone()));
+        Ok(Response::new(digest))
+    }
+
+    type BenchStream = ReceiverStream<Result<BenchResponse, Status>>;
+
+    async fn bench(
+        &self,
+        request: Request<BenchRequest>,
+    ) -> Result<Response<Self::BenchStream>, Status> {
+        let req = request.into_inner();
+
+        // Verify authentication
+        if let Err(e) = self.verify_auth(
+            auth::BENCH_SUBJECT,
+            req.timestamp,
+            &req.nonce,
+            &req.signature,
+        ) {
+            tracing::warn!("P2P auth failed: {}", e);
+            return Err(Status::unauthenticated(format!(
+                "Authentication failed: {}",
+                e
+            )));
+        }
+
+        // Filler bytes reveal nothing about the cache, so no consent is needed
+        let size = req.size_bytes.min(MAX_BENCH_BYTES) as usize;
+        tracing::debug!(
+            "P2P bench from {}: sending {} bytes",
+            req.requester_hostname,
+            size
+        );
+
+        let (tx, rx) = tokio::sync::mpsc::channel(128);
+        let throttle = self.upload_throttle.clone();
+        tokio::spawn(async move {
+            // Same chunk size and bandwidth limit as artifact transfers
+            const CHUNK_SIZE: usize = 32 * 1024;
+            let filler = vec![0u8; CHUNK_SIZE];
+            let mut remaining = size;
+            while remaining > 0 {
+                let len = remaining.min(CHUNK_SIZE);
+                if let Some(throttle) = &throttle {
+                    throttle.consume(len).await;
+                }
+                let response = BenchResponse {
+                    chunk: filler[..len].to_vec(),
+                };
+                if tx.send(Ok(response)).await.is_err() {
+                    break;
+                }
+                remaining -= len;
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Build a content digest over the artifacts in the cache (file names are hashes)
+fn build_content_digest(cache_dir: &Path) -> ContentDigest {
+    let hashes: Vec<String> = walkdir::WalkDir::new(cache_dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(is_shareable)
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+
+    ContentDigest::from_hashes(&hashes)
+}
+
+/// Namespace for artifacts stored directly in the cache root
+const ROOT_NAMESPACE: &str = "artifacts";
+
+/// Summarize the cache by namespace (top-level directory)
+///
+/// Only counts and sizes are reported, never artifact hashes. Hidden directories
+/// (e.g. lock files) and the cache's internal directories are skipped.
+fn summarize_namespaces(cache_dir: &Path) -> Vec<NamespaceSummary> {
+    let mut namespaces: BTreeMap<String, NamespaceSummary> = BTreeMap::new();
+
+    let walker = walkdir::WalkDir::new(cache_dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(is_shareable);
+
+    for entry in walker.filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let name = if entry.depth() == 1 {
+            ROOT_NAMESPACE.to_string()
+        } else {
+            entry
+                .path()
+                .strip_prefix(cache_dir)
+                .ok()
+                .and_then(|rel| rel.components().next())
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .unwrap_or_else(|| ROOT_NAMESPACE.to_string())
+        };
+
+        let metadata = entry.metadata().ok();
+        let size = metadata.as_ref().map(|m| m.len()).unwrap_or(0);
+        let modified = metadata
+            .and_then(|m| m.modified().ok())
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        let summary = namespaces
+            .entry(name.clone())
+            .or_insert_with(|| NamespaceSummary {
+                name,
+                ..Default::default()
+            });
+        summary.artifact_count += 1;
+        summary.approximate_size += size;
+        summary.last_modified = summary.last_modified.max(modified);
+    }
+
+    namespaces
+        .into_values()
+        .map(|mut summary| {
+            summary.approximate_size = summary.approximate_size.div_ceil(1024) * 1024;
+            summary
+        })
+        .collect()
+}
+
+/// Whether a cache entry may be listed to peers: not hidden, and not one of the
+/// internal top-level directories
+fn is_shareable(entry: &walkdir::DirEntry) -> bool {
+    let name = entry.file_name().to_string_lossy();
+    if name.starts_with('.') {
+        return false;
+    }
+    !(entry.depth() == 1 && entry.file_type().is_dir() && INTERNAL_DIRS.contains(&&*name))
 }
 
 impl P2PCacheService {
-    fn verify_auth(&self, hash: &str, timestamp: i64, signature: &[u8]) -> Result<()> {
+    fn verify_auth(&self, hash: &str, timestamp: i64, nonce: &str, signature: &[u8]) -> Result<()> {
         let secret = self
             .config
             .secret
             .as_ref()
             .context("P2P secret not configured")?;
 
-        auth::verify_request(secret, hash, timestamp, signature)
+        auth::verify_request(secret, hash, timestamp, nonce, signature)?;
+        self.replay_guard.check(nonce, timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_summarize_namespaces() {
+        let temp = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp.path().join("objects/ab")).unwrap();
+        std::fs::write(temp.path().join("objects/ab/abcd"), vec![0u8; 1500]).unwrap();
+        std::fs::write(temp.path().join("objects/ab/abef"), vec![0u8; 100]).unwrap();
+        std::fs::write(temp.path().join("deadbeef"), b"root artifact").unwrap();
+        std::fs::create_dir_all(temp.path().join(".locks")).unwrap();
+        std::fs::write(temp.path().join(".locks/key.lock"), b"{}").unwrap();
+        for internal in ["metadata", "chunks/ab"] {
+            std::fs::create_dir_all(temp.path().join(internal)).unwrap();
+            std::fs::write(temp.path().join(internal).join("000001.sst"), b"x").unwrap();
+        }
+
+        let namespaces = summarize_namespaces(temp.path());
+        let names: Vec<&str> = namespaces.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, vec!["artifacts", "objects"]);
+
+        let objects = &namespaces[1];
+        assert_eq!(objects.artifact_count, 2);
+        assert_eq!(objects.approximate_size, 2048);
+        assert!(objects.last_modified > 0);
+    }
+
+    #[test]
+    fn test_build_content_digest() {
+        let temp = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp.path().join("objects/ab")).unwrap();
+        std::fs::write(temp.path().join("objects/ab/abcd"), b"nested").unwrap();
+        std::fs::write(temp.path().join("deadbeef"), b"root artifact").unwrap();
+        std::fs::create_dir_all(temp.path().join(".locks")).unwrap();
+        std::fs::write(temp.path().join(".locks/secret"), b"{}").unwrap();
+
+        let digest = build_content_digest(temp.path());
+        assert_eq!(digest.item_count(), 2);
+        assert!(digest.might_contain("abcd"));
+        assert!(digest.might_contain("deadbeef"));
+    }
+
+    #[test]
+    fn test_summarize_missing_cache_dir() {
+        let temp = TempDir::new().unwrap();
+        assert!(summarize_namespaces(&temp.path().join("missing")).is_empty());
     }
 }
@@ -0,0 +1,178 @@
+#![allow(dead_code)] // P2P feature not fully integrated yet
+use crate::error::{FabrikError, Result, ResultExt};
+use crate::p2p::{P2PClient, Peer};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use tokio::sync::RwLock;
+
+/// Explicitly configured peers (`p2p.static_peers`), for networks where mDNS is blocked
+///
+/// Static peers are health-checked periodically with a Hello; only peers that answered
+/// the last check are handed out, so unreachable peers drop out of the peer table and
+/// come back once they answer again.
+pub struct StaticPeers {
+    /// Configured `host:port` addresses
+    addresses: Vec<String>,
+
+    /// Healthy peers, keyed by configured address
+    healthy: RwLock<HashMap<String, Peer>>,
+}
+
+impl StaticPeers {
+    pub fn new(addresses: Vec<String>) -> Self {
+        Self {
+            addresses,
+            healthy: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Configured peer addresses
+    pub fn addresses(&self) -> &[String] {
+        &self.addresses
+    }
+
+    /// Check every configured peer and update the set of healthy ones
+    pub async fn health_check(&self, client: &P2PClient) {
+        let mut checks = tokio::task::JoinSet::new();
+        for address in &self.addresses {
+            let address = address.clone();
+            let client = client.clone();
+
+            checks.spawn(async move {
+                let result = async {
+                    let socket_addr = resolve(&address).await?;
+                    client.probe(socket_addr).await
+                }
+                .await;
+                (address, result)
+            });
+        }
+
+        while let Some(Ok((address, result))) = checks.join_next().await {
+            let mut healthy = self.healthy.write().await;
+            match result {
+                Ok(info) => {
+                    if !healthy.contains_key(&address) {
+                        tracing::info!(
+                            "Static peer {} is up: {} ({})",
+                            address,
+                            info.hostname,
+                            info.machine_id
+                        );
+                    }
+                    healthy.insert(address, Peer::new(info));
+                }
+                Err(e) => {
+                    if healthy.remove(&address).is_some() {
+                        tracing::warn!("Static peer {} is down: {:#}", address, e);
+                    } else {
+                        tracing::debug!("Static peer {} is unreachable: {:#}", address, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Peers that answered the last health check
+    pub async fn get_peers(&self) -> Vec<Peer> {
+        self.healthy.read().await.values().cloned().collect()
+    }
+}
+
+/// Resolve a `host:port` address, preferring IPv4 like mDNS discovery does
+async fn resolve(address: &str) -> Result<SocketAddr> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host(address)
+        .await
+        .upstream_context(&format!("Failed to resolve {}", address))?
+        .collect();
+
+    addrs
+        .iter()
+        .find(|addr| addr.is_ipv4())
+        .or_else(|| addrs.first())
+        .copied()
+        .ok_or_else(|| {
+            FabrikError::unavailable(format!("{} did not resolve to any address", address))
+        })
+}
+
+/// Combine discovered and static peers, each machine once
+///
+/// A static peer that is also discovered via mDNS keeps its discovered entry.
+pub fn merge_peers(discovered: Vec<Peer>, static_peers: Vec<Peer>) -> Vec<Peer> {
+    let mut peers = discovered;
+    for peer in static_peers {
+        if !peers
+            .iter()
+            .any(|known| known.info.machine_id == peer.info.machine_id)
+        {
+            peers.push(peer);
+        }
+    }
+    peers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::PeerInfo;
+    use std::time::SystemTime;
+
+    fn peer(machine_id: &str, address: &str) -> Peer {
+        Peer::new(PeerInfo {
+            machine_id: machine_id.to_string(),
+            hostname: machine_id.to_string(),
+            address: address.parse().unwrap(),
+            port: 7071,
+            last_seen: SystemTime::now(),
+            accepting_requests: true,
+        })
+    }
+
+    #[test]
+    fn test_merge_peers_deduplicates_by_machine() {
+        let discovered = vec![peer("a", "192.168.1.2"), peer("b", "192.168.1.3")];
+        let static_peers = vec![peer("b", "10.0.0.3"), peer("c", "10.0.0.5")];
+
+        let merged = merge_peers(discovered, static_peers);
+        let addresses: Vec<String> = merged
+            .iter()
+            .map(|p| format!("{}@{}", p.info.machine_id, p.info.address))
+            .collect();
+        assert_eq!(
+            addresses,
+            vec!["a@192.168.1.2", "b@192.168.1.3", "c@10.0.0.5"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        assert_eq!(
+            resolve("10.0.0.5:7071").await.unwrap(),
+            "10.0.0.5:7071".parse::<SocketAddr>().unwrap()
+        );
+        assert!(resolve("localhost:7071").await.unwrap().ip().is_loopback());
+        assert!(resolve("10.0.0.5").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_peer_is_not_healthy() {
+        // Nothing listens on a port we just released
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let config = crate::config::P2PConfig {
+            secret: Some("p2p-shared-secret".to_string()),
+            ..Default::default()
+        };
+        let client = P2PClient::new(
+            std::sync::Arc::new(config),
+            crate::grpc_transport::GrpcTransport::default(),
+        );
+        let peers = StaticPeers::new(vec![address]);
+
+        peers.health_check(&client).await;
+        assert!(peers.get_peers().await.is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+#![allow(dead_code)] // P2P feature not fully integrated yet
+/// Persistent per-peer transfer statistics
+///
+/// Tracks, for each peer, how often it was asked for artifacts, how often it had them,
+/// how many bytes it delivered and how often it failed, plus the last `fabrik p2p bench`
+/// result. The table lives in the XDG data directory next to the consents and is shared
+/// by every Fabrik process on the machine: each process only keeps the changes since its
+/// last save and merges them into the file when saving.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Statistics for one peer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerStats {
+    /// Last known hostname
+    pub hostname: String,
+
+    /// Artifact requests sent to the peer
+    pub requests: u64,
+
+    /// Requests the peer answered with the artifact
+    pub hits: u64,
+
+    /// Requests that failed (connection errors, timeouts, denied consent)
+    pub failures: u64,
+
+    /// Bytes fetched from the peer
+    pub bytes_fetched: u64,
+
+    /// UNIX timestamp of the last request
+    pub last_request_at: Option<i64>,
+
+    /// Last benchmark result
+    pub bench: Option<BenchStats>,
+}
+
+impl PeerStats {
+    /// Fraction of requests the peer answered with the artifact (0.0 to 1.0)
+    pub fn hit_rate(&self) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.requests as f64
+        }
+    }
+
+    /// Add the counters of `delta`; newer hostname, timestamps and bench result win
+    fn merge(&mut self, delta: &PeerStats) {
+        if !delta.hostname.is_empty() {
+            self.hostname = delta.hostname.clone();
+        }
+        self.requests += delta.requests;
+        self.hits += delta.hits;
+        self.failures += delta.failures;
+        self.bytes_fetched += delta.bytes_fetched;
+        self.last_request_at = self.last_request_at.max(delta.last_request_at);
+        if delta.bench.is_some() {
+            self.bench = delta.bench.clone();
+        }
+    }
+}
+
+/// Result of `fabrik p2p bench` against a peer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchStats {
+    /// Median round-trip time of a Hello, in milliseconds
+    pub latency_ms: f64,
+
+    /// Transfer throughput in bytes per second
+    pub throughput_bytes_per_sec: f64,
+
+    /// UNIX timestamp of the measurement
+    pub measured_at: i64,
+}
+
+/// Per-peer statistics table, keyed by machine ID
+pub struct PeerStatsStore {
+    /// File the table is persisted to (None: in memory only)
+    path: Option<PathBuf>,
+
+    /// Changes since the last save
+    pending: Mutex<HashMap<String, PeerStats>>,
+}
+
+impl PeerStatsStore {
+    /// Open the table in the XDG data directory
+    pub fn open() -> Self {
+        let path =
+            dirs::data_dir().map(|dir| dir.join("fabrik").join("p2p").join("peer-stats.json"));
+        Self::with_path(path)
+    }
+
+    /// Open the table at `path` (None: keep statistics in memory only)
+    pub fn with_path(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a request the peer answered with `bytes` of artifact
+    pub fn record_hit(&self, machine_id: &str, hostname: &str, bytes: u64) {
+        self.update(machine_id, hostname, |stats| {
+            stats.requests += 1;
+            stats.hits += 1;
+            stats.bytes_fetched += bytes;
+        });
+    }
+
+    /// Record a request the peer didn't have the artifact for
+    pub fn record_miss(&self, machine_id: &str, hostname: &str) {
+        self.update(machine_id, hostname, |stats| stats.requests += 1);
+    }
+
+    /// Record a request that failed
+    pub fn record_failure(&self, machine_id: &str, hostname: &str) {
+        self.update(machine_id, hostname, |stats| {
+            stats.requests += 1;
+            stats.failures += 1;
+        });
+    }
+
+    /// Record a benchmark result
+    pub fn record_bench(&self, machine_id: &str, hostname: &str, bench: BenchStats) {
+        if machine_id.is_empty() {
+            return;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        let stats = pending.entry(machine_id.to_string()).or_default();
+        stats.hostname = hostname.to_string();
+        stats.bench = Some(bench);
+    }
+
+    fn update(&self, machine_id: &str, hostname: &str, f: impl FnOnce(&mut PeerStats)) {
+        if machine_id.is_empty() {
+            return;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        let stats = pending.entry(machine_id.to_string()).or_default();
+        stats.hostname = hostname.to_string();
+        stats.last_request_at = Some(chrono::Utc::now().timestamp());
+        f(stats);
+    }
+
+    /// Persisted statistics plus unsaved changes
+    pub fn snapshot(&self) -> Result<BTreeMap<String, PeerStats>> {
+        let mut table = match &self.path {
+            Some(path) => load(path)?,
+            None => BTreeMap::new(),
+        };
+        for (machine_id, delta) in self.pending.lock().unwrap().iter() {
+            table.entry(machine_id.clone()).or_default().merge(delta);
+        }
+        Ok(table)
+    }
+
+    /// Merge unsaved changes into the file
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut pending = self.pending.lock().unwrap();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut table = load(path)?;
+        for (machine_id, delta) in pending.iter() {
+            table.entry(machine_id.clone()).or_default().merge(delta);
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create P2P data directory")?;
+        }
+        // Write to a temporary file and rename so readers never see a partial table
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&table)?)
+            .context("Failed to write peer statistics")?;
+        fs::rename(&tmp_path, path).context("Failed to write peer statistics")?;
+
+        pending.clear();
+        Ok(())
+    }
+}
+
+fn load(path: &Path) -> Result<BTreeMap<String, PeerStats>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let data = fs::read_to_string(path).context("Failed to read peer statistics")?;
+    // A corrupt table is not worth failing over; start over
+    Ok(serde_json::from_str(&data).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_counters_and_hit_rate() {
+        let store = PeerStatsStore::with_path(None);
+        store.record_hit("m1", "alice", 1000);
+        store.record_hit("m1", "alice", 500);
+        store.record_miss("m1", "alice");
+        store.record_failure("m1", "alice");
+
+        let table = store.snapshot().unwrap();
+        let stats = &table["m1"];
+        assert_eq!(stats.hostname, "alice");
+        assert_eq!(stats.requests, 4);
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.failures, 1);
+        assert_eq!(stats.bytes_fetched, 1500);
+        assert_eq!(stats.hit_rate(), 0.5);
+        assert!(stats.last_request_at.is_some());
+    }
+
+    #[test]
+    fn test_saves_merge_across_processes() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("peer-stats.json");
+
+        // Two processes sharing the table
+        let daemon = PeerStatsStore::with_path(Some(path.clone()));
+        let cli = PeerStatsStore::with_path(Some(path.clone()));
+
+        daemon.record_hit("m1", "alice", 100);
+        daemon.save().unwrap();
+
+        cli.record_bench(
+            "m1",
+            "alice",
+            BenchStats {
+                latency_ms: 1.5,
+                throughput_bytes_per_sec: 1e8,
+                measured_at: 1,
+            },
+        );
+        cli.save().unwrap();
+
+        daemon.record_hit("m1", "alice", 200);
+        daemon.save().unwrap();
+        daemon.save().unwrap(); // Nothing pending: no double counting
+
+        let table = PeerStatsStore::with_path(Some(path)).snapshot().unwrap();
+        let stats = &table["m1"];
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.bytes_fetched, 300);
+        assert_eq!(stats.bench.as_ref().unwrap().latency_ms, 1.5);
+    }
+
+    #[test]
+    fn test_unidentified_peers_are_not_tracked() {
+        let store = PeerStatsStore::with_path(None);
+        store.record_miss("", "10.0.0.5");
+        assert!(store.snapshot().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,442 @@
+#![allow(dead_code)] // P2P feature not fully integrated yet
+/// Encrypted transport for P2P gRPC connections
+///
+/// HMAC signatures authenticate requests, but on their own they leave artifacts and
+/// request metadata readable by anyone on the LAN. Every P2P connection is therefore
+/// wrapped in a Noise session (`NNpsk0`) keyed by a pre-shared key derived from the
+/// shared secret: peers without the secret fail the handshake, and all gRPC traffic is
+/// encrypted with ChaCha20-Poly1305 under per-connection ephemeral keys.
+///
+/// On the wire, each Noise message is prefixed with its length as a big-endian u16.
+use crate::p2p::auth;
+use anyhow::{anyhow, Context, Result};
+use hyper_util::rt::TokioIo;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context as TaskContext, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::{Connected, TcpConnectInfo};
+use tonic::transport::Uri;
+
+/// Noise protocol: no static keys, PSK mixed into the first message
+const NOISE_PARAMS: &str = "Noise_NNpsk0_25519_ChaChaPoly_SHA256";
+
+/// Bound into the handshake so sessions can't be confused with other Noise protocols
+const PROLOGUE: &[u8] = b"fabrik-p2p/1";
+
+/// HMAC label used to derive the transport key from the shared secret
+const PSK_LABEL: &str = "fabrik-p2p-transport-psk-v1";
+
+/// Largest Noise message (including the authentication tag)
+const MAX_MESSAGE_LEN: usize = 65535;
+
+/// ChaCha20-Poly1305 authentication tag
+const TAG_LEN: usize = 16;
+
+/// Largest plaintext carried by one message
+const MAX_CHUNK_LEN: usize = MAX_MESSAGE_LEN - TAG_LEN;
+
+/// How long a peer has to complete the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Derive the 32-byte Noise pre-shared key from the P2P shared secret
+///
+/// Kept separate from the request signing key so the two uses can't interfere.
+pub fn derive_psk(secret: &str) -> [u8; 32] {
+    auth::compute_signature(secret, PSK_LABEL)
+        .try_into()
+        .expect("HMAC-SHA256 output is 32 bytes")
+}
+
+fn builder(psk: &[u8; 32]) -> Result<snow::Builder<'_>> {
+    let params = NOISE_PARAMS
+        .parse()
+        .map_err(|e| anyhow!("Invalid Noise parameters: {:?}", e))?;
+    Ok(snow::Builder::new(params).prologue(PROLOGUE).psk(0, psk))
+}
+
+/// Run the initiator side of the handshake (client)
+pub async fn connect<S>(stream: S, psk: &[u8; 32]) -> Result<NoiseStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, async move {
+        let mut stream = stream;
+        let mut handshake = builder(psk)?.build_initiator()?;
+        let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+
+        // -> e, psk
+        let len = handshake.write_message(&[], &mut buf)?;
+        write_frame(&mut stream, &buf[..len]).await?;
+
+        // <- e, ee
+        let message = read_frame(&mut stream).await?;
+        handshake
+            .read_message(&message, &mut buf)
+            .map_err(|_| anyhow!("Peer rejected the handshake (shared secrets differ?)"))?;
+
+        Ok(NoiseStream::new(stream, handshake.into_transport_mode()?))
+    })
+    .await
+    .map_err(|_| anyhow!("P2P handshake timed out"))?
+}
+
+/// Run the responder side of the handshake (server)
+pub async fn accept<S>(stream: S, psk: &[u8; 32]) -> Result<NoiseStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, async move {
+        let mut stream = stream;
+        let mut handshake = builder(psk)?.build_responder()?;
+        let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+
+        // -> e, psk (fails unless the initiator knows the shared secret)
+        let message = read_frame(&mut stream).await?;
+        handshake
+            .read_message(&message, &mut buf)
+            .map_err(|_| anyhow!("Invalid handshake (shared secrets differ?)"))?;
+
+        // <- e, ee
+        let len = handshake.write_message(&[], &mut buf)?;
+        write_frame(&mut stream, &buf[..len]).await?;
+
+        Ok(NoiseStream::new(stream, handshake.into_transport_mode()?))
+    })
+    .await
+    .map_err(|_| anyhow!("P2P handshake timed out"))?
+}
+
+/// Connect to a peer and run the handshake (tonic connector)
+pub async fn connect_uri(uri: Uri, psk: [u8; 32]) -> io::Result<TokioIo<NoiseStream<TcpStream>>> {
+    let host = uri
+        .host()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Peer URI has no host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = uri.port_u16().unwrap_or(80);
+
+    let stream = TcpStream::connect((host, port)).await?;
+    stream.set_nodelay(true)?;
+
+    connect(stream, &psk)
+        .await
+        .map(TokioIo::new)
+        .map_err(io::Error::other)
+}
+
+/// Accept connections on `listener`, yielding those that complete the handshake
+///
+/// Handshakes run concurrently so a slow or hostile client can't stall the accept
+/// loop. Connections that fail the handshake (wrong secret, plaintext gRPC) are
+/// dropped.
+pub fn incoming(
+    listener: TcpListener,
+    psk: [u8; 32],
+) -> ReceiverStream<io::Result<NoiseStream<TcpStream>>> {
+    let (tx, rx) = mpsc::channel(32);
+
+    tokio::spawn(async move {
+        while !tx.is_closed() {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Usually file descriptor exhaustion; back off instead of spinning
+                    tracing::warn!("P2P accept failed: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let _ = stream.set_nodelay(true);
+                match accept(stream, &psk).await {
+                    Ok(stream) => {
+                        let _ = tx.send(Ok(stream)).await;
+                    }
+                    Err(e) => tracing::warn!("P2P connection from {} rejected: {}", addr, e),
+                }
+            });
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+async fn write_frame<S: AsyncWrite + Unpin>(stream: &mut S, message: &[u8]) -> Result<()> {
+    stream
+        .write_all(&(message.len() as u16).to_be_bytes())
+        .await?;
+    stream.write_all(message).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    stream
+        .read_exact(&mut len)
+        .await
+        .context("Connection closed during handshake")?;
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream
+        .read_exact(&mut message)
+        .await
+        .context("Connection closed during handshake")?;
+    Ok(message)
+}
+
+fn crypto_error(e: snow::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("P2P transport: {}", e))
+}
+
+/// A stream encrypted with an established Noise session
+pub struct NoiseStream<S> {
+    inner: S,
+    transport: snow::TransportState,
+    /// Ciphertext received but not yet decrypted (incomplete frame)
+    read_buf: Vec<u8>,
+    /// Decrypted data not yet handed to the reader
+    plaintext: Vec<u8>,
+    plaintext_pos: usize,
+    /// Encrypted frame not yet fully written to `inner`
+    write_buf: Vec<u8>,
+    write_pos: usize,
+}
+
+impl<S> NoiseStream<S> {
+    fn new(inner: S, transport: snow::TransportState) -> Self {
+        Self {
+            inner,
+            transport,
+            read_buf: Vec::new(),
+            plaintext: Vec::new(),
+            plaintext_pos: 0,
+            write_buf: Vec::new(),
+            write_pos: 0,
+        }
+    }
+
+    /// Length of the first frame in `read_buf`, if it has been fully received
+    fn complete_frame_len(&self) -> Option<usize> {
+        let header = self.read_buf.get(..2)?;
+        let len = u16::from_be_bytes([header[0], header[1]]) as usize;
+        (self.read_buf.len() >= 2 + len).then_some(len)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> NoiseStream<S> {
+    /// Write out the pending encrypted frame
+    fn poll_write_pending(&mut self, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        while self.write_pos < self.write_buf.len() {
+            let n = ready!(
+                Pin::new(&mut self.inner).poll_write(cx, &self.write_buf[self.write_pos..])
+            )?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_pos += n;
+        }
+        self.write_buf.clear();
+        self.write_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for NoiseStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.plaintext_pos < this.plaintext.len() {
+                let available = &this.plaintext[this.plaintext_pos..];
+                let n = available.len().min(buf.remaining());
+                buf.put_slice(&available[..n]);
+                this.plaintext_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+
+            if let Some(len) = this.complete_frame_len() {
+                this.plaintext.resize(MAX_MESSAGE_LEN, 0);
+                let n = this
+                    .transport
+                    .read_message(&this.read_buf[2..2 + len], &mut this.plaintext)
+                    .map_err(crypto_error)?;
+                this.plaintext.truncate(n);
+                this.plaintext_pos = 0;
+                this.read_buf.drain(..2 + len);
+                continue;
+            }
+
+            let mut chunk = [0u8; 8192];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+            if chunk_buf.filled().is_empty() {
+                return Poll::Ready(if this.read_buf.is_empty() {
+                    Ok(())
+                } else {
+                    Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "P2P connection closed mid-message",
+                    ))
+                });
+            }
+            this.read_buf.extend_from_slice(chunk_buf.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for NoiseStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let n = buf.len().min(MAX_CHUNK_LEN);
+        this.write_buf.resize(2 + n + TAG_LEN, 0);
+        let len = this
+            .transport
+            .write_message(&buf[..n], &mut this.write_buf[2..])
+            .map_err(crypto_error)?;
+        this.write_buf.truncate(2 + len);
+        this.write_buf[..2].copy_from_slice(&(len as u16).to_be_bytes());
+
+        // Start sending right away; whatever doesn't fit goes out on the next write/flush
+        if let Poll::Ready(Err(e)) = this.poll_write_pending(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+impl Connected for NoiseStream<TcpStream> {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.inner.connect_info()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "a-shared-secret-of-some-length";
+
+    #[tokio::test]
+    async fn test_roundtrip_large_payload() {
+        let (client, server) = tokio::io::duplex(4096);
+        let psk = derive_psk(SECRET);
+
+        let server = tokio::spawn(async move {
+            let mut stream = accept(server, &psk).await.unwrap();
+            let mut received = vec![0u8; 200_000];
+            stream.read_exact(&mut received).await.unwrap();
+            stream.write_all(b"ack").await.unwrap();
+            stream.flush().await.unwrap();
+            received
+        });
+
+        let payload: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let mut stream = connect(client, &psk).await.unwrap();
+        stream.write_all(&payload).await.unwrap();
+        stream.flush().await.unwrap();
+
+        let mut ack = [0u8; 3];
+        stream.read_exact(&mut ack).await.unwrap();
+        assert_eq!(&ack, b"ack");
+        assert_eq!(server.await.unwrap(), payload);
+    }
+
+    #[tokio::test]
+    async fn test_traffic_is_encrypted() {
+        let (client, server) = tokio::io::duplex(4096);
+        let psk = derive_psk(SECRET);
+
+        let server = tokio::spawn(async move { accept(server, &psk).await.unwrap() });
+        let mut client = connect(client, &psk).await.unwrap();
+        let mut server = server.await.unwrap();
+
+        client.write_all(b"secret artifact contents").await.unwrap();
+        client.shutdown().await.unwrap();
+
+        // What an eavesdropper sees
+        let mut wire = Vec::new();
+        server.inner.read_to_end(&mut wire).await.unwrap();
+        assert!(!wire.windows(6).any(|w| w == b"secret"));
+
+        // What the peer sees
+        server.read_buf = wire;
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"secret artifact contents");
+    }
+
+    #[tokio::test]
+    async fn test_wrong_secret_fails_handshake() {
+        let (client, server) = tokio::io::duplex(4096);
+
+        let server = tokio::spawn(async move { accept(server, &derive_psk(SECRET)).await });
+        let client = connect(client, &derive_psk("a-different-secret-entirely")).await;
+
+        assert!(server.await.unwrap().is_err());
+        assert!(client.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_incoming_over_tcp() {
+        use tokio_stream::StreamExt;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri: Uri = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let mut incoming = incoming(listener, derive_psk(SECRET));
+
+        // Peers with another secret never make it past the handshake
+        assert!(
+            connect_uri(uri.clone(), derive_psk("a-different-secret-entirely"))
+                .await
+                .is_err()
+        );
+
+        let mut client = connect_uri(uri, derive_psk(SECRET))
+            .await
+            .unwrap()
+            .into_inner();
+        client.write_all(b"hello").await.unwrap();
+        client.flush().await.unwrap();
+
+        let mut server = incoming.next().await.unwrap().unwrap();
+        let mut received = [0u8; 5];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"hello");
+    }
+}
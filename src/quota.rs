@@ -0,0 +1,212 @@
+/// Upload size limits and per-client daily upload quotas
+///
+/// Protects a shared cache from runaway jobs: every upload is checked against the
+/// maximum artifact size for its protocol, and its size is charged against the
+/// uploading client's daily quota (reset at midnight UTC). Clients are identified the
+/// same way as for rate limiting (JWT `sub`, else remote IP).
+///
+/// Services hold an [`UploadLimits`] (unlimited by default) and call
+/// [`UploadLimits::check_upload`] before storing data.
+use anyhow::{Context, Result};
+use chrono::{NaiveDate, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use thiserror::Error;
+
+use crate::config::LimitsConfig;
+use crate::eviction::EvictionConfig;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum QuotaError {
+    #[error("Artifact of {size} bytes exceeds the {max} byte limit for {service}")]
+    TooLarge {
+        service: String,
+        size: u64,
+        max: u64,
+    },
+
+    #[error("Daily upload quota of {quota} bytes exceeded ({used} bytes used today)")]
+    QuotaExceeded { used: u64, quota: u64 },
+}
+
+impl From<QuotaError> for tonic::Status {
+    fn from(e: QuotaError) -> Self {
+        tonic::Status::resource_exhausted(e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DailyUsage {
+    day: NaiveDate,
+    bytes: u64,
+}
+
+/// Upload limits shared by all protocol services
+#[derive(Debug, Default)]
+pub struct UploadLimits {
+    max_artifact_size: Option<u64>,
+    max_artifact_size_by_protocol: HashMap<String, u64>,
+    daily_upload_quota: Option<u64>,
+    usage: Mutex<HashMap<String, DailyUsage>>,
+}
+
+impl UploadLimits {
+    /// No limits (the default when nothing is configured)
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: &LimitsConfig) -> Result<Self> {
+        let parse = |value: &str| {
+            EvictionConfig::parse_size(value).with_context(|| format!("Invalid size: {}", value))
+        };
+
+        Ok(Self {
+            max_artifact_size: config.max_artifact_size.as_deref().map(parse).transpose()?,
+            max_artifact_size_by_protocol: config
+                .max_artifact_size_by_protocol
+                .iter()
+                .map(|(protocol, size)| Ok((protocol.clone(), parse(size)?)))
+                .collect::<Result<_>>()?,
+            daily_upload_quota: config
+                .daily_upload_quota
+                .as_deref()
+                .map(parse)
+                .transpose()?,
+            usage: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Whether any limit is configured
+    pub fn is_enabled(&self) -> bool {
+        self.max_artifact_size.is_some()
+            || !self.max_artifact_size_by_protocol.is_empty()
+            || self.daily_upload_quota.is_some()
+    }
+
+    /// Maximum artifact size for a protocol/service, if limited
+    pub fn max_artifact_size(&self, service: &str) -> Option<u64> {
+        self.max_artifact_size_by_protocol
+            .get(service)
+            .copied()
+            .or(self.max_artifact_size)
+    }
+
+    /// Check an upload of `size` bytes by `client` and charge it to the client's quota
+    pub fn check_upload(&self, service: &str, client: &str, size: u64) -> Result<(), QuotaError> {
+        if let Some(max) = self.max_artifact_size(service) {
+            if size > max {
+                return Err(QuotaError::TooLarge {
+                    service: service.to_string(),
+                    size,
+                    max,
+                });
+            }
+        }
+
+        if let Some(quota) = self.daily_upload_quota {
+            let today = Utc::now().date_naive();
+            let mut usage = self.usage.lock().unwrap();
+
+            // Drop yesterday's counters so the map doesn't grow without bound
+            usage.retain(|_, u| u.day == today);
+
+            let entry = usage.entry(client.to_string()).or_insert(DailyUsage {
+                day: today,
+                bytes: 0,
+            });
+            if entry.bytes.saturating_add(size) > quota {
+                return Err(QuotaError::QuotaExceeded {
+                    used: entry.bytes,
+                    quota,
+                });
+            }
+            entry.bytes += size;
+        }
+
+        Ok(())
+    }
+
+    /// Bytes uploaded by `client` today
+    #[allow(dead_code)]
+    pub fn used_today(&self, client: &str) -> u64 {
+        let today = Utc::now().date_naive();
+        self.usage
+            .lock()
+            .unwrap()
+            .get(client)
+            .filter(|u| u.day == today)
+            .map(|u| u.bytes)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn limits(
+        max: Option<&str>,
+        by_protocol: &[(&str, &str)],
+        quota: Option<&str>,
+    ) -> UploadLimits {
+        UploadLimits::from_config(&LimitsConfig {
+            max_artifact_size: max.map(String::from),
+            max_artifact_size_by_protocol: by_protocol
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+            daily_upload_quota: quota.map(String::from),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_unlimited_by_default() {
+        let limits = UploadLimits::unlimited();
+        assert!(!limits.is_enabled());
+        assert!(limits.check_upload("bazel", "ci", u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn test_per_protocol_size_limit() {
+        let limits = limits(Some("1MB"), &[("bazel", "10MB")], None);
+
+        assert!(limits.check_upload("bazel", "ci", 5 * 1024 * 1024).is_ok());
+        assert_eq!(
+            limits.check_upload("gradle", "ci", 5 * 1024 * 1024),
+            Err(QuotaError::TooLarge {
+                service: "gradle".to_string(),
+                size: 5 * 1024 * 1024,
+                max: 1024 * 1024,
+            })
+        );
+    }
+
+    #[test]
+    fn test_daily_quota_per_client() {
+        let limits = limits(None, &[], Some("1KB"));
+
+        assert!(limits.check_upload("nx", "ci-1", 600).is_ok());
+        assert!(matches!(
+            limits.check_upload("nx", "ci-1", 600),
+            Err(QuotaError::QuotaExceeded {
+                used: 600,
+                quota: 1024
+            })
+        ));
+        // Rejected uploads are not charged; other clients have their own quota
+        assert_eq!(limits.used_today("ci-1"), 600);
+        assert!(limits.check_upload("nx", "ci-2", 600).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_size_rejected() {
+        assert!(UploadLimits::from_config(&LimitsConfig {
+            max_artifact_size: Some("lots".to_string()),
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,573 @@
+/// Per-client rate limiting for the cache server (Layer 2)
+///
+/// A misbehaving CI fleet can overwhelm a regional server, so each client identity
+/// gets its own token bucket (sustained requests/second plus a burst allowance) and a
+/// cap on requests in flight. Throttled gRPC requests fail with `RESOURCE_EXHAUSTED`,
+/// HTTP requests with `429 Too Many Requests`; both carry a `Retry-After` header.
+///
+/// Clients are identified by the [`ClientIdentity`] attached by the authentication
+/// layer (the JWT `sub`), falling back to the remote IP address.
+///
+/// A request counts as in flight until its response body ends, so streamed downloads
+/// count for as long as they stream.
+use axum::http::{header, HeaderValue, Request, Response, StatusCode};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower::{Layer, Service};
+
+/// Number of tracked clients above which idle clients are pruned
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Time after which a client with no requests in flight is forgotten
+const IDLE_CLIENT_TTL: Duration = Duration::from_secs(300);
+
+/// Rate limiting settings (from `[runtime]`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RateLimitConfig {
+    /// Sustained requests per second per client (0 = unlimited)
+    pub requests_per_second: u32,
+    /// Bucket size, i.e. requests allowed in a burst (0 = same as `requests_per_second`)
+    pub burst: u32,
+    /// Maximum requests in flight per client (0 = unlimited)
+    pub max_in_flight: u32,
+}
+
+impl RateLimitConfig {
+    /// Whether any limit is configured
+    pub fn is_enabled(&self) -> bool {
+        self.requests_per_second > 0 || self.max_in_flight > 0
+    }
+
+    fn bucket_size(&self) -> f64 {
+        if self.burst > 0 {
+            self.burst as f64
+        } else {
+            self.requests_per_second as f64
+        }
+    }
+}
+
+/// Identity of the client making a request, attached as a request extension by the
+/// authentication layer (JWT `sub`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClientIdentity(pub String);
+
+/// Why a request was throttled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Throttled {
+    /// Token bucket empty
+    RateLimited { retry_after: Duration },
+    /// Too many requests in flight
+    TooManyInFlight { retry_after: Duration },
+}
+
+impl Throttled {
+    pub fn retry_after(&self) -> Duration {
+        match self {
+            Throttled::RateLimited { retry_after } | Throttled::TooManyInFlight { retry_after } => {
+                *retry_after
+            }
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Throttled::RateLimited { .. } => "Rate limit exceeded",
+            Throttled::TooManyInFlight { .. } => "Too many concurrent requests",
+        }
+    }
+}
+
+/// Counters for throttled requests
+#[derive(Debug, Default)]
+pub struct RateLimitMetrics {
+    allowed_total: AtomicU64,
+    rate_limited_total: AtomicU64,
+    concurrency_limited_total: AtomicU64,
+}
+
+impl RateLimitMetrics {
+    pub fn allowed_total(&self) -> u64 {
+        self.allowed_total.load(Ordering::Relaxed)
+    }
+
+    pub fn rate_limited_total(&self) -> u64 {
+        self.rate_limited_total.load(Ordering::Relaxed)
+    }
+
+    pub fn concurrency_limited_total(&self) -> u64 {
+        self.concurrency_limited_total.load(Ordering::Relaxed)
+    }
+
+    /// Export metrics in Prometheus format
+    pub fn export_prometheus(&self) -> String {
+        format!(
+            r#"# HELP fabrik_requests_allowed_total Requests admitted by the rate limiter
+# TYPE fabrik_requests_allowed_total counter
+fabrik_requests_allowed_total {}
+
+# HELP fabrik_requests_throttled_total Requests rejected by the rate limiter
+# TYPE fabrik_requests_throttled_total counter
+fabrik_requests_throttled_total{{reason="rate"}} {}
+fabrik_requests_throttled_total{{reason="in_flight"}} {}
+"#,
+            self.allowed_total(),
+            self.rate_limited_total(),
+            self.concurrency_limited_total(),
+        )
+    }
+}
+
+struct ClientState {
+    tokens: f64,
+    last_refill: Instant,
+    in_flight: u32,
+}
+
+/// Token-bucket rate limiter keyed by client identity
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    clients: Mutex<HashMap<String, ClientState>>,
+    metrics: RateLimitMetrics,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            clients: Mutex::new(HashMap::new()),
+            metrics: RateLimitMetrics::default(),
+        }
+    }
+
+    pub fn metrics(&self) -> &RateLimitMetrics {
+        &self.metrics
+    }
+
+    /// Admit a request from `client`, returning a permit that must be held until the
+    /// request completes
+    pub fn try_acquire(self: &Arc<Self>, client: &str) -> Result<InFlightPermit, Throttled> {
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients.len() > PRUNE_THRESHOLD {
+            clients.retain(|_, state| {
+                state.in_flight > 0 || now.duration_since(state.last_refill) < IDLE_CLIENT_TTL
+            });
+        }
+
+        let bucket_size = self.config.bucket_size();
+        let state = clients
+            .entry(client.to_string())
+            .or_insert_with(|| ClientState {
+                tokens: bucket_size,
+                last_refill: now,
+                in_flight: 0,
+            });
+
+        if self.config.max_in_flight > 0 && state.in_flight >= self.config.max_in_flight {
+            self.metrics
+                .concurrency_limited_total
+                .fetch_add(1, Ordering::Relaxed);
+            return Err(Throttled::TooManyInFlight {
+                retry_after: Duration::from_secs(1),
+            });
+        }
+
+        if self.config.requests_per_second > 0 {
+            let rate = self.config.requests_per_second as f64;
+            let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+            state.tokens = (state.tokens + elapsed * rate).min(bucket_size);
+            state.last_refill = now;
+
+            if state.tokens < 1.0 {
+                self.metrics
+                    .rate_limited_total
+                    .fetch_add(1, Ordering::Relaxed);
+                let wait = ((1.0 - state.tokens) / rate).ceil().max(1.0);
+                return Err(Throttled::RateLimited {
+                    retry_after: Duration::from_secs(wait as u64),
+                });
+            }
+            state.tokens -= 1.0;
+        } else {
+            state.last_refill = now;
+        }
+
+        state.in_flight += 1;
+        self.metrics.allowed_total.fetch_add(1, Ordering::Relaxed);
+
+        Ok(InFlightPermit {
+            limiter: self.clone(),
+            client: client.to_string(),
+        })
+    }
+}
+
+/// Marks a request as in flight for its client until dropped
+pub struct InFlightPermit {
+    limiter: Arc<RateLimiter>,
+    client: String,
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        let mut clients = self.limiter.clients.lock().unwrap();
+        if let Some(state) = clients.get_mut(&self.client) {
+            state.in_flight = state.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+/// Response body holding the [`InFlightPermit`] of its request until it ends
+pub struct PermitBody<B> {
+    inner: Pin<Box<B>>,
+    permit: Option<InFlightPermit>,
+}
+
+impl<B> PermitBody<B> {
+    fn new(inner: B, permit: Option<InFlightPermit>) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            permit,
+        }
+    }
+}
+
+impl<B: Default> Default for PermitBody<B> {
+    fn default() -> Self {
+        Self::new(B::default(), None)
+    }
+}
+
+impl<B: HttpBody> HttpBody for PermitBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = std::task::ready!(self.inner.as_mut().poll_frame(cx));
+        // Release the permit as soon as the body ends, not when it's dropped
+        if frame.is_none() || self.inner.is_end_stream() {
+            self.permit = None;
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Tower layer applying a [`RateLimiter`] to gRPC (tonic) and HTTP (axum) services
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RateLimitService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<PermitBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        if !self.limiter.config.is_enabled() {
+            let future = self.inner.call(request);
+            return Box::pin(async move {
+                let response = future.await?;
+                Ok(response.map(|body| PermitBody::new(body, None)))
+            });
+        }
+
+        let client = client_identity(&request);
+
+        match self.limiter.try_acquire(&client) {
+            Ok(permit) => {
+                let future = self.inner.call(request);
+                // The permit is released when the response body ends, or right away if
+                // the service fails
+                Box::pin(async move {
+                    let response = future.await?;
+                    Ok(response.map(|body| PermitBody::new(body, Some(permit))))
+                })
+            }
+            Err(throttled) => {
+                tracing::debug!("Throttled request from {}: {:?}", client, throttled);
+                let response = throttled_response(is_grpc(&request), throttled);
+                Box::pin(std::future::ready(Ok(response)))
+            }
+        }
+    }
+}
+
+/// Identify the client of an HTTP request: authenticated identity, else remote IP
+pub fn client_identity<B>(request: &Request<B>) -> String {
+    if let Some(identity) = request.extensions().get::<ClientIdentity>() {
+        return identity.0.clone();
+    }
+
+    let remote_addr = request
+        .extensions()
+        .get::<tonic::transport::server::TcpConnectInfo>()
+        .and_then(|info| info.remote_addr())
+        .or_else(|| {
+            request
+                .extensions()
+                .get::<axum::extract::ConnectInfo<SocketAddr>>()
+                .map(|info| info.0)
+        });
+
+    match remote_addr {
+        Some(addr) => addr.ip().to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Identify the client of a gRPC request: authenticated identity, else remote IP
+pub fn grpc_client_identity<T>(request: &tonic::Request<T>) -> String {
+    if let Some(identity) = request.extensions().get::<ClientIdentity>() {
+        return identity.0.clone();
+    }
+
+    match request.remote_addr() {
+        Some(addr) => addr.ip().to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Whether an HTTP request carries a gRPC call
+pub(crate) fn is_grpc<B>(request: &Request<B>) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/grpc"))
+}
+
+/// Build the rejection: a trailers-only `RESOURCE_EXHAUSTED` for gRPC, 429 for HTTP
+fn throttled_response<B: Default>(grpc: bool, throttled: Throttled) -> Response<B> {
+    let mut response = Response::new(B::default());
+    let retry_after = HeaderValue::from(throttled.retry_after().as_secs());
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, retry_after);
+
+    if grpc {
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/grpc"),
+        );
+        headers.insert(
+            "grpc-status",
+            HeaderValue::from(tonic::Code::ResourceExhausted as i32),
+        );
+        headers.insert(
+            "grpc-message",
+            HeaderValue::from_static(throttled.message()),
+        );
+    } else {
+        *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    fn limiter(config: RateLimitConfig) -> Arc<RateLimiter> {
+        Arc::new(RateLimiter::new(config))
+    }
+
+    #[test]
+    fn test_token_bucket_allows_burst_then_throttles() {
+        let limiter = limiter(RateLimitConfig {
+            requests_per_second: 1,
+            burst: 3,
+            max_in_flight: 0,
+        });
+
+        for _ in 0..3 {
+            assert!(limiter.try_acquire("ci-runner").is_ok());
+        }
+        let err = limiter.try_acquire("ci-runner").err().unwrap();
+        assert!(matches!(err, Throttled::RateLimited { .. }));
+        assert_eq!(err.retry_after(), Duration::from_secs(1));
+
+        // Other clients have their own bucket
+        assert!(limiter.try_acquire("laptop").is_ok());
+        assert_eq!(limiter.metrics().rate_limited_total(), 1);
+        assert_eq!(limiter.metrics().allowed_total(), 4);
+    }
+
+    #[test]
+    fn test_in_flight_cap_released_on_drop() {
+        let limiter = limiter(RateLimitConfig {
+            requests_per_second: 0,
+            burst: 0,
+            max_in_flight: 2,
+        });
+
+        let first = limiter.try_acquire("ci-runner").unwrap();
+        let _second = limiter.try_acquire("ci-runner").unwrap();
+        assert!(matches!(
+            limiter.try_acquire("ci-runner"),
+            Err(Throttled::TooManyInFlight { .. })
+        ));
+
+        drop(first);
+        assert!(limiter.try_acquire("ci-runner").is_ok());
+        assert_eq!(limiter.metrics().concurrency_limited_total(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_layer_rejects_http_with_429() {
+        let limiter = limiter(RateLimitConfig {
+            requests_per_second: 1,
+            burst: 1,
+            max_in_flight: 0,
+        });
+        let service =
+            RateLimitLayer::new(limiter).layer(tower::service_fn(|_req: Request<Body>| async {
+                Ok::<_, std::convert::Infallible>(Response::new(Body::empty()))
+            }));
+
+        let request = || {
+            let mut request = Request::new(Body::empty());
+            request
+                .extensions_mut()
+                .insert(ClientIdentity("ci".to_string()));
+            request
+        };
+
+        let ok = service.clone().oneshot(request()).await.unwrap();
+        assert_eq!(ok.status(), StatusCode::OK);
+
+        let throttled = service.oneshot(request()).await.unwrap();
+        assert_eq!(throttled.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(throttled.headers()[header::RETRY_AFTER], "1");
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_until_body_ends() {
+        let limiter = limiter(RateLimitConfig {
+            requests_per_second: 0,
+            burst: 0,
+            max_in_flight: 1,
+        });
+        let service =
+            RateLimitLayer::new(limiter).layer(tower::service_fn(|_req: Request<Body>| async {
+                Ok::<_, std::convert::Infallible>(Response::new(Body::from("artifact")))
+            }));
+        let request = || Request::new(Body::empty());
+
+        // The first response is still being sent
+        let streaming = service.clone().oneshot(request()).await.unwrap();
+        let throttled = service.clone().oneshot(request()).await.unwrap();
+        assert_eq!(throttled.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let body = axum::body::to_bytes(Body::new(streaming.into_body()), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "artifact");
+        let ok = service.oneshot(request()).await.unwrap();
+        assert_eq!(ok.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_clients_identified_by_token() {
+        use crate::auth::layer::{test_support, AuthLayer};
+
+        let limiter = limiter(RateLimitConfig {
+            requests_per_second: 1,
+            burst: 1,
+            max_in_flight: 0,
+        });
+        let service = tower::ServiceBuilder::new()
+            .layer(AuthLayer::new(Some(test_support::validator(false))))
+            .layer(RateLimitLayer::new(limiter))
+            .service(tower::service_fn(|_req: Request<Body>| async {
+                Ok::<_, std::convert::Infallible>(Response::new(Body::empty()))
+            }));
+        let request = |sub: &str| {
+            Request::get("/cache/abc")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", test_support::token(sub, "cache:read")),
+                )
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Runners sharing an address have their own buckets
+        let ok = service.clone().oneshot(request("runner-1")).await.unwrap();
+        assert_eq!(ok.status(), StatusCode::OK);
+        let ok = service.clone().oneshot(request("runner-2")).await.unwrap();
+        assert_eq!(ok.status(), StatusCode::OK);
+        let throttled = service.oneshot(request("runner-1")).await.unwrap();
+        assert_eq!(throttled.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[test]
+    fn test_grpc_rejection_is_resource_exhausted() {
+        let response: Response<Body> = throttled_response(
+            true,
+            Throttled::TooManyInFlight {
+                retry_after: Duration::from_secs(1),
+            },
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["grpc-status"], "8");
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+    }
+}
@@ -0,0 +1,170 @@
+/// Affected detection for `fabrik run --affected-base`
+///
+/// A script is affected by a change when git reports a changed file that is the script,
+/// one of the scripts it depends on, or matches one of their input globs. Changes are
+/// taken relative to the merge base of the base ref and HEAD, and include uncommitted
+/// and untracked files, so deleted inputs and new files matching a glob count too.
+///
+/// Only file inputs can be compared against git: `env` and `docker-image` inputs, and
+/// the runtime version, are not considered. Like the glob expansion of inputs, files
+/// ignored by `.gitignore`/`.fabrikignore` don't match unless the input includes them.
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::dependencies::DependencyResolver;
+use super::ignore_files::IgnoreFilter;
+
+/// Same matching as the glob expansion of inputs: `*` stays within a directory
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Files (absolute paths) changed in the repository of `dir` relative to `base`
+pub fn changed_files(dir: &Path, base: &str) -> Result<Vec<PathBuf>> {
+    let root = PathBuf::from(git(dir, &["rev-parse", "--show-toplevel"])?.trim());
+    let merge_base = git(dir, &["merge-base", base, "HEAD"])
+        .with_context(|| format!("Failed to find the merge base of {} and HEAD", base))?;
+
+    // Committed and uncommitted changes since the merge base, then untracked files
+    let diff = git(
+        &root,
+        &["diff", "--name-only", "--no-renames", merge_base.trim()],
+    )?;
+    let untracked = git(&root, &["ls-files", "--others", "--exclude-standard"])?;
+
+    let mut files: Vec<PathBuf> = diff
+        .lines()
+        .chain(untracked.lines())
+        .filter(|line| !line.is_empty())
+        .map(|line| root.join(line))
+        .collect();
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Changed files that affect `script_path`, sorted
+pub fn affecting_changes(script_path: &Path, changed: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut scripts = Vec::new();
+    let mut patterns = Vec::new();
+
+    for dep in DependencyResolver::new().resolve(script_path)? {
+        let base_dir = dep.script_path.parent().unwrap_or_else(|| Path::new("."));
+        for input in &dep.annotations.inputs {
+            let pattern = if Path::new(&input.path).is_absolute() {
+                input.path.clone()
+            } else {
+                base_dir.join(&input.path).to_string_lossy().to_string()
+            };
+            let pattern = Pattern::new(&pattern)
+                .with_context(|| format!("Invalid glob pattern: {}", input.path))?;
+            let filter = (!input.include_ignored).then(|| IgnoreFilter::new(base_dir));
+            patterns.push((pattern, filter));
+        }
+        scripts.push(dep.script_path);
+    }
+
+    Ok(changed
+        .iter()
+        .filter(|file| {
+            scripts.contains(file)
+                || patterns.iter_mut().any(|(pattern, filter)| {
+                    pattern.matches_path_with(file, MATCH_OPTIONS)
+                        && filter
+                            .as_mut()
+                            .is_none_or(|filter| !filter.is_ignored(file, false))
+                })
+        })
+        .cloned()
+        .collect())
+}
+
+/// Run git in `dir`, returning its output
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_affecting_changes() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path().canonicalize().unwrap();
+        fs::write(
+            dir.join("gen.sh"),
+            "#!/usr/bin/env -S fabrik run bash\n#FABRIK input \"src/*.txt\"\n",
+        )
+        .unwrap();
+        let script = dir.join("gen.sh");
+
+        let changed = vec![
+            dir.join("README.md"),
+            dir.join("src/a.txt"),
+            dir.join("src/nested/b.txt"),
+        ];
+        assert_eq!(
+            affecting_changes(&script, &changed).unwrap(),
+            vec![dir.join("src/a.txt")]
+        );
+        assert_eq!(
+            affecting_changes(&script, std::slice::from_ref(&script)).unwrap(),
+            vec![script.clone()]
+        );
+        assert!(affecting_changes(&script, &changed[..1])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_changed_files_since_base() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path().canonicalize().unwrap();
+        let run = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(&dir)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        run(&["init", "--quiet", "--initial-branch=main"]);
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("b.txt"), "b").unwrap();
+        run(&["add", "."]);
+        run(&["commit", "--quiet", "-m", "base"]);
+
+        // Committed, uncommitted, deleted and untracked changes all count
+        run(&["checkout", "--quiet", "-b", "feature"]);
+        fs::write(dir.join("a.txt"), "a2").unwrap();
+        run(&["commit", "--quiet", "-am", "change"]);
+        fs::remove_file(dir.join("b.txt")).unwrap();
+        fs::write(dir.join("c.txt"), "c").unwrap();
+
+        assert_eq!(
+            changed_files(&dir, "main").unwrap(),
+            vec![dir.join("a.txt"), dir.join("b.txt"), dir.join("c.txt")]
+        );
+        assert!(changed_files(&dir, "missing-ref").is_err());
+    }
+}
@@ -7,11 +7,16 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use super::docker::ImageOutputSpec;
+use super::outputs::RestoreOptions;
+
 /// Input specification for cache key generation
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct InputSpec {
     pub path: String,
     pub hash: HashMethod,
+    /// Keep files ignored by `.gitignore`/`.fabrikignore` (see `ignore_files`)
+    pub include_ignored: bool,
 }
 
 /// How to hash input files
@@ -47,14 +52,26 @@ pub struct ScriptAnnotations {
     pub runtime_args: Vec<String>,
     pub inputs: Vec<InputSpec>,
     pub outputs: Vec<OutputSpec>,
+    /// Docker images whose IDs are part of the cache key (`input docker-image="..."`)
+    pub image_inputs: Vec<String>,
+    /// Docker images saved as outputs (`output docker-image="..."`)
+    pub image_outputs: Vec<ImageOutputSpec>,
     pub env_vars: Vec<String>,
+    /// Variables passed to hermetic runs without being part of the cache key (`env-allow`)
+    pub env_allow: Vec<String>,
     pub cache_ttl: Option<Duration>,
     pub cache_key: Option<String>,
     pub cache_disabled: bool,
+    /// How cached outputs are restored (`cache restore-mtimes=#false clean-restore=#true`)
+    pub cache_restore: RestoreOptions,
     pub runtime_version: bool,
     pub exec_cwd: Option<PathBuf>,
     pub exec_timeout: Option<Duration>,
     pub exec_shell: bool,
+    /// Run with a cleaned environment (`exec hermetic=#true`)
+    pub exec_hermetic: bool,
+    /// Max dependency scripts to run at once (`exec parallel=4`)
+    pub exec_parallel: Option<usize>,
     pub depends_on: Vec<DependencySpec>,
 }
 
@@ -282,6 +299,11 @@ fn parse_shebang(line: &str) -> Result<(String, Vec<String>)> {
 fn parse_kdl_node(annotations: &mut ScriptAnnotations, node: &KdlNode) -> Result<()> {
     match node.name().value() {
         "input" => {
+            if let Some(image) = node.get("docker-image").and_then(|e| e.as_string()) {
+                annotations.image_inputs.push(image.to_string());
+                return Ok(());
+            }
+
             let path = get_positional_string(node, 0)
                 .ok_or_else(|| anyhow!("input requires path argument"))?;
 
@@ -302,21 +324,35 @@ fn parse_kdl_node(annotations: &mut ScriptAnnotations, node: &KdlNode) -> Result
                 }
             };
 
+            let include_ignored = node
+                .get("include-ignored")
+                .and_then(|e| e.as_bool())
+                .unwrap_or(false);
+
             annotations.inputs.push(InputSpec {
                 path,
                 hash: hash_method,
+                include_ignored,
             });
         }
 
         "output" => {
-            let path = get_positional_string(node, 0)
-                .ok_or_else(|| anyhow!("output requires path argument"))?;
-
             let required = node
                 .get("required")
                 .and_then(|e| e.as_bool())
                 .unwrap_or(true);
 
+            if let Some(image) = node.get("docker-image").and_then(|e| e.as_string()) {
+                annotations.image_outputs.push(ImageOutputSpec {
+                    image: image.to_string(),
+                    required,
+                });
+                return Ok(());
+            }
+
+            let path = get_positional_string(node, 0)
+                .ok_or_else(|| anyhow!("output requires path argument"))?;
+
             annotations.outputs.push(OutputSpec { path, required });
         }
 
@@ -329,6 +365,14 @@ fn parse_kdl_node(annotations: &mut ScriptAnnotations, node: &KdlNode) -> Result
             }
         }
 
+        "env-allow" => {
+            for entry in node.entries() {
+                if let Some(var) = entry.value().as_string() {
+                    annotations.env_allow.push(var.to_string());
+                }
+            }
+        }
+
         "cache" => {
             if let Some(ttl) = node.get("ttl").and_then(|e| e.as_string()) {
                 annotations.cache_ttl = Some(parse_duration(ttl)?);
@@ -339,6 +383,12 @@ fn parse_kdl_node(annotations: &mut ScriptAnnotations, node: &KdlNode) -> Result
             if let Some(disabled) = node.get("disabled").and_then(|e| e.as_bool()) {
                 annotations.cache_disabled = disabled;
             }
+            if let Some(mtimes) = node.get("restore-mtimes").and_then(|e| e.as_bool()) {
+                annotations.cache_restore.mtimes = mtimes;
+            }
+            if let Some(clean) = node.get("clean-restore").and_then(|e| e.as_bool()) {
+                annotations.cache_restore.clean = clean;
+            }
         }
 
         "runtime" => {
@@ -374,6 +424,17 @@ fn parse_kdl_node(annotations: &mut ScriptAnnotations, node: &KdlNode) -> Result
             if let Some(shell) = node.get("shell").and_then(|e| e.as_bool()) {
                 annotations.exec_shell = shell;
             }
+            if let Some(hermetic) = node.get("hermetic").and_then(|e| e.as_bool()) {
+                annotations.exec_hermetic = hermetic;
+            }
+            if let Some(parallel) = node.get("parallel") {
+                let parallel = parallel
+                    .as_integer()
+                    .and_then(|n| usize::try_from(n).ok())
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| anyhow!("exec parallel must be a positive integer"))?;
+                annotations.exec_parallel = Some(parallel);
+            }
         }
 
         "depends" => {
@@ -476,6 +537,39 @@ mod tests {
         assert_eq!(annotations.inputs[1].hash, HashMethod::Content);
     }
 
+    #[test]
+    fn test_parse_kdl_docker_images() {
+        let kdl = r#"
+            input docker-image="node:20-alpine"
+            output docker-image="myapp:dev"
+            output docker-image="myapp:debug" required=#false
+            output "dist/"
+        "#;
+        let doc: KdlDocument = kdl.parse().unwrap();
+        let mut annotations = ScriptAnnotations::default();
+
+        for node in doc.nodes() {
+            parse_kdl_node(&mut annotations, node).unwrap();
+        }
+
+        assert!(annotations.inputs.is_empty());
+        assert_eq!(annotations.image_inputs, vec!["node:20-alpine"]);
+        assert_eq!(
+            annotations.image_outputs,
+            vec![
+                ImageOutputSpec {
+                    image: "myapp:dev".to_string(),
+                    required: true,
+                },
+                ImageOutputSpec {
+                    image: "myapp:debug".to_string(),
+                    required: false,
+                },
+            ]
+        );
+        assert_eq!(annotations.outputs.len(), 1);
+    }
+
     #[test]
     fn test_parse_kdl_cache() {
         let kdl = r#"cache ttl="7d" key="v2""#;
@@ -491,6 +585,47 @@ mod tests {
             Duration::from_secs(7 * 86400)
         );
         assert_eq!(annotations.cache_key.unwrap(), "v2");
+
+        let mut restore = ScriptAnnotations::default();
+        assert_eq!(restore.cache_restore, RestoreOptions::default());
+        let doc: KdlDocument = "cache restore-mtimes=#false clean-restore=#true"
+            .parse()
+            .unwrap();
+        parse_kdl_node(&mut restore, &doc.nodes()[0]).unwrap();
+        assert_eq!(
+            restore.cache_restore,
+            RestoreOptions {
+                mtimes: false,
+                clean: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_kdl_exec_parallel() {
+        let doc: KdlDocument = r#"exec parallel=4"#.parse().unwrap();
+        let mut annotations = ScriptAnnotations::default();
+        for node in doc.nodes() {
+            parse_kdl_node(&mut annotations, node).unwrap();
+        }
+        assert_eq!(annotations.exec_parallel, Some(4));
+
+        let doc: KdlDocument = r#"exec parallel=0"#.parse().unwrap();
+        assert!(parse_kdl_node(&mut annotations, &doc.nodes()[0]).is_err());
+    }
+
+    #[test]
+    fn test_parse_kdl_hermetic() {
+        let doc: KdlDocument = "exec hermetic=#true\nenv-allow \"SSH_AUTH_SOCK\" \"CI\""
+            .parse()
+            .unwrap();
+        let mut annotations = ScriptAnnotations::default();
+        for node in doc.nodes() {
+            parse_kdl_node(&mut annotations, node).unwrap();
+        }
+        assert!(annotations.exec_hermetic);
+        assert_eq!(annotations.env_allow, vec!["SSH_AUTH_SOCK", "CI"]);
+        assert!(annotations.env_vars.is_empty());
     }
 
     // =========================================================================
@@ -1,14 +1,39 @@
 /// Script cache storage and retrieval
 ///
 /// Integrates with Fabrik's existing cache infrastructure to store/retrieve script outputs.
+/// Entry metadata lives under `scripts/<cache key>/`, while output archives are stored
+/// as content-addressed objects in the CAS. Identical archives are therefore stored
+/// once, and eviction, `fabrik cas` stats and upstream replication treat them like any
+/// other blob. With a remote cache attached, local misses are looked up on the
+/// upstreams and new entries are uploaded to them (see `remote`).
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use super::outputs::ArchivedOutput;
+use super::cache_key::KeyExplanation;
+use super::docker::SavedImage;
+use super::lock::{CacheKeyLock, LockOptions, LockOutcome};
+use super::outputs::{ArchivedOutput, ARCHIVE_FORMAT_VERSION};
+use super::remote::RemoteCache;
+use crate::eviction::EvictionConfig;
+use crate::storage::filesystem::hash_data;
+use crate::storage::{FilesystemStorage, Storage};
+
+/// Directory (inside the script cache) holding cache key lock files
+const LOCKS_DIR: &str = ".locks";
+
+/// Directory (inside the script cache) holding `fabrik run --explain` snapshots
+const EXPLAIN_DIR: &str = ".explain";
+
+/// Directory (inside the script cache) holding the input hash memo (see `memo`)
+pub(super) const INPUT_HASHES_DIR: &str = ".input-hashes";
+
+/// Archive file of entries created before archives moved to the CAS
+const LEGACY_ARCHIVE: &str = "outputs.tar.zst";
 
 /// Cache entry metadata
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,6 +48,38 @@ pub struct CacheMetadata {
     pub outputs: Vec<ArchivedOutput>,
     pub environment: std::collections::HashMap<String, String>,
     pub cache_info: CacheInfo,
+    /// CAS object holding the output archive (None for legacy entries)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<ArchiveRef>,
+    /// Format of the output archive (see `outputs::ARCHIVE_FORMAT_VERSION`)
+    #[serde(default = "legacy_archive_format")]
+    pub archive_format: u32,
+    /// Docker images saved in the output archive
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub docker_images: Vec<SavedImage>,
+}
+
+/// Archive format of entries written before the format was recorded
+fn legacy_archive_format() -> u32 {
+    1
+}
+
+impl CacheMetadata {
+    /// Whether this version can restore the entry's archive
+    ///
+    /// Entries written by newer versions (e.g. fetched from a shared upstream) are misses.
+    pub fn archive_supported(&self) -> bool {
+        self.archive_format <= ARCHIVE_FORMAT_VERSION
+    }
+}
+
+/// Reference to an output archive stored in the CAS
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveRef {
+    /// SHA256 of the compressed archive (hex), also its CAS id
+    pub digest: String,
+    /// Compressed archive size
+    pub size_bytes: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,16 +104,29 @@ pub struct CacheInfo {
     pub restore_time_ms: Option<u64>,
 }
 
-/// Cache entry with metadata and archive path
+/// Where a cache entry's output archive is stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveLocation {
+    /// CAS object with this id
+    Cas(Vec<u8>),
+    /// Standalone archive file (entries written by older versions)
+    File(PathBuf),
+}
+
+/// Cache entry with metadata and archive location
 #[derive(Debug, Clone)]
 pub struct CacheEntry {
     pub metadata: CacheMetadata,
-    pub archive_path: PathBuf,
+    pub archive: ArchiveLocation,
+    /// Upstream the entry was just fetched from (None for local hits)
+    pub upstream: Option<String>,
 }
 
 /// Script cache manager
 pub struct ScriptCache {
     cache_dir: PathBuf,
+    cas: FilesystemStorage,
+    remote: Option<RemoteCache>,
 }
 
 impl ScriptCache {
@@ -70,13 +140,51 @@ impl ScriptCache {
             )
         })?;
 
+        let cas = FilesystemStorage::with_eviction(&cache_dir, Some(EvictionConfig::default()))
+            .context("Failed to open CAS for script archives")?;
+
         Ok(Self {
             cache_dir: script_cache_dir,
+            cas,
+            remote: None,
         })
     }
 
+    /// Share entries through upstream caches
+    pub fn with_remote(mut self, remote: Option<RemoteCache>) -> Self {
+        self.remote = remote;
+        self
+    }
+
     /// Get cache entry if it exists and is not expired
+    ///
+    /// Local misses are fetched from the remote cache, if any, and stored locally.
     pub fn get(&self, cache_key: &str) -> Result<Option<CacheEntry>> {
+        if let Some(entry) = self.get_local(cache_key)? {
+            return Ok(Some(entry));
+        }
+
+        let Some(remote) = self
+            .remote
+            .as_ref()
+            .and_then(|remote| remote.fetch(cache_key))
+        else {
+            return Ok(None);
+        };
+        if !remote.metadata.archive_supported() {
+            return Ok(None);
+        }
+        let mut metadata = remote.metadata;
+        metadata.cache_info.upstream_used = Some(remote.upstream.clone());
+        self.store(cache_key, metadata, &remote.archive)?;
+
+        Ok(self.get_local(cache_key)?.map(|entry| CacheEntry {
+            upstream: Some(remote.upstream),
+            ..entry
+        }))
+    }
+
+    fn get_local(&self, cache_key: &str) -> Result<Option<CacheEntry>> {
         let entry_dir = self.cache_dir.join(cache_key);
 
         if !entry_dir.exists() {
@@ -84,9 +192,8 @@ impl ScriptCache {
         }
 
         let metadata_path = entry_dir.join("metadata.json");
-        let archive_path = entry_dir.join("outputs.tar.zst");
 
-        if !metadata_path.exists() || !archive_path.exists() {
+        if !metadata_path.exists() {
             // Invalid cache entry - clean it up
             let _ = fs::remove_dir_all(&entry_dir);
             return Ok(None);
@@ -99,6 +206,29 @@ impl ScriptCache {
         let metadata: CacheMetadata =
             serde_json::from_str(&metadata_json).context("Failed to parse metadata JSON")?;
 
+        // Left in place for the version that wrote it
+        if !metadata.archive_supported() {
+            return Ok(None);
+        }
+
+        let archive = match &metadata.archive {
+            Some(archive) => {
+                let id = hex::decode(&archive.digest).context("Invalid archive digest")?;
+                ArchiveLocation::Cas(id)
+            }
+            None => ArchiveLocation::File(entry_dir.join(LEGACY_ARCHIVE)),
+        };
+
+        let archive_exists = match &archive {
+            ArchiveLocation::Cas(id) => self.cas.exists(id)?,
+            ArchiveLocation::File(path) => path.exists(),
+        };
+        if !archive_exists {
+            // Archive was evicted (or never written) - the entry is unusable
+            let _ = fs::remove_dir_all(&entry_dir);
+            return Ok(None);
+        }
+
         // Check if expired
         if let Some(expires_at) = metadata.expires_at {
             if Utc::now() > expires_at {
@@ -110,12 +240,57 @@ impl ScriptCache {
 
         Ok(Some(CacheEntry {
             metadata,
-            archive_path,
+            archive,
+            upstream: None,
         }))
     }
 
+    /// Read the compressed output archive of an entry
+    pub fn read_archive(&self, entry: &CacheEntry) -> Result<Vec<u8>> {
+        match &entry.archive {
+            ArchiveLocation::Cas(id) => self
+                .cas
+                .get(id)?
+                .with_context(|| format!("Archive {} was evicted from the CAS", hex::encode(id))),
+            ArchiveLocation::File(path) => fs::read(path)
+                .with_context(|| format!("Failed to read archive: {}", path.display())),
+        }
+    }
+
     /// Store cache entry
+    ///
+    /// The archive is stored in the CAS under its content hash; storing an archive
+    /// that is already there (e.g. the same outputs for another cache key) costs no
+    /// extra space. The entry is then uploaded to the remote cache, if any.
     pub fn put(&self, cache_key: &str, metadata: CacheMetadata, archive_path: &Path) -> Result<()> {
+        let data = fs::read(archive_path)
+            .with_context(|| format!("Failed to read archive: {}", archive_path.display()))?;
+        let metadata = self.store(cache_key, metadata, &data)?;
+
+        if let Some(remote) = &self.remote {
+            remote.upload(cache_key, &metadata, &data);
+        }
+        Ok(())
+    }
+
+    /// Store an entry locally, returning its metadata with the archive reference set
+    fn store(
+        &self,
+        cache_key: &str,
+        mut metadata: CacheMetadata,
+        data: &[u8],
+    ) -> Result<CacheMetadata> {
+        let id = hash_data(data);
+        if !self.cas.exists(&id)? {
+            self.cas
+                .put(&id, data)
+                .context("Failed to store archive in CAS")?;
+        }
+        metadata.archive = Some(ArchiveRef {
+            digest: hex::encode(&id),
+            size_bytes: data.len() as u64,
+        });
+
         let entry_dir = self.cache_dir.join(cache_key);
         fs::create_dir_all(&entry_dir).with_context(|| {
             format!("Failed to create entry directory: {}", entry_dir.display())
@@ -128,15 +303,15 @@ impl ScriptCache {
         fs::write(&metadata_path, metadata_json)
             .with_context(|| format!("Failed to write metadata: {}", metadata_path.display()))?;
 
-        // Copy archive
-        let dest_archive = entry_dir.join("outputs.tar.zst");
-        fs::copy(archive_path, &dest_archive)
-            .with_context(|| format!("Failed to copy archive to: {}", dest_archive.display()))?;
+        // Drop the archive of a legacy entry being overwritten
+        let _ = fs::remove_file(entry_dir.join(LEGACY_ARCHIVE));
 
-        Ok(())
+        Ok(metadata)
     }
 
     /// Remove cache entry
+    ///
+    /// The archive stays in the CAS (other entries may share it) until evicted.
     pub fn remove(&self, cache_key: &str) -> Result<()> {
         let entry_dir = self.cache_dir.join(cache_key);
 
@@ -149,6 +324,14 @@ impl ScriptCache {
         Ok(())
     }
 
+    /// Acquire a cooperative lock on a cache key
+    ///
+    /// Used to prevent concurrent executions of the same script from all missing
+    /// and executing; see [`CacheKeyLock`].
+    pub fn lock(&self, cache_key: &str, options: &LockOptions) -> Result<LockOutcome> {
+        CacheKeyLock::acquire(&self.cache_dir.join(LOCKS_DIR), cache_key, options)
+    }
+
     /// List all cache entries
     pub fn list(&self) -> Result<Vec<String>> {
         let mut entries = Vec::new();
@@ -166,7 +349,9 @@ impl ScriptCache {
             let entry = entry?;
             if entry.file_type()?.is_dir() {
                 if let Some(name) = entry.file_name().to_str() {
-                    entries.push(name.to_string());
+                    if name != LOCKS_DIR && name != EXPLAIN_DIR && name != INPUT_HASHES_DIR {
+                        entries.push(name.to_string());
+                    }
                 }
             }
         }
@@ -175,23 +360,66 @@ impl ScriptCache {
         Ok(entries)
     }
 
+    /// Cache key explanation saved by the last `fabrik run --explain` of a script
+    pub fn previous_explanation(&self, script_path: &Path) -> Result<Option<KeyExplanation>> {
+        let path = self.explanation_path(script_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read explanation: {}", path.display()))?;
+        // Snapshots of older versions aren't worth failing over
+        Ok(serde_json::from_str(&json).ok())
+    }
+
+    /// Save a cache key explanation for the next `fabrik run --explain` to compare with
+    pub fn save_explanation(&self, script_path: &Path, explanation: &KeyExplanation) -> Result<()> {
+        let path = self.explanation_path(script_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let json =
+            serde_json::to_string_pretty(explanation).context("Failed to serialize explanation")?;
+        fs::write(&path, json)
+            .with_context(|| format!("Failed to write explanation: {}", path.display()))
+    }
+
+    /// Snapshots are per script, so moving a script starts over
+    fn explanation_path(&self, script_path: &Path) -> PathBuf {
+        let script_path = script_path
+            .canonicalize()
+            .unwrap_or_else(|_| script_path.to_path_buf());
+        let id = hex::encode(hash_data(script_path.to_string_lossy().as_bytes()));
+        self.cache_dir
+            .join(EXPLAIN_DIR)
+            .join(format!("{}.json", &id[..16]))
+    }
+
     /// Get cache statistics
     pub fn stats(&self) -> Result<CacheStats> {
         let mut total_entries = 0;
         let mut total_size = 0;
         let mut total_files = 0;
+        let mut archive_size = 0;
+        let mut archives = HashSet::new();
 
         if !self.cache_dir.exists() {
             return Ok(CacheStats {
                 total_entries,
                 total_size_bytes: total_size,
                 total_files,
+                archive_size_bytes: archive_size,
             });
         }
 
         for entry in fs::read_dir(&self.cache_dir)? {
             let entry = entry?;
-            if entry.file_type()?.is_dir() {
+            if entry.file_type()?.is_dir()
+                && entry.file_name() != LOCKS_DIR
+                && entry.file_name() != EXPLAIN_DIR
+                && entry.file_name() != INPUT_HASHES_DIR
+            {
                 total_entries += 1;
 
                 // Read metadata
@@ -202,6 +430,20 @@ impl ScriptCache {
                             total_size += output.size_bytes;
                             total_files += output.file_count;
                         }
+
+                        // Entries sharing an archive only use its space once
+                        match metadata.archive {
+                            Some(archive) => {
+                                if archives.insert(archive.digest) {
+                                    archive_size += archive.size_bytes;
+                                }
+                            }
+                            None => {
+                                archive_size += fs::metadata(entry.path().join(LEGACY_ARCHIVE))
+                                    .map(|m| m.len())
+                                    .unwrap_or(0);
+                            }
+                        }
                     }
                 }
             }
@@ -211,6 +453,7 @@ impl ScriptCache {
             total_entries,
             total_size_bytes: total_size,
             total_files,
+            archive_size_bytes: archive_size,
         })
     }
 
@@ -241,6 +484,8 @@ pub struct CacheStats {
     pub total_entries: usize,
     pub total_size_bytes: u64,
     pub total_files: usize,
+    /// Space used by the (compressed, deduplicated) output archives
+    pub archive_size_bytes: u64,
 }
 
 /// Parameters for creating cache metadata
@@ -290,6 +535,9 @@ pub fn create_metadata(params: CreateMetadataParams) -> CacheMetadata {
             upstream_used: None,
             restore_time_ms: None,
         },
+        archive: None,
+        archive_format: ARCHIVE_FORMAT_VERSION,
+        docker_images: Vec::new(),
     }
 }
 
@@ -333,7 +581,7 @@ mod tests {
 
         let entry = entry.unwrap();
         assert_eq!(entry.metadata.cache_key, "test-key-123");
-        assert!(entry.archive_path.exists());
+        assert_eq!(cache.read_archive(&entry).unwrap(), b"test archive data");
     }
 
     #[test]
@@ -399,4 +647,87 @@ mod tests {
         let entries = cache.list().unwrap();
         assert_eq!(entries.len(), 3);
     }
+
+    fn metadata(cache_key: &str) -> CacheMetadata {
+        create_metadata(CreateMetadataParams {
+            cache_key: cache_key.to_string(),
+            script_path: Path::new("/path/to/script.sh"),
+            exit_code: 0,
+            duration: Duration::from_secs(10),
+            runtime: "bash".to_string(),
+            runtime_version: None,
+            outputs: Vec::new(),
+            env_vars: &[],
+            ttl: None,
+        })
+    }
+
+    #[test]
+    fn test_archives_are_deduplicated_in_cas() {
+        let temp = TempDir::new().unwrap();
+        let cache_dir = temp.path().join("cache");
+        let cache = ScriptCache::new(cache_dir.clone()).unwrap();
+
+        let archive_path = temp.path().join("test.tar.zst");
+        fs::write(&archive_path, b"same outputs").unwrap();
+
+        cache
+            .put("key-1", metadata("key-1"), &archive_path)
+            .unwrap();
+        cache
+            .put("key-2", metadata("key-2"), &archive_path)
+            .unwrap();
+
+        let first = cache.get("key-1").unwrap().unwrap();
+        let second = cache.get("key-2").unwrap().unwrap();
+        assert_eq!(first.archive, second.archive);
+        assert!(matches!(first.archive, ArchiveLocation::Cas(_)));
+        assert!(!cache_dir
+            .join("scripts/key-1")
+            .join(LEGACY_ARCHIVE)
+            .exists());
+
+        let stats = cache.stats().unwrap();
+        assert_eq!(stats.total_entries, 2);
+        assert_eq!(stats.archive_size_bytes, b"same outputs".len() as u64);
+        assert_eq!(cache.cas.list_ids().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_evicted_archive_invalidates_entry() {
+        let temp = TempDir::new().unwrap();
+        let cache = ScriptCache::new(temp.path().join("cache")).unwrap();
+
+        let archive_path = temp.path().join("test.tar.zst");
+        fs::write(&archive_path, b"test archive data").unwrap();
+        cache.put("key", metadata("key"), &archive_path).unwrap();
+
+        let ArchiveLocation::Cas(id) = cache.get("key").unwrap().unwrap().archive else {
+            panic!("archive should be stored in the CAS");
+        };
+        cache.cas.delete(&id).unwrap();
+
+        assert!(cache.get("key").unwrap().is_none());
+        assert!(cache.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_legacy_entries_are_readable() {
+        let temp = TempDir::new().unwrap();
+        let cache = ScriptCache::new(temp.path().join("cache")).unwrap();
+
+        let entry_dir = cache.cache_dir.join("legacy");
+        fs::create_dir_all(&entry_dir).unwrap();
+        fs::write(
+            entry_dir.join("metadata.json"),
+            serde_json::to_string(&metadata("legacy")).unwrap(),
+        )
+        .unwrap();
+        fs::write(entry_dir.join(LEGACY_ARCHIVE), b"legacy archive").unwrap();
+
+        let entry = cache.get("legacy").unwrap().unwrap();
+        assert!(matches!(entry.archive, ArchiveLocation::File(_)));
+        assert_eq!(cache.read_archive(&entry).unwrap(), b"legacy archive");
+        assert_eq!(cache.stats().unwrap().archive_size_bytes, 14);
+    }
 }
@@ -3,50 +3,147 @@
 /// Generates content-addressed cache keys based on:
 /// - Script content (normalized)
 /// - Input files (hashed)
+/// - Input Docker images (their IDs)
 /// - Environment variables
 /// - Runtime version (optional)
 /// - Custom key component (optional)
 use anyhow::{Context, Result};
-use sha2::{Digest, Sha256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
 use std::path::Path;
 
 use super::annotations::ScriptAnnotations;
-use super::inputs::{get_runtime_version, hash_inputs};
+use super::docker::image_id;
+use super::inputs::{get_runtime_version, hash_inputs, InputHasher};
+use crate::hashing::HashAlgorithm;
 
 /// Compute cache key for a script
 ///
 /// The cache key is deterministic based on all inputs that affect the script's output.
-/// Format: "script-{hex_hash}" where hex_hash is first 16 characters of SHA256.
-pub fn compute_cache_key(script_path: &Path, annotations: &ScriptAnnotations) -> Result<String> {
-    let mut hasher = Sha256::new();
+/// Format: "script-{hex_hash}" where hex_hash is first 16 characters of the digest
+/// (SHA256 unless `[cache] hash_algorithm` selects BLAKE3).
+pub fn compute_cache_key(
+    script_path: &Path,
+    annotations: &ScriptAnnotations,
+    inputs: &InputHasher,
+) -> Result<String> {
+    Ok(explain_cache_key(script_path, annotations, inputs)?.key)
+}
+
+/// Cache key of a script with every component that went into it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyExplanation {
+    pub key: String,
+    pub components: Vec<KeyComponent>,
+}
+
+/// One component of a cache key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyComponent {
+    /// What it is, e.g. `input "src/*.ts"` or `env CI`
+    pub name: String,
+    /// Short description of its value, e.g. "12 files"
+    pub summary: String,
+    /// Digest (hex) of what it adds to the key
+    pub hash: String,
+    /// Hash of each matched file, for inputs (paths relative to the script)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<String, String>,
+}
+
+impl KeyComponent {
+    fn new(name: String, summary: String, value: &[u8], algorithm: HashAlgorithm) -> Self {
+        Self {
+            name,
+            summary,
+            hash: algorithm.hex_digest(value),
+            files: BTreeMap::new(),
+        }
+    }
+}
+
+/// Compute the cache key of a script, recording each of its components
+pub fn explain_cache_key(
+    script_path: &Path,
+    annotations: &ScriptAnnotations,
+    inputs: &InputHasher,
+) -> Result<KeyExplanation> {
+    let algorithm = inputs.algorithm();
+    let mut hasher = algorithm.hasher();
+    let mut components = Vec::new();
 
     // 1. Hash normalized script content
     let script_content = normalize_script_content(script_path)?;
     hasher.update(script_content.as_bytes());
+    components.push(KeyComponent::new(
+        "script".to_string(),
+        format!("{} lines", script_content.lines().count()),
+        script_content.as_bytes(),
+        algorithm,
+    ));
 
     // 2. Hash all input files
     let base_dir = script_path
         .parent()
         .ok_or_else(|| anyhow::anyhow!("Script has no parent directory"))?;
 
-    let input_hashes =
-        hash_inputs(&annotations.inputs, base_dir).with_context(|| "Failed to hash input files")?;
+    let input_hashes = hash_inputs(&annotations.inputs, base_dir, inputs)
+        .with_context(|| "Failed to hash input files")?;
 
-    for input_hash in input_hashes {
+    for (input, input_hash) in annotations.inputs.iter().zip(input_hashes) {
         hasher.update(input_hash.combined_hash.as_bytes());
+
+        let mut component = KeyComponent::new(
+            format!("input \"{}\"", input.path),
+            format!("{} files", input_hash.files.len()),
+            input_hash.combined_hash.as_bytes(),
+            algorithm,
+        );
+        component.files = input_hash
+            .files
+            .iter()
+            .zip(input_hash.file_hashes)
+            .map(|(file, hash)| {
+                let path = file.strip_prefix(base_dir).unwrap_or(file);
+                (path.to_string_lossy().to_string(), hash)
+            })
+            .collect();
+        components.push(component);
+    }
+
+    // 2b. Hash input Docker images by ID
+    for image in &annotations.image_inputs {
+        let id = image_id(image)?;
+        // Image not pulled/built yet - include marker to make key different
+        let value = id.as_deref().unwrap_or("<missing>");
+        hasher.update(image.as_bytes());
+        hasher.update(value.as_bytes());
+        components.push(KeyComponent::new(
+            format!("docker-image {}", image),
+            value.to_string(),
+            value.as_bytes(),
+            algorithm,
+        ));
     }
 
     // 3. Hash environment variables
     for var in &annotations.env_vars {
         hasher.update(var.as_bytes());
-        if let Ok(value) = env::var(var) {
-            hasher.update(value.as_bytes());
-        } else {
+        // Values are only shown hashed, as they may be secrets
+        let (summary, value) = match env::var(var) {
+            Ok(value) => ("set".to_string(), value),
             // Variable not set - include marker to make key different
-            hasher.update(b"<unset>");
-        }
+            Err(_) => ("unset".to_string(), "<unset>".to_string()),
+        };
+        hasher.update(value.as_bytes());
+        components.push(KeyComponent::new(
+            format!("env {}", var),
+            summary,
+            value.as_bytes(),
+            algorithm,
+        ));
     }
 
     // 4. Include runtime version (if requested)
@@ -54,20 +151,115 @@ pub fn compute_cache_key(script_path: &Path, annotations: &ScriptAnnotations) ->
         let version = get_runtime_version(&annotations.runtime)
             .with_context(|| format!("Failed to get runtime version: {}", annotations.runtime))?;
         hasher.update(version.as_bytes());
+        components.push(KeyComponent::new(
+            "runtime-version".to_string(),
+            version.clone(),
+            version.as_bytes(),
+            algorithm,
+        ));
     }
 
     // 5. Custom cache key component
     if let Some(key) = &annotations.cache_key {
         hasher.update(key.as_bytes());
+        components.push(KeyComponent::new(
+            "cache key".to_string(),
+            key.clone(),
+            key.as_bytes(),
+            algorithm,
+        ));
     }
 
     // 6. Include OS for cross-platform considerations
     hasher.update(std::env::consts::OS.as_bytes());
+    components.push(KeyComponent::new(
+        "os".to_string(),
+        std::env::consts::OS.to_string(),
+        std::env::consts::OS.as_bytes(),
+        algorithm,
+    ));
 
-    let hash = hex::encode(hasher.finalize());
+    let hash = hasher.finalize_hex();
 
     // Use first 16 characters (64 bits) for shorter keys
-    Ok(format!("script-{}", &hash[..16]))
+    Ok(KeyExplanation {
+        key: format!("script-{}", &hash[..16]),
+        components,
+    })
+}
+
+/// How a cache key's components changed since a previous explanation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyChange {
+    Added(String),
+    Removed(String),
+    /// Component whose value changed, with the input files that changed
+    Changed {
+        name: String,
+        from: String,
+        to: String,
+        files: Vec<FileChange>,
+    },
+}
+
+/// Input file that was added, removed or modified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileChange {
+    Added(String),
+    Removed(String),
+    Modified(String),
+}
+
+impl KeyExplanation {
+    /// Components that differ from `previous`, in key order
+    pub fn changes_since(&self, previous: &KeyExplanation) -> Vec<KeyChange> {
+        let mut changes = Vec::new();
+
+        for component in &self.components {
+            match previous
+                .components
+                .iter()
+                .find(|c| c.name == component.name)
+            {
+                None => changes.push(KeyChange::Added(component.name.clone())),
+                Some(old) if old.hash != component.hash => changes.push(KeyChange::Changed {
+                    name: component.name.clone(),
+                    from: old.summary.clone(),
+                    to: component.summary.clone(),
+                    files: file_changes(&old.files, &component.files),
+                }),
+                Some(_) => {}
+            }
+        }
+        for old in &previous.components {
+            if !self.components.iter().any(|c| c.name == old.name) {
+                changes.push(KeyChange::Removed(old.name.clone()));
+            }
+        }
+
+        changes
+    }
+}
+
+fn file_changes(
+    previous: &BTreeMap<String, String>,
+    current: &BTreeMap<String, String>,
+) -> Vec<FileChange> {
+    let mut changes: Vec<FileChange> = current
+        .iter()
+        .filter_map(|(path, hash)| match previous.get(path) {
+            None => Some(FileChange::Added(path.clone())),
+            Some(old) if old != hash => Some(FileChange::Modified(path.clone())),
+            Some(_) => None,
+        })
+        .collect();
+    changes.extend(
+        previous
+            .keys()
+            .filter(|path| !current.contains_key(*path))
+            .map(|path| FileChange::Removed(path.clone())),
+    );
+    changes
 }
 
 /// Normalize script content by removing volatile directives
@@ -104,6 +296,7 @@ fn normalize_script_content(path: &Path) -> Result<String> {
 mod tests {
     use super::*;
     use crate::recipe::annotations::{HashMethod, InputSpec};
+    use crate::recipe::outputs::RestoreOptions;
     use tempfile::TempDir;
 
     #[test]
@@ -154,25 +347,41 @@ echo "hello"
             inputs: vec![InputSpec {
                 path: "*.txt".to_string(),
                 hash: HashMethod::Content,
+                include_ignored: false,
             }],
             outputs: vec![],
+            image_inputs: vec![],
+            image_outputs: vec![],
             env_vars: vec![],
+            env_allow: vec![],
             cache_ttl: None,
             cache_key: None,
             cache_disabled: false,
+            cache_restore: RestoreOptions::default(),
             runtime_version: false,
             exec_cwd: None,
             exec_timeout: None,
             exec_shell: false,
+            exec_hermetic: false,
+            exec_parallel: None,
             depends_on: vec![],
         };
 
-        let key1 = compute_cache_key(&script, &annotations).unwrap();
-        let key2 = compute_cache_key(&script, &annotations).unwrap();
+        let key1 = compute_cache_key(&script, &annotations, &InputHasher::default()).unwrap();
+        let key2 = compute_cache_key(&script, &annotations, &InputHasher::default()).unwrap();
 
         // Should be deterministic
         assert_eq!(key1, key2);
         assert!(key1.starts_with("script-"));
+
+        // Each algorithm has its own keys
+        let blake3 = compute_cache_key(
+            &script,
+            &annotations,
+            &InputHasher::new(HashAlgorithm::Blake3),
+        )
+        .unwrap();
+        assert_ne!(key1, blake3);
     }
 
     #[test]
@@ -195,27 +404,87 @@ echo "hello"
             inputs: vec![InputSpec {
                 path: "*.txt".to_string(),
                 hash: HashMethod::Content,
+                include_ignored: false,
             }],
             outputs: vec![],
+            image_inputs: vec![],
+            image_outputs: vec![],
             env_vars: vec![],
+            env_allow: vec![],
             cache_ttl: None,
             cache_key: None,
             cache_disabled: false,
+            cache_restore: RestoreOptions::default(),
             runtime_version: false,
             exec_cwd: None,
             exec_timeout: None,
             exec_shell: false,
+            exec_hermetic: false,
+            exec_parallel: None,
             depends_on: vec![],
         };
 
-        let key1 = compute_cache_key(&script, &annotations).unwrap();
+        let key1 = compute_cache_key(&script, &annotations, &InputHasher::default()).unwrap();
 
         // Change input file
         fs::write(temp.path().join("file1.txt"), "content2").unwrap();
 
-        let key2 = compute_cache_key(&script, &annotations).unwrap();
+        let key2 = compute_cache_key(&script, &annotations, &InputHasher::default()).unwrap();
 
         // Cache key should be different
         assert_ne!(key1, key2);
     }
+
+    #[test]
+    fn test_explanation_changes() {
+        let temp = TempDir::new().unwrap();
+        let script = temp.path().join("script.sh");
+        fs::write(&script, "#!/usr/bin/env -S fabrik run bash\necho hi\n").unwrap();
+        fs::write(temp.path().join("a.txt"), "a").unwrap();
+        fs::write(temp.path().join("b.txt"), "b").unwrap();
+
+        let mut annotations = ScriptAnnotations {
+            runtime: "bash".to_string(),
+            inputs: vec![InputSpec {
+                path: "*.txt".to_string(),
+                hash: HashMethod::Content,
+                include_ignored: false,
+            }],
+            ..Default::default()
+        };
+
+        let before = explain_cache_key(&script, &annotations, &InputHasher::default()).unwrap();
+        assert_eq!(
+            before.key,
+            compute_cache_key(&script, &annotations, &InputHasher::default()).unwrap()
+        );
+        assert_eq!(before.components[1].summary, "2 files");
+        assert!(before.changes_since(&before).is_empty());
+
+        fs::write(temp.path().join("a.txt"), "a2").unwrap();
+        fs::remove_file(temp.path().join("b.txt")).unwrap();
+        fs::write(temp.path().join("c.txt"), "c").unwrap();
+        annotations
+            .env_vars
+            .push("FABRIK_TEST_EXPLAIN_UNSET".to_string());
+
+        let after = explain_cache_key(&script, &annotations, &InputHasher::default()).unwrap();
+        assert_ne!(after.key, before.key);
+        assert_eq!(
+            after.changes_since(&before),
+            vec![
+                KeyChange::Changed {
+                    name: "input \"*.txt\"".to_string(),
+                    from: "2 files".to_string(),
+                    to: "2 files".to_string(),
+                    files: vec![
+                        FileChange::Modified("a.txt".to_string()),
+                        FileChange::Added("c.txt".to_string()),
+                        FileChange::Removed("b.txt".to_string()),
+                    ],
+                },
+                KeyChange::Added("env FABRIK_TEST_EXPLAIN_UNSET".to_string()),
+            ]
+        );
+    }
 }
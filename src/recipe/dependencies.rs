@@ -1,15 +1,26 @@
 /// Dependency resolution for script caching
 ///
-/// Handles executing dependencies recursively with cycle detection.
+/// Handles resolving dependencies recursively with cycle detection, and running the
+/// resolved dependency scripts: independent ones run in parallel (up to
+/// `exec parallel=N` of the script being run), and their output is printed in
+/// dependency order once each has finished, so it never interleaves.
 use anyhow::{Context, Result};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
 use super::annotations::{parse_annotations, InputSpec, ScriptAnnotations};
+use crate::cli_utils::fabrik_prefix;
 
 /// Dependency resolution context
 pub struct DependencyResolver {
-    visited: HashSet<PathBuf>,
+    /// Scripts whose dependencies are being resolved (the current path)
+    visiting: Vec<PathBuf>,
+    /// Scripts already resolved, which a shared dependency doesn't add twice
+    resolved: HashSet<PathBuf>,
 }
 
 impl Default for DependencyResolver {
@@ -21,14 +32,15 @@ impl Default for DependencyResolver {
 impl DependencyResolver {
     pub fn new() -> Self {
         Self {
-            visited: HashSet::new(),
+            visiting: Vec::new(),
+            resolved: HashSet::new(),
         }
     }
 
     /// Resolve dependencies for a script
     ///
-    /// Returns the list of dependencies in execution order (depth-first).
-    /// Detects cycles and returns an error if found.
+    /// Returns the list of dependencies in execution order (depth-first), each script
+    /// once, ending with the script itself. Detects cycles and returns an error if found.
     pub fn resolve(&mut self, script_path: &Path) -> Result<Vec<ResolvedDependency>> {
         let mut dependencies = Vec::new();
         self.resolve_recursive(script_path, &mut dependencies)?;
@@ -45,20 +57,24 @@ impl DependencyResolver {
             .with_context(|| format!("Failed to canonicalize path: {}", script_path.display()))?;
 
         // Cycle detection
-        if self.visited.contains(&abs_path) {
+        if self.visiting.contains(&abs_path) {
             return Err(anyhow::anyhow!(
                 "Cyclic dependency detected: {}",
                 script_path.display()
             ));
         }
+        if self.resolved.contains(&abs_path) {
+            return Ok(());
+        }
 
-        self.visited.insert(abs_path.clone());
+        self.visiting.push(abs_path.clone());
 
         // Parse annotations
         let annotations = parse_annotations(&abs_path)
             .with_context(|| format!("Failed to parse annotations: {}", script_path.display()))?;
 
         // Recursively resolve dependencies
+        let mut direct = Vec::new();
         for dep in &annotations.depends_on {
             let dep_path = if dep.script.is_absolute() {
                 dep.script.clone()
@@ -70,12 +86,17 @@ impl DependencyResolver {
             };
 
             self.resolve_recursive(&dep_path, dependencies)?;
+            direct.push(dep_path.canonicalize()?);
         }
 
+        self.visiting.pop();
+        self.resolved.insert(abs_path.clone());
+
         // Add this dependency
         dependencies.push(ResolvedDependency {
             script_path: abs_path,
             annotations,
+            dependencies: direct,
         });
 
         Ok(())
@@ -107,11 +128,16 @@ impl DependencyResolver {
                 if spec.use_outputs {
                     // Add dependency outputs as inputs
                     for output in &resolved_dep.annotations.outputs {
+                        // Outputs are usually ignored build products
                         annotations.inputs.push(InputSpec {
                             path: output.path.clone(),
                             hash: super::annotations::HashMethod::Content,
+                            include_ignored: true,
                         });
                     }
+                    for image in &resolved_dep.annotations.image_outputs {
+                        annotations.image_inputs.push(image.image.clone());
+                    }
                 }
             }
         }
@@ -123,6 +149,139 @@ impl DependencyResolver {
 pub struct ResolvedDependency {
     pub script_path: PathBuf,
     pub annotations: ScriptAnnotations,
+    /// Canonical paths of the scripts this one depends on directly
+    pub dependencies: Vec<PathBuf>,
+}
+
+/// Outcome of running (or restoring) one dependency script
+#[derive(Debug, Clone)]
+pub struct DependencyRun {
+    pub cache_key: String,
+    pub cached: bool,
+    pub exit_code: i32,
+    pub duration: Duration,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Run dependency scripts with `run`, each after the ones it depends on
+///
+/// At most `parallel` scripts run at once. When a script fails (error or non-zero
+/// exit code), no further scripts are started, the running ones are waited for, and
+/// the first failure is returned. The output of every finished script is printed in
+/// the order of `dependencies`.
+pub fn run_dependencies<F>(
+    dependencies: &[ResolvedDependency],
+    parallel: usize,
+    run: F,
+) -> Result<()>
+where
+    F: Fn(&ResolvedDependency) -> Result<DependencyRun> + Sync,
+{
+    let index: HashMap<&Path, usize> = dependencies
+        .iter()
+        .enumerate()
+        .map(|(i, dep)| (dep.script_path.as_path(), i))
+        .collect();
+    let deps_of: Vec<Vec<usize>> = dependencies
+        .iter()
+        .map(|dep| {
+            dep.dependencies
+                .iter()
+                .filter_map(|path| index.get(path.as_path()).copied())
+                .collect()
+        })
+        .collect();
+
+    let mut pending: Vec<usize> = (0..dependencies.len()).collect();
+    let mut succeeded = vec![false; dependencies.len()];
+    let mut results: Vec<Option<Result<DependencyRun>>> =
+        (0..dependencies.len()).map(|_| None).collect();
+    let mut printed = 0;
+    let mut failure = None;
+
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        let mut running = 0;
+
+        loop {
+            // Start scripts whose dependencies succeeded, unless one failed
+            while failure.is_none() && running < parallel.max(1) {
+                let Some(position) = pending
+                    .iter()
+                    .position(|&i| deps_of[i].iter().all(|&dep| succeeded[dep]))
+                else {
+                    break;
+                };
+                let i = pending.remove(position);
+                let (sender, run, dep) = (sender.clone(), &run, &dependencies[i]);
+                scope.spawn(move || {
+                    let _ = sender.send((i, run(dep)));
+                });
+                running += 1;
+            }
+
+            if running == 0 {
+                break;
+            }
+
+            let (i, result) = receiver.recv().expect("running scripts hold a sender");
+            running -= 1;
+
+            let name = dependencies[i].script_path.display();
+            match &result {
+                Ok(run) if run.exit_code == 0 => succeeded[i] = true,
+                Ok(run) => {
+                    failure.get_or_insert_with(|| {
+                        anyhow::anyhow!(
+                            "Dependency {} failed with exit code {}",
+                            name,
+                            run.exit_code
+                        )
+                    });
+                }
+                Err(e) => {
+                    failure.get_or_insert_with(|| {
+                        anyhow::anyhow!("Dependency {} failed: {:#}", name, e)
+                    });
+                }
+            }
+            results[i] = Some(result);
+
+            // Print what has finished, in order
+            while let Some(Some(result)) = results.get(printed) {
+                print_run(&dependencies[printed], result);
+                printed += 1;
+            }
+        }
+    });
+
+    // After a failure, scripts that never ran leave gaps
+    for (dep, result) in dependencies.iter().zip(&results).skip(printed) {
+        if let Some(result) = result {
+            print_run(dep, result);
+        }
+    }
+
+    failure.map_or(Ok(()), Err)
+}
+
+/// Print the captured output and a summary line of a finished dependency
+fn print_run(dep: &ResolvedDependency, result: &Result<DependencyRun>) {
+    let Ok(run) = result else {
+        return;
+    };
+    let _ = std::io::stdout().write_all(&run.stdout);
+    let _ = std::io::stderr().write_all(&run.stderr);
+    eprintln!(
+        "{} Dependency {}: {} | {} | {:.2}s (exit: {})",
+        fabrik_prefix(),
+        dep.script_path.display(),
+        run.cache_key,
+        if run.cached { "HIT ✓" } else { "MISS ✗" },
+        run.duration.as_secs_f64(),
+        run.exit_code
+    );
 }
 
 #[cfg(test)]
@@ -204,6 +363,87 @@ echo "b"
             .contains("Cyclic dependency"));
     }
 
+    /// Scripts of a diamond: main depends on left and right, which depend on base
+    fn write_diamond(dir: &Path) -> PathBuf {
+        for (name, deps) in [
+            ("base", vec![]),
+            ("left", vec!["base"]),
+            ("right", vec!["base"]),
+            ("main", vec!["left", "right"]),
+        ] {
+            let mut script = "#!/usr/bin/env -S fabrik run bash\n".to_string();
+            for dep in deps {
+                script.push_str(&format!("#FABRIK depends \"./{}.sh\"\n", dep));
+            }
+            fs::write(dir.join(format!("{}.sh", name)), script).unwrap();
+        }
+        dir.join("main.sh")
+    }
+
+    fn name(dep: &ResolvedDependency) -> String {
+        dep.script_path
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn finished(exit_code: i32) -> DependencyRun {
+        DependencyRun {
+            cache_key: "key".to_string(),
+            cached: false,
+            exit_code,
+            duration: Duration::ZERO,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_shared_dependency_once() {
+        let temp = TempDir::new().unwrap();
+        let main_script = write_diamond(temp.path());
+
+        let deps = DependencyResolver::new().resolve(&main_script).unwrap();
+        let names: Vec<String> = deps.iter().map(name).collect();
+        assert_eq!(names, ["base", "left", "right", "main"]);
+        assert_eq!(deps[3].dependencies.len(), 2);
+    }
+
+    #[test]
+    fn test_run_dependencies_in_parallel_after_their_dependencies() {
+        use std::sync::Mutex;
+
+        let temp = TempDir::new().unwrap();
+        let deps = DependencyResolver::new()
+            .resolve(&write_diamond(temp.path()))
+            .unwrap();
+        let deps = &deps[..deps.len() - 1];
+
+        let started = Mutex::new(Vec::new());
+        run_dependencies(deps, 2, |dep| {
+            started.lock().unwrap().push(name(dep));
+            Ok(finished(0))
+        })
+        .unwrap();
+        assert_eq!(started.lock().unwrap()[0], "base");
+        assert_eq!(started.lock().unwrap().len(), 3);
+
+        // Nothing that depends on a failed script runs
+        let started = Mutex::new(Vec::new());
+        let err = run_dependencies(deps, 2, |dep| {
+            started.lock().unwrap().push(name(dep));
+            Ok(finished(if name(dep) == "base" { 2 } else { 0 }))
+        })
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("base.sh failed with exit code 2"),
+            "{}",
+            err
+        );
+        assert_eq!(*started.lock().unwrap(), ["base"]);
+    }
+
     #[test]
     fn test_augment_with_dependency_outputs() {
         let temp = TempDir::new().unwrap();
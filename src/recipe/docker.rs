@@ -0,0 +1,215 @@
+/// Docker images as script inputs and outputs
+///
+/// `#FABRIK input docker-image="node:20"` adds the ID of the local image (the digest of
+/// its configuration, which changes whenever any of its layers does) to the cache key.
+/// `#FABRIK output docker-image="myapp:dev"` saves the image with `docker save` into
+/// the output archive, under `.fabrik-docker/`, and restoring the archive loads it back
+/// with `docker load`, tag included.
+///
+/// Images are handled through the `docker` CLI, or the one `FABRIK_DOCKER` names
+/// (e.g. `podman`).
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Output archive directory holding saved images
+pub const IMAGES_DIR: &str = ".fabrik-docker";
+
+/// Image saved into an output archive
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedImage {
+    /// Image reference, as annotated
+    pub image: String,
+    /// Image ID when it was saved
+    pub image_id: String,
+    /// Size of the `docker save` tarball
+    pub size_bytes: u64,
+}
+
+/// `(name in archive, path)` pairs of saved images
+type ArchiveEntries = Vec<(String, PathBuf)>;
+
+/// Image output of a script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageOutputSpec {
+    pub image: String,
+    pub required: bool,
+}
+
+/// Docker CLI to run
+fn docker() -> String {
+    std::env::var("FABRIK_DOCKER").unwrap_or_else(|_| "docker".to_string())
+}
+
+/// ID of a local image, None if there is no such image
+pub fn image_id(image: &str) -> Result<Option<String>> {
+    let output = Command::new(docker())
+        .args(["image", "inspect", "--format", "{{.Id}}", image])
+        .output()
+        .with_context(|| format!("Failed to run {}", docker()))?;
+
+    if output.status.success() {
+        let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        return Ok(Some(id));
+    }
+
+    // docker says "No such image", podman "image not known"
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lower = stderr.to_lowercase();
+    if lower.contains("no such") || lower.contains("not known") {
+        return Ok(None);
+    }
+    anyhow::bail!("Failed to inspect image {}: {}", image, stderr.trim())
+}
+
+/// Save images into `dir`, returning their archive entries and what was saved
+///
+/// Missing optional images are skipped.
+pub fn save_images(
+    images: &[ImageOutputSpec],
+    dir: &Path,
+) -> Result<(ArchiveEntries, Vec<SavedImage>)> {
+    let mut entries = Vec::new();
+    let mut saved = Vec::new();
+
+    for spec in images {
+        let Some(image_id) = image_id(&spec.image)? else {
+            if spec.required {
+                anyhow::bail!("Required output image not found: {}", spec.image);
+            }
+            continue;
+        };
+
+        let name = format!("{}/{}.tar", IMAGES_DIR, saved.len());
+        let path = dir.join(format!("{}.tar", saved.len()));
+        let status = Command::new(docker())
+            .args(["save", "--output"])
+            .arg(&path)
+            .arg(&spec.image)
+            .status()
+            .with_context(|| format!("Failed to run {}", docker()))?;
+        if !status.success() {
+            anyhow::bail!("Failed to save image {}: {}", spec.image, status);
+        }
+
+        saved.push(SavedImage {
+            image: spec.image.clone(),
+            image_id,
+            size_bytes: fs::metadata(&path)?.len(),
+        });
+        entries.push((name, path));
+    }
+
+    Ok((entries, saved))
+}
+
+/// Load the images extracted into `dir`, returning how many were loaded
+pub fn load_images(dir: &Path) -> Result<usize> {
+    let images_dir = dir.join(IMAGES_DIR);
+    if !images_dir.exists() {
+        return Ok(0);
+    }
+
+    let mut tarballs: Vec<PathBuf> = fs::read_dir(&images_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    tarballs.sort();
+
+    for tarball in &tarballs {
+        let output = Command::new(docker())
+            .args(["load", "--quiet", "--input"])
+            .arg(tarball)
+            .output()
+            .with_context(|| format!("Failed to run {}", docker()))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to load image {}: {}",
+                tarball.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    }
+
+    Ok(tarballs.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipe::annotations::OutputSpec;
+    use crate::recipe::outputs::{archive_outputs, extract_archive, RestoreOptions};
+    use tempfile::TempDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_images_round_trip_through_archives() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = TempDir::new().unwrap();
+        let base = temp.path().join("work");
+        fs::create_dir(&base).unwrap();
+        fs::write(base.join("out.txt"), "out").unwrap();
+
+        // Stand-in CLI that knows one image and records what it loads
+        let docker = temp.path().join("docker");
+        let script = format!(
+            "#!/bin/sh\n\
+             case \"$1\" in\n\
+             image) [ \"$5\" = app:dev ] && echo sha256:abc && exit 0\n\
+                    echo \"Error: No such image: $5\" >&2; exit 1 ;;\n\
+             save) echo \"image $4\" > \"$3\" ;;\n\
+             load) cat \"$4\" >> {} ;;\n\
+             esac\n",
+            temp.path().join("loaded").display()
+        );
+        fs::write(&docker, script).unwrap();
+        fs::set_permissions(&docker, fs::Permissions::from_mode(0o755)).unwrap();
+        std::env::set_var("FABRIK_DOCKER", &docker);
+
+        assert_eq!(image_id("app:dev").unwrap().as_deref(), Some("sha256:abc"));
+        assert_eq!(image_id("app:other").unwrap(), None);
+
+        let outputs = vec![OutputSpec {
+            path: "out.txt".to_string(),
+            required: true,
+        }];
+        let images = vec![
+            ImageOutputSpec {
+                image: "app:dev".to_string(),
+                required: true,
+            },
+            ImageOutputSpec {
+                image: "app:other".to_string(),
+                required: false,
+            },
+        ];
+        let archive = temp.path().join("outputs.tar.zst");
+        let (archived, saved) = archive_outputs(&outputs, &images, &base, &archive).unwrap();
+        assert_eq!(archived.len(), 1);
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].image_id, "sha256:abc");
+
+        // Images are loaded, not extracted among the outputs
+        let paths = extract_archive(
+            fs::File::open(&archive).unwrap(),
+            &base,
+            RestoreOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(paths, vec![PathBuf::from("out.txt")]);
+        assert!(!base.join(IMAGES_DIR).exists());
+        assert_eq!(
+            fs::read_to_string(temp.path().join("loaded")).unwrap(),
+            "image app:dev\n"
+        );
+
+        // Missing required images fail the archive
+        let required = vec![ImageOutputSpec {
+            image: "app:other".to_string(),
+            required: true,
+        }];
+        assert!(archive_outputs(&[], &required, &base, &archive).is_err());
+    }
+}
@@ -8,6 +8,7 @@ use std::process::{Command, Stdio};
 use std::time::{Duration, Instant};
 
 use super::annotations::ScriptAnnotations;
+use super::hermetic::hermetic_env;
 
 /// Result of script execution
 #[derive(Debug, Clone)]
@@ -23,11 +24,22 @@ pub struct ExecutionResult {
 /// Script executor
 pub struct ScriptExecutor {
     verbose: bool,
+    capture: bool,
 }
 
 impl ScriptExecutor {
     pub fn new(verbose: bool) -> Self {
-        Self { verbose }
+        Self {
+            verbose,
+            capture: false,
+        }
+    }
+
+    /// Always capture stdout/stderr, even on a terminal (e.g. to print the output of
+    /// scripts running in parallel one after the other)
+    pub fn capture_output(mut self) -> Self {
+        self.capture = true;
+        self
     }
 
     /// Execute script with the specified runtime
@@ -95,8 +107,15 @@ impl ScriptExecutor {
             }
         }
 
+        // Hermetic runs only see declared and allowed variables
+        if annotations.exec_hermetic {
+            cmd.env_clear();
+            cmd.envs(hermetic_env(annotations));
+        }
+
         // Check if stdout/stderr are TTYs - if so, inherit them for colors
-        let use_tty = std::io::stdout().is_terminal() && std::io::stderr().is_terminal();
+        let use_tty =
+            !self.capture && std::io::stdout().is_terminal() && std::io::stderr().is_terminal();
 
         if use_tty {
             // Inherit stdout/stderr to preserve colors and interactivity
@@ -117,6 +136,10 @@ impl ScriptExecutor {
             .spawn()
             .with_context(|| format!("Failed to spawn runtime: {}", annotations.runtime))?;
 
+        // Drain piped output while the script runs, so it can't block on a full pipe
+        let stdout_reader = child.stdout.take().map(read_to_end);
+        let stderr_reader = child.stderr.take().map(read_to_end);
+
         // Handle timeout if specified
         let result = if let Some(timeout) = annotations.exec_timeout {
             self.wait_with_timeout(&mut child, timeout)
@@ -141,17 +164,9 @@ impl ScriptExecutor {
 
         let exit_code = status.code().unwrap_or(-1);
 
-        // Capture output only if we piped it
-        let (stdout, stderr) = if use_tty {
-            // Output was inherited, nothing to capture
-            (Vec::new(), Vec::new())
-        } else {
-            // Output was piped, capture it
-            let output = child
-                .wait_with_output()
-                .context("Failed to capture output")?;
-            (output.stdout, output.stderr)
-        };
+        // Output was captured only if we piped it
+        let stdout = join_output(stdout_reader)?;
+        let stderr = join_output(stderr_reader)?;
 
         if self.verbose {
             eprintln!(
@@ -235,10 +250,34 @@ impl ScriptExecutor {
     }
 }
 
+/// Read a piped output stream to the end on a separate thread
+fn read_to_end<R: std::io::Read + Send + 'static>(
+    mut pipe: R,
+) -> std::thread::JoinHandle<std::io::Result<Vec<u8>>> {
+    std::thread::spawn(move || {
+        let mut output = Vec::new();
+        pipe.read_to_end(&mut output)?;
+        Ok(output)
+    })
+}
+
+fn join_output(
+    reader: Option<std::thread::JoinHandle<std::io::Result<Vec<u8>>>>,
+) -> Result<Vec<u8>> {
+    match reader {
+        Some(reader) => reader
+            .join()
+            .map_err(|_| anyhow::anyhow!("Output reader panicked"))?
+            .context("Failed to capture output"),
+        None => Ok(Vec::new()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::recipe::annotations::ScriptAnnotations;
+    use crate::recipe::outputs::RestoreOptions;
     use std::fs;
     use tempfile::TempDir;
 
@@ -268,14 +307,20 @@ echo "hello world" > output.txt
             runtime_args: vec![],
             inputs: vec![],
             outputs: vec![],
+            image_inputs: vec![],
+            image_outputs: vec![],
             env_vars: vec![],
+            env_allow: vec![],
             cache_ttl: None,
             cache_key: None,
             cache_disabled: false,
+            cache_restore: RestoreOptions::default(),
             runtime_version: false,
             exec_cwd: None,
             exec_timeout: None,
             exec_shell: false,
+            exec_hermetic: false,
+            exec_parallel: None,
             depends_on: vec![],
         };
 
@@ -312,14 +357,20 @@ sleep 10
             runtime_args: vec![],
             inputs: vec![],
             outputs: vec![],
+            image_inputs: vec![],
+            image_outputs: vec![],
             env_vars: vec![],
+            env_allow: vec![],
             cache_ttl: None,
             cache_key: None,
             cache_disabled: false,
+            cache_restore: RestoreOptions::default(),
             runtime_version: false,
             exec_cwd: None,
             exec_timeout: Some(Duration::from_secs(1)),
             exec_shell: false,
+            exec_hermetic: false,
+            exec_parallel: None,
             depends_on: vec![],
         };
 
@@ -330,4 +381,30 @@ sleep 10
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("timed out"));
     }
+
+    #[test]
+    fn test_execute_hermetic() {
+        let temp = TempDir::new().unwrap();
+        let script = temp.path().join("test.sh");
+        fs::write(
+            &script,
+            "#!/usr/bin/env -S fabrik run bash\necho \"[$FABRIK_TEST_HERMETIC_LEAK][$FABRIK_TEST_HERMETIC_KEPT]\"\n",
+        )
+        .unwrap();
+        std::env::set_var("FABRIK_TEST_HERMETIC_LEAK", "leak");
+        std::env::set_var("FABRIK_TEST_HERMETIC_KEPT", "kept");
+
+        let mut annotations = ScriptAnnotations {
+            runtime: "bash".to_string(),
+            env_vars: vec!["FABRIK_TEST_HERMETIC_KEPT".to_string()],
+            ..Default::default()
+        };
+        let executor = ScriptExecutor::new(false).capture_output();
+        let result = executor.execute(&script, &annotations, &[]).unwrap();
+        assert_eq!(result.stdout, b"[leak][kept]\n");
+
+        annotations.exec_hermetic = true;
+        let result = executor.execute(&script, &annotations, &[]).unwrap();
+        assert_eq!(result.stdout, b"[][kept]\n");
+    }
 }
@@ -0,0 +1,236 @@
+/// Environment hygiene for script execution
+///
+/// Only the variables declared with `#FABRIK env` are part of a script's cache key, so a
+/// script reading anything else from the environment can produce different outputs for
+/// the same key. Two tools help with that:
+///
+/// - Hermetic mode (`#FABRIK exec hermetic=#true` or `fabrik run --hermetic`) runs the
+///   script with a cleaned environment: declared `env` variables, `env-allow` variables
+///   and a small allowlist of system variables ([`DEFAULT_ALLOWED_ENV`]).
+/// - Environment diagnostics (`fabrik run --check-env`) scan the script for variables
+///   it reads, and report those that are set but neither declared nor allowed.
+///
+/// The scan is lexical: `$VAR` and `${VAR}` in shell scripts, and the usual accessors
+/// (`process.env.VAR`, `os.environ["VAR"]`, `os.getenv("VAR")`, `ENV["VAR"]`,
+/// `std::env::var("VAR")`, ...) in other languages. Variables read indirectly (e.g.
+/// by programs the script runs) aren't found.
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use super::annotations::ScriptAnnotations;
+
+/// Variables passed to hermetic scripts besides the declared ones
+pub const DEFAULT_ALLOWED_ENV: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "TMPDIR",
+    "TMP",
+    "TEMP",
+    "TERM",
+    "LANG",
+    "LC_ALL",
+    "LC_CTYPE",
+    "TZ",
+    // Needed by most programs on Windows
+    "SYSTEMROOT",
+    "WINDIR",
+    "COMSPEC",
+    "PATHEXT",
+];
+
+/// Runtimes whose scripts reference variables as `$VAR`
+const SHELL_RUNTIMES: &[&str] = &[
+    "bash", "sh", "zsh", "fish", "ksh", "dash", "csh", "tcsh", "make",
+];
+
+/// Accessors followed by a quoted variable name
+const QUOTED_ACCESSORS: &[&str] = &[
+    "environ[",
+    "environ.get(",
+    "getenv(",
+    "Getenv(",
+    "LookupEnv(",
+    "env.get(",
+    "env[",
+    "env::var(",
+    "env::var_os(",
+    "ENV[",
+    "ENV.fetch(",
+    "$ENV{",
+];
+
+/// Whether a variable is passed to the script in hermetic mode
+pub fn is_allowed(annotations: &ScriptAnnotations, var: &str) -> bool {
+    DEFAULT_ALLOWED_ENV.contains(&var)
+        || annotations.env_vars.iter().any(|v| v == var)
+        || annotations.env_allow.iter().any(|v| v == var)
+}
+
+/// Environment of a hermetic run: the allowed variables of the current environment
+pub fn hermetic_env(annotations: &ScriptAnnotations) -> Vec<(String, String)> {
+    std::env::vars()
+        .filter(|(var, _)| is_allowed(annotations, var))
+        .collect()
+}
+
+/// Variables the script reads that are set, but neither declared nor allowed, sorted
+pub fn undeclared_env_reads(
+    script_path: &Path,
+    annotations: &ScriptAnnotations,
+) -> Result<Vec<String>> {
+    let content = fs::read_to_string(script_path)
+        .with_context(|| format!("Failed to read script: {}", script_path.display()))?;
+
+    Ok(env_reads(&content, &annotations.runtime)
+        .into_iter()
+        .filter(|var| !is_allowed(annotations, var) && std::env::var_os(var).is_some())
+        .collect())
+}
+
+/// Variables a script reads from its environment
+///
+/// For shell scripts, variables the script assigns itself are left out.
+pub fn env_reads(content: &str, runtime: &str) -> BTreeSet<String> {
+    let mut reads = BTreeSet::new();
+
+    for accessor in QUOTED_ACCESSORS {
+        for (start, _) in content.match_indices(accessor) {
+            let rest = content[start + accessor.len()..].trim_start();
+            let rest = rest.strip_prefix(['"', '\'']).unwrap_or(rest);
+            if let Some(name) = identifier(rest) {
+                reads.insert(name.to_string());
+            }
+        }
+    }
+
+    // process.env.VAR, Bun.env.VAR, $env:VAR (PowerShell)
+    for accessor in ["env.", "$env:"] {
+        for (start, _) in content.match_indices(accessor) {
+            if let Some(name) = identifier(&content[start + accessor.len()..]) {
+                // Methods like Deno.env.get(...)
+                if !content[start + accessor.len() + name.len()..].starts_with('(') {
+                    reads.insert(name.to_string());
+                }
+            }
+        }
+    }
+
+    if SHELL_RUNTIMES.contains(&runtime) {
+        let assigned = shell_assignments(content);
+        for (start, _) in content.match_indices('$') {
+            if content[..start].ends_with('\\') {
+                continue;
+            }
+            let rest = &content[start + 1..];
+            let rest = rest.strip_prefix('{').unwrap_or(rest);
+            if let Some(name) = identifier(rest) {
+                if !assigned.contains(name) {
+                    reads.insert(name.to_string());
+                }
+            }
+        }
+    }
+
+    reads
+}
+
+/// Variables a shell script assigns (`VAR=...`, `export VAR=...`, `for VAR in`, `read VAR`)
+fn shell_assignments(content: &str) -> BTreeSet<&str> {
+    let mut assigned = BTreeSet::new();
+    for line in content.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        for (i, word) in words.iter().enumerate() {
+            if let Some(name) = identifier(word) {
+                if word[name.len()..].starts_with('=') {
+                    assigned.insert(name);
+                }
+            }
+            if i > 0 && matches!(words[i - 1], "for" | "read") {
+                if let Some(name) = identifier(word) {
+                    assigned.insert(name);
+                }
+            }
+        }
+    }
+    assigned
+}
+
+/// Leading identifier (`[A-Za-z_][A-Za-z0-9_]*`) of `s`
+fn identifier(s: &str) -> Option<&str> {
+    let end = s
+        .char_indices()
+        .find(|(i, c)| !(c.is_ascii_alphanumeric() || *c == '_') || (*i == 0 && c.is_ascii_digit()))
+        .map(|(i, _)| i)
+        .unwrap_or(s.len());
+    (end > 0).then(|| &s[..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(reads: BTreeSet<String>) -> Vec<String> {
+        reads.into_iter().collect()
+    }
+
+    #[test]
+    fn test_env_reads() {
+        let shell = "#!/usr/bin/env -S fabrik run bash\n\
+                     OUT=dist\n\
+                     for f in src/*; do cp \"$f\" \"${OUT}/$BUILD_MODE\"; done\n\
+                     echo \\$ESCAPED $1 $HOME\n";
+        assert_eq!(names(env_reads(shell, "bash")), vec!["BUILD_MODE", "HOME"]);
+
+        let node = "const mode = process.env.NODE_ENV;\n\
+                    const key = process.env['API_KEY'];\n\
+                    Deno.env.get(\"DENO_VAR\");\n";
+        assert_eq!(
+            names(env_reads(node, "node")),
+            vec!["API_KEY", "DENO_VAR", "NODE_ENV"]
+        );
+
+        let python = "import os\nos.environ['A']\nos.environ.get(\"B\")\nos.getenv('C')\n";
+        assert_eq!(names(env_reads(python, "python3")), vec!["A", "B", "C"]);
+
+        // `$VAR` is only shell syntax
+        assert!(env_reads("const s = `${name}`;", "node").is_empty());
+    }
+
+    #[test]
+    fn test_undeclared_env_reads_and_hermetic_env() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let script = temp.path().join("gen.sh");
+        fs::write(
+            &script,
+            "#!/bin/bash\necho $FABRIK_TEST_DECLARED $FABRIK_TEST_ALLOWED $FABRIK_TEST_UNDECLARED $FABRIK_TEST_UNSET $PATH\n",
+        )
+        .unwrap();
+        std::env::set_var("FABRIK_TEST_DECLARED", "1");
+        std::env::set_var("FABRIK_TEST_ALLOWED", "1");
+        std::env::set_var("FABRIK_TEST_UNDECLARED", "1");
+
+        let annotations = ScriptAnnotations {
+            runtime: "bash".to_string(),
+            env_vars: vec!["FABRIK_TEST_DECLARED".to_string()],
+            env_allow: vec!["FABRIK_TEST_ALLOWED".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            undeclared_env_reads(&script, &annotations).unwrap(),
+            vec!["FABRIK_TEST_UNDECLARED"]
+        );
+
+        let env: Vec<String> = hermetic_env(&annotations)
+            .into_iter()
+            .map(|(var, _)| var)
+            .collect();
+        assert!(env.contains(&"FABRIK_TEST_DECLARED".to_string()));
+        assert!(env.contains(&"FABRIK_TEST_ALLOWED".to_string()));
+        assert!(!env.contains(&"FABRIK_TEST_UNDECLARED".to_string()));
+    }
+}
@@ -0,0 +1,136 @@
+/// Ignore-file aware filtering of globbed inputs
+///
+/// Globs like `src/**/*` also match dependencies and build outputs (`node_modules/`,
+/// `target/`), which then change cache keys on every install or build. Matched paths are
+/// therefore filtered through the ignore files of their directories, from the root of
+/// the enclosing git repository (or the glob's base directory outside one) down:
+///
+/// - `.gitignore`, with git's pattern syntax
+/// - `.fabrikignore`, same syntax, for paths git tracks but caching shouldn't see;
+///   its rules take precedence over `.gitignore` in the same directory
+///
+/// Rules of deeper directories override shallower ones, and `!pattern` re-includes paths.
+/// Paths inside `.git/` are always ignored. Inputs can opt out of filtering
+/// (`include-ignored=#true` in annotations, `{ includeIgnored: true }` in recipes).
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Ignore files read in each directory, later ones taking precedence
+const IGNORE_FILES: &[&str] = &[".gitignore", ".fabrikignore"];
+
+/// Filter of the paths ignored by ignore files, caching each directory's rules
+pub struct IgnoreFilter {
+    root: PathBuf,
+    matchers: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl IgnoreFilter {
+    /// Filter for paths under `base_dir`
+    pub fn new(base_dir: &Path) -> Self {
+        let base_dir = absolute(base_dir);
+        let root = base_dir
+            .ancestors()
+            .find(|dir| dir.join(".git").exists())
+            .unwrap_or(&base_dir)
+            .to_path_buf();
+        Self {
+            root,
+            matchers: HashMap::new(),
+        }
+    }
+
+    /// Whether `path` is ignored (paths outside the root never are)
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let path = absolute(path);
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if relative.components().any(|c| c.as_os_str() == ".git") {
+            return true;
+        }
+
+        // The deepest directory with a matching rule decides
+        let dirs: Vec<PathBuf> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.root))
+            .map(Path::to_path_buf)
+            .collect();
+        for dir in dirs {
+            let Some(matcher) = self.matcher(&dir) else {
+                continue;
+            };
+            let matched = matcher.matched_path_or_any_parents(&path, is_dir);
+            if matched.is_ignore() {
+                return true;
+            }
+            if matched.is_whitelist() {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Remove the ignored files and directories from `paths`
+    pub fn retain(&mut self, paths: &mut Vec<PathBuf>) {
+        paths.retain(|path| !self.is_ignored(path, path.is_dir()));
+    }
+
+    fn matcher(&mut self, dir: &Path) -> Option<&Gitignore> {
+        self.matchers
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let mut builder = GitignoreBuilder::new(dir);
+                let mut found = false;
+                for name in IGNORE_FILES {
+                    let file = dir.join(name);
+                    // Unreadable ignore files and invalid lines are skipped, as git does
+                    if file.is_file() && builder.add(&file).is_none() {
+                        found = true;
+                    }
+                }
+                found.then(|| builder.build().ok()).flatten()
+            })
+            .as_ref()
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_ignore_files() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        fs::create_dir(root.join(".git")).unwrap();
+        fs::write(root.join(".gitignore"), "node_modules/\n*.log\n").unwrap();
+        fs::create_dir_all(root.join("app/src")).unwrap();
+        fs::write(root.join("app/.gitignore"), "!keep.log\n").unwrap();
+        fs::write(root.join("app/.fabrikignore"), "fixtures/\n").unwrap();
+
+        // Rules of the repository root apply below it
+        let mut filter = IgnoreFilter::new(&root.join("app"));
+        assert!(filter.is_ignored(&root.join("app/node_modules/lib/index.js"), false));
+        assert!(filter.is_ignored(&root.join("app/src/debug.log"), false));
+        assert!(filter.is_ignored(&root.join("app/fixtures/big.bin"), false));
+        assert!(filter.is_ignored(&root.join(".git/HEAD"), false));
+        assert!(!filter.is_ignored(&root.join("app/src/main.ts"), false));
+        // Deeper rules re-include
+        assert!(!filter.is_ignored(&root.join("app/keep.log"), false));
+
+        let mut paths = vec![
+            root.join("app/src/main.ts"),
+            root.join("app/node_modules/lib/index.js"),
+        ];
+        filter.retain(&mut paths);
+        assert_eq!(paths, vec![root.join("app/src/main.ts")]);
+    }
+}
@@ -3,26 +3,133 @@
 /// Handles glob expansion and file hashing with different strategies.
 use anyhow::{Context, Result};
 use glob::glob;
-use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
 use super::annotations::{HashMethod, InputSpec};
+use super::ignore_files::IgnoreFilter;
+use super::memo::HashMemo;
+use crate::hashing::HashAlgorithm;
 
 /// Result of hashing input files
 #[derive(Debug, Clone)]
 pub struct InputHash {
-    #[allow(dead_code)] // May be used in future for detailed cache metadata
     pub files: Vec<PathBuf>,
+    /// Hash of each file (hex), in the order of `files`
+    pub file_hashes: Vec<String>,
     pub combined_hash: String,
 }
 
+/// Inputs with fewer files than this per thread are hashed on fewer threads
+const MIN_FILES_PER_WORKER: usize = 16;
+
+/// Hashes input files with an algorithm, optionally reusing memoized content hashes
+#[derive(Debug, Default)]
+pub struct InputHasher {
+    algorithm: HashAlgorithm,
+    memo: Option<HashMemo>,
+}
+
+impl InputHasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        Self {
+            algorithm,
+            memo: None,
+        }
+    }
+
+    /// Reuse and record content hashes in `memo`
+    pub fn with_memo(mut self, memo: HashMemo) -> Self {
+        self.memo = Some(memo);
+        self
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// Hash `files` on up to one thread per CPU, returning their hashes in order
+    fn hash_files(&self, files: &[PathBuf], method: HashMethod) -> Result<Vec<[u8; 32]>> {
+        let workers = num_cpus::get()
+            .min(files.len() / MIN_FILES_PER_WORKER)
+            .max(1);
+        if workers == 1 {
+            return files
+                .iter()
+                .map(|file| self.hash_file(file, method))
+                .collect();
+        }
+
+        // Workers take the next file as they go, so a few large files don't hold up one
+        let next = AtomicUsize::new(0);
+        let mut hashes: Vec<Option<Result<[u8; 32]>>> = (0..files.len()).map(|_| None).collect();
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut hashed = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(file) = files.get(i) else {
+                                break;
+                            };
+                            hashed.push((i, self.hash_file(file, method)));
+                        }
+                        hashed
+                    })
+                })
+                .collect();
+            for handle in handles {
+                let hashed = handle
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("Input hashing thread panicked"))?;
+                for (i, hash) in hashed {
+                    hashes[i] = Some(hash);
+                }
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+
+        hashes
+            .into_iter()
+            .map(|hash| hash.expect("every file is hashed"))
+            .collect()
+    }
+
+    fn hash_file(&self, file: &Path, method: HashMethod) -> Result<[u8; 32]> {
+        match (method, &self.memo) {
+            (HashMethod::Content, Some(memo)) => {
+                let metadata = fs::metadata(file)
+                    .with_context(|| format!("Failed to read metadata: {}", file.display()))?;
+                // Scripts may be run from anywhere, so memo entries use absolute paths
+                let path = std::path::absolute(file).unwrap_or_else(|_| file.to_path_buf());
+                if let Some(hash) = memo.get(self.algorithm, &path, &metadata) {
+                    return Ok(hash);
+                }
+                let hash = hash_file_content(file, self.algorithm)?;
+                // A memo that can't be written only costs the next run some hashing
+                let _ = memo.insert(self.algorithm, &path, &metadata, hash);
+                Ok(hash)
+            }
+            (HashMethod::Content, None) => hash_file_content(file, self.algorithm),
+            (HashMethod::Mtime, _) => hash_file_mtime(file, self.algorithm),
+            (HashMethod::Size, _) => hash_file_size(file, self.algorithm),
+        }
+    }
+}
+
 /// Hash all input files according to their specifications
-pub fn hash_inputs(inputs: &[InputSpec], base_dir: &Path) -> Result<Vec<InputHash>> {
+pub fn hash_inputs(
+    inputs: &[InputSpec],
+    base_dir: &Path,
+    hasher: &InputHasher,
+) -> Result<Vec<InputHash>> {
     let mut results = Vec::new();
 
     for input in inputs {
-        let input_hash = hash_input(input, base_dir)
+        let input_hash = hash_input(input, base_dir, hasher)
             .with_context(|| format!("Failed to hash input: {}", input.path))?;
         results.push(input_hash);
     }
@@ -31,45 +138,55 @@ pub fn hash_inputs(inputs: &[InputSpec], base_dir: &Path) -> Result<Vec<InputHas
 }
 
 /// Hash a single input specification
-fn hash_input(input: &InputSpec, base_dir: &Path) -> Result<InputHash> {
-    // Expand glob pattern
-    let files = expand_glob(&input.path, base_dir)?;
+///
+/// Files are hashed in parallel and combined in path order, so the result doesn't
+/// depend on which thread finished first.
+fn hash_input(input: &InputSpec, base_dir: &Path, hasher: &InputHasher) -> Result<InputHash> {
+    let files = expand_input(input, base_dir)?;
 
     if files.is_empty() {
         // Empty input is valid (might be optional files)
         return Ok(InputHash {
             files: vec![],
+            file_hashes: vec![],
             combined_hash: String::from("empty"),
         });
     }
 
     // Hash each file and combine
-    let mut hasher = Sha256::new();
-
-    for file in &files {
-        let file_hash = match input.hash {
-            HashMethod::Content => hash_file_content(file)?,
-            HashMethod::Mtime => hash_file_mtime(file)?,
-            HashMethod::Size => hash_file_size(file)?,
-        };
+    let hashes = hasher.hash_files(&files, input.hash)?;
+    let mut combined = hasher.algorithm.hasher();
+    let mut file_hashes = Vec::with_capacity(files.len());
 
+    for (file, file_hash) in files.iter().zip(hashes) {
         // Include file path (relative to base_dir) in hash for uniqueness
         let rel_path = file
             .strip_prefix(base_dir)
             .unwrap_or(file)
             .to_string_lossy();
-        hasher.update(rel_path.as_bytes());
-        hasher.update(&file_hash);
+        combined.update(rel_path.as_bytes());
+        combined.update(file_hash);
+        file_hashes.push(hex::encode(file_hash));
     }
 
-    let combined_hash = hex::encode(hasher.finalize());
+    let combined_hash = combined.finalize_hex();
 
     Ok(InputHash {
         files,
+        file_hashes,
         combined_hash,
     })
 }
 
+/// Files matched by an input, without ignored ones unless it includes them
+pub fn expand_input(input: &InputSpec, base_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = expand_glob(&input.path, base_dir)?;
+    if !input.include_ignored {
+        IgnoreFilter::new(base_dir).retain(&mut files);
+    }
+    Ok(files)
+}
+
 /// Expand glob pattern relative to base directory
 pub fn expand_glob(pattern: &str, base_dir: &Path) -> Result<Vec<PathBuf>> {
     // Make pattern relative to base_dir
@@ -98,18 +215,16 @@ pub fn expand_glob(pattern: &str, base_dir: &Path) -> Result<Vec<PathBuf>> {
     Ok(paths)
 }
 
-/// Hash file contents using SHA256
-fn hash_file_content(path: &Path) -> Result<Vec<u8>> {
+/// Hash file contents
+fn hash_file_content(path: &Path, algorithm: HashAlgorithm) -> Result<[u8; 32]> {
     let content =
         fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
 
-    let mut hasher = Sha256::new();
-    hasher.update(&content);
-    Ok(hasher.finalize().to_vec())
+    Ok(algorithm.digest(&content))
 }
 
 /// Hash file modification time
-fn hash_file_mtime(path: &Path) -> Result<Vec<u8>> {
+fn hash_file_mtime(path: &Path, algorithm: HashAlgorithm) -> Result<[u8; 32]> {
     let metadata = fs::metadata(path)
         .with_context(|| format!("Failed to read metadata: {}", path.display()))?;
 
@@ -122,21 +237,17 @@ fn hash_file_mtime(path: &Path) -> Result<Vec<u8>> {
         .expect("Time went backwards")
         .as_secs();
 
-    let mut hasher = Sha256::new();
-    hasher.update(timestamp.to_le_bytes());
-    Ok(hasher.finalize().to_vec())
+    Ok(algorithm.digest(&timestamp.to_le_bytes()))
 }
 
 /// Hash file size
-fn hash_file_size(path: &Path) -> Result<Vec<u8>> {
+fn hash_file_size(path: &Path, algorithm: HashAlgorithm) -> Result<[u8; 32]> {
     let metadata = fs::metadata(path)
         .with_context(|| format!("Failed to read metadata: {}", path.display()))?;
 
     let size = metadata.len();
 
-    let mut hasher = Sha256::new();
-    hasher.update(size.to_le_bytes());
-    Ok(hasher.finalize().to_vec())
+    Ok(algorithm.digest(&size.to_le_bytes()))
 }
 
 /// Get runtime version (e.g., bash --version)
@@ -188,12 +299,16 @@ mod tests {
         let file = temp.path().join("test.txt");
         fs::write(&file, "hello world").unwrap();
 
-        let hash = hash_file_content(&file).unwrap();
+        let hash = hash_file_content(&file, HashAlgorithm::Sha256).unwrap();
         assert!(!hash.is_empty());
 
         // Same content = same hash
-        let hash2 = hash_file_content(&file).unwrap();
+        let hash2 = hash_file_content(&file, HashAlgorithm::Sha256).unwrap();
         assert_eq!(hash, hash2);
+
+        // Each algorithm hashes differently
+        let blake3 = hash_file_content(&file, HashAlgorithm::Blake3).unwrap();
+        assert_ne!(hash, blake3);
     }
 
     #[test]
@@ -207,10 +322,55 @@ mod tests {
         let input = InputSpec {
             path: "*.txt".to_string(),
             hash: HashMethod::Content,
+            include_ignored: false,
         };
 
-        let result = hash_input(&input, base).unwrap();
+        let result = hash_input(&input, base, &InputHasher::default()).unwrap();
         assert_eq!(result.files.len(), 2);
         assert!(!result.combined_hash.is_empty());
     }
+
+    #[test]
+    fn test_parallel_hashing_is_deterministic() {
+        let temp = TempDir::new().unwrap();
+        let base = temp.path();
+        for i in 0..200 {
+            fs::write(
+                base.join(format!("file{:03}.txt", i)),
+                format!("content{}", i),
+            )
+            .unwrap();
+        }
+        let input = InputSpec {
+            path: "*.txt".to_string(),
+            hash: HashMethod::Content,
+            include_ignored: false,
+        };
+
+        // One thread per CPU, or the files one after the other
+        let parallel = hash_input(&input, base, &InputHasher::default()).unwrap();
+        let mut sequential = HashAlgorithm::Sha256.hasher();
+        for (i, file) in parallel.files.iter().enumerate() {
+            sequential.update(
+                file.strip_prefix(base)
+                    .unwrap()
+                    .to_string_lossy()
+                    .as_bytes(),
+            );
+            sequential.update(hash_file_content(file, HashAlgorithm::Sha256).unwrap());
+            assert_eq!(
+                parallel.file_hashes[i],
+                HashAlgorithm::Sha256.hex_digest(format!("content{}", i).as_bytes())
+            );
+        }
+        assert_eq!(parallel.combined_hash, sequential.finalize_hex());
+
+        // Memoized hashes give the same result
+        let cache = TempDir::new().unwrap();
+        let memoized = InputHasher::default().with_memo(HashMemo::open(cache.path()).unwrap());
+        let first = hash_input(&input, base, &memoized).unwrap();
+        let second = hash_input(&input, base, &memoized).unwrap();
+        assert_eq!(first.combined_hash, parallel.combined_hash);
+        assert_eq!(second.combined_hash, parallel.combined_hash);
+    }
 }
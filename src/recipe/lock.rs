@@ -0,0 +1,362 @@
+/// Cooperative locks on script cache keys
+///
+/// When several processes run the same script concurrently (e.g. CI shards sharing a
+/// cache volume), they all miss and all execute. A lock file per cache key lets the
+/// first process execute while the others wait for its result and restore it from
+/// cache instead. Locks are best-effort: a waiter that times out executes anyway, and
+/// locks held by dead or stalled processes are taken over.
+///
+/// The lock files live in the script cache directory, so only processes sharing that
+/// directory are coordinated: on one machine, or on several machines mounting the same
+/// cache volume. Machines with separate caches don't see each other's locks.
+///
+/// Lock files are only ever removed after being renamed aside and checked, so a stale
+/// lock is taken over by a single waiter, and a holder whose lock was taken over
+/// doesn't remove its successor's.
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Options controlling how long to wait for a lock and when to take it over
+#[derive(Debug, Clone)]
+pub struct LockOptions {
+    /// Maximum time to wait for another process to release the lock
+    pub wait_timeout: Duration,
+    /// Age after which a held lock is considered abandoned and taken over
+    pub stale_after: Duration,
+    /// Interval between lock acquisition attempts
+    pub poll_interval: Duration,
+}
+
+impl Default for LockOptions {
+    fn default() -> Self {
+        Self {
+            wait_timeout: Duration::from_secs(300),
+            stale_after: Duration::from_secs(1800),
+            poll_interval: Duration::from_millis(200),
+        }
+    }
+}
+
+/// Information about the lock holder (stored in the lock file)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub hostname: String,
+    pub acquired_at: DateTime<Utc>,
+    /// Random identifier of this acquisition
+    #[serde(default)]
+    pub token: String,
+}
+
+impl LockHolder {
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            hostname: current_hostname(),
+            acquired_at: Utc::now(),
+            token: random_token(),
+        }
+    }
+
+    /// Whether this lock can be taken over by another process
+    fn is_stale(&self, stale_after: Duration) -> bool {
+        // Holder on this machine that is no longer running
+        if self.hostname == current_hostname()
+            && !crate::config_discovery::is_process_running(self.pid)
+        {
+            return true;
+        }
+
+        // Holder (possibly on another machine) that has held the lock too long
+        let age = Utc::now() - self.acquired_at;
+        age.to_std().map(|age| age > stale_after).unwrap_or(false)
+    }
+}
+
+/// Result of trying to acquire a cache key lock
+#[derive(Debug)]
+pub enum LockOutcome {
+    /// Lock acquired. `waited` is true if another process held it first, in which
+    /// case the caller should re-check the cache before executing.
+    Acquired { lock: CacheKeyLock, waited: bool },
+    /// Another process still holds the lock after the wait timeout
+    TimedOut { holder: Option<LockHolder> },
+}
+
+/// Held lock on a cache key (released on drop)
+#[derive(Debug)]
+pub struct CacheKeyLock {
+    path: PathBuf,
+    token: String,
+}
+
+impl CacheKeyLock {
+    /// Acquire the lock for `cache_key` in `locks_dir`, waiting for other holders
+    ///
+    /// Blocks the calling thread while waiting; on a multi-threaded Tokio runtime the
+    /// wait is moved off the worker with `block_in_place`.
+    pub fn acquire(
+        locks_dir: &Path,
+        cache_key: &str,
+        options: &LockOptions,
+    ) -> Result<LockOutcome> {
+        fs::create_dir_all(locks_dir)
+            .with_context(|| format!("Failed to create lock directory: {}", locks_dir.display()))?;
+
+        let path = locks_dir.join(format!("{}.lock", cache_key));
+        let start = Instant::now();
+        let mut waited = false;
+
+        loop {
+            if let Some(token) = Self::try_create(&path)? {
+                return Ok(LockOutcome::Acquired {
+                    lock: Self { path, token },
+                    waited,
+                });
+            }
+
+            let holder = read_holder(&path);
+            match &holder {
+                Some(h) if h.is_stale(options.stale_after) => {
+                    tracing::warn!(
+                        "Taking over stale lock for {} (held by pid {} on {})",
+                        cache_key,
+                        h.pid,
+                        h.hostname
+                    );
+                    // Only if it's still the lock found stale, not one created since
+                    remove_lock_if(&path, |aside| read_holder(aside).as_ref() == Some(h));
+                    continue;
+                }
+                // Lock file exists but is unreadable or half-written; treat it as held
+                // until it ages past the stale threshold
+                None => {
+                    if lock_file_age(&path).is_some_and(|age| age > options.stale_after) {
+                        remove_lock_if(&path, |aside| {
+                            read_holder(aside).is_none()
+                                && lock_file_age(aside).is_some_and(|age| age > options.stale_after)
+                        });
+                        continue;
+                    }
+                }
+                Some(_) => {}
+            }
+
+            if start.elapsed() >= options.wait_timeout {
+                return Ok(LockOutcome::TimedOut { holder });
+            }
+
+            waited = true;
+            pause(options.poll_interval);
+        }
+    }
+
+    /// Atomically create the lock file, returning its token, or None if it already exists
+    fn try_create(path: &Path) -> Result<Option<String>> {
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(mut file) => {
+                let holder = LockHolder::current();
+                let json =
+                    serde_json::to_vec(&holder).context("Failed to serialize lock holder")?;
+                file.write_all(&json)
+                    .with_context(|| format!("Failed to write lock: {}", path.display()))?;
+                Ok(Some(holder.token))
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to create lock: {}", path.display())),
+        }
+    }
+}
+
+impl Drop for CacheKeyLock {
+    fn drop(&mut self) {
+        // The lock may have been taken over (e.g. after stalling): leave the new holder's
+        remove_lock_if(&self.path, |aside| {
+            read_holder(aside).is_some_and(|holder| holder.token == self.token)
+        });
+    }
+}
+
+/// Remove the lock file at `path` if `is_expected` accepts it, returning whether it did
+///
+/// The file is first renamed aside, which only one process can do, then checked and
+/// put back if it turns out to be another lock than the one expected (one created
+/// after the caller read it). Checking in place and then removing would race with that.
+fn remove_lock_if(path: &Path, is_expected: impl FnOnce(&Path) -> bool) -> bool {
+    let aside = path.with_extension(format!("lock.{}.removed", random_token()));
+    if fs::rename(path, &aside).is_err() {
+        return false;
+    }
+    let expected = is_expected(&aside);
+    if !expected {
+        // Unless yet another lock was created meanwhile
+        let _ = fs::hard_link(&aside, path);
+    }
+    let _ = fs::remove_file(&aside);
+    expected
+}
+
+/// Sleep between acquisition attempts without stalling a Tokio worker thread
+fn pause(interval: Duration) {
+    use tokio::runtime::{Handle, RuntimeFlavor};
+
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| std::thread::sleep(interval))
+        }
+        _ => std::thread::sleep(interval),
+    }
+}
+
+fn random_token() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+fn read_holder(path: &Path) -> Option<LockHolder> {
+    let contents = fs::read(path).ok()?;
+    serde_json::from_slice(&contents).ok()
+}
+
+fn lock_file_age(path: &Path) -> Option<Duration> {
+    fs::metadata(path).ok()?.modified().ok()?.elapsed().ok()
+}
+
+fn current_hostname() -> String {
+    hostname::get()
+        .ok()
+        .and_then(|h| h.into_string().ok())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn fast_options() -> LockOptions {
+        LockOptions {
+            wait_timeout: Duration::from_millis(100),
+            stale_after: Duration::from_secs(60),
+            poll_interval: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn test_acquire_and_release() {
+        let temp = TempDir::new().unwrap();
+
+        let outcome = CacheKeyLock::acquire(temp.path(), "script-abc", &fast_options()).unwrap();
+        let lock = match outcome {
+            LockOutcome::Acquired { lock, waited } => {
+                assert!(!waited);
+                lock
+            }
+            LockOutcome::TimedOut { .. } => panic!("expected lock to be acquired"),
+        };
+
+        assert!(temp.path().join("script-abc.lock").exists());
+        drop(lock);
+        assert!(!temp.path().join("script-abc.lock").exists());
+    }
+
+    #[test]
+    fn test_second_acquire_times_out_while_held() {
+        let temp = TempDir::new().unwrap();
+
+        let _first = CacheKeyLock::acquire(temp.path(), "script-abc", &fast_options()).unwrap();
+        let second = CacheKeyLock::acquire(temp.path(), "script-abc", &fast_options()).unwrap();
+
+        match second {
+            LockOutcome::TimedOut { holder } => {
+                assert_eq!(holder.unwrap().pid, std::process::id());
+            }
+            LockOutcome::Acquired { .. } => panic!("expected timeout while lock is held"),
+        }
+    }
+
+    #[test]
+    fn test_takeover_of_stale_lock() {
+        let temp = TempDir::new().unwrap();
+
+        // Simulate a lock held for longer than the stale threshold
+        let holder = LockHolder {
+            pid: std::process::id(),
+            hostname: current_hostname(),
+            acquired_at: Utc::now() - chrono::Duration::hours(2),
+            token: random_token(),
+        };
+        fs::write(
+            temp.path().join("script-abc.lock"),
+            serde_json::to_vec(&holder).unwrap(),
+        )
+        .unwrap();
+
+        let outcome = CacheKeyLock::acquire(temp.path(), "script-abc", &fast_options()).unwrap();
+        assert!(matches!(outcome, LockOutcome::Acquired { .. }));
+    }
+
+    #[test]
+    fn test_stale_lock_taken_over_once() {
+        let temp = TempDir::new().unwrap();
+        let holder = LockHolder {
+            pid: std::process::id(),
+            hostname: current_hostname(),
+            acquired_at: Utc::now() - chrono::Duration::hours(2),
+            token: random_token(),
+        };
+        fs::write(
+            temp.path().join("script-abc.lock"),
+            serde_json::to_vec(&holder).unwrap(),
+        )
+        .unwrap();
+
+        // Waiters racing for the stale lock: exactly one gets it
+        let barrier = std::sync::Barrier::new(4);
+        let outcomes: Vec<LockOutcome> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        CacheKeyLock::acquire(temp.path(), "script-abc", &fast_options()).unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let acquired = outcomes
+            .iter()
+            .filter(|outcome| matches!(outcome, LockOutcome::Acquired { .. }))
+            .count();
+        assert_eq!(acquired, 1);
+    }
+
+    #[test]
+    fn test_release_keeps_successor_lock() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("script-abc.lock");
+
+        let LockOutcome::Acquired { lock, .. } =
+            CacheKeyLock::acquire(temp.path(), "script-abc", &fast_options()).unwrap()
+        else {
+            panic!("expected lock to be acquired");
+        };
+
+        // Taken over by another process while this one stalled
+        let successor = LockHolder {
+            pid: 1,
+            hostname: "other-host".to_string(),
+            acquired_at: Utc::now(),
+            token: random_token(),
+        };
+        fs::write(&path, serde_json::to_vec(&successor).unwrap()).unwrap();
+
+        drop(lock);
+        assert_eq!(read_holder(&path), Some(successor));
+        assert_eq!(fs::read_dir(temp.path()).unwrap().count(), 1);
+    }
+}
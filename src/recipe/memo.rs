@@ -0,0 +1,237 @@
+/// Persistent cache of the content hashes of input files
+///
+/// Hashing the inputs of a script reads every file they match, which takes seconds on
+/// repositories with tens of thousands of files. The memo remembers the content hash of
+/// each file along with its size, modification time and inode, and `fabrik run` reuses
+/// the hash while all three are unchanged. It's a RocksDB database in the script cache
+/// (`scripts/.input-hashes/`), so lookups and new hashes don't load or rewrite the whole
+/// memo:
+///
+/// - key: hash algorithm, a NUL byte and the absolute path of the file
+/// - value: size, mtime (nanoseconds since the epoch) and inode, each u64 LE, then the
+///   32-byte hash
+///
+/// Files modified within `RACY_WINDOW` of being hashed aren't memoized: a write in the
+/// same timestamp tick as the hash could change the file without changing its stamp.
+use anyhow::{Context, Result};
+use rocksdb::{IteratorMode, Options, DB};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::cache::INPUT_HASHES_DIR;
+use crate::hashing::HashAlgorithm;
+
+/// Files modified more recently than this are hashed every time
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// Above this many entries, entries of deleted or changed files are pruned on open
+const MAX_ENTRIES: u64 = 200_000;
+
+const STAMP_LEN: usize = 24;
+
+/// Size, modification time (nanoseconds since the epoch) and inode of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    size: u64,
+    mtime_ns: u64,
+    inode: u64,
+}
+
+impl Stamp {
+    fn of(metadata: &fs::Metadata) -> Option<Self> {
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            size: metadata.len(),
+            mtime_ns: u64::try_from(mtime.as_nanos()).ok()?,
+            inode: inode(metadata),
+        })
+    }
+
+    fn to_bytes(self) -> [u8; STAMP_LEN] {
+        let mut bytes = [0; STAMP_LEN];
+        bytes[..8].copy_from_slice(&self.size.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.mtime_ns.to_le_bytes());
+        bytes[16..].copy_from_slice(&self.inode.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let field = |i: usize| Some(u64::from_le_bytes(bytes.get(i..i + 8)?.try_into().ok()?));
+        Some(Self {
+            size: field(0)?,
+            mtime_ns: field(8)?,
+            inode: field(16)?,
+        })
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> u64 {
+    0
+}
+
+fn key(algorithm: HashAlgorithm, path: &Path) -> Vec<u8> {
+    let mut key = algorithm.as_str().as_bytes().to_vec();
+    key.push(0);
+    key.extend_from_slice(path.as_os_str().as_encoded_bytes());
+    key
+}
+
+/// Stamp and hash of a memo value
+fn decode(value: &[u8]) -> Option<(Stamp, [u8; 32])> {
+    let stamp = Stamp::from_bytes(value)?;
+    let hash = value.get(STAMP_LEN..)?.try_into().ok()?;
+    Some((stamp, hash))
+}
+
+/// Content hashes of input files by path, size, modification time and inode
+pub struct HashMemo {
+    db: DB,
+}
+
+impl std::fmt::Debug for HashMemo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HashMemo").finish_non_exhaustive()
+    }
+}
+
+impl HashMemo {
+    /// Memo of the script cache in `cache_dir`
+    ///
+    /// Fails while another `fabrik run` has the memo open; callers then hash without it.
+    pub fn open(cache_dir: &Path) -> Result<Self> {
+        let path = cache_dir.join("scripts").join(INPUT_HASHES_DIR);
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let db = DB::open(&opts, &path)
+            .with_context(|| format!("Failed to open input hash memo: {}", path.display()))?;
+
+        let memo = Self { db };
+        let entries = memo
+            .db
+            .property_int_value("rocksdb.estimate-num-keys")
+            .ok()
+            .flatten()
+            .unwrap_or(0);
+        if entries > MAX_ENTRIES {
+            memo.prune()?;
+        }
+        Ok(memo)
+    }
+
+    /// Memoized hash of `path`, if it's unchanged since it was hashed
+    pub fn get(
+        &self,
+        algorithm: HashAlgorithm,
+        path: &Path,
+        metadata: &fs::Metadata,
+    ) -> Option<[u8; 32]> {
+        let stamp = Stamp::of(metadata)?;
+        let value = self.db.get(key(algorithm, path)).ok()??;
+        let (memoized, hash) = decode(&value)?;
+        (memoized == stamp).then_some(hash)
+    }
+
+    /// Remember the hash of `path`, read when it had `metadata`
+    pub fn insert(
+        &self,
+        algorithm: HashAlgorithm,
+        path: &Path,
+        metadata: &fs::Metadata,
+        hash: [u8; 32],
+    ) -> Result<()> {
+        let Some(stamp) = Stamp::of(metadata) else {
+            return Ok(());
+        };
+        let racy = metadata
+            .modified()
+            .ok()
+            .and_then(|mtime| SystemTime::now().duration_since(mtime).ok())
+            .is_none_or(|age| age < RACY_WINDOW);
+        if racy {
+            return Ok(());
+        }
+
+        let mut value = stamp.to_bytes().to_vec();
+        value.extend_from_slice(&hash);
+        self.db
+            .put(key(algorithm, path), value)
+            .context("Failed to write input hash memo")
+    }
+
+    /// Remove the entries of files that were deleted or changed since they were hashed
+    fn prune(&self) -> Result<()> {
+        for item in self.db.iterator(IteratorMode::Start) {
+            let (key, value) = item.context("Failed to read input hash memo")?;
+            let current = key
+                .iter()
+                .position(|byte| *byte == 0)
+                .and_then(|nul| std::str::from_utf8(&key[nul + 1..]).ok())
+                .and_then(|path| fs::metadata(path).ok())
+                .and_then(|metadata| Stamp::of(&metadata));
+            let memoized = decode(&value).map(|(stamp, _)| stamp);
+            if current.is_none() || current != memoized {
+                self.db
+                    .delete(&key)
+                    .context("Failed to prune input hash memo")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_memo_persists_hashes() {
+        let temp = TempDir::new().unwrap();
+        let file = temp.path().join("input.txt");
+        fs::write(&file, "content").unwrap();
+        // Old enough not to be racy
+        let old = SystemTime::now() - Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        let metadata = fs::metadata(&file).unwrap();
+
+        let memo = HashMemo::open(temp.path()).unwrap();
+        assert_eq!(memo.get(HashAlgorithm::Sha256, &file, &metadata), None);
+        memo.insert(HashAlgorithm::Sha256, &file, &metadata, [7; 32])
+            .unwrap();
+        drop(memo);
+
+        let memo = HashMemo::open(temp.path()).unwrap();
+        assert_eq!(
+            memo.get(HashAlgorithm::Sha256, &file, &metadata),
+            Some([7; 32])
+        );
+        assert_eq!(memo.get(HashAlgorithm::Blake3, &file, &metadata), None);
+
+        // A changed file misses
+        fs::write(&file, "changed").unwrap();
+        let changed = fs::metadata(&file).unwrap();
+        assert_eq!(memo.get(HashAlgorithm::Sha256, &file, &changed), None);
+
+        // Files modified just now aren't memoized
+        memo.insert(HashAlgorithm::Sha256, &file, &changed, [8; 32])
+            .unwrap();
+        assert_eq!(memo.get(HashAlgorithm::Sha256, &file, &changed), None);
+
+        // Entries of changed files are pruned
+        memo.prune().unwrap();
+        assert_eq!(memo.db.iterator(IteratorMode::Start).count(), 0);
+    }
+}
@@ -3,13 +3,21 @@
 /// Provides Nx-style caching for arbitrary scripts using KDL annotations.
 /// Scripts can declare inputs, outputs, environment dependencies, and cache behavior
 /// inline using KDL comments.
+pub mod affected;
 pub mod annotations;
 pub mod cache;
 pub mod cache_key;
 pub mod dependencies;
+pub mod docker;
 pub mod executor;
+pub mod hermetic;
+pub mod ignore_files;
 pub mod inputs;
+pub mod lock;
+pub mod memo;
 pub mod outputs;
+pub mod remote;
+pub mod watch;
 
 #[allow(unused_imports)]
 pub use annotations::ScriptAnnotations;
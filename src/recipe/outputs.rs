@@ -1,14 +1,51 @@
 /// Output archiving and restoration
 ///
 /// Handles creating tar+zstd archives of script outputs and extracting them for cache restoration.
+/// Archives keep file modes, modification times and symlinks (stored as links, never
+/// followed), and are streamed through the compressor in a single pass over the files,
+/// which also computes their sizes and hashes. Restoration (see `RestoreOptions`) can
+/// skip modification times and delete stale files from output directories.
 use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
 use std::fs::{self, File};
-use std::io::Write;
-use std::path::{Path, PathBuf};
-use tar::{Archive, Builder};
-use zstd::{decode_all, encode_all};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, Builder, EntryType, Header, HeaderMode};
+use zstd::{Decoder, Encoder};
 
 use super::annotations::OutputSpec;
+use super::docker::{load_images, save_images, ImageOutputSpec, SavedImage, IMAGES_DIR};
+
+/// Version of the archive format, recorded in cache metadata
+///
+/// 1: tar+zstd with symlinks followed and permissions not restored
+/// 2: modes, modification times and symlinks preserved
+/// 3: Docker images saved under `.fabrik-docker/`
+pub const ARCHIVE_FORMAT_VERSION: u32 = 3;
+
+/// zstd compression level of output archives
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// How cached outputs are restored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestoreOptions {
+    /// Restore modification times; otherwise restored files are stamped with the current
+    /// time, so mtime-based tools such as make see them as new
+    pub mtimes: bool,
+    /// Delete files and directories in restored output directories that aren't in the
+    /// archive, so outputs match the cached run exactly
+    pub clean: bool,
+}
+
+impl Default for RestoreOptions {
+    fn default() -> Self {
+        Self {
+            mtimes: true,
+            clean: false,
+        }
+    }
+}
 
 /// Information about archived outputs
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -20,17 +57,14 @@ pub struct ArchivedOutput {
     pub is_directory: bool,
 }
 
-/// Archive outputs to a tar+zstd file
+/// Archive outputs and Docker images (see `docker`) to a tar+zstd file
 pub fn archive_outputs(
     outputs: &[OutputSpec],
+    images: &[ImageOutputSpec],
     base_dir: &Path,
     archive_path: &Path,
-) -> Result<Vec<ArchivedOutput>> {
-    // Create tar archive in memory
-    let mut tar_data = Vec::new();
-    let mut tar = Builder::new(&mut tar_data);
-
-    let mut archived_outputs = Vec::new();
+) -> Result<(Vec<ArchivedOutput>, Vec<SavedImage>)> {
+    let mut paths = Vec::new();
 
     for output in outputs {
         let output_path = if Path::new(&output.path).is_absolute() {
@@ -39,7 +73,8 @@ pub fn archive_outputs(
             base_dir.join(&output.path)
         };
 
-        if !output_path.exists() {
+        // Dangling symlinks are outputs too
+        if output_path.symlink_metadata().is_err() {
             if output.required {
                 return Err(anyhow::anyhow!(
                     "Required output not found: {}",
@@ -51,108 +86,259 @@ pub fn archive_outputs(
             }
         }
 
-        let is_directory = output_path.is_dir();
-        let (size, file_count) = if is_directory {
-            // Archive directory recursively
-            tar.append_dir_all(&output.path, &output_path)
-                .with_context(|| format!("Failed to archive directory: {}", output.path))?;
-            get_dir_size_and_count(&output_path)?
-        } else {
-            // Archive single file
-            let mut file = File::open(&output_path)
-                .with_context(|| format!("Failed to open file: {}", output.path))?;
-            tar.append_file(&output.path, &mut file)
-                .with_context(|| format!("Failed to archive file: {}", output.path))?;
-            (file.metadata()?.len(), 1)
-        };
-
-        // Compute hash of output
-        let hash = compute_path_hash(&output_path)?;
-
-        archived_outputs.push(ArchivedOutput {
-            path: output.path.clone(),
-            artifact_hash: hash,
-            size_bytes: size,
-            file_count,
-            is_directory,
-        });
+        paths.push((output.path.clone(), output_path));
     }
 
-    // Finish tar archive
-    tar.finish().context("Failed to finalize tar archive")?;
-
-    drop(tar); // Release mutable borrow
+    archive_paths_with_images(paths, images, archive_path)
+}
 
-    // Compress with zstd
-    let compressed =
-        encode_all(tar_data.as_slice(), 3).context("Failed to compress archive with zstd")?;
+/// Archive `(name in archive, path)` pairs and Docker images to a tar+zstd file
+pub fn archive_paths_with_images(
+    mut paths: Vec<(String, PathBuf)>,
+    images: &[ImageOutputSpec],
+    archive_path: &Path,
+) -> Result<(Vec<ArchivedOutput>, Vec<SavedImage>)> {
+    // Images are appended last, and aren't outputs
+    let images_dir = tempfile::tempdir().context("Failed to create image directory")?;
+    let (image_paths, saved) = save_images(images, images_dir.path())?;
+    let output_count = paths.len();
+    paths.extend(image_paths);
+
+    let mut archived = archive_paths(&paths, archive_path)?;
+    archived.truncate(output_count);
+    Ok((archived, saved))
+}
 
-    // Write to file
-    let mut file = File::create(archive_path)
+/// Archive `(name in archive, path)` pairs to a tar+zstd file
+pub fn archive_paths(
+    paths: &[(String, PathBuf)],
+    archive_path: &Path,
+) -> Result<Vec<ArchivedOutput>> {
+    let file = File::create(archive_path)
         .with_context(|| format!("Failed to create archive: {}", archive_path.display()))?;
-    file.write_all(&compressed)
-        .context("Failed to write compressed archive")?;
+    let encoder =
+        Encoder::new(file, COMPRESSION_LEVEL).context("Failed to start zstd compression")?;
+    let mut tar = Builder::new(encoder);
+    tar.follow_symlinks(false);
+    tar.mode(HeaderMode::Complete);
+
+    let mut archived_outputs = Vec::new();
+    for (name, path) in paths {
+        archived_outputs.push(
+            append_output(&mut tar, name, path)
+                .with_context(|| format!("Failed to archive output: {}", name))?,
+        );
+    }
+
+    // Finish tar archive, then the compressed stream
+    let mut file = tar
+        .into_inner()
+        .context("Failed to finalize tar archive")?
+        .finish()
+        .context("Failed to compress archive with zstd")?;
+    file.flush().context("Failed to write compressed archive")?;
 
     Ok(archived_outputs)
 }
 
-/// Extract outputs from tar+zstd archive
-pub fn extract_outputs(archive_path: &Path, base_dir: &Path) -> Result<()> {
-    // Read compressed archive
-    let compressed = fs::read(archive_path)
-        .with_context(|| format!("Failed to read archive: {}", archive_path.display()))?;
+/// Append a file, symlink or directory tree, returning its size, file count and hash
+fn append_output<W: Write>(
+    tar: &mut Builder<W>,
+    name: &str,
+    path: &Path,
+) -> Result<ArchivedOutput> {
+    let mut hasher = Sha256::new();
+    let mut size_bytes = 0;
+    let mut file_count = 0;
+
+    let walker = walkdir::WalkDir::new(path)
+        .follow_links(false)
+        .follow_root_links(false)
+        .sort_by_file_name();
+    for entry in walker {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(path)?;
+        let entry_name = match relative.as_os_str().is_empty() {
+            true => PathBuf::from(name),
+            false => Path::new(name).join(relative),
+        };
 
-    // Decompress
-    let tar_data =
-        decode_all(compressed.as_slice()).context("Failed to decompress archive with zstd")?;
+        let file_type = entry.file_type();
+        if file_type.is_file() {
+            let metadata = entry.metadata()?;
+            let mut header = Header::new_gnu();
+            header.set_metadata_in_mode(&metadata, HeaderMode::Complete);
+            header.set_entry_type(EntryType::Regular);
+
+            // Hash the contents while tar reads them
+            let file = File::open(entry.path())?;
+            let reader = HashingReader {
+                inner: file,
+                hasher: &mut hasher,
+            };
+            tar.append_data(&mut header, &entry_name, reader)?;
+
+            size_bytes += metadata.len();
+            file_count += 1;
+        } else {
+            if file_type.is_symlink() {
+                let target = fs::read_link(entry.path())?;
+                hasher.update(target.to_string_lossy().as_bytes());
+            }
+            tar.append_path_with_name(entry.path(), &entry_name)?;
+        }
+    }
 
-    // Extract tar archive
-    let mut archive = Archive::new(tar_data.as_slice());
-    archive
-        .unpack(base_dir)
-        .with_context(|| format!("Failed to extract archive to: {}", base_dir.display()))?;
+    Ok(ArchivedOutput {
+        path: name.to_string(),
+        artifact_hash: hex::encode(hasher.finalize()),
+        size_bytes,
+        file_count,
+        is_directory: path.symlink_metadata()?.is_dir(),
+    })
+}
 
+/// Extract outputs from a tar+zstd archive
+pub fn extract_outputs(compressed: &[u8], base_dir: &Path, options: RestoreOptions) -> Result<()> {
+    extract_archive(compressed, base_dir, options)?;
     Ok(())
 }
 
-/// Compute hash of file or directory
-fn compute_path_hash(path: &Path) -> Result<String> {
-    use sha2::{Digest, Sha256};
+/// Extract a tar+zstd archive, returning the paths of its entries
+///
+/// Existing files are replaced, and modes (and modification times, unless `options`
+/// skip them) are restored. Docker images in the archive are loaded instead of extracted.
+pub fn extract_archive<R: Read>(
+    compressed: R,
+    base_dir: &Path,
+    options: RestoreOptions,
+) -> Result<Vec<PathBuf>> {
+    let images_dir = tempfile::tempdir().context("Failed to create image directory")?;
+    let paths = unpack_archive(compressed, base_dir, images_dir.path(), options)?;
+    load_images(images_dir.path())?;
+    Ok(paths)
+}
+
+/// Unpack outputs into `base_dir`, and images into `images_dir`
+fn unpack_archive<R: Read>(
+    compressed: R,
+    base_dir: &Path,
+    images_dir: &Path,
+    options: RestoreOptions,
+) -> Result<Vec<PathBuf>> {
+    let decoder = Decoder::new(compressed).context("Failed to decompress archive with zstd")?;
+    let mut archive = Archive::new(decoder);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(options.mtimes);
+    archive.set_overwrite(true);
+
+    let mut paths = Vec::new();
+    let mut directories = Vec::new();
+    for entry in archive.entries().context("Failed to read archive")? {
+        let mut entry = entry.context("Failed to read archive entry")?;
+        let path = entry.path()?.into_owned();
+        if path.starts_with(IMAGES_DIR) {
+            unpack_entry(&mut entry, images_dir)?;
+            continue;
+        }
+        // Cleaning works on these paths before `unpack_in` has checked them
+        if !is_contained(&path) {
+            anyhow::bail!(
+                "Archive entry escapes the output directory: {}",
+                path.display()
+            );
+        }
+        paths.push(path);
 
-    let mut hasher = Sha256::new();
+        // Directories last, so read-only ones don't block their contents
+        if entry.header().entry_type() == EntryType::Directory {
+            directories.push(entry);
+        } else {
+            unpack_entry(&mut entry, base_dir)?;
+        }
+    }
 
-    if path.is_dir() {
-        // Hash all files in directory
-        for entry in walkdir::WalkDir::new(path).sort_by_file_name() {
-            let entry = entry?;
-            if entry.file_type().is_file() {
-                let content = fs::read(entry.path())?;
-                hasher.update(&content);
-            }
+    // Before directory modes are restored, as they may be read-only
+    if options.clean {
+        let archived: HashSet<&Path> = paths.iter().map(PathBuf::as_path).collect();
+        for directory in &directories {
+            remove_stale(base_dir, &directory.path()?, &archived)?;
         }
-    } else {
-        // Hash single file
-        let content = fs::read(path)?;
-        hasher.update(&content);
     }
 
-    Ok(hex::encode(hasher.finalize()))
+    // Deepest first, so a directory's mode and mtime aren't changed by its children
+    directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+    for mut directory in directories {
+        unpack_entry(&mut directory, base_dir)?;
+    }
+
+    Ok(paths)
 }
 
-/// Get total size and file count of a directory
-fn get_dir_size_and_count(path: &Path) -> Result<(u64, usize)> {
-    let mut total_size = 0;
-    let mut file_count = 0;
+fn unpack_entry<R: Read>(entry: &mut tar::Entry<'_, R>, base_dir: &Path) -> Result<()> {
+    entry.unpack_in(base_dir).with_context(|| {
+        format!(
+            "Failed to extract {} to: {}",
+            String::from_utf8_lossy(&entry.path_bytes()),
+            base_dir.display()
+        )
+    })?;
+    Ok(())
+}
 
-    for entry in walkdir::WalkDir::new(path) {
-        let entry = entry?;
-        if entry.file_type().is_file() {
-            total_size += entry.metadata()?.len();
-            file_count += 1;
+/// Whether an archive path stays below the directory it is extracted into
+fn is_contained(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Delete the entries of `base_dir/dir` that aren't in the archive
+///
+/// Refuses to clean a directory reached through a symlink, which could point anywhere.
+fn remove_stale(base_dir: &Path, dir: &Path, archived: &HashSet<&Path>) -> Result<()> {
+    let mut current = base_dir.to_path_buf();
+    for component in dir.components() {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.file_type().is_symlink() => anyhow::bail!(
+                "Refusing to clean output directory through a symlink: {}",
+                current.display()
+            ),
+            Ok(_) => {}
+            Err(_) => return Ok(()),
+        }
+    }
+    let Ok(children) = fs::read_dir(base_dir.join(dir)) else {
+        return Ok(());
+    };
+    for child in children {
+        let child = child?;
+        if archived.contains(dir.join(child.file_name()).as_path()) {
+            continue;
         }
+        // Symlinks to directories are removed, not followed
+        let path = child.path();
+        let removed = match child.file_type()?.is_dir() {
+            true => fs::remove_dir_all(&path),
+            false => fs::remove_file(&path),
+        };
+        removed.with_context(|| format!("Failed to remove stale output: {}", path.display()))?;
     }
+    Ok(())
+}
 
-    Ok((total_size, file_count))
+/// Reader that hashes what is read through it
+struct HashingReader<'a, R> {
+    inner: R,
+    hasher: &'a mut Sha256,
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
 }
 
 #[cfg(test)]
@@ -176,7 +362,9 @@ mod tests {
         let archive_path = base.join("outputs.tar.zst");
 
         // Archive
-        let archived = archive_outputs(&outputs, base, &archive_path).unwrap();
+        let archived = archive_outputs(&outputs, &[], base, &archive_path)
+            .unwrap()
+            .0;
         assert_eq!(archived.len(), 1);
         assert_eq!(archived[0].path, "output.txt");
         assert!(!archived[0].is_directory);
@@ -185,7 +373,12 @@ mod tests {
         fs::remove_file(base.join("output.txt")).unwrap();
 
         // Extract
-        extract_outputs(&archive_path, base).unwrap();
+        extract_outputs(
+            &fs::read(&archive_path).unwrap(),
+            base,
+            RestoreOptions::default(),
+        )
+        .unwrap();
 
         // Verify
         assert!(base.join("output.txt").exists());
@@ -211,7 +404,9 @@ mod tests {
         let archive_path = base.join("outputs.tar.zst");
 
         // Archive
-        let archived = archive_outputs(&outputs, base, &archive_path).unwrap();
+        let archived = archive_outputs(&outputs, &[], base, &archive_path)
+            .unwrap()
+            .0;
         assert_eq!(archived.len(), 1);
         assert_eq!(archived[0].path, "dist/");
         assert!(archived[0].is_directory);
@@ -221,7 +416,12 @@ mod tests {
         fs::remove_dir_all(base.join("dist")).unwrap();
 
         // Extract
-        extract_outputs(&archive_path, base).unwrap();
+        extract_outputs(
+            &fs::read(&archive_path).unwrap(),
+            base,
+            RestoreOptions::default(),
+        )
+        .unwrap();
 
         // Verify
         assert!(base.join("dist").exists());
@@ -229,6 +429,154 @@ mod tests {
         assert!(base.join("dist/file2.txt").exists());
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_archive_preserves_modes_and_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let temp = TempDir::new().unwrap();
+        let base = temp.path();
+
+        fs::create_dir_all(base.join("bin/lib")).unwrap();
+        fs::write(base.join("bin/lib/tool-1.2"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(
+            base.join("bin/lib/tool-1.2"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        symlink("lib/tool-1.2", base.join("bin/tool")).unwrap();
+        symlink("missing", base.join("bin/dangling")).unwrap();
+
+        let outputs = vec![OutputSpec {
+            path: "bin".to_string(),
+            required: true,
+        }];
+        let archive_path = base.join("outputs.tar.zst");
+        let archived = archive_outputs(&outputs, &[], base, &archive_path)
+            .unwrap()
+            .0;
+        assert_eq!(archived[0].file_count, 1);
+
+        // Restoring over stale outputs replaces them
+        fs::remove_dir_all(base.join("bin")).unwrap();
+        fs::create_dir(base.join("bin")).unwrap();
+        fs::write(base.join("bin/tool"), "stale").unwrap();
+        let paths = extract_archive(
+            fs::File::open(&archive_path).unwrap(),
+            base,
+            RestoreOptions::default(),
+        )
+        .unwrap();
+        assert!(paths.contains(&PathBuf::from("bin/tool")));
+
+        let mode = fs::metadata(base.join("bin/lib/tool-1.2"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(
+            fs::read_link(base.join("bin/tool")).unwrap(),
+            PathBuf::from("lib/tool-1.2")
+        );
+        assert_eq!(
+            fs::read_link(base.join("bin/dangling")).unwrap(),
+            PathBuf::from("missing")
+        );
+    }
+
+    #[test]
+    fn test_clean_restore_without_mtimes() {
+        let temp = TempDir::new().unwrap();
+        let base = temp.path();
+
+        fs::create_dir_all(base.join("dist/assets")).unwrap();
+        fs::write(base.join("dist/app.js"), "app").unwrap();
+        fs::write(base.join("dist/assets/logo.svg"), "logo").unwrap();
+        // Whole seconds, as tar stores them
+        let old = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        fs::File::options()
+            .write(true)
+            .open(base.join("dist/app.js"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let outputs = vec![OutputSpec {
+            path: "dist".to_string(),
+            required: true,
+        }];
+        let archive_path = base.join("outputs.tar.zst");
+        archive_outputs(&outputs, &[], base, &archive_path).unwrap();
+
+        // Files of a later build that the cached run didn't produce
+        fs::write(base.join("dist/stale.js"), "stale").unwrap();
+        fs::create_dir(base.join("dist/old")).unwrap();
+        fs::write(base.join("dist/old/chunk.js"), "chunk").unwrap();
+        fs::write(base.join("dist/assets/old.svg"), "old").unwrap();
+
+        // Default restores keep them
+        let compressed = fs::read(&archive_path).unwrap();
+        extract_outputs(&compressed, base, RestoreOptions::default()).unwrap();
+        assert!(base.join("dist/stale.js").exists());
+        let mtime = |path: &str| fs::metadata(base.join(path)).unwrap().modified().unwrap();
+        assert_eq!(mtime("dist/app.js"), old);
+
+        let options = RestoreOptions {
+            mtimes: false,
+            clean: true,
+        };
+        extract_outputs(&compressed, base, options).unwrap();
+        assert!(!base.join("dist/stale.js").exists());
+        assert!(!base.join("dist/old").exists());
+        assert!(!base.join("dist/assets/old.svg").exists());
+        assert!(base.join("dist/assets/logo.svg").exists());
+        assert_eq!(fs::read_to_string(base.join("dist/app.js")).unwrap(), "app");
+        assert!(mtime("dist/app.js") > old);
+    }
+
+    #[test]
+    fn test_clean_restore_stays_in_output_directory() {
+        let temp = TempDir::new().unwrap();
+        let base = temp.path().join("base");
+        let victim = temp.path().join("victim");
+        fs::create_dir_all(&base).unwrap();
+        fs::create_dir_all(&victim).unwrap();
+        fs::write(victim.join("keep.txt"), "keep").unwrap();
+
+        // Built by hand, as tar's own builder refuses these paths
+        let hostile = |entries: &[(&[u8], EntryType, Option<&Path>)]| {
+            let mut builder = Builder::new(Encoder::new(Vec::new(), 0).unwrap());
+            for (name, entry_type, link) in entries {
+                let mut header = Header::new_gnu();
+                header.as_old_mut().name[..name.len()].copy_from_slice(name);
+                header.set_entry_type(*entry_type);
+                header.set_mode(0o755);
+                header.set_size(0);
+                if let Some(link) = link {
+                    header.set_link_name(link).unwrap();
+                }
+                header.set_cksum();
+                builder.append(&header, io::empty()).unwrap();
+            }
+            builder.into_inner().unwrap().finish().unwrap()
+        };
+        let clean = RestoreOptions {
+            mtimes: true,
+            clean: true,
+        };
+
+        let escaping = hostile(&[(&b"../victim"[..], EntryType::Directory, None)]);
+        assert!(extract_outputs(&escaping, &base, clean).is_err());
+        assert!(victim.join("keep.txt").exists());
+
+        let through_symlink = hostile(&[
+            (&b"out"[..], EntryType::Symlink, Some(victim.as_path())),
+            (&b"out"[..], EntryType::Directory, None),
+        ]);
+        assert!(extract_outputs(&through_symlink, &base, clean).is_err());
+        assert!(victim.join("keep.txt").exists());
+    }
+
     #[test]
     fn test_optional_output_missing() {
         let temp = TempDir::new().unwrap();
@@ -242,7 +590,9 @@ mod tests {
         let archive_path = base.join("outputs.tar.zst");
 
         // Should succeed with no outputs
-        let archived = archive_outputs(&outputs, base, &archive_path).unwrap();
+        let archived = archive_outputs(&outputs, &[], base, &archive_path)
+            .unwrap()
+            .0;
         assert_eq!(archived.len(), 0);
     }
 
@@ -259,7 +609,7 @@ mod tests {
         let archive_path = base.join("outputs.tar.zst");
 
         // Should fail
-        let result = archive_outputs(&outputs, base, &archive_path);
+        let result = archive_outputs(&outputs, &[], base, &archive_path);
         assert!(result.is_err());
         assert!(result
             .unwrap_err()
@@ -0,0 +1,484 @@
+/// Shared script cache on upstream Fabrik servers
+///
+/// When a script misses the local cache, `fabrik run` asks the upstreams configured for
+/// `scripts/<cache key>` (see `upstream_routing`) for the entry, and after a successful
+/// run it uploads the entry, so CI and teammates reuse each other's script outputs.
+/// Entries go through the artifact API of the HTTP server
+/// (`/api/v1/artifacts/{hash}`) in two parts:
+///
+/// - the entry metadata (JSON), under the SHA256 of `scripts/<cache key>`
+/// - the output archive, under its content hash (so shared archives upload once)
+///
+/// Before uploading an archive, the upstream is asked whether it already has it
+/// (`POST /api/v1/artifacts/find-missing`), so archives another machine uploaded don't
+/// cross the network again. Upstreams without that endpoint get every upload. On
+/// upstreams with `chunking`, large archives are uploaded as content-defined chunks
+/// (see `chunking`), so an archive that changed slightly only sends the changed chunks.
+///
+/// Only `http://` and `https://` upstreams are used, and `read_only` upstreams are never
+/// written to. Transfers stay under the upstream's `max_upload_rate` and
+/// `max_download_rate`. Failed requests are retried per `[retry]`; upstream errors never
+/// fail a run: a failed lookup is a miss and a failed upload is logged.
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tracing::{debug, warn};
+
+use super::cache::CacheMetadata;
+use crate::auth::provider::AuthProvider;
+use crate::bandwidth::{self, Throttle};
+use crate::chunking::{self, ChunkManifest};
+use crate::config::{FabrikConfig, UpstreamConfig};
+use crate::eviction::EvictionConfig;
+use crate::retry::{Protocol, RetryPolicy};
+use crate::upstream_routing::{namespaces, UpstreamRouter};
+
+/// Path of the artifact API, relative to the upstream URL
+const ARTIFACTS_PATH: &str = "/api/v1/artifacts";
+
+/// Upload negotiation endpoint, relative to the upstream URL
+const FIND_MISSING_PATH: &str = "/api/v1/artifacts/find-missing";
+
+#[derive(Serialize)]
+struct FindMissingRequest<'a> {
+    hashes: &'a [&'a str],
+}
+
+#[derive(Deserialize)]
+struct FindMissingResponse {
+    missing: Vec<String>,
+}
+
+/// Entry fetched from an upstream
+#[derive(Debug, Clone)]
+pub struct RemoteEntry {
+    pub metadata: CacheMetadata,
+    pub archive: Vec<u8>,
+    /// URL of the upstream that had it
+    pub upstream: String,
+}
+
+/// Bandwidth limits of an upstream, shared by its transfers
+#[derive(Default)]
+struct Throttles {
+    upload: Option<Arc<Throttle>>,
+    download: Option<Arc<Throttle>>,
+}
+
+/// Client for the script entries on upstreams
+///
+/// Methods block on the runtime the cache was created in, so they must be called
+/// outside of async code (e.g. inside `tokio::task::block_in_place`).
+#[derive(Clone)]
+pub struct RemoteCache {
+    client: reqwest::Client,
+    upstreams: Vec<UpstreamConfig>,
+    token: Option<String>,
+    retry: RetryPolicy,
+    /// By upstream URL
+    throttles: Arc<HashMap<String, Throttles>>,
+    runtime: Handle,
+}
+
+impl RemoteCache {
+    /// Remote cache over the HTTP upstreams of `config`, or None if it has none
+    pub async fn from_config(config: &FabrikConfig) -> Result<Option<Self>> {
+        let upstreams: Vec<UpstreamConfig> = config
+            .upstream
+            .iter()
+            .filter(|upstream| is_http(&upstream.url))
+            .cloned()
+            .collect();
+        if upstreams.is_empty() {
+            return Ok(None);
+        }
+        UpstreamRouter::new(&upstreams)?;
+        let retry = RetryPolicy::from_config(&config.retry)?;
+        let throttles = upstreams
+            .iter()
+            .map(|upstream| {
+                let throttles = Throttles {
+                    upload: Throttle::from_config(upstream.max_upload_rate.as_deref())?,
+                    download: Throttle::from_config(upstream.max_download_rate.as_deref())?,
+                };
+                Ok((upstream.url.clone(), throttles))
+            })
+            .collect::<Result<_>>()?;
+
+        let token = match config.auth.provider {
+            Some(_) => {
+                let provider = AuthProvider::new(config.auth.clone(), config.url.clone())
+                    .context("Failed to initialize authentication provider")?;
+                match provider.get_token().await {
+                    Ok(token) => Some(token),
+                    Err(e) => {
+                        warn!("Using upstreams without authentication: {}", e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let client = reqwest::Client::builder()
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        Ok(Some(Self {
+            client,
+            upstreams,
+            token,
+            retry,
+            throttles: Arc::new(throttles),
+            runtime: Handle::current(),
+        }))
+    }
+
+    /// Look up an entry on the upstreams, in routing order
+    pub fn fetch(&self, cache_key: &str) -> Option<RemoteEntry> {
+        for upstream in self.route(cache_key) {
+            match self.runtime.block_on(self.fetch_from(upstream, cache_key)) {
+                Ok(Some(entry)) => return Some(entry),
+                Ok(None) => debug!("Script entry {} not on {}", cache_key, upstream.url),
+                Err(e) => warn!(
+                    "Failed to fetch {} from {}: {:#}",
+                    cache_key, upstream.url, e
+                ),
+            }
+        }
+        None
+    }
+
+    /// Upload an entry to the writable upstreams
+    pub fn upload(&self, cache_key: &str, metadata: &CacheMetadata, archive: &[u8]) {
+        for upstream in self.route(cache_key) {
+            if upstream.read_only {
+                continue;
+            }
+            match self
+                .runtime
+                .block_on(self.upload_to(upstream, cache_key, metadata, archive))
+            {
+                Ok(()) => debug!("Uploaded script entry {} to {}", cache_key, upstream.url),
+                Err(e) => warn!(
+                    "Failed to upload {} to {}: {:#}",
+                    cache_key, upstream.url, e
+                ),
+            }
+        }
+    }
+
+    /// Fetch an artifact by ID from the first upstream that has it (e.g. to warm a
+    /// cache), with the URL of that upstream
+    pub async fn fetch_artifact(&self, id: &str) -> Result<Option<(Vec<u8>, String)>> {
+        let mut last_error = None;
+        for upstream in &self.upstreams {
+            match self.get(upstream, id).await {
+                Ok(Some(data)) => return Ok(Some((data, upstream.url.clone()))),
+                Ok(None) => {}
+                Err(e) => {
+                    last_error = Some(e.context(format!("Failed to fetch from {}", upstream.url)))
+                }
+            }
+        }
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    fn route(&self, cache_key: &str) -> Vec<&UpstreamConfig> {
+        // Patterns were validated when the cache was created
+        UpstreamRouter::new(&self.upstreams)
+            .map(|router| router.route(namespaces::SCRIPTS, cache_key))
+            .unwrap_or_default()
+    }
+
+    async fn fetch_from(
+        &self,
+        upstream: &UpstreamConfig,
+        cache_key: &str,
+    ) -> Result<Option<RemoteEntry>> {
+        let Some(json) = self.get(upstream, &entry_id(cache_key)).await? else {
+            return Ok(None);
+        };
+        let metadata: CacheMetadata =
+            serde_json::from_slice(&json).context("Invalid entry metadata")?;
+        let digest = metadata
+            .archive
+            .as_ref()
+            .map(|archive| archive.digest.clone())
+            .context("Entry has no archive")?;
+
+        let Some(archive) = self.get(upstream, &digest).await? else {
+            return Ok(None);
+        };
+        if hex::encode(Sha256::digest(&archive)) != digest {
+            anyhow::bail!("Archive {} doesn't match its digest", digest);
+        }
+
+        Ok(Some(RemoteEntry {
+            metadata,
+            archive,
+            upstream: upstream.url.clone(),
+        }))
+    }
+
+    async fn upload_to(
+        &self,
+        upstream: &UpstreamConfig,
+        cache_key: &str,
+        metadata: &CacheMetadata,
+        archive: &[u8],
+    ) -> Result<()> {
+        let digest = hex::encode(Sha256::digest(archive));
+        // The archive goes first, so the metadata never points to a missing archive
+        if self.find_missing(upstream, &[&digest]).await.is_empty() {
+            debug!(
+                "Archive {} already on {}, skipping {} byte upload",
+                digest,
+                upstream.url,
+                archive.len()
+            );
+        } else {
+            self.put_artifact(upstream, &digest, archive).await?;
+        }
+        let json = serde_json::to_vec(metadata).context("Failed to serialize metadata")?;
+        self.put(upstream, &entry_id(cache_key), json).await
+    }
+
+    /// Send the request `build` makes with the token, retrying failures
+    async fn send(
+        &self,
+        build: impl Fn() -> reqwest::RequestBuilder,
+    ) -> reqwest::Result<reqwest::Response> {
+        self.retry
+            .http(Protocol::Http, || {
+                let request = build();
+                match &self.token {
+                    Some(token) => request.bearer_auth(token),
+                    None => request,
+                }
+                .send()
+            })
+            .await
+    }
+
+    async fn get(&self, upstream: &UpstreamConfig, id: &str) -> Result<Option<Vec<u8>>> {
+        let url = artifact_url(&upstream.url, id);
+        let mut response = self
+            .send(|| self.client.get(&url).timeout(timeout(upstream)))
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            anyhow::bail!("HTTP {}", response.status().as_u16());
+        }
+
+        let throttle = self.throttles(upstream).download.as_deref();
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if let Some(throttle) = throttle {
+                throttle.consume(chunk.len()).await;
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(Some(data))
+    }
+
+    fn throttles(&self, upstream: &UpstreamConfig) -> &Throttles {
+        static UNLIMITED: Throttles = Throttles {
+            upload: None,
+            download: None,
+        };
+        self.throttles.get(&upstream.url).unwrap_or(&UNLIMITED)
+    }
+
+    /// IDs the upstream doesn't have (all of them if it can't tell)
+    ///
+    /// Negotiation only saves uploads, so when it fails everything is uploaded.
+    async fn find_missing(&self, upstream: &UpstreamConfig, ids: &[&str]) -> Vec<String> {
+        match self.query_missing(upstream, ids).await {
+            Ok(missing) => missing,
+            Err(e) => {
+                debug!(
+                    "Failed to ask {} which artifacts it lacks, uploading all: {:#}",
+                    upstream.url, e
+                );
+                ids.iter().map(|id| id.to_string()).collect()
+            }
+        }
+    }
+
+    /// Ask the upstream which of `ids` it lacks
+    async fn query_missing(&self, upstream: &UpstreamConfig, ids: &[&str]) -> Result<Vec<String>> {
+        let url = format!(
+            "{}{}",
+            upstream.url.trim_end_matches('/'),
+            FIND_MISSING_PATH
+        );
+        let response = self
+            .send(|| {
+                self.client
+                    .post(&url)
+                    .timeout(timeout(upstream))
+                    .json(&FindMissingRequest { hashes: ids })
+            })
+            .await?;
+        // Servers predating upload negotiation
+        if response.status() == reqwest::StatusCode::NOT_FOUND
+            || response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED
+        {
+            return Ok(ids.iter().map(|id| id.to_string()).collect());
+        }
+        if !response.status().is_success() {
+            anyhow::bail!("HTTP {}", response.status().as_u16());
+        }
+        let response: FindMissingResponse = response.json().await?;
+        Ok(response.missing)
+    }
+
+    /// Upload the artifact `digest`, as chunks when the upstream has `chunking` and the
+    /// artifact is large enough
+    async fn put_artifact(
+        &self,
+        upstream: &UpstreamConfig,
+        digest: &str,
+        data: &[u8],
+    ) -> Result<()> {
+        if upstream.chunking
+            && data.len() >= chunking::MIN_CHUNKED_SIZE
+            && self.put_chunked(upstream, digest, data).await?
+        {
+            return Ok(());
+        }
+        self.put(upstream, digest, data.to_vec()).await
+    }
+
+    /// Upload the chunks of `data` the upstream lacks, then its manifest; false if the
+    /// upstream doesn't support chunked artifacts
+    async fn put_chunked(
+        &self,
+        upstream: &UpstreamConfig,
+        digest: &str,
+        data: &[u8],
+    ) -> Result<bool> {
+        let chunks = chunking::chunks(data);
+        let manifest = ChunkManifest::new(&chunks);
+        let hashes = manifest.hashes();
+        let ids: Vec<&str> = hashes.iter().map(String::as_str).collect();
+        let mut missing: HashSet<String> = self
+            .find_missing(upstream, &ids)
+            .await
+            .into_iter()
+            .collect();
+
+        let mut uploaded = 0;
+        for (chunk, hash) in chunks.iter().zip(&hashes) {
+            // Repeated chunks upload once
+            if missing.remove(hash) {
+                self.put(upstream, hash, chunk.to_vec()).await?;
+                uploaded += chunk.len();
+            }
+        }
+
+        let url = format!("{}/chunks", artifact_url(&upstream.url, digest));
+        let response = self
+            .send(|| {
+                self.client
+                    .put(&url)
+                    .timeout(timeout(upstream))
+                    .json(&manifest)
+            })
+            .await?;
+        // Servers predating chunked artifacts
+        if response.status() == reqwest::StatusCode::NOT_FOUND
+            || response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED
+        {
+            debug!("{} doesn't support chunked artifacts", upstream.url);
+            return Ok(false);
+        }
+        if !response.status().is_success() {
+            anyhow::bail!("HTTP {}", response.status().as_u16());
+        }
+        debug!(
+            "Uploaded {} of {} bytes of {} to {} ({} chunks)",
+            uploaded,
+            data.len(),
+            digest,
+            upstream.url,
+            chunks.len()
+        );
+        Ok(true)
+    }
+
+    async fn put(&self, upstream: &UpstreamConfig, id: &str, data: Vec<u8>) -> Result<()> {
+        let url = artifact_url(&upstream.url, id);
+        let data = Bytes::from(data);
+        let throttle = self.throttles(upstream).upload.as_ref();
+        let response = self
+            .send(|| {
+                let body = match throttle {
+                    Some(throttle) => reqwest::Body::wrap_stream(bandwidth::throttled_stream(
+                        data.clone(),
+                        throttle.clone(),
+                    )),
+                    None => data.clone().into(),
+                };
+                self.client.put(&url).timeout(timeout(upstream)).body(body)
+            })
+            .await?;
+        if !response.status().is_success() {
+            anyhow::bail!("HTTP {}", response.status().as_u16());
+        }
+        Ok(())
+    }
+}
+
+/// Artifact ID of the metadata of the entry `cache_key`
+pub fn entry_id(cache_key: &str) -> String {
+    let path = format!("{}/{}", namespaces::SCRIPTS, cache_key);
+    hex::encode(Sha256::digest(path.as_bytes()))
+}
+
+fn artifact_url(upstream_url: &str, id: &str) -> String {
+    format!(
+        "{}{}/{}",
+        upstream_url.trim_end_matches('/'),
+        ARTIFACTS_PATH,
+        id
+    )
+}
+
+fn is_http(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+fn timeout(upstream: &UpstreamConfig) -> Duration {
+    Duration::from_secs(EvictionConfig::parse_ttl(&upstream.timeout).unwrap_or(30))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_urls() {
+        let id = entry_id("script-abc");
+        assert_eq!(id.len(), 64);
+        assert_ne!(id, entry_id("script-abd"));
+
+        assert_eq!(
+            artifact_url("https://cache.example.com/", &id),
+            format!("https://cache.example.com/api/v1/artifacts/{}", id)
+        );
+        assert!(is_http("http://localhost:7070"));
+        assert!(!is_http("grpc://localhost:7070"));
+    }
+}
@@ -0,0 +1,186 @@
+/// Watch mode for `fabrik run --watch`
+///
+/// Watches the files a script's cache key is computed from — the script, its declared
+/// input globs, and the same for every script it depends on — and reports when they
+/// change. Files are polled (modification time and size), and globs are expanded again
+/// on every poll, so files created after the watch started are picked up. A change is
+/// only reported once the files have been quiet for the debounce interval, so editors
+/// and formatters saving several files trigger one run.
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use super::dependencies::DependencyResolver;
+use super::inputs::expand_input;
+
+/// How often watched files are checked
+pub const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Modification time and size of each watched file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    files: BTreeMap<PathBuf, (Option<SystemTime>, u64)>,
+}
+
+impl Snapshot {
+    /// Snapshot the script, its inputs and those of its dependencies
+    ///
+    /// If the annotations can't be resolved (e.g. while the script is being edited),
+    /// only the script itself is watched.
+    pub fn take(script_path: &Path) -> Self {
+        let paths = watched_files(script_path).unwrap_or_else(|_| vec![script_path.to_path_buf()]);
+
+        let files = paths
+            .into_iter()
+            .filter_map(|path| {
+                let metadata = fs::metadata(&path).ok()?;
+                Some((path, (metadata.modified().ok(), metadata.len())))
+            })
+            .collect();
+        Self { files }
+    }
+
+    /// Number of files watched
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Files added, removed or modified since `previous`, sorted
+    pub fn changes(&self, previous: &Snapshot) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|(path, state)| previous.files.get(*path) != Some(state))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.extend(
+            previous
+                .files
+                .keys()
+                .filter(|path| !self.files.contains_key(*path))
+                .cloned(),
+        );
+        changed.sort();
+        changed
+    }
+}
+
+/// Block until a watched file changes and stays unchanged for `debounce`
+///
+/// Returns the files that changed since `baseline`, and the snapshot they settled on.
+pub fn wait_for_change(
+    script_path: &Path,
+    baseline: &Snapshot,
+    debounce: Duration,
+) -> (Vec<PathBuf>, Snapshot) {
+    let mut current = baseline.clone();
+    let mut last_change: Option<Instant> = None;
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let next = Snapshot::take(script_path);
+        if next != current {
+            current = next;
+            last_change = Some(Instant::now());
+            continue;
+        }
+
+        if last_change.is_some_and(|at| at.elapsed() >= debounce) {
+            let changes = current.changes(baseline);
+            if !changes.is_empty() {
+                return (changes, current);
+            }
+            // Changed and changed back (e.g. an editor's save-via-rename)
+            last_change = None;
+        }
+    }
+}
+
+/// Scripts and input files that make up the cache key of `script_path`
+fn watched_files(script_path: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for dep in DependencyResolver::new().resolve(script_path)? {
+        let base_dir = dep
+            .script_path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .to_path_buf();
+        for input in &dep.annotations.inputs {
+            files.extend(expand_input(input, &base_dir)?);
+        }
+        files.push(dep.script_path);
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_snapshot_tracks_script_and_inputs() {
+        let temp_dir = TempDir::new().unwrap();
+        let script = temp_dir.path().join("gen.sh");
+        fs::write(
+            &script,
+            "#!/usr/bin/env -S fabrik run bash\n#FABRIK input \"src/*.txt\"\necho hi\n",
+        )
+        .unwrap();
+        fs::create_dir(temp_dir.path().join("src")).unwrap();
+        fs::write(temp_dir.path().join("src/a.txt"), "a").unwrap();
+        fs::write(temp_dir.path().join("unrelated.txt"), "x").unwrap();
+
+        let before = Snapshot::take(&script);
+        assert_eq!(before.file_count(), 2);
+
+        // New and modified inputs are changes, other files aren't
+        fs::write(temp_dir.path().join("src/a.txt"), "a2").unwrap();
+        fs::write(temp_dir.path().join("src/b.txt"), "b").unwrap();
+        fs::write(temp_dir.path().join("unrelated.txt"), "y").unwrap();
+        let after = Snapshot::take(&script);
+        let names: Vec<_> = after
+            .changes(&before)
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec!["a.txt", "b.txt"]);
+
+        // Removed inputs too
+        fs::remove_file(temp_dir.path().join("src/a.txt")).unwrap();
+        let removed = Snapshot::take(&script);
+        assert_eq!(removed.changes(&after).len(), 1);
+        assert!(Snapshot::take(&script).changes(&removed).is_empty());
+    }
+
+    #[test]
+    fn test_wait_for_change_debounces() {
+        let temp_dir = TempDir::new().unwrap();
+        let script = temp_dir.path().join("gen.sh");
+        fs::write(
+            &script,
+            "#!/usr/bin/env -S fabrik run bash\n#FABRIK input \"*.txt\"\n",
+        )
+        .unwrap();
+        let baseline = Snapshot::take(&script);
+
+        let dir = temp_dir.path().to_path_buf();
+        let writer = thread::spawn(move || {
+            for i in 0..3 {
+                thread::sleep(Duration::from_millis(100));
+                fs::write(dir.join(format!("{}.txt", i)), "x").unwrap();
+            }
+        });
+
+        let (changes, settled) = wait_for_change(&script, &baseline, Duration::from_millis(400));
+        writer.join().unwrap();
+
+        // All three writes are reported as one change
+        assert_eq!(changes.len(), 3);
+        assert_eq!(settled, Snapshot::take(&script));
+    }
+}
@@ -0,0 +1,152 @@
+// Artifact storage behind Fabrik.cache
+//
+// Recipes store and retrieve artifacts by hash in the same content-addressed storage
+// the daemon serves to build systems. The storage is only opened on first use: most
+// recipes never touch Fabrik.cache, and they shouldn't fail because a running daemon
+// holds the cache directory.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+use crate::eviction::EvictionConfig;
+use crate::storage::{FilesystemStorage, Storage};
+
+/// Content-addressed storage used by Fabrik.cache.get/put/has
+pub struct ArtifactStore {
+    cache_dir: PathBuf,
+    storage: OnceCell<Arc<dyn Storage>>,
+}
+
+impl ArtifactStore {
+    /// Storage in `cache_dir`, opened when first needed
+    pub fn new(cache_dir: PathBuf) -> Self {
+        Self {
+            cache_dir,
+            storage: OnceCell::new(),
+        }
+    }
+
+    /// Use an already open storage
+    #[allow(dead_code)]
+    pub fn from_storage(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            cache_dir: PathBuf::new(),
+            storage: OnceCell::new_with(Some(storage)),
+        }
+    }
+
+    /// Retrieve an artifact
+    pub async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let id = parse_hash(hash)?;
+        let storage = self.storage().await?;
+        tokio::task::spawn_blocking(move || storage.get(&id))
+            .await?
+            .with_context(|| format!("Failed to read artifact {}", hash))
+    }
+
+    /// Store an artifact, replacing any previous content
+    pub async fn put(&self, hash: &str, data: Vec<u8>) -> Result<()> {
+        let id = parse_hash(hash)?;
+        let storage = self.storage().await?;
+        tokio::task::spawn_blocking(move || storage.put(&id, &data))
+            .await?
+            .with_context(|| format!("Failed to store artifact {}", hash))
+    }
+
+    /// Store the content of a file as an artifact
+    pub async fn put_file(&self, hash: &str, path: &Path) -> Result<()> {
+        let id = parse_hash(hash)?;
+        let storage = self.storage().await?;
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let data = std::fs::read(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            Ok::<(), anyhow::Error>(storage.put(&id, &data)?)
+        })
+        .await?
+        .with_context(|| format!("Failed to store artifact {}", hash))
+    }
+
+    /// Whether an artifact is stored
+    pub async fn has(&self, hash: &str) -> Result<bool> {
+        let id = parse_hash(hash)?;
+        let storage = self.storage().await?;
+        tokio::task::spawn_blocking(move || storage.exists(&id))
+            .await?
+            .with_context(|| format!("Failed to look up artifact {}", hash))
+    }
+
+    async fn storage(&self) -> Result<Arc<dyn Storage>> {
+        self.storage
+            .get_or_try_init(|| async {
+                let cache_dir = self.cache_dir.clone();
+                tracing::debug!("Opening recipe artifact storage at {}", cache_dir.display());
+                let storage = tokio::task::spawn_blocking(move || {
+                    FilesystemStorage::with_eviction(&cache_dir, Some(EvictionConfig::default()))
+                })
+                .await?
+                .with_context(|| {
+                    format!(
+                        "Failed to open cache storage at {} (is a daemon using it?)",
+                        self.cache_dir.display()
+                    )
+                })?;
+                Ok::<Arc<dyn Storage>, anyhow::Error>(Arc::new(storage))
+            })
+            .await
+            .cloned()
+    }
+}
+
+/// Artifact ID of a hex-encoded hash
+fn parse_hash(hash: &str) -> Result<Vec<u8>> {
+    match hex::decode(hash) {
+        Ok(id) if !id.is_empty() => Ok(id),
+        _ => anyhow::bail!("Invalid artifact hash '{}': expected a hex string", hash),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_put_get_has() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = ArtifactStore::new(temp_dir.path().to_path_buf());
+        let hash = "ab".repeat(32);
+
+        assert!(!store.has(&hash).await.unwrap());
+        assert_eq!(store.get(&hash).await.unwrap(), None);
+
+        store.put(&hash, b"artifact".to_vec()).await.unwrap();
+        assert!(store.has(&hash).await.unwrap());
+        assert_eq!(store.get(&hash).await.unwrap(), Some(b"artifact".to_vec()));
+
+        // Artifacts are shared with everything else using the cache directory
+        drop(store);
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+        assert!(storage.exists(&hex::decode(&hash).unwrap()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_hash() {
+        let store = ArtifactStore::new(PathBuf::from("/nonexistent"));
+
+        let err = store.has("not-hex").await.unwrap_err();
+        assert!(err.to_string().contains("Invalid artifact hash"));
+        assert!(store.put("", Vec::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_from_storage() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path()).unwrap());
+        let store = ArtifactStore::from_storage(storage.clone());
+
+        store.put("00ff", b"data".to_vec()).await.unwrap();
+        assert_eq!(storage.get(&[0x00, 0xff]).unwrap(), Some(b"data".to_vec()));
+    }
+}
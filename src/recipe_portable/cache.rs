@@ -6,8 +6,12 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
-use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+
+use crate::recipe::docker::{image_id, ImageOutputSpec};
+use crate::recipe::ignore_files::IgnoreFilter;
+use crate::recipe::outputs::{archive_paths_with_images, extract_archive, RestoreOptions};
 
 /// Configuration options for cache operations
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +28,14 @@ pub struct CacheOptions {
     #[serde(default)]
     pub env: Vec<String>,
 
+    /// Docker images whose IDs affect cache key
+    #[serde(default)]
+    pub input_images: Vec<String>,
+
+    /// Docker images to cache/restore (with docker save/load)
+    #[serde(default)]
+    pub output_images: Vec<String>,
+
     /// Cache directory override (optional)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cache_dir: Option<String>,
@@ -39,22 +51,58 @@ pub struct CacheOptions {
     /// Hash method: "content", "mtime", or "size"
     #[serde(default = "default_hash_method")]
     pub hash_method: String,
+
+    /// Also hash input files ignored by .gitignore/.fabrikignore
+    #[serde(default)]
+    pub include_ignored: bool,
+
+    /// Restore the modification times of outputs (otherwise they get the restore time)
+    #[serde(default = "default_restore_mtimes")]
+    pub restore_mtimes: bool,
+
+    /// Delete files in output directories that aren't in the cached outputs
+    #[serde(default)]
+    pub clean_restore: bool,
+
+    /// Recipe parameters that affect cache key (see defineRecipe)
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
 }
 
 fn default_hash_method() -> String {
     "content".to_string()
 }
 
+fn default_restore_mtimes() -> bool {
+    true
+}
+
 impl Default for CacheOptions {
     fn default() -> Self {
         Self {
             inputs: Vec::new(),
             outputs: Vec::new(),
             env: Vec::new(),
+            input_images: Vec::new(),
+            output_images: Vec::new(),
             cache_dir: None,
             upstream: None,
             ttl: None,
             hash_method: default_hash_method(),
+            include_ignored: false,
+            restore_mtimes: default_restore_mtimes(),
+            clean_restore: false,
+            params: BTreeMap::new(),
+        }
+    }
+}
+
+impl CacheOptions {
+    /// How `restore_outputs` restores the outputs
+    pub fn restore_options(&self) -> RestoreOptions {
+        RestoreOptions {
+            mtimes: self.restore_mtimes,
+            clean: self.clean_restore,
         }
     }
 }
@@ -80,16 +128,34 @@ pub struct CacheResult {
 
 /// Compute cache key from options
 ///
-/// Cache key = SHA256(inputs_hash + env_values + hash_method)
+/// Cache key = SHA256(inputs_hash + image_ids + env_values + params + hash_method)
 pub async fn compute_cache_key(options: &CacheOptions, working_dir: &Path) -> Result<String> {
     let mut hasher = Sha256::new();
 
     // Hash input files
     for pattern in &options.inputs {
-        let input_hash = hash_input_pattern(pattern, &options.hash_method, working_dir).await?;
+        let input_hash = hash_input_pattern(
+            pattern,
+            &options.hash_method,
+            options.include_ignored,
+            working_dir,
+        )
+        .await?;
         hasher.update(input_hash.as_bytes());
     }
 
+    // Hash input Docker images by ID
+    for image in &options.input_images {
+        let name = image.clone();
+        let id = tokio::task::spawn_blocking(move || image_id(&name))
+            .await
+            .context("Image inspection task failed")??;
+        hasher.update(b"image:");
+        hasher.update(image.as_bytes());
+        hasher.update(id.as_deref().unwrap_or("<missing>").as_bytes());
+        hasher.update(b"\0");
+    }
+
     // Hash environment variables
     for env_var in &options.env {
         if let Ok(value) = std::env::var(env_var) {
@@ -99,6 +165,15 @@ pub async fn compute_cache_key(options: &CacheOptions, working_dir: &Path) -> Re
         }
     }
 
+    // Hash recipe parameters (sorted by name)
+    for (name, value) in &options.params {
+        hasher.update(b"param:");
+        hasher.update(name.as_bytes());
+        hasher.update(b"=");
+        hasher.update(value.as_bytes());
+        hasher.update(b"\0");
+    }
+
     // Include hash method in cache key
     hasher.update(options.hash_method.as_bytes());
 
@@ -110,6 +185,7 @@ pub async fn compute_cache_key(options: &CacheOptions, working_dir: &Path) -> Re
 async fn hash_input_pattern(
     pattern: &str,
     hash_method: &str,
+    include_ignored: bool,
     working_dir: &Path,
 ) -> Result<String> {
     let mut hasher = Sha256::new();
@@ -121,11 +197,14 @@ async fn hash_input_pattern(
         working_dir.join(pattern).to_string_lossy().to_string()
     };
 
-    // Find all matching files
-    let paths = glob::glob(&pattern_path)
+    // Find all matching files, except ignored ones
+    let mut paths = glob::glob(&pattern_path)
         .context("Failed to parse glob pattern")?
         .filter_map(|entry| entry.ok())
         .collect::<Vec<_>>();
+    if !include_ignored {
+        IgnoreFilter::new(working_dir).retain(&mut paths);
+    }
 
     // Sort for deterministic hashing
     let mut sorted_paths = paths;
@@ -266,45 +345,56 @@ pub async fn needs_run(options: CacheOptions, working_dir: &Path) -> Result<bool
     Ok(!exists)
 }
 
+/// Archive file of the outputs cached under `cache_key`
+fn archive_path(cache_dir: &Path, cache_key: &str) -> PathBuf {
+    cache_dir
+        .join("artifacts")
+        .join(format!("{}.tar.zst", cache_key))
+}
+
 /// Archive outputs to cache
+///
+/// Outputs are stored in one tar+zstd archive per cache key, which keeps file modes,
+/// modification times and symlinks (see `recipe::outputs`). Images are saved into the
+/// archive too, and loaded back by `restore_outputs`.
 pub async fn archive_outputs(
     outputs: &[String],
+    images: &[String],
     cache_dir: &Path,
     cache_key: &str,
     working_dir: &Path,
 ) -> Result<Vec<String>> {
-    let mut archived = Vec::new();
-
     // Create archive directory
-    let archive_dir = cache_dir.join("artifacts").join(cache_key);
-    tokio::fs::create_dir_all(&archive_dir)
+    let archive_path = archive_path(cache_dir, cache_key);
+    tokio::fs::create_dir_all(cache_dir.join("artifacts"))
         .await
         .context("Failed to create archive directory")?;
 
-    for output_pattern in outputs {
-        let output_path = working_dir.join(output_pattern);
-
-        if output_path.is_file() {
-            // Archive single file
-            let dest = archive_dir.join(
-                output_path
-                    .file_name()
-                    .context("Invalid output file name")?,
-            );
-            tokio::fs::copy(&output_path, &dest)
-                .await
-                .context("Failed to archive output file")?;
-            archived.push(output_pattern.clone());
-        } else if output_path.is_dir() {
-            // Archive directory recursively
-            copy_dir_all(&output_path, &archive_dir.join(output_pattern))
-                .await
-                .context("Failed to archive output directory")?;
-            archived.push(output_pattern.clone());
-        }
-    }
+    // Missing outputs are skipped; dangling symlinks are outputs too
+    let paths: Vec<(String, PathBuf)> = outputs
+        .iter()
+        .map(|output| (output.clone(), working_dir.join(output)))
+        .filter(|(_, path)| path.symlink_metadata().is_ok())
+        .collect();
+
+    let images: Vec<ImageOutputSpec> = images
+        .iter()
+        .map(|image| ImageOutputSpec {
+            image: image.clone(),
+            required: true,
+        })
+        .collect();
+    let (archived, _) = tokio::task::spawn_blocking(move || {
+        archive_paths_with_images(paths, &images, &archive_path)
+    })
+    .await
+    .context("Archiving task failed")?
+    .context("Failed to archive outputs")?;
 
-    Ok(archived)
+    // Drop the plain copies older versions cached under this key
+    let _ = tokio::fs::remove_dir_all(cache_dir.join("artifacts").join(cache_key)).await;
+
+    Ok(archived.into_iter().map(|output| output.path).collect())
 }
 
 /// Restore outputs from cache
@@ -313,7 +403,32 @@ pub async fn restore_outputs(
     cache_dir: &Path,
     cache_key: &str,
     working_dir: &Path,
+    options: RestoreOptions,
 ) -> Result<Vec<String>> {
+    let archive_path = archive_path(cache_dir, cache_key);
+    if archive_path.exists() {
+        let file = std::fs::File::open(&archive_path).context("Failed to open archive")?;
+        let dest = working_dir.to_path_buf();
+        let entries = tokio::task::spawn_blocking(move || extract_archive(file, &dest, options))
+            .await
+            .context("Restore task failed")?
+            .context("Failed to restore outputs")?;
+
+        return Ok(outputs
+            .iter()
+            .filter(|output| {
+                // Entry paths have no `./` components
+                let output: PathBuf = Path::new(output)
+                    .components()
+                    .filter(|component| !matches!(component, Component::CurDir))
+                    .collect();
+                entries.iter().any(|entry| entry.starts_with(&output))
+            })
+            .cloned()
+            .collect());
+    }
+
+    // Entries cached by older versions are plain copies
     let mut restored = Vec::new();
     let archive_dir = cache_dir.join("artifacts").join(cache_key);
 
@@ -416,6 +531,18 @@ mod tests {
         std::env::set_var("TEST_VAR", "different_value");
         let key3 = compute_cache_key(&options, temp_dir.path()).await.unwrap();
         assert_ne!(key1, key3);
+
+        // Different recipe parameters should produce different keys
+        let mut prod = options.clone();
+        prod.params.insert("target".to_string(), "prod".to_string());
+        let mut dev = options.clone();
+        dev.params.insert("target".to_string(), "dev".to_string());
+        let key4 = compute_cache_key(&prod, temp_dir.path()).await.unwrap();
+        assert_ne!(key3, key4);
+        assert_ne!(
+            key4,
+            compute_cache_key(&dev, temp_dir.path()).await.unwrap()
+        );
     }
 
     #[tokio::test]
@@ -482,6 +609,7 @@ mod tests {
         // Archive
         let archived = archive_outputs(
             &["output.txt".to_string()],
+            &[],
             &cache_dir,
             "test_key",
             &working_dir,
@@ -501,6 +629,7 @@ mod tests {
             &cache_dir,
             "test_key",
             &working_dir,
+            RestoreOptions::default(),
         )
         .await
         .unwrap();
@@ -0,0 +1,199 @@
+// Subprocesses spawned by Fabrik.exec
+//
+// Recipes get the exit code and the captured stdout/stderr of every command. With
+// `verbose`, the output is also streamed to Fabrik's own stdout/stderr as it arrives so
+// long builds don't look stuck.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::process::Command;
+
+/// Options of a Fabrik.exec call (its optional third argument)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecOptions {
+    /// Working directory, relative to the recipe directory (default: the recipe directory)
+    pub cwd: Option<String>,
+    /// Variables added to the inherited environment
+    pub env: HashMap<String, String>,
+    /// Kill the command if it runs longer than this
+    pub timeout: Option<Duration>,
+    /// Written to the command's stdin, which is closed afterwards (default: no input)
+    pub stdin: Option<String>,
+    /// Stream output live in addition to capturing it
+    pub verbose: bool,
+}
+
+/// Result of a Fabrik.exec call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecOutput {
+    /// Exit code, -1 if the command was killed by a signal
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ExecOptions {
+    /// Directory the command runs in
+    pub fn working_dir(&self, recipe_dir: &Path) -> PathBuf {
+        match self.cwd {
+            Some(ref cwd) => recipe_dir.join(cwd),
+            None => recipe_dir.to_path_buf(),
+        }
+    }
+
+    /// Build the command for `program` with these options applied
+    pub fn command(&self, program: &str, args: &[String], recipe_dir: &Path) -> Command {
+        let mut cmd = Command::new(program);
+        cmd.args(args)
+            .current_dir(self.working_dir(recipe_dir))
+            .envs(&self.env)
+            .kill_on_drop(true);
+        cmd
+    }
+}
+
+/// Run a command to completion, capturing its output
+///
+/// The command is killed if the returned future is dropped.
+pub async fn run(mut cmd: Command, options: &ExecOptions) -> io::Result<ExecOutput> {
+    cmd.stdin(if options.stdin.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    })
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+
+    let mut child = cmd.spawn()?;
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    let write_stdin = async {
+        if let (Some(mut pipe), Some(input)) = (stdin, options.stdin.as_deref()) {
+            // A command that exits without reading its input isn't an error
+            match pipe.write_all(input.as_bytes()).await {
+                Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    };
+    let echo = options.verbose;
+    let (stdin_result, stdout, stderr, status) = tokio::join!(
+        write_stdin,
+        capture(stdout, echo.then(tokio::io::stdout)),
+        capture(stderr, echo.then(tokio::io::stderr)),
+        child.wait(),
+    );
+    stdin_result?;
+
+    Ok(ExecOutput {
+        code: status?.code().unwrap_or(-1),
+        stdout: String::from_utf8_lossy(&stdout?).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr?).into_owned(),
+    })
+}
+
+/// Read a pipe to the end, copying what's read to `echo` as it arrives
+async fn capture<R, W>(mut pipe: R, mut echo: Option<W>) -> io::Result<Vec<u8>>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut captured = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = pipe.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(captured);
+        }
+        if let Some(ref mut echo) = echo {
+            // Losing the live copy must not fail the command
+            if echo.write_all(&buf[..n]).await.is_err() || echo.flush().await.is_err() {
+                tracing::debug!("Failed to stream Fabrik.exec output");
+            }
+        }
+        captured.extend_from_slice(&buf[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    async fn sh(script: &str, options: &ExecOptions) -> ExecOutput {
+        let cmd = options.command(
+            "sh",
+            &["-c".to_string(), script.to_string()],
+            Path::new("/"),
+        );
+        run(cmd, options).await.unwrap()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_captures_output_and_code() {
+        let output = sh("echo out; echo err >&2; exit 3", &ExecOptions::default()).await;
+
+        assert_eq!(
+            output,
+            ExecOutput {
+                code: 3,
+                stdout: "out\n".to_string(),
+                stderr: "err\n".to_string(),
+            }
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_options() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        let options = ExecOptions {
+            cwd: Some("sub".to_string()),
+            env: HashMap::from([("FABRIK_EXEC_TEST".to_string(), "42".to_string())]),
+            stdin: Some("input".to_string()),
+            ..Default::default()
+        };
+
+        let cmd = options.command(
+            "sh",
+            &[
+                "-c".to_string(),
+                "basename \"$PWD\"; echo $FABRIK_EXEC_TEST; cat".to_string(),
+            ],
+            temp_dir.path(),
+        );
+        let output = run(cmd, &options).await.unwrap();
+
+        assert_eq!(output.code, 0);
+        assert_eq!(output.stdout, "sub\n42\ninput");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unread_stdin() {
+        let options = ExecOptions {
+            stdin: Some("x".repeat(1 << 20)),
+            verbose: true,
+            ..Default::default()
+        };
+
+        assert_eq!(sh("exit 0", &options).await.code, 0);
+    }
+
+    #[tokio::test]
+    async fn test_missing_command() {
+        let options = ExecOptions::default();
+        let cmd = options.command("fabrik-no-such-command", &[], Path::new("/"));
+
+        assert!(run(cmd, &options).await.is_err());
+    }
+}
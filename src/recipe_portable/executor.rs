@@ -1,20 +1,79 @@
 // Recipe executor - Runs portable recipes in QuickJS runtime
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use rquickjs::async_with;
 use std::path::PathBuf;
+use std::sync::Arc;
 
-use super::runtime::create_fabrik_runtime_with_dir;
+use super::artifacts::ArtifactStore;
+use super::limits::ExecLimits;
+use super::params;
+use super::permissions::{PermissionGuard, PermissionMode, Permissions};
+use super::runtime::{
+    create_fabrik_runtime_with_artifacts, set_recipe_args, set_recipe_targets, tasks_defined,
+};
 
 /// Executes portable recipes (JavaScript files with Fabrik APIs)
 pub struct RecipeExecutor {
     recipe_path: PathBuf,
+    exec_limits: ExecLimits,
+    permission_mode: PermissionMode,
+    cache_dir: PathBuf,
+    args: Vec<String>,
+    targets: Vec<String>,
+    task_concurrency: usize,
 }
 
 impl RecipeExecutor {
     /// Create a new recipe executor
+    ///
+    /// Recipes may do anything unless a permission mode is set.
     pub fn new(recipe_path: PathBuf) -> Self {
-        Self { recipe_path }
+        Self {
+            recipe_path,
+            exec_limits: ExecLimits::default(),
+            permission_mode: PermissionMode::Allow,
+            cache_dir: crate::storage::default_cache_dir(),
+            args: Vec::new(),
+            targets: Vec::new(),
+            task_concurrency: 0,
+        }
+    }
+
+    /// Limit the subprocesses the recipe may spawn through Fabrik.exec
+    pub fn with_exec_limits(mut self, exec_limits: ExecLimits) -> Self {
+        self.exec_limits = exec_limits;
+        self
+    }
+
+    /// Enforce the recipe's `//FABRIK allow-*` declarations
+    pub fn with_permission_mode(mut self, permission_mode: PermissionMode) -> Self {
+        self.permission_mode = permission_mode;
+        self
+    }
+
+    /// Store the artifacts of Fabrik.cache in `cache_dir` (default: the user cache directory)
+    pub fn with_cache_dir(mut self, cache_dir: PathBuf) -> Self {
+        self.cache_dir = cache_dir;
+        self
+    }
+
+    /// Pass the arguments given after `--` (Fabrik.args, parsed by defineRecipe)
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Run these defineTasks() tasks instead of `default`
+    pub fn with_targets(mut self, targets: Vec<String>) -> Self {
+        self.targets = targets;
+        self
+    }
+
+    /// Run at most `task_concurrency` defineTasks() tasks at once (0: one per CPU)
+    pub fn with_task_concurrency(mut self, task_concurrency: usize) -> Self {
+        self.task_concurrency = task_concurrency;
+        self
     }
 
     /// Execute a recipe at root level
@@ -34,21 +93,72 @@ impl RecipeExecutor {
             .unwrap_or_else(|| std::path::Path::new("."))
             .to_path_buf();
 
+        // Permissions come from the code being run, not a separate read of the file
+        let permissions = match self.permission_mode {
+            PermissionMode::Allow => Permissions::all(),
+            _ => Permissions::parse(&recipe_code).context("Invalid recipe permissions")?,
+        };
+        let permissions = Arc::new(PermissionGuard::new(
+            permissions,
+            recipe_dir.clone(),
+            self.permission_mode,
+        ));
+
         // Create QuickJS runtime with Fabrik APIs
-        let (_runtime, context) = create_fabrik_runtime_with_dir(recipe_dir).await?;
+        let (_runtime, context) = create_fabrik_runtime_with_artifacts(
+            recipe_dir,
+            self.exec_limits.clone(),
+            permissions,
+            Arc::new(ArtifactStore::new(self.cache_dir.clone())),
+        )
+        .await?;
+
+        let recipe_name = self
+            .recipe_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let args = self.args.clone();
+        let targets = self.targets.clone();
+        let task_concurrency = self.task_concurrency;
 
         // Execute recipe at root level (wrap in async IIFE)
-        async_with!(context => |ctx| {
+        let result = async_with!(context => |ctx| {
+            set_recipe_args(&ctx, &recipe_name, args)?;
+            set_recipe_targets(&ctx, targets, task_concurrency)?;
+
             let wrapped_code = format!("(async () => {{ {} }})();", recipe_code);
             let promise: rquickjs::Promise = ctx.eval(wrapped_code.as_bytes())?;
 
-            // Wait for promise to complete
-            promise.into_future::<()>().await?;
-
-            Ok::<_, rquickjs::Error>(())
+            // Wait for promise to complete, keeping the message of uncaught exceptions
+            // (e.g. exceeded exec budgets) so the failure is actionable
+            match promise.into_future::<()>().await {
+                Ok(()) => Ok(Ok(tasks_defined(&ctx)?)),
+                Err(rquickjs::Error::Exception) => {
+                    let exception = ctx.catch();
+                    let message = exception
+                        .as_exception()
+                        .and_then(|e| e.message())
+                        .unwrap_or_else(|| format!("{:?}", exception));
+                    Ok(Err(message))
+                }
+                Err(e) => Err(e),
+            }
         })
         .await?;
 
+        let tasks_defined = match result {
+            // defineRecipe() printed the help
+            Err(message) if message.contains(params::HELP_SHOWN) => return Ok(()),
+            result => result.map_err(|message| anyhow::anyhow!("Recipe failed: {}", message))?,
+        };
+        if !self.targets.is_empty() && !tasks_defined {
+            anyhow::bail!(
+                "Tasks {} were given, but the recipe doesn't call defineTasks()",
+                self.targets.join(", ")
+            );
+        }
+
         tracing::info!("Recipe completed successfully");
 
         Ok(())
@@ -67,8 +177,8 @@ mod tests {
 
         let recipe_code = r#"
             console.log("Running simple recipe");
-            const exitCode = await Fabrik.exec("echo", ["hello from recipe"]);
-            if (exitCode !== 0) {
+            const { code, stdout } = await Fabrik.exec("echo", ["hello from recipe"]);
+            if (code !== 0 || stdout !== "hello from recipe\n") {
                 throw new Error("Command failed");
             }
         "#;
@@ -147,4 +257,157 @@ mod tests {
         let executor = RecipeExecutor::new(recipe_path);
         executor.execute().await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_recipe_args() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let recipe_path = temp_dir.path().join("args.recipe.js");
+
+        tokio::fs::write(
+            &recipe_path,
+            r#"
+            const { target } = defineRecipe({ params: { target: { type: "string", required: true } } });
+            await Fabrik.writeFile("target.txt", target);
+        "#,
+        )
+        .await
+        .unwrap();
+
+        RecipeExecutor::new(recipe_path.clone())
+            .with_args(vec!["--target=prod".to_string()])
+            .execute()
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.path().join("target.txt")).unwrap(),
+            "prod"
+        );
+
+        // --help ends the recipe successfully, a missing argument fails it
+        RecipeExecutor::new(recipe_path.clone())
+            .with_args(vec!["--help".to_string()])
+            .execute()
+            .await
+            .unwrap();
+        let err = RecipeExecutor::new(recipe_path)
+            .execute()
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("Missing required argument --target"),
+            "unexpected error: {:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_define_tasks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let recipe_path = temp_dir.path().join("tasks.recipe.js");
+        std::fs::write(temp_dir.path().join("src.txt"), "source").unwrap();
+
+        tokio::fs::write(
+            &recipe_path,
+            r#"
+            const log = async (line) => {
+                const previous = (await Fabrik.exists("log.txt")) ? await Fabrik.readFile("log.txt") : "";
+                await Fabrik.writeFile("log.txt", previous + line + "\n");
+            };
+            await defineTasks({
+                build: {
+                    inputs: ["src.txt"],
+                    outputs: ["dist/out.txt"],
+                    run: async () => {
+                        await log("build");
+                        await Fabrik.writeFile("dist/out.txt", "built");
+                    },
+                },
+                test: { deps: ["build"], run: () => log("test") },
+                fail: { run: () => { throw new Error("boom"); } },
+            });
+        "#,
+        )
+        .await
+        .unwrap();
+        let run = |targets: &[&str]| {
+            let executor = RecipeExecutor::new(recipe_path.clone())
+                .with_targets(targets.iter().map(|t| t.to_string()).collect());
+            async move { executor.execute().await }
+        };
+        let log = || std::fs::read_to_string(temp_dir.path().join("log.txt")).unwrap();
+
+        run(&["test"]).await.unwrap();
+        assert_eq!(log(), "build\ntest\n");
+
+        // build is cached, test always runs
+        std::fs::remove_dir_all(temp_dir.path().join("dist")).unwrap();
+        run(&["test"]).await.unwrap();
+        assert_eq!(log(), "build\ntest\ntest\n");
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.path().join("dist/out.txt")).unwrap(),
+            "built"
+        );
+
+        let err = run(&["fail"]).await.unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("Task 'fail' failed: boom"),
+            "unexpected error: {:?}",
+            err
+        );
+        let err = run(&[]).await.unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("available tasks: build, fail, test"),
+            "unexpected error: {:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_undeclared_exec_is_denied() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let recipe_path = temp_dir.path().join("denied.recipe.js");
+
+        tokio::fs::write(
+            &recipe_path,
+            r#"
+            //FABRIK allow-exec "echo"
+            await Fabrik.exec("echo", ["allowed"]);
+            await Fabrik.exec("ls", ["/"]);
+        "#,
+        )
+        .await
+        .unwrap();
+
+        let executor = RecipeExecutor::new(recipe_path).with_permission_mode(PermissionMode::Deny);
+        let err = executor.execute().await.unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("exec access to ls"),
+            "unexpected error: {:?}",
+            err
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_exec_time_budget_exceeded() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let recipe_path = temp_dir.path().join("slow.recipe.js");
+
+        tokio::fs::write(&recipe_path, r#"await Fabrik.exec("sleep", ["5"]);"#)
+            .await
+            .unwrap();
+
+        let executor = RecipeExecutor::new(recipe_path).with_exec_limits(ExecLimits {
+            max_concurrent: 1,
+            cpu_budget: None,
+            time_budget: Some(std::time::Duration::from_millis(200)),
+        });
+
+        let err = executor.execute().await.unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("exec time budget"),
+            "unexpected error: {:?}",
+            err
+        );
+    }
 }
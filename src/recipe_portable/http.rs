@@ -0,0 +1,403 @@
+// HTTP requests made by the fetch() global
+//
+// Recipes download toolchains and call APIs with a fetch()-like function. Successful GET
+// responses are stored in the content-addressed cache under their SHA256, so a recipe
+// that pins a download with `sha256` gets it from the cache on later runs without
+// touching the network. With `output`, the body is streamed to a file instead of being
+// kept in memory.
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+use super::artifacts::ArtifactStore;
+use super::permissions::PermissionGuard;
+
+/// Redirects followed before giving up
+const MAX_REDIRECTS: usize = 10;
+
+/// Options of a fetch() call (its optional second argument)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FetchRequest {
+    /// HTTP method (default: GET)
+    pub method: Option<String>,
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<String>,
+    /// Stream the body to this file, relative to the recipe directory
+    pub output: Option<String>,
+    /// Expected SHA256 of the body (hex); a cached copy is used without a request
+    pub sha256: Option<String>,
+    /// Abort the request if it takes longer than this
+    pub timeout: Option<Duration>,
+}
+
+/// Result of a fetch() call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchResponse {
+    pub status: u16,
+    /// Final URL, after redirects
+    pub url: String,
+    /// Lowercase header names
+    pub headers: BTreeMap<String, String>,
+    /// Body, empty when it was written to `path`
+    pub body: Vec<u8>,
+    /// SHA256 of the body (hex)
+    pub sha256: String,
+    pub size: u64,
+    /// File the body was written to (`output` of a successful request)
+    pub path: Option<PathBuf>,
+    /// Served from the cache without a request
+    pub cached: bool,
+}
+
+impl FetchResponse {
+    pub fn ok(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+impl FetchRequest {
+    fn is_get(&self) -> bool {
+        self.method
+            .as_deref()
+            .is_none_or(|method| method.eq_ignore_ascii_case("GET"))
+    }
+
+    /// File the body is written to
+    pub fn output_path(&self, recipe_dir: &Path) -> Option<PathBuf> {
+        self.output.as_ref().map(|output| recipe_dir.join(output))
+    }
+}
+
+/// Host of an http(s) URL, as checked against `allow-net`
+pub fn host(url: &str) -> Result<String> {
+    let parsed = reqwest::Url::parse(url).with_context(|| format!("Invalid URL '{}'", url))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        bail!(
+            "Unsupported URL '{}': only http and https are supported",
+            url
+        );
+    }
+    match parsed.host_str() {
+        Some(host) => Ok(host.to_string()),
+        None => bail!("Invalid URL '{}': missing host", url),
+    }
+}
+
+/// HTTP client of a recipe; redirects to other hosts are subject to `permissions`
+pub fn client(permissions: Arc<PermissionGuard>) -> Result<reqwest::Client> {
+    let policy = reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error(format!("more than {} redirects", MAX_REDIRECTS));
+        }
+        let allowed = match attempt.url().host_str() {
+            Some(host) => permissions.check_net(host).map_err(|e| e.to_string()),
+            None => Err(format!("redirect to {} without a host", attempt.url())),
+        };
+        match allowed {
+            Ok(()) => attempt.follow(),
+            Err(e) => attempt.error(e),
+        }
+    });
+
+    reqwest::Client::builder()
+        .user_agent(concat!("fabrik/", env!("CARGO_PKG_VERSION")))
+        .redirect(policy)
+        .build()
+        .context("Failed to create HTTP client")
+}
+
+/// Make a request, caching successful GET responses in `store`
+///
+/// Permissions for the URL's host and `output` must have been checked by the caller.
+pub async fn fetch(
+    client: &reqwest::Client,
+    url: &str,
+    request: &FetchRequest,
+    recipe_dir: &Path,
+    store: &ArtifactStore,
+) -> Result<FetchResponse> {
+    let output = request.output_path(recipe_dir);
+    let expected = request.sha256.as_deref().map(str::to_ascii_lowercase);
+    if let Some(ref hash) = expected {
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            bail!("Invalid sha256 '{}': expected 64 hex characters", hash);
+        }
+    }
+
+    if let Some(ref hash) = expected {
+        if request.is_get() {
+            // A cache that can't be read (e.g. locked by a daemon) means downloading again
+            match from_cache(url, hash, output.as_deref(), store).await {
+                Ok(Some(response)) => return Ok(response),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to look up {} in the cache: {:#}", url, e),
+            }
+        }
+    }
+
+    let method = request.method.as_deref().unwrap_or("GET");
+    let method = reqwest::Method::from_bytes(method.as_bytes())
+        .with_context(|| format!("Invalid HTTP method '{}'", method))?;
+    let mut builder = client.request(method, url);
+    for (name, value) in &request.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    if let Some(ref body) = request.body {
+        builder = builder.body(body.clone());
+    }
+    if let Some(timeout) = request.timeout {
+        builder = builder.timeout(timeout);
+    }
+
+    let mut response = builder
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch {}", url))?;
+    let status = response.status().as_u16();
+    let final_url = response.url().to_string();
+    let headers = response
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| {
+            let value = value.to_str().ok()?;
+            Some((name.as_str().to_ascii_lowercase(), value.to_string()))
+        })
+        .collect();
+
+    // Only successful responses are written to `output`
+    let ok = (200..300).contains(&status);
+    let mut sink = match output {
+        Some(ref path) if ok => Sink::file(path).await?,
+        _ => Sink::memory(),
+    };
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .with_context(|| format!("Failed to download {}", url))?
+    {
+        sink.write(&chunk).await?;
+    }
+    let (sha256, size, body) = sink.finish().await?;
+
+    let path = if ok { output } else { None };
+    if ok {
+        if let Some(ref expected) = expected {
+            if *expected != sha256 {
+                if let Some(ref path) = path {
+                    let _ = tokio::fs::remove_file(path).await;
+                }
+                bail!(
+                    "Checksum mismatch for {}: expected sha256 {}, got {}",
+                    url,
+                    expected,
+                    sha256
+                );
+            }
+        }
+        if request.is_get() {
+            let stored = match path {
+                Some(ref path) => store.put_file(&sha256, path).await,
+                None => store.put(&sha256, body.clone()).await,
+            };
+            // The download succeeded, a cache that can't take it isn't fatal
+            if let Err(e) = stored {
+                tracing::warn!("Failed to cache download of {}: {:#}", url, e);
+            }
+        }
+    }
+
+    Ok(FetchResponse {
+        status,
+        url: final_url,
+        headers,
+        body,
+        sha256,
+        size,
+        path,
+        cached: false,
+    })
+}
+
+/// Response for a pinned download that's already in the cache
+async fn from_cache(
+    url: &str,
+    hash: &str,
+    output: Option<&Path>,
+    store: &ArtifactStore,
+) -> Result<Option<FetchResponse>> {
+    let Some(data) = store.get(hash).await? else {
+        return Ok(None);
+    };
+    tracing::debug!("Fetch {} served from the cache ({})", url, hash);
+
+    let size = data.len() as u64;
+    let body = match output {
+        Some(path) => {
+            let mut sink = Sink::file(path).await?;
+            sink.write(&data).await?;
+            sink.finish().await?;
+            Vec::new()
+        }
+        None => data,
+    };
+
+    Ok(Some(FetchResponse {
+        status: 200,
+        url: url.to_string(),
+        headers: BTreeMap::new(),
+        body,
+        sha256: hash.to_string(),
+        size,
+        path: output.map(Path::to_path_buf),
+        cached: true,
+    }))
+}
+
+/// Destination of a response body, hashed as it's written
+struct Sink {
+    hasher: Sha256,
+    size: u64,
+    file: Option<(PathBuf, tokio::fs::File)>,
+    body: Vec<u8>,
+}
+
+impl Sink {
+    fn memory() -> Self {
+        Self {
+            hasher: Sha256::new(),
+            size: 0,
+            file: None,
+            body: Vec::new(),
+        }
+    }
+
+    async fn file(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let file = tokio::fs::File::create(path)
+            .await
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+
+        Ok(Self {
+            file: Some((path.to_path_buf(), file)),
+            ..Self::memory()
+        })
+    }
+
+    async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.hasher.update(chunk);
+        self.size += chunk.len() as u64;
+        match self.file {
+            Some((ref path, ref mut file)) => file
+                .write_all(chunk)
+                .await
+                .with_context(|| format!("Failed to write {}", path.display())),
+            None => {
+                self.body.extend_from_slice(chunk);
+                Ok(())
+            }
+        }
+    }
+
+    /// SHA256 (hex), size and in-memory body
+    async fn finish(self) -> Result<(String, u64, Vec<u8>)> {
+        if let Some((path, mut file)) = self.file {
+            file.flush()
+                .await
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok((hex::encode(self.hasher.finalize()), self.size, self.body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FilesystemStorage;
+
+    fn sha256(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    #[test]
+    fn test_host() {
+        assert_eq!(
+            host("https://nodejs.org/dist/v20.0.0/").unwrap(),
+            "nodejs.org"
+        );
+        assert_eq!(host("http://localhost:8080/api").unwrap(), "localhost");
+        assert!(host("nodejs.org/dist").is_err());
+        assert!(host("file:///etc/passwd").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sink_hashes_and_streams_to_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("downloads/tool.tar.gz");
+
+        let mut sink = Sink::file(&path).await.unwrap();
+        sink.write(b"hello ").await.unwrap();
+        sink.write(b"world").await.unwrap();
+        let (hash, size, body) = sink.finish().await.unwrap();
+
+        assert_eq!(hash, sha256(b"hello world"));
+        assert_eq!(size, 11);
+        assert!(body.is_empty());
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_pinned_download_served_from_cache() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage = Arc::new(FilesystemStorage::new(temp_dir.path().join("cache")).unwrap());
+        let store = ArtifactStore::from_storage(storage);
+        let hash = sha256(b"toolchain");
+        store.put(&hash, b"toolchain".to_vec()).await.unwrap();
+
+        // Nothing listens on the discard port: a request would fail
+        let client = client(Arc::new(PermissionGuard::allow_all(temp_dir.path().into()))).unwrap();
+        let url = "http://127.0.0.1:9/toolchain.tar.gz";
+        let request = FetchRequest {
+            sha256: Some(hash.to_uppercase()),
+            output: Some("bin/toolchain.tar.gz".to_string()),
+            ..Default::default()
+        };
+
+        let response = fetch(&client, url, &request, temp_dir.path(), &store)
+            .await
+            .unwrap();
+        assert!(response.cached && response.ok());
+        assert_eq!(response.size, 9);
+        assert_eq!(
+            std::fs::read(temp_dir.path().join("bin/toolchain.tar.gz")).unwrap(),
+            b"toolchain"
+        );
+
+        // Other methods always make the request
+        let request = FetchRequest {
+            method: Some("POST".to_string()),
+            sha256: Some(hash),
+            ..Default::default()
+        };
+        assert!(fetch(&client, url, &request, temp_dir.path(), &store)
+            .await
+            .is_err());
+
+        let request = FetchRequest {
+            sha256: Some("abc".to_string()),
+            ..Default::default()
+        };
+        let err = fetch(&client, url, &request, temp_dir.path(), &store)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid sha256"));
+    }
+}
@@ -0,0 +1,312 @@
+// Admission control for Fabrik.exec subprocesses
+//
+// Recipes can spawn any number of concurrent subprocesses through Fabrik.exec. On a
+// shared CI host that quickly starves other jobs, so every exec has to take a token
+// from a per-recipe bucket (released when the subprocess exits) and is charged
+// against an aggregate wall-clock and CPU budget for the whole recipe execution.
+
+use anyhow::{Context, Result};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::eviction::EvictionConfig;
+
+/// Limits applied to the subprocesses spawned by one recipe execution
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecLimits {
+    /// Maximum number of subprocesses running at the same time
+    pub max_concurrent: usize,
+    /// Total CPU time (user + system) subprocesses may consume
+    pub cpu_budget: Option<Duration>,
+    /// Total wall-clock time subprocesses may run for, summed across all execs
+    pub time_budget: Option<Duration>,
+}
+
+impl Default for ExecLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent: default_max_concurrent(),
+            cpu_budget: None,
+            time_budget: None,
+        }
+    }
+}
+
+impl ExecLimits {
+    /// Build limits from CLI/env values
+    ///
+    /// `max_concurrent` of 0 means one subprocess per available CPU. Budgets use the
+    /// same duration format as TTLs (e.g. `90s`, `30m`, `2h`).
+    pub fn from_args(
+        max_concurrent: usize,
+        cpu_budget: Option<&str>,
+        time_budget: Option<&str>,
+    ) -> Result<Self> {
+        let parse = |value: &str| {
+            EvictionConfig::parse_ttl(value)
+                .map(Duration::from_secs)
+                .with_context(|| format!("Invalid exec budget: {}", value))
+        };
+
+        Ok(Self {
+            max_concurrent: if max_concurrent == 0 {
+                default_max_concurrent()
+            } else {
+                max_concurrent
+            },
+            cpu_budget: cpu_budget.map(parse).transpose()?,
+            time_budget: time_budget.map(parse).transpose()?,
+        })
+    }
+}
+
+fn default_max_concurrent() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ExecLimitError {
+    #[error(
+        "Recipe exceeded its exec time budget of {budget:?} (subprocesses ran for {used:?}); \
+         raise it with --exec-time-budget"
+    )]
+    TimeBudgetExceeded { used: Duration, budget: Duration },
+
+    #[error(
+        "Recipe exceeded its exec CPU budget of {budget:?} (subprocesses used {used:?}); \
+         raise it with --exec-cpu-budget"
+    )]
+    CpuBudgetExceeded { used: Duration, budget: Duration },
+}
+
+/// Shared exec budget for one recipe execution
+#[derive(Debug)]
+pub struct ExecBudget {
+    limits: ExecLimits,
+    tokens: Arc<Semaphore>,
+    time_used: Mutex<Duration>,
+    /// Child CPU time already consumed by this process when the recipe started
+    cpu_baseline: Option<Duration>,
+}
+
+impl ExecBudget {
+    pub fn new(limits: ExecLimits) -> Self {
+        Self {
+            tokens: Arc::new(Semaphore::new(limits.max_concurrent.max(1))),
+            time_used: Mutex::new(Duration::ZERO),
+            cpu_baseline: children_cpu_time(),
+            limits,
+        }
+    }
+
+    pub fn limits(&self) -> &ExecLimits {
+        &self.limits
+    }
+
+    /// Wait for an exec token, failing if the recipe has already used up its budgets
+    pub async fn admit(self: &Arc<Self>) -> Result<ExecPermit, ExecLimitError> {
+        self.check()?;
+
+        let token = self
+            .tokens
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("exec token semaphore is never closed");
+
+        // Budgets may have run out while this exec was queued
+        self.check()?;
+
+        Ok(ExecPermit {
+            budget: Arc::clone(self),
+            started: Instant::now(),
+            _token: token,
+        })
+    }
+
+    /// Fail if any budget has been exhausted
+    pub fn check(&self) -> Result<(), ExecLimitError> {
+        if let Some(budget) = self.limits.time_budget {
+            let used = self.time_used();
+            if used >= budget {
+                return Err(ExecLimitError::TimeBudgetExceeded { used, budget });
+            }
+        }
+
+        if let (Some(budget), Some(used)) = (self.limits.cpu_budget, self.cpu_used()) {
+            if used >= budget {
+                return Err(ExecLimitError::CpuBudgetExceeded { used, budget });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wall-clock time left for subprocesses
+    pub fn remaining_time(&self) -> Option<Duration> {
+        self.limits
+            .time_budget
+            .map(|budget| budget.saturating_sub(self.time_used()))
+    }
+
+    /// CPU time left for subprocesses
+    pub fn remaining_cpu(&self) -> Option<Duration> {
+        let budget = self.limits.cpu_budget?;
+        Some(budget.saturating_sub(self.cpu_used().unwrap_or_default()))
+    }
+
+    /// Wall-clock time charged so far
+    pub fn time_used(&self) -> Duration {
+        *self.time_used.lock().unwrap()
+    }
+
+    /// CPU time consumed by subprocesses reaped since the recipe started
+    ///
+    /// Returns None on platforms where child CPU time isn't available.
+    pub fn cpu_used(&self) -> Option<Duration> {
+        Some(children_cpu_time()?.saturating_sub(self.cpu_baseline?))
+    }
+
+    fn charge(&self, elapsed: Duration) {
+        *self.time_used.lock().unwrap() += elapsed;
+    }
+}
+
+/// Token held while a subprocess runs
+///
+/// Returns the token to the bucket and charges the elapsed time when dropped.
+#[derive(Debug)]
+pub struct ExecPermit {
+    budget: Arc<ExecBudget>,
+    started: Instant,
+    _token: OwnedSemaphorePermit,
+}
+
+impl ExecPermit {
+    /// How long this subprocess may run before the time budget runs out
+    pub fn timeout(&self) -> Option<Duration> {
+        self.budget.remaining_time()
+    }
+}
+
+impl Drop for ExecPermit {
+    fn drop(&mut self) {
+        self.budget.charge(self.started.elapsed());
+    }
+}
+
+/// Total CPU time of this process's waited-for children
+#[cfg(unix)]
+fn children_cpu_time() -> Option<Duration> {
+    use nix::sys::resource::{getrusage, UsageWho};
+
+    let usage = getrusage(UsageWho::RUSAGE_CHILDREN).ok()?;
+    let to_duration = |tv: nix::sys::time::TimeVal| {
+        Duration::from_secs(tv.tv_sec().max(0) as u64)
+            + Duration::from_micros(tv.tv_usec().max(0) as u64)
+    };
+    Some(to_duration(usage.user_time()) + to_duration(usage.system_time()))
+}
+
+#[cfg(not(unix))]
+fn children_cpu_time() -> Option<Duration> {
+    None
+}
+
+/// Cap a subprocess's own CPU time at the remaining budget (RLIMIT_CPU)
+///
+/// The kernel stops the child once it runs past the limit, so a single runaway
+/// process can't blow through the budget before it is accounted for.
+#[cfg(unix)]
+pub fn limit_child_cpu(command: &mut tokio::process::Command, remaining: Duration) {
+    use nix::sys::resource::{setrlimit, Resource};
+
+    // RLIMIT_CPU has one-second granularity; round up so short budgets still run
+    let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+
+    // SAFETY: setrlimit is async-signal-safe and doesn't allocate
+    unsafe {
+        command.pre_exec(move || {
+            setrlimit(Resource::RLIMIT_CPU, secs, secs + 1).map_err(std::io::Error::from)
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub fn limit_child_cpu(_command: &mut tokio::process::Command, _remaining: Duration) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(max_concurrent: usize, time_budget: Option<Duration>) -> Arc<ExecBudget> {
+        Arc::new(ExecBudget::new(ExecLimits {
+            max_concurrent,
+            cpu_budget: None,
+            time_budget,
+        }))
+    }
+
+    #[test]
+    fn test_from_args() {
+        let limits = ExecLimits::from_args(2, Some("90s"), Some("1h")).unwrap();
+        assert_eq!(limits.max_concurrent, 2);
+        assert_eq!(limits.cpu_budget, Some(Duration::from_secs(90)));
+        assert_eq!(limits.time_budget, Some(Duration::from_secs(3600)));
+
+        assert!(ExecLimits::from_args(0, None, None).unwrap().max_concurrent >= 1);
+        assert!(ExecLimits::from_args(1, Some("forever"), None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_is_capped() {
+        let budget = budget(1, None);
+
+        let first = budget.admit().await.unwrap();
+        let second = tokio::time::timeout(Duration::from_millis(50), budget.admit()).await;
+        assert!(second.is_err(), "second exec should wait for a token");
+
+        drop(first);
+        let second = tokio::time::timeout(Duration::from_millis(50), budget.admit()).await;
+        assert!(
+            second.is_ok(),
+            "token should be released when the exec finishes"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_time_budget_exceeded() {
+        let budget = budget(4, Some(Duration::from_millis(20)));
+
+        let permit = budget.admit().await.unwrap();
+        assert!(permit.timeout().unwrap() <= Duration::from_millis(20));
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        drop(permit);
+
+        match budget.admit().await {
+            Err(ExecLimitError::TimeBudgetExceeded { used, budget }) => {
+                assert!(used >= Duration::from_millis(30));
+                assert_eq!(budget, Duration::from_millis(20));
+            }
+            other => panic!("expected time budget error, got {:?}", other),
+        }
+        assert_eq!(budget.remaining_time(), Some(Duration::ZERO));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cpu_usage_is_measured() {
+        let budget = ExecBudget::new(ExecLimits {
+            max_concurrent: 1,
+            cpu_budget: Some(Duration::from_secs(60)),
+            time_budget: None,
+        });
+        assert!(budget.cpu_used().is_some());
+        assert!(budget.remaining_cpu().unwrap() <= Duration::from_secs(60));
+        assert!(budget.check().is_ok());
+    }
+}
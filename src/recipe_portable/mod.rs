@@ -3,10 +3,21 @@
 // This module provides cross-platform build automation using JavaScript that runs
 // on Fabrik's embedded QuickJS runtime with custom Fabrik APIs exposed.
 
+pub mod artifacts;
 pub mod cache;
+pub mod exec;
 pub mod executor;
+pub mod http;
+pub mod limits;
+pub mod params;
+pub mod permissions;
 pub mod remote;
 pub mod runtime;
+pub mod signature;
+pub mod tasks;
 
 pub use executor::RecipeExecutor;
-pub use remote::RemoteRecipe;
+pub use limits::ExecLimits;
+pub use permissions::PermissionMode;
+pub use remote::{FetchEvent, FetchOptions, Mirror, RemoteRecipe};
+pub use signature::{SignaturePolicy, Verification};
@@ -0,0 +1,439 @@
+// Recipe parameters - typed arguments for portable recipes
+//
+// A recipe declares its parameters with defineRecipe({ params }) and receives the
+// arguments given after `--` (`fabrik run build.js -- --target=prod --release`)
+// parsed into strings, booleans and numbers. Parameters are part of the cache key of
+// runCached()/needsRun() unless declared with `cacheKey: false`, so runs with
+// different arguments don't share cached outputs.
+
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Message of the exception thrown by defineRecipe() after printing `--help`, which
+/// ends the recipe successfully
+pub const HELP_SHOWN: &str = "__FABRIK_HELP_SHOWN__";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    String,
+    Bool,
+    Number,
+}
+
+impl ParamType {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "string" => Ok(Self::String),
+            "bool" | "boolean" => Ok(Self::Bool),
+            "number" => Ok(Self::Number),
+            other => bail!(
+                "Unknown parameter type '{}' (expected string, bool or number)",
+                other
+            ),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::String => "string",
+            Self::Bool => "bool",
+            Self::Number => "number",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamValue {
+    String(String),
+    Bool(bool),
+    Number(f64),
+}
+
+impl ParamValue {
+    fn kind(&self) -> ParamType {
+        match self {
+            Self::String(_) => ParamType::String,
+            Self::Bool(_) => ParamType::Bool,
+            Self::Number(_) => ParamType::Number,
+        }
+    }
+}
+
+impl fmt::Display for ParamValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(value) => write!(f, "{}", value),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Number(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// One entry of `params`: `{ type, required, default, description, cacheKey }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamSpec {
+    /// Key in the parsed arguments; the flag is its kebab-case form (`dryRun` -> `--dry-run`)
+    pub name: String,
+    pub kind: ParamType,
+    pub required: bool,
+    pub default: Option<ParamValue>,
+    pub description: Option<String>,
+    /// Whether the value is part of the cache key (default: true)
+    pub cache_key: bool,
+}
+
+impl ParamSpec {
+    fn flag(&self) -> String {
+        let mut flag = String::new();
+        for c in self.name.chars() {
+            if c.is_ascii_uppercase() {
+                flag.push('-');
+                flag.push(c.to_ascii_lowercase());
+            } else if c == '_' {
+                flag.push('-');
+            } else {
+                flag.push(c);
+            }
+        }
+        flag
+    }
+}
+
+/// Argument passed to defineRecipe()
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecipeDefinition {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub params: Vec<ParamSpec>,
+}
+
+/// Outcome of parsing the recipe arguments
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParsedArgs {
+    /// `--help` was given; the generated help text
+    Help(String),
+    /// Value of every parameter that was given or has a default
+    Values(BTreeMap<String, ParamValue>),
+}
+
+impl RecipeDefinition {
+    /// Check the parameter declarations
+    pub fn validate(&self) -> Result<()> {
+        let mut flags = BTreeMap::new();
+        for spec in &self.params {
+            if spec.name.is_empty() {
+                bail!("Parameter names must not be empty");
+            }
+            if let Some(other) = flags.insert(spec.flag(), &spec.name) {
+                bail!(
+                    "Parameters '{}' and '{}' have the same flag --{}",
+                    other,
+                    spec.name,
+                    spec.flag()
+                );
+            }
+            if spec.flag() == "help" {
+                bail!("Parameter '{}' conflicts with --help", spec.name);
+            }
+            if let Some(ref default) = spec.default {
+                if default.kind() != spec.kind {
+                    bail!(
+                        "Default of parameter '{}' must be a {}",
+                        spec.name,
+                        spec.kind.name()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse `args` (everything after `--` on the command line)
+    ///
+    /// Accepts `--name=value` and `--name value`; booleans also accept a bare `--name`
+    /// and `--no-name`. `program` is shown in the usage line of the help.
+    pub fn parse_args(&self, args: &[String], program: &str) -> Result<ParsedArgs> {
+        self.validate()?;
+
+        let mut values = BTreeMap::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                return Ok(ParsedArgs::Help(self.help(program)));
+            }
+            let Some(flag) = arg.strip_prefix("--") else {
+                bail!("Unexpected argument '{}' (see --help)", arg);
+            };
+            let (flag, inline_value) = match flag.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (flag, None),
+            };
+
+            let (spec, value) = if let Some(spec) = self.find(flag) {
+                let value = match (spec.kind, inline_value) {
+                    (_, Some(value)) => value,
+                    (ParamType::Bool, None) => "true".to_string(),
+                    (_, None) => match args.next() {
+                        Some(value) => value.clone(),
+                        None => bail!("Missing value for --{}", flag),
+                    },
+                };
+                (spec, value)
+            } else if let Some(spec) = flag
+                .strip_prefix("no-")
+                .and_then(|flag| self.find(flag))
+                .filter(|spec| spec.kind == ParamType::Bool && inline_value.is_none())
+            {
+                (spec, "false".to_string())
+            } else {
+                bail!("Unknown argument --{} (see --help)", flag);
+            };
+
+            values.insert(spec.name.clone(), parse_value(spec, &value)?);
+        }
+
+        for spec in &self.params {
+            if values.contains_key(&spec.name) {
+                continue;
+            }
+            if let Some(ref default) = spec.default {
+                values.insert(spec.name.clone(), default.clone());
+            } else if spec.required {
+                bail!("Missing required argument --{} (see --help)", spec.flag());
+            }
+        }
+
+        Ok(ParsedArgs::Values(values))
+    }
+
+    /// Generated `--help` text
+    pub fn help(&self, program: &str) -> String {
+        let mut help = String::new();
+        let header: Vec<&str> = [self.name.as_deref(), self.description.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect();
+        if !header.is_empty() {
+            help.push_str(&header.join(" - "));
+            help.push_str("\n\n");
+        }
+        help.push_str(&format!(
+            "Usage: fabrik run {} -- [OPTIONS]\n\nOptions:\n",
+            program
+        ));
+
+        let usages: Vec<String> = self
+            .params
+            .iter()
+            .map(|spec| match spec.kind {
+                ParamType::Bool => format!("--{}", spec.flag()),
+                kind => format!("--{} <{}>", spec.flag(), kind.name()),
+            })
+            .collect();
+        let width = usages.iter().map(String::len).max().unwrap_or(0).max(10);
+
+        for (spec, usage) in self.params.iter().zip(&usages) {
+            let mut notes = Vec::new();
+            if spec.required && spec.default.is_none() {
+                notes.push("required".to_string());
+            }
+            if let Some(ref default) = spec.default {
+                notes.push(format!("default: {}", default));
+            }
+            let mut text = spec.description.clone().unwrap_or_default();
+            if !notes.is_empty() {
+                text.push_str(&format!(" [{}]", notes.join(", ")));
+            }
+            let line = format!("  {:width$}  {}", usage, text.trim(), width = width);
+            help.push_str(line.trim_end());
+            help.push('\n');
+        }
+        help.push_str(&format!(
+            "  {:width$}  Print help\n",
+            "-h, --help",
+            width = width
+        ));
+        help
+    }
+
+    /// Values of the parameters that are part of the cache key
+    pub fn cache_key_values(
+        &self,
+        values: &BTreeMap<String, ParamValue>,
+    ) -> BTreeMap<String, String> {
+        self.params
+            .iter()
+            .filter(|spec| spec.cache_key)
+            .filter_map(|spec| {
+                values
+                    .get(&spec.name)
+                    .map(|value| (spec.name.clone(), value.to_string()))
+            })
+            .collect()
+    }
+
+    fn find(&self, flag: &str) -> Option<&ParamSpec> {
+        self.params.iter().find(|spec| spec.flag() == flag)
+    }
+}
+
+fn parse_value(spec: &ParamSpec, value: &str) -> Result<ParamValue> {
+    match spec.kind {
+        ParamType::String => Ok(ParamValue::String(value.to_string())),
+        ParamType::Bool => match value {
+            "true" | "1" | "yes" => Ok(ParamValue::Bool(true)),
+            "false" | "0" | "no" => Ok(ParamValue::Bool(false)),
+            _ => bail!(
+                "Invalid value '{}' for --{}: expected true or false",
+                value,
+                spec.flag()
+            ),
+        },
+        ParamType::Number => match value.parse::<f64>() {
+            Ok(number) if number.is_finite() => Ok(ParamValue::Number(number)),
+            _ => bail!(
+                "Invalid value '{}' for --{}: expected a number",
+                value,
+                spec.flag()
+            ),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(name: &str, kind: ParamType) -> ParamSpec {
+        ParamSpec {
+            name: name.to_string(),
+            kind,
+            required: false,
+            default: None,
+            description: None,
+            cache_key: true,
+        }
+    }
+
+    fn definition() -> RecipeDefinition {
+        RecipeDefinition {
+            name: None,
+            description: Some("Build the app".to_string()),
+            params: vec![
+                ParamSpec {
+                    required: true,
+                    description: Some("Deployment target".to_string()),
+                    ..param("target", ParamType::String)
+                },
+                ParamSpec {
+                    default: Some(ParamValue::Number(4.0)),
+                    ..param("jobs", ParamType::Number)
+                },
+                ParamSpec {
+                    cache_key: false,
+                    ..param("dryRun", ParamType::Bool)
+                },
+            ],
+        }
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn values(parsed: ParsedArgs) -> BTreeMap<String, ParamValue> {
+        match parsed {
+            ParsedArgs::Values(values) => values,
+            ParsedArgs::Help(help) => panic!("unexpected help: {}", help),
+        }
+    }
+
+    #[test]
+    fn test_parse_typed_values() {
+        let def = definition();
+
+        let parsed = values(
+            def.parse_args(
+                &args(&["--target=prod", "--jobs", "8", "--dry-run"]),
+                "b.js",
+            )
+            .unwrap(),
+        );
+        assert_eq!(parsed["target"], ParamValue::String("prod".to_string()));
+        assert_eq!(parsed["jobs"], ParamValue::Number(8.0));
+        assert_eq!(parsed["dryRun"], ParamValue::Bool(true));
+
+        // Defaults apply, unset optional booleans stay unset
+        let parsed = values(def.parse_args(&args(&["--target", "dev"]), "b.js").unwrap());
+        assert_eq!(parsed["jobs"], ParamValue::Number(4.0));
+        assert!(!parsed.contains_key("dryRun"));
+
+        let parsed = values(
+            def.parse_args(&args(&["--target=dev", "--no-dry-run"]), "b.js")
+                .unwrap(),
+        );
+        assert_eq!(parsed["dryRun"], ParamValue::Bool(false));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let def = definition();
+        let error = |a: &[&str]| def.parse_args(&args(a), "b.js").unwrap_err().to_string();
+
+        assert!(error(&[]).contains("Missing required argument --target"));
+        assert!(error(&["--target=a", "--jobs=many"]).contains("expected a number"));
+        assert!(error(&["--target=a", "--dry-run=maybe"]).contains("expected true or false"));
+        assert!(error(&["--target=a", "--verbose"]).contains("Unknown argument --verbose"));
+        assert!(error(&["--target"]).contains("Missing value for --target"));
+        assert!(error(&["prod"]).contains("Unexpected argument 'prod'"));
+    }
+
+    #[test]
+    fn test_help() {
+        let def = definition();
+        let ParsedArgs::Help(help) = def
+            .parse_args(&args(&["--jobs=2", "--help"]), "b.js")
+            .unwrap()
+        else {
+            panic!("expected help");
+        };
+
+        assert!(help.starts_with("Build the app\n\nUsage: fabrik run b.js -- [OPTIONS]"));
+        assert!(help.contains("--target <string>  Deployment target [required]"));
+        assert!(help.contains("--jobs <number>    [default: 4]"));
+        assert!(help.contains("--dry-run"));
+        assert!(help.contains("-h, --help"));
+    }
+
+    #[test]
+    fn test_cache_key_values() {
+        let def = definition();
+        let parsed = values(
+            def.parse_args(&args(&["--target=prod", "--dry-run"]), "b.js")
+                .unwrap(),
+        );
+
+        let key = def.cache_key_values(&parsed);
+        assert_eq!(
+            key.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("jobs".to_string(), "4".to_string()),
+                ("target".to_string(), "prod".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_definition() {
+        let mut def = definition();
+        def.params[1].default = Some(ParamValue::String("4".to_string()));
+        assert!(def.validate().is_err());
+
+        let mut def = definition();
+        def.params.push(param("dry_run", ParamType::Bool));
+        assert!(def.validate().is_err());
+
+        assert!(ParamType::parse("int").is_err());
+    }
+}
@@ -0,0 +1,568 @@
+// Permission model for portable recipes
+//
+// Recipes declare the capabilities they need with `//FABRIK` directives:
+//
+//     //FABRIK allow-read "src" "package.json"
+//     //FABRIK allow-write "dist"
+//     //FABRIK allow-exec "npm" "git"
+//     //FABRIK allow-net "registry.npmjs.org"
+//
+// A directive without arguments grants the capability for everything, and `allow-all`
+// grants every capability. The runtime checks each file access and subprocess against
+// the declarations and, depending on the mode, prompts for or refuses undeclared ones.
+
+use anyhow::{anyhow, bail, Result};
+use kdl::KdlDocument;
+use std::fmt;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// Marker of permission directives, after the `//` comment prefix
+const DIRECTIVE_MARKER: &str = "FABRIK";
+
+/// The body of a `//FABRIK` (or `// FABRIK`) comment
+fn directive(line: &str) -> Option<&str> {
+    let body = line.strip_prefix("//")?.trim_start();
+    let rest = body.strip_prefix(DIRECTIVE_MARKER)?;
+    (rest.is_empty() || rest.starts_with(char::is_whitespace)).then_some(rest)
+}
+
+/// A capability a recipe can be granted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Read,
+    Write,
+    Exec,
+    Net,
+}
+
+impl Capability {
+    const ALL: [Capability; 4] = [
+        Capability::Read,
+        Capability::Write,
+        Capability::Exec,
+        Capability::Net,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Read => "read",
+            Capability::Write => "write",
+            Capability::Exec => "exec",
+            Capability::Net => "net",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    /// Whether targets are file system paths (as opposed to commands or hosts)
+    fn is_path(&self) -> bool {
+        matches!(self, Capability::Read | Capability::Write)
+    }
+}
+
+/// What a recipe may do with one capability
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Allowlist {
+    /// Granted for every target
+    any: bool,
+
+    /// Granted targets: path prefixes, commands or hosts
+    entries: Vec<String>,
+}
+
+/// Capabilities declared by a recipe
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Permissions {
+    read: Allowlist,
+    write: Allowlist,
+    exec: Allowlist,
+    net: Allowlist,
+}
+
+impl Permissions {
+    /// Every capability for every target
+    pub fn all() -> Self {
+        let any = Allowlist {
+            any: true,
+            entries: Vec::new(),
+        };
+        Self {
+            read: any.clone(),
+            write: any.clone(),
+            exec: any.clone(),
+            net: any,
+        }
+    }
+
+    /// Parse the `//FABRIK allow-*` directives of a recipe
+    pub fn parse(script: &str) -> Result<Self> {
+        let directives: Vec<&str> = script
+            .lines()
+            .filter_map(|line| directive(line.trim()))
+            .map(str::trim)
+            .collect();
+
+        let doc: KdlDocument = directives
+            .join("\n")
+            .parse()
+            .map_err(|e| anyhow!("Invalid permission directive: {}", e))?;
+
+        let mut permissions = Self::default();
+        for node in doc.nodes() {
+            let name = node.name().value();
+            let Some(capability) = name.strip_prefix("allow-") else {
+                continue;
+            };
+
+            let targets = node
+                .entries()
+                .iter()
+                .filter(|e| e.name().is_none())
+                .map(|e| {
+                    e.value()
+                        .as_string()
+                        .map(str::to_string)
+                        .ok_or_else(|| anyhow!("{} arguments must be strings", name))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            if capability == "all" {
+                if !targets.is_empty() {
+                    bail!("allow-all takes no arguments");
+                }
+                permissions = Self::all();
+                continue;
+            }
+
+            let capability = Capability::from_name(capability).ok_or_else(|| {
+                anyhow!(
+                    "Unknown permission '{}'. Use: allow-read, allow-write, allow-exec, allow-net, allow-all",
+                    name
+                )
+            })?;
+            let allowlist = permissions.allowlist_mut(capability);
+            if targets.is_empty() {
+                allowlist.any = true;
+            } else {
+                allowlist.entries.extend(targets);
+            }
+        }
+
+        Ok(permissions)
+    }
+
+    /// Whether every capability is granted for everything
+    pub fn is_unrestricted(&self) -> bool {
+        Capability::ALL.iter().all(|c| self.allowlist(*c).any)
+    }
+
+    fn allowlist(&self, capability: Capability) -> &Allowlist {
+        match capability {
+            Capability::Read => &self.read,
+            Capability::Write => &self.write,
+            Capability::Exec => &self.exec,
+            Capability::Net => &self.net,
+        }
+    }
+
+    fn allowlist_mut(&mut self, capability: Capability) -> &mut Allowlist {
+        match capability {
+            Capability::Read => &mut self.read,
+            Capability::Write => &mut self.write,
+            Capability::Exec => &mut self.exec,
+            Capability::Net => &mut self.net,
+        }
+    }
+}
+
+/// What happens when a recipe uses a capability it didn't declare
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PermissionMode {
+    /// Ask on the terminal; refuse when not running in one
+    Prompt,
+
+    /// Refuse
+    Deny,
+
+    /// Ignore declarations and allow everything
+    Allow,
+}
+
+impl PermissionMode {
+    /// Parse `--recipe-permissions`
+    pub fn parse(input: &str) -> Result<Self> {
+        match input {
+            "prompt" => Ok(Self::Prompt),
+            "deny" => Ok(Self::Deny),
+            "allow" => Ok(Self::Allow),
+            _ => bail!(
+                "Invalid permission mode '{}'. Use: prompt, deny, allow",
+                input
+            ),
+        }
+    }
+}
+
+/// An undeclared capability the recipe was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionError {
+    pub capability: Capability,
+    pub target: String,
+}
+
+impl fmt::Display for PermissionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Permission denied: {} access to {} (declare it with //FABRIK allow-{} {:?})",
+            self.capability.name(),
+            self.target,
+            self.capability.name(),
+            self.target
+        )
+    }
+}
+
+impl std::error::Error for PermissionError {}
+
+/// Asks the user whether to grant a capability
+type Prompter = Box<dyn Fn(Capability, &str) -> bool + Send + Sync>;
+
+/// Enforces a recipe's permissions while it runs
+pub struct PermissionGuard {
+    /// Directory relative paths are resolved against
+    base_dir: PathBuf,
+
+    mode: PermissionMode,
+
+    /// Declared capabilities plus those granted at a prompt (paths resolved)
+    granted: Mutex<Permissions>,
+
+    prompter: Prompter,
+}
+
+impl PermissionGuard {
+    /// Guard for a recipe in `base_dir` that declared `permissions`
+    pub fn new(permissions: Permissions, base_dir: PathBuf, mode: PermissionMode) -> Self {
+        let mut granted = permissions;
+        for capability in [Capability::Read, Capability::Write] {
+            for entry in &mut granted.allowlist_mut(capability).entries {
+                *entry = resolve(&base_dir, entry).to_string_lossy().to_string();
+            }
+        }
+
+        Self {
+            base_dir,
+            mode,
+            granted: Mutex::new(granted),
+            prompter: Box::new(prompt_on_terminal),
+        }
+    }
+
+    /// Guard that allows everything
+    pub fn allow_all(base_dir: PathBuf) -> Self {
+        Self::new(Permissions::all(), base_dir, PermissionMode::Allow)
+    }
+
+    /// Whether nothing is checked (required for the unrestricted Node.js modules)
+    pub fn is_unrestricted(&self) -> bool {
+        self.mode == PermissionMode::Allow || self.granted.lock().unwrap().is_unrestricted()
+    }
+
+    /// Check a capability by name, as called from JavaScript; the error is the message
+    pub fn check_named(&self, capability: &str, target: &str) -> Result<(), String> {
+        let result = match Capability::from_name(capability) {
+            Some(capability) if capability.is_path() => {
+                self.check_path(capability, Path::new(target))
+            }
+            Some(capability) => self.check(capability, target),
+            None => return Err(format!("Unknown capability '{}'", capability)),
+        };
+        result.map_err(|e| e.to_string())
+    }
+
+    /// Check read or write access to a path or glob pattern
+    ///
+    /// Relative paths are resolved against the recipe directory; for patterns, the
+    /// directory before the first wildcard is checked. A `..` after a wildcard climbs an
+    /// unknown number of levels (`**` matches any depth), so such patterns are checked
+    /// as reaching the filesystem root.
+    pub fn check_path(&self, capability: Capability, path: &Path) -> Result<(), PermissionError> {
+        let base = resolve(&self.base_dir, &glob_base(path).to_string_lossy());
+        let base = match climbs_after_wildcard(path) {
+            true => base
+                .ancestors()
+                .last()
+                .map(Path::to_path_buf)
+                .unwrap_or(base),
+            false => base,
+        };
+        self.check(capability, &base.to_string_lossy())
+    }
+
+    /// Check running `command`
+    pub fn check_exec(&self, command: &str) -> Result<(), PermissionError> {
+        self.check(Capability::Exec, command)
+    }
+
+    /// Check connecting to `host`
+    pub fn check_net(&self, host: &str) -> Result<(), PermissionError> {
+        self.check(Capability::Net, host)
+    }
+
+    fn check(&self, capability: Capability, target: &str) -> Result<(), PermissionError> {
+        if self.mode == PermissionMode::Allow {
+            return Ok(());
+        }
+
+        // Held while prompting so concurrent checks don't interleave prompts
+        let mut granted = self.granted.lock().unwrap();
+        let allowlist = granted.allowlist_mut(capability);
+        let allowed = allowlist.any
+            || allowlist.entries.iter().any(|entry| {
+                if capability.is_path() {
+                    Path::new(target).starts_with(entry)
+                } else {
+                    entry == target
+                }
+            });
+        if allowed {
+            return Ok(());
+        }
+
+        if self.mode == PermissionMode::Prompt && (self.prompter)(capability, target) {
+            allowlist.entries.push(target.to_string());
+            return Ok(());
+        }
+
+        Err(PermissionError {
+            capability,
+            target: target.to_string(),
+        })
+    }
+}
+
+/// Ask on stderr/stdin; refuse without a terminal
+fn prompt_on_terminal(capability: Capability, target: &str) -> bool {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return false;
+    }
+
+    eprint!(
+        "{} Recipe requests {} access to {}. Allow? [y/N] ",
+        crate::cli_utils::fabrik_prefix(),
+        capability.name(),
+        target
+    );
+    let _ = std::io::stderr().flush();
+
+    let mut answer = String::new();
+    if stdin.lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Absolute, lexically normalized `path` (relative paths are joined to `base`)
+fn resolve(base: &Path, path: &str) -> PathBuf {
+    let path = Path::new(path);
+    let joined = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base.join(path)
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    normalized
+}
+
+/// Leading components of a glob pattern that contain no wildcard
+fn glob_base(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|c| !is_wildcard(c))
+        .collect()
+}
+
+/// Whether a glob pattern has a `..` at or after its first wildcard
+fn climbs_after_wildcard(pattern: &Path) -> bool {
+    pattern
+        .components()
+        .skip_while(|c| !is_wildcard(c))
+        .any(|c| c == Component::ParentDir)
+}
+
+fn is_wildcard(component: &Component) -> bool {
+    component
+        .as_os_str()
+        .to_string_lossy()
+        .contains(['*', '?', '[', '{'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECIPE: &str = r#"
+//FABRIK allow-read "src" "package.json"
+//FABRIK allow-write "dist"
+//FABRIK allow-exec "npm"
+await Fabrik.exec("npm", ["run", "build"]);
+"#;
+
+    fn guard(script: &str, mode: PermissionMode) -> PermissionGuard {
+        PermissionGuard::new(
+            Permissions::parse(script).unwrap(),
+            PathBuf::from("/work/recipe"),
+            mode,
+        )
+    }
+
+    #[test]
+    fn test_parse_directives() {
+        let permissions = Permissions::parse(RECIPE).unwrap();
+        assert_eq!(permissions.read.entries, vec!["src", "package.json"]);
+        assert_eq!(permissions.write.entries, vec!["dist"]);
+        assert_eq!(permissions.exec.entries, vec!["npm"]);
+        assert_eq!(permissions.net, Allowlist::default());
+        assert!(!permissions.is_unrestricted());
+
+        let permissions = Permissions::parse("//FABRIK allow-net\n").unwrap();
+        assert!(permissions.net.any);
+
+        let permissions = Permissions::parse("// FABRIK allow-exec \"git\"\n").unwrap();
+        assert_eq!(permissions.exec.entries, vec!["git"]);
+        assert!(!Permissions::parse("//FABRIKallow-all\n")
+            .unwrap()
+            .is_unrestricted());
+
+        assert!(Permissions::parse("//FABRIK allow-all\n")
+            .unwrap()
+            .is_unrestricted());
+        assert!(Permissions::parse("//FABRIK allow-everything\n").is_err());
+        assert!(Permissions::parse("//FABRIK allow-all \"src\"\n").is_err());
+    }
+
+    #[test]
+    fn test_declared_paths_are_allowed() {
+        let guard = guard(RECIPE, PermissionMode::Deny);
+
+        assert!(guard
+            .check_path(Capability::Read, Path::new("src/main.ts"))
+            .is_ok());
+        assert!(guard
+            .check_path(Capability::Read, Path::new("/work/recipe/package.json"))
+            .is_ok());
+        assert!(guard
+            .check_path(Capability::Read, Path::new("src/**/*.ts"))
+            .is_ok());
+        assert!(guard
+            .check_path(Capability::Write, Path::new("dist/app.js"))
+            .is_ok());
+
+        // Writing isn't reading, and prefixes match whole components
+        assert!(guard
+            .check_path(Capability::Write, Path::new("src/main.ts"))
+            .is_err());
+        assert!(guard
+            .check_path(Capability::Read, Path::new("src-private/key"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_paths_cannot_escape() {
+        let guard = guard(RECIPE, PermissionMode::Deny);
+
+        let err = guard
+            .check_path(Capability::Read, Path::new("src/../../../etc/passwd"))
+            .unwrap_err();
+        assert_eq!(err.target, "/etc/passwd");
+        assert!(err.to_string().contains("allow-read \"/etc/passwd\""));
+
+        assert!(guard
+            .check_path(Capability::Read, Path::new("**/id_rsa"))
+            .is_err());
+
+        // `..` after a wildcard can't climb out of the allowed directory
+        let err = guard
+            .check_path(Capability::Read, Path::new("src/*/../../../etc/*"))
+            .unwrap_err();
+        assert_eq!(err.target, "/");
+        assert!(guard
+            .check_path(Capability::Read, Path::new("src/**/.."))
+            .is_err());
+        assert!(guard
+            .check_path(Capability::Read, Path::new("src/../src/*.ts"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_exec_allowlist() {
+        let guard = guard(RECIPE, PermissionMode::Deny);
+        assert!(guard.check_exec("npm").is_ok());
+        assert!(guard.check_exec("/tmp/npm").is_err());
+        assert!(guard.check_exec("curl").is_err());
+        assert!(guard.check_net("example.com").is_err());
+        assert!(guard.check_named("exec", "npm").is_ok());
+        assert!(guard.check_named("read", "src/index.ts").is_ok());
+        assert!(guard.check_named("admin", "npm").is_err());
+    }
+
+    #[test]
+    fn test_prompt_grants_are_remembered() {
+        let mut guard = guard(RECIPE, PermissionMode::Prompt);
+        let asked = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = asked.clone();
+        guard.prompter = Box::new(move |capability, _| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            capability == Capability::Exec
+        });
+
+        assert!(guard.check_exec("git").is_ok());
+        assert!(guard.check_exec("git").is_ok());
+        assert!(guard.check_exec("npm").is_ok());
+        assert_eq!(asked.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        assert!(guard.check_net("example.com").is_err());
+        assert_eq!(asked.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_allow_mode_and_unrestricted() {
+        let guard = guard(RECIPE, PermissionMode::Allow);
+        assert!(guard.is_unrestricted());
+        assert!(guard.check_exec("curl").is_ok());
+
+        assert!(!self::guard(RECIPE, PermissionMode::Deny).is_unrestricted());
+        assert!(self::guard("//FABRIK allow-all\n", PermissionMode::Deny).is_unrestricted());
+        assert!(PermissionGuard::allow_all(PathBuf::from("/work")).is_unrestricted());
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(
+            PermissionMode::parse("prompt").unwrap(),
+            PermissionMode::Prompt
+        );
+        assert_eq!(PermissionMode::parse("deny").unwrap(), PermissionMode::Deny);
+        assert_eq!(
+            PermissionMode::parse("allow").unwrap(),
+            PermissionMode::Allow
+        );
+        assert!(PermissionMode::parse("yes").is_err());
+    }
+}
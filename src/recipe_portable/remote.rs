@@ -3,7 +3,8 @@
 // Handles `@org/repo/path/script.js@ref` syntax for remote recipes
 
 use anyhow::{anyhow, Result};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Parsed remote recipe reference
 #[derive(Debug, Clone, PartialEq)]
@@ -106,11 +107,28 @@ impl RemoteRecipe {
         Ok(self.cache_dir()?.join(&self.path))
     }
 
+    /// Git URLs to fetch from: the host itself, then any mirrors configured for it
+    pub fn git_urls(&self, mirrors: &[Mirror]) -> Vec<String> {
+        let mut urls = vec![self.git_url()];
+        urls.extend(
+            mirrors
+                .iter()
+                .filter(|mirror| mirror.host == self.host)
+                .map(|mirror| mirror.git_url(&self.org, &self.repo)),
+        );
+        urls
+    }
+
     /// Fetch the remote recipe to local cache
     ///
-    /// Uses `git clone --depth 1` for efficient fetching.
-    /// If already cached, skips fetch.
-    pub async fn fetch(&self) -> Result<PathBuf> {
+    /// Uses a shallow `git fetch` of the ref into the cache directory. If already cached,
+    /// skips fetch. Failed fetches fall back to the configured mirrors and are retried
+    /// with exponential backoff; `on_event` is told about each failover and retry. A
+    /// repository left behind by an interrupted fetch is reused rather than recloned.
+    pub async fn fetch<F>(&self, options: &FetchOptions, mut on_event: F) -> Result<PathBuf>
+    where
+        F: FnMut(&FetchEvent),
+    {
         let cache_dir = self.cache_dir()?;
         let script_path = self.script_path()?;
 
@@ -120,51 +138,238 @@ impl RemoteRecipe {
             return Ok(script_path);
         }
 
-        tracing::info!(
-            "Fetching remote recipe: {} from {}",
-            self.path,
-            self.git_url()
-        );
+        let urls = self.git_urls(&options.mirrors);
+        let attempts = options.retries + 1;
+        let mut last_error = String::new();
+
+        for attempt in 1..=attempts {
+            let mut retryable = false;
+
+            for (index, url) in urls.iter().enumerate() {
+                tracing::info!("Fetching remote recipe: {} from {}", self.path, url);
+
+                match self.fetch_from(url, &cache_dir).await {
+                    Ok(()) => {
+                        // Verify script exists
+                        if !script_path.exists() {
+                            return Err(anyhow!("Script not found at {} in repository", self.path));
+                        }
+
+                        tracing::info!("Remote recipe fetched successfully");
+                        return Ok(script_path);
+                    }
+                    Err(failure) => {
+                        retryable |= failure.transient;
+                        last_error = failure.message;
+                    }
+                }
+
+                if let Some(next_url) = urls.get(index + 1) {
+                    on_event(&FetchEvent::Failover {
+                        url,
+                        next_url,
+                        error: &last_error,
+                    });
+                }
+            }
+
+            if !retryable || attempt == attempts {
+                break;
+            }
+
+            let delay = options.backoff(attempt);
+            on_event(&FetchEvent::Retry {
+                attempt,
+                attempts,
+                delay,
+                error: &last_error,
+            });
+            tokio::time::sleep(delay).await;
+        }
+
+        Err(anyhow!(
+            "Failed to fetch repository {}: {}",
+            self.git_url(),
+            last_error
+        ))
+    }
 
-        // Create cache directory
-        tokio::fs::create_dir_all(&cache_dir).await?;
+    /// Fetch the ref from `url` into `cache_dir` and check it out
+    async fn fetch_from(&self, url: &str, cache_dir: &Path) -> Result<(), FetchFailure> {
+        // Objects from an interrupted fetch are kept in `.git` and reused; anything else
+        // in the directory is a leftover of unknown state
+        if !cache_dir.join(".git").is_dir() {
+            if cache_dir.exists() {
+                tokio::fs::remove_dir_all(cache_dir)
+                    .await
+                    .map_err(FetchFailure::io)?;
+            }
+            tokio::fs::create_dir_all(cache_dir)
+                .await
+                .map_err(FetchFailure::io)?;
+            git(cache_dir, &["init", "--quiet"]).await?;
+        }
 
-        // Clone repository with shallow clone
         let git_ref = self.git_ref.as_deref().unwrap_or("main");
-        let output = tokio::process::Command::new("git")
-            .args([
-                "clone",
+        git(
+            cache_dir,
+            &[
+                "fetch",
+                "--quiet",
                 "--depth",
                 "1",
-                "--branch",
+                "--no-tags",
+                url,
                 git_ref,
-                "--single-branch",
-                &self.git_url(),
-                cache_dir
-                    .to_str()
-                    .ok_or_else(|| anyhow!("Invalid cache directory path"))?,
-            ])
-            .output()
-            .await?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow!(
-                "Failed to clone repository {}: {}",
-                self.git_url(),
-                stderr
-            ));
+            ],
+        )
+        .await?;
+        git(cache_dir, &["checkout", "--quiet", "--force", "FETCH_HEAD"]).await
+    }
+}
+
+/// Alternate base URL to fetch a host's repositories from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mirror {
+    /// Host being mirrored (e.g., "github.com")
+    pub host: String,
+
+    /// Base URL that `{org}/{repo}.git` is appended to
+    pub url: String,
+}
+
+impl Mirror {
+    /// Parse a `host=url` mirror specification
+    ///
+    /// Example: `github.com=https://git-mirror.company.com/github`
+    pub fn parse(input: &str) -> Result<Self> {
+        let (host, url) = input
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Mirror must be in host=url format: {}", input))?;
+        let (host, url) = (host.trim(), url.trim().trim_end_matches('/'));
+
+        if host.is_empty() || url.is_empty() {
+            return Err(anyhow!("Mirror must be in host=url format: {}", input));
         }
 
-        // Verify script exists
-        if !script_path.exists() {
-            return Err(anyhow!("Script not found at {} in repository", self.path));
+        Ok(Mirror {
+            host: host.to_string(),
+            url: url.to_string(),
+        })
+    }
+
+    /// Git URL of `org/repo` on this mirror
+    pub fn git_url(&self, org: &str, repo: &str) -> String {
+        format!("{}/{}/{}.git", self.url, org, repo)
+    }
+}
+
+/// How remote recipes are fetched
+#[derive(Debug, Clone)]
+pub struct FetchOptions {
+    /// Number of times to retry after every source failed
+    pub retries: u32,
+
+    /// Delay before the first retry; doubled for each further retry
+    pub initial_backoff: Duration,
+
+    /// Mirrors to fall back to when the host fails
+    pub mirrors: Vec<Mirror>,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            initial_backoff: Duration::from_secs(1),
+            mirrors: Vec::new(),
         }
+    }
+}
+
+impl FetchOptions {
+    /// Maximum delay between retries
+    const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+    /// Delay after the given (1-based) failed attempt
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(Self::MAX_BACKOFF)
+    }
+}
+
+/// Progress of a fetch that ran into errors
+#[derive(Debug)]
+pub enum FetchEvent<'a> {
+    /// Fetching from `url` failed and `next_url` is tried next
+    Failover {
+        url: &'a str,
+        next_url: &'a str,
+        error: &'a str,
+    },
+
+    /// Every source failed; attempt `attempt + 1` of `attempts` starts after `delay`
+    Retry {
+        attempt: u32,
+        attempts: u32,
+        delay: Duration,
+        error: &'a str,
+    },
+}
+
+/// A failed git invocation
+#[derive(Debug)]
+struct FetchFailure {
+    message: String,
 
-        tracing::info!("Remote recipe fetched successfully");
+    /// Whether trying again may succeed (network errors, as opposed to a missing ref)
+    transient: bool,
+}
+
+impl FetchFailure {
+    fn io(err: std::io::Error) -> Self {
+        Self {
+            message: err.to_string(),
+            transient: false,
+        }
+    }
+}
+
+/// Git errors that retrying won't fix
+const PERMANENT_GIT_ERRORS: &[&str] = &[
+    "couldn't find remote ref",
+    "not our ref",
+    "Repository not found",
+];
+
+fn is_transient_git_error(stderr: &str) -> bool {
+    !PERMANENT_GIT_ERRORS
+        .iter()
+        .any(|pattern| stderr.contains(pattern))
+}
 
-        Ok(script_path)
+/// Run git in `dir` without prompting for credentials
+async fn git(dir: &Path, args: &[&str]) -> Result<(), FetchFailure> {
+    let output = tokio::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .await
+        .map_err(FetchFailure::io)?;
+
+    if output.status.success() {
+        return Ok(());
     }
+
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(FetchFailure {
+        transient: is_transient_git_error(&stderr),
+        message: stderr,
+    })
 }
 
 #[cfg(test)]
@@ -247,4 +452,62 @@ mod tests {
         assert!(cache_dir_str.contains("tuist"));
         assert!(cache_dir_str.contains("v1.0.0"));
     }
+
+    #[test]
+    fn test_mirror_parse() {
+        let mirror = Mirror::parse("github.com=https://git-mirror.company.com/github/").unwrap();
+        assert_eq!(mirror.host, "github.com");
+        assert_eq!(mirror.url, "https://git-mirror.company.com/github");
+
+        assert!(Mirror::parse("github.com").is_err());
+        assert!(Mirror::parse("=https://git-mirror.company.com").is_err());
+        assert!(Mirror::parse("github.com=").is_err());
+    }
+
+    #[test]
+    fn test_git_urls_with_mirrors() {
+        let recipe = RemoteRecipe::parse("@tuist/recipes/build.js").unwrap();
+        let mirrors = vec![
+            Mirror::parse("gitlab.com=https://gitlab-mirror.company.com").unwrap(),
+            Mirror::parse("github.com=https://git-mirror.company.com/github").unwrap(),
+        ];
+
+        assert_eq!(
+            recipe.git_urls(&mirrors),
+            vec![
+                "https://github.com/tuist/recipes.git",
+                "https://git-mirror.company.com/github/tuist/recipes.git",
+            ]
+        );
+        assert_eq!(
+            recipe.git_urls(&[]),
+            vec!["https://github.com/tuist/recipes.git"]
+        );
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let options = FetchOptions::default();
+        assert_eq!(options.backoff(1), Duration::from_secs(1));
+        assert_eq!(options.backoff(2), Duration::from_secs(2));
+        assert_eq!(options.backoff(3), Duration::from_secs(4));
+        assert_eq!(options.backoff(10), Duration::from_secs(30));
+        assert_eq!(options.backoff(u32::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_transient_git_errors() {
+        assert!(is_transient_git_error(
+            "fatal: unable to access 'https://github.com/tuist/recipes.git/': Could not resolve host: github.com"
+        ));
+        assert!(is_transient_git_error(
+            "error: RPC failed; curl 56 GnuTLS recv error (-9)"
+        ));
+        assert!(!is_transient_git_error(
+            "fatal: couldn't find remote ref v9.9.9"
+        ));
+        assert!(!is_transient_git_error(
+            "remote: Repository not found.\nfatal: repository 'https://github.com/tuist/nope.git/' not found"
+        ));
+    }
 }
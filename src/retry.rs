@@ -0,0 +1,362 @@
+//! Retries of failed requests to upstreams (`[retry]`)
+//!
+//! Shared by the Fabrik protocol client (cluster peers and replication targets), the S3
+//! client of backups and the HTTP upstreams of `fabrik run` and `fabrik cache warm`.
+//! Failed attempts are retried with exponential backoff and full jitter: the wait before
+//! retry `n` is a random duration up to `backoff_base * 2^(n-1)`, capped at
+//! `backoff_cap`, so clients failing together don't retry together.
+
+use anyhow::{Context, Result};
+use rand::Rng;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::config::RetryConfig;
+use crate::eviction::EvictionConfig;
+
+/// Kind of upstream a request goes to (the `protocol` label of the metrics)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Fabrik,
+    S3,
+    Http,
+}
+
+impl Protocol {
+    const ALL: [Protocol; 3] = [Protocol::Fabrik, Protocol::S3, Protocol::Http];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Fabrik => "fabrik",
+            Protocol::S3 => "s3",
+            Protocol::Http => "http",
+        }
+    }
+}
+
+/// Retry counters per protocol
+#[derive(Debug, Default)]
+pub struct RetryMetrics {
+    retries: [AtomicU64; 3],
+    exhausted: [AtomicU64; 3],
+}
+
+impl RetryMetrics {
+    /// Failed attempts that were retried
+    pub fn retries(&self, protocol: Protocol) -> u64 {
+        self.retries[protocol as usize].load(Ordering::Relaxed)
+    }
+
+    /// Requests that still failed after their last attempt
+    pub fn exhausted(&self, protocol: Protocol) -> u64 {
+        self.exhausted[protocol as usize].load(Ordering::Relaxed)
+    }
+
+    /// Export metrics in Prometheus format
+    pub fn export_prometheus(&self) -> String {
+        let mut output = String::new();
+        let mut metric = |name: &str, help: &str, value: &dyn Fn(Protocol) -> u64| {
+            output.push_str(&format!("# HELP {} {}\n", name, help));
+            output.push_str(&format!("# TYPE {} counter\n", name));
+            for protocol in Protocol::ALL {
+                output.push_str(&format!(
+                    "{}{{protocol=\"{}\"}} {}\n",
+                    name,
+                    protocol.as_str(),
+                    value(protocol)
+                ));
+            }
+        };
+        metric(
+            "fabrik_upstream_retries_total",
+            "Failed upstream requests that were retried",
+            &|p| self.retries(p),
+        );
+        metric(
+            "fabrik_upstream_retries_exhausted_total",
+            "Upstream requests that failed on their last attempt",
+            &|p| self.exhausted(p),
+        );
+        output
+    }
+}
+
+/// When and how often failed requests are retried; clones share their metrics
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff_base: Duration,
+    backoff_cap: Duration,
+    grpc_codes: Vec<tonic::Code>,
+    http_statuses: Vec<u16>,
+    metrics: Arc<RetryMetrics>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::from_config(&RetryConfig::default()).expect("default retry config is valid")
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &RetryConfig) -> Result<Self> {
+        anyhow::ensure!(
+            config.max_attempts >= 1,
+            "retry.max_attempts must be at least 1"
+        );
+        let backoff_base =
+            parse_duration(&config.backoff_base).context("Invalid retry.backoff_base")?;
+        let backoff_cap =
+            parse_duration(&config.backoff_cap).context("Invalid retry.backoff_cap")?;
+        let grpc_codes = config
+            .grpc_codes
+            .iter()
+            .map(|code| {
+                parse_grpc_code(code)
+                    .with_context(|| format!("retry.grpc_codes: unknown gRPC code '{}'", code))
+            })
+            .collect::<Result<_>>()?;
+        if let Some(status) = config
+            .http_statuses
+            .iter()
+            .find(|status| !(100..600).contains(*status))
+        {
+            anyhow::bail!("retry.http_statuses: invalid HTTP status {}", status);
+        }
+
+        Ok(Self {
+            max_attempts: config.max_attempts,
+            backoff_base,
+            backoff_cap,
+            grpc_codes,
+            http_statuses: config.http_statuses.clone(),
+            metrics: Arc::new(RetryMetrics::default()),
+        })
+    }
+
+    pub fn metrics(&self) -> Arc<RetryMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Longest wait before retry `retry` (1 = first retry)
+    fn max_backoff(&self, retry: u32) -> Duration {
+        self.backoff_base
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.backoff_cap)
+    }
+
+    /// Random wait before retry `retry`, up to `max_backoff`
+    fn backoff(&self, retry: u32) -> Duration {
+        let max = self.max_backoff(retry);
+        if max.is_zero() {
+            return max;
+        }
+        max.mul_f64(rand::rng().random_range(0.0..=1.0))
+    }
+
+    /// Run a gRPC call, retrying the configured status codes
+    pub async fn grpc<T, F, Fut>(&self, protocol: Protocol, call: F) -> Result<T, tonic::Status>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, tonic::Status>>,
+    {
+        self.run(
+            protocol,
+            call,
+            |result| matches!(result, Err(status) if self.grpc_codes.contains(&status.code())),
+        )
+        .await
+    }
+
+    /// Send an HTTP request, retrying connection failures, timeouts and the configured
+    /// status codes
+    pub async fn http<F, Fut>(
+        &self,
+        protocol: Protocol,
+        send: F,
+    ) -> reqwest::Result<reqwest::Response>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = reqwest::Result<reqwest::Response>>,
+    {
+        self.run(protocol, send, |result| match result {
+            Ok(response) => self.http_statuses.contains(&response.status().as_u16()),
+            Err(e) => e.is_connect() || e.is_timeout(),
+        })
+        .await
+    }
+
+    async fn run<T, F, Fut>(
+        &self,
+        protocol: Protocol,
+        mut attempt: F,
+        retryable: impl Fn(&T) -> bool,
+    ) -> T
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let mut attempts = 1;
+        loop {
+            let result = attempt().await;
+            if !retryable(&result) {
+                return result;
+            }
+            if attempts >= self.max_attempts {
+                self.metrics.exhausted[protocol as usize].fetch_add(1, Ordering::Relaxed);
+                return result;
+            }
+
+            let delay = self.backoff(attempts);
+            debug!(
+                "{} upstream request failed (attempt {}/{}), retrying in {:?}",
+                protocol.as_str(),
+                attempts,
+                self.max_attempts,
+                delay
+            );
+            self.metrics.retries[protocol as usize].fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(delay).await;
+            attempts += 1;
+        }
+    }
+}
+
+/// Duration in milliseconds ("100ms") or as a TTL ("2s", "1m")
+fn parse_duration(value: &str) -> Result<Duration> {
+    match value.trim().strip_suffix("ms") {
+        Some(millis) => Ok(Duration::from_millis(
+            millis.trim().parse().context("Invalid milliseconds")?,
+        )),
+        None => EvictionConfig::parse_ttl(value).map(Duration::from_secs),
+    }
+}
+
+/// gRPC status code by name ("unavailable", "DEADLINE_EXCEEDED", "ResourceExhausted")
+fn parse_grpc_code(name: &str) -> Option<tonic::Code> {
+    let name: String = name
+        .chars()
+        .filter(|c| *c != '_' && *c != '-')
+        .collect::<String>()
+        .to_lowercase();
+    let code = match name.as_str() {
+        "cancelled" => tonic::Code::Cancelled,
+        "unknown" => tonic::Code::Unknown,
+        "invalidargument" => tonic::Code::InvalidArgument,
+        "deadlineexceeded" => tonic::Code::DeadlineExceeded,
+        "notfound" => tonic::Code::NotFound,
+        "alreadyexists" => tonic::Code::AlreadyExists,
+        "permissiondenied" => tonic::Code::PermissionDenied,
+        "resourceexhausted" => tonic::Code::ResourceExhausted,
+        "failedprecondition" => tonic::Code::FailedPrecondition,
+        "aborted" => tonic::Code::Aborted,
+        "outofrange" => tonic::Code::OutOfRange,
+        "unimplemented" => tonic::Code::Unimplemented,
+        "internal" => tonic::Code::Internal,
+        "unavailable" => tonic::Code::Unavailable,
+        "dataloss" => tonic::Code::DataLoss,
+        "unauthenticated" => tonic::Code::Unauthenticated,
+        _ => return None,
+    };
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    #[test]
+    fn test_from_config() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.max_attempts, 3);
+        assert_eq!(policy.backoff_base, Duration::from_millis(100));
+        assert_eq!(policy.max_backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.max_backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.max_backoff(20), Duration::from_secs(5));
+        assert!(policy.backoff(2) <= Duration::from_millis(200));
+        assert!(policy.grpc_codes.contains(&tonic::Code::Unavailable));
+
+        let config = RetryConfig {
+            grpc_codes: vec!["RESOURCE_EXHAUSTED".to_string(), "data-loss".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            RetryPolicy::from_config(&config).unwrap().grpc_codes,
+            vec![tonic::Code::ResourceExhausted, tonic::Code::DataLoss]
+        );
+
+        for invalid in [
+            RetryConfig {
+                max_attempts: 0,
+                ..Default::default()
+            },
+            RetryConfig {
+                backoff_base: "soon".to_string(),
+                ..Default::default()
+            },
+            RetryConfig {
+                grpc_codes: vec!["flaky".to_string()],
+                ..Default::default()
+            },
+            RetryConfig {
+                http_statuses: vec![42],
+                ..Default::default()
+            },
+        ] {
+            assert!(RetryPolicy::from_config(&invalid).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_configured_codes() {
+        let policy = RetryPolicy::from_config(&RetryConfig {
+            backoff_base: "1ms".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Succeeds on the last attempt
+        let calls = AtomicU32::new(0);
+        let result = policy
+            .grpc(Protocol::Fabrik, || async {
+                match calls.fetch_add(1, Ordering::Relaxed) {
+                    0 | 1 => Err(tonic::Status::unavailable("down")),
+                    _ => Ok("found"),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), "found");
+        assert_eq!(policy.metrics().retries(Protocol::Fabrik), 2);
+
+        // Not retried
+        let calls = AtomicU32::new(0);
+        let result: Result<(), _> = policy
+            .grpc(Protocol::Fabrik, || async {
+                calls.fetch_add(1, Ordering::Relaxed);
+                Err(tonic::Status::permission_denied("no"))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        // Gives up after max_attempts
+        let calls = AtomicU32::new(0);
+        let result: Result<(), _> = policy
+            .grpc(Protocol::Fabrik, || async {
+                calls.fetch_add(1, Ordering::Relaxed);
+                Err(tonic::Status::unavailable("down"))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert_eq!(policy.metrics().exhausted(Protocol::Fabrik), 1);
+        assert!(policy
+            .metrics()
+            .export_prometheus()
+            .contains("fabrik_upstream_retries_total{protocol=\"fabrik\"} 4"));
+    }
+}
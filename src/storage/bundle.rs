@@ -0,0 +1,328 @@
+/// Cache bundles for moving blobs between caches without a network
+///
+/// `fabrik cas export` writes blobs into a single bundle file, and `fabrik cas import`
+/// stores them into another cache, e.g. to seed a cache on an air-gapped build network.
+///
+/// A bundle is a zstd-compressed tar archive:
+///
+/// - `fabrik-bundle.json`: the format version and export time
+/// - for each blob, `blobs/<hex ID>.json` (size, SHA256 and origin tags) followed by
+///   `blobs/<hex ID>` (the blob itself)
+///
+/// Blob IDs are hex-encoded in entry names, so any ID round-trips. Imports check each
+/// blob against its size and SHA256 before storing it, and skip blobs the cache already
+/// has.
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{EntryTags, Storage};
+use crate::error::{FabrikError, Result, ResultExt};
+
+/// Version of the bundle format written by `export`
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// First entry of a bundle
+const HEADER_ENTRY: &str = "fabrik-bundle.json";
+
+/// Directory of the blob entries
+const BLOBS_DIR: &str = "blobs/";
+
+/// zstd compression level of bundles
+const COMPRESSION_LEVEL: i32 = 3;
+
+#[derive(Serialize, Deserialize)]
+struct BundleHeader {
+    version: u32,
+    /// Export time (Unix seconds)
+    created_at: i64,
+}
+
+/// Metadata entry preceding each blob
+#[derive(Serialize, Deserialize)]
+struct BlobEntry {
+    size: u64,
+    sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tags: Option<EntryTags>,
+}
+
+/// What an export wrote
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportStats {
+    pub blobs: u64,
+    pub bytes: u64,
+    /// Requested blobs that weren't in the cache (e.g. evicted since they were listed)
+    pub missing: u64,
+}
+
+/// What an import stored
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportStats {
+    pub imported: u64,
+    pub bytes: u64,
+    /// Blobs the cache already had
+    pub skipped: u64,
+}
+
+/// Write the blobs `ids` of `storage` as a bundle
+pub fn export<W: Write>(storage: &dyn Storage, ids: &[Vec<u8>], writer: W) -> Result<ExportStats> {
+    let encoder = zstd::Encoder::new(writer, COMPRESSION_LEVEL)
+        .io_context("Failed to start bundle compression")?;
+    let mut builder = tar::Builder::new(encoder);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0);
+
+    let header = BundleHeader {
+        version: BUNDLE_VERSION,
+        created_at: now,
+    };
+    append(&mut builder, HEADER_ENTRY, &to_json(&header)?, now)?;
+
+    let mut stats = ExportStats::default();
+    for id in ids {
+        let Some(data) = storage.get(id)? else {
+            stats.missing += 1;
+            continue;
+        };
+        let entry = BlobEntry {
+            size: data.len() as u64,
+            sha256: hex::encode(Sha256::digest(&data)),
+            tags: storage.get_tags(id)?,
+        };
+        let name = format!("{}{}", BLOBS_DIR, hex::encode(id));
+        append(
+            &mut builder,
+            &format!("{}.json", name),
+            &to_json(&entry)?,
+            now,
+        )?;
+        append(&mut builder, &name, &data, now)?;
+        stats.blobs += 1;
+        stats.bytes += entry.size;
+    }
+
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .and_then(|mut writer| writer.flush())
+        .io_context("Failed to finish bundle")?;
+    Ok(stats)
+}
+
+/// Store the blobs of a bundle into `storage`
+///
+/// Fails on the first blob that doesn't match its metadata; blobs stored before it stay
+/// in the cache.
+pub fn import<R: Read>(storage: &dyn Storage, reader: R) -> Result<ImportStats> {
+    let decoder = zstd::Decoder::new(reader).io_context("Failed to start bundle decompression")?;
+    let mut archive = tar::Archive::new(decoder);
+    let mut entries = archive.entries().io_context("Failed to read bundle")?;
+
+    let mut header = entries
+        .next()
+        .ok_or_else(|| FabrikError::corrupt("Empty bundle"))?
+        .io_context("Failed to read bundle")?;
+    if entry_name(&header)? != HEADER_ENTRY {
+        return Err(FabrikError::corrupt("Not a Fabrik bundle"));
+    }
+    let header: BundleHeader = from_json(&read_data(&mut header)?)?;
+    if header.version != BUNDLE_VERSION {
+        return Err(FabrikError::corrupt(format!(
+            "Unsupported bundle version {} (expected {})",
+            header.version, BUNDLE_VERSION
+        )));
+    }
+
+    let mut stats = ImportStats::default();
+    let mut pending: Option<(String, BlobEntry)> = None;
+    for entry in entries {
+        let mut entry = entry.io_context("Failed to read bundle")?;
+        let name = entry_name(&entry)?;
+        let Some(name) = name.strip_prefix(BLOBS_DIR) else {
+            return Err(FabrikError::corrupt(format!(
+                "Unexpected bundle entry: {}",
+                name
+            )));
+        };
+
+        if let Some(hex_id) = name.strip_suffix(".json") {
+            if let Some((previous, _)) = pending {
+                return Err(FabrikError::corrupt(format!(
+                    "Bundle is missing blob {}",
+                    previous
+                )));
+            }
+            pending = Some((hex_id.to_string(), from_json(&read_data(&mut entry)?)?));
+            continue;
+        }
+
+        let metadata = match pending.take() {
+            Some((hex_id, metadata)) if hex_id == name => metadata,
+            _ => {
+                return Err(FabrikError::corrupt(format!(
+                    "Bundle blob {} has no metadata",
+                    name
+                )))
+            }
+        };
+        let id = hex::decode(name)
+            .map_err(|_| FabrikError::corrupt(format!("Invalid blob ID in bundle: {}", name)))?;
+        if storage.exists(&id)? {
+            stats.skipped += 1;
+            continue;
+        }
+
+        let data = read_data(&mut entry)?;
+        if data.len() as u64 != metadata.size
+            || hex::encode(Sha256::digest(&data)) != metadata.sha256
+        {
+            return Err(FabrikError::corrupt(format!(
+                "Bundle blob {} doesn't match its checksum",
+                name
+            )));
+        }
+        storage.put(&id, &data)?;
+        if let Some(tags) = &metadata.tags {
+            storage.put_tags(&id, tags)?;
+        }
+        stats.imported += 1;
+        stats.bytes += metadata.size;
+    }
+
+    if let Some((hex_id, _)) = pending {
+        return Err(FabrikError::corrupt(format!(
+            "Bundle is missing blob {}",
+            hex_id
+        )));
+    }
+    Ok(stats)
+}
+
+fn append<W: Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    data: &[u8],
+    mtime: i64,
+) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime.max(0) as u64);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, name, data)
+        .io_context("Failed to write bundle")
+}
+
+fn entry_name<R: Read>(entry: &tar::Entry<R>) -> Result<String> {
+    let path = entry.path().io_context("Failed to read bundle")?;
+    path.to_str()
+        .map(str::to_string)
+        .ok_or_else(|| FabrikError::corrupt("Invalid bundle entry name"))
+}
+
+fn read_data<R: Read>(entry: &mut tar::Entry<R>) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(entry.size() as usize);
+    entry
+        .read_to_end(&mut data)
+        .io_context("Failed to read bundle")?;
+    Ok(data)
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    serde_json::to_vec(value)
+        .map_err(|e| FabrikError::corrupt(format!("Failed to encode bundle metadata: {}", e)))
+}
+
+fn from_json<T: for<'de> Deserialize<'de>>(bytes: &[u8]) -> Result<T> {
+    serde_json::from_slice(bytes)
+        .map_err(|e| FabrikError::corrupt(format!("Invalid bundle metadata: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FilesystemStorage;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let source_dir = TempDir::new().unwrap();
+        let source = FilesystemStorage::new(source_dir.path()).unwrap();
+        source.put(b"abc", b"first blob").unwrap();
+        source.put(b"cas:def:4", b"next").unwrap();
+        let tags = EntryTags {
+            build_system: Some("gradle".to_string()),
+            machine: None,
+            origin: None,
+        };
+        source.put_tags(b"abc", &tags).unwrap();
+
+        let ids = vec![b"abc".to_vec(), b"cas:def:4".to_vec(), b"gone".to_vec()];
+        let mut bundle = Vec::new();
+        let exported = export(&source, &ids, &mut bundle).unwrap();
+        assert_eq!(
+            exported,
+            ExportStats {
+                blobs: 2,
+                bytes: 14,
+                missing: 1
+            }
+        );
+
+        let target_dir = TempDir::new().unwrap();
+        let target = FilesystemStorage::new(target_dir.path()).unwrap();
+        target.put(b"cas:def:4", b"next").unwrap();
+        let imported = import(&target, bundle.as_slice()).unwrap();
+        assert_eq!(
+            imported,
+            ImportStats {
+                imported: 1,
+                bytes: 10,
+                skipped: 1
+            }
+        );
+        assert_eq!(target.get(b"abc").unwrap().unwrap(), b"first blob");
+        assert_eq!(target.get_tags(b"abc").unwrap(), Some(tags));
+    }
+
+    #[test]
+    fn test_import_rejects_corrupt_bundles() {
+        let dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(dir.path()).unwrap();
+
+        let mut bundle = Vec::new();
+        let mut builder = tar::Builder::new(zstd::Encoder::new(&mut bundle, 1).unwrap());
+        let header = BundleHeader {
+            version: BUNDLE_VERSION,
+            created_at: 0,
+        };
+        append(&mut builder, HEADER_ENTRY, &to_json(&header).unwrap(), 0).unwrap();
+        let entry = BlobEntry {
+            size: 5,
+            sha256: hex::encode(Sha256::digest(b"hello")),
+            tags: None,
+        };
+        let name = format!("{}{}", BLOBS_DIR, hex::encode(b"abc"));
+        append(
+            &mut builder,
+            &format!("{}.json", name),
+            &to_json(&entry).unwrap(),
+            0,
+        )
+        .unwrap();
+        append(&mut builder, &name, b"jello", 0).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        assert!(matches!(
+            import(&storage, bundle.as_slice()),
+            Err(FabrikError::Corrupt(_))
+        ));
+        assert!(!storage.exists(b"abc").unwrap());
+        assert!(import(&storage, b"not a bundle".as_slice()).is_err());
+    }
+}
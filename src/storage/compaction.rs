@@ -0,0 +1,202 @@
+/// Storage compaction
+///
+/// Objects are stored in 256 two-character shard directories, and the metadata lives in
+/// RocksDB. Heavy eviction leaves both bloated: emptied shard directories stay behind,
+/// and deleted metadata only turns into tombstones that keep SST files large until
+/// RocksDB happens to compact them. Compaction (`fabrik cache compact`, or every
+/// `cache.compact_interval` in the daemon and server) removes empty shard directories
+/// and runs a manual compaction over every column family. It also deletes chunks of
+/// the deduplicating chunk store that no object references (see `storage::dedup`).
+///
+/// Removing a shard directory races with writers creating objects in it; writers retry
+/// once after recreating the directory (see `filesystem::write_object`).
+use super::dedup;
+use crate::error::{FabrikError, Result, ResultExt};
+use crate::eviction::EvictionConfig;
+use rocksdb::DB;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Weak;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// What a compaction reclaimed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactStats {
+    /// Empty shard directories removed
+    pub shard_dirs_removed: u64,
+
+    /// Disk space the removed directories took up
+    pub shard_dir_bytes: u64,
+
+    /// Unreferenced chunks deleted, and their size
+    pub chunks_removed: u64,
+    pub chunk_bytes: u64,
+
+    /// Size of the metadata database before and after compaction
+    pub metadata_bytes_before: u64,
+    pub metadata_bytes_after: u64,
+}
+
+impl CompactStats {
+    /// Total disk space reclaimed
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.shard_dir_bytes
+            + self.chunk_bytes
+            + self
+                .metadata_bytes_before
+                .saturating_sub(self.metadata_bytes_after)
+    }
+}
+
+/// Parse `cache.compact_interval` (e.g. "24h")
+pub fn parse_interval(interval: &str) -> Result<Duration> {
+    EvictionConfig::parse_ttl(interval)
+        .ok()
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+        .ok_or_else(|| {
+            FabrikError::config(format!(
+                "Invalid compaction interval '{}': expected a duration such as 24h",
+                interval
+            ))
+        })
+}
+
+/// Delete unreferenced chunks, remove empty shard directories and compact the metadata
+/// database
+pub(super) fn run(
+    db: &DB,
+    column_families: &[&str],
+    objects_dirs: &[PathBuf],
+    chunks_dir: &Path,
+    db_dir: &Path,
+) -> Result<CompactStats> {
+    let mut stats = CompactStats::default();
+    (stats.chunks_removed, stats.chunk_bytes) = dedup::collect_garbage(db, chunks_dir)?;
+
+    // The chunk store has the same shard layout, once an object was deduplicated
+    let chunks = [chunks_dir.to_path_buf()];
+    let chunks = chunks.iter().filter(|dir| dir.exists());
+    let entries = objects_dirs
+        .iter()
+        .chain(chunks)
+        .map(fs::read_dir)
+        .collect::<io::Result<Vec<_>>>()
+        .io_context("Failed to read objects directory")?;
+    for entry in entries.into_iter().flatten() {
+        let entry = entry.io_context("Failed to read objects directory")?;
+        let metadata = match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => metadata,
+            _ => continue,
+        };
+        // Fails unless the directory is empty, so objects written meanwhile are safe
+        match fs::remove_dir(entry.path()) {
+            Ok(()) => {
+                stats.shard_dirs_removed += 1;
+                stats.shard_dir_bytes += disk_usage(&metadata);
+            }
+            Err(e) if is_not_empty(&e) || e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).io_context(&format!(
+                    "Failed to remove shard directory {}",
+                    entry.path().display()
+                ))
+            }
+        }
+    }
+
+    stats.metadata_bytes_before = dir_size(db_dir);
+    db.flush().io_context("Failed to flush metadata")?;
+    for name in column_families {
+        match db.cf_handle(name) {
+            Some(cf) => db.compact_range_cf(cf, None::<&[u8]>, None::<&[u8]>),
+            None => db.compact_range(None::<&[u8]>, None::<&[u8]>),
+        }
+    }
+    stats.metadata_bytes_after = dir_size(db_dir);
+
+    Ok(stats)
+}
+
+/// Compact every `interval` on a background thread, until the storage is closed
+pub(super) fn spawn(
+    db: Weak<DB>,
+    column_families: Vec<&'static str>,
+    objects_dirs: Vec<PathBuf>,
+    chunks_dir: PathBuf,
+    db_dir: PathBuf,
+    interval: Duration,
+) -> Result<()> {
+    thread::Builder::new()
+        .name("fabrik-compact".to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+
+            let Some(db) = db.upgrade() else {
+                debug!("Storage compaction stopped: storage closed");
+                return;
+            };
+            match run(&db, &column_families, &objects_dirs, &chunks_dir, &db_dir) {
+                Ok(stats) => info!(
+                    "Storage compaction: removed {} empty shard directories and {} unreferenced chunks, metadata {} -> {} bytes, {} bytes reclaimed",
+                    stats.shard_dirs_removed,
+                    stats.chunks_removed,
+                    stats.metadata_bytes_before,
+                    stats.metadata_bytes_after,
+                    stats.reclaimed_bytes()
+                ),
+                Err(e) => warn!("Storage compaction failed: {}", e),
+            }
+        })
+        .io_context("Failed to spawn compaction thread")?;
+    Ok(())
+}
+
+fn is_not_empty(e: &io::Error) -> bool {
+    // Some systems report EEXIST instead of ENOTEMPTY
+    matches!(
+        e.kind(),
+        io::ErrorKind::DirectoryNotEmpty | io::ErrorKind::AlreadyExists
+    )
+}
+
+/// Disk space a file or directory entry takes up
+fn disk_usage(metadata: &fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.blocks() * 512
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.len()
+    }
+}
+
+/// Total disk usage of the files in a directory (not recursive)
+fn dir_size(dir: &Path) -> u64 {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.metadata().ok())
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| disk_usage(&metadata))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("24h").unwrap(), Duration::from_secs(86400));
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("daily").is_err());
+    }
+}
@@ -0,0 +1,321 @@
+/// Deduplicating chunk store (`[cache] dedup`)
+///
+/// Many cached artifacts (app bundles, archives) differ only slightly from each other.
+/// With dedup enabled, objects of at least `chunking::MIN_CHUNKED_SIZE` are cut into
+/// content-defined chunks (see `chunking`) and each distinct chunk is stored once, in
+/// `chunks/` next to `objects/`. The object keeps its metadata in the default column
+/// family (so eviction, TTLs and stats see it at its full size), and two column
+/// families track the chunks:
+///
+/// - "chunk_lists": object ID -> SHA256 of each chunk, in order (32 bytes each)
+/// - "chunks": chunk SHA256 -> reference count and size (u64 LE each)
+///
+/// A chunk's file is deleted as soon as its last reference goes. Chunks left without
+/// references by an interrupted write are collected by compaction (`collect_garbage`).
+/// Changes to reference counts are serialized by a process-wide lock, so a chunk is
+/// never deleted while another write starts referencing it.
+use rocksdb::{WriteBatch, DB};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::filesystem::{object_path, write_object};
+use crate::chunking;
+use crate::error::{FabrikError, Result, ResultExt};
+
+pub(super) const CF_CHUNKS: &str = "chunks";
+pub(super) const CF_CHUNK_LISTS: &str = "chunk_lists";
+
+/// Directory of the chunk files, relative to the cache directory
+const CHUNKS_DIR: &str = "chunks";
+
+/// Serializes reference count changes and garbage collection
+static LOCK: Mutex<()> = Mutex::new(());
+
+type ChunkHash = [u8; 32];
+
+/// Directory of the chunk files of the cache whose objects are in `objects_dir`
+pub(super) fn chunks_dir(objects_dir: &Path) -> PathBuf {
+    objects_dir.parent().unwrap_or(objects_dir).join(CHUNKS_DIR)
+}
+
+/// Chunk hashes of a deduplicated object, None for objects stored whole
+fn chunk_list(db: &DB, id: &[u8]) -> Result<Option<Vec<ChunkHash>>> {
+    let Some(cf) = db.cf_handle(CF_CHUNK_LISTS) else {
+        return Ok(None);
+    };
+    let Some(bytes) = db.get_cf(cf, id)? else {
+        return Ok(None);
+    };
+    if bytes.len() % 32 != 0 {
+        return Err(FabrikError::corrupt(format!(
+            "Invalid chunk list of {}",
+            hex::encode(id)
+        )));
+    }
+    Ok(Some(
+        bytes
+            .chunks_exact(32)
+            .map(|hash| hash.try_into().unwrap())
+            .collect(),
+    ))
+}
+
+/// Reference count and size of a chunk
+fn chunk_entry(db: &DB, hash: &ChunkHash) -> Result<Option<(u64, u64)>> {
+    let cf = chunks_cf(db)?;
+    Ok(db.get_cf(cf, hash)?.and_then(|bytes| {
+        let bytes: [u8; 16] = bytes.as_slice().try_into().ok()?;
+        Some((
+            u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            u64::from_le_bytes(bytes[8..].try_into().unwrap()),
+        ))
+    }))
+}
+
+fn encode_entry(references: u64, size: u64) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&references.to_le_bytes());
+    bytes[8..].copy_from_slice(&size.to_le_bytes());
+    bytes
+}
+
+fn chunks_cf(db: &DB) -> Result<&rocksdb::ColumnFamily> {
+    db.cf_handle(CF_CHUNKS)
+        .ok_or_else(|| FabrikError::corrupt("Failed to get CF_CHUNKS handle"))
+}
+
+/// Files of the chunks of a deduplicated object, in order; None for objects stored
+/// whole
+pub(super) fn chunk_paths(db: &DB, chunks_dir: &Path, id: &[u8]) -> Result<Option<Vec<PathBuf>>> {
+    Ok(chunk_list(db, id)?.map(|hashes| {
+        hashes
+            .iter()
+            .map(|hash| object_path(chunks_dir, hash))
+            .collect()
+    }))
+}
+
+/// Contents of an object from its chunk files (NotFound if one is gone)
+pub(super) fn read_chunks(paths: &[PathBuf]) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for path in paths {
+        data.extend_from_slice(&fs::read(path)?);
+    }
+    Ok(data)
+}
+
+/// Reads an object from its chunk files, opening each one in turn
+pub(super) struct ChunkReader {
+    paths: std::vec::IntoIter<PathBuf>,
+    current: Option<fs::File>,
+}
+
+impl ChunkReader {
+    pub(super) fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            paths: paths.into_iter(),
+            current: None,
+        }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(file) = &mut self.current {
+                let read = file.read(buf)?;
+                if read > 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+            }
+            match self.paths.next() {
+                Some(path) => self.current = Some(fs::File::open(path)?),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Store `data` as the chunks of object `id`, replacing its previous chunks
+pub(super) fn store(db: &DB, chunks_dir: &Path, id: &[u8], data: &[u8]) -> Result<()> {
+    let pieces = chunking::chunks(data);
+    let hashes: Vec<ChunkHash> = pieces
+        .iter()
+        .map(|piece| Sha256::digest(piece).into())
+        .collect();
+
+    let _lock = LOCK.lock().unwrap();
+    let mut entries: HashMap<ChunkHash, (u64, u64)> = HashMap::new();
+    let previous = chunk_list(db, id)?.unwrap_or_default();
+    for hash in &previous {
+        let entry = load(db, &mut entries, hash)?;
+        entry.0 = entry.0.saturating_sub(1);
+    }
+    for (piece, hash) in pieces.iter().zip(&hashes) {
+        let path = object_path(chunks_dir, hash);
+        let entry = load(db, &mut entries, hash)?;
+        // Unreferenced chunks may have lost their file to garbage collection
+        if entry.0 == 0 && !path.exists() {
+            write_object(&path, piece)?;
+        }
+        *entry = (entry.0 + 1, piece.len() as u64);
+    }
+
+    let mut batch = WriteBatch::default();
+    let unreferenced = put_entries(db, &mut batch, &entries)?;
+    let lists = db
+        .cf_handle(CF_CHUNK_LISTS)
+        .ok_or_else(|| FabrikError::corrupt("Failed to get CF_CHUNK_LISTS handle"))?;
+    batch.put_cf(lists, id, hashes.concat());
+    db.write(batch).io_context("Failed to store chunk list")?;
+
+    remove_chunks(chunks_dir, &unreferenced)
+}
+
+/// Drop the chunk references of object `id` (stored whole or deleted), deleting the
+/// chunks no other object references
+pub(super) fn release(db: &DB, chunks_dir: &Path, id: &[u8]) -> Result<()> {
+    // Most objects are stored whole: skip the lock for them
+    if chunk_list(db, id)?.is_none() {
+        return Ok(());
+    }
+    let _lock = LOCK.lock().unwrap();
+    let Some(hashes) = chunk_list(db, id)? else {
+        return Ok(());
+    };
+
+    let mut entries: HashMap<ChunkHash, (u64, u64)> = HashMap::new();
+    for hash in &hashes {
+        let entry = load(db, &mut entries, hash)?;
+        entry.0 = entry.0.saturating_sub(1);
+    }
+
+    let mut batch = WriteBatch::default();
+    let unreferenced = put_entries(db, &mut batch, &entries)?;
+    if let Some(lists) = db.cf_handle(CF_CHUNK_LISTS) {
+        batch.delete_cf(lists, id);
+    }
+    db.write(batch).io_context("Failed to release chunks")?;
+
+    remove_chunks(chunks_dir, &unreferenced)
+}
+
+/// Write the chunk files of object `id`, whose chunk list was restored from a snapshot
+/// (the files of chunks already present are kept)
+pub(super) fn restore(db: &DB, chunks_dir: &Path, id: &[u8], data: &[u8]) -> Result<()> {
+    let hashes = chunk_list(db, id)?
+        .ok_or_else(|| FabrikError::corrupt(format!("No chunk list for {}", hex::encode(id))))?;
+    let mut offset = 0;
+    for hash in &hashes {
+        let (_, size) = chunk_entry(db, hash)?.ok_or_else(|| {
+            FabrikError::corrupt(format!(
+                "Unknown chunk {} of {}",
+                hex::encode(hash),
+                hex::encode(id)
+            ))
+        })?;
+        let end = offset + size as usize;
+        let piece = data.get(offset..end).ok_or_else(|| {
+            FabrikError::corrupt(format!("Chunks of {} exceed its size", hex::encode(id)))
+        })?;
+        let path = object_path(chunks_dir, hash);
+        if !path.exists() {
+            write_object(&path, piece)?;
+        }
+        offset = end;
+    }
+    Ok(())
+}
+
+/// Delete chunk files no object references (left by interrupted writes), returning the
+/// number of chunks and bytes deleted
+pub(super) fn collect_garbage(db: &DB, chunks_dir: &Path) -> Result<(u64, u64)> {
+    let (mut removed, mut bytes) = (0, 0);
+    let shards = match fs::read_dir(chunks_dir) {
+        Ok(shards) => shards,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e).io_context("Failed to read chunks directory"),
+    };
+    for shard in shards {
+        let shard = shard.io_context("Failed to read chunks directory")?;
+        let Ok(files) = fs::read_dir(shard.path()) else {
+            continue;
+        };
+        // Per shard, so writes aren't held up for the whole sweep
+        let _lock = LOCK.lock().unwrap();
+        for file in files {
+            let file = file.io_context("Failed to read chunks directory")?;
+            let name = format!(
+                "{}{}",
+                shard.file_name().to_string_lossy(),
+                file.file_name().to_string_lossy()
+            );
+            // Temp files of writes in progress aren't valid hashes
+            let Some(hash) = hex::decode(&name)
+                .ok()
+                .and_then(|hash| ChunkHash::try_from(hash).ok())
+            else {
+                continue;
+            };
+            if chunk_entry(db, &hash)?.is_none() {
+                let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+                match fs::remove_file(file.path()) {
+                    Ok(()) => {
+                        removed += 1;
+                        bytes += size;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e).io_context("Failed to delete chunk"),
+                }
+            }
+        }
+    }
+    Ok((removed, bytes))
+}
+
+/// Add the changed chunk entries to `batch`, returning the chunks without references
+fn put_entries(
+    db: &DB,
+    batch: &mut WriteBatch,
+    entries: &HashMap<ChunkHash, (u64, u64)>,
+) -> Result<Vec<ChunkHash>> {
+    let cf = chunks_cf(db)?;
+    let mut unreferenced = Vec::new();
+    for (hash, (references, size)) in entries {
+        if *references == 0 {
+            batch.delete_cf(cf, hash);
+            unreferenced.push(*hash);
+        } else {
+            batch.put_cf(cf, hash, encode_entry(*references, *size));
+        }
+    }
+    Ok(unreferenced)
+}
+
+/// Entry of `hash` in `entries`, loaded from the database on first use
+fn load<'a>(
+    db: &DB,
+    entries: &'a mut HashMap<ChunkHash, (u64, u64)>,
+    hash: &ChunkHash,
+) -> Result<&'a mut (u64, u64)> {
+    if !entries.contains_key(hash) {
+        let entry = chunk_entry(db, hash)?.unwrap_or((0, 0));
+        entries.insert(*hash, entry);
+    }
+    Ok(entries.get_mut(hash).unwrap())
+}
+
+fn remove_chunks(chunks_dir: &Path, hashes: &[ChunkHash]) -> Result<()> {
+    for hash in hashes {
+        match fs::remove_file(object_path(chunks_dir, hash)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).io_context("Failed to delete chunk"),
+        }
+    }
+    Ok(())
+}
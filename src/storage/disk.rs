@@ -0,0 +1,41 @@
+/// Free space of the volume holding the cache
+///
+/// `max_size` bounds the cache itself, but on a shared disk other data can fill the
+/// volume first. With `[cache] min_free_disk`, background eviction also evicts until
+/// the volume has that much free space, and puts that would leave less than half of it
+/// free are rejected (see `FilesystemStorage::put`).
+use std::path::Path;
+
+use crate::error::{Result, ResultExt};
+
+/// Space available to unprivileged users on the volume holding `path`, in bytes
+///
+/// None on platforms where it can't be read.
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> Result<Option<u64>> {
+    let stat = nix::sys::statvfs::statvfs(path).io_context("Failed to read free disk space")?;
+    Ok(Some(
+        (stat.blocks_available() as u64).saturating_mul(stat.fragment_size() as u64),
+    ))
+}
+
+/// Space available to unprivileged users on the volume holding `path`, in bytes
+///
+/// None on platforms where it can't be read.
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_free_space() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        assert!(free_space(temp_dir.path()).unwrap().unwrap() > 0);
+        assert!(free_space(&temp_dir.path().join("missing")).is_err());
+    }
+}
@@ -0,0 +1,487 @@
+/// Embedded metadata backend for small caches
+///
+/// RocksDB brings a large native dependency, background threads and a noticeable open
+/// cost, which is a poor fit for the C API and one-shot CLI commands working on small
+/// caches. `EmbeddedStorage` stores objects in the same `objects/` layout as
+/// `FilesystemStorage`, but keeps their metadata in memory, persisted to a single
+/// append-only log (`metadata.log` in the cache directory):
+///
+/// - Every write, delete and access appends one record; opening the storage replays
+///   the log. A record cut short by a crash is dropped.
+/// - The log is rewritten with only the live entries once it holds more than twice as
+///   many records as objects, so it stays proportional to the cache.
+/// - The cache is kept under `max_objects` (and the eviction `max_size`, if configured)
+///   by evicting least recently used objects on write.
+///
+/// A cache directory belongs to one backend: each refuses to open a directory the other
+/// one manages, since its objects would be invisible to the metadata.
+use super::filesystem::{
+    object_path, write_object, write_object_from, FilesystemStorage, ObjectMetadata,
+};
+use super::{ObjectInfo, Storage, StorageStats};
+use crate::error::{FabrikError, Result, ResultExt};
+use crate::eviction::{EvictionCandidate, EvictionConfig, EvictionManager};
+use crate::hashing::{HashAlgorithm, HashingReader};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{debug, warn};
+
+/// Metadata log in the cache directory
+pub(super) const LOG_FILE: &str = "metadata.log";
+
+/// Default object limit of the embedded backend
+pub const DEFAULT_MAX_OBJECTS: usize = 10_000;
+
+/// Log rewrites are skipped below this many stale records
+const MIN_STALE_RECORDS: usize = 1024;
+
+// Record: tag (u8), id length (u16 LE), metadata length (u8), id, metadata
+const TAG_PUT: u8 = 1;
+const TAG_DELETE: u8 = 2;
+
+/// Filesystem storage with in-memory metadata persisted to an append-only log
+pub struct EmbeddedStorage {
+    objects_dir: PathBuf,
+    log_path: PathBuf,
+    max_objects: usize,
+    eviction_manager: Option<EvictionManager>,
+    state: Mutex<State>,
+}
+
+struct State {
+    index: HashMap<Vec<u8>, ObjectMetadata>,
+    total_bytes: u64,
+    log: BufWriter<File>,
+    records: usize,
+}
+
+impl EmbeddedStorage {
+    /// Open (or create) an embedded storage holding at most `max_objects` objects
+    pub fn open<P: AsRef<Path>>(
+        cache_dir: P,
+        max_objects: usize,
+        eviction_config: Option<EvictionConfig>,
+    ) -> Result<Self> {
+        let cache_dir = cache_dir.as_ref();
+        if max_objects == 0 {
+            return Err(FabrikError::config("max_objects must be at least 1"));
+        }
+        if cache_dir.join("metadata").exists() {
+            return Err(FabrikError::config(format!(
+                "{} uses the RocksDB metadata backend",
+                cache_dir.display()
+            )));
+        }
+
+        let objects_dir = cache_dir.join("objects");
+        fs::create_dir_all(&objects_dir).io_context("Failed to create objects directory")?;
+
+        let log_path = cache_dir.join(LOG_FILE);
+        let (index, records, valid_len) = replay(&log_path)?;
+        let total_bytes = index.values().map(|metadata| metadata.size).sum();
+
+        let log = open_log(&log_path)?;
+        if log.get_ref().metadata().map_or(0, |m| m.len()) > valid_len {
+            warn!(
+                "Dropping incomplete record at the end of {}",
+                log_path.display()
+            );
+            log.get_ref()
+                .set_len(valid_len)
+                .io_context("Failed to truncate metadata log")?;
+        }
+        let storage = Self {
+            objects_dir,
+            log_path,
+            max_objects,
+            eviction_manager: eviction_config.map(EvictionManager::new),
+            state: Mutex::new(State {
+                index,
+                total_bytes,
+                log,
+                records,
+            }),
+        };
+        {
+            let mut state = storage.state.lock().unwrap();
+            if needs_rewrite(&state) {
+                storage.rewrite(&mut state)?;
+            }
+        }
+        Ok(storage)
+    }
+
+    fn append(
+        &self,
+        state: &mut State,
+        id: &[u8],
+        metadata: Option<&ObjectMetadata>,
+    ) -> Result<()> {
+        let record = encode(id, metadata);
+        state
+            .log
+            .write_all(&record)
+            .and_then(|_| state.log.flush())
+            .io_context("Failed to append to metadata log")?;
+        state.records += 1;
+        if needs_rewrite(state) {
+            self.rewrite(state)?;
+        }
+        Ok(())
+    }
+
+    /// Replace the log with one record per live object
+    fn rewrite(&self, state: &mut State) -> Result<()> {
+        let temp_path = self.log_path.with_extension("log.tmp");
+        let mut temp =
+            BufWriter::new(File::create(&temp_path).io_context("Failed to create metadata log")?);
+        for (id, metadata) in &state.index {
+            temp.write_all(&encode(id, Some(metadata)))
+                .io_context("Failed to write metadata log")?;
+        }
+        temp.into_inner()
+            .map_err(|e| e.into_error())
+            .and_then(|file| file.sync_all())
+            .io_context("Failed to write metadata log")?;
+        fs::rename(&temp_path, &self.log_path).io_context("Failed to replace metadata log")?;
+
+        state.log = open_log(&self.log_path)?;
+        state.records = state.index.len();
+        debug!("Rewrote metadata log with {} records", state.records);
+        Ok(())
+    }
+
+    fn remove(&self, state: &mut State, id: &[u8]) -> Result<()> {
+        let path = object_path(&self.objects_dir, id);
+        if path.exists() {
+            fs::remove_file(&path).io_context("Failed to delete object")?;
+        }
+        if let Some(previous) = state.index.remove(id) {
+            state.total_bytes = state.total_bytes.saturating_sub(previous.size);
+            self.append(state, id, None)?;
+        }
+        Ok(())
+    }
+
+    /// Record an object of `size` bytes just written to the objects directory
+    fn record_put(
+        &self,
+        id: &[u8],
+        size: u64,
+        checksum: [u8; 32],
+        expires_at: Option<i64>,
+    ) -> Result<()> {
+        let now = FilesystemStorage::current_timestamp();
+        let mut state = self.state.lock().unwrap();
+        let (previous_size, access_count) = state
+            .index
+            .get(id)
+            .map_or((0, 0), |m| (m.size, m.access_count));
+        let metadata = ObjectMetadata {
+            size,
+            created_at: now,
+            accessed_at: now,
+            access_count,
+            checksum: Some(checksum),
+            checksum_algorithm: HashAlgorithm::Sha256,
+            expires_at,
+        };
+
+        state.total_bytes = state.total_bytes - previous_size + size;
+        self.append(&mut state, id, Some(&metadata))?;
+        state.index.insert(id.to_vec(), metadata);
+        self.evict(&mut state, id)
+    }
+
+    /// Evict least recently used objects (never `keep`) until under the limits
+    fn evict(&self, state: &mut State, keep: &[u8]) -> Result<()> {
+        let excess_objects = state.index.len().saturating_sub(self.max_objects);
+        let excess_bytes = match &self.eviction_manager {
+            Some(manager) if manager.needs_eviction(state.total_bytes) => {
+                manager.bytes_to_evict(state.total_bytes)
+            }
+            _ => 0,
+        };
+        if excess_objects == 0 && excess_bytes == 0 {
+            return Ok(());
+        }
+
+        let candidates: Vec<EvictionCandidate> = state
+            .index
+            .iter()
+            .filter(|(id, _)| id.as_slice() != keep)
+            .map(|(id, metadata)| EvictionCandidate {
+                id: id.clone(),
+                size: metadata.size,
+                accessed_at: metadata.accessed_at,
+                access_count: metadata.access_count,
+                created_at: metadata.created_at,
+                expires_at: metadata.expires_at,
+            })
+            .collect();
+
+        let mut victims = match &self.eviction_manager {
+            Some(manager) if excess_bytes > 0 => {
+                manager.select_candidates(&candidates, excess_bytes)
+            }
+            _ => Vec::new(),
+        };
+        if victims.len() < excess_objects {
+            let mut by_access: Vec<&EvictionCandidate> = candidates
+                .iter()
+                .filter(|candidate| !victims.iter().any(|victim| victim.id == candidate.id))
+                .collect();
+            by_access.sort_by_key(|candidate| (candidate.accessed_at, candidate.created_at));
+            let missing = excess_objects - victims.len();
+            victims.extend(by_access.into_iter().take(missing).cloned());
+        }
+
+        for victim in &victims {
+            if let Err(e) = self.remove(state, &victim.id) {
+                warn!("Failed to evict object {}: {}", hex::encode(&victim.id), e);
+            }
+        }
+        debug!("Evicted {} objects", victims.len());
+        Ok(())
+    }
+}
+
+impl Storage for EmbeddedStorage {
+    fn put(&self, id: &[u8], data: &[u8]) -> Result<()> {
+        self.put_with_expiry(id, data, None)
+    }
+
+    fn put_with_expiry(&self, id: &[u8], data: &[u8], expires_at: Option<i64>) -> Result<()> {
+        write_object(&object_path(&self.objects_dir, id), data)?;
+        let checksum = Sha256::digest(data).into();
+        self.record_put(id, data.len() as u64, checksum, expires_at)
+    }
+
+    fn put_file(&self, id: &[u8], path: &Path, expires_at: Option<i64>) -> Result<()> {
+        let file = File::open(path).io_context("Failed to open file")?;
+        let size = file.metadata().io_context("Failed to read file")?.len();
+        let mut reader = HashingReader::new(file, HashAlgorithm::Sha256);
+        write_object_from(&object_path(&self.objects_dir, id), &mut reader)?;
+        self.record_put(id, size, reader.finalize(), expires_at)
+    }
+
+    fn get(&self, id: &[u8]) -> Result<Option<Vec<u8>>> {
+        let path = object_path(&self.objects_dir, id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(&path).io_context("Failed to read object")?;
+        self.touch(id)?;
+        Ok(Some(data))
+    }
+
+    fn open(&self, id: &[u8]) -> Result<Option<Box<dyn std::io::Read + Send>>> {
+        let file = match fs::File::open(object_path(&self.objects_dir, id)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).io_context("Failed to open object"),
+        };
+        self.touch(id)?;
+        Ok(Some(Box::new(file)))
+    }
+
+    fn exists(&self, id: &[u8]) -> Result<bool> {
+        Ok(object_path(&self.objects_dir, id).exists())
+    }
+
+    fn delete(&self, id: &[u8]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.remove(&mut state, id)
+    }
+
+    fn size(&self, id: &[u8]) -> Result<Option<u64>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.index.get(id).map(|metadata| metadata.size))
+    }
+
+    fn created_at(&self, id: &[u8]) -> Result<Option<i64>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.index.get(id).map(|metadata| metadata.created_at))
+    }
+
+    fn info(&self, id: &[u8]) -> Result<Option<ObjectInfo>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.index.get(id).map(|metadata| ObjectInfo {
+            size: metadata.size,
+            created_at: Some(metadata.created_at),
+            accessed_at: Some(metadata.accessed_at),
+            access_count: Some(metadata.access_count),
+            expires_at: metadata.expires_at,
+            ..ObjectInfo::default()
+        }))
+    }
+
+    fn touch(&self, id: &[u8]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let Some(metadata) = state.index.get_mut(id) else {
+            return Ok(());
+        };
+        metadata.accessed_at = FilesystemStorage::current_timestamp();
+        metadata.access_count += 1;
+        let metadata = metadata.clone();
+        self.append(&mut state, id, Some(&metadata))
+    }
+
+    fn list_ids(&self) -> Result<Vec<Vec<u8>>> {
+        let state = self.state.lock().unwrap();
+        let mut ids: Vec<Vec<u8>> = state.index.keys().cloned().collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn stats(&self) -> Result<StorageStats> {
+        let state = self.state.lock().unwrap();
+        Ok(StorageStats {
+            total_objects: state.index.len() as u64,
+            total_bytes: state.total_bytes,
+            cache_dir: self.objects_dir.parent().unwrap().to_path_buf(),
+        })
+    }
+}
+
+fn needs_rewrite(state: &State) -> bool {
+    let stale = state.records.saturating_sub(state.index.len());
+    stale > MIN_STALE_RECORDS && stale > state.index.len()
+}
+
+fn open_log(path: &Path) -> Result<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .io_context("Failed to open metadata log")?;
+    Ok(BufWriter::new(file))
+}
+
+fn encode(id: &[u8], metadata: Option<&ObjectMetadata>) -> Vec<u8> {
+    let metadata = metadata.map(ObjectMetadata::to_bytes).unwrap_or_default();
+    let tag = if metadata.is_empty() {
+        TAG_DELETE
+    } else {
+        TAG_PUT
+    };
+
+    let mut record = Vec::with_capacity(4 + id.len() + metadata.len());
+    record.push(tag);
+    record.extend_from_slice(&(id.len() as u16).to_le_bytes());
+    record.push(metadata.len() as u8);
+    record.extend_from_slice(id);
+    record.extend_from_slice(&metadata);
+    record
+}
+
+/// Live metadata in the log, the number of records read and the length of the log
+/// up to the first incomplete record
+fn replay(path: &Path) -> Result<(HashMap<Vec<u8>, ObjectMetadata>, usize, u64)> {
+    let mut bytes = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_end(&mut bytes)
+                .io_context("Failed to read metadata log")?;
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).io_context("Failed to open metadata log"),
+    }
+
+    let mut index = HashMap::new();
+    let mut records = 0;
+    let mut rest = bytes.as_slice();
+    while rest.len() >= 4 {
+        let tag = rest[0];
+        let id_len = u16::from_le_bytes([rest[1], rest[2]]) as usize;
+        let metadata_len = rest[3] as usize;
+        let Some(record) = rest.get(4..4 + id_len + metadata_len) else {
+            break;
+        };
+        let (id, metadata) = record.split_at(id_len);
+
+        match tag {
+            TAG_PUT => {
+                index.insert(id.to_vec(), ObjectMetadata::from_bytes(metadata)?);
+            }
+            TAG_DELETE => {
+                index.remove(id);
+            }
+            other => {
+                return Err(FabrikError::corrupt(format!(
+                    "Invalid metadata log record tag {}",
+                    other
+                )))
+            }
+        }
+        records += 1;
+        rest = &rest[4 + id_len + metadata_len..];
+    }
+
+    let valid_len = (bytes.len() - rest.len()) as u64;
+    Ok((index, records, valid_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_embedded_storage_survives_reopen() {
+        let temp_dir = TempDir::new().unwrap();
+        {
+            let storage = EmbeddedStorage::open(temp_dir.path(), 10, None).unwrap();
+            storage.put(b"kept", b"hello").unwrap();
+            storage.put(b"deleted", b"bye").unwrap();
+            storage.delete(b"deleted").unwrap();
+            assert_eq!(storage.get(b"kept").unwrap(), Some(b"hello".to_vec()));
+        }
+
+        // A torn record from a crash is dropped
+        let log_path = temp_dir.path().join(LOG_FILE);
+        let mut log = OpenOptions::new().append(true).open(&log_path).unwrap();
+        log.write_all(&[TAG_PUT, 4, 0]).unwrap();
+        drop(log);
+
+        let storage = EmbeddedStorage::open(temp_dir.path(), 10, None).unwrap();
+        assert_eq!(storage.list_ids().unwrap(), vec![b"kept".to_vec()]);
+        assert_eq!(storage.size(b"kept").unwrap(), Some(5));
+        let stats = storage.stats().unwrap();
+        assert_eq!((stats.total_objects, stats.total_bytes), (1, 5));
+        let valid_len = replay(&log_path).unwrap().2;
+        assert_eq!(valid_len, fs::metadata(&log_path).unwrap().len());
+
+        // The directory now belongs to the embedded backend
+        assert!(FilesystemStorage::new(temp_dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_embedded_storage_evicts_least_recently_used() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = EmbeddedStorage::open(temp_dir.path(), 2, None).unwrap();
+        storage.put(b"aaaa", b"1").unwrap();
+        storage.put(b"bbbb", b"2").unwrap();
+
+        // Make "b" the least recently used one
+        storage
+            .state
+            .lock()
+            .unwrap()
+            .index
+            .get_mut(&b"bbbb"[..])
+            .unwrap()
+            .accessed_at -= 10;
+        storage.put(b"cccc", b"3").unwrap();
+
+        assert_eq!(
+            storage.list_ids().unwrap(),
+            vec![b"aaaa".to_vec(), b"cccc".to_vec()]
+        );
+        assert!(!storage.exists(b"bbbb").unwrap());
+    }
+}
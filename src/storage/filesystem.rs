@@ -1,11 +1,32 @@
-use super::{Storage, StorageStats};
-use crate::eviction::{EvictableStorage, EvictionCandidate, EvictionConfig, EvictionManager};
-use anyhow::{Context, Result};
+use super::compaction::{self, CompactStats};
+use super::dedup::{self, CF_CHUNKS, CF_CHUNK_LISTS};
+use super::disk;
+use super::format;
+use super::labels::{self, Labels, CF_LABELS, CF_LABEL_INDEX};
+use super::migrate::{self, MigrateStats, MigrationObject};
+use super::partitions::{self, Change, ObjectTotals};
+use super::pins::{self, CF_PINS};
+use super::popularity::{self, CF_ACCESS_DAILY};
+#[cfg(test)]
+use super::scrub::Scrubber;
+use super::scrub::{self, ScrubConfig, ScrubMetrics};
+use super::snapshot::{self, Snapshot};
+use super::tags::{EntryTags, CF_ENTRY_TAGS};
+use super::tiers::{self, ColdTier, TierConfig, CF_COLD_TIER};
+use super::warmup::{self, WarmupConfig, WarmupStats};
+use super::{Compression, ObjectInfo, Storage, StorageStats};
+use crate::chunking;
+use crate::error::{FabrikError, Result, ResultExt};
+use crate::eviction::{
+    EvictableStorage, EvictionCandidate, EvictionConfig, EvictionManager, EvictionPolicy,
+};
+use crate::hashing::{HashAlgorithm, HashingReader};
 use crossbeam_channel::{bounded, Sender};
-use rocksdb::{IteratorMode, Options, DB};
+use rocksdb::{Options, DB};
 use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::Write;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
@@ -19,9 +40,36 @@ use tracing::{debug, info, warn};
 /// - "default": Object metadata (size, timestamps, access count)
 /// - "index_accessed": Secondary index for accessed_at (for LRU eviction)
 /// - "index_access_count": Secondary index for access_count (for LFU eviction)
+///   (one entry per accessed object, see `storage::format`)
+/// - "access_daily": Per-day access counters (see `storage::popularity`)
+/// - "entry_tags": Origin tags of entries (see `storage::tags`)
+/// - "pins": Blobs pinned by other entries (see `storage::pins`)
+/// - "labels", "label_index": User labels of objects (see `storage::labels`)
+/// - "cold_tier": Objects moved to the cold tier (see `storage::tiers`)
+/// - "chunks", "chunk_lists": Chunks of deduplicated objects (see `storage::dedup`)
 const CF_DEFAULT: &str = "default";
-const CF_INDEX_ACCESSED: &str = "index_accessed";
-const CF_INDEX_ACCESS_COUNT: &str = "index_access_count";
+pub(super) const CF_INDEX_ACCESSED: &str = "index_accessed";
+pub(super) const CF_INDEX_ACCESS_COUNT: &str = "index_access_count";
+
+const COLUMN_FAMILIES: [&str; 11] = [
+    CF_DEFAULT,
+    CF_INDEX_ACCESSED,
+    CF_INDEX_ACCESS_COUNT,
+    CF_ACCESS_DAILY,
+    CF_ENTRY_TAGS,
+    CF_PINS,
+    CF_LABELS,
+    CF_LABEL_INDEX,
+    CF_COLD_TIER,
+    CF_CHUNKS,
+    CF_CHUNK_LISTS,
+];
+
+/// Key read by `check_metadata`; never stored, so the read only touches the index
+const METADATA_PROBE_KEY: &[u8] = b"fabrik:readiness-probe";
+
+/// Last metadata byte of objects with a BLAKE3 checksum (see `ObjectMetadata`)
+const CHECKSUM_BLAKE3: u8 = 1;
 
 /// Metadata stored for each cached object in RocksDB
 ///
@@ -30,39 +78,78 @@ const CF_INDEX_ACCESS_COUNT: &str = "index_access_count";
 /// - created_at: i64 (8 bytes)
 /// - accessed_at: i64 (8 bytes)
 /// - access_count: u64 (8 bytes)
+/// - checksum: SHA256 or BLAKE3 of the content (32 bytes, optional)
+/// - expires_at: i64 (8 bytes, optional, only for objects stored with their own TTL)
+/// - checksum algorithm: u8 (1 byte, only for BLAKE3 checksums)
 ///
-/// Total: 32 bytes per object
+/// Total: 64 bytes per object, or 32 for objects written before checksums were
+/// recorded (the scrubber adds theirs, see `storage::scrub`), plus 8 with an expiry
+/// and 1 with a BLAKE3 checksum
 #[derive(Debug, Clone)]
-struct ObjectMetadata {
-    size: u64,
-    created_at: i64,
-    accessed_at: i64,
-    access_count: u64,
+pub(super) struct ObjectMetadata {
+    pub(super) size: u64,
+    pub(super) created_at: i64,
+    pub(super) accessed_at: i64,
+    pub(super) access_count: u64,
+    pub(super) checksum: Option<[u8; 32]>,
+    /// Algorithm of `checksum`
+    pub(super) checksum_algorithm: HashAlgorithm,
+    pub(super) expires_at: Option<i64>,
 }
 
 impl ObjectMetadata {
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(32);
+    pub(super) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(73);
         bytes.extend_from_slice(&self.size.to_le_bytes());
         bytes.extend_from_slice(&self.created_at.to_le_bytes());
         bytes.extend_from_slice(&self.accessed_at.to_le_bytes());
         bytes.extend_from_slice(&self.access_count.to_le_bytes());
+        if let Some(checksum) = &self.checksum {
+            bytes.extend_from_slice(checksum);
+        }
+        if let Some(expires_at) = self.expires_at {
+            bytes.extend_from_slice(&expires_at.to_le_bytes());
+        }
+        if self.checksum.is_some() && self.checksum_algorithm == HashAlgorithm::Blake3 {
+            bytes.push(CHECKSUM_BLAKE3);
+        }
         bytes
     }
 
-    fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        if bytes.len() != 32 {
-            anyhow::bail!(
-                "Invalid metadata size: expected 32 bytes, got {}",
-                bytes.len()
-            );
+    pub(super) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (bytes, checksum_algorithm) = match bytes.split_last() {
+            Some((&CHECKSUM_BLAKE3, rest)) if rest.len() % 8 == 0 => (rest, HashAlgorithm::Blake3),
+            _ => (bytes, HashAlgorithm::Sha256),
+        };
+        let (has_checksum, has_expiry) = match bytes.len() {
+            32 => (false, false),
+            40 => (false, true),
+            64 => (true, false),
+            72 => (true, true),
+            len => {
+                return Err(FabrikError::corrupt(format!(
+                    "Invalid metadata size: expected 32, 40, 64 or 72 bytes, got {}",
+                    len
+                )))
+            }
+        };
+        if checksum_algorithm == HashAlgorithm::Blake3 && !has_checksum {
+            return Err(FabrikError::corrupt(
+                "Invalid metadata: checksum algorithm without a checksum",
+            ));
         }
 
+        // The length check above guarantees every 8-byte field is present
+        let field = |start: usize| -> [u8; 8] { bytes[start..start + 8].try_into().unwrap() };
+
         Ok(Self {
-            size: u64::from_le_bytes(bytes[0..8].try_into()?),
-            created_at: i64::from_le_bytes(bytes[8..16].try_into()?),
-            accessed_at: i64::from_le_bytes(bytes[16..24].try_into()?),
-            access_count: u64::from_le_bytes(bytes[24..32].try_into()?),
+            size: u64::from_le_bytes(field(0)),
+            created_at: i64::from_le_bytes(field(8)),
+            accessed_at: i64::from_le_bytes(field(16)),
+            access_count: u64::from_le_bytes(field(24)),
+            checksum: has_checksum.then(|| bytes[32..64].try_into().unwrap()),
+            checksum_algorithm,
+            expires_at: has_expiry.then(|| i64::from_le_bytes(field(bytes.len() - 8))),
         })
     }
 }
@@ -79,6 +166,8 @@ struct TouchMessage {
 /// Layout:
 /// - `.fabrik/cache/objects/ab/cd1234...` - Content-addressed blob storage (first 2 chars = subdir)
 /// - `.fabrik/cache/metadata/` - RocksDB database for access tracking and eviction
+/// - `.fabrik/cache/chunks/ab/cd1234...` - Chunks of deduplicated objects (see
+///   `storage::dedup`)
 ///
 /// Optimizations:
 /// - RocksDB provides concurrent reads/writes out of the box
@@ -92,8 +181,17 @@ pub struct FilesystemStorage {
     db: Arc<DB>,
     touch_sender: Sender<TouchMessage>,
     worker_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+    totals: Arc<ObjectTotals>,
     #[allow(dead_code)]
     eviction_manager: Option<Arc<EvictionManager>>,
+    /// Opened with `read_only`: changes are refused and accesses aren't tracked
+    read_only: bool,
+    /// Second objects directory for large and cold objects (see `storage::tiers`)
+    cold_tier: Option<Arc<ColdTier>>,
+    /// Algorithm of the checksums of new objects
+    hash_algorithm: HashAlgorithm,
+    /// Store large new objects as deduplicated chunks (see `storage::dedup`)
+    dedup: bool,
 }
 
 impl FilesystemStorage {
@@ -118,8 +216,19 @@ impl FilesystemStorage {
         let objects_dir = cache_dir.join("objects");
         let db_path = cache_dir.join("metadata");
 
+        if cache_dir.join(super::embedded::LOG_FILE).exists() {
+            return Err(FabrikError::config(format!(
+                "{} uses the embedded metadata backend",
+                cache_dir.display()
+            )));
+        }
+
+        // Refuse formats newer than this build before touching anything
+        let format_version = format::read(cache_dir)?;
+        format::check(cache_dir, format_version)?;
+
         // Create directories
-        fs::create_dir_all(&objects_dir).context("Failed to create objects directory")?;
+        fs::create_dir_all(&objects_dir).io_context("Failed to create objects directory")?;
 
         // Configure RocksDB options
         let mut opts = Options::default();
@@ -139,13 +248,10 @@ impl FilesystemStorage {
         opts.set_max_write_buffer_number(3);
 
         // Open database with column families
-        let db = DB::open_cf(
-            &opts,
-            &db_path,
-            vec![CF_DEFAULT, CF_INDEX_ACCESSED, CF_INDEX_ACCESS_COUNT],
-        )
-        .context("Failed to open RocksDB database")?;
+        let db = DB::open_cf(&opts, &db_path, COLUMN_FAMILIES)
+            .io_context("Failed to open RocksDB database")?;
 
+        format::upgrade(&db, cache_dir, format_version)?;
         let db = Arc::new(db);
 
         // Create channel for async touch operations (buffered for batching)
@@ -156,6 +262,7 @@ impl FilesystemStorage {
         let worker_handle = thread::spawn(move || {
             let mut batch = Vec::with_capacity(100);
             let batch_timeout = Duration::from_millis(100);
+            let mut pruned_day = 0;
 
             loop {
                 // Collect messages for up to 100ms or 100 items
@@ -176,6 +283,18 @@ impl FilesystemStorage {
                             debug!("Failed to batch update access tracking: {}", e);
                         }
 
+                        // Expire old access counters once a day
+                        let today = popularity::day_of(Self::current_timestamp());
+                        if today != pruned_day {
+                            match popularity::prune(&db_clone, today) {
+                                Ok(deleted) => {
+                                    pruned_day = today;
+                                    debug!("Pruned {} expired access counters", deleted);
+                                }
+                                Err(e) => debug!("Failed to prune access counters: {}", e),
+                            }
+                        }
+
                         batch.clear();
                     }
                     Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
@@ -206,10 +325,117 @@ impl FilesystemStorage {
             db,
             touch_sender,
             worker_handle: Arc::new(Mutex::new(Some(worker_handle))),
+            totals: Arc::new(ObjectTotals::default()),
             eviction_manager,
+            read_only: false,
+            cold_tier: None,
+            hash_algorithm: HashAlgorithm::default(),
+            dedup: false,
+        })
+    }
+
+    /// Open an existing cache without ever writing to it (`[cache] read_only`)
+    ///
+    /// The metadata database is opened read-only, so any number of processes can serve
+    /// the same pre-seeded cache, e.g. from a shared volume. Puts, deletes and other
+    /// changes fail with `FabrikError::ReadOnly`; accesses aren't tracked and nothing is
+    /// evicted. The cache must already be in the current format.
+    pub fn read_only<P: AsRef<Path>>(cache_dir: P) -> Result<Self> {
+        let cache_dir = cache_dir.as_ref();
+        let db_path = cache_dir.join("metadata");
+
+        if cache_dir.join(super::embedded::LOG_FILE).exists() {
+            return Err(FabrikError::config(format!(
+                "{} uses the embedded metadata backend",
+                cache_dir.display()
+            )));
+        }
+
+        let format_version = format::read(cache_dir)?;
+        format::check(cache_dir, format_version)?;
+        match format_version {
+            None => {
+                return Err(FabrikError::config(format!(
+                    "No cache at {} to open read-only",
+                    cache_dir.display()
+                )))
+            }
+            Some(version) if version < format::CURRENT_VERSION => {
+                return Err(FabrikError::config(format!(
+                    "Cache at {} uses format version {}; open it once without read_only to \
+                     upgrade it to version {}",
+                    cache_dir.display(),
+                    version,
+                    format::CURRENT_VERSION
+                )))
+            }
+            Some(_) => {}
+        }
+
+        // Caches from before a column family was added don't have it
+        let opts = Options::default();
+        let existing =
+            DB::list_cf(&opts, &db_path).io_context("Failed to open RocksDB database")?;
+        let column_families = COLUMN_FAMILIES
+            .iter()
+            .filter(|name| existing.iter().any(|existing| existing == *name));
+        let db = DB::open_cf_for_read_only(&opts, &db_path, column_families, false)
+            .io_context("Failed to open RocksDB database")?;
+
+        Ok(Self {
+            objects_dir: cache_dir.join("objects"),
+            db: Arc::new(db),
+            touch_sender: bounded(0).0,
+            worker_handle: Arc::new(Mutex::new(None)),
+            totals: Arc::new(ObjectTotals::default()),
+            eviction_manager: None,
+            read_only: true,
+            cold_tier: None,
+            hash_algorithm: HashAlgorithm::default(),
+            dedup: false,
         })
     }
 
+    /// Keep large and cold objects in a second directory (see `storage::tiers`)
+    pub fn with_cold_tier(mut self, config: TierConfig) -> Result<Self> {
+        info!(
+            "Cold tier: {} (hot tier up to {}MB, objects up to {}MB)",
+            config.cold_dir.display(),
+            config.hot_max_bytes / (1024 * 1024),
+            config.hot_max_object_bytes / (1024 * 1024)
+        );
+        self.cold_tier = Some(Arc::new(ColdTier::open(config)?));
+        Ok(self)
+    }
+
+    /// Checksum new objects with `algorithm` (objects keep the algorithm they were
+    /// stored with)
+    pub fn with_hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = algorithm;
+        self
+    }
+
+    /// Store new objects of at least `chunking::MIN_CHUNKED_SIZE` as chunks shared with
+    /// other objects (see `storage::dedup`); objects already stored keep their layout
+    pub fn with_dedup(mut self, enabled: bool) -> Self {
+        if enabled {
+            info!("Deduplicating chunk store enabled");
+        }
+        self.dedup = enabled;
+        self
+    }
+
+    /// Refuse changes to a read-only cache
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(FabrikError::read_only(format!(
+                "Cache {} is read-only",
+                self.objects_dir.parent().unwrap().display()
+            )));
+        }
+        Ok(())
+    }
+
     /// Batch update access tracking for multiple objects
     fn batch_touch(db: &Arc<DB>, batch: &[TouchMessage]) -> Result<()> {
         if batch.is_empty() {
@@ -218,56 +444,255 @@ impl FilesystemStorage {
 
         // Use RocksDB write batch for atomic updates
         let mut write_batch = rocksdb::WriteBatch::default();
+        let mut accesses = Vec::with_capacity(batch.len());
+        // Metadata updated earlier in this batch, which the database doesn't have yet
+        let mut updated: HashMap<&[u8], ObjectMetadata> = HashMap::new();
 
         for msg in batch {
             // Get existing metadata
-            if let Some(existing_bytes) = db.get(&msg.id)? {
-                if let Ok(mut metadata) = ObjectMetadata::from_bytes(&existing_bytes) {
-                    // Update access tracking
-                    metadata.accessed_at = msg.timestamp;
-                    metadata.access_count += 1;
-
-                    // Write updated metadata
-                    write_batch.put(&msg.id, metadata.to_bytes());
-
-                    // Update secondary indexes (for efficient LRU/LFU queries)
-                    let cf_accessed = db
-                        .cf_handle(CF_INDEX_ACCESSED)
-                        .context("Failed to get CF_INDEX_ACCESSED handle")?;
-                    let cf_access_count = db
-                        .cf_handle(CF_INDEX_ACCESS_COUNT)
-                        .context("Failed to get CF_INDEX_ACCESS_COUNT handle")?;
-
-                    // Index key: timestamp + id (for range queries)
-                    let mut accessed_key = msg.timestamp.to_le_bytes().to_vec();
-                    accessed_key.extend_from_slice(&msg.id);
-
-                    let mut access_count_key = metadata.access_count.to_le_bytes().to_vec();
-                    access_count_key.extend_from_slice(&msg.id);
-
-                    write_batch.put_cf(cf_accessed, accessed_key, b"");
-                    write_batch.put_cf(cf_access_count, access_count_key, b"");
-                }
+            let existing = match updated.remove(msg.id.as_slice()) {
+                Some(metadata) => Some(metadata),
+                None => match db.get(&msg.id)? {
+                    Some(existing_bytes) => ObjectMetadata::from_bytes(&existing_bytes).ok(),
+                    None => None,
+                },
+            };
+            if let Some(mut metadata) = existing {
+                // Secondary index entries of the previous access (for efficient LRU/LFU
+                // queries), replaced by those of this one
+                let cf_accessed = db.cf_handle(CF_INDEX_ACCESSED).ok_or_else(|| {
+                    FabrikError::corrupt("Failed to get CF_INDEX_ACCESSED handle")
+                })?;
+                let cf_access_count = db.cf_handle(CF_INDEX_ACCESS_COUNT).ok_or_else(|| {
+                    FabrikError::corrupt("Failed to get CF_INDEX_ACCESS_COUNT handle")
+                })?;
+                let (previous_accessed_key, previous_access_count_key) =
+                    index_keys(&msg.id, &metadata);
+
+                // Update access tracking
+                metadata.accessed_at = msg.timestamp;
+                metadata.access_count += 1;
+
+                // Write updated metadata
+                write_batch.put(&msg.id, metadata.to_bytes());
+
+                let (accessed_key, access_count_key) = index_keys(&msg.id, &metadata);
+                write_batch.delete_cf(cf_accessed, previous_accessed_key);
+                write_batch.delete_cf(cf_access_count, previous_access_count_key);
+                write_batch.put_cf(cf_accessed, accessed_key, b"");
+                write_batch.put_cf(cf_access_count, access_count_key, b"");
+
+                accesses.push((msg.timestamp, msg.id.as_slice(), metadata.size));
+                updated.insert(msg.id.as_slice(), metadata);
             }
         }
+        popularity::record(db, &mut write_batch, &accesses)?;
 
         db.write(write_batch)
-            .context("Failed to write batch update")?;
+            .io_context("Failed to write batch update")?;
         debug!("Batched {} access tracking updates", batch.len());
 
         Ok(())
     }
 
     /// Convert blob ID to filesystem path
-    /// Uses git-style sharding: first 2 hex chars as subdirectory
     fn id_to_path(&self, id: &[u8]) -> PathBuf {
-        let hex_id = hex::encode(id);
-        let (prefix, suffix) = hex_id.split_at(2);
-        self.objects_dir.join(prefix).join(suffix)
+        object_path(&self.objects_dir, id)
+    }
+
+    fn chunks_dir(&self) -> PathBuf {
+        dedup::chunks_dir(&self.objects_dir)
+    }
+
+    /// Whether a new object of `size` bytes is stored as chunks
+    fn chunked(&self, size: u64) -> bool {
+        self.dedup && size >= chunking::MIN_CHUNKED_SIZE as u64
+    }
+
+    /// Store an object, in chunks if it's large enough and dedup is enabled
+    fn put_object(&self, id: &[u8], contents: Contents, expires_at: Option<i64>) -> Result<()> {
+        self.check_writable()?;
+        // Note: Eviction is handled by a background task (spawn_background_eviction)
+        // to avoid blocking put() operations. The background task periodically
+        // checks cache size and evicts objects according to the configured policy.
+
+        let size = contents.size();
+        let chunked = self.chunked(size);
+        let cold = (self.cold_tier.as_deref()).filter(|cold| !chunked && !cold.fits_hot(size));
+        // Whether a previous version stored whole was in the cold tier
+        let mut was_cold = false;
+        let checksum = if chunked {
+            self.check_free_disk(size)?;
+            // Chunking needs the whole object
+            let data = contents.read()?;
+            dedup::store(&self.db, &self.chunks_dir(), id, &data)?;
+            let (path, cold) = self.locate(id)?;
+            match fs::remove_file(&path) {
+                Ok(()) => was_cold = cold,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).io_context("Failed to delete replaced object"),
+            }
+            self.hash_algorithm.digest(&data)
+        } else {
+            let checksum = match cold {
+                Some(cold) => {
+                    contents.write(&object_path(&cold.objects_dir, id), self.hash_algorithm)?
+                }
+                None => {
+                    self.check_free_disk(size)?;
+                    contents.write(&self.id_to_path(id), self.hash_algorithm)?
+                }
+            };
+            // A previous version stored as chunks
+            dedup::release(&self.db, &self.chunks_dir(), id)?;
+            checksum
+        };
+
+        // Update metadata in RocksDB
+        let now = Self::current_timestamp();
+
+        let previous_size = self.totals.record(|| {
+            // Check if object already exists to preserve access_count
+            let previous = match self.db.get(id)? {
+                Some(existing_bytes) => ObjectMetadata::from_bytes(&existing_bytes).ok(),
+                None => None,
+            };
+
+            let metadata = ObjectMetadata {
+                size,
+                created_at: now,
+                accessed_at: now,
+                access_count: previous.as_ref().map_or(0, |m| m.access_count),
+                checksum: Some(checksum),
+                checksum_algorithm: self.hash_algorithm,
+                expires_at,
+            };
+
+            self.db
+                .put(id, metadata.to_bytes())
+                .io_context("Failed to update metadata")?;
+            // The access time was reset, move the object in the access indexes
+            if let Some(previous) = previous.as_ref().filter(|m| m.access_count > 0) {
+                delete_index_entries(&self.db, id, previous)?;
+                put_index_entries(&self.db, id, &metadata)?;
+            }
+
+            let previous_size = previous.as_ref().map(|m| m.size);
+            Ok((previous_size, Change::put(previous.as_ref(), size)))
+        })?;
+
+        match &self.cold_tier {
+            Some(tier) if chunked => match was_cold {
+                true => tier.forget(&self.db, id, previous_size),
+                false => Ok(()),
+            },
+            Some(tier) => tier.settle(
+                &self.db,
+                &self.objects_dir,
+                id,
+                previous_size,
+                size,
+                cold.is_some(),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// Path of an object, and whether it's in the cold tier
+    fn locate(&self, id: &[u8]) -> Result<(PathBuf, bool)> {
+        tiers::locate(&self.db, &self.objects_dir, self.cold_tier.as_deref(), id)
+    }
+
+    /// Bytes in the hot tier (the whole cache without a cold tier)
+    fn hot_bytes(&self) -> Result<u64> {
+        let (_, total_bytes) = self.totals.get(&self.db)?;
+        match &self.cold_tier {
+            Some(cold) => Ok(total_bytes.saturating_sub(cold.bytes(&self.db)?)),
+            None => Ok(total_bytes),
+        }
+    }
+
+    /// Write an object to the other tier and record the move, unless it was deleted
+    fn move_tier(&self, cold: &ColdTier, id: &[u8], data: &[u8], to_cold: bool) -> Result<()> {
+        let dir = if to_cold {
+            &cold.objects_dir
+        } else {
+            &self.objects_dir
+        };
+        let path = object_path(dir, id);
+        write_object(&path, data)?;
+        if self.db.get_pinned(id)?.is_none() {
+            fs::remove_file(&path).io_context("Failed to remove moved object")?;
+            return Ok(());
+        }
+        let size = data.len() as u64;
+        cold.settle(&self.db, &self.objects_dir, id, Some(size), size, to_cold)
+    }
+
+    /// Move a cold object that was just read back to the hot tier, if it fits there
+    fn promote(&self, cold: &ColdTier, id: &[u8], data: &[u8]) {
+        let size = data.len() as u64;
+        if self.read_only || !cold.fits_hot(size) {
+            return;
+        }
+        let promoted = self.hot_bytes().and_then(|hot_bytes| {
+            if hot_bytes + size > cold.config.hot_max_bytes {
+                return Ok(false);
+            }
+            self.move_tier(cold, id, data, false).map(|()| true)
+        });
+        match promoted {
+            Ok(true) => debug!("Promoted object {} to the hot tier", hex::encode(id)),
+            Ok(false) => {}
+            Err(e) => debug!("Failed to promote object {}: {}", hex::encode(id), e),
+        }
+    }
+
+    /// Move objects to the cold tier, in `policy` order, while the hot tier is over
+    /// `hot_max_size`, until it's under 90% of it
+    ///
+    /// Returns the number of objects and bytes demoted.
+    pub fn demote_hot_tier(&self, policy: &dyn EvictionPolicy) -> Result<(usize, u64)> {
+        let Some(cold) = self.cold_tier.as_deref() else {
+            return Ok((0, 0));
+        };
+        if self.read_only {
+            return Ok((0, 0));
+        }
+        let hot_bytes = self.hot_bytes()?;
+        if hot_bytes <= cold.config.hot_max_bytes {
+            return Ok((0, 0));
+        }
+        let to_demote = hot_bytes - cold.config.hot_max_bytes / 10 * 9;
+
+        let cold_ids = cold.ids(&self.db)?;
+        let partials = partitions::fold(&self.db, Vec::new, |candidates, id, metadata| {
+            if !cold_ids.contains(id) {
+                candidates.push(eviction_candidate(id, metadata));
+            }
+        })?;
+        let mut candidates: Vec<_> = partials.into_iter().flatten().collect();
+        policy.sort_candidates(&mut candidates);
+
+        let (mut demoted, mut demoted_bytes) = (0usize, 0u64);
+        for candidate in candidates {
+            if demoted_bytes >= to_demote {
+                break;
+            }
+            let data = match fs::read(self.id_to_path(&candidate.id)) {
+                Ok(data) => data,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).io_context("Failed to read object"),
+            };
+            self.move_tier(cold, &candidate.id, &data, true)?;
+            demoted += 1;
+            demoted_bytes += candidate.size;
+        }
+        Ok((demoted, demoted_bytes))
     }
 
     /// Get current Unix timestamp
-    fn current_timestamp() -> i64 {
+    pub(super) fn current_timestamp() -> i64 {
         SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -276,26 +701,59 @@ impl FilesystemStorage {
 
     /// Get all eviction candidates with their metadata
     ///
-    /// Returns all objects in the cache with metadata needed for eviction decisions.
-    /// Used by the eviction manager to select which objects to evict.
+    /// Returns all objects in the cache with metadata needed for eviction decisions,
+    /// except pinned ones (see `storage::pins`). Used by the eviction manager to select
+    /// which objects to evict. The metadata is scanned in parallel key ranges (see
+    /// `storage::partitions`).
     pub fn get_eviction_candidates(&self) -> Result<Vec<EvictionCandidate>> {
-        let mut candidates = Vec::new();
-        let iter = self.db.iterator(IteratorMode::Start);
-
-        for item in iter {
-            let (key, value) = item?;
-            if let Ok(metadata) = ObjectMetadata::from_bytes(&value) {
-                candidates.push(EvictionCandidate {
-                    id: key.to_vec(),
-                    size: metadata.size,
-                    accessed_at: metadata.accessed_at,
-                    access_count: metadata.access_count,
-                    created_at: metadata.created_at,
-                });
+        let pinned = self.pinned_ids()?;
+        let partials = partitions::fold(&self.db, Vec::new, |candidates, id, metadata| {
+            if !pinned.contains(id) {
+                candidates.push(eviction_candidate(id, metadata));
             }
+        })?;
+
+        Ok(partials.into_iter().flatten().collect())
+    }
+
+    /// IDs pinned by stored entries
+    pub fn pinned_ids(&self) -> Result<HashSet<Vec<u8>>> {
+        let Some(cf) = self.db.cf_handle(CF_PINS) else {
+            return Ok(HashSet::new());
+        };
+        let mut pinned = HashSet::new();
+        for item in self.db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+            let (_, value) = item?;
+            pinned.extend(pins::decode(&value)?);
         }
+        Ok(pinned)
+    }
 
-        Ok(candidates)
+    /// Refuse a write of `size` bytes that would leave less than half of
+    /// `min_free_disk` free on the cache volume (see `storage::disk`)
+    ///
+    /// Background eviction keeps `min_free_disk` free; this stops writes when something
+    /// else fills the volume faster than eviction frees it.
+    fn check_free_disk(&self, size: u64) -> Result<()> {
+        let min_free = self
+            .eviction_manager
+            .as_ref()
+            .map_or(0, |m| m.config().min_free_disk_bytes);
+        if min_free == 0 {
+            return Ok(());
+        }
+        let Some(free) = disk::free_space(&self.objects_dir)? else {
+            return Ok(());
+        };
+        if free.saturating_sub(size) < min_free / 2 {
+            return Err(FabrikError::QuotaExceeded(format!(
+                "Cache volume is nearly full ({}MB free, min_free_disk is {}MB): not storing {} bytes",
+                free / (1024 * 1024),
+                min_free / (1024 * 1024),
+                size
+            )));
+        }
+        Ok(())
     }
 
     /// Run eviction if needed
@@ -375,8 +833,9 @@ impl FilesystemStorage {
     /// the cache is under max_size.
     #[allow(dead_code)]
     pub fn force_eviction(&self, bytes_to_free: u64) -> Result<(usize, u64)> {
+        self.check_writable()?;
         let Some(ref eviction_manager) = self.eviction_manager else {
-            anyhow::bail!("Eviction manager not configured");
+            return Err(FabrikError::config("Eviction manager not configured"));
         };
 
         let start = Instant::now();
@@ -443,6 +902,216 @@ impl FilesystemStorage {
     pub fn objects_dir(&self) -> &Path {
         &self.objects_dir
     }
+
+    /// Whether the cache was opened read-only
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Read from the metadata database, failing when it can't serve reads (readiness checks)
+    pub fn check_metadata(&self) -> Result<()> {
+        self.db
+            .get(METADATA_PROBE_KEY)
+            .io_context("Metadata database isn't readable")?;
+        Ok(())
+    }
+
+    /// Populate the RocksDB block cache with the metadata of recently accessed objects
+    /// on a background thread (see `storage::warmup`)
+    pub fn spawn_warmup(&self, config: WarmupConfig) -> Result<()> {
+        warmup::spawn(Arc::downgrade(&self.db), config)
+    }
+
+    /// Re-hash objects against their checksums on a background thread, quarantining
+    /// corrupt ones (see `storage::scrub`)
+    pub fn spawn_scrub(&self, config: ScrubConfig, metrics: Arc<ScrubMetrics>) -> Result<()> {
+        self.check_writable()?;
+        scrub::spawn(
+            Arc::downgrade(&self.db),
+            self.objects_dir.clone(),
+            self.cold_tier.clone(),
+            self.totals.clone(),
+            config,
+            metrics,
+        )
+    }
+
+    /// Remove empty shard directories and compact the metadata database (see
+    /// `storage::compaction`)
+    pub fn compact(&self) -> Result<CompactStats> {
+        self.check_writable()?;
+        compaction::run(
+            &self.db,
+            &COLUMN_FAMILIES,
+            &self.objects_dirs(),
+            &self.chunks_dir(),
+            &self.metadata_dir(),
+        )
+    }
+
+    /// Compact every `interval` on a background thread
+    pub fn spawn_compaction(&self, interval: Duration) -> Result<()> {
+        self.check_writable()?;
+        compaction::spawn(
+            Arc::downgrade(&self.db),
+            COLUMN_FAMILIES.to_vec(),
+            self.objects_dirs(),
+            self.chunks_dir(),
+            self.metadata_dir(),
+            interval,
+        )
+    }
+
+    /// Copy every intact object, with its metadata, tags, pins and labels, into `target` (see
+    /// `storage::migrate`)
+    pub fn migrate_to(
+        &self,
+        target: &FilesystemStorage,
+        progress: impl FnMut(&MigrateStats),
+    ) -> Result<MigrateStats> {
+        migrate::run(
+            &self.db,
+            &self.objects_dir,
+            self.cold_tier.as_deref(),
+            |object| target.restore(object),
+            progress,
+        )
+    }
+
+    /// Hand every intact object to `store` (e.g. to migrate into a remote cache), see
+    /// `storage::migrate`
+    pub fn migrate_with(
+        &self,
+        store: impl FnMut(&MigrationObject) -> Result<bool>,
+        progress: impl FnMut(&MigrateStats),
+    ) -> Result<MigrateStats> {
+        migrate::run(
+            &self.db,
+            &self.objects_dir,
+            self.cold_tier.as_deref(),
+            store,
+            progress,
+        )
+    }
+
+    /// Store a migrated object as it was in its source cache, unless it's already stored
+    fn restore(&self, object: &MigrationObject) -> Result<bool> {
+        self.check_writable()?;
+        if self.exists(&object.id)? {
+            return Ok(false);
+        }
+        let metadata = &object.metadata;
+        let chunked = self.chunked(metadata.size);
+        let cold =
+            (self.cold_tier.as_deref()).filter(|cold| !chunked && !cold.fits_hot(metadata.size));
+        match cold {
+            Some(cold) => write_object(&object_path(&cold.objects_dir, &object.id), &object.data)?,
+            None => {
+                self.check_free_disk(metadata.size)?;
+                if chunked {
+                    dedup::store(&self.db, &self.chunks_dir(), &object.id, &object.data)?;
+                } else {
+                    write_object(&self.id_to_path(&object.id), &object.data)?;
+                }
+            }
+        }
+
+        self.totals.record(|| {
+            let cf_accessed = self
+                .db
+                .cf_handle(CF_INDEX_ACCESSED)
+                .ok_or_else(|| FabrikError::corrupt("Failed to get CF_INDEX_ACCESSED handle"))?;
+            let cf_access_count = self.db.cf_handle(CF_INDEX_ACCESS_COUNT).ok_or_else(|| {
+                FabrikError::corrupt("Failed to get CF_INDEX_ACCESS_COUNT handle")
+            })?;
+
+            let mut batch = rocksdb::WriteBatch::default();
+            batch.put(&object.id, metadata.to_bytes());
+            if metadata.access_count > 0 {
+                let (accessed_key, access_count_key) = index_keys(&object.id, metadata);
+                batch.put_cf(cf_accessed, accessed_key, b"");
+                batch.put_cf(cf_access_count, access_count_key, b"");
+            }
+            self.db
+                .write(batch)
+                .io_context("Failed to store migrated metadata")?;
+            Ok(((), Change::Added(metadata.size)))
+        })?;
+        if let Some(cold) = cold {
+            cold.settle(
+                &self.db,
+                &self.objects_dir,
+                &object.id,
+                None,
+                metadata.size,
+                true,
+            )?;
+        }
+
+        if let Some(tags) = &object.tags {
+            self.put_tags(&object.id, tags)?;
+        }
+        if !object.pins.is_empty() {
+            self.pin(&object.id, &object.pins)?;
+        }
+        if !object.labels.is_empty() {
+            labels::add(&self.db, &object.id, &object.labels)?;
+        }
+        Ok(true)
+    }
+
+    /// Write a consistent snapshot of the cache to `dir`, an empty directory (see
+    /// `storage::snapshot`)
+    pub fn snapshot(&self, dir: &Path) -> Result<Snapshot> {
+        snapshot::create(
+            &self.db,
+            &COLUMN_FAMILIES,
+            &self.objects_dir,
+            self.cold_tier.as_deref(),
+            dir,
+        )
+    }
+
+    /// Write the file of an object whose metadata was restored from a snapshot, in the
+    /// tier the metadata assigns it to
+    pub fn restore_object(&self, id: &[u8], data: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        if dedup::chunk_paths(&self.db, &self.chunks_dir(), id)?.is_some() {
+            return dedup::restore(&self.db, &self.chunks_dir(), id, data);
+        }
+        let (path, _) = self.locate(id)?;
+        write_object(&path, data)
+    }
+
+    fn metadata_dir(&self) -> PathBuf {
+        self.objects_dir.parent().unwrap().join("metadata")
+    }
+
+    /// Objects directories of the tiers
+    fn objects_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![self.objects_dir.clone()];
+        dirs.extend(self.cold_tier.iter().map(|cold| cold.objects_dir.clone()));
+        dirs
+    }
+
+    /// Scrubber over this storage, to be stepped on the current thread
+    #[cfg(test)]
+    pub(super) fn scrubber(&self, metrics: Arc<ScrubMetrics>) -> Scrubber {
+        Scrubber::new(
+            Arc::downgrade(&self.db),
+            self.objects_dir.clone(),
+            self.cold_tier.clone(),
+            self.totals.clone(),
+            metrics,
+        )
+    }
+
+    /// Run the warm-up on the current thread
+    #[allow(dead_code)]
+    pub fn warm_up(&self, config: &WarmupConfig) -> Result<WarmupStats> {
+        warmup::run(&Arc::downgrade(&self.db), config)
+            .map(|stats| stats.expect("storage is open while borrowed"))
+    }
 }
 
 /// Implementation of EvictableStorage for background eviction
@@ -460,6 +1129,14 @@ impl EvictableStorage for FilesystemStorage {
     fn delete_object(&self, id: &[u8]) -> Result<()> {
         self.delete(id)
     }
+
+    fn demote_hot_tier(&self, policy: &dyn EvictionPolicy) -> Result<(usize, u64)> {
+        FilesystemStorage::demote_hot_tier(self, policy)
+    }
+
+    fn free_disk_space(&self) -> Result<Option<u64>> {
+        disk::free_space(&self.objects_dir)
+    }
 }
 
 impl Drop for FilesystemStorage {
@@ -487,8 +1164,11 @@ impl Drop for FilesystemStorage {
         }
 
         // Step 2: Flush any pending writes to ensure data consistency
-        if let Err(e) = self.db.flush() {
-            eprintln!("Warning: Failed to flush RocksDB on shutdown: {}", e);
+        // (a read-only database has nothing to flush)
+        if !self.read_only {
+            if let Err(e) = self.db.flush() {
+                eprintln!("Warning: Failed to flush RocksDB on shutdown: {}", e);
+            }
         }
 
         // Step 3: Cancel all background work to ensure clean shutdown
@@ -503,92 +1183,119 @@ impl Drop for FilesystemStorage {
 
 impl Storage for FilesystemStorage {
     fn put(&self, id: &[u8], data: &[u8]) -> Result<()> {
-        // Note: Eviction is handled by a background task (spawn_background_eviction)
-        // to avoid blocking put() operations. The background task periodically
-        // checks cache size and evicts objects according to the configured policy.
-
-        let path = self.id_to_path(id);
-
-        // Create parent directory
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent).context("Failed to create parent directory")?;
-        }
-
-        // Write data atomically (write to temp file, then rename)
-        // Use PID + thread ID to avoid collisions in concurrent writes
-        let temp_name = format!(
-            "{}.tmp.{}.{:?}",
-            path.file_name().unwrap().to_str().unwrap(),
-            std::process::id(),
-            thread::current().id()
-        );
-        let temp_path = path.parent().unwrap().join(temp_name);
-
-        let mut file = fs::File::create(&temp_path).context("Failed to create temp file")?;
-        file.write_all(data).context("Failed to write data")?;
-        file.sync_all().context("Failed to sync file")?;
-        fs::rename(&temp_path, &path).context("Failed to rename temp file")?;
-
-        // Update metadata in RocksDB
-        let now = Self::current_timestamp();
-        let size = data.len() as u64;
-
-        // Check if object already exists to preserve access_count
-        let access_count = if let Some(existing_bytes) = self.db.get(id)? {
-            ObjectMetadata::from_bytes(&existing_bytes)
-                .map(|m| m.access_count)
-                .unwrap_or(0)
-        } else {
-            0
-        };
-
-        let metadata = ObjectMetadata {
-            size,
-            created_at: now,
-            accessed_at: now,
-            access_count,
-        };
+        self.put_with_expiry(id, data, None)
+    }
 
-        self.db
-            .put(id, metadata.to_bytes())
-            .context("Failed to update metadata")?;
+    fn put_with_expiry(&self, id: &[u8], data: &[u8], expires_at: Option<i64>) -> Result<()> {
+        self.put_object(id, Contents::Data(data), expires_at)
+    }
 
-        Ok(())
+    fn put_file(&self, id: &[u8], path: &Path, expires_at: Option<i64>) -> Result<()> {
+        let size = fs::metadata(path).io_context("Failed to read file")?.len();
+        self.put_object(id, Contents::File { path, size }, expires_at)
     }
 
     fn get(&self, id: &[u8]) -> Result<Option<Vec<u8>>> {
-        let path = self.id_to_path(id);
+        let (path, cold) = self.locate(id)?;
 
         if !path.exists() {
-            return Ok(None);
+            let Some(paths) = dedup::chunk_paths(&self.db, &self.chunks_dir(), id)? else {
+                return Ok(None);
+            };
+            return match dedup::read_chunks(&paths) {
+                Ok(data) => {
+                    self.touch(id)?;
+                    Ok(Some(data))
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e).io_context("Failed to read chunk"),
+            };
         }
 
         // Read data
-        let data = fs::read(&path).context("Failed to read object")?;
+        let data = fs::read(&path).io_context("Failed to read object")?;
 
         // Update access metadata asynchronously (non-blocking)
         self.touch(id)?;
 
+        if let Some(tier) = self.cold_tier.as_deref().filter(|_| cold) {
+            self.promote(tier, id, &data);
+        }
+
         Ok(Some(data))
     }
 
+    /// Cold objects are read from the cold tier without being promoted
+    fn open(&self, id: &[u8]) -> Result<Option<Box<dyn std::io::Read + Send>>> {
+        let (path, _) = self.locate(id)?;
+        let reader: Box<dyn std::io::Read + Send> = match fs::File::open(&path) {
+            Ok(file) => Box::new(file),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                match dedup::chunk_paths(&self.db, &self.chunks_dir(), id)? {
+                    Some(paths) => Box::new(dedup::ChunkReader::new(paths)),
+                    None => return Ok(None),
+                }
+            }
+            Err(e) => return Err(e).io_context("Failed to open object"),
+        };
+        self.touch(id)?;
+        Ok(Some(reader))
+    }
+
+    /// Existence is answered by the metadata, without touching the filesystem. Objects
+    /// removed behind the cache's back count as stored until the scrubber drops their
+    /// metadata (see `storage::scrub`).
     fn exists(&self, id: &[u8]) -> Result<bool> {
-        let path = self.id_to_path(id);
-        Ok(path.exists())
+        Ok(self.db.get_pinned(id)?.is_some())
+    }
+
+    fn exists_many(&self, ids: &[Vec<u8>]) -> Result<Vec<bool>> {
+        self.db
+            .multi_get(ids)
+            .into_iter()
+            .map(|metadata| Ok(metadata?.is_some()))
+            .collect()
     }
 
     fn delete(&self, id: &[u8]) -> Result<()> {
-        let path = self.id_to_path(id);
+        self.check_writable()?;
+        let (path, cold) = self.locate(id)?;
 
-        // Delete file
+        // Delete file, or the object's references to its chunks
         if path.exists() {
-            fs::remove_file(&path).context("Failed to delete object")?;
+            fs::remove_file(&path).io_context("Failed to delete object")?;
         }
+        dedup::release(&self.db, &self.chunks_dir(), id)?;
 
         // Delete metadata from RocksDB
-        self.db.delete(id).context("Failed to delete metadata")?;
-
-        Ok(())
+        let previous_size = self.totals.record(|| {
+            let previous = match self.db.get(id)? {
+                Some(existing_bytes) => ObjectMetadata::from_bytes(&existing_bytes).ok(),
+                None => None,
+            };
+            self.db.delete(id).io_context("Failed to delete metadata")?;
+            if let Some(previous) = &previous {
+                delete_index_entries(&self.db, id, previous)?;
+            }
+            if let Some(cf) = self.db.cf_handle(CF_ENTRY_TAGS) {
+                self.db
+                    .delete_cf(cf, id)
+                    .io_context("Failed to delete entry tags")?;
+            }
+            if let Some(cf) = self.db.cf_handle(CF_PINS) {
+                self.db
+                    .delete_cf(cf, id)
+                    .io_context("Failed to delete pins")?;
+            }
+            labels::delete(&self.db, id)?;
+            let previous_size = previous.as_ref().map(|m| m.size);
+            Ok((previous_size, Change::delete(previous.as_ref())))
+        })?;
+
+        match self.cold_tier.as_deref().filter(|_| cold) {
+            Some(tier) => tier.forget(&self.db, id, previous_size),
+            None => Ok(()),
+        }
     }
 
     fn size(&self, id: &[u8]) -> Result<Option<u64>> {
@@ -600,7 +1307,52 @@ impl Storage for FilesystemStorage {
         }
     }
 
+    fn created_at(&self, id: &[u8]) -> Result<Option<i64>> {
+        match self.db.get(id)? {
+            Some(metadata_bytes) => Ok(Some(
+                ObjectMetadata::from_bytes(&metadata_bytes)?.created_at,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn info(&self, id: &[u8]) -> Result<Option<ObjectInfo>> {
+        let Some(metadata_bytes) = self.db.get(id)? else {
+            return Ok(None);
+        };
+        let metadata = ObjectMetadata::from_bytes(&metadata_bytes)?;
+        let tags = self.get_tags(id)?;
+        let pins = match self.db.cf_handle(CF_PINS) {
+            Some(cf) => match self.db.get_cf(cf, id)? {
+                Some(bytes) => pins::decode(&bytes)?.len() as u64,
+                None => 0,
+            },
+            None => 0,
+        };
+
+        Ok(Some(ObjectInfo {
+            size: metadata.size,
+            created_at: Some(metadata.created_at),
+            accessed_at: Some(metadata.accessed_at),
+            access_count: Some(metadata.access_count),
+            expires_at: metadata.expires_at,
+            compression: Compression::None,
+            origin: tags
+                .as_ref()
+                .and_then(|tags| tags.origin)
+                .unwrap_or_default(),
+            tags,
+            labels: self.get_labels(id)?,
+            pinned: self.pinned_ids()?.contains(id),
+            pins,
+        }))
+    }
+
     fn touch(&self, id: &[u8]) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
         // Send to async batch worker (non-blocking)
         let msg = TouchMessage {
             id: id.to_vec(),
@@ -614,6 +1366,24 @@ impl Storage for FilesystemStorage {
         Ok(())
     }
 
+    /// Written as one batch right away: queued one by one, large batches would
+    /// overflow the channel and be dropped
+    fn touch_many(&self, ids: &[Vec<u8>]) -> Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        let timestamp = Self::current_timestamp();
+        let batch: Vec<TouchMessage> = ids
+            .iter()
+            .map(|id| TouchMessage {
+                id: id.clone(),
+                timestamp,
+            })
+            .collect();
+        Self::batch_touch(&self.db, &batch)
+    }
+
     fn list_ids(&self) -> Result<Vec<Vec<u8>>> {
         let mut ids = Vec::new();
         let iter = self.db.iterator(rocksdb::IteratorMode::Start);
@@ -627,18 +1397,8 @@ impl Storage for FilesystemStorage {
     }
 
     fn stats(&self) -> Result<StorageStats> {
-        let mut total_objects = 0u64;
-        let mut total_bytes = 0u64;
-
-        let iter = self.db.iterator(rocksdb::IteratorMode::Start);
-
-        for item in iter {
-            let (_, value) = item?;
-            if let Ok(metadata) = ObjectMetadata::from_bytes(&value) {
-                total_objects += 1;
-                total_bytes += metadata.size;
-            }
-        }
+        // Running totals, counted on first use (see `storage::partitions`)
+        let (total_objects, total_bytes) = self.totals.get(&self.db)?;
 
         Ok(StorageStats {
             total_objects,
@@ -646,6 +1406,199 @@ impl Storage for FilesystemStorage {
             cache_dir: self.objects_dir.parent().unwrap().to_path_buf(),
         })
     }
+
+    fn put_tags(&self, id: &[u8], tags: &EntryTags) -> Result<()> {
+        self.check_writable()?;
+        let cf = self
+            .db
+            .cf_handle(CF_ENTRY_TAGS)
+            .ok_or_else(|| FabrikError::corrupt("Failed to get CF_ENTRY_TAGS handle"))?;
+        self.db
+            .put_cf(cf, id, tags.to_bytes()?)
+            .io_context("Failed to store entry tags")
+    }
+
+    fn get_tags(&self, id: &[u8]) -> Result<Option<EntryTags>> {
+        let Some(cf) = self.db.cf_handle(CF_ENTRY_TAGS) else {
+            return Ok(None);
+        };
+        match self.db.get_cf(cf, id)? {
+            Some(bytes) => Ok(Some(EntryTags::from_bytes(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn add_labels(&self, id: &[u8], labels: &Labels) -> Result<()> {
+        self.check_writable()?;
+        if self.db.get(id)?.is_none() {
+            return Err(FabrikError::not_found(format!(
+                "Blob not found: {}",
+                hex::encode(id)
+            )));
+        }
+        labels::add(&self.db, id, labels)
+    }
+
+    fn get_labels(&self, id: &[u8]) -> Result<Labels> {
+        labels::get(&self.db, id)
+    }
+
+    fn find_by_labels(&self, labels: &Labels) -> Result<Vec<Vec<u8>>> {
+        labels::find(&self.db, labels)
+    }
+
+    fn pin(&self, holder: &[u8], ids: &[Vec<u8>]) -> Result<()> {
+        self.check_writable()?;
+        let cf = self
+            .db
+            .cf_handle(CF_PINS)
+            .ok_or_else(|| FabrikError::corrupt("Failed to get CF_PINS handle"))?;
+        if ids.is_empty() {
+            return self
+                .db
+                .delete_cf(cf, holder)
+                .io_context("Failed to delete pins");
+        }
+        self.db
+            .put_cf(cf, holder, pins::encode(ids))
+            .io_context("Failed to store pins")
+    }
+}
+
+/// Keys of an object in the access indexes: access time and access count, each
+/// followed by the ID (for range queries)
+pub(super) fn index_keys(id: &[u8], metadata: &ObjectMetadata) -> (Vec<u8>, Vec<u8>) {
+    let mut accessed_key = metadata.accessed_at.to_le_bytes().to_vec();
+    accessed_key.extend_from_slice(id);
+    let mut access_count_key = metadata.access_count.to_le_bytes().to_vec();
+    access_count_key.extend_from_slice(id);
+    (accessed_key, access_count_key)
+}
+
+/// Add an object to the access indexes
+fn put_index_entries(db: &DB, id: &[u8], metadata: &ObjectMetadata) -> Result<()> {
+    let (accessed_key, access_count_key) = index_keys(id, metadata);
+    if let Some(cf) = db.cf_handle(CF_INDEX_ACCESSED) {
+        db.put_cf(cf, accessed_key, b"")
+            .io_context("Failed to update index entry")?;
+    }
+    if let Some(cf) = db.cf_handle(CF_INDEX_ACCESS_COUNT) {
+        db.put_cf(cf, access_count_key, b"")
+            .io_context("Failed to update index entry")?;
+    }
+    Ok(())
+}
+
+/// Remove an object from the access indexes
+pub(super) fn delete_index_entries(db: &DB, id: &[u8], metadata: &ObjectMetadata) -> Result<()> {
+    let (accessed_key, access_count_key) = index_keys(id, metadata);
+    if let Some(cf) = db.cf_handle(CF_INDEX_ACCESSED) {
+        db.delete_cf(cf, accessed_key)
+            .io_context("Failed to delete index entry")?;
+    }
+    if let Some(cf) = db.cf_handle(CF_INDEX_ACCESS_COUNT) {
+        db.delete_cf(cf, access_count_key)
+            .io_context("Failed to delete index entry")?;
+    }
+    Ok(())
+}
+
+/// Path of an object in `objects_dir`
+///
+/// Uses git-style sharding: first 2 hex chars as subdirectory
+pub(super) fn object_path(objects_dir: &Path, id: &[u8]) -> PathBuf {
+    let hex_id = hex::encode(id);
+    let (prefix, suffix) = hex_id.split_at(2);
+    objects_dir.join(prefix).join(suffix)
+}
+
+/// Eviction candidate of an object
+fn eviction_candidate(id: &[u8], metadata: &ObjectMetadata) -> EvictionCandidate {
+    EvictionCandidate {
+        id: id.to_vec(),
+        size: metadata.size,
+        accessed_at: metadata.accessed_at,
+        access_count: metadata.access_count,
+        created_at: metadata.created_at,
+        expires_at: metadata.expires_at,
+    }
+}
+
+/// Contents of an object to store
+enum Contents<'a> {
+    Data(&'a [u8]),
+    /// A file streamed into the cache, so it isn't loaded into memory
+    File {
+        path: &'a Path,
+        size: u64,
+    },
+}
+
+impl Contents<'_> {
+    fn size(&self) -> u64 {
+        match self {
+            Contents::Data(data) => data.len() as u64,
+            Contents::File { size, .. } => *size,
+        }
+    }
+
+    fn read(&self) -> Result<std::borrow::Cow<'_, [u8]>> {
+        match self {
+            Contents::Data(data) => Ok((*data).into()),
+            Contents::File { path, .. } => {
+                Ok(fs::read(path).io_context("Failed to read file")?.into())
+            }
+        }
+    }
+
+    /// Write the object to `path`, returning its checksum
+    fn write(&self, path: &Path, algorithm: HashAlgorithm) -> Result<[u8; 32]> {
+        match self {
+            Contents::Data(data) => {
+                write_object(path, data)?;
+                Ok(algorithm.digest(data))
+            }
+            Contents::File { path: source, .. } => {
+                let file = fs::File::open(source).io_context("Failed to open file")?;
+                let mut reader = HashingReader::new(file, algorithm);
+                write_object_from(path, &mut reader)?;
+                Ok(reader.finalize())
+            }
+        }
+    }
+}
+
+/// Write an object atomically (to a temp file, then renamed into place)
+pub(super) fn write_object(path: &Path, data: &[u8]) -> Result<()> {
+    write_object_from(path, &mut &*data)
+}
+
+/// Write an object atomically from `reader`
+pub(super) fn write_object_from(path: &Path, reader: &mut dyn Read) -> Result<()> {
+    let parent = path.parent().unwrap();
+    fs::create_dir_all(parent).io_context("Failed to create parent directory")?;
+
+    // Use PID + thread ID to avoid collisions in concurrent writes
+    let temp_name = format!(
+        "{}.tmp.{}.{:?}",
+        path.file_name().unwrap().to_str().unwrap(),
+        std::process::id(),
+        thread::current().id()
+    );
+    let temp_path = parent.join(temp_name);
+
+    // Compaction may have removed the (empty) shard directory since it was created
+    let mut file = match fs::File::create(&temp_path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            fs::create_dir_all(parent).io_context("Failed to create parent directory")?;
+            fs::File::create(&temp_path)
+        }
+        result => result,
+    }
+    .io_context("Failed to create temp file")?;
+    std::io::copy(reader, &mut file).io_context("Failed to write data")?;
+    file.sync_all().io_context("Failed to sync file")?;
+    fs::rename(&temp_path, path).io_context("Failed to rename temp file")
 }
 
 /// Hash data using SHA256
@@ -659,8 +1612,142 @@ pub fn hash_data(data: &[u8]) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::Origin;
     use tempfile::TempDir;
 
+    #[test]
+    fn test_entry_tags_deleted_with_object() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+        let id = hash_data(b"tagged");
+        let tags = EntryTags {
+            build_system: Some("gradle".to_string()),
+            machine: Some("Linux/amd64/Eclipse Adoptium 17".to_string()),
+            origin: None,
+        };
+
+        storage.put(&id, b"tagged").unwrap();
+        assert_eq!(storage.get_tags(&id).unwrap(), None);
+        storage.put_tags(&id, &tags).unwrap();
+        assert_eq!(storage.get_tags(&id).unwrap(), Some(tags));
+
+        storage.delete(&id).unwrap();
+        assert_eq!(storage.get_tags(&id).unwrap(), None);
+    }
+
+    #[test]
+    fn test_pinned_blobs_not_evicted_while_holder_stored() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+        let holder = b"action_cache:main:abc:10".to_vec();
+        let blob = b"cas:def:4".to_vec();
+        storage.put(&holder, b"result").unwrap();
+        storage.put(&blob, b"blob").unwrap();
+
+        let candidate_ids = |storage: &FilesystemStorage| -> Vec<Vec<u8>> {
+            let mut ids: Vec<_> = storage
+                .get_eviction_candidates()
+                .unwrap()
+                .into_iter()
+                .map(|c| c.id)
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        storage.pin(&holder, std::slice::from_ref(&blob)).unwrap();
+        assert_eq!(candidate_ids(&storage), vec![holder.clone()]);
+
+        // Evicting the holder releases its pins
+        storage.delete(&holder).unwrap();
+        assert!(storage.pinned_ids().unwrap().is_empty());
+        assert_eq!(candidate_ids(&storage), vec![blob]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_puts_rejected_when_volume_nearly_full() {
+        let temp_dir = TempDir::new().unwrap();
+        let free = disk::free_space(temp_dir.path()).unwrap().unwrap();
+
+        let roomy = EvictionConfig::default()
+            .with_min_free_disk(Some("1MB"))
+            .unwrap();
+        let storage = FilesystemStorage::with_eviction(temp_dir.path(), Some(roomy)).unwrap();
+        storage.put(&hash_data(b"fits"), b"fits").unwrap();
+        assert_eq!(
+            storage.free_disk_space().unwrap().map(|f| f > 0),
+            Some(true)
+        );
+        drop(storage);
+
+        // Asking for more free space than the volume has rejects every write
+        let full = EvictionConfig {
+            min_free_disk_bytes: free.saturating_mul(4),
+            ..Default::default()
+        };
+        let storage = FilesystemStorage::with_eviction(temp_dir.path(), Some(full)).unwrap();
+        let err = storage
+            .put(&hash_data(b"rejected"), b"rejected")
+            .unwrap_err();
+        assert!(matches!(err, FabrikError::QuotaExceeded(_)));
+        assert!(err.to_string().contains("nearly full"));
+    }
+
+    #[test]
+    fn test_exists_many() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+        let stored = hash_data(b"stored");
+        let deleted = hash_data(b"deleted");
+        storage.put(&stored, b"stored").unwrap();
+        storage.put(&deleted, b"deleted").unwrap();
+        storage.delete(&deleted).unwrap();
+
+        let ids = vec![deleted, stored.clone(), hash_data(b"never stored"), stored];
+        assert_eq!(
+            storage.exists_many(&ids).unwrap(),
+            vec![false, true, false, true]
+        );
+        assert!(storage.exists_many(&[]).unwrap().is_empty());
+    }
+
+    /// FindMissingBlobs-sized existence checks: one stat per blob vs a batched metadata
+    /// lookup. Run with `cargo test --release bench_exists_many -- --ignored --nocapture`
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_exists_many() {
+        const BLOBS: usize = 10_000;
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+        let ids: Vec<Vec<u8>> = (0..BLOBS)
+            .map(|i| hash_data(i.to_string().as_bytes()))
+            .collect();
+        for id in ids.iter().step_by(2) {
+            storage.put(id, b"blob").unwrap();
+        }
+
+        let start = Instant::now();
+        let stats: Vec<bool> = ids
+            .iter()
+            .map(|id| storage.id_to_path(id).exists())
+            .collect();
+        let stat_time = start.elapsed();
+
+        let start = Instant::now();
+        let batched = storage.exists_many(&ids).unwrap();
+        let batched_time = start.elapsed();
+
+        assert_eq!(stats, batched);
+        eprintln!(
+            "{} blobs: filesystem stat {:?}, batched metadata lookup {:?} ({:.1}x)",
+            BLOBS,
+            stat_time,
+            batched_time,
+            stat_time.as_secs_f64() / batched_time.as_secs_f64()
+        );
+    }
+
     #[test]
     fn test_filesystem_storage() {
         let temp_dir = TempDir::new().unwrap();
@@ -688,4 +1775,639 @@ mod tests {
         storage.delete(&id).unwrap();
         assert!(!storage.exists(&id).unwrap());
     }
+
+    #[test]
+    fn test_totals_track_writes_and_deletes() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+
+        // Spread over every partition, written before and after the first count
+        let ids: Vec<Vec<u8>> = (0..64u8).map(|i| hash_data(&[i])).collect();
+        for (i, id) in ids[..32].iter().enumerate() {
+            storage.put(id, &vec![0; i]).unwrap();
+        }
+        assert_eq!(storage.stats().unwrap().total_objects, 32);
+        for (i, id) in ids[32..].iter().enumerate() {
+            storage.put(id, &vec![0; 32 + i]).unwrap();
+        }
+
+        storage.put(&ids[0], b"rewritten").unwrap();
+        storage.delete(&ids[1]).unwrap();
+        storage.delete(&hash_data(b"never stored")).unwrap();
+
+        let stats = storage.stats().unwrap();
+        let counted = ObjectTotals::default().get(&storage.db).unwrap();
+        assert_eq!((stats.total_objects, stats.total_bytes), counted);
+        assert_eq!(counted, (63, (2..64).sum::<u64>() + 9));
+
+        let mut candidates: Vec<Vec<u8>> = storage
+            .get_eviction_candidates()
+            .unwrap()
+            .into_iter()
+            .map(|candidate| candidate.id)
+            .collect();
+        candidates.sort();
+        let mut expected: Vec<Vec<u8>> = ids
+            .into_iter()
+            .filter(|id| *id != hash_data(&[1]))
+            .collect();
+        expected.sort();
+        assert_eq!(candidates, expected);
+    }
+
+    #[test]
+    fn test_warm_up_reads_recently_accessed_objects() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+
+        let ids: Vec<Vec<u8>> = (0..3u8).map(|i| hash_data(&[i])).collect();
+        for id in &ids {
+            storage.put(id, b"data").unwrap();
+        }
+
+        // Access two of them (written to the index by the batch worker)
+        let now = FilesystemStorage::current_timestamp();
+        FilesystemStorage::batch_touch(
+            &storage.db,
+            &[
+                TouchMessage {
+                    id: ids[0].clone(),
+                    timestamp: now,
+                },
+                TouchMessage {
+                    id: ids[1].clone(),
+                    timestamp: now - 30 * 24 * 60 * 60,
+                },
+            ],
+        )
+        .unwrap();
+
+        let config = WarmupConfig::parse("7d", "1GB").unwrap();
+        let stats = storage.warm_up(&config).unwrap();
+        assert_eq!(stats.objects, 1);
+        assert!(stats.bytes_read > 0);
+
+        let config = WarmupConfig::parse("60d", "1GB").unwrap();
+        assert_eq!(storage.warm_up(&config).unwrap().objects, 2);
+    }
+
+    #[test]
+    fn test_batch_touch_counts_daily_accesses() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+
+        let hot = hash_data(b"hot");
+        let cold = hash_data(b"cold");
+        storage.put(&hot, b"hot artifact").unwrap();
+        storage.put(&cold, b"cold").unwrap();
+
+        let now = FilesystemStorage::current_timestamp();
+        let touch = |id: &Vec<u8>| TouchMessage {
+            id: id.clone(),
+            timestamp: now,
+        };
+        FilesystemStorage::batch_touch(&storage.db, &[touch(&hot), touch(&cold), touch(&hot)])
+            .unwrap();
+        FilesystemStorage::batch_touch(&storage.db, &[touch(&hot)]).unwrap();
+
+        let popular = popularity::popular(&storage.db, popularity::day_of(now), 7, 10).unwrap();
+        assert_eq!(popular.len(), 2);
+        assert_eq!(popular[0].id, hot);
+        assert_eq!(popular[0].hits, 3);
+        assert_eq!(popular[0].bytes_served, 3 * 12);
+        assert_eq!(popular[0].size, Some(12));
+
+        // Counters outlive the artifact
+        storage.delete(&hot).unwrap();
+        let popular = popularity::popular(&storage.db, popularity::day_of(now), 7, 1).unwrap();
+        assert_eq!((popular[0].hits, popular[0].size), (3, None));
+    }
+
+    #[test]
+    fn test_access_indexes_hold_latest_access() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+        let id = hash_data(b"indexed");
+        storage.put(&id, b"indexed").unwrap();
+
+        let touch = |timestamp| TouchMessage {
+            id: id.clone(),
+            timestamp,
+        };
+        FilesystemStorage::batch_touch(&storage.db, &[touch(10), touch(20)]).unwrap();
+        FilesystemStorage::batch_touch(&storage.db, &[touch(30)]).unwrap();
+
+        let keys = |name: &str| -> Vec<Vec<u8>> {
+            let cf = storage.db.cf_handle(name).unwrap();
+            storage
+                .db
+                .iterator_cf(cf, rocksdb::IteratorMode::Start)
+                .map(|item| item.unwrap().0.to_vec())
+                .collect()
+        };
+        let metadata = ObjectMetadata::from_bytes(&storage.db.get(&id).unwrap().unwrap()).unwrap();
+        assert_eq!((metadata.accessed_at, metadata.access_count), (30, 3));
+        let (accessed_key, access_count_key) = index_keys(&id, &metadata);
+        assert_eq!(keys(CF_INDEX_ACCESSED), vec![accessed_key]);
+        assert_eq!(keys(CF_INDEX_ACCESS_COUNT), vec![access_count_key]);
+
+        storage.delete(&id).unwrap();
+        assert!(keys(CF_INDEX_ACCESSED).is_empty());
+        assert!(keys(CF_INDEX_ACCESS_COUNT).is_empty());
+    }
+
+    #[test]
+    fn test_touch_many_records_accesses_in_one_batch() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+        let ids: Vec<Vec<u8>> = (0..2000)
+            .map(|i| {
+                let data = format!("blob {}", i);
+                let id = hash_data(data.as_bytes());
+                storage.put(&id, data.as_bytes()).unwrap();
+                id
+            })
+            .collect();
+
+        // More than the touch channel holds
+        storage.touch_many(&ids).unwrap();
+        for id in [&ids[0], &ids[1999]] {
+            let metadata =
+                ObjectMetadata::from_bytes(&storage.db.get(id).unwrap().unwrap()).unwrap();
+            assert_eq!(metadata.access_count, 1);
+        }
+    }
+
+    #[test]
+    fn test_info_reports_metadata_and_pins() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+        let holder = hash_data(b"holder");
+        let blob = hash_data(b"blob");
+        storage.put(&holder, b"holder").unwrap();
+        storage.put(&blob, b"blob").unwrap();
+        storage.pin(&holder, std::slice::from_ref(&blob)).unwrap();
+        let tags = EntryTags {
+            origin: Some(Origin::Upstream),
+            ..EntryTags::default()
+        };
+        storage.put_tags(&blob, &tags).unwrap();
+        FilesystemStorage::batch_touch(
+            &storage.db,
+            &[TouchMessage {
+                id: blob.clone(),
+                timestamp: 42,
+            }],
+        )
+        .unwrap();
+
+        let info = storage.info(&blob).unwrap().unwrap();
+        assert_eq!(info.size, 4);
+        assert_eq!((info.accessed_at, info.access_count), (Some(42), Some(1)));
+        assert_eq!(info.origin, Origin::Upstream);
+        assert!(info.pinned);
+        assert_eq!(info.pins, 0);
+
+        let info = storage.info(&holder).unwrap().unwrap();
+        assert_eq!(info.origin, Origin::Local);
+        assert!(!info.pinned);
+        assert_eq!(info.pins, 1);
+        assert_eq!(info.access_count, Some(0));
+
+        assert!(storage.info(&hash_data(b"absent")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_put_with_expiry() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+        let id = hash_data(b"short-lived");
+        storage
+            .put_with_expiry(&id, b"short-lived", Some(1234))
+            .unwrap();
+        assert_eq!(storage.info(&id).unwrap().unwrap().expires_at, Some(1234));
+        let candidates = storage.get_eviction_candidates().unwrap();
+        assert_eq!(candidates[0].expires_at, Some(1234));
+
+        // Storing it again without a TTL goes back to the default one
+        storage.put(&id, b"short-lived").unwrap();
+        assert_eq!(storage.info(&id).unwrap().unwrap().expires_at, None);
+
+        for (checksum, checksum_algorithm) in [
+            (None, HashAlgorithm::Sha256),
+            (Some([7u8; 32]), HashAlgorithm::Sha256),
+            (Some([7u8; 32]), HashAlgorithm::Blake3),
+        ] {
+            let metadata = ObjectMetadata {
+                size: 1,
+                created_at: 2,
+                accessed_at: 3,
+                access_count: 4,
+                checksum,
+                checksum_algorithm,
+                expires_at: Some(-5),
+            };
+            let decoded = ObjectMetadata::from_bytes(&metadata.to_bytes()).unwrap();
+            assert_eq!(decoded.checksum, checksum);
+            assert_eq!(decoded.checksum_algorithm, checksum_algorithm);
+            assert_eq!(decoded.expires_at, Some(-5));
+        }
+        assert!(ObjectMetadata::from_bytes(&[0; 48]).is_err());
+        assert!(ObjectMetadata::from_bytes(&[0; 33]).is_err());
+    }
+
+    #[test]
+    fn test_put_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path().join("cache")).unwrap();
+        let source = temp_dir.path().join("artifact.bin");
+        fs::write(&source, b"streamed content").unwrap();
+
+        let id = hash_data(b"streamed content");
+        storage.put_file(&id, &source, Some(1234)).unwrap();
+        assert_eq!(
+            storage.get(&id).unwrap(),
+            Some(b"streamed content".to_vec())
+        );
+        let info = storage.info(&id).unwrap().unwrap();
+        assert_eq!(info.size, 16);
+        assert_eq!(info.expires_at, Some(1234));
+        let metadata = ObjectMetadata::from_bytes(&storage.db.get(&id).unwrap().unwrap()).unwrap();
+        assert_eq!(
+            metadata.checksum,
+            Some(HashAlgorithm::Sha256.digest(b"streamed content"))
+        );
+        // The source is copied, not moved
+        assert!(source.exists());
+    }
+
+    #[test]
+    fn test_read_only_rejects_changes() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(matches!(
+            FilesystemStorage::read_only(temp_dir.path()),
+            Err(FabrikError::Config(_))
+        ));
+
+        let id = hash_data(b"golden");
+        FilesystemStorage::new(temp_dir.path())
+            .unwrap()
+            .put(&id, b"golden")
+            .unwrap();
+
+        let storage = FilesystemStorage::read_only(temp_dir.path()).unwrap();
+        let other = FilesystemStorage::read_only(temp_dir.path()).unwrap();
+        assert_eq!(storage.get(&id).unwrap().unwrap(), b"golden");
+        assert_eq!(other.get(&id).unwrap().unwrap(), b"golden");
+        assert_eq!(storage.stats().unwrap().total_objects, 1);
+
+        let other_id = hash_data(b"new");
+        assert!(matches!(
+            storage.put(&other_id, b"new"),
+            Err(FabrikError::ReadOnly(_))
+        ));
+        assert!(matches!(storage.delete(&id), Err(FabrikError::ReadOnly(_))));
+        assert!(storage.pin(&id, &[]).is_err());
+        assert!(storage.compact().is_err());
+        assert!(!storage.exists(&other_id).unwrap());
+        assert!(storage.exists(&id).unwrap());
+    }
+
+    #[test]
+    fn test_cold_tier() {
+        let temp_dir = TempDir::new().unwrap();
+        let cold_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path())
+            .unwrap()
+            .with_cold_tier(TierConfig {
+                cold_dir: cold_dir.path().to_path_buf(),
+                hot_max_bytes: 100,
+                hot_max_object_bytes: 40,
+            })
+            .unwrap();
+        let cold = storage.cold_tier.clone().unwrap();
+        let in_cold = |id: &[u8]| {
+            let cold_tier = cold.contains(&storage.db, id).unwrap();
+            assert_eq!(object_path(&cold.objects_dir, id).exists(), cold_tier);
+            assert_eq!(storage.id_to_path(id).exists(), !cold_tier);
+            cold_tier
+        };
+
+        // Objects over hot_max_object_size go straight to the cold tier
+        let large = hash_data(b"large");
+        storage.put(&large, &[1; 50]).unwrap();
+        assert!(in_cold(&large));
+        assert_eq!(storage.get(&large).unwrap().unwrap(), vec![1; 50]);
+        assert!(in_cold(&large));
+
+        // Past hot_max_size, objects are demoted until the hot tier is under 90% of it
+        let hot: Vec<_> = (0..3u8).map(|i| hash_data(&[i])).collect();
+        for id in &hot {
+            storage.put(id, &[2; 40]).unwrap();
+        }
+        assert_eq!(storage.hot_bytes().unwrap(), 120);
+        assert_eq!(
+            storage
+                .demote_hot_tier(&crate::eviction::LruPolicy)
+                .unwrap(),
+            (1, 40)
+        );
+        assert_eq!(storage.hot_bytes().unwrap(), 80);
+        assert_eq!(storage.stats().unwrap().total_bytes, 170);
+
+        // Reading a demoted object promotes it once the hot tier has room
+        let demoted = hot.iter().find(|id| in_cold(id)).unwrap().clone();
+        assert_eq!(storage.get(&demoted).unwrap().unwrap(), vec![2; 40]);
+        assert!(in_cold(&demoted));
+        for id in hot.iter().filter(|id| **id != demoted) {
+            storage.delete(id).unwrap();
+        }
+        assert_eq!(storage.get(&demoted).unwrap().unwrap(), vec![2; 40]);
+        assert!(!in_cold(&demoted));
+
+        // Rewriting an object small enough moves it to the hot tier; deleting one in the
+        // cold tier removes it from there
+        storage.put(&large, &[3; 10]).unwrap();
+        assert!(!in_cold(&large));
+        storage.put(&large, &[4; 60]).unwrap();
+        assert!(in_cold(&large));
+        storage.delete(&large).unwrap();
+        assert!(!object_path(&cold.objects_dir, &large).exists());
+        assert!(!cold.contains(&storage.db, &large).unwrap());
+        assert_eq!(cold.bytes(&storage.db).unwrap(), 0);
+        assert_eq!(storage.hot_bytes().unwrap(), 40);
+    }
+
+    #[test]
+    fn test_labels_find_and_delete() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+        let ios = hash_data(b"ios");
+        let android = hash_data(b"android");
+        storage.put(&ios, b"ios").unwrap();
+        storage.put(&android, b"android").unwrap();
+
+        let main = labels::parse_all(&["branch=main"]).unwrap();
+        storage
+            .add_labels(
+                &ios,
+                &labels::parse_all(&["branch=main", "target=ios"]).unwrap(),
+            )
+            .unwrap();
+        storage
+            .add_labels(&android, &labels::parse_all(&["branch=feature"]).unwrap())
+            .unwrap();
+        assert_eq!(storage.find_by_labels(&main).unwrap(), vec![ios.clone()]);
+        assert!(storage.add_labels(&hash_data(b"absent"), &main).is_err());
+
+        // Relabelling moves the blob to the new value
+        storage.add_labels(&android, &main).unwrap();
+        let mut found = storage.find_by_labels(&main).unwrap();
+        found.sort();
+        let mut expected = vec![ios.clone(), android.clone()];
+        expected.sort();
+        assert_eq!(found, expected);
+        let feature = labels::parse_all(&["branch=feature"]).unwrap();
+        assert!(storage.find_by_labels(&feature).unwrap().is_empty());
+        let ios_main = labels::parse_all(&["branch=main", "target=ios"]).unwrap();
+        assert_eq!(
+            storage.find_by_labels(&ios_main).unwrap(),
+            vec![ios.clone()]
+        );
+
+        storage.delete(&ios).unwrap();
+        assert_eq!(
+            storage.find_by_labels(&main).unwrap(),
+            vec![android.clone()]
+        );
+        storage.put(&ios, b"ios").unwrap();
+        assert!(storage.get_labels(&ios).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_compact_removes_empty_shard_dirs() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+
+        let evicted = hash_data(b"evicted");
+        let kept = hash_data(b"kept");
+        assert_ne!(evicted[0], kept[0], "objects must be in different shards");
+        storage.put(&evicted, b"evicted").unwrap();
+        storage.put(&kept, b"kept").unwrap();
+        storage.delete(&evicted).unwrap();
+
+        let stats = storage.compact().unwrap();
+        assert_eq!(stats.shard_dirs_removed, 1);
+        assert!(!storage.id_to_path(&evicted).parent().unwrap().exists());
+        assert_eq!(storage.get(&kept).unwrap(), Some(b"kept".to_vec()));
+
+        // Writes recreate the shard directory
+        storage.put(&evicted, b"evicted").unwrap();
+        assert_eq!(storage.get(&evicted).unwrap(), Some(b"evicted".to_vec()));
+        assert_eq!(storage.compact().unwrap().shard_dirs_removed, 0);
+    }
+
+    #[test]
+    fn test_dedup_shares_chunks() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path())
+            .unwrap()
+            .with_dedup(true);
+        let chunk_files = || -> Vec<PathBuf> {
+            let Ok(shards) = fs::read_dir(storage.chunks_dir()) else {
+                return Vec::new();
+            };
+            shards
+                .flat_map(|shard| fs::read_dir(shard.unwrap().path()).unwrap())
+                .map(|file| file.unwrap().path())
+                .collect()
+        };
+
+        // Two similar artifacts: the second has a few bytes inserted near the start
+        let mut state: u64 = 7;
+        let first: Vec<u8> = (0..12 * 1024 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let mut second = b"inserted".to_vec();
+        second.extend_from_slice(&first);
+        let (first_id, second_id) = (hash_data(&first), hash_data(&second));
+        storage.put(&first_id, &first).unwrap();
+        let first_chunks = chunk_files().len();
+        storage.put(&second_id, &second).unwrap();
+
+        assert!(!storage.id_to_path(&first_id).exists());
+        assert!(chunk_files().len() < first_chunks * 2);
+        assert_eq!(storage.get(&first_id).unwrap(), Some(first.clone()));
+        assert_eq!(storage.get(&second_id).unwrap(), Some(second.clone()));
+        assert_eq!(storage.size(&second_id).unwrap(), Some(second.len() as u64));
+        let mut streamed = Vec::new();
+        let mut reader = storage.open(&second_id).unwrap().unwrap();
+        std::io::Read::read_to_end(&mut reader, &mut streamed).unwrap();
+        assert_eq!(streamed, second);
+
+        // Small objects are stored whole
+        storage.put(b"small", b"small object").unwrap();
+        assert!(storage.id_to_path(b"small").exists());
+
+        // Chunks are deleted with the last object referencing them
+        storage.delete(&first_id).unwrap();
+        assert_eq!(storage.get(&second_id).unwrap(), Some(second));
+        storage.delete(&second_id).unwrap();
+        assert!(chunk_files().is_empty());
+
+        // Compaction collects chunks left without references
+        let orphan = object_path(&storage.chunks_dir(), &[7; 32]);
+        write_object(&orphan, b"orphan").unwrap();
+        assert_eq!(storage.compact().unwrap().chunks_removed, 1);
+        assert!(!orphan.exists());
+    }
+
+    #[test]
+    fn test_scrub_quarantines_corrupt_objects() {
+        use crate::storage::scrub::{ScrubOutcome, ScrubStep};
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+
+        let intact = hash_data(b"intact");
+        let corrupt = hash_data(b"corrupt");
+        let missing = hash_data(b"missing");
+        storage.put(&intact, b"intact").unwrap();
+        storage.put(&corrupt, b"corrupt").unwrap();
+        storage.put(&missing, b"missing").unwrap();
+        fs::write(storage.id_to_path(&corrupt), b"c0rrupt").unwrap();
+        fs::remove_file(storage.id_to_path(&missing)).unwrap();
+
+        let metrics = Arc::new(ScrubMetrics::new());
+        let mut scrubber = storage.scrubber(metrics.clone());
+        let mut outcomes = Vec::new();
+        while let Some(ScrubStep::Checked { outcome, .. }) = scrubber.step().unwrap() {
+            outcomes.push(outcome);
+        }
+        outcomes.sort_by_key(|o| format!("{:?}", o));
+        assert_eq!(
+            outcomes,
+            vec![
+                ScrubOutcome::Corrupt,
+                ScrubOutcome::Missing,
+                ScrubOutcome::Ok
+            ]
+        );
+
+        assert!(storage.exists(&intact).unwrap());
+        assert!(!storage.exists(&corrupt).unwrap());
+        assert!(!storage.exists(&missing).unwrap());
+        let quarantined: Vec<_> = fs::read_dir(temp_dir.path().join("quarantine"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(quarantined.len(), 1);
+        assert!(quarantined[0].starts_with(&hex::encode(&corrupt)));
+        assert_eq!(metrics.corrupt_total(), 1);
+        assert_eq!(metrics.missing_total(), 1);
+        assert_eq!(metrics.passes_total(), 1);
+    }
+
+    #[test]
+    fn test_scrub_records_missing_checksums() {
+        use crate::storage::scrub::{ScrubOutcome, ScrubStep};
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+
+        // Objects stored before checksums were recorded
+        let id = hash_data(b"legacy");
+        storage.put(&id, b"legacy").unwrap();
+        let mut metadata =
+            ObjectMetadata::from_bytes(&storage.db.get(&id).unwrap().unwrap()).unwrap();
+        metadata.checksum = None;
+        storage.db.put(&id, &metadata.to_bytes()[..32]).unwrap();
+
+        let mut scrubber = storage.scrubber(Arc::new(ScrubMetrics::new()));
+        assert!(matches!(
+            scrubber.step().unwrap(),
+            Some(ScrubStep::Checked {
+                outcome: ScrubOutcome::Unverified,
+                bytes: 6
+            })
+        ));
+        assert_eq!(scrubber.step().unwrap(), Some(ScrubStep::PassComplete));
+
+        // Verified against the recorded checksum on the next pass
+        assert!(matches!(
+            scrubber.step().unwrap(),
+            Some(ScrubStep::Checked {
+                outcome: ScrubOutcome::Ok,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_blake3_checksums() {
+        use crate::storage::scrub::{ScrubOutcome, ScrubStep};
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path())
+            .unwrap()
+            .with_hash_algorithm(HashAlgorithm::Blake3);
+        storage.put(b"blake3", b"content").unwrap();
+        let storage = storage.with_hash_algorithm(HashAlgorithm::Sha256);
+        storage.put(b"sha256", b"content").unwrap();
+
+        // Each object keeps the algorithm it was stored with
+        let metadata =
+            |id: &[u8]| ObjectMetadata::from_bytes(&storage.db.get(id).unwrap().unwrap()).unwrap();
+        assert_eq!(
+            metadata(b"blake3").checksum_algorithm,
+            HashAlgorithm::Blake3
+        );
+        assert_eq!(
+            metadata(b"blake3").checksum,
+            Some(HashAlgorithm::Blake3.digest(b"content"))
+        );
+        assert_eq!(
+            metadata(b"sha256").checksum_algorithm,
+            HashAlgorithm::Sha256
+        );
+
+        let mut scrubber = storage.scrubber(Arc::new(ScrubMetrics::new()));
+        for _ in 0..2 {
+            assert!(matches!(
+                scrubber.step().unwrap(),
+                Some(ScrubStep::Checked {
+                    outcome: ScrubOutcome::Ok,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_scrub_resumes_from_cursor() {
+        use crate::storage::scrub::ScrubStep;
+
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path()).unwrap();
+        for i in 0..3u8 {
+            storage.put(&hash_data(&[i]), &[i]).unwrap();
+        }
+
+        let mut scrubber = storage.scrubber(Arc::new(ScrubMetrics::new()));
+        scrubber.step().unwrap();
+        drop(scrubber);
+
+        // A new scrubber picks up where the last one stopped
+        let mut scrubber = storage.scrubber(Arc::new(ScrubMetrics::new()));
+        let mut checked = 0;
+        while let Some(ScrubStep::Checked { .. }) = scrubber.step().unwrap() {
+            checked += 1;
+        }
+        assert_eq!(checked, 2);
+    }
 }
@@ -0,0 +1,249 @@
+/// On-disk format version of RocksDB cache directories
+///
+/// The `FORMAT` file of a cache directory holds the version of the layout and metadata
+/// schemas the cache was written with. Opening a cache upgrades older formats in place,
+/// one migration per version, and records each completed step, so an interrupted upgrade
+/// resumes where it stopped. Caches written by a newer Fabrik are refused rather than
+/// misread.
+///
+/// Versions:
+///
+/// 1. Caches from before the version marker
+/// 2. The access indexes (`index_accessed`, `index_access_count`) hold one entry per
+///    accessed object, for its latest access, instead of one entry per access
+/// 3. Object metadata may end with an expiry (objects stored with their own TTL), which
+///    older versions reject as corrupt; nothing is rewritten
+/// 4. Object metadata may end with a checksum algorithm byte (BLAKE3 checksums, see
+///    `cache.hash_algorithm`), which older versions reject as corrupt; nothing is
+///    rewritten
+///
+/// The embedded metadata backend has its own log format and no marker.
+use rocksdb::{IteratorMode, WriteBatch, DB};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use tracing::info;
+
+use super::filesystem::{index_keys, ObjectMetadata, CF_INDEX_ACCESSED, CF_INDEX_ACCESS_COUNT};
+use crate::error::{FabrikError, Result, ResultExt};
+
+/// Version marker in the cache directory
+pub const FORMAT_FILE: &str = "FORMAT";
+
+/// Format written by this version of Fabrik
+pub const CURRENT_VERSION: u32 = 4;
+
+/// Format of caches without a marker
+const UNVERSIONED: u32 = 1;
+
+/// Deletes or writes per batch of a migration
+const MIGRATION_BATCH: usize = 10_000;
+
+/// Upgrade of a cache to format `to` from the version before it
+struct Migration {
+    to: u32,
+    description: &'static str,
+    run: fn(&DB) -> Result<()>,
+}
+
+/// Migrations in version order, the last one to `CURRENT_VERSION`
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        to: 2,
+        description: "rebuild the access indexes with one entry per object",
+        run: rebuild_access_indexes,
+    },
+    Migration {
+        to: 3,
+        description: "allow per-object expiry in object metadata",
+        run: |_| Ok(()),
+    },
+    Migration {
+        to: 4,
+        description: "allow BLAKE3 checksums in object metadata",
+        run: |_| Ok(()),
+    },
+];
+
+/// Format version of a cache directory, None for a new cache
+pub(super) fn read(cache_dir: &Path) -> Result<Option<u32>> {
+    let path = cache_dir.join(FORMAT_FILE);
+    match fs::read_to_string(&path) {
+        Ok(content) => content.trim().parse().map(Some).map_err(|_| {
+            FabrikError::corrupt(format!(
+                "Invalid cache format marker {}: {:?}",
+                path.display(),
+                content.trim()
+            ))
+        }),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            Ok(cache_dir.join("metadata").exists().then_some(UNVERSIONED))
+        }
+        Err(e) => Err(e).io_context("Failed to read cache format marker"),
+    }
+}
+
+/// Refuse caches written in a format newer than this version of Fabrik knows
+pub(super) fn check(cache_dir: &Path, version: Option<u32>) -> Result<()> {
+    match version {
+        Some(version) if version > CURRENT_VERSION => Err(FabrikError::config(format!(
+            "Cache at {} uses format version {}, but this version of Fabrik only supports up \
+             to {}. Upgrade Fabrik, or use another cache directory.",
+            cache_dir.display(),
+            version,
+            CURRENT_VERSION
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Bring an opened cache of format `version` (None for a new cache) to the current one
+pub(super) fn upgrade(db: &DB, cache_dir: &Path, version: Option<u32>) -> Result<()> {
+    let Some(version) = version else {
+        return write(cache_dir, CURRENT_VERSION);
+    };
+
+    for migration in MIGRATIONS.iter().filter(|migration| migration.to > version) {
+        info!(
+            "Upgrading cache {} to format version {}: {}",
+            cache_dir.display(),
+            migration.to,
+            migration.description
+        );
+        (migration.run)(db)?;
+        write(cache_dir, migration.to)?;
+    }
+    Ok(())
+}
+
+/// Record the format of a cache (atomically, so a crash leaves the previous version)
+pub(super) fn write(cache_dir: &Path, version: u32) -> Result<()> {
+    let path = cache_dir.join(FORMAT_FILE);
+    let temp_path = cache_dir.join(format!("{}.tmp", FORMAT_FILE));
+    fs::write(&temp_path, format!("{}\n", version))
+        .io_context("Failed to write cache format marker")?;
+    fs::rename(&temp_path, &path).io_context("Failed to write cache format marker")
+}
+
+/// Version 2: replace the per-access index entries with one per accessed object
+fn rebuild_access_indexes(db: &DB) -> Result<()> {
+    for name in [CF_INDEX_ACCESSED, CF_INDEX_ACCESS_COUNT] {
+        let cf = db
+            .cf_handle(name)
+            .ok_or_else(|| FabrikError::corrupt(format!("Failed to get {} handle", name)))?;
+        let mut batch = WriteBatch::default();
+        for item in db.iterator_cf(cf, IteratorMode::Start) {
+            let (key, _) = item?;
+            batch.delete_cf(cf, key);
+            if batch.len() >= MIGRATION_BATCH {
+                db.write(std::mem::take(&mut batch))
+                    .io_context("Failed to clear access index")?;
+            }
+        }
+        db.write(batch).io_context("Failed to clear access index")?;
+    }
+
+    let cf_accessed = db
+        .cf_handle(CF_INDEX_ACCESSED)
+        .ok_or_else(|| FabrikError::corrupt("Failed to get CF_INDEX_ACCESSED handle"))?;
+    let cf_access_count = db
+        .cf_handle(CF_INDEX_ACCESS_COUNT)
+        .ok_or_else(|| FabrikError::corrupt("Failed to get CF_INDEX_ACCESS_COUNT handle"))?;
+    let mut batch = WriteBatch::default();
+    for item in db.iterator(IteratorMode::Start) {
+        let (id, value) = item?;
+        let Ok(metadata) = ObjectMetadata::from_bytes(&value) else {
+            continue;
+        };
+        // Objects never read have no index entries
+        if metadata.access_count == 0 {
+            continue;
+        }
+        let (accessed_key, access_count_key) = index_keys(&id, &metadata);
+        batch.put_cf(cf_accessed, accessed_key, b"");
+        batch.put_cf(cf_access_count, access_count_key, b"");
+        if batch.len() >= MIGRATION_BATCH {
+            db.write(std::mem::take(&mut batch))
+                .io_context("Failed to rebuild access indexes")?;
+        }
+    }
+    db.write(batch)
+        .io_context("Failed to rebuild access indexes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::HashAlgorithm;
+    use crate::storage::FilesystemStorage;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_new_and_newer_caches() {
+        let temp = TempDir::new().unwrap();
+        drop(FilesystemStorage::new(temp.path()).unwrap());
+        assert_eq!(read(temp.path()).unwrap(), Some(CURRENT_VERSION));
+
+        fs::write(temp.path().join(FORMAT_FILE), "99\n").unwrap();
+        assert!(matches!(
+            FilesystemStorage::new(temp.path()),
+            Err(FabrikError::Config(_))
+        ));
+
+        assert_eq!(MIGRATIONS.last().unwrap().to, CURRENT_VERSION);
+        fs::write(temp.path().join(FORMAT_FILE), "two").unwrap();
+        assert!(read(temp.path()).is_err());
+    }
+
+    #[test]
+    fn test_upgrade_rebuilds_access_indexes() {
+        let temp = TempDir::new().unwrap();
+        let mut opts = rocksdb::Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        let db = DB::open_cf(
+            &opts,
+            temp.path().join("metadata"),
+            ["default", CF_INDEX_ACCESSED, CF_INDEX_ACCESS_COUNT],
+        )
+        .unwrap();
+        assert_eq!(read(temp.path()).unwrap(), Some(UNVERSIONED));
+
+        // Read three times (one index entry per access), and never read
+        let read_object = ObjectMetadata {
+            size: 1,
+            created_at: 10,
+            accessed_at: 30,
+            access_count: 3,
+            checksum: None,
+            checksum_algorithm: HashAlgorithm::Sha256,
+            expires_at: None,
+        };
+        let unread_object = ObjectMetadata {
+            access_count: 0,
+            accessed_at: 10,
+            ..read_object.clone()
+        };
+        db.put(b"read", read_object.to_bytes()).unwrap();
+        db.put(b"unread", unread_object.to_bytes()).unwrap();
+        let cf = db.cf_handle(CF_INDEX_ACCESSED).unwrap();
+        for accessed_at in [10i64, 20, 30] {
+            let mut key = accessed_at.to_le_bytes().to_vec();
+            key.extend_from_slice(b"read");
+            db.put_cf(cf, key, b"").unwrap();
+        }
+
+        upgrade(&db, temp.path(), Some(UNVERSIONED)).unwrap();
+        assert_eq!(read(temp.path()).unwrap(), Some(CURRENT_VERSION));
+
+        let keys = |name: &str| -> Vec<Vec<u8>> {
+            let cf = db.cf_handle(name).unwrap();
+            db.iterator_cf(cf, IteratorMode::Start)
+                .map(|item| item.unwrap().0.to_vec())
+                .collect()
+        };
+        let (accessed_key, access_count_key) = index_keys(b"read", &read_object);
+        assert_eq!(keys(CF_INDEX_ACCESSED), vec![accessed_key]);
+        assert_eq!(keys(CF_INDEX_ACCESS_COUNT), vec![access_count_key]);
+    }
+}
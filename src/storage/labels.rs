@@ -0,0 +1,171 @@
+/// User-defined labels on cached objects
+///
+/// Labels are `key=value` pairs attached to objects by users and scripts (e.g.
+/// `fabrik cas put --tag branch=main --tag target=ios`), so objects can be listed and
+/// deleted by label, such as everything stored for a branch that was merged.
+///
+/// The RocksDB backend keeps the labels of an object in the `labels` column family
+/// (value: JSON object of the labels), keyed by object ID, and indexes them in the
+/// `label_index` column family (key: `<key> 0x00 <value> 0x00 <object ID>`, empty value).
+/// Both are deleted with the object. The embedded backend doesn't store labels.
+use rocksdb::{ColumnFamily, Direction, IteratorMode, WriteBatch, DB};
+use std::collections::BTreeMap;
+
+use crate::error::{FabrikError, Result, ResultExt};
+
+/// Column family of the labels of each object
+pub(super) const CF_LABELS: &str = "labels";
+
+/// Column family of the label index
+pub(super) const CF_LABEL_INDEX: &str = "label_index";
+
+/// Labels of an object, by key
+pub type Labels = BTreeMap<String, String>;
+
+/// Parse a `key=value` label
+pub fn parse(label: &str) -> Result<(String, String)> {
+    let Some((key, value)) = label.split_once('=') else {
+        return Err(FabrikError::config(format!(
+            "Invalid label '{}' (expected KEY=VALUE)",
+            label
+        )));
+    };
+    let key = key.trim();
+    if key.is_empty() || key.contains('\0') || value.contains('\0') {
+        return Err(FabrikError::config(format!("Invalid label '{}'", label)));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Parse several `key=value` labels (a later label overrides an earlier one of its key)
+pub fn parse_all<S: AsRef<str>>(labels: &[S]) -> Result<Labels> {
+    labels.iter().map(|label| parse(label.as_ref())).collect()
+}
+
+/// Prefix of the index keys of objects labelled `key=value`
+fn index_prefix(key: &str, value: &str) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(key.len() + value.len() + 2);
+    prefix.extend_from_slice(key.as_bytes());
+    prefix.push(0);
+    prefix.extend_from_slice(value.as_bytes());
+    prefix.push(0);
+    prefix
+}
+
+/// Index key of the label `key=value` of object `id`
+fn index_key(key: &str, value: &str, id: &[u8]) -> Vec<u8> {
+    let mut index_key = index_prefix(key, value);
+    index_key.extend_from_slice(id);
+    index_key
+}
+
+/// Labels of object `id`
+pub(super) fn get(db: &DB, id: &[u8]) -> Result<Labels> {
+    let Some(cf) = db.cf_handle(CF_LABELS) else {
+        return Ok(Labels::new());
+    };
+    match db.get_cf(cf, id)? {
+        Some(bytes) => from_bytes(&bytes),
+        None => Ok(Labels::new()),
+    }
+}
+
+/// Add `labels` to object `id`, replacing the values of keys it already has
+pub(super) fn add(db: &DB, id: &[u8], labels: &Labels) -> Result<()> {
+    let (cf_labels, cf_index) = (handle(db, CF_LABELS)?, handle(db, CF_LABEL_INDEX)?);
+    let mut current = get(db, id)?;
+    let mut batch = WriteBatch::default();
+    for (key, value) in labels {
+        if let Some(previous) = current.insert(key.clone(), value.clone()) {
+            batch.delete_cf(cf_index, index_key(key, &previous, id));
+        }
+        batch.put_cf(cf_index, index_key(key, value, id), b"");
+    }
+    batch.put_cf(cf_labels, id, to_bytes(&current)?);
+    db.write(batch).io_context("Failed to store labels")
+}
+
+/// Remove the labels of object `id`
+pub(super) fn delete(db: &DB, id: &[u8]) -> Result<()> {
+    let current = get(db, id)?;
+    if current.is_empty() {
+        return Ok(());
+    }
+    let (cf_labels, cf_index) = (handle(db, CF_LABELS)?, handle(db, CF_LABEL_INDEX)?);
+    let mut batch = WriteBatch::default();
+    for (key, value) in &current {
+        batch.delete_cf(cf_index, index_key(key, value, id));
+    }
+    batch.delete_cf(cf_labels, id);
+    db.write(batch).io_context("Failed to delete labels")
+}
+
+/// IDs of the stored objects carrying all of `labels`, in ID order
+///
+/// Walks the index entries of one label and checks the others against each object's
+/// labels.
+pub(super) fn find(db: &DB, labels: &Labels) -> Result<Vec<Vec<u8>>> {
+    let Some((key, value)) = labels.iter().next() else {
+        return Ok(Vec::new());
+    };
+    let cf_index = handle(db, CF_LABEL_INDEX)?;
+    let prefix = index_prefix(key, value);
+
+    let mut ids = Vec::new();
+    for item in db.iterator_cf(cf_index, IteratorMode::From(&prefix, Direction::Forward)) {
+        let (index_key, _) = item?;
+        let Some(id) = index_key.strip_prefix(prefix.as_slice()) else {
+            break;
+        };
+        // Skip entries left behind by objects that are gone
+        if db.get(id)?.is_none() {
+            continue;
+        }
+        let object_labels = get(db, id)?;
+        if labels
+            .iter()
+            .all(|(key, value)| object_labels.get(key) == Some(value))
+        {
+            ids.push(id.to_vec());
+        }
+    }
+    Ok(ids)
+}
+
+fn handle<'a>(db: &'a DB, name: &str) -> Result<&'a ColumnFamily> {
+    db.cf_handle(name)
+        .ok_or_else(|| FabrikError::corrupt(format!("Failed to get {} handle", name)))
+}
+
+fn to_bytes(labels: &Labels) -> Result<Vec<u8>> {
+    serde_json::to_vec(labels)
+        .map_err(|e| FabrikError::corrupt(format!("Failed to encode labels: {}", e)))
+}
+
+fn from_bytes(bytes: &[u8]) -> Result<Labels> {
+    serde_json::from_slice(bytes)
+        .map_err(|e| FabrikError::corrupt(format!("Invalid labels: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("branch=feature/a=b").unwrap(),
+            ("branch".to_string(), "feature/a=b".to_string())
+        );
+        assert_eq!(parse("empty=").unwrap().1, "");
+        assert!(parse("branch").is_err());
+        assert!(parse("=main").is_err());
+
+        let labels = parse_all(&["target=ios", "branch=main", "target=android"]).unwrap();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels["target"], "android");
+
+        assert!(index_key("branch", "main", b"id").starts_with(&index_prefix("branch", "main")));
+        assert!(!index_key("branch", "main2", b"id").starts_with(&index_prefix("branch", "main")));
+    }
+}
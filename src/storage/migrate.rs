@@ -0,0 +1,215 @@
+/// Cache migration between directories (`fabrik cache migrate`)
+///
+/// Moving a cache to another disk by copying the directory carries along whatever the
+/// old one accumulated: stale secondary index entries, objects whose metadata outlived
+/// them, corrupt files. Migration instead walks the object metadata of the source and,
+/// for every object whose file is present and matches its size and checksum, hands the
+/// target the object with its metadata (timestamps, access count, checksum), origin tags,
+/// pins and labels. The target writes the object at the path its own layout assigns to the ID
+/// (or as chunks, when it deduplicates) and rebuilds the LRU/LFU indexes from the copied
+/// metadata.
+///
+/// The source is only read: access times aren't touched. Daily access counters (see
+/// `storage::popularity`) are not migrated and start over in the target.
+use rocksdb::{IteratorMode, DB};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use super::dedup;
+use super::filesystem::ObjectMetadata;
+use super::labels::{self, Labels};
+use super::pins::{self, CF_PINS};
+use super::tags::{EntryTags, CF_ENTRY_TAGS};
+use super::tiers::{self, ColdTier};
+use crate::error::{FabrikError, Result, ResultExt};
+
+/// An object read from the source cache
+#[derive(Debug, Clone)]
+pub struct MigrationObject {
+    pub id: Vec<u8>,
+    pub data: Vec<u8>,
+    pub(super) metadata: ObjectMetadata,
+    pub tags: Option<EntryTags>,
+    /// IDs this object pins (see `storage::pins`)
+    pub pins: Vec<Vec<u8>>,
+    pub labels: Labels,
+}
+
+/// What a migration did so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrateStats {
+    /// Objects stored in the target
+    pub copied: u64,
+    pub bytes: u64,
+    /// Objects the target already had
+    pub skipped: u64,
+    /// Objects whose file is gone from the source
+    pub missing: u64,
+    /// Objects whose file or metadata doesn't check out (left behind)
+    pub corrupt: u64,
+}
+
+/// Hand every intact object of the source to `store`, which returns whether it stored
+/// it (false if the target already had it); `progress` is called after each object
+pub(super) fn run(
+    db: &DB,
+    objects_dir: &Path,
+    cold_tier: Option<&ColdTier>,
+    mut store: impl FnMut(&MigrationObject) -> Result<bool>,
+    mut progress: impl FnMut(&MigrateStats),
+) -> Result<MigrateStats> {
+    let tags_cf = db.cf_handle(CF_ENTRY_TAGS);
+    let pins_cf = db.cf_handle(CF_PINS);
+    let mut stats = MigrateStats::default();
+
+    for item in db.iterator(IteratorMode::Start) {
+        let (id, value) = item.io_context("Failed to read source metadata")?;
+        let Ok(metadata) = ObjectMetadata::from_bytes(&value) else {
+            stats.corrupt += 1;
+            progress(&stats);
+            continue;
+        };
+
+        let read = match dedup::chunk_paths(db, &dedup::chunks_dir(objects_dir), &id)? {
+            Some(paths) => dedup::read_chunks(&paths),
+            None => fs::read(tiers::locate(db, objects_dir, cold_tier, &id)?.0),
+        };
+        let data = match read {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                stats.missing += 1;
+                progress(&stats);
+                continue;
+            }
+            Err(e) => return Err(e).io_context("Failed to read source object"),
+        };
+        let intact = data.len() as u64 == metadata.size
+            && metadata
+                .checksum
+                .is_none_or(|checksum| metadata.checksum_algorithm.digest(&data) == checksum);
+        if !intact {
+            stats.corrupt += 1;
+            progress(&stats);
+            continue;
+        }
+
+        let tags = match tags_cf {
+            Some(cf) => match db.get_cf(cf, &id)? {
+                Some(bytes) => EntryTags::from_bytes(&bytes).ok(),
+                None => None,
+            },
+            None => None,
+        };
+        let pins = match pins_cf {
+            Some(cf) => match db.get_cf(cf, &id)? {
+                Some(bytes) => pins::decode(&bytes)?,
+                None => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+
+        let size = metadata.size;
+        let object = MigrationObject {
+            labels: labels::get(db, &id)?,
+            id: id.to_vec(),
+            data,
+            metadata,
+            tags,
+            pins,
+        };
+        if store(&object)? {
+            stats.copied += 1;
+            stats.bytes += size;
+        } else {
+            stats.skipped += 1;
+        }
+        progress(&stats);
+    }
+
+    Ok(stats)
+}
+
+/// Fail if `from` and `to` are the same cache directory
+pub fn check_distinct(from: &Path, to: &Path) -> Result<()> {
+    let (Ok(from), Ok(to)) = (from.canonicalize(), to.canonicalize()) else {
+        return Ok(());
+    };
+    if from == to {
+        return Err(FabrikError::config(format!(
+            "Can't migrate {} onto itself",
+            from.display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::filesystem::object_path;
+    use crate::storage::{FilesystemStorage, Storage};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_migrate_copies_objects_with_metadata() {
+        let source_dir = TempDir::new().unwrap();
+        let source = FilesystemStorage::new(source_dir.path()).unwrap();
+        source.put(b"holder", b"action result").unwrap();
+        source.put(b"blob", b"output").unwrap();
+        source
+            .put(b"gone", b"deleted behind the cache's back")
+            .unwrap();
+        source.put(b"rotten", b"bit rot").unwrap();
+        let tags = EntryTags {
+            build_system: Some("bazel".to_string()),
+            machine: None,
+            origin: None,
+        };
+        source.put_tags(b"holder", &tags).unwrap();
+        source.pin(b"holder", &[b"blob".to_vec()]).unwrap();
+        let labels = labels::parse_all(&["branch=main"]).unwrap();
+        source.add_labels(b"holder", &labels).unwrap();
+        fs::remove_file(object_path(&source_dir.path().join("objects"), b"gone")).unwrap();
+        fs::write(
+            object_path(&source_dir.path().join("objects"), b"rotten"),
+            b"bit rut",
+        )
+        .unwrap();
+
+        let target_dir = TempDir::new().unwrap();
+        let target = FilesystemStorage::new(target_dir.path()).unwrap();
+        target.put(b"blob", b"output").unwrap();
+
+        let mut calls = 0;
+        let stats = source.migrate_to(&target, |_| calls += 1).unwrap();
+        assert_eq!(
+            stats,
+            MigrateStats {
+                copied: 1,
+                bytes: 13,
+                skipped: 1,
+                missing: 1,
+                corrupt: 1,
+            }
+        );
+        assert_eq!(calls, 4);
+
+        assert_eq!(target.get(b"holder").unwrap().unwrap(), b"action result");
+        assert_eq!(target.get_tags(b"holder").unwrap(), Some(tags));
+        assert_eq!(
+            target.find_by_labels(&labels).unwrap(),
+            vec![b"holder".to_vec()]
+        );
+        assert_eq!(
+            target.created_at(b"holder").unwrap(),
+            source.created_at(b"holder").unwrap()
+        );
+        assert!(!target.exists(b"gone").unwrap());
+        assert!(!target.exists(b"rotten").unwrap());
+        assert_eq!(target.stats().unwrap().total_objects, 2);
+
+        assert!(check_distinct(source_dir.path(), target_dir.path()).is_ok());
+        assert!(check_distinct(source_dir.path(), &source_dir.path().join(".")).is_err());
+    }
+}
@@ -1,13 +1,38 @@
+pub mod bundle;
 pub mod cache_dir;
+pub mod compaction;
+mod dedup;
+pub mod disk;
+pub mod embedded;
 pub mod filesystem;
+pub mod format;
+pub mod labels;
+pub mod migrate;
+mod partitions;
+pub mod pins;
+pub mod popularity;
+pub mod scrub;
+pub mod snapshot;
+pub mod tags;
+pub mod tiers;
+pub mod tree;
+pub mod warmup;
 
 #[allow(unused_imports)]
 pub use cache_dir::default_cache_dir;
+pub use embedded::EmbeddedStorage;
 pub use filesystem::FilesystemStorage;
+pub use labels::Labels;
+pub use scrub::{ScrubConfig, ScrubMetrics};
+pub use tags::{EntryTags, Origin};
+pub use tiers::TierConfig;
+pub use warmup::WarmupConfig;
 
+use crate::error::{FabrikError, Result, ResultExt};
 use crate::eviction::EvictionConfig;
-use anyhow::Result;
-use std::path::PathBuf;
+use serde::Serialize;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
 use tracing::info;
 
 /// Storage backend trait for content-addressable storage
@@ -16,26 +41,122 @@ pub trait Storage: Send + Sync {
     /// Store a blob with the given ID
     fn put(&self, id: &[u8], data: &[u8]) -> Result<()>;
 
+    /// Store a blob that expires at `expires_at` (Unix seconds) under the TTL eviction
+    /// policy instead of after the default TTL (backends without expiry store it as is)
+    fn put_with_expiry(&self, id: &[u8], data: &[u8], _expires_at: Option<i64>) -> Result<()> {
+        self.put(id, data)
+    }
+
+    /// Store the contents of the file at `path`, streamed in so large blobs aren't loaded
+    /// into memory (backends without streaming writes load it whole)
+    fn put_file(&self, id: &[u8], path: &Path, expires_at: Option<i64>) -> Result<()> {
+        let data = std::fs::read(path).io_context("Failed to read file")?;
+        self.put_with_expiry(id, &data, expires_at)
+    }
+
     /// Retrieve a blob by ID
     fn get(&self, id: &[u8]) -> Result<Option<Vec<u8>>>;
 
+    /// Open a blob for reading in pieces, so large blobs aren't loaded into memory
+    /// (backends without streaming reads load it whole)
+    fn open(&self, id: &[u8]) -> Result<Option<Box<dyn Read + Send>>> {
+        Ok(self
+            .get(id)?
+            .map(|data| Box::new(Cursor::new(data)) as Box<dyn Read + Send>))
+    }
+
     /// Check if a blob exists
     fn exists(&self, id: &[u8]) -> Result<bool>;
 
+    /// Check which of several blobs exist, in order
+    ///
+    /// Backends with a metadata index answer this in one batched lookup, instead of one
+    /// per blob (e.g. for Bazel's FindMissingBlobs, which asks about thousands at once).
+    fn exists_many(&self, ids: &[Vec<u8>]) -> Result<Vec<bool>> {
+        ids.iter().map(|id| self.exists(id)).collect()
+    }
+
     /// Delete a blob by ID
     fn delete(&self, id: &[u8]) -> Result<()>;
 
     /// Get the size of a blob in bytes
     fn size(&self, id: &[u8]) -> Result<Option<u64>>;
 
+    /// When a blob was stored (Unix seconds), if the backend tracks it
+    fn created_at(&self, _id: &[u8]) -> Result<Option<i64>> {
+        Ok(None)
+    }
+
+    /// Everything known about a blob, or None if it isn't stored
+    fn info(&self, id: &[u8]) -> Result<Option<ObjectInfo>> {
+        let Some(size) = self.size(id)? else {
+            return Ok(None);
+        };
+        let tags = self.get_tags(id)?;
+        Ok(Some(ObjectInfo {
+            size,
+            created_at: self.created_at(id)?,
+            origin: tags
+                .as_ref()
+                .and_then(|tags| tags.origin)
+                .unwrap_or_default(),
+            tags,
+            labels: self.get_labels(id)?,
+            ..ObjectInfo::default()
+        }))
+    }
+
     /// Update access time for LRU tracking
     fn touch(&self, id: &[u8]) -> Result<()>;
 
+    /// Update the access time of several blobs
+    ///
+    /// Backends with a metadata index record them in one batched write, instead of one
+    /// per blob (e.g. for the blobs FindMissingBlobs found).
+    fn touch_many(&self, ids: &[Vec<u8>]) -> Result<()> {
+        ids.iter().try_for_each(|id| self.touch(id))
+    }
+
     /// List all blob IDs (for eviction/cleanup)
     fn list_ids(&self) -> Result<Vec<Vec<u8>>>;
 
     /// Get cache statistics
     fn stats(&self) -> Result<StorageStats>;
+
+    /// Attach origin tags to a blob (ignored by backends that don't store tags)
+    fn put_tags(&self, _id: &[u8], _tags: &EntryTags) -> Result<()> {
+        Ok(())
+    }
+
+    /// Origin tags of a blob, if any were stored
+    fn get_tags(&self, _id: &[u8]) -> Result<Option<EntryTags>> {
+        Ok(None)
+    }
+
+    /// Add user labels to a stored blob, replacing the values of keys it already has
+    fn add_labels(&self, _id: &[u8], _labels: &Labels) -> Result<()> {
+        Err(FabrikError::config(
+            "Labels are not supported by this metadata backend",
+        ))
+    }
+
+    /// User labels of a blob
+    fn get_labels(&self, _id: &[u8]) -> Result<Labels> {
+        Ok(Labels::new())
+    }
+
+    /// IDs of the stored blobs carrying all of `labels`
+    fn find_by_labels(&self, _labels: &Labels) -> Result<Vec<Vec<u8>>> {
+        Err(FabrikError::config(
+            "Labels are not supported by this metadata backend",
+        ))
+    }
+
+    /// Keep `ids` from being evicted while `holder` is stored, replacing earlier pins of
+    /// `holder` (ignored by backends that don't pin)
+    fn pin(&self, _holder: &[u8], _ids: &[Vec<u8>]) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// Storage statistics
@@ -47,6 +168,105 @@ pub struct StorageStats {
     pub cache_dir: PathBuf,
 }
 
+/// What the cache knows about a stored blob
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ObjectInfo {
+    #[serde(rename = "size_bytes")]
+    pub size: u64,
+
+    /// When the blob was stored and last read (Unix seconds), if the backend tracks it
+    pub created_at: Option<i64>,
+    pub accessed_at: Option<i64>,
+    pub access_count: Option<u64>,
+
+    /// When the blob expires under the TTL eviction policy, if it was stored with its own
+    /// TTL (Unix seconds)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+
+    /// Compression of the stored blob (blobs are stored as uploaded)
+    pub compression: Compression,
+
+    pub origin: Origin,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<EntryTags>,
+
+    /// User labels (see `storage::labels`)
+    #[serde(skip_serializing_if = "Labels::is_empty")]
+    pub labels: Labels,
+
+    /// Whether another entry pins the blob (see `storage::pins`)
+    pub pinned: bool,
+
+    /// Number of blobs this entry pins
+    pub pins: u64,
+}
+
+/// Compression of stored blobs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::None => f.write_str("none"),
+        }
+    }
+}
+
+/// Metadata backend of a cache directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetadataBackend {
+    /// RocksDB (`FilesystemStorage`), for caches of any size
+    #[default]
+    RocksDb,
+    /// Append-only log (`EmbeddedStorage`), for small caches without RocksDB's startup cost
+    Embedded,
+}
+
+impl std::str::FromStr for MetadataBackend {
+    type Err = FabrikError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "rocksdb" => Ok(Self::RocksDb),
+            "embedded" => Ok(Self::Embedded),
+            other => Err(FabrikError::config(format!(
+                "Unknown metadata backend '{}' (expected rocksdb or embedded)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Open the storage of `cache_dir` with the given metadata backend
+///
+/// `max_objects` only applies to the embedded backend.
+pub fn open_storage<P: AsRef<Path>>(
+    cache_dir: P,
+    backend: MetadataBackend,
+    max_objects: usize,
+    eviction_config: Option<EvictionConfig>,
+) -> Result<Box<dyn Storage>> {
+    info!("Cache directory: {}", cache_dir.as_ref().display());
+    Ok(match backend {
+        MetadataBackend::RocksDb => Box::new(FilesystemStorage::with_eviction(
+            cache_dir,
+            eviction_config,
+        )?),
+        MetadataBackend::Embedded => Box::new(EmbeddedStorage::open(
+            cache_dir,
+            max_objects,
+            eviction_config,
+        )?),
+    })
+}
+
 /// Create storage backend without eviction
 ///
 /// Currently only supports filesystem storage. Future versions may add
@@ -76,6 +296,13 @@ pub fn create_storage_with_eviction(
     FilesystemStorage::with_eviction(cache_dir, Some(eviction_config))
 }
 
+/// Open the existing cache at `cache_dir` read-only, without eviction
+pub fn open_read_only(cache_dir: &str) -> Result<FilesystemStorage> {
+    info!("Initializing storage backend: filesystem (read-only)");
+    info!("Cache directory: {}", cache_dir);
+    FilesystemStorage::read_only(cache_dir)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,225 @@
+/// Partitioned metadata scans and running object totals
+///
+/// Object IDs are content hashes, so their first byte spreads them evenly over the key
+/// space. Full scans of the object metadata (eviction candidates, the initial count of
+/// the totals) split it into one contiguous first-byte range per thread and iterate the
+/// ranges in parallel.
+///
+/// The number and total size of objects are kept in memory and updated by every
+/// metadata write and delete, so `stats()` and eviction size checks don't scan at all.
+/// They are counted once, on first use, so short-lived commands that open the storage
+/// don't pay for a scan they never need.
+use super::filesystem::ObjectMetadata;
+use crate::error::{FabrikError, Result};
+use rocksdb::{Direction, IteratorMode, ReadOptions, DB};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::thread;
+
+/// Upper bound on scan threads; more doesn't help once RocksDB is I/O bound
+const MAX_PARTITIONS: usize = 16;
+
+/// First-byte ranges `[start, end)` covering the key space, `end` of the last being 256
+pub(super) fn partitions(count: usize) -> Vec<(u16, u16)> {
+    let count = count.clamp(1, 256);
+    let bound = |i: usize| (i * 256 / count) as u16;
+    (0..count).map(|i| (bound(i), bound(i + 1))).collect()
+}
+
+/// Fold the object metadata in parallel, one accumulator per key range (in key order)
+///
+/// Entries whose metadata can't be read are skipped.
+pub(super) fn fold<A, I, F>(db: &DB, init: I, step: F) -> Result<Vec<A>>
+where
+    A: Send,
+    I: Fn() -> A + Sync,
+    F: Fn(&mut A, &[u8], &ObjectMetadata) + Sync,
+{
+    let ranges = partitions(num_cpus::get().min(MAX_PARTITIONS));
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = ranges
+            .into_iter()
+            .map(|(start, end)| {
+                let (init, step) = (&init, &step);
+                scope.spawn(move || -> Result<A> {
+                    let mut acc = init();
+                    let mut read_options = ReadOptions::default();
+                    read_options.fill_cache(false);
+
+                    // The first range also covers (unexpected) empty keys
+                    let from = [start as u8];
+                    let mode = match start {
+                        0 => IteratorMode::Start,
+                        _ => IteratorMode::From(&from, Direction::Forward),
+                    };
+                    for item in db.iterator_opt(mode, read_options) {
+                        let (key, value) = item?;
+                        if key.first().is_some_and(|first| u16::from(*first) >= end) {
+                            break;
+                        }
+                        if let Ok(metadata) = ObjectMetadata::from_bytes(&value) {
+                            step(&mut acc, &key, &metadata);
+                        }
+                    }
+                    Ok(acc)
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .map_err(|_| FabrikError::corrupt("Metadata scan thread panicked"))?
+            })
+            .collect()
+    })
+}
+
+/// How a metadata write or delete changed the objects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Change {
+    None,
+    Added(u64),
+    Removed(u64),
+    Resized { from: u64, to: u64 },
+}
+
+impl Change {
+    /// Change from writing metadata for an object of `size` over `previous`
+    pub(super) fn put(previous: Option<&ObjectMetadata>, size: u64) -> Self {
+        match previous {
+            Some(previous) => Self::Resized {
+                from: previous.size,
+                to: size,
+            },
+            None => Self::Added(size),
+        }
+    }
+
+    /// Change from deleting the metadata `previous`
+    pub(super) fn delete(previous: Option<&ObjectMetadata>) -> Self {
+        previous.map_or(Self::None, |previous| Self::Removed(previous.size))
+    }
+}
+
+/// Number and total size of the objects in the cache
+#[derive(Debug, Default)]
+pub(super) struct ObjectTotals {
+    // None until counted; writers hold the read lock across their metadata change so
+    // the count never misses or double-counts one
+    counts: RwLock<Option<Counts>>,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    objects: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl ObjectTotals {
+    /// Objects and bytes, counting them first if this is the first call
+    pub(super) fn get(&self, db: &DB) -> Result<(u64, u64)> {
+        self.get_or_count(|| {
+            let partials = fold(
+                db,
+                || (0u64, 0u64),
+                |acc, _, metadata| {
+                    acc.0 += 1;
+                    acc.1 += metadata.size;
+                },
+            )?;
+            Ok(partials
+                .into_iter()
+                .fold((0, 0), |total, (objects, bytes)| {
+                    (total.0 + objects, total.1 + bytes)
+                }))
+        })
+    }
+
+    /// Objects and bytes, counted by `count` if this is the first call (for totals of
+    /// a subset of the objects, such as a storage tier)
+    pub(super) fn get_or_count(
+        &self,
+        count: impl FnOnce() -> Result<(u64, u64)>,
+    ) -> Result<(u64, u64)> {
+        if let Some(counts) = self.counts.read().unwrap().as_ref() {
+            return Ok(counts.load());
+        }
+
+        let mut counts = self.counts.write().unwrap();
+        if counts.is_none() {
+            let (objects, bytes) = count()?;
+            *counts = Some(Counts {
+                objects: AtomicU64::new(objects),
+                bytes: AtomicU64::new(bytes),
+            });
+        }
+        Ok(counts.as_ref().unwrap().load())
+    }
+
+    /// Make a metadata change and account for it
+    pub(super) fn record<T>(&self, write: impl FnOnce() -> Result<(T, Change)>) -> Result<T> {
+        let counts = self.counts.read().unwrap();
+        let (result, change) = write()?;
+        if let Some(counts) = counts.as_ref() {
+            counts.apply(change);
+        }
+        Ok(result)
+    }
+}
+
+impl Counts {
+    fn load(&self) -> (u64, u64) {
+        (
+            self.objects.load(Ordering::Relaxed),
+            self.bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    fn apply(&self, change: Change) {
+        // Concurrent changes of the same object may race; never wrap around
+        fn sub(counter: &AtomicU64, n: u64) {
+            let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                Some(value.saturating_sub(n))
+            });
+        }
+
+        match change {
+            Change::None => {}
+            Change::Added(size) => {
+                self.objects.fetch_add(1, Ordering::Relaxed);
+                self.bytes.fetch_add(size, Ordering::Relaxed);
+            }
+            Change::Removed(size) => {
+                sub(&self.objects, 1);
+                sub(&self.bytes, size);
+            }
+            Change::Resized { from, to } => {
+                self.bytes.fetch_add(to, Ordering::Relaxed);
+                sub(&self.bytes, from);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partitions_cover_the_key_space() {
+        for count in [1, 3, 16, 256, 1000] {
+            let ranges = partitions(count);
+            assert_eq!(ranges.len(), count.min(256));
+            assert_eq!(ranges.first().unwrap().0, 0);
+            assert_eq!(ranges.last().unwrap().1, 256);
+            for pair in ranges.windows(2) {
+                assert_eq!(pair[0].1, pair[1].0);
+                assert!(pair[0].0 < pair[0].1);
+            }
+        }
+    }
+}
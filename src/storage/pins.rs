@@ -0,0 +1,55 @@
+/// Blob pinning
+///
+/// Some entries are only useful together with the blobs they reference: a Bazel
+/// ActionResult whose output blobs were evicted is a cache hit the client can't use.
+/// Such a holder entry can pin the blobs it references. Pinned blobs are left out of
+/// eviction for as long as the holder is stored, and deleting (or evicting) the holder
+/// releases them.
+///
+/// The RocksDB backend keeps pins in the `pins` column family, keyed by holder ID
+/// (value: the pinned IDs, each prefixed with its length as a little-endian u32). The
+/// embedded backend doesn't pin.
+use crate::error::{FabrikError, Result};
+
+/// Column family of the pins
+pub(super) const CF_PINS: &str = "pins";
+
+/// Encode the IDs pinned by a holder
+pub(super) fn encode(ids: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(ids.iter().map(|id| id.len() + 4).sum());
+    for id in ids {
+        bytes.extend_from_slice(&(id.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(id);
+    }
+    bytes
+}
+
+/// Decode the IDs pinned by a holder
+pub(super) fn decode(mut bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut ids = Vec::new();
+    while !bytes.is_empty() {
+        let len = bytes
+            .get(..4)
+            .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+            .ok_or_else(|| FabrikError::corrupt("Truncated pin length"))?;
+        let id = bytes
+            .get(4..4 + len)
+            .ok_or_else(|| FabrikError::corrupt("Truncated pinned ID"))?;
+        ids.push(id.to_vec());
+        bytes = &bytes[4 + len..];
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let ids = vec![b"cas:abc:3".to_vec(), Vec::new(), b"cas:def:10".to_vec()];
+        assert_eq!(decode(&encode(&ids)).unwrap(), ids);
+        assert!(decode(&encode(&[])).unwrap().is_empty());
+        assert!(decode(&[9, 0, 0, 0, 1]).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+/// Per-day access counters for artifact popularity reports
+///
+/// The metadata of an object only tracks its lifetime access count, which can't tell
+/// what's popular *now*. Every access recorded by the batched touch worker also bumps a
+/// counter for the object in the current UTC day (`access_daily` column family), together
+/// with the bytes served. `fabrik stats popular` sums the counters over the last days to
+/// list the artifacts worth pre-seeding into new caches.
+///
+/// Counters are kept for `RETENTION_DAYS` and survive eviction of the object, so
+/// artifacts that were popular but got evicted still show up. Like the rest of the
+/// access tracking, they're best-effort: accesses dropped by a full touch queue aren't
+/// counted.
+use crate::error::{FabrikError, Result, ResultExt};
+use rocksdb::{Direction, IteratorMode, Options, WriteBatch, DB};
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::filesystem::{FilesystemStorage, ObjectMetadata};
+
+/// Column family of the daily counters
+///
+/// Key: day (u32, big-endian, days since the Unix epoch) + object ID, so that a day's
+/// counters are contiguous and old days sort first. Value: hits (u64) + bytes served
+/// (u64), little-endian.
+pub(super) const CF_ACCESS_DAILY: &str = "access_daily";
+
+/// Days of counters kept (and the longest window that can be reported)
+pub const RETENTION_DAYS: u32 = 90;
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Artifact with its accesses over a reporting window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PopularArtifact {
+    pub id: Vec<u8>,
+    pub hits: u64,
+    pub bytes_served: u64,
+    /// Current size, `None` if the artifact is no longer cached
+    pub size: Option<u64>,
+}
+
+/// UTC day of a Unix timestamp
+pub(super) fn day_of(timestamp: i64) -> u32 {
+    timestamp.div_euclid(SECS_PER_DAY).max(0) as u32
+}
+
+fn counter_key(day: u32, id: &[u8]) -> Vec<u8> {
+    let mut key = day.to_be_bytes().to_vec();
+    key.extend_from_slice(id);
+    key
+}
+
+fn decode_counter(value: &[u8]) -> (u64, u64) {
+    let field = |start: usize| {
+        value
+            .get(start..start + 8)
+            .and_then(|b| b.try_into().ok())
+            .map(u64::from_le_bytes)
+            .unwrap_or(0)
+    };
+    (field(0), field(8))
+}
+
+fn encode_counter(hits: u64, bytes: u64) -> Vec<u8> {
+    let mut value = hits.to_le_bytes().to_vec();
+    value.extend_from_slice(&bytes.to_le_bytes());
+    value
+}
+
+/// Add accesses (timestamp, object ID, object size) to the daily counters
+pub(super) fn record(
+    db: &DB,
+    write_batch: &mut WriteBatch,
+    accesses: &[(i64, &[u8], u64)],
+) -> Result<()> {
+    if accesses.is_empty() {
+        return Ok(());
+    }
+    let cf = db
+        .cf_handle(CF_ACCESS_DAILY)
+        .ok_or_else(|| FabrikError::corrupt("Failed to get CF_ACCESS_DAILY handle"))?;
+
+    // Several accesses of the same object in one batch must add up, not overwrite
+    let mut increments: HashMap<Vec<u8>, (u64, u64)> = HashMap::new();
+    for (timestamp, id, size) in accesses {
+        let entry = increments
+            .entry(counter_key(day_of(*timestamp), id))
+            .or_default();
+        entry.0 += 1;
+        entry.1 += size;
+    }
+
+    for (key, (hits, bytes)) in increments {
+        let (old_hits, old_bytes) = db
+            .get_cf(cf, &key)?
+            .map(|value| decode_counter(&value))
+            .unwrap_or_default();
+        write_batch.put_cf(
+            cf,
+            key,
+            encode_counter(old_hits + hits, old_bytes.saturating_add(bytes)),
+        );
+    }
+
+    Ok(())
+}
+
+/// Delete counters older than `RETENTION_DAYS`, returns the number deleted
+pub(super) fn prune(db: &DB, today: u32) -> Result<usize> {
+    let Some(cf) = db.cf_handle(CF_ACCESS_DAILY) else {
+        return Ok(0);
+    };
+    let cutoff = today.saturating_sub(RETENTION_DAYS - 1).to_be_bytes();
+
+    let mut write_batch = WriteBatch::default();
+    for item in db.iterator_cf(cf, IteratorMode::Start) {
+        let (key, _) = item?;
+        if key.as_ref() >= cutoff.as_slice() {
+            break;
+        }
+        write_batch.delete_cf(cf, key);
+    }
+
+    let deleted = write_batch.len();
+    if deleted > 0 {
+        db.write(write_batch)
+            .io_context("Failed to prune access counters")?;
+    }
+    Ok(deleted)
+}
+
+/// The `top` most accessed artifacts of the last `days` days (including today)
+///
+/// Ordered by hits, then bytes served.
+pub(super) fn popular(db: &DB, today: u32, days: u32, top: usize) -> Result<Vec<PopularArtifact>> {
+    let Some(cf) = db.cf_handle(CF_ACCESS_DAILY) else {
+        return Ok(Vec::new());
+    };
+    let start = today.saturating_sub(days.saturating_sub(1)).to_be_bytes();
+
+    let mut totals: HashMap<Vec<u8>, (u64, u64)> = HashMap::new();
+    for item in db.iterator_cf(cf, IteratorMode::From(&start, Direction::Forward)) {
+        let (key, value) = item?;
+        if key.len() <= 4 {
+            continue;
+        }
+        let (hits, bytes) = decode_counter(&value);
+        let total = totals.entry(key[4..].to_vec()).or_default();
+        total.0 += hits;
+        total.1 = total.1.saturating_add(bytes);
+    }
+
+    let mut ranked: Vec<_> = totals.into_iter().collect();
+    ranked.sort_by(|(a_id, a), (b_id, b)| b.cmp(a).then_with(|| a_id.cmp(b_id)));
+    ranked.truncate(top);
+
+    ranked
+        .into_iter()
+        .map(|(id, (hits, bytes_served))| {
+            let size = match db.get(&id)? {
+                Some(value) => ObjectMetadata::from_bytes(&value).ok().map(|m| m.size),
+                None => None,
+            };
+            Ok(PopularArtifact {
+                id,
+                hits,
+                bytes_served,
+                size,
+            })
+        })
+        .collect()
+}
+
+/// Popular artifacts of the cache in `cache_dir`, see `popular`
+///
+/// Opens the metadata read-only, so it works while a daemon or server is using the
+/// cache. Recent accesses that are still in the serving process's memtable may be
+/// missing.
+pub fn read_popular(cache_dir: &Path, days: u32, top: usize) -> Result<Vec<PopularArtifact>> {
+    let db_path = cache_dir.join("metadata");
+    if !db_path.exists() {
+        return Err(FabrikError::not_found(format!(
+            "No cache metadata at {}",
+            db_path.display()
+        )));
+    }
+
+    let opts = Options::default();
+    // Caches created before access counters existed don't have the column family yet
+    let column_families =
+        DB::list_cf(&opts, &db_path).io_context("Failed to read cache metadata")?;
+    let db = DB::open_cf_for_read_only(&opts, &db_path, column_families, false)
+        .io_context("Failed to open cache metadata")?;
+
+    popular(
+        &db,
+        day_of(FilesystemStorage::current_timestamp()),
+        days,
+        top,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn open(dir: &Path) -> DB {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        DB::open_cf(&opts, dir, vec!["default", CF_ACCESS_DAILY]).unwrap()
+    }
+
+    fn record_all(db: &DB, accesses: &[(i64, &[u8], u64)]) {
+        let mut write_batch = WriteBatch::default();
+        record(db, &mut write_batch, accesses).unwrap();
+        db.write(write_batch).unwrap();
+    }
+
+    #[test]
+    fn test_popular_ranks_by_hits_in_window() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = open(temp_dir.path());
+        let today = 20_000u32;
+        let now = today as i64 * SECS_PER_DAY + 100;
+        let days_ago = |n: i64| now - n * SECS_PER_DAY;
+
+        record_all(
+            &db,
+            &[
+                (now, b"aa", 10),
+                (now, b"aa", 10),
+                (days_ago(1), b"aa", 10),
+                (now, b"bb", 1000),
+                (days_ago(3), b"bb", 1000),
+                (days_ago(10), b"cc", 1),
+                (days_ago(10), b"cc", 1),
+                (days_ago(10), b"cc", 1),
+                (days_ago(10), b"cc", 1),
+            ],
+        );
+
+        let week = popular(&db, today, 7, 10).unwrap();
+        assert_eq!(
+            week,
+            vec![
+                PopularArtifact {
+                    id: b"aa".to_vec(),
+                    hits: 3,
+                    bytes_served: 30,
+                    size: None,
+                },
+                PopularArtifact {
+                    id: b"bb".to_vec(),
+                    hits: 2,
+                    bytes_served: 2000,
+                    size: None,
+                },
+            ]
+        );
+
+        let month = popular(&db, today, 30, 1).unwrap();
+        assert_eq!(month.len(), 1);
+        assert_eq!(month[0].id, b"cc".to_vec());
+
+        assert_eq!(popular(&db, today, 1, 10).unwrap()[0].hits, 2);
+    }
+
+    #[test]
+    fn test_prune_drops_expired_days() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = open(temp_dir.path());
+        let today = 20_000u32;
+        let now = today as i64 * SECS_PER_DAY;
+
+        record_all(
+            &db,
+            &[
+                (now - RETENTION_DAYS as i64 * SECS_PER_DAY, b"old", 1),
+                (now - (RETENTION_DAYS as i64 - 1) * SECS_PER_DAY, b"kept", 1),
+                (now, b"new", 1),
+            ],
+        );
+
+        assert_eq!(prune(&db, today).unwrap(), 1);
+        assert_eq!(prune(&db, today).unwrap(), 0);
+        assert_eq!(popular(&db, today, RETENTION_DAYS, 10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_missing_column_family() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let db = DB::open(&opts, temp_dir.path()).unwrap();
+
+        assert!(popular(&db, 20_000, 7, 10).unwrap().is_empty());
+        assert_eq!(prune(&db, 20_000).unwrap(), 0);
+    }
+}
@@ -0,0 +1,593 @@
+/// Background integrity scrubbing
+///
+/// Disks and filesystems corrupt data silently, and a cache that serves a corrupt
+/// artifact breaks builds in ways that are hard to trace back to it. The optional
+/// scrubber (`cache.scrub`) re-hashes stored objects on a background thread and
+/// compares them with the checksum (SHA256 or BLAKE3) recorded when they were written,
+/// working through the whole cache in key order over many hours:
+///
+/// - Corrupt objects are moved to `quarantine/` in the cache directory (or in the cold
+///   tier directory, see `storage::tiers`) and dropped from the metadata, so the next
+///   request is a miss and the object is fetched again from upstream or rebuilt.
+/// - Metadata whose object file is gone is dropped.
+/// - Deduplicated objects are checked as assembled from their chunks, and dropped
+///   (releasing their chunks) when a chunk is gone or doesn't match (see
+///   `storage::dedup`).
+/// - Objects written before checksums were recorded get a SHA256 one on their first
+///   scrub.
+///
+/// The scrubber stays out of the way of real traffic: it checks `cache.scrub_rate`
+/// percent of the objects per hour, evenly spread, reads at most `cache.scrub_bandwidth`
+/// bytes per second, and gives up as soon as the storage is closed. Its position is
+/// saved in the cache directory, so restarts don't start the pass over.
+use super::dedup;
+use super::filesystem::{delete_index_entries, ObjectMetadata};
+use super::labels;
+use super::partitions::{Change, ObjectTotals};
+use super::tiers::{self, ColdTier};
+use crate::error::{FabrikError, Result, ResultExt};
+use crate::eviction::EvictionConfig;
+use rocksdb::{Direction, IteratorMode, ReadOptions, DB};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// File in the cache directory holding the key of the last scrubbed object
+const CURSOR_FILE: &str = "scrub-cursor";
+
+/// Directory in the cache directory corrupt objects are moved to
+const QUARANTINE_DIR: &str = "quarantine";
+
+/// Objects checked between cursor saves
+const CURSOR_SAVE_INTERVAL: u64 = 64;
+
+/// Pause after an error before trying again
+const ERROR_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Scrub settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrubConfig {
+    /// Share of the objects checked per hour (0.01 = 1%)
+    pub fraction_per_hour: f64,
+
+    /// Maximum object bytes read per second
+    pub max_bytes_per_sec: u64,
+}
+
+impl ScrubConfig {
+    /// Parse `cache.scrub_rate` (e.g. "1%") and `cache.scrub_bandwidth` (e.g. "8MB")
+    pub fn parse(rate: &str, bandwidth: &str) -> Result<Self> {
+        let percent = rate
+            .strip_suffix('%')
+            .and_then(|p| p.trim().parse::<f64>().ok())
+            .filter(|p| *p > 0.0 && *p <= 100.0)
+            .ok_or_else(|| {
+                FabrikError::config(format!(
+                    "Invalid scrub rate '{}': expected a percentage between 0% and 100%",
+                    rate
+                ))
+            })?;
+        let max_bytes_per_sec = EvictionConfig::parse_size(bandwidth).map_err(|e| {
+            FabrikError::config(format!("Invalid scrub bandwidth '{}': {:#}", bandwidth, e))
+        })?;
+        if max_bytes_per_sec == 0 {
+            return Err(FabrikError::config(
+                "Scrub bandwidth must be greater than zero",
+            ));
+        }
+
+        Ok(Self {
+            fraction_per_hour: percent / 100.0,
+            max_bytes_per_sec,
+        })
+    }
+
+    /// Pause after checking an object of `bytes` in a cache of `objects`
+    fn delay(&self, objects: u64, bytes: u64) -> Duration {
+        let per_hour = (objects as f64 * self.fraction_per_hour).max(1.0);
+        let pacing = Duration::from_secs_f64(3600.0 / per_hour);
+        let bandwidth = Duration::from_secs_f64(bytes as f64 / self.max_bytes_per_sec as f64);
+        pacing.max(bandwidth)
+    }
+}
+
+/// What checking an object found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrubOutcome {
+    /// Content matches its checksum
+    Ok,
+    /// No checksum was recorded; it is now
+    Unverified,
+    /// The object file is gone; its metadata was dropped
+    Missing,
+    /// Content doesn't match its checksum; the object was quarantined
+    Corrupt,
+    /// The object was rewritten or deleted while being checked
+    Skipped,
+}
+
+/// Result of one scrubber step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrubStep {
+    /// An object was checked, reading `bytes`
+    Checked { outcome: ScrubOutcome, bytes: u64 },
+    /// Every object has been checked once; the next step starts a new pass
+    PassComplete,
+}
+
+/// Scrub counters, exported as Prometheus metrics
+#[derive(Debug, Default)]
+pub struct ScrubMetrics {
+    objects_total: AtomicU64,
+    bytes_total: AtomicU64,
+    corrupt_total: AtomicU64,
+    missing_total: AtomicU64,
+    unverified_total: AtomicU64,
+    passes_total: AtomicU64,
+    pass_objects: AtomicU64,
+    pass_checked: AtomicU64,
+}
+
+impl ScrubMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&self, outcome: ScrubOutcome, bytes: u64) {
+        self.objects_total.fetch_add(1, Ordering::Relaxed);
+        self.pass_checked.fetch_add(1, Ordering::Relaxed);
+        self.bytes_total.fetch_add(bytes, Ordering::Relaxed);
+        let counter = match outcome {
+            ScrubOutcome::Corrupt => &self.corrupt_total,
+            ScrubOutcome::Missing => &self.missing_total,
+            ScrubOutcome::Unverified => &self.unverified_total,
+            ScrubOutcome::Ok | ScrubOutcome::Skipped => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn start_pass(&self, objects: u64) {
+        self.pass_objects.store(objects, Ordering::Relaxed);
+        self.pass_checked.store(0, Ordering::Relaxed);
+    }
+
+    fn finish_pass(&self) {
+        self.passes_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn objects_total(&self) -> u64 {
+        self.objects_total.load(Ordering::Relaxed)
+    }
+
+    pub fn corrupt_total(&self) -> u64 {
+        self.corrupt_total.load(Ordering::Relaxed)
+    }
+
+    pub fn missing_total(&self) -> u64 {
+        self.missing_total.load(Ordering::Relaxed)
+    }
+
+    pub fn passes_total(&self) -> u64 {
+        self.passes_total.load(Ordering::Relaxed)
+    }
+
+    /// Share of the cache checked in the current pass (0.0 to 1.0)
+    pub fn pass_coverage(&self) -> f64 {
+        let objects = self.pass_objects.load(Ordering::Relaxed);
+        if objects == 0 {
+            return 0.0;
+        }
+        (self.pass_checked.load(Ordering::Relaxed) as f64 / objects as f64).min(1.0)
+    }
+
+    /// Share of checked objects that were corrupt or missing (0.0 to 1.0)
+    pub fn error_rate(&self) -> f64 {
+        let objects = self.objects_total();
+        if objects == 0 {
+            return 0.0;
+        }
+        (self.corrupt_total() + self.missing_total()) as f64 / objects as f64
+    }
+
+    /// Export metrics in Prometheus format
+    pub fn export_prometheus(&self) -> String {
+        format!(
+            r#"# HELP fabrik_scrub_objects_total Objects checked by the integrity scrubber
+# TYPE fabrik_scrub_objects_total counter
+fabrik_scrub_objects_total {}
+
+# HELP fabrik_scrub_bytes_total Bytes re-hashed by the integrity scrubber
+# TYPE fabrik_scrub_bytes_total counter
+fabrik_scrub_bytes_total {}
+
+# HELP fabrik_scrub_errors_total Objects found corrupt (quarantined) or missing
+# TYPE fabrik_scrub_errors_total counter
+fabrik_scrub_errors_total{{kind="corrupt"}} {}
+fabrik_scrub_errors_total{{kind="missing"}} {}
+
+# HELP fabrik_scrub_unverified_total Objects that had no checksum yet
+# TYPE fabrik_scrub_unverified_total counter
+fabrik_scrub_unverified_total {}
+
+# HELP fabrik_scrub_passes_total Completed passes over the whole cache
+# TYPE fabrik_scrub_passes_total counter
+fabrik_scrub_passes_total {}
+
+# HELP fabrik_scrub_pass_coverage Share of the cache checked in the current pass (0.0 to 1.0)
+# TYPE fabrik_scrub_pass_coverage gauge
+fabrik_scrub_pass_coverage {:.4}
+
+# HELP fabrik_scrub_error_rate Share of checked objects that were corrupt or missing (0.0 to 1.0)
+# TYPE fabrik_scrub_error_rate gauge
+fabrik_scrub_error_rate {:.6}
+"#,
+            self.objects_total(),
+            self.bytes_total.load(Ordering::Relaxed),
+            self.corrupt_total(),
+            self.missing_total(),
+            self.unverified_total.load(Ordering::Relaxed),
+            self.passes_total(),
+            self.pass_coverage(),
+            self.error_rate(),
+        )
+    }
+}
+
+/// Walks the cache one object at a time
+///
+/// The DB is only held for one step at a time so the scrubber never keeps a closed
+/// storage alive.
+pub struct Scrubber {
+    db: Weak<DB>,
+    objects_dir: PathBuf,
+    cold_tier: Option<Arc<ColdTier>>,
+    cache_dir: PathBuf,
+    totals: Arc<ObjectTotals>,
+    metrics: Arc<ScrubMetrics>,
+    /// Key of the last checked object (empty at the start of a pass)
+    cursor: Vec<u8>,
+    /// Objects in the cache when the current pass started
+    pass_objects: Option<u64>,
+    unsaved: u64,
+}
+
+impl Scrubber {
+    pub(super) fn new(
+        db: Weak<DB>,
+        objects_dir: PathBuf,
+        cold_tier: Option<Arc<ColdTier>>,
+        totals: Arc<ObjectTotals>,
+        metrics: Arc<ScrubMetrics>,
+    ) -> Self {
+        let cache_dir = objects_dir
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| objects_dir.clone());
+        let cursor = fs::read_to_string(cache_dir.join(CURSOR_FILE))
+            .ok()
+            .and_then(|hex_id| hex::decode(hex_id.trim()).ok())
+            .unwrap_or_default();
+
+        Self {
+            db,
+            objects_dir,
+            cold_tier,
+            cache_dir,
+            totals,
+            metrics,
+            cursor,
+            pass_objects: None,
+            unsaved: 0,
+        }
+    }
+
+    /// Objects in the cache when the current pass started
+    pub fn pass_objects(&self) -> u64 {
+        self.pass_objects.unwrap_or(0)
+    }
+
+    /// Check the object after the cursor; None if the storage was closed
+    pub fn step(&mut self) -> Result<Option<ScrubStep>> {
+        let Some(db) = self.db.upgrade() else {
+            self.save_cursor();
+            return Ok(None);
+        };
+
+        if self.pass_objects.is_none() {
+            let (objects, _) = self.totals.get(&db)?;
+            self.pass_objects = Some(objects);
+            self.metrics.start_pass(objects);
+        }
+
+        // Resume right after the cursor
+        let mut from = self.cursor.clone();
+        if !from.is_empty() {
+            from.push(0);
+        }
+        let mut read_options = ReadOptions::default();
+        read_options.fill_cache(false);
+        let next = db
+            .iterator_opt(IteratorMode::From(&from, Direction::Forward), read_options)
+            .next()
+            .transpose()?;
+
+        let Some((id, value)) = next else {
+            self.cursor.clear();
+            self.pass_objects = None;
+            self.metrics.finish_pass();
+            self.save_cursor();
+            return Ok(Some(ScrubStep::PassComplete));
+        };
+
+        let (outcome, bytes) = self.check(&db, &id, &value)?;
+        self.metrics.record(outcome, bytes);
+        self.cursor = id.to_vec();
+
+        self.unsaved += 1;
+        if self.unsaved >= CURSOR_SAVE_INTERVAL {
+            self.save_cursor();
+        }
+
+        Ok(Some(ScrubStep::Checked { outcome, bytes }))
+    }
+
+    /// Re-hash an object and compare it with its recorded checksum
+    fn check(&self, db: &DB, id: &[u8], value: &[u8]) -> Result<(ScrubOutcome, u64)> {
+        let (path, cold) = tiers::locate(db, &self.objects_dir, self.cold_tier.as_deref(), id)?;
+
+        let Ok(metadata) = ObjectMetadata::from_bytes(value) else {
+            warn!(
+                "Integrity scrub: unreadable metadata for object {}",
+                hex::encode(id)
+            );
+            self.quarantine(db, id, &path, cold, None)?;
+            return Ok((ScrubOutcome::Corrupt, 0));
+        };
+
+        // Deduplicated objects are read from their chunks (see `storage::dedup`)
+        let chunks = dedup::chunk_paths(db, &self.chunks_dir(), id)?;
+        let read = || match &chunks {
+            Some(paths) => dedup::read_chunks(paths),
+            None => fs::read(&path),
+        };
+        let data = match read() {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // Deleted between listing and reading, or lost
+                if db.get(id)?.as_deref() != Some(value) {
+                    return Ok((ScrubOutcome::Skipped, 0));
+                }
+                warn!(
+                    "Integrity scrub: object {} is missing, dropping its metadata",
+                    hex::encode(id)
+                );
+                self.totals.record(|| {
+                    db.delete(id)
+                        .io_context("Failed to delete metadata of missing object")?;
+                    delete_index_entries(db, id, &metadata)?;
+                    labels::delete(db, id)?;
+                    Ok(((), Change::delete(Some(&metadata))))
+                })?;
+                dedup::release(db, &self.chunks_dir(), id)?;
+                self.forget_cold(db, id, cold, Some(metadata.size))?;
+                return Ok((ScrubOutcome::Missing, 0));
+            }
+            Err(e) => return Err(e).io_context("Failed to read object"),
+        };
+        let bytes = data.len() as u64;
+        let checksum = metadata.checksum_algorithm.digest(&data);
+
+        // Anything but a match is only acted upon if the object wasn't rewritten in
+        // the meantime
+        let current = db.get(id)?;
+        let unchanged = |current: &Option<Vec<u8>>| {
+            current
+                .as_deref()
+                .and_then(|v| ObjectMetadata::from_bytes(v).ok())
+                .is_some_and(|m| m.created_at == metadata.created_at)
+        };
+
+        match metadata.checksum {
+            Some(expected) if expected == checksum => Ok((ScrubOutcome::Ok, bytes)),
+            None => {
+                if !unchanged(&current) {
+                    return Ok((ScrubOutcome::Skipped, bytes));
+                }
+                let mut latest = ObjectMetadata::from_bytes(current.as_deref().unwrap())?;
+                latest.checksum = Some(checksum);
+                latest.checksum_algorithm = metadata.checksum_algorithm;
+                db.put(id, latest.to_bytes())
+                    .io_context("Failed to record checksum")?;
+                Ok((ScrubOutcome::Unverified, bytes))
+            }
+            Some(expected) => {
+                if !unchanged(&current) {
+                    return Ok((ScrubOutcome::Skipped, bytes));
+                }
+                // Rule out a rewrite within the same second before giving up on it
+                let reread = match read() {
+                    Ok(data) => data,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        return Ok((ScrubOutcome::Skipped, bytes))
+                    }
+                    Err(e) => return Err(e).io_context("Failed to read object"),
+                };
+                if metadata.checksum_algorithm.digest(&reread) == expected {
+                    return Ok((ScrubOutcome::Ok, bytes));
+                }
+                warn!(
+                    "Integrity scrub: object {} doesn't match its checksum, quarantining it",
+                    hex::encode(id)
+                );
+                self.quarantine(db, id, &path, cold, Some(&metadata))?;
+                Ok((ScrubOutcome::Corrupt, bytes))
+            }
+        }
+    }
+
+    /// Move an object out of the cache for inspection and forget it
+    fn quarantine(
+        &self,
+        db: &DB,
+        id: &[u8],
+        path: &Path,
+        cold: bool,
+        metadata: Option<&ObjectMetadata>,
+    ) -> Result<()> {
+        // Next to the object, so it's a rename on the same volume
+        let quarantine_dir = match self.cold_tier.as_deref().filter(|_| cold) {
+            Some(tier) => tier.config.cold_dir.join(QUARANTINE_DIR),
+            None => self.cache_dir.join(QUARANTINE_DIR),
+        };
+        fs::create_dir_all(&quarantine_dir).io_context("Failed to create quarantine directory")?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let target = quarantine_dir.join(format!("{}.{}", hex::encode(id), now));
+        match fs::rename(path, &target) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).io_context("Failed to quarantine object"),
+        }
+
+        self.totals.record(|| {
+            db.delete(id)
+                .io_context("Failed to delete metadata of corrupt object")?;
+            if let Some(metadata) = metadata {
+                delete_index_entries(db, id, metadata)?;
+            }
+            labels::delete(db, id)?;
+            Ok(((), Change::delete(metadata)))
+        })?;
+        dedup::release(db, &self.chunks_dir(), id)?;
+        self.forget_cold(db, id, cold, metadata.map(|m| m.size))
+    }
+
+    fn chunks_dir(&self) -> PathBuf {
+        dedup::chunks_dir(&self.objects_dir)
+    }
+
+    /// Drop the cold tier entry of a dropped object
+    fn forget_cold(&self, db: &DB, id: &[u8], cold: bool, size: Option<u64>) -> Result<()> {
+        match self.cold_tier.as_deref().filter(|_| cold) {
+            Some(tier) => tier.forget(db, id, size),
+            None => Ok(()),
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.unsaved = 0;
+        let path = self.cache_dir.join(CURSOR_FILE);
+        if let Err(e) = fs::write(&path, hex::encode(&self.cursor)) {
+            debug!("Failed to save integrity scrub position: {}", e);
+        }
+    }
+}
+
+impl Drop for Scrubber {
+    fn drop(&mut self) {
+        if self.unsaved > 0 {
+            self.save_cursor();
+        }
+    }
+}
+
+/// Run the scrubber on a background thread until the storage is closed
+pub(super) fn spawn(
+    db: Weak<DB>,
+    objects_dir: PathBuf,
+    cold_tier: Option<Arc<ColdTier>>,
+    totals: Arc<ObjectTotals>,
+    config: ScrubConfig,
+    metrics: Arc<ScrubMetrics>,
+) -> Result<()> {
+    thread::Builder::new()
+        .name("fabrik-scrub".to_string())
+        .spawn(move || {
+            let mut scrubber = Scrubber::new(db, objects_dir, cold_tier, totals, metrics.clone());
+            loop {
+                match scrubber.step() {
+                    Ok(Some(ScrubStep::Checked { bytes, .. })) => {
+                        thread::sleep(config.delay(scrubber.pass_objects(), bytes));
+                    }
+                    Ok(Some(ScrubStep::PassComplete)) => info!(
+                        "Integrity scrub pass complete: {} objects checked so far, {} corrupt, {} missing",
+                        metrics.objects_total(),
+                        metrics.corrupt_total(),
+                        metrics.missing_total()
+                    ),
+                    Ok(None) => {
+                        debug!("Integrity scrub stopped: storage closed");
+                        return;
+                    }
+                    Err(e) => {
+                        warn!("Integrity scrub failed: {}", e);
+                        thread::sleep(ERROR_BACKOFF);
+                    }
+                }
+            }
+        })
+        .io_context("Failed to spawn scrub thread")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(
+            ScrubConfig::parse("1%", "8MB").unwrap(),
+            ScrubConfig {
+                fraction_per_hour: 0.01,
+                max_bytes_per_sec: 8 * 1024 * 1024,
+            }
+        );
+        assert_eq!(
+            ScrubConfig::parse("0.5%", "8MB").unwrap().fraction_per_hour,
+            0.005
+        );
+        assert!(ScrubConfig::parse("1", "8MB").is_err());
+        assert!(ScrubConfig::parse("0%", "8MB").is_err());
+        assert!(ScrubConfig::parse("150%", "8MB").is_err());
+        assert!(ScrubConfig::parse("1%", "0").is_err());
+    }
+
+    #[test]
+    fn test_delay() {
+        let config = ScrubConfig::parse("10%", "1MB").unwrap();
+
+        // 10% of 1000 objects per hour: one every 36s
+        assert_eq!(config.delay(1000, 1024), Duration::from_secs(36));
+        // A large object is limited by the bandwidth instead
+        assert_eq!(
+            config.delay(1000, 60 * 1024 * 1024),
+            Duration::from_secs(60)
+        );
+        // Tiny caches still get at least one object per hour
+        assert_eq!(config.delay(0, 0), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let metrics = ScrubMetrics::new();
+        metrics.start_pass(4);
+        metrics.record(ScrubOutcome::Ok, 10);
+        metrics.record(ScrubOutcome::Corrupt, 10);
+
+        assert_eq!(metrics.pass_coverage(), 0.5);
+        assert_eq!(metrics.error_rate(), 0.5);
+        let exported = metrics.export_prometheus();
+        assert!(exported.contains("fabrik_scrub_objects_total 2"));
+        assert!(exported.contains("fabrik_scrub_errors_total{kind=\"corrupt\"} 1"));
+        assert!(exported.contains("fabrik_scrub_bytes_total 20"));
+    }
+}
@@ -0,0 +1,204 @@
+/// Consistent snapshots of a cache (`fabrik server backup`)
+///
+/// A snapshot is a RocksDB checkpoint of the metadata, taken atomically while the cache
+/// serves requests (SST files are hard-linked when the snapshot directory is on the same
+/// filesystem), plus the list of objects the checkpoint knows about, read from the
+/// checkpoint itself so the two always agree. The object files aren't copied: the caller
+/// uploads them, and an object evicted before it's read is simply left out.
+///
+/// Restoring writes the snapshot's files into an empty cache directory, then each
+/// object with `FilesystemStorage::restore_object`; the metadata of objects that
+/// couldn't be restored is dropped with `Storage::delete`.
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::{IteratorMode, Options, DB};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::dedup;
+use super::filesystem::ObjectMetadata;
+use super::format::{self, FORMAT_FILE};
+use super::tiers::{self, ColdTier};
+use crate::error::{FabrikError, Result, ResultExt};
+use crate::hashing::HashAlgorithm;
+
+/// Metadata directory of a cache, relative to the cache directory
+const METADATA_DIR: &str = "metadata";
+
+/// An object of a snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotObject {
+    pub id: Vec<u8>,
+    pub size: u64,
+    /// Checksum recorded in the metadata, None for objects written before checksums
+    pub checksum: Option<[u8; 32]>,
+    pub checksum_algorithm: HashAlgorithm,
+    /// Where the object's file is (in the hot or the cold tier)
+    pub path: PathBuf,
+    /// Files of the object's chunks, in order, for deduplicated objects (see
+    /// `storage::dedup`); empty for objects stored whole
+    pub chunks: Vec<PathBuf>,
+}
+
+impl SnapshotObject {
+    /// Contents of the object (NotFound if it was evicted since the snapshot)
+    pub async fn read(&self) -> std::io::Result<Vec<u8>> {
+        if self.chunks.is_empty() {
+            return tokio::fs::read(&self.path).await;
+        }
+        let mut data = Vec::with_capacity(self.size as usize);
+        for path in &self.chunks {
+            data.extend_from_slice(&tokio::fs::read(path).await?);
+        }
+        Ok(data)
+    }
+}
+
+/// A snapshot written to a directory
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Files to restore into the cache directory, relative to the snapshot's directory
+    pub files: Vec<String>,
+    pub objects: Vec<SnapshotObject>,
+    /// Metadata entries that aren't valid object metadata (left out of `objects`)
+    pub corrupt: u64,
+}
+
+/// Write a snapshot of `db` to `dir`, which must exist and be empty
+pub(super) fn create(
+    db: &DB,
+    column_families: &[&str],
+    objects_dir: &Path,
+    cold_tier: Option<&ColdTier>,
+    dir: &Path,
+) -> Result<Snapshot> {
+    let checkpoint_dir = dir.join(METADATA_DIR);
+    Checkpoint::new(db)
+        .and_then(|checkpoint| checkpoint.create_checkpoint(&checkpoint_dir))
+        .io_context("Failed to create metadata checkpoint")?;
+    // The live cache was upgraded when it was opened
+    format::write(dir, format::CURRENT_VERSION)?;
+
+    let checkpoint =
+        DB::open_cf_for_read_only(&Options::default(), &checkpoint_dir, column_families, false)
+            .io_context("Failed to open metadata checkpoint")?;
+    let chunks_dir = dedup::chunks_dir(objects_dir);
+    let mut objects = Vec::new();
+    let mut corrupt = 0;
+    for item in checkpoint.iterator(IteratorMode::Start) {
+        let (id, value) = item.io_context("Failed to read metadata checkpoint")?;
+        let Ok(metadata) = ObjectMetadata::from_bytes(&value) else {
+            corrupt += 1;
+            continue;
+        };
+        let (path, _) = tiers::locate(&checkpoint, objects_dir, cold_tier, &id)?;
+        objects.push(SnapshotObject {
+            id: id.to_vec(),
+            size: metadata.size,
+            checksum: metadata.checksum,
+            checksum_algorithm: metadata.checksum_algorithm,
+            path,
+            chunks: dedup::chunk_paths(&checkpoint, &chunks_dir, &id)?.unwrap_or_default(),
+        });
+    }
+    drop(checkpoint);
+
+    let mut files = vec![FORMAT_FILE.to_string()];
+    for entry in fs::read_dir(&checkpoint_dir).io_context("Failed to list metadata checkpoint")? {
+        let entry = entry.io_context("Failed to list metadata checkpoint")?;
+        if entry.file_type().is_ok_and(|t| t.is_file()) {
+            files.push(format!(
+                "{}/{}",
+                METADATA_DIR,
+                entry.file_name().to_string_lossy()
+            ));
+        }
+    }
+    files.sort();
+
+    Ok(Snapshot {
+        files,
+        objects,
+        corrupt,
+    })
+}
+
+/// Check that a snapshot can be restored into `cache_dir` (no cache there yet)
+pub fn check_restore_target(cache_dir: &Path) -> Result<()> {
+    if cache_dir.join(METADATA_DIR).exists() || cache_dir.join(FORMAT_FILE).exists() {
+        return Err(FabrikError::config(format!(
+            "{} already holds a cache; restore into an empty directory",
+            cache_dir.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Write a file of a snapshot (`Snapshot::files`) into `cache_dir`
+pub fn restore_file(cache_dir: &Path, name: &str, data: &[u8]) -> Result<()> {
+    let valid = name == FORMAT_FILE
+        || name
+            .strip_prefix(METADATA_DIR)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .is_some_and(|file| !file.is_empty() && !file.contains(['/', '\\']) && file != "..");
+    if !valid {
+        return Err(FabrikError::corrupt(format!(
+            "Unexpected file in snapshot: {}",
+            name
+        )));
+    }
+    let path = cache_dir.join(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).io_context("Failed to create metadata directory")?;
+    }
+    fs::write(&path, data).io_context("Failed to write snapshot file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{FilesystemStorage, Storage};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_snapshot_and_restore_files() {
+        let cache = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(cache.path()).unwrap();
+        storage.put(b"one", b"first object").unwrap();
+        storage.put(b"two", b"second object").unwrap();
+
+        let dir = TempDir::new().unwrap();
+        let snapshot = storage.snapshot(dir.path()).unwrap();
+        // Written after the checkpoint, so not part of it
+        storage.put(b"three", b"third object").unwrap();
+
+        let ids: Vec<&[u8]> = snapshot.objects.iter().map(|o| o.id.as_slice()).collect();
+        assert_eq!(ids, vec![b"one".as_slice(), b"two".as_slice()]);
+        assert_eq!(snapshot.objects[0].size, 12);
+        assert!(snapshot.objects[0].checksum.is_some());
+        assert_eq!(
+            fs::read(&snapshot.objects[0].path).unwrap(),
+            b"first object"
+        );
+        assert!(snapshot.files.contains(&FORMAT_FILE.to_string()));
+
+        // Restoring the files and objects brings back the cache as it was
+        let restored = TempDir::new().unwrap();
+        check_restore_target(restored.path()).unwrap();
+        for name in &snapshot.files {
+            let data = fs::read(dir.path().join(name)).unwrap();
+            restore_file(restored.path(), name, &data).unwrap();
+        }
+        let target = FilesystemStorage::new(restored.path()).unwrap();
+        for object in &snapshot.objects {
+            let data = fs::read(&object.path).unwrap();
+            target.restore_object(&object.id, &data).unwrap();
+        }
+        assert_eq!(target.get(b"one").unwrap(), Some(b"first object".to_vec()));
+        assert_eq!(target.get(b"two").unwrap(), Some(b"second object".to_vec()));
+        assert!(!target.exists(b"three").unwrap());
+        assert!(check_restore_target(restored.path()).is_err());
+
+        assert!(restore_file(restored.path(), "../escape", b"").is_err());
+        assert!(restore_file(restored.path(), "metadata/../../escape", b"").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+/// Per-entry origin tags
+///
+/// Content hashes say nothing about where an entry came from. Adapters can attach tags
+/// to the entries they store — the build system that uploaded them and a fingerprint of
+/// the machine that produced them — so entries that only make sense on similar machines
+/// can be told apart (see the Gradle `machine_specific` setting). Entries fetched from
+/// elsewhere rather than uploaded by a client (e.g. by `fabrik cache warm`) record where
+/// they came from.
+///
+/// The RocksDB backend keeps tags in the `entry_tags` column family, keyed by object ID
+/// and deleted with the object (including on eviction). The embedded backend doesn't
+/// store tags.
+use serde::{Deserialize, Serialize};
+
+use crate::error::{FabrikError, Result};
+
+/// Column family of the entry tags (value: JSON-encoded `EntryTags`)
+pub(super) const CF_ENTRY_TAGS: &str = "entry_tags";
+
+/// Origin of a cached entry
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryTags {
+    /// Build system that stored the entry (e.g. "gradle")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_system: Option<String>,
+
+    /// Fingerprint of the machine that produced the entry (OS, architecture, runtime)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub machine: Option<String>,
+
+    /// Where the entry came from (untagged entries were uploaded by a client)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+}
+
+/// Where a cached entry came from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    /// Uploaded by a client of this cache
+    #[default]
+    Local,
+    /// Fetched from an upstream cache
+    Upstream,
+    /// Fetched from a peer on the local network
+    Peer,
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Origin::Local => "local",
+            Origin::Upstream => "upstream",
+            Origin::Peer => "peer",
+        })
+    }
+}
+
+impl std::str::FromStr for Origin {
+    type Err = FabrikError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "local" => Ok(Origin::Local),
+            "upstream" => Ok(Origin::Upstream),
+            "peer" => Ok(Origin::Peer),
+            other => Err(FabrikError::config(format!(
+                "Unknown origin '{}' (expected local, upstream or peer)",
+                other
+            ))),
+        }
+    }
+}
+
+impl EntryTags {
+    pub(super) fn to_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self)
+            .map_err(|e| FabrikError::corrupt(format!("Failed to encode entry tags: {}", e)))
+    }
+
+    pub(super) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes)
+            .map_err(|e| FabrikError::corrupt(format!("Invalid entry tags: {}", e)))
+    }
+}
@@ -0,0 +1,226 @@
+/// Tiered local storage: a hot tier on a fast disk and a cold tier on a large one
+///
+/// With `[cache] cold_dir`, each object lives in one of two directories: the cache's own
+/// `objects/` (the hot tier, e.g. on an SSD) or `<cold_dir>/objects/` (the cold tier,
+/// e.g. on an HDD). The metadata database stays in the hot tier and lists the objects
+/// of the cold tier in the `cold_tier` column family (key: object ID, empty value), so
+/// reads go straight to the right directory.
+///
+/// - Objects larger than `hot_max_object_size` are written to the cold tier.
+/// - When the hot tier grows past `hot_max_size`, background eviction demotes objects
+///   to the cold tier, in eviction policy order, until it's under 90% of it. Objects are
+///   only deleted when both tiers together exceed `max_size`.
+/// - Reading a cold object that is small enough promotes it back to the hot tier, if
+///   the hot tier has room for it.
+use rocksdb::{ColumnFamily, IteratorMode, DB};
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use super::filesystem::{object_path, ObjectMetadata};
+use super::partitions::{Change, ObjectTotals};
+use crate::error::{FabrikError, Result, ResultExt};
+use crate::eviction::EvictionConfig;
+
+/// Column family of the objects in the cold tier
+pub(super) const CF_COLD_TIER: &str = "cold_tier";
+
+/// Default `hot_max_object_size`
+pub const DEFAULT_HOT_MAX_OBJECT_SIZE: &str = "16MB";
+
+/// Location and sizes of the tiers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TierConfig {
+    /// Directory of the cold tier (objects go to `<cold_dir>/objects`)
+    pub cold_dir: PathBuf,
+    /// Size the hot tier is kept under by demoting objects
+    pub hot_max_bytes: u64,
+    /// Objects larger than this are stored in the cold tier
+    pub hot_max_object_bytes: u64,
+}
+
+impl TierConfig {
+    /// Parse the tier settings of `[cache]` (sizes such as "20GB")
+    pub fn parse(
+        cold_dir: &str,
+        hot_max_size: Option<&str>,
+        hot_max_object_size: &str,
+    ) -> Result<Self> {
+        let hot_max_size = hot_max_size.ok_or_else(|| {
+            FabrikError::config("cache.hot_max_size is required with cache.cold_dir")
+        })?;
+        let parse_size = |name: &str, size: &str| {
+            EvictionConfig::parse_size(size).map_err(|e| {
+                FabrikError::config(format!("Invalid cache.{} '{}': {:#}", name, size, e))
+            })
+        };
+
+        Ok(Self {
+            cold_dir: PathBuf::from(cold_dir),
+            hot_max_bytes: parse_size("hot_max_size", hot_max_size)?,
+            hot_max_object_bytes: parse_size("hot_max_object_size", hot_max_object_size)?,
+        })
+    }
+}
+
+/// The cold tier of a storage
+#[derive(Debug)]
+pub(super) struct ColdTier {
+    pub(super) config: TierConfig,
+    pub(super) objects_dir: PathBuf,
+    /// Objects and bytes in the cold tier, counted on first use
+    totals: ObjectTotals,
+}
+
+impl ColdTier {
+    pub(super) fn open(config: TierConfig) -> Result<Self> {
+        let objects_dir = config.cold_dir.join("objects");
+        fs::create_dir_all(&objects_dir).io_context("Failed to create cold tier directory")?;
+        Ok(Self {
+            config,
+            objects_dir,
+            totals: ObjectTotals::default(),
+        })
+    }
+
+    /// Whether an object of `size` bytes belongs in the hot tier
+    pub(super) fn fits_hot(&self, size: u64) -> bool {
+        size <= self.config.hot_max_object_bytes
+    }
+
+    /// Whether object `id` is in the cold tier
+    pub(super) fn contains(&self, db: &DB, id: &[u8]) -> Result<bool> {
+        // Caches opened read-only may predate the column family
+        let Some(cf) = db.cf_handle(CF_COLD_TIER) else {
+            return Ok(false);
+        };
+        Ok(db.get_cf(cf, id)?.is_some())
+    }
+
+    /// IDs of the objects in the cold tier
+    pub(super) fn ids(&self, db: &DB) -> Result<HashSet<Vec<u8>>> {
+        let mut ids = HashSet::new();
+        let Some(cf) = db.cf_handle(CF_COLD_TIER) else {
+            return Ok(ids);
+        };
+        for item in db.iterator_cf(cf, IteratorMode::Start) {
+            let (id, _) = item?;
+            ids.insert(id.to_vec());
+        }
+        Ok(ids)
+    }
+
+    /// Bytes stored in the cold tier
+    pub(super) fn bytes(&self, db: &DB) -> Result<u64> {
+        let (_, bytes) = self.totals.get_or_count(|| {
+            let (mut objects, mut bytes) = (0, 0);
+            for id in self.ids(db)? {
+                let Some(value) = db.get(&id)? else {
+                    continue;
+                };
+                if let Ok(metadata) = ObjectMetadata::from_bytes(&value) {
+                    objects += 1;
+                    bytes += metadata.size;
+                }
+            }
+            Ok((objects, bytes))
+        })?;
+        Ok(bytes)
+    }
+
+    /// Record that object `id`, stored with `previous` bytes before (None if it's new),
+    /// now has `size` bytes in the cold tier if `cold`, or else in the hot tier
+    /// (`hot_dir`), and remove its file from the other tier
+    pub(super) fn settle(
+        &self,
+        db: &DB,
+        hot_dir: &Path,
+        id: &[u8],
+        previous: Option<u64>,
+        size: u64,
+        cold: bool,
+    ) -> Result<()> {
+        let cf = handle(db)?;
+        let was_cold = self.contains(db, id)?;
+        match (was_cold, cold) {
+            (false, false) => Ok(()),
+            (true, true) => self.totals.record(|| {
+                let change = previous.map_or(Change::Added(size), |from| Change::Resized {
+                    from,
+                    to: size,
+                });
+                Ok(((), change))
+            }),
+            (false, true) => {
+                self.totals.record(|| {
+                    db.put_cf(cf, id, b"")
+                        .io_context("Failed to move object to the cold tier")?;
+                    Ok(((), Change::Added(size)))
+                })?;
+                remove_object(&object_path(hot_dir, id))
+            }
+            (true, false) => {
+                self.totals.record(|| {
+                    db.delete_cf(cf, id)
+                        .io_context("Failed to move object to the hot tier")?;
+                    Ok(((), previous.map_or(Change::None, Change::Removed)))
+                })?;
+                remove_object(&object_path(&self.objects_dir, id))
+            }
+        }
+    }
+
+    /// Forget object `id` of `size` bytes, deleted from the cache while in the cold tier
+    pub(super) fn forget(&self, db: &DB, id: &[u8], size: Option<u64>) -> Result<()> {
+        let cf = handle(db)?;
+        self.totals.record(|| {
+            db.delete_cf(cf, id)
+                .io_context("Failed to delete cold tier entry")?;
+            Ok(((), size.map_or(Change::None, Change::Removed)))
+        })
+    }
+}
+
+/// Path of object `id`, and whether it's in the cold tier
+pub(super) fn locate(
+    db: &DB,
+    hot_dir: &Path,
+    cold: Option<&ColdTier>,
+    id: &[u8],
+) -> Result<(PathBuf, bool)> {
+    match cold {
+        Some(cold) if cold.contains(db, id)? => Ok((object_path(&cold.objects_dir, id), true)),
+        _ => Ok((object_path(hot_dir, id), false)),
+    }
+}
+
+fn remove_object(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(e).io_context("Failed to remove object from its previous tier")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn handle(db: &DB) -> Result<&ColumnFamily> {
+    db.cf_handle(CF_COLD_TIER)
+        .ok_or_else(|| FabrikError::corrupt("Failed to get CF_COLD_TIER handle"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = TierConfig::parse("/mnt/hdd", Some("2GB"), "1MB").unwrap();
+        assert_eq!(config.cold_dir, PathBuf::from("/mnt/hdd"));
+        assert_eq!(config.hot_max_bytes, 2 * 1024 * 1024 * 1024);
+        assert_eq!(config.hot_max_object_bytes, 1024 * 1024);
+
+        assert!(TierConfig::parse("/mnt/hdd", None, "1MB").is_err());
+        assert!(TierConfig::parse("/mnt/hdd", Some("lots"), "1MB").is_err());
+    }
+}
@@ -0,0 +1,567 @@
+/// Directory snapshots stored in the CAS as Merkle trees
+///
+/// `fabrik cas put-dir` stores each file of a directory as a blob and each directory as
+/// a manifest blob listing its entries: files with their hash, size and executable bit,
+/// symlinks with their target, and subdirectories with the hash of their own manifest.
+/// The hash of the root manifest identifies the whole snapshot, and `fabrik cas
+/// get-tree` rebuilds the directory from it elsewhere.
+///
+/// Manifests are JSON with entries sorted by name, so the same contents always hash the
+/// same: unchanged files and subdirectories are stored once across snapshots. Each
+/// manifest pins the blobs it lists (see `pins`), so evicting a snapshot's files or
+/// subdirectories while its manifest is stored can't break it.
+///
+/// Blobs are stored under their hex hash, like `fabrik cas put`. Rebuilding checks every
+/// manifest and file against its hash, and never writes through a symlink: entries are
+/// created exclusively and symlinks are only created once everything else is written.
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::Storage;
+use crate::error::{FabrikError, Result, ResultExt};
+use crate::hashing::{HashAlgorithm, HashingReader};
+
+/// Version of the manifests written by `put_dir`
+pub const TREE_VERSION: u32 = 1;
+
+/// Manifest of one directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryManifest {
+    pub version: u32,
+    /// Algorithm of the hashes of the entries (and of this manifest)
+    pub hash_algorithm: HashAlgorithm,
+    /// Sorted by name
+    pub entries: Vec<TreeEntry>,
+}
+
+/// Entry of a directory manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TreeEntry {
+    File {
+        name: String,
+        hash: String,
+        size: u64,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        executable: bool,
+    },
+    Symlink {
+        name: String,
+        target: String,
+    },
+    Directory {
+        name: String,
+        hash: String,
+    },
+}
+
+impl TreeEntry {
+    pub fn name(&self) -> &str {
+        match self {
+            TreeEntry::File { name, .. }
+            | TreeEntry::Symlink { name, .. }
+            | TreeEntry::Directory { name, .. } => name,
+        }
+    }
+}
+
+/// What `put_dir` stored
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PutDirStats {
+    /// Hash of the root manifest
+    pub root: String,
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    /// Total size of the files
+    pub bytes: u64,
+    /// Blobs (files and manifests) that weren't in the cache yet
+    pub stored: u64,
+}
+
+/// What `get_tree` wrote
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GetTreeStats {
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    pub bytes: u64,
+}
+
+/// Store the directory `dir` as a tree of blobs, returning the hash of its manifest
+pub fn put_dir(storage: &dyn Storage, dir: &Path, algorithm: HashAlgorithm) -> Result<PutDirStats> {
+    let mut stats = PutDirStats::default();
+    stats.root = put_directory(storage, dir, algorithm, &mut stats)?;
+    Ok(stats)
+}
+
+fn put_directory(
+    storage: &dyn Storage,
+    dir: &Path,
+    algorithm: HashAlgorithm,
+    stats: &mut PutDirStats,
+) -> Result<String> {
+    let context = format!("Failed to read directory {}", dir.display());
+    let mut children = fs::read_dir(dir)
+        .io_context(&context)?
+        .collect::<io::Result<Vec<_>>>()
+        .io_context(&context)?;
+    children.sort_by_key(|child| child.file_name());
+
+    let mut entries = Vec::with_capacity(children.len());
+    for child in children {
+        let path = child.path();
+        let name = child.file_name().into_string().map_err(|_| {
+            FabrikError::config(format!("Unsupported non-UTF-8 name: {}", path.display()))
+        })?;
+        let file_type = child
+            .file_type()
+            .io_context(&format!("Failed to read {}", path.display()))?;
+
+        let entry = if file_type.is_symlink() {
+            let target = fs::read_link(&path)
+                .io_context(&format!("Failed to read symlink {}", path.display()))?;
+            let target = target.into_os_string().into_string().map_err(|_| {
+                FabrikError::config(format!(
+                    "Unsupported non-UTF-8 symlink target: {}",
+                    path.display()
+                ))
+            })?;
+            stats.symlinks += 1;
+            TreeEntry::Symlink { name, target }
+        } else if file_type.is_dir() {
+            let hash = put_directory(storage, &path, algorithm, stats)?;
+            TreeEntry::Directory { name, hash }
+        } else if file_type.is_file() {
+            let (hash, size, executable) = put_file(storage, &path, algorithm, stats)?;
+            TreeEntry::File {
+                name,
+                hash,
+                size,
+                executable,
+            }
+        } else {
+            return Err(FabrikError::config(format!(
+                "Unsupported file type: {}",
+                path.display()
+            )));
+        };
+        entries.push(entry);
+    }
+
+    let manifest = DirectoryManifest {
+        version: TREE_VERSION,
+        hash_algorithm: algorithm,
+        entries,
+    };
+    let json = serde_json::to_vec(&manifest).expect("manifest serializes");
+    let hash = algorithm.hex_digest(&json);
+    if !storage.exists(hash.as_bytes())? {
+        storage.put(hash.as_bytes(), &json)?;
+        stats.stored += 1;
+    }
+    let children: Vec<Vec<u8>> = manifest
+        .entries
+        .iter()
+        .filter_map(|entry| match entry {
+            TreeEntry::File { hash, .. } | TreeEntry::Directory { hash, .. } => {
+                Some(hash.as_bytes().to_vec())
+            }
+            TreeEntry::Symlink { .. } => None,
+        })
+        .collect();
+    storage.pin(hash.as_bytes(), &children)?;
+    stats.directories += 1;
+    Ok(hash)
+}
+
+/// Store a file unless it's already stored, returning its hash, size and executable bit
+fn put_file(
+    storage: &dyn Storage,
+    path: &Path,
+    algorithm: HashAlgorithm,
+    stats: &mut PutDirStats,
+) -> Result<(String, u64, bool)> {
+    let context = format!("Failed to read {}", path.display());
+    let file = File::open(path).io_context(&context)?;
+    let metadata = file.metadata().io_context(&context)?;
+    let mut reader = HashingReader::new(file, algorithm);
+    io::copy(&mut reader, &mut io::sink()).io_context(&context)?;
+    let hash = hex::encode(reader.finalize());
+
+    if !storage.exists(hash.as_bytes())? {
+        storage.put_file(hash.as_bytes(), path, None)?;
+        stats.stored += 1;
+    }
+    stats.files += 1;
+    stats.bytes += metadata.len();
+    Ok((hash, metadata.len(), is_executable(&metadata)))
+}
+
+/// Rebuild the tree whose root manifest is `root` in `dest`, which must not exist or be
+/// empty
+pub fn get_tree(storage: &dyn Storage, root: &str, dest: &Path) -> Result<GetTreeStats> {
+    let empty = match fs::read_dir(dest) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+        Err(e) => return Err(e).io_context(&format!("Failed to read {}", dest.display())),
+    };
+    if !empty {
+        return Err(FabrikError::config(format!(
+            "{} is not empty",
+            dest.display()
+        )));
+    }
+
+    fs::create_dir_all(dest).io_context(&format!("Failed to create {}", dest.display()))?;
+    let mut stats = GetTreeStats::default();
+    let mut symlinks = Vec::new();
+    get_directory(storage, root, dest, &mut symlinks, &mut stats)?;
+    for (target, path) in symlinks {
+        symlink(&target, &path)?;
+        stats.symlinks += 1;
+    }
+    Ok(stats)
+}
+
+/// Manifest stored under `hash`, checked against it
+pub fn load_manifest(storage: &dyn Storage, hash: &str) -> Result<DirectoryManifest> {
+    let json = storage
+        .get(hash.as_bytes())?
+        .ok_or_else(|| FabrikError::not_found(format!("Directory manifest {}", hash)))?;
+    let manifest: DirectoryManifest = serde_json::from_slice(&json)
+        .map_err(|e| FabrikError::corrupt(format!("Invalid directory manifest {}: {}", hash, e)))?;
+    if manifest.version > TREE_VERSION {
+        return Err(FabrikError::corrupt(format!(
+            "Directory manifest {} has unsupported version {}",
+            hash, manifest.version
+        )));
+    }
+    if manifest.hash_algorithm.hex_digest(&json) != hash {
+        return Err(FabrikError::corrupt(format!(
+            "Directory manifest {} doesn't match its hash",
+            hash
+        )));
+    }
+    Ok(manifest)
+}
+
+/// Write the entries of the manifest `hash` into the existing directory `dir`, queueing
+/// its symlinks (target, path) in `symlinks`
+fn get_directory(
+    storage: &dyn Storage,
+    hash: &str,
+    dir: &Path,
+    symlinks: &mut Vec<(String, PathBuf)>,
+    stats: &mut GetTreeStats,
+) -> Result<()> {
+    let manifest = load_manifest(storage, hash)?;
+    stats.directories += 1;
+
+    // Names come from the cache: never let them escape `dir`, nor name an entry twice
+    // (a symlink and a file of the same name would write through the symlink)
+    let mut names = HashSet::with_capacity(manifest.entries.len());
+    for entry in &manifest.entries {
+        let name = entry.name();
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(FabrikError::corrupt(format!(
+                "Invalid entry name {:?} in directory manifest {}",
+                name, hash
+            )));
+        }
+        if !names.insert(name) {
+            return Err(FabrikError::corrupt(format!(
+                "Duplicate entry name {:?} in directory manifest {}",
+                name, hash
+            )));
+        }
+    }
+
+    for entry in &manifest.entries {
+        let path = dir.join(entry.name());
+        match entry {
+            TreeEntry::File {
+                hash: file_hash,
+                size,
+                executable,
+                ..
+            } => {
+                get_file(
+                    storage,
+                    manifest.hash_algorithm,
+                    file_hash,
+                    *size,
+                    &path,
+                    *executable,
+                )?;
+                stats.files += 1;
+                stats.bytes += size;
+            }
+            TreeEntry::Symlink { target, .. } => symlinks.push((target.clone(), path)),
+            TreeEntry::Directory { hash: dir_hash, .. } => {
+                fs::create_dir(&path)
+                    .io_context(&format!("Failed to create {}", path.display()))?;
+                get_directory(storage, dir_hash, &path, symlinks, stats)?
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get_file(
+    storage: &dyn Storage,
+    algorithm: HashAlgorithm,
+    hash: &str,
+    size: u64,
+    path: &Path,
+    executable: bool,
+) -> Result<()> {
+    let reader = storage
+        .open(hash.as_bytes())?
+        .ok_or_else(|| FabrikError::not_found(format!("Blob {} of {}", hash, path.display())))?;
+    let mut reader = HashingReader::new(reader, algorithm);
+    // Never follows a symlink or overwrites an entry
+    let mut file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .io_context(&format!("Failed to create {}", path.display()))?;
+    let written = io::copy(&mut reader, &mut file)
+        .io_context(&format!("Failed to write {}", path.display()))?;
+    if written != size || hex::encode(reader.finalize()) != hash {
+        let _ = fs::remove_file(path);
+        return Err(FabrikError::corrupt(format!(
+            "Blob {} of {} doesn't match its hash",
+            hash,
+            path.display()
+        )));
+    }
+    if executable {
+        set_executable(&file).io_context(&format!("Failed to set mode of {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn set_executable(file: &File) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = file.metadata()?.permissions();
+    permissions.set_mode(permissions.mode() | 0o111);
+    file.set_permissions(permissions)
+}
+
+#[cfg(not(unix))]
+fn set_executable(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, path: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, path)
+        .io_context(&format!("Failed to create symlink {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &str, path: &Path) -> Result<()> {
+    Err(FabrikError::config(format!(
+        "Symlinks aren't supported on this platform: {}",
+        path.display()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FilesystemStorage;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_put_dir_and_get_tree() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path().join("cache")).unwrap();
+        let source = temp_dir.path().join("source");
+        fs::create_dir_all(source.join("lib/empty")).unwrap();
+        fs::write(source.join("README"), b"hello").unwrap();
+        fs::write(source.join("lib/a.txt"), b"same").unwrap();
+        fs::write(source.join("lib/b.txt"), b"same").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::write(source.join("run.sh"), b"#!/bin/sh\n").unwrap();
+            fs::set_permissions(source.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+            std::os::unix::fs::symlink("README", source.join("link")).unwrap();
+        }
+
+        let stats = put_dir(&storage, &source, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(stats.directories, 3);
+        let manifest = load_manifest(&storage, &stats.root).unwrap();
+        assert_eq!(manifest.entries[0].name(), "README");
+
+        // Unchanged contents hash the same and aren't stored again
+        let again = put_dir(&storage, &source, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(again.root, stats.root);
+        assert_eq!(again.stored, 0);
+
+        let dest = temp_dir.path().join("dest");
+        let restored = get_tree(&storage, &stats.root, &dest).unwrap();
+        assert_eq!(restored.files, stats.files);
+        assert_eq!(restored.bytes, stats.bytes);
+        assert_eq!(fs::read(dest.join("README")).unwrap(), b"hello");
+        assert_eq!(fs::read(dest.join("lib/b.txt")).unwrap(), b"same");
+        assert!(dest.join("lib/empty").is_dir());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dest.join("run.sh"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_ne!(mode & 0o111, 0);
+            assert_eq!(
+                fs::read_link(dest.join("link")).unwrap(),
+                Path::new("README")
+            );
+        }
+
+        // The destination must be empty
+        assert!(get_tree(&storage, &stats.root, &dest).is_err());
+    }
+
+    #[test]
+    fn test_get_tree_rejects_corrupt_trees() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path().join("cache")).unwrap();
+        let store = |manifest: &DirectoryManifest| {
+            let json = serde_json::to_vec(manifest).unwrap();
+            let hash = HashAlgorithm::Sha256.hex_digest(&json);
+            storage.put(hash.as_bytes(), &json).unwrap();
+            hash
+        };
+
+        // Names escaping the destination
+        let escaping = store(&DirectoryManifest {
+            version: TREE_VERSION,
+            hash_algorithm: HashAlgorithm::Sha256,
+            entries: vec![TreeEntry::Symlink {
+                name: "../outside".to_string(),
+                target: "x".to_string(),
+            }],
+        });
+        let err = get_tree(&storage, &escaping, &temp_dir.path().join("a")).unwrap_err();
+        assert!(matches!(err, FabrikError::Corrupt(_)));
+        assert!(!temp_dir.path().join("outside").exists());
+
+        // Files that don't match their hash
+        storage.put(b"not-the-hash", b"content").unwrap();
+        let mismatched = store(&DirectoryManifest {
+            version: TREE_VERSION,
+            hash_algorithm: HashAlgorithm::Sha256,
+            entries: vec![TreeEntry::File {
+                name: "file".to_string(),
+                hash: "not-the-hash".to_string(),
+                size: 7,
+                executable: false,
+            }],
+        });
+        let dest = temp_dir.path().join("b");
+        let err = get_tree(&storage, &mismatched, &dest).unwrap_err();
+        assert!(matches!(err, FabrikError::Corrupt(_)));
+        assert!(!dest.join("file").exists());
+
+        // Missing manifests
+        let err = get_tree(&storage, "missing", &temp_dir.path().join("c")).unwrap_err();
+        assert!(matches!(err, FabrikError::NotFound(_)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_get_tree_never_writes_through_symlinks() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(temp_dir.path().join("cache")).unwrap();
+        let store = |manifest: &DirectoryManifest| {
+            let json = serde_json::to_vec(manifest).unwrap();
+            let hash = HashAlgorithm::Sha256.hex_digest(&json);
+            storage.put(hash.as_bytes(), &json).unwrap();
+            hash
+        };
+        let outside = temp_dir.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        let payload = HashAlgorithm::Sha256.hex_digest(b"payload");
+        storage.put(payload.as_bytes(), b"payload").unwrap();
+        let inner = store(&DirectoryManifest {
+            version: TREE_VERSION,
+            hash_algorithm: HashAlgorithm::Sha256,
+            entries: vec![TreeEntry::File {
+                name: "planted".to_string(),
+                hash: payload.clone(),
+                size: 7,
+                executable: false,
+            }],
+        });
+
+        // A symlink followed by a file or directory of the same name
+        for shadowing in [
+            TreeEntry::File {
+                name: "x".to_string(),
+                hash: payload.clone(),
+                size: 7,
+                executable: false,
+            },
+            TreeEntry::Directory {
+                name: "x".to_string(),
+                hash: inner.clone(),
+            },
+        ] {
+            let malicious = store(&DirectoryManifest {
+                version: TREE_VERSION,
+                hash_algorithm: HashAlgorithm::Sha256,
+                entries: vec![
+                    TreeEntry::Symlink {
+                        name: "x".to_string(),
+                        target: outside.to_str().unwrap().to_string(),
+                    },
+                    shadowing,
+                ],
+            });
+            let dest = TempDir::new().unwrap();
+            let err = get_tree(&storage, &malicious, dest.path()).unwrap_err();
+            assert!(matches!(err, FabrikError::Corrupt(_)));
+        }
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+
+        // Symlinks next to other entries are still restored
+        let linked = store(&DirectoryManifest {
+            version: TREE_VERSION,
+            hash_algorithm: HashAlgorithm::Sha256,
+            entries: vec![
+                TreeEntry::Symlink {
+                    name: "a".to_string(),
+                    target: outside.to_str().unwrap().to_string(),
+                },
+                TreeEntry::Directory {
+                    name: "b".to_string(),
+                    hash: inner,
+                },
+            ],
+        });
+        let dest = temp_dir.path().join("dest");
+        let restored = get_tree(&storage, &linked, &dest).unwrap();
+        assert_eq!(restored.symlinks, 1);
+        assert_eq!(fs::read(dest.join("b/planted")).unwrap(), b"payload");
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+    }
+}
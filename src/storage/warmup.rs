@@ -0,0 +1,265 @@
+/// Startup warm-up of the metadata block cache
+///
+/// After a reboot the RocksDB metadata DB is cold: every metadata lookup on the hot path
+/// (object sizes, access tracking) reads SST blocks from disk until regular traffic has
+/// filled the block cache, which makes the first builds after a restart slow on large
+/// caches. The optional warm-up (`cache.warmup`) reads the metadata of recently accessed
+/// objects on a background thread, most recent first, so their blocks are cached before
+/// builds ask for them.
+///
+/// The warm-up stays out of the way of real traffic: it reads at most
+/// `cache.warmup_rate` bytes per second, doesn't cache the index blocks it scans to find
+/// recent objects, stops once the block cache is full, and gives up as soon as the
+/// storage is closed.
+use super::filesystem::CF_INDEX_ACCESSED;
+use crate::error::{FabrikError, Result, ResultExt};
+use crate::eviction::EvictionConfig;
+use rocksdb::{Direction, IteratorMode, ReadOptions, DB};
+use std::collections::HashMap;
+use std::sync::Weak;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// Index entries or objects read between rate-limit pauses
+const BATCH_SIZE: usize = 256;
+
+/// Warm-up settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarmupConfig {
+    /// Only warm objects accessed within this many seconds
+    pub window_secs: u64,
+
+    /// Maximum metadata bytes read per second
+    pub max_bytes_per_sec: u64,
+}
+
+impl WarmupConfig {
+    /// Parse `cache.warmup_window` (e.g. "7d") and `cache.warmup_rate` (e.g. "16MB")
+    pub fn parse(window: &str, rate: &str) -> Result<Self> {
+        let window_secs = EvictionConfig::parse_ttl(window).map_err(|e| {
+            FabrikError::config(format!("Invalid warm-up window '{}': {:#}", window, e))
+        })?;
+        let max_bytes_per_sec = EvictionConfig::parse_size(rate).map_err(|e| {
+            FabrikError::config(format!("Invalid warm-up rate '{}': {:#}", rate, e))
+        })?;
+        if max_bytes_per_sec == 0 {
+            return Err(FabrikError::config(
+                "Warm-up rate must be greater than zero",
+            ));
+        }
+
+        Ok(Self {
+            window_secs,
+            max_bytes_per_sec,
+        })
+    }
+}
+
+/// What a warm-up read
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WarmupStats {
+    /// Objects whose metadata was read
+    pub objects: u64,
+
+    /// Bytes read (index scan and metadata)
+    pub bytes_read: u64,
+}
+
+/// Keeps the read rate under a limit by telling how long to pause
+struct Throttle {
+    start: Instant,
+    bytes: u64,
+    max_bytes_per_sec: u64,
+}
+
+impl Throttle {
+    fn new(max_bytes_per_sec: u64) -> Self {
+        Self {
+            start: Instant::now(),
+            bytes: 0,
+            max_bytes_per_sec,
+        }
+    }
+
+    fn record(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+    }
+
+    /// Pause needed for the bytes read so far to respect the rate
+    fn delay(&self) -> Duration {
+        let budget = Duration::from_secs_f64(self.bytes as f64 / self.max_bytes_per_sec as f64);
+        budget.saturating_sub(self.start.elapsed())
+    }
+}
+
+/// Run the warm-up on a background thread
+pub fn spawn(db: Weak<DB>, config: WarmupConfig) -> Result<()> {
+    thread::Builder::new()
+        .name("fabrik-warmup".to_string())
+        .spawn(move || {
+            let start = Instant::now();
+            match run(&db, &config) {
+                Ok(Some(stats)) => info!(
+                    "Metadata cache warm-up finished: {} objects, {}KB read in {:.1}s",
+                    stats.objects,
+                    stats.bytes_read / 1024,
+                    start.elapsed().as_secs_f64()
+                ),
+                Ok(None) => debug!("Metadata cache warm-up stopped: storage closed"),
+                Err(e) => warn!("Metadata cache warm-up failed: {}", e),
+            }
+        })
+        .io_context("Failed to spawn warm-up thread")?;
+    Ok(())
+}
+
+/// Warm the block cache; None if the storage was closed before the end
+///
+/// The DB is only held for one batch at a time so the warm-up never keeps a closed
+/// storage alive.
+pub fn run(db: &Weak<DB>, config: &WarmupConfig) -> Result<Option<WarmupStats>> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let cutoff = now.saturating_sub(config.window_secs as i64);
+    let mut throttle = Throttle::new(config.max_bytes_per_sec);
+
+    // Find recently accessed objects (the index has an entry per accessed object, see
+    // `storage::format`)
+    let mut recent: HashMap<Vec<u8>, i64> = HashMap::new();
+    let mut from = Vec::new();
+    loop {
+        let Some(db) = db.upgrade() else {
+            return Ok(None);
+        };
+        let cf = db
+            .cf_handle(CF_INDEX_ACCESSED)
+            .ok_or_else(|| FabrikError::corrupt("Failed to get CF_INDEX_ACCESSED handle"))?;
+        let mut read_options = ReadOptions::default();
+        read_options.fill_cache(false);
+
+        let mut scanned = 0;
+        let mut last_key = None;
+        for item in db.iterator_cf_opt(
+            cf,
+            read_options,
+            IteratorMode::From(&from, Direction::Forward),
+        ) {
+            let (key, _) = item?;
+            throttle.record(key.len());
+            if let Some((accessed_at, id)) = parse_index_key(&key) {
+                if accessed_at >= cutoff {
+                    let latest = recent.entry(id.to_vec()).or_insert(accessed_at);
+                    *latest = (*latest).max(accessed_at);
+                }
+            }
+
+            scanned += 1;
+            if scanned == BATCH_SIZE {
+                last_key = Some(key);
+                break;
+            }
+        }
+        drop(db);
+
+        let Some(last_key) = last_key else {
+            break;
+        };
+        // Resume right after the last key
+        from = last_key.to_vec();
+        from.push(0);
+        thread::sleep(throttle.delay());
+    }
+
+    let mut hot: Vec<(Vec<u8>, i64)> = recent.into_iter().collect();
+    hot.sort_by_key(|(_, accessed_at)| std::cmp::Reverse(*accessed_at));
+    debug!(
+        "Metadata cache warm-up: {} objects accessed in the last {}s",
+        hot.len(),
+        config.window_secs
+    );
+
+    // Read their metadata, most recent first, until the block cache is full
+    let mut stats = WarmupStats::default();
+    for batch in hot.chunks(BATCH_SIZE) {
+        let Some(db) = db.upgrade() else {
+            return Ok(None);
+        };
+        if block_cache_full(&db) {
+            debug!("Metadata cache warm-up: block cache full");
+            break;
+        }
+
+        for (id, _) in batch {
+            if let Some(value) = db.get_pinned(id)? {
+                throttle.record(id.len() + value.len());
+                stats.objects += 1;
+            }
+        }
+        drop(db);
+
+        thread::sleep(throttle.delay());
+    }
+
+    stats.bytes_read = throttle.bytes;
+    Ok(Some(stats))
+}
+
+/// Split an `index_accessed` key into access timestamp and object ID
+fn parse_index_key(key: &[u8]) -> Option<(i64, &[u8])> {
+    if key.len() <= 8 {
+        return None;
+    }
+    let (timestamp, id) = key.split_at(8);
+    Some((i64::from_le_bytes(timestamp.try_into().ok()?), id))
+}
+
+fn block_cache_full(db: &DB) -> bool {
+    let usage = db.property_int_value("rocksdb.block-cache-usage");
+    let capacity = db.property_int_value("rocksdb.block-cache-capacity");
+    match (usage, capacity) {
+        (Ok(Some(usage)), Ok(Some(capacity))) => capacity > 0 && usage >= capacity,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(
+            WarmupConfig::parse("7d", "16MB").unwrap(),
+            WarmupConfig {
+                window_secs: 7 * 24 * 60 * 60,
+                max_bytes_per_sec: 16 * 1024 * 1024,
+            }
+        );
+        assert!(WarmupConfig::parse("7d", "0").is_err());
+        assert!(WarmupConfig::parse("soon", "16MB").is_err());
+    }
+
+    #[test]
+    fn test_parse_index_key() {
+        let mut key = 1_700_000_000i64.to_le_bytes().to_vec();
+        key.extend_from_slice(&[0xab, 0xcd]);
+        assert_eq!(
+            parse_index_key(&key),
+            Some((1_700_000_000, &[0xab, 0xcd][..]))
+        );
+        assert_eq!(parse_index_key(&key[..8]), None);
+    }
+
+    #[test]
+    fn test_throttle_delay() {
+        let mut throttle = Throttle::new(1000);
+        assert_eq!(throttle.delay(), Duration::ZERO);
+
+        throttle.record(2000);
+        let delay = throttle.delay();
+        assert!(delay > Duration::from_millis(1900) && delay <= Duration::from_secs(2));
+    }
+}
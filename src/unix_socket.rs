@@ -0,0 +1,183 @@
+//! Unix domain sockets of daemons (`[daemon] socket`, `http_socket`, `grpc_socket`)
+//!
+//! A socket is a path or, on Linux, an abstract name written `@name`. Abstract sockets
+//! have no file in the filesystem, so nothing is left behind when a daemon is killed.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Marks abstract socket names (`@name`)
+const ABSTRACT_PREFIX: char = '@';
+
+/// Name of an abstract socket (`@name`), None for a path
+pub fn abstract_name(socket: &Path) -> Option<&str> {
+    socket.to_str()?.strip_prefix(ABSTRACT_PREFIX)
+}
+
+/// Socket from the config, relative paths being resolved against the config file's
+/// directory
+pub fn resolve(socket: &str, config_path: Option<&Path>) -> PathBuf {
+    if socket.starts_with(ABSTRACT_PREFIX) {
+        return PathBuf::from(socket);
+    }
+    match config_path {
+        Some(config_path) => config_path.parent().unwrap_or(Path::new(".")).join(socket),
+        None => PathBuf::from(socket),
+    }
+}
+
+/// gRPC target of a socket (`unix:<path>` or `unix-abstract:<name>`), as Bazel's
+/// `--remote_cache` accepts it
+pub fn grpc_url(socket: &Path) -> String {
+    match abstract_name(socket) {
+        Some(name) => format!("unix-abstract:{}", name),
+        None => format!("unix:{}", socket.display()),
+    }
+}
+
+/// Listen on `socket`, replacing the socket file a previous process left behind
+///
+/// Anything else at the path (a file that isn't a socket, or the socket of a running
+/// process) is left alone, and binding fails.
+#[cfg(unix)]
+pub fn bind(socket: &Path) -> io::Result<tokio::net::UnixListener> {
+    if let Some(name) = abstract_name(socket) {
+        let listener = std::os::unix::net::UnixListener::bind_addr(&abstract_addr(name)?)?;
+        listener.set_nonblocking(true)?;
+        return tokio::net::UnixListener::from_std(listener);
+    }
+    if remove_stale(socket)? {
+        tracing::info!("Removed stale socket file: {}", socket.display());
+    }
+    tokio::net::UnixListener::bind(socket)
+}
+
+/// Connect to `socket` (blocking)
+#[cfg(unix)]
+pub fn connect(socket: &Path) -> io::Result<std::os::unix::net::UnixStream> {
+    match abstract_name(socket) {
+        Some(name) => std::os::unix::net::UnixStream::connect_addr(&abstract_addr(name)?),
+        None => std::os::unix::net::UnixStream::connect(socket),
+    }
+}
+
+/// Remove the file of `socket` (abstract sockets have none), once nothing listens on it
+pub fn remove(socket: &Path) -> io::Result<()> {
+    if abstract_name(socket).is_none() {
+        remove_stale(socket)?;
+    }
+    Ok(())
+}
+
+/// Remove the socket file at `path` if no process listens on it, returning whether there
+/// was one
+#[cfg(unix)]
+fn remove_stale(path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("Socket {} is in use by another process", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            std::fs::remove_file(path)?;
+            Ok(true)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(unix))]
+fn remove_stale(path: &Path) -> io::Result<bool> {
+    let exists = path.exists();
+    if exists {
+        std::fs::remove_file(path)?;
+    }
+    Ok(exists)
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_addr(name: &str) -> io::Result<std::os::unix::net::SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+    std::os::unix::net::SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn abstract_addr(name: &str) -> io::Result<std::os::unix::net::SocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "Abstract socket @{} requires Linux; use a socket path instead",
+            name
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_and_grpc_url() {
+        let config = Path::new("/project/fabrik.toml");
+        assert_eq!(
+            resolve(".fabrik/grpc.sock", Some(config)),
+            PathBuf::from("/project/.fabrik/grpc.sock")
+        );
+        assert_eq!(
+            resolve("@fabrik-ci", Some(config)),
+            PathBuf::from("@fabrik-ci")
+        );
+
+        assert_eq!(
+            grpc_url(Path::new("/project/.fabrik/grpc.sock")),
+            "unix:/project/.fabrik/grpc.sock"
+        );
+        assert_eq!(grpc_url(Path::new("@fabrik-ci")), "unix-abstract:fabrik-ci");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_bind_only_replaces_stale_sockets() {
+        let temp = tempfile::TempDir::new().unwrap();
+
+        // Not a socket, e.g. a mistyped path
+        let file = temp.path().join("fabrik.toml");
+        std::fs::write(&file, "[cache]").unwrap();
+        assert!(bind(&file).is_err());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "[cache]");
+
+        // The socket of a running daemon
+        let socket = temp.path().join("http.sock");
+        let listener = bind(&socket).unwrap();
+        assert_eq!(bind(&socket).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        assert!(remove(&socket).is_err());
+
+        // Left behind by a daemon that is gone
+        drop(listener);
+        let _listener = bind(&socket).unwrap();
+        assert!(connect(&socket).is_ok());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_abstract_socket() {
+        let socket = PathBuf::from(format!("@fabrik-test-{}", std::process::id()));
+        let _listener = bind(&socket).unwrap();
+        assert!(connect(&socket).is_ok());
+        // Nothing to remove
+        remove(&socket).unwrap();
+    }
+}
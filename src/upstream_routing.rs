@@ -0,0 +1,183 @@
+/// Routing of artifacts to upstreams by namespace or artifact type
+///
+/// Every artifact has a routing path `<namespace>/<key>`, e.g. `bazel-cas/<hash>` for a
+/// Bazel CAS blob or `scripts/<cache key>` for a `fabrik run` output. An upstream with
+/// `match = [...]` patterns only receives artifacts whose path matches one of them;
+/// upstreams without patterns receive everything no pattern claims. This lets e.g. large
+/// CAS blobs go to object storage while small action results stay on a Layer 2 server:
+///
+/// ```toml
+/// [[upstream]]
+/// url = "s3://big-bucket"
+/// match = ["bazel-cas/*"]
+///
+/// [[upstream]]
+/// url = "grpc://cache:7070"
+/// ```
+use anyhow::{Context, Result};
+use glob::Pattern;
+
+use crate::config::UpstreamConfig;
+
+/// Artifact namespaces (first component of the routing path)
+pub mod namespaces {
+    pub const BAZEL_CAS: &str = "bazel-cas";
+    pub const BAZEL_AC: &str = "bazel-ac";
+    pub const XCODE_CAS: &str = "xcode-cas";
+    pub const XCODE_KV: &str = "xcode-kv";
+    pub const GRADLE: &str = "gradle";
+    pub const NX: &str = "nx";
+    pub const TURBOREPO: &str = "turborepo";
+    pub const METRO: &str = "metro";
+    pub const SCCACHE: &str = "sccache";
+    pub const SCRIPTS: &str = "scripts";
+    pub const KV: &str = "kv";
+
+    pub const ALL: &[&str] = &[
+        BAZEL_CAS, BAZEL_AC, XCODE_CAS, XCODE_KV, GRADLE, NX, TURBOREPO, METRO, SCCACHE, SCRIPTS,
+        KV,
+    ];
+}
+
+/// An upstream and the compiled patterns it matches
+struct Route<'a> {
+    upstream: &'a UpstreamConfig,
+    patterns: Vec<Pattern>,
+}
+
+/// Picks the upstreams responsible for an artifact
+pub struct UpstreamRouter<'a> {
+    routes: Vec<Route<'a>>,
+}
+
+impl<'a> UpstreamRouter<'a> {
+    /// Compile the `match` patterns of `upstreams`
+    pub fn new(upstreams: &'a [UpstreamConfig]) -> Result<Self> {
+        let routes = upstreams
+            .iter()
+            .map(|upstream| {
+                let patterns = upstream
+                    .match_patterns
+                    .iter()
+                    .map(|pattern| compile(pattern))
+                    .collect::<Result<Vec<_>>>()
+                    .with_context(|| {
+                        format!("Invalid match pattern for upstream {}", upstream.url)
+                    })?;
+                Ok(Route { upstream, patterns })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { routes })
+    }
+
+    /// Upstreams for the artifact `key` in `namespace`, in lookup order
+    ///
+    /// Upstreams with a matching pattern if there are any, otherwise the upstreams
+    /// without patterns.
+    pub fn route(&self, namespace: &str, key: &str) -> Vec<&'a UpstreamConfig> {
+        let path = format!("{}/{}", namespace, key);
+
+        let matched: Vec<&UpstreamConfig> = self
+            .routes
+            .iter()
+            .filter(|route| route.patterns.iter().any(|p| p.matches(&path)))
+            .map(|route| route.upstream)
+            .collect();
+        if !matched.is_empty() {
+            return matched;
+        }
+
+        self.routes
+            .iter()
+            .filter(|route| route.patterns.is_empty())
+            .map(|route| route.upstream)
+            .collect()
+    }
+}
+
+/// Compile a pattern; a bare namespace (`bazel-cas`) matches everything in it
+fn compile(pattern: &str) -> Result<Pattern> {
+    let pattern = if pattern.contains('/') {
+        pattern.to_string()
+    } else {
+        format!("{}/*", pattern)
+    };
+    Pattern::new(&pattern).with_context(|| format!("'{}' is not a valid glob", pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream(url: &str, patterns: &[&str]) -> UpstreamConfig {
+        let toml = format!(
+            "url = {:?}\nmatch = {:?}",
+            url,
+            patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>()
+        );
+        toml::from_str(&toml).unwrap()
+    }
+
+    fn urls(upstreams: Vec<&UpstreamConfig>) -> Vec<&str> {
+        upstreams.iter().map(|u| u.url.as_str()).collect()
+    }
+
+    #[test]
+    fn test_route_by_namespace() {
+        let upstreams = vec![
+            upstream("s3://big-bucket", &["bazel-cas/*"]),
+            upstream("grpc://cache:7070", &[]),
+        ];
+        let router = UpstreamRouter::new(&upstreams).unwrap();
+
+        assert_eq!(
+            urls(router.route(namespaces::BAZEL_CAS, "abc123")),
+            vec!["s3://big-bucket"]
+        );
+        assert_eq!(
+            urls(router.route(namespaces::BAZEL_AC, "abc123")),
+            vec!["grpc://cache:7070"]
+        );
+        assert_eq!(
+            urls(router.route(namespaces::SCRIPTS, "build-key")),
+            vec!["grpc://cache:7070"]
+        );
+    }
+
+    #[test]
+    fn test_route_multiple_matches_and_bare_namespace() {
+        let upstreams = vec![
+            upstream("s3://big-bucket", &["bazel-cas", "xcode-cas"]),
+            upstream("grpc://cache:7070", &["bazel-*/*", "scripts/*"]),
+            upstream("https://fallback.example.com", &[]),
+        ];
+        let router = UpstreamRouter::new(&upstreams).unwrap();
+
+        assert_eq!(
+            urls(router.route(namespaces::BAZEL_CAS, "abc123")),
+            vec!["s3://big-bucket", "grpc://cache:7070"]
+        );
+        assert_eq!(
+            urls(router.route(namespaces::XCODE_CAS, "abc123")),
+            vec!["s3://big-bucket"]
+        );
+        assert_eq!(
+            urls(router.route(namespaces::GRADLE, "abc123")),
+            vec!["https://fallback.example.com"]
+        );
+    }
+
+    #[test]
+    fn test_unmatched_without_default_has_no_upstream() {
+        let upstreams = vec![upstream("s3://big-bucket", &["bazel-cas/*"])];
+        let router = UpstreamRouter::new(&upstreams).unwrap();
+        assert!(router.route(namespaces::KV, "key").is_empty());
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let upstreams = vec![upstream("s3://big-bucket", &["bazel-cas/[abc"])];
+        assert!(UpstreamRouter::new(&upstreams).is_err());
+    }
+}
@@ -1,5 +1,8 @@
 use super::proto::cas::*;
+use crate::auth::scopes::{self, Permission};
 use crate::logging::{operations, services, status};
+use crate::quota::{QuotaError, UploadLimits};
+use crate::rate_limit::grpc_client_identity;
 use crate::storage::Storage;
 use anyhow::Result;
 use prost::Message;
@@ -7,14 +10,27 @@ use std::sync::Arc;
 use tonic::{Request, Response, Status};
 use tracing::{debug, info};
 
+/// Length of CAS IDs (SHA256 of the stored object)
+const ID_LEN: usize = 32;
+
 /// CAS (Content-Addressable Storage) service implementation
 pub struct CasService<S: Storage> {
     storage: Arc<S>,
+    limits: Arc<UploadLimits>,
 }
 
 impl<S: Storage> CasService<S> {
     pub fn new(storage: Arc<S>) -> Self {
-        Self { storage }
+        Self {
+            storage,
+            limits: Arc::new(UploadLimits::unlimited()),
+        }
+    }
+
+    /// Enforce upload size limits and quotas
+    pub fn with_upload_limits(mut self, limits: Arc<UploadLimits>) -> Self {
+        self.limits = limits;
+        self
     }
 
     /// Serialize a CASObject to bytes
@@ -41,30 +57,73 @@ impl<S: Storage> CasService<S> {
         Ok(CasBlob::decode(data)?)
     }
 
-    /// Extract bytes from CASBytes (either inline data or file path)
-    #[allow(dead_code)]
-    fn extract_cas_bytes(cas_bytes: Option<CasBytes>) -> Result<Vec<u8>> {
+    /// Check that a CAS ID has the length of the IDs this service hands out
+    #[allow(clippy::result_large_err)]
+    fn validate_id(cas_id: &CasDataId) -> Result<(), Status> {
+        if cas_id.id.len() != ID_LEN {
+            return Err(Status::invalid_argument(format!(
+                "Malformed CAS ID: expected {} bytes, got {}",
+                ID_LEN,
+                cas_id.id.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Inline the contents of CASBytes sent as a file path, so stored objects never
+    /// point to files of the client
+    ///
+    /// Files over the max artifact size are rejected before they are read (quotas are
+    /// charged for the whole object afterwards).
+    #[allow(clippy::result_large_err)]
+    fn inline_cas_bytes(&self, cas_bytes: Option<CasBytes>) -> Result<Option<CasBytes>, Status> {
         match cas_bytes {
-            Some(CasBytes {
-                contents: Some(cas_bytes::Contents::Data(data)),
-            }) => Ok(data),
             Some(CasBytes {
                 contents: Some(cas_bytes::Contents::FilePath(path)),
             }) => {
-                // Read from file path
-                std::fs::read(&path)
-                    .map_err(|e| anyhow::anyhow!("Failed to read file {}: {}", path, e))
+                let size = std::fs::metadata(&path)
+                    .map_err(|e| {
+                        Status::invalid_argument(format!("Failed to read file {}: {}", path, e))
+                    })?
+                    .len();
+                if let Some(max) = self.limits.max_artifact_size(scopes::services::XCODE) {
+                    if size > max {
+                        return Err(QuotaError::TooLarge {
+                            service: scopes::services::XCODE.to_string(),
+                            size,
+                            max,
+                        }
+                        .into());
+                    }
+                }
+                let data = std::fs::read(&path).map_err(|e| {
+                    Status::invalid_argument(format!("Failed to read file {}: {}", path, e))
+                })?;
+                Ok(Some(CasBytes {
+                    contents: Some(cas_bytes::Contents::Data(data)),
+                }))
             }
-            _ => Err(anyhow::anyhow!("Invalid CASBytes: missing contents")),
+            other => Ok(other),
         }
     }
 
-    /// Create CASBytes from data (inline)
-    #[allow(dead_code)]
-    fn create_cas_bytes(data: Vec<u8>) -> CasBytes {
-        CasBytes {
-            contents: Some(cas_bytes::Contents::Data(data)),
+    /// Reference of `object` missing from storage, if any
+    ///
+    /// Clients walk references with chained gets, so an object is only stored once
+    /// everything it references is.
+    #[allow(clippy::result_large_err)]
+    fn missing_reference(&self, object: &CasObject) -> Result<Option<String>, Status> {
+        for reference in &object.references {
+            Self::validate_id(reference)?;
+            let exists = self
+                .storage
+                .exists(&reference.id)
+                .map_err(|e| Status::internal(format!("Failed to check reference: {}", e)))?;
+            if !exists {
+                return Ok(Some(hex::encode(&reference.id)));
+            }
         }
+        Ok(None)
     }
 }
 
@@ -74,16 +133,31 @@ impl<S: Storage + 'static> super::proto::cas::casdb_service_server::CasdbService
         &self,
         request: Request<CasPutRequest>,
     ) -> Result<Response<CasPutResponse>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::XCODE, Permission::Write)?;
+        let client = grpc_client_identity(&request);
+
         let req = request.into_inner();
 
-        let object = req
+        let mut object = req
             .data
             .ok_or_else(|| Status::invalid_argument("Missing CASObject data"))?;
+        object.blob = self.inline_cas_bytes(object.blob)?;
+
+        if let Some(missing) = self.missing_reference(&object)? {
+            return Ok(Response::new(CasPutResponse {
+                contents: Some(cas_put_response::Contents::Error(ResponseError {
+                    description: format!("Referenced object {} not found", missing),
+                })),
+            }));
+        }
 
         // Serialize the object
         let serialized = Self::serialize_object(&object)
             .map_err(|e| Status::internal(format!("Failed to serialize object: {}", e)))?;
 
+        self.limits
+            .check_upload(scopes::services::XCODE, &client, serialized.len() as u64)?;
+
         // Compute content hash (ID)
         let id = crate::storage::filesystem::hash_data(&serialized);
         let object_id = hex::encode(&id);
@@ -120,10 +194,13 @@ impl<S: Storage + 'static> super::proto::cas::casdb_service_server::CasdbService
         &self,
         request: Request<CasGetRequest>,
     ) -> Result<Response<CasGetResponse>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::XCODE, Permission::Read)?;
+
         let req = request.into_inner();
         let cas_id = req
             .cas_id
             .ok_or_else(|| Status::invalid_argument("Missing CAS ID"))?;
+        Self::validate_id(&cas_id)?;
 
         let object_id = hex::encode(&cas_id.id);
 
@@ -144,7 +221,7 @@ impl<S: Storage + 'static> super::proto::cas::casdb_service_server::CasdbService
             Some(bytes) => {
                 // Deserialize the object
                 let object = Self::deserialize_object(&bytes).map_err(|e| {
-                    Status::internal(format!("Failed to deserialize object: {}", e))
+                    Status::data_loss(format!("Failed to deserialize object: {}", e))
                 })?;
 
                 info!(
@@ -169,7 +246,7 @@ impl<S: Storage + 'static> super::proto::cas::casdb_service_server::CasdbService
                     object_id = %object_id,
                     "cache miss"
                 );
-                Ok(Response::new(CasGetResponse {
+                Ok(super::miss(CasGetResponse {
                     outcome: cas_get_response::Outcome::ObjectNotFound as i32,
                     contents: None,
                 }))
@@ -181,16 +258,23 @@ impl<S: Storage + 'static> super::proto::cas::casdb_service_server::CasdbService
         &self,
         request: Request<CasSaveRequest>,
     ) -> Result<Response<CasSaveResponse>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::XCODE, Permission::Write)?;
+        let client = grpc_client_identity(&request);
+
         let req = request.into_inner();
 
-        let blob = req
+        let mut blob = req
             .data
             .ok_or_else(|| Status::invalid_argument("Missing CASBlob data"))?;
+        blob.blob = self.inline_cas_bytes(blob.blob)?;
 
         // Serialize the blob
         let serialized = Self::serialize_blob(&blob)
             .map_err(|e| Status::internal(format!("Failed to serialize blob: {}", e)))?;
 
+        self.limits
+            .check_upload(scopes::services::XCODE, &client, serialized.len() as u64)?;
+
         // Compute content hash (ID)
         let id = crate::storage::filesystem::hash_data(&serialized);
         let object_id = hex::encode(&id);
@@ -227,10 +311,13 @@ impl<S: Storage + 'static> super::proto::cas::casdb_service_server::CasdbService
         &self,
         request: Request<CasLoadRequest>,
     ) -> Result<Response<CasLoadResponse>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::XCODE, Permission::Read)?;
+
         let req = request.into_inner();
         let cas_id = req
             .cas_id
             .ok_or_else(|| Status::invalid_argument("Missing CAS ID"))?;
+        Self::validate_id(&cas_id)?;
 
         let object_id = hex::encode(&cas_id.id);
 
@@ -251,7 +338,7 @@ impl<S: Storage + 'static> super::proto::cas::casdb_service_server::CasdbService
             Some(bytes) => {
                 // Deserialize the blob
                 let blob = Self::deserialize_blob(&bytes)
-                    .map_err(|e| Status::internal(format!("Failed to deserialize blob: {}", e)))?;
+                    .map_err(|e| Status::data_loss(format!("Failed to deserialize blob: {}", e)))?;
 
                 info!(
                     service = services::XCODE_CAS,
@@ -275,7 +362,7 @@ impl<S: Storage + 'static> super::proto::cas::casdb_service_server::CasdbService
                     object_id = %object_id,
                     "cache miss"
                 );
-                Ok(Response::new(CasLoadResponse {
+                Ok(super::miss(CasLoadResponse {
                     outcome: cas_load_response::Outcome::ObjectNotFound as i32,
                     contents: None,
                 }))
@@ -283,3 +370,190 @@ impl<S: Storage + 'static> super::proto::cas::casdb_service_server::CasdbService
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FilesystemStorage;
+    use crate::xcode::proto::cas::casdb_service_server::CasdbService;
+    use tempfile::TempDir;
+
+    fn service(dir: &TempDir) -> CasService<FilesystemStorage> {
+        let storage = FilesystemStorage::new(dir.path().to_str().unwrap()).unwrap();
+        CasService::new(Arc::new(storage))
+    }
+
+    fn object(data: &[u8], references: Vec<CasDataId>) -> CasObject {
+        CasObject {
+            blob: Some(CasBytes {
+                contents: Some(cas_bytes::Contents::Data(data.to_vec())),
+            }),
+            references,
+        }
+    }
+
+    async fn put(service: &CasService<FilesystemStorage>, object: CasObject) -> CasPutResponse {
+        service
+            .put(Request::new(CasPutRequest { data: Some(object) }))
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    fn put_id(response: CasPutResponse) -> CasDataId {
+        match response.contents {
+            Some(cas_put_response::Contents::CasId(id)) => id,
+            other => panic!("Expected a CAS ID, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chained_get() {
+        let dir = TempDir::new().unwrap();
+        let service = service(&dir);
+
+        // Leaf saved as a blob, referenced by an object
+        let leaf = service
+            .save(Request::new(CasSaveRequest {
+                data: Some(CasBlob {
+                    blob: Some(CasBytes {
+                        contents: Some(cas_bytes::Contents::Data(b"leaf".to_vec())),
+                    }),
+                }),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(cas_save_response::Contents::CasId(leaf_id)) = leaf.contents else {
+            panic!("Expected a CAS ID");
+        };
+        let root_id = put_id(put(&service, object(b"root", vec![leaf_id.clone()])).await);
+
+        // Walk from the root to its reference; blobs and objects are wire compatible
+        let root = service
+            .get(Request::new(CasGetRequest {
+                cas_id: Some(root_id),
+                write_to_disk: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(root.outcome, cas_get_response::Outcome::Success as i32);
+        let Some(cas_get_response::Contents::Data(root)) = root.contents else {
+            panic!("Expected an object");
+        };
+        assert_eq!(root.references, vec![leaf_id.clone()]);
+
+        let leaf = service
+            .get(Request::new(CasGetRequest {
+                cas_id: Some(root.references[0].clone()),
+                write_to_disk: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let Some(cas_get_response::Contents::Data(leaf)) = leaf.contents else {
+            panic!("Expected an object");
+        };
+        assert_eq!(leaf, object(b"leaf", vec![]));
+    }
+
+    #[tokio::test]
+    async fn test_put_validation() {
+        let dir = TempDir::new().unwrap();
+        let service = service(&dir);
+
+        // Objects referencing missing objects aren't stored
+        let missing = CasDataId {
+            id: vec![7; ID_LEN],
+        };
+        let response = put(&service, object(b"root", vec![missing])).await;
+        assert!(matches!(
+            response.contents,
+            Some(cas_put_response::Contents::Error(_))
+        ));
+
+        // Malformed IDs are invalid arguments, unknown ones are misses
+        let error = service
+            .get(Request::new(CasGetRequest {
+                cas_id: Some(CasDataId { id: vec![1, 2, 3] }),
+                write_to_disk: false,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::InvalidArgument);
+        let error = service
+            .put(Request::new(CasPutRequest {
+                data: Some(object(b"x", vec![CasDataId { id: vec![] }])),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::InvalidArgument);
+        let response = service
+            .load(Request::new(CasLoadRequest {
+                cas_id: Some(CasDataId {
+                    id: vec![9; ID_LEN],
+                }),
+                write_to_disk: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            response.outcome,
+            cas_load_response::Outcome::ObjectNotFound as i32
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_inlines_file_paths_and_enforces_size_limits() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("object.o");
+        std::fs::write(&file, b"compiled").unwrap();
+        let from_file = CasObject {
+            blob: Some(CasBytes {
+                contents: Some(cas_bytes::Contents::FilePath(
+                    file.to_string_lossy().to_string(),
+                )),
+            }),
+            references: vec![],
+        };
+
+        // Stored by content: same ID as the inline object, and readable without the file
+        let service = service(&dir);
+        let id = put_id(put(&service, from_file.clone()).await);
+        assert_eq!(id, put_id(put(&service, object(b"compiled", vec![])).await));
+        std::fs::remove_file(&file).unwrap();
+        let response = service
+            .get(Request::new(CasGetRequest {
+                cas_id: Some(id),
+                write_to_disk: true,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            response.contents,
+            Some(cas_get_response::Contents::Data(object(
+                b"compiled",
+                vec![]
+            )))
+        );
+
+        // Files over the size limit are rejected before they are read
+        std::fs::write(&file, vec![0; 64]).unwrap();
+        let limits = UploadLimits::from_config(&crate::config::LimitsConfig {
+            max_artifact_size: Some("32".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let service = service.with_upload_limits(Arc::new(limits));
+        let error = service
+            .put(Request::new(CasPutRequest {
+                data: Some(from_file),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::ResourceExhausted);
+    }
+}
@@ -1,5 +1,8 @@
 use super::proto::keyvalue::*;
+use crate::auth::scopes::{self, Permission};
 use crate::logging::{operations, services, status};
+use crate::quota::UploadLimits;
+use crate::rate_limit::grpc_client_identity;
 use crate::storage::Storage;
 use prost::Message;
 use std::sync::Arc;
@@ -10,11 +13,21 @@ use tracing::{debug, info};
 /// Maps build keys to cached value maps
 pub struct KeyValueService<S: Storage> {
     storage: Arc<S>,
+    limits: Arc<UploadLimits>,
 }
 
 impl<S: Storage> KeyValueService<S> {
     pub fn new(storage: Arc<S>) -> Self {
-        Self { storage }
+        Self {
+            storage,
+            limits: Arc::new(UploadLimits::unlimited()),
+        }
+    }
+
+    /// Enforce upload size limits and quotas
+    pub fn with_upload_limits(mut self, limits: Arc<UploadLimits>) -> Self {
+        self.limits = limits;
+        self
     }
 
     /// Serialize a Value to bytes
@@ -31,7 +44,7 @@ impl<S: Storage> KeyValueService<S> {
     #[allow(clippy::result_large_err)]
     fn deserialize_value(data: &[u8]) -> Result<Value, Status> {
         Value::decode(data)
-            .map_err(|e| Status::internal(format!("Failed to deserialize value: {}", e)))
+            .map_err(|e| Status::data_loss(format!("Failed to deserialize value: {}", e)))
     }
 
     /// Create a key for the KeyValue store with a prefix to avoid collision with CAS objects
@@ -51,7 +64,13 @@ impl<S: Storage + 'static> super::proto::keyvalue::key_value_db_server::KeyValue
         &self,
         request: Request<PutValueRequest>,
     ) -> Result<Response<PutValueResponse>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::XCODE, Permission::Write)?;
+        let client = grpc_client_identity(&request);
+
         let req = request.into_inner();
+        if req.key.is_empty() {
+            return Err(Status::invalid_argument("Missing key"));
+        }
         let key = hex::encode(&req.key);
 
         let value = req
@@ -70,6 +89,8 @@ impl<S: Storage + 'static> super::proto::keyvalue::key_value_db_server::KeyValue
 
         // Serialize the value
         let serialized = Self::serialize_value(&value)?;
+        self.limits
+            .check_upload(scopes::services::XCODE, &client, serialized.len() as u64)?;
 
         // Store with prefixed key
         let storage_key = Self::storage_key(&req.key);
@@ -94,7 +115,12 @@ impl<S: Storage + 'static> super::proto::keyvalue::key_value_db_server::KeyValue
         &self,
         request: Request<GetValueRequest>,
     ) -> Result<Response<GetValueResponse>, Status> {
+        scopes::authorize_grpc(&request, scopes::services::XCODE, Permission::Read)?;
+
         let req = request.into_inner();
+        if req.key.is_empty() {
+            return Err(Status::invalid_argument("Missing key"));
+        }
         let key = hex::encode(&req.key);
 
         debug!(
@@ -139,7 +165,7 @@ impl<S: Storage + 'static> super::proto::keyvalue::key_value_db_server::KeyValue
                     key = %key,
                     "cache miss"
                 );
-                Ok(Response::new(GetValueResponse {
+                Ok(super::miss(GetValueResponse {
                     outcome: get_value_response::Outcome::KeyNotFound as i32,
                     contents: None,
                 }))
@@ -147,3 +173,61 @@ impl<S: Storage + 'static> super::proto::keyvalue::key_value_db_server::KeyValue
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FilesystemStorage;
+    use crate::xcode::proto::keyvalue::key_value_db_server::KeyValueDb;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_values_and_malformed_keys() {
+        let dir = TempDir::new().unwrap();
+        let storage = FilesystemStorage::new(dir.path().to_str().unwrap()).unwrap();
+        let service = KeyValueService::new(Arc::new(storage));
+
+        let value = Value {
+            entries: [("output".to_string(), vec![1, 2, 3])]
+                .into_iter()
+                .collect(),
+        };
+        service
+            .put_value(Request::new(PutValueRequest {
+                key: b"cache-key".to_vec(),
+                value: Some(value.clone()),
+            }))
+            .await
+            .unwrap();
+
+        let response = service
+            .get_value(Request::new(GetValueRequest {
+                key: b"cache-key".to_vec(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            response.contents,
+            Some(get_value_response::Contents::Value(value))
+        );
+
+        let response = service
+            .get_value(Request::new(GetValueRequest {
+                key: b"other-key".to_vec(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            response.outcome,
+            get_value_response::Outcome::KeyNotFound as i32
+        );
+
+        let error = service
+            .get_value(Request::new(GetValueRequest { key: vec![] }))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -4,6 +4,49 @@ mod keyvalue;
 pub use cas::CasService;
 pub use keyvalue::KeyValueService;
 
+use crate::daemon_stats::CacheMiss;
+use crate::storage::Storage;
+use proto::cas::casdb_service_server::CasdbServiceServer;
+use proto::keyvalue::key_value_db_server::KeyValueDbServer;
+
+/// Largest gRPC message the Xcode services accept or send
+///
+/// tonic defaults to 4 MiB, less than many compilation outputs (object files,
+/// serialized modules), which the compiler would report as failed cache uploads.
+pub const MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+
+/// gRPC service names of the Xcode cache server (for health checking)
+pub const SERVICE_NAMES: &[&str] = &[
+    proto::cas::casdb_service_server::SERVICE_NAME,
+    proto::keyvalue::key_value_db_server::SERVICE_NAME,
+];
+
+/// Response of a lookup that found nothing, marked as a miss for `fabrik top` (the
+/// protocol reports misses in the message, not as a gRPC status)
+fn miss<T>(message: T) -> tonic::Response<T> {
+    let mut response = tonic::Response::new(message);
+    response.extensions_mut().insert(CacheMiss);
+    response
+}
+
+/// gRPC server for the CAS service
+pub fn cas_server<S: Storage + 'static>(
+    service: CasService<S>,
+) -> CasdbServiceServer<CasService<S>> {
+    CasdbServiceServer::new(service)
+        .max_decoding_message_size(MAX_MESSAGE_SIZE)
+        .max_encoding_message_size(MAX_MESSAGE_SIZE)
+}
+
+/// gRPC server for the key-value service
+pub fn keyvalue_server<S: Storage + 'static>(
+    service: KeyValueService<S>,
+) -> KeyValueDbServer<KeyValueService<S>> {
+    KeyValueDbServer::new(service)
+        .max_decoding_message_size(MAX_MESSAGE_SIZE)
+        .max_encoding_message_size(MAX_MESSAGE_SIZE)
+}
+
 // Include generated proto code
 pub mod proto {
     pub mod cas {
@@ -13,4 +56,7 @@ pub mod proto {
     pub mod keyvalue {
         tonic::include_proto!("compilation_cache_service.keyvalue.v1");
     }
+
+    /// Encoded descriptors of the CAS and KeyValue protos
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("xcode_descriptor");
 }
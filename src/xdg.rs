@@ -95,6 +95,19 @@ pub fn daemon_state_dir() -> PathBuf {
     state_dir().join("daemons")
 }
 
+/// Get the logs directory
+///
+/// Used for daemon log files (included in `fabrik doctor --bundle`).
+///
+/// # Example
+/// ```
+/// let logs_dir = fabrik::xdg::logs_dir();
+/// // Unix: ~/.local/state/fabrik/logs
+/// ```
+pub fn logs_dir() -> PathBuf {
+    state_dir().join("logs")
+}
+
 /// Get the OAuth tokens directory
 ///
 /// Used by schlussel for file-based OAuth token storage.
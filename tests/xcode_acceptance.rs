@@ -180,3 +180,117 @@ fn test_xcode_socket_isolation() {
 
     println!("✅ Socket isolation verified - each test gets unique socket");
 }
+
+/// Compile a Swift file with `swift-frontend -cache-compile-job` using the daemon as the
+/// remote CAS, with a fresh local CAS each time, so the second compilation can only
+/// hit through the socket
+fn swift_cached_compile(
+    swift_frontend: &std::path::Path,
+    plugin: &std::path::Path,
+    sdk: &str,
+    socket: &std::path::Path,
+    work_dir: &std::path::Path,
+    run: &str,
+) -> std::process::Output {
+    let output_path = work_dir.join(format!("{}.o", run));
+    Command::new(swift_frontend)
+        .current_dir(work_dir)
+        .arg("-c")
+        .arg("main.swift")
+        .args(["-module-name", "Main", "-sdk", sdk, "-o"])
+        .arg(&output_path)
+        .arg("-cache-compile-job")
+        .arg("-Rcache-compile-job")
+        .arg("-cas-path")
+        .arg(work_dir.join(format!("cas-{}", run)))
+        .arg("-cas-plugin-path")
+        .arg(plugin)
+        .arg("-cas-plugin-option")
+        .arg(format!("remote-service-path={}", socket.display()))
+        .output()
+        .expect("Failed to execute swift-frontend")
+}
+
+#[test]
+fn test_swift_frontend_cache_compile_job() {
+    let daemon = TestDaemon::start_with_socket();
+    let socket_path = daemon
+        .socket_path()
+        .expect("Socket path should be available in Unix socket mode");
+
+    // Toolchain pieces: the compiler, its CAS plugin and the macOS SDK
+    let xcrun = |args: &[&str]| {
+        Command::new("xcrun")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let (Some(swift_frontend), Some(sdk)) = (
+        xcrun(&["--find", "swift-frontend"]),
+        xcrun(&["--show-sdk-path", "--sdk", "macosx"]),
+    ) else {
+        println!("⚠️  swift-frontend or the macOS SDK not found, skipping");
+        return;
+    };
+    let swift_frontend = PathBuf::from(swift_frontend);
+    let plugin = swift_frontend
+        .parent()
+        .and_then(|bin| bin.parent())
+        .map(|usr| usr.join("lib").join("libToolchainCASPlugin.dylib"))
+        .filter(|plugin| plugin.exists());
+    let Some(plugin) = plugin else {
+        println!("⚠️  libToolchainCASPlugin.dylib not found, skipping");
+        return;
+    };
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    std::fs::write(
+        temp_dir.path().join("main.swift"),
+        "public func greet() -> String { \"Hello from Fabrik\" }\n",
+    )
+    .unwrap();
+
+    println!("\n=== First compilation (cache miss) ===");
+    let first = swift_cached_compile(
+        &swift_frontend,
+        &plugin,
+        &sdk,
+        &socket_path,
+        temp_dir.path(),
+        "first",
+    );
+    let first_stderr = String::from_utf8_lossy(&first.stderr);
+    println!("stderr: {}", first_stderr);
+    assert!(first.status.success(), "First compilation failed");
+    assert!(
+        first_stderr.contains("cache miss"),
+        "First compilation should miss the cache"
+    );
+
+    println!("\n=== Second compilation (remote cache hit) ===");
+    let second = swift_cached_compile(
+        &swift_frontend,
+        &plugin,
+        &sdk,
+        &socket_path,
+        temp_dir.path(),
+        "second",
+    );
+    let second_stderr = String::from_utf8_lossy(&second.stderr);
+    println!("stderr: {}", second_stderr);
+    assert!(second.status.success(), "Second compilation failed");
+    assert!(
+        !second_stderr.contains("cache miss"),
+        "Second compilation should be replayed from the daemon"
+    );
+
+    // The replayed object file is the one the first compilation produced
+    assert_eq!(
+        std::fs::read(temp_dir.path().join("first.o")).unwrap(),
+        std::fs::read(temp_dir.path().join("second.o")).unwrap()
+    );
+
+    println!("✅ swift-frontend compilation cache served through the Unix socket");
+}